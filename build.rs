@@ -7,11 +7,15 @@
 //! Cargo re-run the build script whenever `memory.x` is changed,
 //! updating `memory.x` ensures a rebuild of the application with the
 //! new memory settings.
+//!
+//! It also bakes the git revision, build profile and rp2040-hal version into
+//! environment variables for the `version` command, see `version_info()`.
 
 use std::env;
 use std::fs::File;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
 fn main() {
     // Put `memory.x` in our output directory and ensure it's
@@ -28,4 +32,53 @@ fn main() {
     // here, we ensure the build script is only re-run when
     // `memory.x` is changed.
     println!("cargo:rerun-if-changed=memory.x");
+
+    version_info();
+}
+
+/// Sets `GIT_REVISION`, `BUILD_PROFILE` and `HAL_VERSION` for the firmware to read with `option_env!`.
+/// Whatever can't be found out (e.g. building from a tarball without git) is just left unset.
+fn version_info() {
+    // Short hash with a `-dirty` suffix if there are uncommitted changes
+    let revision = Command::new("git")
+        .args(["describe", "--always", "--dirty", "--abbrev=8"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+    if let Some(revision) = revision {
+        println!("cargo:rustc-env=GIT_REVISION={}", revision.trim());
+    }
+
+    // Only ever "debug" or "release", custom profiles report the one they inherit from
+    if let Ok(profile) = env::var("PROFILE") {
+        println!("cargo:rustc-env=BUILD_PROFILE={}", profile);
+    }
+
+    // Cargo doesn't tell us our dependencies' versions, so we find it in the lockfile
+    if let Ok(lockfile) = std::fs::read_to_string("Cargo.lock") {
+        let version = lockfile
+            .split("[[package]]")
+            .find(|package| package.contains("name = \"rp2040-hal\""))
+            .and_then(|package| package.lines().find_map(|line| line.strip_prefix("version = ")))
+            .map(|version| version.trim_matches('"'));
+        if let Some(version) = version {
+            println!("cargo:rustc-env=HAL_VERSION={}", version);
+        }
+    }
+    println!("cargo:rerun-if-changed=Cargo.lock");
+
+    // Rerun after commits, checkouts and staging, but only if we're in a git repo,
+    // since a missing file would make Cargo rerun the script on every build
+    for file in [".git/HEAD", ".git/index"] {
+        if Path::new(file).exists() {
+            println!("cargo:rerun-if-changed={}", file);
+        }
+    }
+    if let Ok(head) = std::fs::read_to_string(".git/HEAD")
+        && let Some(reference) = head.trim().strip_prefix("ref: ")
+        && Path::new(".git").join(reference).exists()
+    {
+        println!("cargo:rerun-if-changed=.git/{}", reference);
+    }
 }
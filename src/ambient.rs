@@ -0,0 +1,121 @@
+use rp2040_hal as hal;
+use hal::{
+    adc::{Adc, AdcPin},
+    gpio::{bank0::Gpio26, FunctionSioInput, Pin, PullNone},
+};
+
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// How many ADC samples to average per reading
+const SAMPLE_COUNT: u32 = 8;
+/// Full scale of the 12-bit ADC
+const ADC_FULL_SCALE: u32 = 4096;
+/// How often the light gets measured while auto brightness is on, in microseconds
+const SAMPLE_INTERVAL_US: u64 = 250_000;
+/// Weight of a new reading in the running average, as a power of two (1/4), so that a passing shadow doesn't flicker the display
+const SMOOTHING_SHIFT: u32 = 2;
+/// How much the averaged reading has to move from the one the contrast was last set by, in raw ADC units (about 5 %)
+const HYSTERESIS: u32 = 200;
+/// Contrast in complete darkness, not zero, so that the display stays readable
+const MIN_CONTRAST: u8 = 0x08;
+/// Contrast in full light
+const MAX_CONTRAST: u8 = 0xFF;
+
+// HACK: Evaluate the block of code at compile time to assert that constants aren't malformed
+const fn _check_consts() {
+    if HYSTERESIS >= ADC_FULL_SCALE {
+        core::panic!("The hysteresis has to be less than the ADC's full scale, or the contrast would never change!");
+    }
+}
+const _: () = _check_consts();
+
+/// GPIO26 with its digital circuitry disabled, see `AdcPin`
+pub type LightPin = AdcPin<Pin<Gpio26, FunctionSioInput, PullNone>>;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Reads a photoresistor divider on ADC0 (GPIO26): the photoresistor from 3V3, a fixed resistor (about 10 kΩ) to ground,
+/// so that more light means a higher voltage.
+///
+/// The ADC is shared with `Vsys`, which owns it.
+pub struct LightSensor {
+    pin: LightPin,
+}
+
+impl LightSensor {
+    pub fn new(pin: LightPin) -> Self {
+        LightSensor { pin }
+    }
+
+    /// Returns the average of a few raw ADC readings, 0 in the dark up to 4095 in full light.
+    pub fn measure(&mut self, adc: &mut Adc) -> Result<u32, CustomError> {
+        let mut sum: u32 = 0;
+        for _ in 0..SAMPLE_COUNT {
+            sum += u32::from(adc.read(&mut self.pin).map_err(|e| {
+                log_error!("ADC conversion of the light sensor failed: {:?}", e);
+                CE::Other
+            })?);
+        }
+        Ok(sum / SAMPLE_COUNT)
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Maps the ambient light to the display's contrast, see the `autobrt` command.
+///
+/// The readings are averaged over time, and the contrast only changes once the average moves by more than `HYSTERESIS`
+/// from where it last changed, so that the display doesn't flicker between two levels at the edge.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct AutoBrightness {
+    /// Running average of the readings, `None` until the first one
+    average: Option<u32>,
+    /// The average the contrast was last set by, `None` if it wasn't set yet
+    applied: Option<u32>,
+    next_sample: u64,
+}
+
+impl AutoBrightness {
+    pub const fn new() -> Self {
+        AutoBrightness {
+            average: None,
+            applied: None,
+            next_sample: 0,
+        }
+    }
+
+    /// Forgets the history, so that the next tick sets the contrast right away.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Measures the light if it's time to, returns the contrast to set if it should change.
+    pub fn tick(&mut self, sensor: &mut LightSensor, adc: &mut Adc, now: u64) -> Result<Option<u8>, CustomError> {
+        if now < self.next_sample {
+            return Ok(None);
+        }
+        self.next_sample = now + SAMPLE_INTERVAL_US;
+
+        let reading = sensor.measure(adc)?;
+        let average = match self.average {
+            // Can't overflow, both are less than the full scale
+            Some(average) => average - (average >> SMOOTHING_SHIFT) + (reading >> SMOOTHING_SHIFT),
+            None => reading,
+        };
+        self.average = Some(average);
+
+        if let Some(applied) = self.applied && applied.abs_diff(average) <= HYSTERESIS {
+            return Ok(None);
+        }
+        self.applied = Some(average);
+        let contrast = MIN_CONTRAST as u32 + average * (MAX_CONTRAST - MIN_CONTRAST) as u32 / (ADC_FULL_SCALE - 1);
+        log_debug!("Ambient light at {} of {}, setting contrast to {}", average, ADC_FULL_SCALE - 1, contrast);
+        Ok(Some(contrast.min(MAX_CONTRAST as u32) as u8))
+    }
+}
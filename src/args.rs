@@ -0,0 +1,128 @@
+use core::str::FromStr;
+use core::num::ParseIntError;
+
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+/// Splits the arguments of a command into tokens, see `Args`.
+pub fn args(input: &str) -> Args<'_> {
+    Args { rest: input }
+}
+
+/// An iterator over the whitespace-separated tokens of a command's arguments.
+///
+/// Any number of spaces between tokens is fine. A token starting with a double quote
+/// lasts until the closing double quote (which is not a part of it), so that it may contain spaces,
+/// e.g. `"supply voltage"`. A missing closing quote means the token lasts until the end of the input.
+/// There are no escape sequences, a quoted token just can't contain a double quote.
+#[derive(Debug, Clone)]
+pub struct Args<'s> {
+    rest: &'s str,
+}
+
+impl<'s> Iterator for Args<'s> {
+    type Item = &'s str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let trimmed = self.rest.trim_start();
+        if trimmed.is_empty() {
+            self.rest = trimmed;
+            return None;
+        }
+
+        let (token, rest) = match trimmed.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => trimmed.split_once(char::is_whitespace).unwrap_or((trimmed, "")),
+        };
+        self.rest = rest;
+        Some(token)
+    }
+}
+
+impl<'s> Args<'s> {
+    /// Whether there are no more tokens
+    pub fn is_empty(&self) -> bool {
+        self.rest.trim_start().is_empty()
+    }
+
+    /// Returns the next token, or `BadInput` if there's none.
+    pub fn next_str(&mut self) -> Result<&'s str, CustomError> {
+        self.next().ok_or(CE::BadInput)
+    }
+
+    /// Parses the next token as an integer, e.g. `args.next_int::<usize>()`.
+    pub fn next_int<I>(&mut self) -> Result<I, CustomError>
+    where
+        I: FromStr<Err = ParseIntError>,
+    {
+        Ok(self.next_str()?.parse::<I>()?)
+    }
+
+    /// Parses the next token as `on` or `off`, e.g. for `invert on`.
+    pub fn next_on_off(&mut self) -> Result<bool, CustomError> {
+        match self.next_str()? {
+            "on" => Ok(true),
+            "off" => Ok(false),
+            other => {
+                log_warn!("Expected on or off, got {:?}", other);
+                Err(CE::BadInput)
+            }
+        }
+    }
+
+    /// Returns `BadInput` if there are tokens left over, call it after taking all the arguments a command wants.
+    pub fn finish(self) -> Result<(), CustomError> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            log_warn!("Too many arguments, left over: {:?}", self.rest.trim_start());
+            Err(CE::BadInput)
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+    use super::args;
+    use crate::custom_error::CE;
+
+    fn tokens(input: &str) -> Vec<&str> {
+        args(input).collect()
+    }
+
+    #[test]
+    fn splits_on_any_whitespace() {
+        assert_eq!(tokens("a b"), ["a", "b"]);
+        assert_eq!(tokens("  a    b\t c  "), ["a", "b", "c"]);
+        assert!(tokens("").is_empty());
+        assert!(tokens("   ").is_empty());
+        assert!(args(" ").is_empty());
+    }
+
+    #[test]
+    fn quoted_tokens_keep_their_spaces() {
+        assert_eq!(tokens(r#"label "supply voltage" 3"#), ["label", "supply voltage", "3"]);
+        assert_eq!(tokens(r#""""#), [""]);
+        // The closing quote ends the token even without a space after it
+        assert_eq!(tokens(r#""a b"c"#), ["a b", "c"]);
+        // A missing closing quote lasts until the end
+        assert_eq!(tokens(r#"x "a  b"#), ["x", "a  b"]);
+    }
+
+    #[test]
+    fn typed_arguments() {
+        let mut a = args(" 42  on x");
+        assert_eq!(a.next_int::<u8>(), Ok(42));
+        assert_eq!(a.next_on_off(), Ok(true));
+        assert_eq!(a.clone().finish(), Err(CE::BadInput));
+        assert_eq!(a.next_on_off(), Err(CE::BadInput));
+        assert_eq!(a.next_str(), Err(CE::BadInput));
+        assert_eq!(a.finish(), Ok(()));
+        assert!(args("300").next_int::<u8>().is_err());
+    }
+}
@@ -0,0 +1,91 @@
+use rp2040_hal::pac;
+
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// The baud rate we boot with unless another one was saved, the same as `UartConfig::default()`
+pub const DEFAULT_BAUD: u32 = 115_200;
+/// Slowest baud rate the `baud` command accepts, anything slower is unusable for a calculator anyway
+pub const MIN_BAUD: u32 = 1200;
+/// Fastest baud rate the `baud` command accepts, the usual USB-UART adapters don't do more
+pub const MAX_BAUD: u32 = 921_600;
+/// UART0's CTS pin, an input the other side holds low while we may send
+pub const CTS_PIN: usize = 2;
+/// UART0's RTS pin, held low by us while there's room in the receive FIFO
+pub const RTS_PIN: usize = 3;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Reprograms UART0 to `baud`, after waiting for whatever's being sent to go out at the old rate.
+/// Returns the actual baud rate, which is a bit off for rates that don't divide the clock evenly.
+///
+/// The HAL only sets the baud rate when enabling the UART, which we can't do again after splitting it,
+/// so we go around it. It doesn't keep any state about the divisors, so it doesn't mind.
+pub fn set_baud_rate(baud: u32, clock_hz: u32) -> Result<u32, CustomError> {
+    if !(MIN_BAUD..=MAX_BAUD).contains(&baud) {
+        log_warn!("Baud rate {} out of range ({}-{})", baud, MIN_BAUD, MAX_BAUD);
+        return Err(CE::BadInput);
+    }
+    let (int, frac) = dividers(baud, clock_hz)?;
+
+    // SAFETY: The pointer is valid, and we only touch registers the HAL doesn't keep state about (see above).
+    let uart = unsafe { &*pac::UART0::ptr() };
+    while uart.uartfr().read().busy().bit_is_set() {} // Until the last stop bit is out
+
+    // The PL011 mustn't be reprogrammed while enabled, and the divisors only latch on a write to LCR_H
+    uart.uartcr().modify(|_, w| w.uarten().clear_bit());
+    // SAFETY: Any value of the dividers is valid, they're just a bit narrower than u32
+    uart.uartibrd().write(|w| unsafe { w.baud_divint().bits(int) });
+    uart.uartfbrd().write(|w| unsafe { w.baud_divfrac().bits(frac) });
+    uart.uartlcr_h().modify(|_, w| w);
+    uart.uartcr().modify(|_, w| w.uarten().set_bit());
+
+    let actual = 4 * clock_hz / (64 * u32::from(int) + u32::from(frac));
+    log_info!("UART baud rate set to {} (asked for {})", actual, baud);
+    Ok(actual)
+}
+
+/// Turns UART0's RTS/CTS flow control on or off, handing `CTS_PIN` and `RTS_PIN` over to the UART or taking them back.
+/// With it on, the other side stops sending while our receive FIFO is half full, so that it can't overrun
+/// even while we're busy flushing the display without the `dma-rx` feature; we only send while it lets us.
+///
+/// Like `set_baud_rate()`, it goes around the HAL, which only sets the flow control when enabling the UART, and only with the pins
+/// in its pinout. Those pins aren't configured by anything else; an unconnected CTS reads as low (clear to send) by the pad's pull-down.
+pub fn set_flow_control(on: bool) -> Result<(), CustomError> {
+    if cfg!(feature = "i2c-peripheral") {
+        log_warn!("GPIO {} and {} are taken by the I²C target (the `i2c-peripheral` feature), there's no flow control", CTS_PIN, RTS_PIN);
+        return Err(CE::Unimplemented);
+    }
+
+    // SAFETY: The pointers are valid, the pins aren't used by anything else and the HAL doesn't keep any state about the flow control
+    let (uart, io) = unsafe { (&*pac::UART0::ptr(), &*pac::IO_BANK0::ptr()) };
+    while uart.uartfr().read().busy().bit_is_set() {} // Until the last stop bit is out
+
+    uart.uartcr().modify(|_, w| w.uarten().clear_bit());
+    for pin in [CTS_PIN, RTS_PIN] {
+        io.gpio(pin).gpio_ctrl().write(|w| if on { w.funcsel().uart() } else { w.funcsel().null() });
+    }
+    uart.uartcr().modify(|_, w| w.ctsen().bit(on).rtsen().bit(on).uarten().set_bit());
+
+    log_info!("UART flow control turned {}", if on { "on" } else { "off" });
+    Ok(())
+}
+
+/// Computes the integer and fractional (in 64ths) part of the divisor, the same way the C SDK does.
+fn dividers(baud: u32, clock_hz: u32) -> Result<(u16, u8), CustomError> {
+    // In 128ths, so that we can round the 64ths
+    let div = 8 * u64::from(clock_hz) / u64::from(baud);
+    match div >> 7 {
+        0 => {
+            log_warn!("Baud rate {} is too fast for the {} Hz clock", baud, clock_hz);
+            Err(CE::BadInput)
+        },
+        int @ 1..65535 => Ok((int as u16, (div & 0x7F).div_ceil(2) as u8)),
+        _ => Ok((65535, 0)), // As slow as it gets
+    }
+}
@@ -0,0 +1,196 @@
+use embedded_graphics::{
+    prelude::*,
+    primitives::{
+        PrimitiveStyle,
+        Rectangle,
+    },
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Smallest digits worth drawing, anything smaller is no bigger than the usual font
+pub const MIN_HEIGHT: u32 = 10;
+
+// The segments of a glyph, as bits of its mask:
+//  aaa
+// f   b
+//  ggg
+// e   c
+//  ddd
+const A: u8 = 1 << 0;
+const B: u8 = 1 << 1;
+const C: u8 = 1 << 2;
+const D: u8 = 1 << 3;
+const E: u8 = 1 << 4;
+const F: u8 = 1 << 5;
+const G: u8 = 1 << 6;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// What a character of a number is drawn as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Glyph {
+    /// A full width cell with the masked segments lit
+    Segments(u8),
+    /// The decimal point, a square on the baseline
+    Point,
+    /// The gap between groups of thousands, half a cell
+    Space,
+}
+
+impl Glyph {
+    fn of(c: char) -> Option<Self> {
+        let segments = match c {
+            '0' => A | B | C | D | E | F,
+            '1' => B | C,
+            '2' => A | B | D | E | G,
+            '3' => A | B | C | D | G,
+            '4' => B | C | F | G,
+            '5' => A | C | D | F | G,
+            '6' => A | C | D | E | F | G,
+            '7' => A | B | C,
+            '8' => A | B | C | D | E | F | G,
+            '9' => A | B | C | D | F | G,
+            '-' => G,
+            'e' => A | B | D | E | F | G, // Of the scientific notation
+            '.' => return Some(Glyph::Point),
+            ' ' => return Some(Glyph::Space),
+            _ => return None,
+        };
+        Some(Glyph::Segments(segments))
+    }
+}
+
+/// Seven-segment digits of a single size, drawn with rectangles, so that they can be as large as the display allows,
+/// unlike the bitmap fonts. Only the characters of a formatted number have glyphs, see `Glyph::of()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BigDigits {
+    height: u32,
+    width: u32,
+    /// Of the segments, and also the gap between the characters
+    thickness: u32,
+}
+
+impl BigDigits {
+    /// The digits of the height, half as wide as they're tall
+    pub fn with_height(height: u32) -> Self {
+        BigDigits {
+            height,
+            width: height / 2,
+            thickness: (height / 9).max(1),
+        }
+    }
+
+    /// The largest digits the text fits into the size with, or `None` if it doesn't fit even with `MIN_HEIGHT`
+    /// or there's a character without a glyph
+    pub fn fitting(text: &str, size: Size) -> Option<Self> {
+        (MIN_HEIGHT..=size.height).rev()
+            .map(Self::with_height)
+            .find(|digits| digits.text_width(text).is_some_and(|width| width <= size.width))
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// How far the next character starts after this one, including the gap
+    fn advance(&self, glyph: Glyph) -> u32 {
+        let width = match glyph {
+            Glyph::Segments(_) => self.width,
+            Glyph::Point => self.thickness,
+            Glyph::Space => self.width / 2,
+        };
+        width + self.thickness
+    }
+
+    /// Width of the whole text, or `None` if there's a character without a glyph
+    pub fn text_width(&self, text: &str) -> Option<u32> {
+        let mut width = 0;
+        for c in text.chars() {
+            width += self.advance(Glyph::of(c)?);
+        }
+        Some(width.saturating_sub(self.thickness)) // No gap after the last one
+    }
+
+    /// The rectangles of the segments lit in the mask, relative to the glyph's top left corner
+    fn segments(&self, mask: u8) -> impl Iterator<Item = Rectangle> + '_ {
+        let (w, h, t) = (self.width, self.height, self.thickness);
+        let middle = (h - t) / 2; // Where the middle segment starts
+        let lower = h - middle - 2 * t; // Height of the lower vertical segments, the upper ones being `middle - t` tall
+        [
+            (A, Point::new(t as i32, 0), Size::new(w - 2 * t, t)),
+            (B, Point::new((w - t) as i32, t as i32), Size::new(t, middle - t)),
+            (C, Point::new((w - t) as i32, (middle + t) as i32), Size::new(t, lower)),
+            (D, Point::new(t as i32, (h - t) as i32), Size::new(w - 2 * t, t)),
+            (E, Point::new(0, (middle + t) as i32), Size::new(t, lower)),
+            (F, Point::new(0, t as i32), Size::new(t, middle - t)),
+            (G, Point::new(t as i32, middle as i32), Size::new(w - 2 * t, t)),
+        ].into_iter()
+            .filter(move |(bit, _, _)| mask & bit != 0)
+            .map(|(_, top_left, size)| Rectangle::new(top_left, size))
+    }
+
+    /// Draws the text with its top left corner at the point. Characters without a glyph are skipped.
+    pub fn draw<T: DrawTarget>(&self, target: &mut T, text: &str, top_left: Point, color: T::Color) -> Result<(), T::Error> {
+        let style = PrimitiveStyle::with_fill(color);
+        let mut x = top_left.x;
+        for glyph in text.chars().filter_map(Glyph::of) {
+            let origin = Point::new(x, top_left.y);
+            match glyph {
+                Glyph::Segments(mask) => {
+                    for segment in self.segments(mask) {
+                        segment.translate(origin).into_styled(style).draw(target)?;
+                    }
+                },
+                Glyph::Point => {
+                    let square = Size::new(self.thickness, self.thickness);
+                    Rectangle::new(origin + Point::new(0, (self.height - self.thickness) as i32), square)
+                        .into_styled(style)
+                        .draw(target)?;
+                },
+                Glyph::Space => {},
+            }
+            x += self.advance(glyph) as i32;
+        }
+        Ok(())
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use embedded_graphics::prelude::*;
+    use embedded_graphics::pixelcolor::BinaryColor;
+    use embedded_graphics::mock_display::MockDisplay;
+    use super::{BigDigits, MIN_HEIGHT};
+
+    #[test]
+    fn shrinks_to_fit() {
+        let digits = BigDigits::with_height(20);
+        // Ten wide plus a gap of two for each digit, a third of that for the point, the last gap left out
+        assert_eq!(digits.text_width("-1.5"), Some(12 + 4 + 12 + 10));
+        assert_eq!(digits.text_width("1 234"), Some(4 * 12 + 7 - 2));
+        assert_eq!(digits.text_width("x: 1"), None);
+
+        assert_eq!(BigDigits::fitting("42", Size::new(128, 40)).map(|d| d.height()), Some(40));
+        let fitted = BigDigits::fitting("3.141592654", Size::new(128, 40)).unwrap();
+        assert!(fitted.text_width("3.141592654").unwrap() <= 128);
+        assert!(BigDigits::with_height(fitted.height() + 1).text_width("3.141592654").unwrap() > 128);
+        assert_eq!(BigDigits::fitting("-1234567890123456789.123456789", Size::new(128, 40)), None);
+        assert_eq!(BigDigits::fitting("1", Size::new(128, MIN_HEIGHT - 1)), None);
+    }
+
+    #[test]
+    fn draws_segments() {
+        let mut display = MockDisplay::<BinaryColor>::new();
+        BigDigits::with_height(MIN_HEIGHT).draw(&mut display, "1-", Point::zero(), BinaryColor::On).unwrap();
+        // The right segments of the one, the middle segment of the minus a cell further
+        assert_eq!(display.get_pixel(Point::new(4, 2)), Some(BinaryColor::On));
+        assert_eq!(display.get_pixel(Point::new(4, 7)), Some(BinaryColor::On));
+        assert_eq!(display.get_pixel(Point::new(0, 2)), None);
+        assert_eq!(display.get_pixel(Point::new(8, 4)), Some(BinaryColor::On));
+        assert_eq!(display.get_pixel(Point::new(8, 0)), None);
+    }
+}
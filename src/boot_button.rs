@@ -0,0 +1,58 @@
+use embedded_hal::digital::InputPin;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// The button's pin, for the sleeper to wake up on it, see `power.rs`
+pub const BOOT_PIN: usize = 21;
+/// How long the button has to be held to reboot into the USB bootloader, in microseconds,
+/// long enough not to happen by brushing against it
+const HOLD_US: u64 = 3_000_000;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// A push button to ground (with the pin's pull-up) that reboots into the USB bootloader once held for `HOLD_US`,
+/// the same as the `boot usb` command, so that the firmware can be updated without a working serial link.
+///
+/// It's polled, so a press is only noticed while the main loop polls for input, which it always does with this feature.
+pub struct BootButton<P: InputPin> {
+    pin: P,
+    /// Timestamp of the press, `None` while released
+    pressed_since: Option<u64>,
+}
+
+impl<P: InputPin> BootButton<P> {
+    /// The pin should already be an input with a pull-up.
+    pub fn new(pin: P) -> Self {
+        BootButton {
+            pin,
+            pressed_since: None,
+        }
+    }
+
+    /// Whether the button is held down, as of the last poll
+    pub fn is_pressed(&self) -> bool {
+        self.pressed_since.is_some()
+    }
+
+    /// Reads the button, returns true once it's been held for `HOLD_US`.
+    /// A bounce just restarts the hold, which doesn't matter with a hold this long.
+    pub fn poll(&mut self, now: u64) -> bool {
+        let pressed = self.pin.is_low().unwrap_or(false); // Infallible on the RP2040
+        match (pressed, self.pressed_since) {
+            (false, since) => {
+                if since.is_some() {
+                    log_debug!("Boot button released before the reboot");
+                }
+                self.pressed_since = None;
+                false
+            },
+            (true, None) => {
+                log_debug!("Boot button pressed, rebooting into the USB bootloader if held");
+                self.pressed_since = Some(now);
+                false
+            },
+            (true, Some(since)) => now.saturating_sub(since) >= HOLD_US,
+        }
+    }
+}
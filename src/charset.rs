@@ -0,0 +1,92 @@
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// The upper half (0xA0 to 0xFF) of ISO 8859-2 (Latin-2), i.e. what our fonts can show besides ASCII.
+/// The lower half is the same as in ASCII, and 0x80 to 0x9F are control characters.
+const LATIN2_HIGH: [char; 96] = [
+    '\u{A0}', 'Ą', '˘', 'Ł', '¤', 'Ľ', 'Ś', '§', '¨', 'Š', 'Ş', 'Ť', 'Ź', '\u{AD}', 'Ž', 'Ż',
+    '°', 'ą', '˛', 'ł', '´', 'ľ', 'ś', 'ˇ', '¸', 'š', 'ş', 'ť', 'ź', '˝', 'ž', 'ż',
+    'Ŕ', 'Á', 'Â', 'Ă', 'Ä', 'Ĺ', 'Ć', 'Ç', 'Č', 'É', 'Ę', 'Ë', 'Ě', 'Í', 'Î', 'Ď',
+    'Đ', 'Ń', 'Ň', 'Ó', 'Ô', 'Ő', 'Ö', '×', 'Ř', 'Ů', 'Ú', 'Ű', 'Ü', 'Ý', 'Ţ', 'ß',
+    'ŕ', 'á', 'â', 'ă', 'ä', 'ĺ', 'ć', 'ç', 'č', 'é', 'ę', 'ë', 'ě', 'í', 'î', 'ď',
+    'đ', 'ń', 'ň', 'ó', 'ô', 'ő', 'ö', '÷', 'ř', 'ů', 'ú', 'ű', 'ü', 'ý', 'ţ', '˙',
+];
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Returns the ISO 8859-2 code of the char, or `None` if it isn't in ISO 8859-2 at all.
+pub fn to_latin2(c: char) -> Option<u8> {
+    if (c as u32) < 0xA0 {
+        return Some(c as u8); // ASCII and the C1 control characters are the same as in Unicode
+    }
+    LATIN2_HIGH.iter()
+        .position(|&x| x == c)
+        .map(|i| 0xA0 + i as u8) // Can't overflow, there's only 96 of them
+}
+
+/// Whether the char is printable with our ISO 8859-2 fonts, i.e. it's in ISO 8859-2 and isn't a control character.
+pub fn is_printable(c: char) -> bool {
+    !c.is_control() && to_latin2(c).is_some()
+}
+
+/// Puts together UTF-8 encoded chars from bytes received one by one, e.g. over UART.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Utf8Decoder {
+    buf: [u8; 4],
+    len: usize,
+    /// How many bytes the char currently being received has in total, zero if we're not in the middle of one
+    needed: usize,
+}
+
+impl Utf8Decoder {
+    pub const fn new() -> Self {
+        Utf8Decoder {
+            buf: [0; 4],
+            len: 0,
+            needed: 0,
+        }
+    }
+
+    /// Feeds one byte into the decoder. Returns `Ok(None)` if the char isn't complete yet.
+    /// Returns `BadInput` if the bytes aren't valid UTF-8, and starts anew with the next byte.
+    pub fn push(&mut self, byte: u8) -> Result<Option<char>, CustomError> {
+        if self.needed == 0 {
+            // The leading byte tells us how long the char is
+            self.needed = match byte {
+                0x00..=0x7F => return Ok(Some(byte as char)), // Plain ASCII, the most common case
+                0xC0..=0xDF => 2,
+                0xE0..=0xEF => 3,
+                0xF0..=0xF7 => 4,
+                _ => return Err(CE::BadInput), // A continuation byte without a leading one, or just garbage
+            };
+        } else if byte & 0xC0 != 0x80 {
+            self.reset();
+            return Err(CE::BadInput); // Expected a continuation byte
+        }
+
+        self.buf[self.len] = byte;
+        self.len += 1;
+        if self.len < self.needed {
+            return Ok(None);
+        }
+
+        // `from_utf8` also rejects the overlong encodings and surrogates for us
+        let result = core::str::from_utf8(&self.buf[..self.len])
+            .ok()
+            .and_then(|s| s.chars().next())
+            .ok_or(CE::BadInput);
+        self.reset();
+        result.map(Some)
+    }
+
+    /// Forgets the partially received char, if there's any.
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.needed = 0;
+    }
+}
@@ -0,0 +1,122 @@
+use heapless::Vec;
+use embedded_graphics::{
+    prelude::*,
+    primitives::{
+        Line,
+        PrimitiveStyle,
+        Rectangle,
+    },
+};
+
+use crate::decfix::DecimalFixed;
+use crate::display::{FlushableDisplay, Palette};
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Height of a single bar, in pixels
+const BAR_HEIGHT: u32 = 6;
+/// Space between two bars, in pixels
+const BAR_GAP: u32 = 2;
+/// Most bars a chart can have, as many as fit onto the 128 px tall SSD1327
+pub const MAX_BARS: usize = 16;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// How many bars fit onto a display of the height, at most `MAX_BARS`
+pub fn bar_count(height: u32) -> usize {
+    (((height + BAR_GAP) / (BAR_HEIGHT + BAR_GAP)) as usize).min(MAX_BARS)
+}
+
+/// Returns the column of zero and the columns each value's bar spans (the start included, the end not) on a chart of the width,
+/// scaled so that the value farthest from zero reaches the edge.
+///
+/// The bars start at the column of zero, which is at the left edge unless there's a negative value,
+/// so that negative values go left and positive ones right. Zero is a single column, so that it still shows up.
+/// Returns `BadInput` if there's more than `MAX_BARS` values.
+pub fn bars(values: &[DecimalFixed], width: u32) -> Result<(u32, Vec<(u32, u32), MAX_BARS>), CustomError> {
+    let mut raw: Vec<i128, MAX_BARS> = Vec::new();
+    for value in values {
+        raw.push(i128::from(value.with_exponent(None)?.value())).map_err(|_| CE::BadInput)?;
+    }
+    let low = raw.iter().copied().min().unwrap_or(0).min(0);
+    let high = raw.iter().copied().max().unwrap_or(0).max(0);
+    let last = i128::from(width.saturating_sub(1));
+    // All of them zero would be a division by zero, they're all at the left edge then anyway
+    let column = |value: i128| if high == low { 0 } else { ((value - low) * last / (high - low)) as u32 };
+
+    let zero = column(0);
+    Ok((zero, raw.iter().map(|&value| {
+        let end = column(value);
+        (zero.min(end), zero.max(end) + 1)
+    }).collect()))
+}
+
+/// Clears the display and draws the values as horizontal bars, the first one on top, see `bars()`.
+/// The column of zero gets a dimmed line through all of them.
+pub fn draw<D>(disp: &mut D, values: &[DecimalFixed]) -> Result<(), CustomError>
+where
+    D: FlushableDisplay,
+    CustomError: From<D::Error>,
+{
+    disp.clear(D::Color::BACKGROUND)?;
+    let area = disp.bounding_box();
+    let (zero, bars) = bars(values, area.size.width)?;
+
+    let x = area.top_left.x + zero as i32;
+    Line::new(Point::new(x, area.top_left.y), Point::new(x, area.top_left.y + area.size.height as i32 - 1))
+        .into_styled(PrimitiveStyle::with_stroke(D::Color::DIMMED, 1))
+        .draw(disp)?;
+    for (i, (start, end)) in bars.into_iter().enumerate() {
+        let top_left = area.top_left + Point::new(start as i32, (i as u32 * (BAR_HEIGHT + BAR_GAP)) as i32);
+        Rectangle::new(top_left, Size::new(end - start, BAR_HEIGHT))
+            .into_styled(PrimitiveStyle::with_fill(D::Color::FOREGROUND))
+            .draw(disp)?;
+    }
+    disp.flush_display()
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::{bars, bar_count, draw, MAX_BARS};
+    use crate::decfix::DecimalFixed;
+    use crate::display::NullDisplay;
+    use crate::custom_error::CE;
+
+    fn nums(values: &[&str]) -> std::vec::Vec<DecimalFixed> {
+        values.iter().map(|s| DecimalFixed::parse_str(s, None).unwrap()).collect()
+    }
+
+    #[test]
+    fn scales_to_the_widest_value() {
+        let (zero, bars) = bars(&nums(&["10", "5", "0", "2.5"]), 101).unwrap();
+        assert_eq!(zero, 0);
+        assert_eq!(bars.as_slice(), [(0, 101), (0, 51), (0, 1), (0, 26)]);
+        // The same with all of them negative, from the right edge
+        let (zero, bars) = super::bars(&nums(&["-10", "-5"]), 101).unwrap();
+        assert_eq!(zero, 100);
+        assert_eq!(bars.as_slice(), [(0, 101), (50, 101)]);
+        assert_eq!(super::bars(&nums(&["0", "0"]), 101).unwrap().1.as_slice(), [(0, 1), (0, 1)]);
+    }
+
+    #[test]
+    fn negative_values_go_left_of_zero() {
+        let (zero, bars) = bars(&nums(&["-1", "3"]), 101).unwrap();
+        assert_eq!(zero, 25);
+        assert_eq!(bars.as_slice(), [(0, 26), (25, 101)]);
+        draw(&mut NullDisplay, &nums(&["-1", "3"])).unwrap();
+    }
+
+    #[test]
+    fn fits_the_display() {
+        assert_eq!(bar_count(64), 8);
+        assert_eq!(bar_count(128), 16);
+        assert_eq!(bars(&[DecimalFixed::ZERO; MAX_BARS + 1], 128), Err(CE::BadInput));
+    }
+}
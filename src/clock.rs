@@ -0,0 +1,181 @@
+use rp2040_hal::{self as hal, pac};
+use hal::rtc::{DateTime, DayOfWeek, RealTimeClock};
+
+use crate::pages::WallTime;
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// What the RTC starts at on boot, it has no battery to keep the time over a reset
+const BOOT_YEAR: u16 = 2000;
+/// The RTC only counts the years up to this one
+const MAX_YEAR: u16 = 4095;
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Keeps the wall-clock time in the RP2040's RTC, see the `time`, `date`, `settime` and `setdate` commands.
+///
+/// There's no timezone, whatever the user sets is the local time. The time is lost on reset,
+/// so until it's set, it starts at midnight of 2000-01-01 and isn't shown in the status bar.
+pub struct WallClock {
+    rtc: RealTimeClock,
+    /// Whether the user has set the time or the date since boot
+    set: bool,
+}
+
+impl WallClock {
+    pub fn new(rtc: pac::RTC, clock: hal::clocks::RtcClock, resets: &mut pac::RESETS) -> Self {
+        let boot = DateTime {
+            year: BOOT_YEAR,
+            month: 1,
+            day: 1,
+            day_of_week: DayOfWeek::Saturday,
+            hour: 0,
+            minute: 0,
+            second: 0,
+        };
+        let rtc = RealTimeClock::new(rtc, clock, resets, boot).expect("The boot date is valid");
+        WallClock { rtc, set: false }
+    }
+
+    /// Whether the time means anything, i.e. the user has set it since boot
+    pub fn is_set(&self) -> bool {
+        self.set
+    }
+
+    pub fn now(&self) -> Result<DateTime, CustomError> {
+        self.rtc.now().map_err(|e| {
+            log_error!("Failed to read the RTC: {:?}", e);
+            CE::Other
+        })
+    }
+
+    /// Hours and minutes for the status bar, `None` until the time is set
+    pub fn status_time(&self) -> Option<(u8, u8)> {
+        if !self.set {
+            return None;
+        }
+        self.now().ok().map(|now| (now.hour, now.minute))
+    }
+
+    /// The date and time for the clock page, `None` until the time is set
+    pub fn wall_time(&self) -> Option<WallTime> {
+        if !self.set {
+            return None;
+        }
+        self.now().ok().map(|now| WallTime {
+            year: now.year,
+            month: now.month,
+            day: now.day,
+            day_name: day_name(now.day_of_week),
+            hour: now.hour,
+            minute: now.minute,
+            second: now.second,
+        })
+    }
+
+    /// Sets the time of day, keeping the date.
+    pub fn set_time(&mut self, hour: u8, minute: u8, second: u8) -> Result<(), CustomError> {
+        if hour > 23 || minute > 59 || second > 59 {
+            return Err(CE::BadInput);
+        }
+        let now = self.now()?;
+        self.set_datetime(DateTime { hour, minute, second, ..now })
+    }
+
+    /// Sets the date, keeping the time of day. The day of the week gets computed.
+    pub fn set_date(&mut self, year: u16, month: u8, day: u8) -> Result<(), CustomError> {
+        if year > MAX_YEAR || !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+            return Err(CE::BadInput);
+        }
+        let day_of_week = day_of_week(days_from_epoch(year, month, day));
+        let now = self.now()?;
+        self.set_datetime(DateTime { year, month, day, day_of_week, ..now })
+    }
+
+    fn set_datetime(&mut self, datetime: DateTime) -> Result<(), CustomError> {
+        self.rtc.set_datetime(datetime).map_err(|e| {
+            log_error!("Failed to set the RTC: {:?}", e);
+            CE::BadInput
+        })?;
+        self.set = true;
+        Ok(())
+    }
+
+    /// Seconds since 1970-01-01 00:00:00 like a Unix timestamp, except that it's in the local time
+    pub fn timestamp(&self) -> Result<i64, CustomError> {
+        let now = self.now()?;
+        Ok(days_from_epoch(now.year, now.month, now.day) * SECONDS_PER_DAY
+            + i64::from(now.hour) * 3600 + i64::from(now.minute) * 60 + i64::from(now.second))
+    }
+}
+
+fn is_leap_year(year: u16) -> bool {
+    year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400))
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days from 1970-01-01 to the date, see Howard Hinnant's `days_from_civil()`
+fn days_from_epoch(year: u16, month: u8, day: u8) -> i64 {
+    // Counting the years from March, so that the leap day is the last one of the year
+    let year = i64::from(year) - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_from_march = (i64::from(month) + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn day_of_week(days_from_epoch: i64) -> DayOfWeek {
+    // 1970-01-01 was a Thursday
+    match (days_from_epoch + 4).rem_euclid(7) {
+        0 => DayOfWeek::Sunday,
+        1 => DayOfWeek::Monday,
+        2 => DayOfWeek::Tuesday,
+        3 => DayOfWeek::Wednesday,
+        4 => DayOfWeek::Thursday,
+        5 => DayOfWeek::Friday,
+        _ => DayOfWeek::Saturday,
+    }
+}
+
+/// Three-letter English name of the day
+pub fn day_name(day_of_week: DayOfWeek) -> &'static str {
+    match day_of_week {
+        DayOfWeek::Sunday => "Sun",
+        DayOfWeek::Monday => "Mon",
+        DayOfWeek::Tuesday => "Tue",
+        DayOfWeek::Wednesday => "Wed",
+        DayOfWeek::Thursday => "Thu",
+        DayOfWeek::Friday => "Fri",
+        DayOfWeek::Saturday => "Sat",
+    }
+}
+
+/// Parses `a<sep>b<sep>c`, e.g. `12:34:56` or `2024-05-06`, into its three numbers.
+pub fn parse_triple<A, B, C>(input: &str, separator: char) -> Result<(A, B, C), CustomError>
+where
+    A: core::str::FromStr<Err = core::num::ParseIntError>,
+    B: core::str::FromStr<Err = core::num::ParseIntError>,
+    C: core::str::FromStr<Err = core::num::ParseIntError>,
+{
+    let mut parts = input.split(separator);
+    let (Some(a), Some(b), Some(c), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+        return Err(CE::BadInput);
+    };
+    Ok((a.parse()?, b.parse()?, c.parse()?))
+}
@@ -1,106 +1,188 @@
-use defmt::*;
 use rp2040_hal as hal;
-use heapless::Vec;
 use core::cell::RefCell;
 
-use ssd1306::{prelude::*, Ssd1306, mode::BufferedGraphicsMode};
-
-
 // Because we already have the `mod` in `main.rs`
+use crate::display::{self, Panel};
 use crate::textbox::CustomTextbox;
-use crate::stack::CustomStack;
+use crate::stack_set::StackSet;
+use crate::decfix::DecimalFixed;
+use crate::state::CalcState;
+use crate::vsys::Vsys;
+use crate::clock::WallClock;
+use crate::commands::{self, Context};
+use crate::uart_rx::UartRx;
+use crate::charset::{self, Utf8Decoder};
 use crate::custom_error::{
-    CustomError,
-    CE // Short type alias
+    CE, // Short type alias
+    WithContext,
 };
 
-/// # List of commands:
-/// 
-/// - `reset`: Reset the microcontroller
-/// - `breakpoint` (aliases: `bkpt`, `b`): Trigger a breakpoint set in your debugger/IDE
-/// - `breakpoint alt` (aliases: `bkpt alt`, `b alt`): Trigger an inline breakpoint instruction (causes exception if no debugger attached)
-/// - `boot usb` (aliases: `usb boot`, `usb`): Reboot into the USB bootloader
-/// - `redraw` (aliases: `refresh`, `reload`, `r`, `f5`): Force a redraw of stack
-///   - Also can be triggered by pressing Ctrl-R, when it also redraws the textbox.
-/// - `brightness N` (aliases: `brt N`): Set display brightness to a predefined level between 1 and 5
-/// - `clear` (aliases: `cls`, `c`): Clear the stack
-/// - `duplicate` (aliases: `dup`): Duplicate the top element of the stack
-/// - `drop`: Remove the top element of the stack
-///   - `drop N`: Remove the top N elements of the stack (where N is a positive integer not exceeding the current stack size)
-/// - `swap` (aliases: `s`): Swap the top two elements of the stack
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// How long to wait for the rest of an escape sequence to arrive, in CPU cycles (50 ms at the default 125 MHz system clock).
+/// We don't have the `Delay` in here (nor in the `menu` command), so we just burn the cycles.
+pub const ESCAPE_WAIT_CYCLES: u32 = 125_000_000 / 20;
+/// Drawn before the input in command mode, so that it's obvious we're not entering a number
+pub const PROMPT: &str = "> ";
+/// Shown dimmed in the empty textbox in command mode
+pub const PLACEHOLDER: &str = "command";
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Reads a command from UART into the textbox, and runs it once Enter is pressed.
+/// See `commands::registry()` for the list of commands.
 /// 
 /// Empty commands are ignored, pressing Ctrl-C cancels command input.
-pub fn handle_commands<'a, DI, SIZE, T, D, P> (
-    uart_rx: &'a hal::uart::Reader<D, P>,
-    disp_refcell: &'a RefCell<Ssd1306<DI, SIZE, BufferedGraphicsMode<SIZE>>>,
-    textbox: &mut CustomTextbox<'a, DI, SIZE>,
-    stack: &mut CustomStack<'a, T, DI, SIZE>,
-) -> Result<(), CustomError>
+/// The left and right arrow keys move the cursor, so that typos can be fixed without retyping the whole command.
+/// Like in a shell, Ctrl-A (or Home) and Ctrl-E (or End) jump to the start and end of the line,
+/// Ctrl-W deletes the word before the cursor and Ctrl-U everything before the cursor.
+#[allow(clippy::too_many_arguments)] // Bundling them into a struct would only move the problem to `main.rs`
+pub fn handle_commands<'a, D, R, U, P> (
+    uart_rx: &'a R,
+    uart_tx: &'a hal::uart::Writer<U, P>,
+    uart_clock_hz: u32,
+    disp_refcell: &'a RefCell<D>,
+    textbox: &mut CustomTextbox<'a, D>,
+    stack: &mut StackSet<'a, DecimalFixed, D>,
+    state: &mut CalcState,
+    vsys: &mut Vsys,
+    clock: &mut WallClock,
+) -> Result<(), WithContext>
 where
-    DI: WriteOnlyDataCommand,
-    SIZE: DisplaySize,
-    T: core::fmt::Display, // For the draw() method of the stack
-    T: Clone, // Needed for duplicating stack elements
+    D: Panel,
 
-    D: hal::uart::UartDevice,
-    P: hal::uart::ValidUartPinout<D>
+    R: UartRx,
+    U: hal::uart::UartDevice,
+    P: hal::uart::ValidUartPinout<U>
 {
-    info!("Entering command mode");
+    log_info!("Entering command mode");
     textbox.clear();
     textbox.draw(true)?;
 
     { // We limit the scope of the mutable borrow to limit the lifetime of the RefMut and prevent panicking upon double-borrow
         let mut disp = disp_refcell.borrow_mut();
-        disp.set_invert(true)?;
+        disp.set_inverted(!state.settings.inverted)?; // The opposite of the usual, so that it's obvious
     }   
 
     let mut buf: [u8; 1] = [0];
     let mut char_buf: char; // We declare it uninitialised mutable here to save on repeated stack allocations (as you should with buffers used in a loop)
+    let mut decoder = Utf8Decoder::new(); // Non-ASCII chars arrive over multiple bytes
 
     // The label is unnecessary, just for clarity
     'read_loop: loop {
+        crate::uart_tx::drain_log_mirror(uart_tx, state.settings.crlf);
+        display::flush_before_blocking(disp_refcell)?;
         if let Err(e) = uart_rx.read_full_blocking(&mut buf) {
-            error!("Failed to read from UART: {:?}", e);
+            log_error!("Failed to read from UART: {:?}", e);
             if let hal::uart::ReadErrorType::Break = e {
-                debug!("Check wiring, usually a break indicates a disconnected wire at the RX pin.");
+                log_debug!("Check wiring, usually a break indicates a disconnected wire at the RX pin.");
             };
-            return Err(e.into());
+            return Err(CE::from(e).into());
         };   
-        char_buf = char::from_u32(buf[0] as u32).unwrap_or('?'); // Replace invalid UTF-8 with a replacement character
+        char_buf = match decoder.push(buf[0]) {
+            Ok(Some(c)) => {
+                crate::uart_tx::echo(uart_tx, c, &state.settings);
+                c
+            },
+            Ok(None) => continue 'read_loop, // The rest of the char is yet to come
+            Err(_) => {
+                log_warn!("Received invalid UTF-8 in command mode, last byte 0x{:X}", buf[0]);
+                continue 'read_loop;
+            }
+        };
 
         match char_buf {
             '\x03' => { // Ctrl-C
-                info!("Aborting command input on Ctrl-C");
+                log_info!("Aborting command input on Ctrl-C");
                 textbox.clear();
                 textbox.draw(true)?;
                 {
                     let mut disp = disp_refcell.borrow_mut();
-                    disp.set_invert(false)?;
+                    disp.set_inverted(state.settings.inverted)?;
                 }
-                return Err(CE::Cancelled);
+                return Err(CE::Cancelled.into());
             },
             '\r' | '\n' => break 'read_loop, // Enter key - breaks out of the reading loop
+            '\x01' => { // Ctrl-A
+                textbox.home();
+                textbox.draw(true)?;
+            },
+            '\x05' => { // Ctrl-E
+                textbox.end();
+                textbox.draw(true)?;
+            },
+            '\x17' => { // Ctrl-W
+                if textbox.delete_word_back() {
+                    textbox.draw(true)?;
+                }
+            },
+            '\x15' => { // Ctrl-U
+                textbox.clear_line();
+                textbox.draw(true)?;
+            },
             '\x08' | '\x7F' => { // Backspace
-                trace!("Backspace character received in command mode: (0x{:X})", buf[0]);
+                log_trace!("Backspace character received in command mode: (0x{:X})", buf[0]);
 
-                if textbox.is_empty() {
-                    info!("Ignoring backspace on empty textbox in command mode.");
+                if textbox.cursor() == 0 {
+                    log_info!("Ignoring backspace with nothing before the cursor in command mode.");
                     continue 'read_loop; // Diverging, does not continue forwards
                 };
                 if textbox.backspace(1).is_err() {
-                    error!("Failed to backspace textbox in command mode");
-                    error!("This should normally be impossible, we already checked it's not empty");
-                    return Err(CE::Impossible);
+                    log_error!("Failed to backspace textbox in command mode");
+                    log_error!("This should normally be impossible, we already checked there's something before the cursor");
+                    return Err(CE::Impossible.into());
                 };
                 textbox.draw(true)?;
             },
-            'a'..='z' | 'A'..='Z' | '0'..='9' | ' ' => { // Allowed characters
+            '\x1B' => { // Escape character - start of an escape sequence
+                let mut seq = [0_u8; 8];
+                seq[0] = 0x1B; // We already read the first byte, so store it
+
+                cortex_m::asm::delay(ESCAPE_WAIT_CYCLES); // HACK: Same as in `main()`, wait a bit to allow the rest of the sequence to arrive.
+                let num_bytes = uart_rx.read_available(&mut seq[1..]); // Nonblocking
+                if num_bytes == 0 {
+                    log_trace!("Escape byte received in command mode: 0x1B");
+                    continue 'read_loop;
+                };
+
+                // With the RangeToInclusive, we account for the first byte
+                match &seq[..=num_bytes] {
+                    b"\x1B[D" => { // Left arrow
+                        if textbox.cursor_left() {
+                            textbox.draw(true)?;
+                        }
+                    },
+                    b"\x1B[C" => { // Right arrow
+                        if textbox.cursor_right() {
+                            textbox.draw(true)?;
+                        }
+                    },
+                    // Terminals can't agree on what Home and End send, these are the most common ones
+                    b"\x1B[H" | b"\x1B[1~" | b"\x1BOH" => {
+                        textbox.home();
+                        textbox.draw(true)?;
+                    },
+                    b"\x1B[F" | b"\x1B[4~" | b"\x1BOF" => {
+                        textbox.end();
+                        textbox.draw(true)?;
+                    },
+                    other => log_trace!("Ignoring escape sequence received in command mode: {:?}", other),
+                };
+            },
+            // Allowed characters (the plus is for `sto+`, the arrow for `r->p`, the quote for arguments with spaces, the rest for decimal arguments)
+            'a'..='z' | 'A'..='Z' | '0'..='9' | ' ' | '+' | '>' | '"' | '.' | '-' => {
                 char_buf.make_ascii_lowercase();
                 textbox.append_char(char_buf)?;
                 textbox.draw(true)?;
             },
+            c if !c.is_ascii() && charset::is_printable(c) => { // Letters with diacritics etc. that our font can show, e.g. for labels
+                // Lowercase letters of ISO 8859-2 are in it too, so it's always a single char
+                textbox.append_char(c.to_lowercase().next().unwrap_or(c))?;
+                textbox.draw(true)?;
+            },
             _ => { // Ignore other characters
-                trace!("Ignoring unsupported character received in command mode: {:?} (0x{:X})", char_buf, buf[0]);
+                log_trace!("Ignoring unsupported character received in command mode: {:?} (0x{:X})", char_buf, buf[0]);
                 // No need for continue, we just loop again anyway
             },
         }
@@ -110,189 +192,95 @@ where
     let command = textbox.get_text_str()
         .trim(); // Trim all Unicode whitespaces from both ends (including newlines)
 
-    match command {
-        "reset" => {
-            error!("Resetting microcontroller (command 'reset')");
-            cortex_m::peripheral::SCB::sys_reset(); // Reset the microcontroller
-        },
-
-        "b" | "bkpt" | "breakpoint" => {
-            // Here should be a breakpoint for debugging purposes in your IDE:
-            debug!("Breakpoint requested by user (command 'breakpoint')");
-        },
-
-        "b alt" | "bkpt alt" | "breakpoint alt" => {
-            debug!("Alternative breakpoint requested by user (command 'breakpoint alt')");
-            // Will cause an exception if no debugger is attached
-            // SAFETY: We know this instruction does not meddle with any registers, and that this is valid assembly, so it has to be safe.
-            // By inlining it without a function call, we keep access to local variables if needed for debugging.
-            unsafe { core::arch::asm!("bkpt"); } // Inline breakpoint instruction
-        },
-
-        "boot usb" | "usb boot" | "usb" => {
-            info!("Rebooting into USB bootloader (command 'boot usb')");
-            {
-                let mut disp = disp_refcell.borrow_mut();
-                disp.set_display_on(false)?; // Turns the display off (well, only the grahpics part, it still retains memory) for conventince
-            }
-            hal::rom_data::reset_to_usb_boot(1 << 25, 0); // Pin 25 for activity LED, both MSC and Picoboot enabled.
-        },
-
-        "r" | "f5" | "refresh" | "reload" | "redraw" => {
-            info!("Doing a forced redraw of stack. (command 'redraw')");
-            stack.draw(true)?; // Just to be sure, we force a flush
-        },
-
-        // It is possible to use an array of char-s in starts_with, but not strings, so this is the next best thing.
-        // https://stackoverflow.com/a/76964109
-        brt_cmd if ["brt", "brightness"].iter().any(|s| brt_cmd.starts_with(*s)) => {
-            let split = brt_cmd.rsplit_once(" ")
-                .expect("Should contain a space; we checked in the match guard!");
-
-            if split.0 != "brt" && split.0 != "brightness" {
-                error!("First part isn't \"brt\" nor \"brightness\", input must've contained multiple spaces.");
-                return Err(CE::BadInput);
-            }
-
-            let brightness_num = split.1.parse::<u8>()?;
-            let brightness = match brightness_num {
-                1 => Brightness::DIMMEST,
-                2 => Brightness::DIM,
-                3 => Brightness::NORMAL,
-                4 => Brightness::BRIGHT,
-                5 => Brightness::BRIGHTEST,
-                _ => {
-                    warn!("Brightness value out of range (1-5): {}", brightness_num);
-                    return Err(CE::BadInput);
-                }
-            };
-            {
-                let mut disp = disp_refcell.borrow_mut();
-                disp.set_brightness(brightness)?;
-            };
-        },
-
-        "c" | "cls" | "clear" => { // We automatically cleared the textbox when switching to command mode
-            if stack.is_empty() {
-                info!("Stack is already empty, ignoring clear command.");
-            } else {
-                info!("Clearing stack by user request (command 'clear')");
-                stack.clear();
-                stack.draw(false)?; // No need to force flush here, we flush after the match block anyway
-            }
-        },
-
-        "dup" | "duplicate" => {
-            if let Some(val) = stack.peek() {
-                if stack.push(val.clone()).is_err() {
-                    error!("Failed to duplicate top element of stack: CapacityError");
-                    return Err(CE::CapacityError);
-                };
-                stack.draw(false)?;
-            } else {
-                warn!("Failed to duplicate top element of stack: stack is empty");
-                return Err(CE::BadInput);
-            }
-        },
-
-        drop_cmd if drop_cmd.starts_with("drop ") => {
-            // By reverse-splitting instead of splitting, we ensure that in case of multiple spaces,
-            // the *first* part would be malformed if there were multiple spaces.
-            let split = drop_cmd.rsplit_once(" ")
-                .expect("Should contain a space; we checked in the match guard!");
-
-            if split.0 != "drop" {
-                error!("First part isn't \"drop\", input must've contained multiple spaces.");
-                return Err(CE::BadInput);
-            }
-
-            let count = split.1.parse::<usize>()?;
-            // This checks if the stack isn't empty as well in sort of a roundabout way
-            // (non-zero count will always be greater than stack size if stack is empty)
-            if (count == 0) || (count > stack.len()) {
-                return Err(CE::BadInput);
-            }
-
-            let iter = stack.multipop(count).expect("We already checked if the stack is not empty!");
-
-            // We need this, can't rely on macro debug_assert_eq!()
-            #[cfg(debug_assertions)]
-            defmt::assert_eq!(iter.count(), count); // Counting the number of stuff popped
-            // (consuming the iterator in the process), and asserting that it's as expected.
-
-            // With this attribute, compiler is happy that iterator gets consumed before `draw()` no matter what
-            #[cfg(not(debug_assertions))]
-            drop(iter); // Automatically pops remaining unconsumed elements without bothering to count them
-
-            stack.draw(false)?; // The cfg-s does not apply to this line anymore
-        },
-
-        "drop" => {
-            if stack.pop().is_none() {
-                warn!("Failed to drop top element of stack: stack is empty.");
-                return Err(CE::BadInput);
-            };
-            stack.draw(false)?;
-        },
-
-        "s" | "swap" => {
-            // multipop can only fail if there are no elements on the stack,
-            // **by design** it will just return fewer elements if there are not enough,
-            // so we have to check the stack length ourselves.
-            if stack.len() < 2 {
-                warn!("Not enough numbers on stack to perform swap. Need 2, got {}.", stack.len());
-                return Err(CE::BadInput);
-            }
-
-            // Remember, multipop yields elements in reverse order (topmost first)...
-            let Some(iter) = stack.multipop(2) else {
-                error!("Failed to pop elements from stack for swap.");
-                error!("This should be impossible, we already checked that there are at least 2 elements on the stack.");
-                return Err(CE::Impossible);
-            };
-
-            // We collect the iterator into a Vec first
-            // (Cannot push the iterator directly because then we'd have two mutable borrows at once)
-            let buf_vec: Vec<T, 2> = iter.collect();
-            let Ok(buf) = buf_vec.into_array::<2>() else {
-                error!("Failed to collect popped elements into array for swap.");
-                error!("This should be impossible, we already checked that we popped exactly 2 elements.");
-                return Err(CE::Impossible);
-            };
-
-            // ...and push_array pushes them in order (last topmost), swapping them
-            if stack.push_array(buf).is_err() {
-                error!("Failed to push numbers onto stack: CapacityError");
-                error!("This should be impossible, the stack should have enough space since we already popped from it.");
-                return Err(CE::Impossible);
-            };
-
-            stack.draw(false)?;
-        },
-
-        "" => {
-            debug!("Ignoring empty command.");
-            textbox.draw(true)?;
-            {
-                let mut disp = disp_refcell.borrow_mut();
-                disp.set_invert(false)?;
-            }
-            return Err(CE::Cancelled);
-        },
-        
-        _ => {
-            warn!("Unknown command received over UART: {:?}", command);
-            return Err(CE::BadInput);
+    if command.is_empty() {
+        log_debug!("Ignoring empty command.");
+        textbox.draw(true)?;
+        {
+            let mut disp = disp_refcell.borrow_mut();
+            disp.set_inverted(state.settings.inverted)?;
         }
+        return Err(CE::Cancelled.into());
     }
 
+    run_command(command, uart_rx, uart_tx, uart_clock_hz, disp_refcell, stack, state, vsys, clock)?;
+
     {
         let mut disp = disp_refcell.borrow_mut();
-        disp.set_invert(false)?;
+        disp.set_inverted(state.settings.inverted)?;
     }
     
     // Have to clear textbox after handling command because get_text_str() keeps a borrow on it
     textbox.clear();
     textbox.draw(true)?;
     Ok(())
-}
\ No newline at end of file
+}
+/// Runs the command line with a `Context` of UART and the rest, e.g. one entered in command mode or played back from a macro.
+#[allow(clippy::too_many_arguments)] // The same as `handle_commands()`
+pub fn run_command<'a, D, R, U, P> (
+    command: &str,
+    uart_rx: &'a R,
+    uart_tx: &'a hal::uart::Writer<U, P>,
+    uart_clock_hz: u32,
+    disp_refcell: &'a RefCell<D>,
+    stack: &mut StackSet<'a, DecimalFixed, D>,
+    state: &mut CalcState,
+    vsys: &mut Vsys,
+    clock: &mut WallClock,
+) -> Result<(), WithContext>
+where
+    D: Panel,
+
+    R: UartRx,
+    U: hal::uart::UartDevice,
+    P: hal::uart::ValidUartPinout<U>
+{
+    let crlf = state.settings.crlf; // Copied, since the context borrows the whole state
+    let print = |bytes: &[u8]| crate::uart_tx::write(uart_tx, bytes, crlf); // The module, not the parameter
+    let print_binary = |bytes: &[u8]| crate::uart_tx::write_binary(uart_tx, bytes);
+    run_command_printing(command, &print, &print_binary, uart_rx, uart_clock_hz, disp_refcell, stack, state, vsys, clock)
+}
+
+/// Same as `run_command()`, but the output goes to `print` (and `print_binary`, see `Context`) instead of UART,
+/// e.g. to be sent back to the host (see `hostlink`). The input still comes from UART.
+#[allow(clippy::too_many_arguments)] // The same as `handle_commands()`
+pub fn run_command_printing<'a, D, R> (
+    command: &str,
+    print: &dyn Fn(&[u8]),
+    print_binary: &dyn Fn(&[u8]),
+    uart_rx: &'a R,
+    uart_clock_hz: u32,
+    disp_refcell: &'a RefCell<D>,
+    stack: &mut StackSet<'a, DecimalFixed, D>,
+    state: &mut CalcState,
+    vsys: &mut Vsys,
+    clock: &mut WallClock,
+) -> Result<(), WithContext>
+where
+    D: Panel,
+
+    R: UartRx,
+{
+    let read_byte = || {
+        let mut buf = [0_u8; 1];
+        display::flush_before_blocking(disp_refcell)?;
+        uart_rx.read_full_blocking(&mut buf)?;
+        Ok(buf[0])
+    };
+    let poll_byte = || {
+        let mut buf = [0_u8; 1];
+        (uart_rx.read_available(&mut buf) > 0).then_some(buf[0])
+    };
+    let mut ctx = Context {
+        print,
+        print_binary,
+        read_byte: &read_byte,
+        poll_byte: &poll_byte,
+        uart_clock_hz,
+        disp_refcell,
+        stack,
+        state,
+        vsys,
+        clock,
+    };
+    commands::execute_with_context(command, &mut ctx)
+}
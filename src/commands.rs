@@ -0,0 +1,3665 @@
+use defmt::*;
+use rp2040_hal as hal;
+use core::cell::RefCell;
+
+use heapless::{String, Vec};
+use embedded_graphics::{
+    prelude::*,
+    mono_font::{iso_8859_2::FONT_6X12 as ISO_FONT_6X12, MonoTextStyle},
+    text::{Baseline, Text},
+};
+
+use crate::display::{Palette, Panel};
+use crate::stack_set::{StackSet, WORKSPACE_COUNT};
+use crate::stack::NumberFormat;
+use crate::decfix::DecimalFixed;
+use crate::state::CalcState;
+use crate::vsys::Vsys;
+use crate::clock::{self, WallClock};
+use crate::night::{NightSchedule, DEFAULT_NIGHT_CONTRAST};
+use crate::registers::{RegisterFile, REGISTER_COUNT};
+use crate::modbus::{self, Exception, Register, RegisterMap, MAX_SCALE, NOT_A_NUMBER};
+use crate::vector::{Vector, MAX_DIMENSIONS};
+use crate::poly::MAX_DEGREE;
+use crate::plot::{self, Plot};
+use crate::chart;
+use crate::screenshot::{self, Format};
+use crate::scpi::{self, ErrorQueue, Header, ScpiCommand, ScpiError};
+use crate::paste;
+use crate::sensors;
+use crate::numtheory;
+use crate::polar::{self, AngleUnit};
+use crate::db::RatioKind;
+use crate::menu::{self, Menu, MenuAction, Selection};
+use crate::pages::PageKind;
+use crate::command_mode;
+use crate::textbox::TEXT_BUFFER_SIZE;
+use crate::persist;
+use crate::snapshots;
+use crate::baud;
+use crate::log::{self, Level};
+use crate::selftest;
+use crate::hiltest;
+use crate::errlog::{ErrorLog, ERROR_LOG_SIZE};
+use crate::screensaver::SaverMode;
+use crate::led::LedMode;
+use crate::get_timestamp_us;
+use crate::args::{self, Args};
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE, // Short type alias
+    WithContext,
+    TruncatingString,
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Number of commands in the registry, see `registry()`
+pub const COMMAND_COUNT: usize = 101;
+/// Ends the input of the `script` command (Ctrl-D, like the end of file in a terminal)
+const END_OF_SCRIPT: u8 = 0x04;
+/// Cancels the `script` command (Ctrl-C, like everywhere else)
+const CANCEL_SCRIPT: u8 = 0x03;
+/// Height of a line of the full-screen text pages, the font's height
+const PAGE_LINE_HEIGHT: u32 = 12;
+/// Width of a character of the full-screen text pages, the font's width
+const PAGE_CHAR_WIDTH: u32 = 6;
+/// Longest text of a vector printed by the vector commands, enough for three numbers with all their digits
+const VECTOR_TEXT_SIZE: usize = 96;
+/// Longest text of a polynomial printed by `poly`, longer ones get cut off
+const POLY_TEXT_SIZE: usize = 128;
+/// Longest line of the ranges printed by `plot`, enough for two numbers with all their digits
+const RANGE_TEXT_SIZE: usize = 64;
+/// Longest line the `load` command accepts, longer ones are rejected
+const LOAD_LINE_SIZE: usize = 40;
+/// How long the `baud` command waits for Enter at the new baud rate before switching back, in microseconds
+const BAUD_CONFIRM_US: u64 = 10_000_000;
+/// Longest line the `scpi` command accepts, longer than a command line since a line can have several commands
+const SCPI_LINE_SIZE: usize = 128;
+/// Longest line of responses the `scpi` command sends, those of all the queries of a line together
+const SCPI_RESPONSE_SIZE: usize = 256;
+/// `*IDN?` says the firmware is of this make and model, there's no company and no serial number
+const SCPI_IDENTITY: &str = "maturitni-projekt,RPN calculator,0";
+/// The version of the SCPI standard `SYSTem:VERSion?` reports
+const SCPI_VERSION: &str = "1999.0";
+/// Decimal places of the scaled integers of `modbus` unless it says otherwise, e.g. 1.5 being 1500
+const MODBUS_DEFAULT_SCALE: u8 = 3;
+/// Highest unit address of a Modbus slave, the ones above are reserved
+const MODBUS_MAX_UNIT: u8 = 247;
+/// Most decimal places `fix` and `sci` accept, more than the default exponent's 9 would only add made-up zeroes anyway
+const MAX_DECIMAL_PLACES: usize = 12;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Contrast of the levels of the `brightness` command, the same as the `ssd1306` presets from `DIMMEST` to `BRIGHTEST`
+const BRIGHTNESS_LEVELS: [u8; 5] = [0x00, 0x2F, 0x5F, 0x9F, 0xFF];
+
+/// Style of the full-screen text pages, e.g. the `regs` view, the same font as the stack's
+const fn page_style<C: Palette>() -> MonoTextStyle<'static, C> {
+    MonoTextStyle::new(&ISO_FONT_6X12, C::FOREGROUND)
+}
+
+/// Whether a command takes arguments (the tokens after its name, see `Args`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum ArgSpec {
+    /// The command must not be followed by anything
+    None,
+    /// The command must be followed by an argument
+    Required,
+    /// The command works both with and without an argument
+    Optional,
+}
+
+/// Everything a command can work with.
+///
+/// The textbox isn't in here, since the input we're handling still borrows it.
+pub struct Context<'c, 'a, D>
+where
+    D: Panel,
+{
+    /// Writes the bytes out over UART, so that the commands don't have to be generic over its pins
+    pub print: &'c dyn Fn(&[u8]),
+    /// Writes the bytes out over UART as they are, for binary data such as the frames of `modbus`
+    pub print_binary: &'c dyn Fn(&[u8]),
+    /// Reads a single byte from UART, blocking until it arrives
+    pub read_byte: &'c dyn Fn() -> Result<u8, CustomError>,
+    /// Reads a single byte from UART if one has arrived already, without blocking
+    pub poll_byte: &'c dyn Fn() -> Option<u8>,
+    /// Frequency of the clock the UART runs from, for computing the baud rate divisors
+    pub uart_clock_hz: u32,
+    pub disp_refcell: &'a RefCell<D>,
+    pub stack: &'c mut StackSet<'a, DecimalFixed, D>,
+    pub state: &'c mut CalcState,
+    pub vsys: &'c mut Vsys,
+    pub clock: &'c mut WallClock,
+}
+
+/// A single command of command mode, see `registry()` for all of them.
+///
+/// The stack is intentionally not generic, only for DecimalFixed, same as in `parse_textbox()`,
+/// since the register commands need to do arithmetics on the values.
+pub trait Command<D>
+where
+    D: Panel,
+{
+    /// The name of the command first, then its aliases
+    fn names(&self) -> &'static [&'static str];
+    /// How to use it and what it does, printed by `help`
+    fn usage(&self) -> &'static str;
+    /// Whether it takes an argument, the dispatcher checks it before calling `run()`
+    fn args(&self) -> ArgSpec {
+        ArgSpec::None
+    }
+    /// Does the work. `args` are the tokens after the command's name, call `args.finish()` once you've taken all you want.
+    fn run(&self, ctx: &mut Context<'_, '_, D>, args: Args<'_>) -> Result<(), CustomError>;
+
+    fn name(&self) -> &'static str {
+        self.names()[0]
+    }
+}
+
+/// # List of commands:
+///
+/// - `help`: Print the list of commands with their usage over UART
+///   - `help CMD`: Print the usage of a single command (CMD can also be any of its aliases)
+/// - `menu`: Open the menu on the display, nested lists of settings, conversions, constants and programs, so that they don't have to be typed
+///   - Also opened by Ctrl-O outside of command mode. Up and Down (or turning the rotary encoder) move, Enter, Right
+///     (or pressing the encoder) chooses, Left, Backspace or Esc go back, Ctrl-C closes it. On the keypad, 2, 8, 4 and 6 are the arrows.
+///   - Choosing a command closes the menu and runs it, a constant gets pushed. Every list ends with an item going back.
+/// - `version` (aliases: `ver`): Print the firmware version, git revision, build profile and rp2040-hal version (and the heap usage with `alloc`),
+///   and show them on the display until a key is pressed
+/// - `selftest`: Flash test patterns on the display, test a bit of RAM, the stack and number formatting,
+///   then show which of them passed until a key is pressed
+/// - `hiltest`: Run a scripted sequence of pushes, pops, arithmetics, formatting, drawing and error paths, for a CI job with a real Pico attached
+///   - Prints `HILTEST BEGIN VERSION REVISION`, then `HILTEST STEP PASS` or `HILTEST STEP FAIL ERROR` for each step,
+///     and `HILTEST END PASS N/M` or `HILTEST END FAIL N/M` last. It doesn't wait for anything, and leaves the stack as it was.
+/// - `reset`: Save the stack into flash and reset the microcontroller
+/// - `persist`: Save the stack into flash, it gets restored automatically on boot
+///   - `save`: The same, kept for compatibility
+/// - `save NAME`: Save the stack and the registers into flash as a snapshot named NAME (up to 8 characters), replacing one of the same name
+///   - There's room for 16 snapshots, kept in a wear-leveled key-value store. Only the elements in RAM are saved, not the ones spilled into flash, nor the labels.
+/// - `loadsnap NAME`: Replace the stack and the registers with the snapshot named NAME
+/// - `snaps`: List the saved snapshots
+///   - `snaps del NAME`: Delete the snapshot named NAME
+/// - `breakpoint` (aliases: `bkpt`, `b`): Trigger a breakpoint set in your debugger/IDE
+/// - `breakpoint alt` (aliases: `bkpt alt`, `b alt`): Trigger an inline breakpoint instruction (without a debugger attached, it faults and shows the crash screen)
+/// - `boot usb` (aliases: `usb boot`, `usb`): Reboot into the USB bootloader
+///   - With the `boot-button` feature, holding the button on GPIO 21 for 3 seconds does the same.
+/// - `redraw` (aliases: `refresh`, `reload`, `r`, `f5`): Force a redraw of stack
+///   - Also can be triggered by pressing Ctrl-R, when it also redraws the textbox.
+/// - `ws N` (aliases: `workspace N`): Switch to the N-th workspace (from 1 to 4), each having its own independent stack
+///   - The number of the active workspace is shown in the top-right corner.
+/// - `scroll N`: Scroll the stack view so that the top N elements are hidden, revealing deeper ones (`scroll 0` resets it)
+///   - Also can be done with the PgUp and PgDn keys outside of command mode, scrolling by a whole page, or Up and Down (or a rotary encoder) by a line.
+/// - `page [stack|regs|status|clock]`: Show the page instead of the stack, or the next one without a name (as does Tab outside of command mode)
+///   - `regs` lists the registers, `status` the uptime, the stack's depth, the supply voltage and such, `clock` the time in large digits.
+///   - The pages other than the stack keep themselves up to date, any key other than Tab goes back to the stack and then does what it always does.
+/// - `brightness N` (aliases: `brt N`): Set display brightness to a predefined level between 1 and 5 (not saved)
+/// - `contrast N`: Set the display's contrast register directly to N between 0 and 255, and save it into flash
+/// - `autobrt on|off`: Whether the contrast follows the ambient light, measured by a photoresistor on GPIO26 (saved into flash)
+///   - While it's on, it overrides `contrast`, `brightness` and adjusting the contrast by the keys, as soon as the light changes.
+/// - `night HH:MM-HH:MM [N]`: Dim the display to contrast N (16 by default) between the two times of day, e.g. `night 22:00-07:00`,
+///   and back to the usual contrast afterwards (saved into flash); needs the time to be set, see `settime`
+///   - `night off`: Stop dimming it at night
+///   - `night`: Print the schedule, and whether it's night now
+///   - At night, adjusting the contrast by the keys adjusts the night one. Auto brightness overrides it, as it does `contrast`.
+/// - `led on|off|blink`: Turn the onboard LED on or off, or blink it once a second (not saved)
+/// - `invert on`: Invert the display (black on white), saved into flash; command mode then shows white on black
+///   - `invert off`: Back to white on black
+/// - `saver N`: Start the screensaver (a bouncing logo) after N seconds without input, saved into flash
+///   - `saver N blank`: Blank the display instead of the bouncing logo
+///   - `saver off`: Disable the screensaver
+/// - `sleep N`: Dim the display 10 seconds before N seconds without input pass, then turn it off and let the core sleep, saved into flash
+///   - A key over UART or the button on GPIO22 wakes it up again, the key itself is swallowed.
+///   - `sleep off`: Never sleep
+/// - `echo on|off`: Whether to echo the received characters back over UART, for terminals that don't echo locally (saved into flash)
+/// - `crlf on|off`: Whether lines sent over UART end with CR LF, or just LF (saved into flash)
+/// - `baud N`: Switch the UART to N baud, then press Enter at the new rate within 10 seconds to keep it (saved into flash),
+///   otherwise it switches back
+/// - `flow on|off`: Whether the UART uses RTS/CTS flow control, CTS on GPIO 2 and RTS on GPIO 3 (saved into flash),
+///   so that large pastes and scripts can't overrun the receiver; wire them crossed to the adapter's RTS and CTS
+///   - Not with the `i2c-peripheral` feature, whose I²C target has the same pins.
+/// - `log [LEVEL | mirror on|off]`: Show or set the log level (trace, debug, info, warn, error or off),
+///   or mirror the log to UART as plain text for when there's no probe attached (neither is saved)
+/// - `errlog`: List the last errors with their uptime, newest first, a page at a time on the display (Ctrl-C or Esc quits)
+///   - `errlog save`: Save them into flash, they're restored on the next boot (marked as from the previous boot)
+///   - `errlog clear`: Forget them, including the saved ones
+/// - `fix N`: Show the numbers rounded to N decimal places, e.g. `fix 2` for money (in all workspaces)
+///   - `fix`: Show the numbers with full precision again
+/// - `sci N`: Show the numbers in scientific notation with N decimal places, e.g. `1.50e3` with `sci 2`
+///   - `sci`: Show the numbers with full precision again
+/// - `group on|off`: Group the digits by thousands, e.g. `1 234 567.89` (in all workspaces, saved into flash)
+/// - `big on|off`: Show just the top element, in seven-segment digits as large as fit, to be read from across the bench (saved into flash)
+///   - The typed number still shows in the textbox. A number too long for the smallest digits, or a scrolled stack, is shown the usual way.
+/// - `anim on|off`: Whether pushed elements slide up from the textbox and the rest of the stack slides along,
+///   in a few frames, so that it's easier to follow what moved where (saved into flash)
+/// - `clear` (aliases: `cls`, `c`): Clear the stack
+/// - `duplicate` (aliases: `dup`): Duplicate the top element of the stack
+/// - `drop`: Remove the top element of the stack
+///   - `drop N`: Remove the top N elements of the stack (where N is a positive integer not exceeding the current stack size)
+/// - `swap` (aliases: `s`): Swap the top two elements of the stack
+/// - `over`: Push a copy of the second element of the stack
+/// - `rot`: Move the third element of the stack to the top
+/// - `pick N`: Push a copy of the N-th element of the stack (`pick 0` is the same as `dup`)
+/// - `roll N`: Move the N-th element of the stack to the top (`roll 1` is the same as `swap`)
+/// - `neg`: Negate the top element of the stack
+///   - `neg N`: Negate the top N elements of the stack
+/// - `label TEXT`: Attach a short label (up to 8 bytes, e.g. `Vcc`) to the top element of the stack, shown before its value
+///   - Labels containing spaces have to be in double quotes, e.g. `label "R 1"`.
+///   - `label`: Remove the label of the top element
+/// - `sort`: Sort the stack in ascending order (biggest element on top)
+/// - `reverse` (aliases: `rev`): Reverse the order of the stack
+/// - `dump`: Send the stack over UART as CSV with an `index,value` header, index 0 being the top (as with `pick`)
+///   - `dump json`: Send it as JSON lines instead, e.g. `{"index":0,"value":1.5}`
+///   - Only the elements in RAM are sent, not the ones spilled into flash.
+/// - `screenshot`: Send what the display shows over UART as a plain PBM image (a plain PGM on the grayscale SSD1327),
+///   e.g. for the documentation or a bug report; save everything from the `P1` line on into a `.pbm` file
+///   - `screenshot xbm`: Send it as an XBM instead, a C source file with the pixels in an array
+///   - The command line shows in it too, and the colours are as outside of command mode (see `invert`).
+/// - `load`: Push the numbers sent over UART, one per line, until an empty line or Ctrl-D (EOT), then say how many were accepted
+///   - Lines that aren't numbers are rejected and skipped. The last line sent ends up on top, so a `dump` has to be reversed first.
+/// - `chart`: Show the top elements of the stack as horizontal bars instead of text, scaled so that the one farthest from zero
+///   spans the display, e.g. for comparing measurements at a glance; any key returns to the stack
+///   - The top element is the bottom bar, like in the stack view. Negative elements go left from a dimmed line at zero.
+///   - As many as fit are shown, 8 on the SSD1306 and 16 on the SSD1327.
+/// - `sum`: Push the sum of all elements of the stack
+/// - `product` (aliases: `prod`): Push the product of all elements of the stack
+/// - `mean` (aliases: `avg`): Push the arithmetic mean of all elements of the stack
+/// - `sdev` (aliases: `stddev`): Push the sample standard deviation of all elements of the stack
+///   - The statistics commands leave the original elements on the stack, use `clear` to get rid of them.
+/// - `s+`: Pop the top element of the stack as x into the statistics registers (n, Σx, Σx², Σy, Σy², Σxy), with zero as y,
+///   like the Σ+ key of other calculators
+///   - `s+ pair`: Pop the top two elements instead, x from the top and y from below it, e.g. for `lr`
+///   - The registers are kept in RAM only. The sums are overflow-checked, a data point that doesn't fit doesn't get added at all.
+/// - `s-`: Pop the top element of the stack as x out of the statistics registers, e.g. one added by mistake (the Σ- key)
+///   - `s- pair`: The same for a pair, as with `s+ pair`
+/// - `stats`: List the statistics registers over UART and on the display (any key returns, Ctrl-C or Esc too)
+///   - `stats clear`: Empty the statistics registers
+/// - `smean`: Push the means of y and x from the statistics registers, x on top
+/// - `ssdev`: Push the sample standard deviations of y and x from the statistics registers, x on top
+/// - `lr` (aliases: `linreg`): Push the slope and the intercept of the least-squares line `y = slope * x + intercept`
+///   through the statistics registers, intercept on top, and print the line over UART
+/// - `predict`: Replace the top element x of the stack with the y the least-squares line predicts for it
+/// - `dot [2|3]`: Replace the top two vectors of the stack with their dot product
+///   - A vector is 3 elements of the stack (or 2 with the argument), x being the deepest one, e.g. `1 2 3` is `[1, 2, 3]`.
+/// - `cross`: Replace the top two 3D vectors a and b of the stack with a × b (b being the topmost), and print it over UART
+/// - `mag [2|3]` (aliases: `vabs`): Replace the top vector of the stack with its magnitude
+/// - `unit [2|3]` (aliases: `normalize`): Replace the top vector of the stack with the unit vector of the same direction, and print it over UART
+/// - `vec [2|3]`: Show the top vector of the stack as `[x, y, z]` over UART and on the display, until a key is pressed
+/// - `poly N`: Pop the top N + 1 elements of the stack as the coefficients of a polynomial of degree N (up to 8) and store it,
+///   the highest degree deepest, e.g. `1 -3 2` and `poly 2` for x^2 - 3x + 2 (not saved)
+///   - `poly`: Print the stored polynomial over UART
+///   - `poly eval`: Replace the top element x of the stack with the value of the polynomial at x
+///   - `poly roots`: Push both real roots of the stored quadratic, the greater one on top; complex roots are an error
+///   - `poly clear`: Forget the stored polynomial
+/// - `plot`: Plot the stored polynomial as y = f(x) from x_min to x_max (the top element), leaving both on the stack,
+///   with the y axis scaled to fit the curve, and print both ranges over UART
+///   - Left and Right (or 4 and 6 on the keypad) pan it by a quarter of the range, any other key returns to the stack.
+///   - The axes are drawn dimmed where they're in the ranges, the curve has gaps where the polynomial overflows.
+/// - `r->p` (aliases: `r>p`, `polar`): Replace x and y (the top element) with the magnitude and the angle (the top element),
+///   e.g. an impedance from its resistance and reactance; the angle is in degrees, from -180 (exclusive) to 180
+///   - `r->p rad`: The angle in radians instead (`r->p deg` is the default)
+/// - `p->r` (aliases: `p>r`, `rect`): Replace the magnitude and the angle in degrees (the top element) with x and y (the top element)
+///   - `p->r rad`: The angle in radians instead (`p->r deg` is the default)
+/// - `hypot`: Replace the top two elements a and b of the stack with sqrt(a² + b²), even when squaring them would overflow
+/// - `db`: Replace the top element of the stack (a power ratio) with it in decibels, i.e. 10 * log10(ratio), e.g. 3.01 for 2
+///   - `db amp`: Of an amplitude ratio (voltage, current, sound pressure) instead, i.e. 20 * log10(ratio) (`db pow` is the default)
+/// - `undb`: Replace the top element of the stack (in decibels) with the power ratio, i.e. 10^(dB / 10), e.g. 100 for 20
+///   - `undb amp`: The amplitude ratio instead, i.e. 10^(dB / 20) (`undb pow` is the default)
+/// - `gcd`: Replace the top two elements of the stack with the greatest common divisor of their whole parts
+/// - `lcm`: Replace the top two elements of the stack with the least common multiple of their whole parts
+/// - `ncr` (aliases: `comb`): Replace n and k (the top element) with the number of combinations of k out of n, e.g. 10 for `5 2 ncr`
+/// - `npr` (aliases: `perm`): Replace n and k (the top element) with the number of permutations of k out of n, e.g. 20 for `5 2 npr`
+///   - Both cancel the factorials out before multiplying, so they only fail with `MathOverflow` when the result itself is too big.
+/// - `isprime`: Replace the top element of the stack with 1 if its whole part is a prime, 0 if not, and say so over UART
+/// - `factor`: Replace the top element of the stack with the prime factors of its whole part, one per element, the greatest on top,
+///   and print them over UART, e.g. `12 = 2 * 2 * 3`
+///   - The number theory commands work on the whole parts (e.g. 12 for 12.7), `isprime` and `factor` up to 10^12.
+/// - `sto X`: Store a copy of the top element of the stack into register X (a letter A to Z)
+/// - `sto+ X`: Add the top element of the stack to register X (empty register counts as zero)
+/// - `sto- X`: Subtract the top element of the stack from register X (empty register counts as zero)
+/// - `rcl X`: Push the value of register X onto the stack
+/// - `clregs`: Empty all registers
+///   - `clregs X`: Empty only register X
+/// - `regs`: List the registers in use over UART and on the display, a page at a time (any key shows the next page, Ctrl-C or Esc quits)
+/// - `lastx` (aliases: `lx`): Push the top element of the stack from before the last arithmetic operation (`+-*/` or `neg`)
+/// - `uptime`: Print the time since boot, as measured by the hardware timer
+/// - `stopwatch start` (aliases: `sw start`): Start the stopwatch, or restart it from zero if it's running
+///   - `stopwatch lap`: Push the seconds elapsed since the start, leaving the stopwatch running
+///   - `stopwatch stop`: Stop the stopwatch and push the seconds elapsed since the start
+/// - `vbat`: Measure the supply voltage (VSYS, i.e. the battery's when running off one) and push it in volts
+/// - `read SENSOR [QUANTITY]`: Measure the quantity (the sensor's first one by default) by a sensor on the second I²C bus and push it,
+///   e.g. `read bme280 pressure` (needs the `sensors` feature)
+///   - `read`: List the sensors there are drivers for, with their quantities and units
+///   - `bme280`: `temp` (°C), `pressure` (hPa), `humidity` (%); `ina219`: `voltage` (V), `current` (A, across a 0.1 Ω shunt), `power` (W);
+///     `ads1115`: `a0` to `a3` (V, against ground, up to 4.096 V)
+/// - `time`: Print the time of day, as kept by the real-time clock
+/// - `date`: Print the date and the day of the week
+/// - `settime HH:MM:SS`: Set the time of day, it then shows in the top-right corner (lost on reset, there's no battery for the clock)
+/// - `setdate YYYY-MM-DD`: Set the date
+/// - `now`: Push the seconds since 1970-01-01 (like a Unix timestamp, but in the local time), e.g. for subtracting two of them
+/// - `macro record`: Start recording the keys pressed and commands entered from now on into a macro, replacing the old one
+///   - `macro stop`: Stop recording
+///   - `macro play [N]`: Play the macro once, or N times (Ctrl-P outside of command mode plays it once)
+///   - Playback stops at the first command that fails, the errors of keystrokes are only shown.
+/// - `script`: Read commands from UART, one per line, and run them one after another, until Ctrl-D or a line `end`
+///   - `script abort`: The same, but stop at the first command that fails
+///   - Each line gets answered with `ok N` or `err N: ERROR` (N being the line number), so that a host PC can drive it.
+///   - Empty lines and lines starting with `#` are skipped, Ctrl-C cancels the rest of the script.
+/// - `scpi`: Take SCPI-like commands from UART, one line at a time, until `:SYSTem:LOCal` or Ctrl-D, for lab automation tools such as pyvisa
+///   - E.g. `:STACK:PUSH 3.14`, `:DISP:BRIGHT 4` or `:SYST:ERR?`; the short or the long form of each mnemonic, in any case.
+///   - `*IDN?`, `*RST` (clears the stack), `*CLS`, `*OPC?`, `:STACk:PUSH N`, `:STACk:POP[?]`, `:STACk:TOP?`, `:STACk:PEEK? N`, `:STACk:DEPTh?`,
+///     `:STACk:CLEar`, `:CALCulate:RPN "1 2 +"`, `:DISPlay:BRIGht N`, `:DISPlay:CONTrast[?] N`, `:SYSTem:ERRor?`, `:SYSTem:VERSion?`,
+///     `:SYSTem:COMMand "CMD"` (any of these commands, its output discarded) and `:SYSTem:LOCal`
+///   - Commands are separated by `;`, one without the leading colon is relative to the previous one (`:STAC:PUSH 1;PUSH 2`).
+///     The responses to the queries of a line are sent together, separated by `;`, ending with LF; errors go into the queue `:SYST:ERR?` reads.
+/// - `modbus UNIT [SCALE]`: Be a Modbus RTU slave at the address UNIT (1 to 247) on UART, e.g. for a PLC or SCADA to read what was computed,
+///   until the master writes 3 into the control register or a lone Ctrl-C arrives
+///   - The holding registers (also readable as input registers) are the stack's depth, the top 16 levels and the registers A to Z,
+///     as 32-bit integers scaled by 10^SCALE (3 by default, so 1.5 reads as 1500), high word first; see `modbus::Register` for the map.
+///   - Functions 3, 4, 6 and 16 are supported. Frames end after 3.5 characters of silence at the baud rate of `baud`.
+///
+/// To add a command, implement `Command` for a new unit struct, add it here and bump `COMMAND_COUNT`.
+/// The order is the one `help` prints them in.
+pub fn registry<'r, D>() -> [&'r dyn Command<D>; COMMAND_COUNT]
+where
+    D: Panel,
+{
+    [
+        &Help, &OpenMenu, &Version, &Selftest, &HilTest, &Reset, &Persist, &Save, &LoadSnap, &Snaps, &Breakpoint, &Boot, &Usb, &Redraw, &Workspace, &Scroll, &Page, &SetBrightness, &Contrast, &AutoBrt, &Night, &Invert, &Led, &Saver, &Sleep, &Echo, &Crlf, &Baud, &Flow, &Log, &ErrLog, &Fix, &Sci, &Group, &Big, &Anim,
+        &Clear, &Dup, &DropTop, &Swap, &Over, &Rot, &Pick, &Roll, &Neg, &Label, &Sort, &Reverse, &Dump, &Screenshot, &Load, &Chart,
+        &Sum, &Product, &Mean, &Stddev, &StatsAdd, &StatsSub, &Stats, &StatsMean, &StatsStddev, &LinReg, &Predict,
+        &Dot, &Cross, &Magnitude, &Unit, &ShowVector, &Poly, &PlotPoly, &RectToPolar, &PolarToRect, &Hypot, &Db, &Undb, &Gcd, &Lcm, &Ncr, &Npr, &IsPrime, &Factor, &Sto, &StoAdd, &StoSub, &Rcl, &ClearRegs, &Regs, &LastX, &Uptime, &Stopwatch, &Vbat, &ReadSensor, &Time, &Date, &SetTime, &SetDate, &Now, &Macro, &Script, &Scpi, &Modbus,
+    ]
+}
+
+/// Finds a command by its name or any of its aliases.
+pub fn find<'r, D>(name: &str) -> Option<&'r dyn Command<D>>
+where
+    D: Panel,
+{
+    registry().into_iter().find(|cmd| cmd.names().contains(&name))
+}
+
+/// Runs a whole command line, e.g. `drop 2`, checking that the command exists and gets the arguments it wants.
+/// If a macro is being recorded, the command line gets recorded too.
+pub fn execute<D>(command: &str, ctx: &mut Context<'_, '_, D>) -> Result<(), CustomError>
+where
+    D: Panel,
+{
+    // The name is everything up to the first space, the arguments are the rest (if any)
+    let (name, rest) = command.split_once(' ').unwrap_or((command, ""));
+    let args = args::args(rest);
+
+    let Some(cmd) = find::<D>(name) else {
+        log_warn!("Unknown command received over UART: {:?}", command);
+        return Err(CE::BadInput);
+    };
+    match (cmd.args(), args.is_empty()) {
+        (ArgSpec::None, false) => {
+            log_warn!("Command '{}' takes no argument, got {:?}", cmd.name(), rest);
+            return Err(CE::BadInput);
+        },
+        (ArgSpec::Required, true) => {
+            log_warn!("Command '{}' requires an argument", cmd.name());
+            return Err(CE::BadInput);
+        },
+        _ => (),
+    }
+    cmd.run(ctx, args)?;
+
+    // The macro commands themselves don't belong into the macro, nor does the menu, whatever it ran got recorded already
+    if !matches!(cmd.name(), "macro" | "menu") {
+        ctx.state.macros.record_command(command)?;
+    }
+    Ok(())
+}
+
+/// Like `execute()`, but the error says which command failed and on what arguments, e.g. for the error toasts.
+pub fn execute_with_context<D>(command: &str, ctx: &mut Context<'_, '_, D>) -> Result<(), WithContext>
+where
+    D: Panel,
+{
+    let (name, rest) = command.split_once(' ').unwrap_or((command, ""));
+    execute(command, ctx).map_err(|e| match find::<D>(name) {
+        Some(cmd) => WithContext::from(e).with_context(cmd.name(), rest),
+        None => WithContext::from(e).with_context("unknown", name),
+    })
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Clears the display and draws the lines onto it, covering the stack and the textbox until they're redrawn.
+/// Lines too long for the display just get cut off by its edge.
+fn draw_page<D>(ctx: &mut Context<'_, '_, D>, lines: &[String<TEXT_BUFFER_SIZE>]) -> Result<(), CustomError>
+where
+    D: Panel,
+{
+    let mut disp = ctx.disp_refcell.borrow_mut();
+    DrawTarget::clear(&mut *disp, D::Color::BACKGROUND)?;
+    for (i, line) in lines.iter().enumerate() {
+        let position = Point::new(0, (i as u32 * PAGE_LINE_HEIGHT) as i32);
+        Text::with_baseline(line, position, page_style(), Baseline::Top).draw(&mut *disp)?;
+    }
+    disp.flush_display()?;
+    Ok(())
+}
+
+/// Pushes the value and redraws the stack, or logs what failed to be pushed.
+fn push_and_draw<D>(ctx: &mut Context<'_, '_, D>, val: DecimalFixed, what: &str) -> Result<(), CustomError>
+where
+    D: Panel,
+{
+    if ctx.stack.push(val).is_err() {
+        log_error!("Failed to push {} onto stack: CapacityError", what);
+        return Err(CE::CapacityError);
+    };
+    ctx.stack.draw(false)
+}
+
+/// Like `push_and_draw()`, but for two values, the first one ends up below the second one. Either both get pushed, or neither.
+fn push_pair_and_draw<D>(ctx: &mut Context<'_, '_, D>, below: DecimalFixed, top: DecimalFixed, what: &str) -> Result<(), CustomError>
+where
+    D: Panel,
+{
+    if let Err(e) = ctx.stack.push_slice(&[below, top]) {
+        log_error!("Failed to push {} onto stack: {:?}", what, e);
+        return Err(e);
+    };
+    ctx.stack.draw(false)
+}
+
+/// Pushes the result of a statistics command, leaving the original elements on the stack.
+fn push_stat<D>(ctx: &mut Context<'_, '_, D>, result: Result<DecimalFixed, CustomError>, name: &str) -> Result<(), CustomError>
+where
+    D: Panel,
+{
+    let val = match result {
+        Ok(val) => val,
+        Err(e) => {
+            log_warn!("Failed to compute {} of the stack with {} elements: {:?}", name, ctx.stack.len(), e);
+            return Err(e);
+        }
+    };
+    push_and_draw(ctx, val, name)
+}
+
+fn reboot_to_usb<D>(ctx: &mut Context<'_, '_, D>) -> Result<(), CustomError>
+where
+    D: Panel,
+{
+    log_info!("Rebooting into USB bootloader (command 'boot usb')");
+    enter_usb_bootloader(&mut *ctx.disp_refcell.borrow_mut())
+}
+
+/// Turns the display off and reboots into the USB bootloader, for `boot usb` as well as the boot button.
+pub fn enter_usb_bootloader<D>(disp: &mut D) -> Result<(), CustomError>
+where
+    D: Panel,
+{
+    disp.set_on(false)?; // Turns the display off (well, only the grahpics part, it still retains memory) for conventince
+    // Pin 25 for activity LED, both MSC and Picoboot enabled.
+    hal::rom_data::reset_to_usb_boot(1 << 25, 0)
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+pub struct Help;
+
+impl<D: Panel> Command<D> for Help {
+    fn names(&self) -> &'static [&'static str] { &["help"] }
+    fn usage(&self) -> &'static str { "help [CMD]: List all commands, or show the usage of CMD" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let Some(name) = args.next() else {
+            log_info!("Printing list of commands (command 'help')");
+            (ctx.print)(b"Commands:\r\n");
+            for cmd in registry::<D>() {
+                (ctx.print)(cmd.usage().as_bytes());
+                (ctx.print)(b"\r\n");
+            }
+            return Ok(());
+        };
+        args.finish()?;
+
+        let Some(cmd) = find::<D>(name) else {
+            log_warn!("Failed to print help: unknown command {:?}", name);
+            return Err(CE::BadInput);
+        };
+        log_info!("Printing usage of {:?} (command 'help')", cmd.name());
+        (ctx.print)(cmd.usage().as_bytes());
+        (ctx.print)(b"\r\n");
+        if cmd.names().len() > 1 {
+            (ctx.print)(b"Aliases:");
+            for alias in &cmd.names()[1..] {
+                (ctx.print)(b" ");
+                (ctx.print)(alias.as_bytes());
+            }
+            (ctx.print)(b"\r\n");
+        }
+        Ok(())
+    }
+}
+
+pub struct OpenMenu;
+
+impl<D: Panel> Command<D> for OpenMenu {
+    fn names(&self) -> &'static [&'static str] { &["menu"] }
+    fn usage(&self) -> &'static str { "menu: Open the menu of settings, conversions, constants and programs (also Ctrl-O)" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        log_info!("Opening the menu (command 'menu')");
+        let mut menu = Menu::new(menu::ROOT);
+        let action = loop {
+            menu.draw(&mut *ctx.disp_refcell.borrow_mut())?;
+            match read_nav_key(ctx)? {
+                NavKey::Up => menu.up(),
+                NavKey::Down => menu.down(),
+                NavKey::Left | NavKey::Back => {
+                    if !menu.back() {
+                        break None;
+                    }
+                },
+                NavKey::Right | NavKey::Select => match menu.enter() {
+                    Selection::Navigated => {},
+                    Selection::Action(action) => break Some(action),
+                    Selection::Closed => break None,
+                },
+                NavKey::Close => break None,
+                NavKey::Other => {},
+            }
+        };
+
+        ctx.stack.invalidate();
+        ctx.stack.draw(true)?;
+        match action {
+            None => {
+                log_info!("Menu closed");
+                Ok(())
+            },
+            Some(MenuAction::Command(command)) => {
+                log_info!("Running {:?} from the menu", command);
+                execute(command, ctx)
+            },
+            Some(MenuAction::Constant(value)) => {
+                log_info!("Pushing {} from the menu", value);
+                push_and_draw(ctx, DecimalFixed::parse_str(value, None)?, "constant")
+            },
+            Some(MenuAction::Submenu(_)) => Err(CE::Impossible), // Opened by `enter()` itself
+        }
+    }
+}
+
+/// The keys the full-screen views that can be navigated understand (e.g. `menu` and `plot`),
+/// from the arrow keys of a terminal, the rotary encoder or the keypad's digits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+enum NavKey {
+    Up,
+    Down,
+    Left,
+    Right,
+    /// Enter, or pressing the rotary encoder
+    Select,
+    /// A lone Esc, Backspace or Delete
+    Back,
+    /// Ctrl-C
+    Close,
+    Other,
+}
+
+/// Reads a key, with the rest of its escape sequence if it has one (the way command mode does).
+/// The keypad has no arrows, so its digits act as ones, the way they're laid out around 5.
+fn read_nav_key<D>(ctx: &mut Context<'_, '_, D>) -> Result<NavKey, CustomError>
+where
+    D: Panel,
+{
+    let key = match (ctx.read_byte)()? {
+        b'2' => NavKey::Up,
+        b'8' => NavKey::Down,
+        b'4' => NavKey::Left,
+        b'6' => NavKey::Right,
+        b'5' | b'\r' | b'\n' => NavKey::Select,
+        0x08 | 0x7F => NavKey::Back,
+        0x03 => NavKey::Close,
+        0x1B => {
+            cortex_m::asm::delay(command_mode::ESCAPE_WAIT_CYCLES); // HACK: Same as in command mode, wait a bit to allow the rest of the sequence to arrive.
+            let mut seq: Vec<u8, 8> = Vec::new();
+            while !seq.is_full() && let Some(byte) = (ctx.poll_byte)() {
+                let _ = seq.push(byte); // Can't fail, we checked it isn't full
+            }
+            match seq.as_slice() {
+                b"" => NavKey::Back,
+                b"[A" => NavKey::Up,
+                b"[B" => NavKey::Down, // The rotary encoder sends these two when turned
+                b"[C" => NavKey::Right,
+                b"[D" => NavKey::Left,
+                b"[2~" => NavKey::Select, // Insert, which is the rotary encoder's button
+                other => {
+                    log_trace!("Ignoring escape sequence received in a full-screen view: {:?}", other);
+                    NavKey::Other
+                },
+            }
+        },
+        _ => NavKey::Other,
+    };
+    Ok(key)
+}
+
+pub struct Version;
+
+impl<D: Panel> Command<D> for Version {
+    fn names(&self) -> &'static [&'static str] { &["version", "ver"] }
+    fn usage(&self) -> &'static str { "version: Show the firmware version and build info" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        // Set by the build script, unless it couldn't find them out
+        let revision = option_env!("GIT_REVISION").unwrap_or("unknown");
+        let profile = option_env!("BUILD_PROFILE").unwrap_or("unknown");
+        let hal_version = option_env!("HAL_VERSION").unwrap_or("unknown");
+        log_info!("Firmware {} {}, revision {}, {} build, rp2040-hal {} (command 'version')",
+            env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), revision, profile, hal_version);
+
+        // Short enough for the display, 21 characters at most
+        let lines: [String<TEXT_BUFFER_SIZE>; 4] = [
+            heapless::format!("FW {}", env!("CARGO_PKG_VERSION"))?,
+            heapless::format!("git {}", revision)?,
+            heapless::format!("{} build", profile)?,
+            heapless::format!("HAL {}", hal_version)?,
+        ];
+        (ctx.print)(env!("CARGO_PKG_NAME").as_bytes());
+        (ctx.print)(b"\r\n");
+        for line in &lines {
+            (ctx.print)(line.as_bytes());
+            (ctx.print)(b"\r\n");
+        }
+        #[cfg(feature = "alloc")]
+        {
+            let line: String<64> = heapless::format!("Heap: {} of {} bytes used\r\n", crate::heap::used(), crate::heap::HEAP_SIZE)?;
+            (ctx.print)(line.as_bytes());
+        }
+
+        draw_page(ctx, &lines)?;
+        (ctx.read_byte)()?; // Any key goes back to the stack
+
+        ctx.stack.invalidate();
+        ctx.stack.draw(true)
+    }
+}
+
+pub struct Selftest;
+
+impl<D: Panel> Command<D> for Selftest {
+    fn names(&self) -> &'static [&'static str] { &["selftest"] }
+    fn usage(&self) -> &'static str { "selftest: Test the display, RAM, stack and number formatting" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        log_info!("Running the self-test (command 'selftest')");
+        let results = selftest::run_all(ctx.disp_refcell);
+
+        let mut lines: Vec<String<TEXT_BUFFER_SIZE>, { selftest::TEST_NAMES.len() + 1 }> = Vec::new();
+        for (name, result) in selftest::TEST_NAMES.iter().zip(results) {
+            let line: String<TEXT_BUFFER_SIZE> = match result {
+                Ok(()) => heapless::format!("{}: PASS", name)?,
+                Err(e) => {
+                    log_warn!("Self-test {} failed: {:?}", name, e);
+                    heapless::format!("{}: FAIL", name)?
+                },
+            };
+            lines.push(line).map_err(|_| CE::Impossible)?; // It has room for all of them
+        }
+        let passed = results.iter().filter(|result| result.is_ok()).count();
+        let summary: String<TEXT_BUFFER_SIZE> = heapless::format!("{}/{} passed", passed, results.len())?;
+        lines.push(summary).map_err(|_| CE::Impossible)?; // And the summary
+        log_info!("Self-test: {} of {} passed", passed, results.len());
+
+        for line in &lines {
+            (ctx.print)(line.as_bytes());
+            (ctx.print)(b"\r\n");
+        }
+        draw_page(ctx, &lines)?;
+        (ctx.read_byte)()?; // Any key goes back to the stack
+
+        ctx.stack.invalidate();
+        ctx.stack.draw(true)
+    }
+}
+
+pub struct HilTest;
+
+impl<D: Panel> Command<D> for HilTest {
+    fn names(&self) -> &'static [&'static str] { &["hiltest"] }
+    fn usage(&self) -> &'static str { "hiltest: Run the hardware-in-the-loop tests, reporting PASS/FAIL over UART" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        log_info!("Running the hardware-in-the-loop tests (command 'hiltest')");
+        // One line each, so that a CI job can match them without caring about anything else we print
+        let begin: String<64> = heapless::format!("{} BEGIN {} {}\r\n",
+            hiltest::REPORT_PREFIX, env!("CARGO_PKG_VERSION"), option_env!("GIT_REVISION").unwrap_or("unknown"))?;
+        (ctx.print)(begin.as_bytes());
+
+        let results = hiltest::run_all(ctx);
+        for (name, result) in hiltest::STEP_NAMES.iter().zip(results) {
+            let line: String<64> = match result {
+                Ok(()) => heapless::format!("{} {} PASS\r\n", hiltest::REPORT_PREFIX, name)?,
+                Err(e) => {
+                    log_warn!("HIL test step {} failed: {:?}", name, e);
+                    heapless::format!("{} {} FAIL {:?}\r\n", hiltest::REPORT_PREFIX, name, e)?
+                },
+            };
+            (ctx.print)(line.as_bytes());
+        }
+
+        let passed = results.iter().filter(|result| result.is_ok()).count();
+        let verdict = if passed == results.len() { "PASS" } else { "FAIL" };
+        let end: String<64> = heapless::format!("{} END {} {}/{}\r\n", hiltest::REPORT_PREFIX, verdict, passed, results.len())?;
+        (ctx.print)(end.as_bytes());
+        log_info!("HIL test: {} of {} steps passed", passed, results.len());
+
+        // The `draw` step drew over the stack
+        ctx.stack.invalidate();
+        ctx.stack.draw(true)?;
+        if passed == results.len() { Ok(()) } else { Err(CE::Other) }
+    }
+}
+
+pub struct Reset;
+
+impl<D: Panel> Command<D> for Reset {
+    fn names(&self) -> &'static [&'static str] { &["reset"] }
+    fn usage(&self) -> &'static str { "reset: Save the stack into flash and reset the microcontroller" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        // We reset anyway, the user asked for it; losing the stack is the lesser evil
+        if let Err(e) = persist::save_stack(ctx.stack) {
+            log_error!("Failed to save stack before reset: {:?}", e);
+        }
+        log_error!("Resetting microcontroller (command 'reset')");
+        cortex_m::peripheral::SCB::sys_reset(); // Reset the microcontroller
+    }
+}
+
+pub struct Persist;
+
+impl<D: Panel> Command<D> for Persist {
+    fn names(&self) -> &'static [&'static str] { &["persist"] }
+    fn usage(&self) -> &'static str { "persist: Save the stack into flash, it gets restored on boot" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        log_info!("Saving stack into flash (command 'persist')");
+        persist::save_stack(ctx.stack)
+    }
+}
+
+pub struct Save;
+
+impl<D: Panel> Command<D> for Save {
+    fn names(&self) -> &'static [&'static str] { &["save"] }
+    fn usage(&self) -> &'static str { "save [NAME]: Save the stack and registers as snapshot NAME (without NAME, same as persist)" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let name = args.next();
+        args.finish()?;
+        match name {
+            None => {
+                log_info!("Saving stack into flash (command 'save')");
+                persist::save_stack(ctx.stack)
+            },
+            Some(name) => {
+                log_info!("Saving snapshot {:?} (command 'save')", name);
+                snapshots::save(name, ctx.stack, &ctx.state.registers)
+            },
+        }
+    }
+}
+
+pub struct LoadSnap;
+
+impl<D: Panel> Command<D> for LoadSnap {
+    fn names(&self) -> &'static [&'static str] { &["loadsnap"] }
+    fn usage(&self) -> &'static str { "loadsnap NAME: Replace the stack and registers with snapshot NAME" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let name = args.next_str()?;
+        args.finish()?;
+        log_info!("Loading snapshot {:?} (command 'loadsnap')", name);
+        snapshots::load(name, ctx.stack, &mut ctx.state.registers)?;
+        ctx.stack.draw(false)
+    }
+}
+
+pub struct Snaps;
+
+impl<D: Panel> Command<D> for Snaps {
+    fn names(&self) -> &'static [&'static str] { &["snaps"] }
+    fn usage(&self) -> &'static str { "snaps [del NAME]: List the saved snapshots, or delete snapshot NAME" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        match args.next() {
+            None => {},
+            Some("del") => {
+                let name = args.next_str()?;
+                args.finish()?;
+                log_info!("Deleting snapshot {:?} (command 'snaps')", name);
+                return snapshots::delete(name);
+            },
+            Some(other) => {
+                log_warn!("Unknown snaps subcommand {:?}", other);
+                return Err(CE::BadInput);
+            },
+        }
+
+        let snaps = snapshots::list()?;
+        log_info!("Listing {} snapshots (command 'snaps')", snaps.len());
+        if snaps.is_empty() {
+            (ctx.print)(b"No snapshots saved\r\n");
+            return Ok(());
+        }
+        for snap in &snaps {
+            let line: String<64> = heapless::format!(
+                "{}: {} elements, {} registers\r\n", snap.name, snap.element_count, snap.register_count
+            )?;
+            (ctx.print)(line.as_bytes());
+        }
+        Ok(())
+    }
+}
+
+pub struct Breakpoint;
+
+impl<D: Panel> Command<D> for Breakpoint {
+    fn names(&self) -> &'static [&'static str] { &["breakpoint", "bkpt", "b"] }
+    fn usage(&self) -> &'static str { "breakpoint [alt]: Trigger a breakpoint (alt: inline instruction, faults without a debugger)" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, _ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let variant = args.next();
+        args.finish()?;
+        match variant {
+            None => {
+                // Here should be a breakpoint for debugging purposes in your IDE:
+                log_debug!("Breakpoint requested by user (command 'breakpoint')");
+            },
+            Some("alt") => {
+                log_debug!("Alternative breakpoint requested by user (command 'breakpoint alt')");
+                // Will cause an exception if no debugger is attached
+                // SAFETY: We know this instruction does not meddle with any registers, and that this is valid assembly, so it has to be safe.
+                // By inlining it without a function call, we keep access to local variables if needed for debugging.
+                unsafe { core::arch::asm!("bkpt"); } // Inline breakpoint instruction
+            },
+            Some(other) => {
+                log_warn!("Unknown breakpoint variant: {:?}", other);
+                return Err(CE::BadInput);
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct Boot;
+
+impl<D: Panel> Command<D> for Boot {
+    fn names(&self) -> &'static [&'static str] { &["boot"] }
+    fn usage(&self) -> &'static str { "boot usb: Reboot into the USB bootloader" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let target = args.next_str()?;
+        args.finish()?;
+        if target != "usb" {
+            log_warn!("Unknown boot target: {:?}", target);
+            return Err(CE::BadInput);
+        }
+        reboot_to_usb(ctx)
+    }
+}
+
+/// Same as `boot usb`, only with the words the other way around (or just the one)
+pub struct Usb;
+
+impl<D: Panel> Command<D> for Usb {
+    fn names(&self) -> &'static [&'static str] { &["usb"] }
+    fn usage(&self) -> &'static str { "usb [boot]: Same as boot usb" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let action = args.next();
+        args.finish()?;
+        if action.is_some_and(|a| a != "boot") {
+            log_warn!("Unknown usb action: {:?}", action);
+            return Err(CE::BadInput);
+        }
+        reboot_to_usb(ctx)
+    }
+}
+
+pub struct Redraw;
+
+impl<D: Panel> Command<D> for Redraw {
+    fn names(&self) -> &'static [&'static str] { &["redraw", "refresh", "reload", "r", "f5"] }
+    fn usage(&self) -> &'static str { "redraw: Force a redraw of the stack (Ctrl-R also redraws the textbox)" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        log_info!("Doing a forced redraw of stack. (command 'redraw')");
+        ctx.stack.invalidate();
+        ctx.stack.draw(true) // Just to be sure, we force a flush
+    }
+}
+
+pub struct Workspace;
+
+impl<D: Panel> Command<D> for Workspace {
+    fn names(&self) -> &'static [&'static str] { &["ws", "workspace"] }
+    fn usage(&self) -> &'static str { "ws N: Switch to the N-th workspace (1 to 4), each with its own stack" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        // Numbered from 1 for the user, from 0 for us
+        let n = args.next_int::<usize>()?;
+        args.finish()?;
+        if n == 0 || n > WORKSPACE_COUNT {
+            log_warn!("Workspace number out of range (1-{}): {}", WORKSPACE_COUNT, n);
+            return Err(CE::BadInput);
+        }
+        ctx.stack.switch_to(n - 1)?;
+        log_info!("Switched to workspace {} (command 'ws')", n);
+        ctx.stack.draw(false)
+    }
+}
+
+pub struct Scroll;
+
+impl<D: Panel> Command<D> for Scroll {
+    fn names(&self) -> &'static [&'static str] { &["scroll"] }
+    fn usage(&self) -> &'static str { "scroll N: Hide the top N elements to reveal deeper ones (PgUp/PgDn scroll by a page)" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        // Out of range values are clamped, not an error
+        let n = args.next_int::<usize>()?;
+        args.finish()?;
+        ctx.stack.set_scroll(n);
+        ctx.stack.draw(false)
+    }
+}
+
+pub struct Page;
+
+impl<D: Panel> Command<D> for Page {
+    fn names(&self) -> &'static [&'static str] { &["page"] }
+    fn usage(&self) -> &'static str { "page [stack|regs|status|clock]: Show the page instead of the stack, the next one by default (also Tab)" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let kind = match args.next().map(PageKind::from_name) {
+            None => ctx.state.pages.current().next(),
+            Some(Ok(kind)) => kind,
+            Some(Err(e)) => {
+                log_warn!("Expected stack, regs, status or clock as the page");
+                return Err(e);
+            },
+        };
+        args.finish()?;
+        log_info!("Showing the {} page (command 'page')", kind.name());
+        ctx.state.pages.show(kind);
+        if kind != PageKind::Stack {
+            return Ok(()); // The main loop draws it, and keeps it up to date
+        }
+        ctx.stack.invalidate();
+        ctx.stack.draw(true)
+    }
+}
+
+pub struct SetBrightness;
+
+impl<D: Panel> Command<D> for SetBrightness {
+    fn names(&self) -> &'static [&'static str] { &["brightness", "brt"] }
+    fn usage(&self) -> &'static str { "brightness N: Set display brightness to a level between 1 and 5" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let brightness_num = args.next_int::<u8>()?;
+        args.finish()?;
+        let Some(&contrast) = BRIGHTNESS_LEVELS.get((brightness_num as usize).wrapping_sub(1)) else {
+            log_warn!("Brightness value out of range (1-5): {}", brightness_num);
+            return Err(CE::BadInput);
+        };
+        let mut disp = ctx.disp_refcell.borrow_mut();
+        disp.set_contrast(contrast)?;
+        Ok(())
+    }
+}
+
+/// Takes the optional number of decimal places of `fix` and `sci`.
+fn decimal_places(mut args: Args<'_>) -> Result<Option<usize>, CustomError> {
+    let places = match args.is_empty() {
+        true => None,
+        false => Some(args.next_int::<usize>()?),
+    };
+    args.finish()?;
+    if let Some(places) = places && places > MAX_DECIMAL_PLACES {
+        log_warn!("Too many decimal places (max {}): {}", MAX_DECIMAL_PLACES, places);
+        return Err(CE::BadInput);
+    }
+    Ok(places)
+}
+
+/// Sets the number format of all workspaces and redraws the stack with it.
+fn set_number_format<D>(ctx: &mut Context<'_, '_, D>, number_format: NumberFormat) -> Result<(), CustomError>
+where
+    D: Panel,
+{
+    log_info!("Setting number format to {:?}", number_format);
+    ctx.stack.set_number_format(number_format);
+    ctx.stack.draw(false)
+}
+
+pub struct Fix;
+
+impl<D: Panel> Command<D> for Fix {
+    fn names(&self) -> &'static [&'static str] { &["fix"] }
+    fn usage(&self) -> &'static str { "fix [N]: Show numbers rounded to N decimal places, or with full precision without N" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, args: Args<'_>) -> Result<(), CustomError> {
+        let number_format = decimal_places(args)?.map_or(NumberFormat::Full, NumberFormat::Fixed);
+        set_number_format(ctx, number_format)
+    }
+}
+
+pub struct Sci;
+
+impl<D: Panel> Command<D> for Sci {
+    fn names(&self) -> &'static [&'static str] { &["sci"] }
+    fn usage(&self) -> &'static str { "sci [N]: Show numbers in scientific notation with N decimal places, or with full precision without N" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, args: Args<'_>) -> Result<(), CustomError> {
+        let number_format = decimal_places(args)?.map_or(NumberFormat::Full, NumberFormat::Scientific);
+        set_number_format(ctx, number_format)
+    }
+}
+
+pub struct Group;
+
+impl<D: Panel> Command<D> for Group {
+    fn names(&self) -> &'static [&'static str] { &["group"] }
+    fn usage(&self) -> &'static str { "group on|off: Group the digits of the numbers by thousands, it's remembered across reboots" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let digit_grouping = args.next_on_off()?;
+        args.finish()?;
+        log_info!("Setting digit grouping to {} (command 'group')", digit_grouping);
+
+        ctx.state.settings.digit_grouping = digit_grouping;
+        ctx.stack.set_digit_grouping(digit_grouping);
+        ctx.stack.draw(false)?;
+        persist::save_settings(&ctx.state.settings)
+    }
+}
+
+pub struct Big;
+
+impl<D: Panel> Command<D> for Big {
+    fn names(&self) -> &'static [&'static str] { &["big"] }
+    fn usage(&self) -> &'static str { "big on|off: Show just the top element in large digits, it's remembered across reboots" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let big_digits = args.next_on_off()?;
+        args.finish()?;
+        log_info!("Setting big digits to {} (command 'big')", big_digits);
+
+        ctx.state.settings.big_digits = big_digits;
+        ctx.stack.set_big_digits(big_digits);
+        ctx.stack.draw(false)?;
+        persist::save_settings(&ctx.state.settings)
+    }
+}
+
+pub struct Anim;
+
+impl<D: Panel> Command<D> for Anim {
+    fn names(&self) -> &'static [&'static str] { &["anim"] }
+    fn usage(&self) -> &'static str { "anim on|off: Slide the stack when pushing and popping, it's remembered across reboots" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let animation = args.next_on_off()?;
+        args.finish()?;
+        log_info!("Setting the animation to {} (command 'anim')", animation);
+
+        ctx.state.settings.animation = animation;
+        ctx.stack.set_animation(animation);
+        persist::save_settings(&ctx.state.settings)
+    }
+}
+
+pub struct Contrast;
+
+impl<D: Panel> Command<D> for Contrast {
+    fn names(&self) -> &'static [&'static str] { &["contrast"] }
+    fn usage(&self) -> &'static str { "contrast N: Set display contrast between 0 and 255, it's remembered across reboots" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        // Out of range values fail to parse into u8
+        let contrast = args.next_int::<u8>()?;
+        args.finish()?;
+        log_info!("Setting display contrast to {} (command 'contrast')", contrast);
+
+        ctx.state.settings.contrast = contrast;
+        let shown = ctx.state.settings.contrast_at(ctx.clock.status_time());
+        if shown != contrast {
+            log_info!("It's night, the contrast stays at {} until the morning (see 'night')", shown);
+        }
+        ctx.disp_refcell.borrow_mut().set_contrast(shown)?;
+        persist::save_settings(&ctx.state.settings)
+    }
+}
+
+pub struct AutoBrt;
+
+impl<D: Panel> Command<D> for AutoBrt {
+    fn names(&self) -> &'static [&'static str] { &["autobrt"] }
+    fn usage(&self) -> &'static str { "autobrt on|off: Follow the ambient light with the display contrast (needs a photoresistor on GPIO26)" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let auto_brightness = args.next_on_off()?;
+        args.finish()?;
+        log_info!("Setting auto brightness to {} (command 'autobrt')", auto_brightness);
+
+        ctx.state.settings.auto_brightness = auto_brightness;
+        // So that turning it on takes effect right away, not only once the light changes
+        ctx.state.auto_brightness.reset();
+        if !auto_brightness {
+            ctx.disp_refcell.borrow_mut().set_contrast(ctx.state.settings.contrast_at(ctx.clock.status_time()))?;
+        }
+        persist::save_settings(&ctx.state.settings)
+    }
+}
+
+pub struct Night;
+
+impl<D: Panel> Command<D> for Night {
+    fn names(&self) -> &'static [&'static str] { &["night"] }
+    fn usage(&self) -> &'static str { "night [HH:MM-HH:MM [N] | off]: Dim the display to contrast N between the two times, or show when it's dimmed" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let night = match args.next() {
+            None => {
+                let line: String<80> = match ctx.state.settings.night {
+                    Some(night) => {
+                        let is_night = ctx.clock.status_time().is_some_and(|(hour, minute)| night.contains(hour, minute));
+                        heapless::format!("Night from {:02}:{:02} to {:02}:{:02} at contrast {}, {}\r\n",
+                            night.start / 60, night.start % 60, night.end / 60, night.end % 60, night.contrast,
+                            if !ctx.clock.is_set() { "the time isn't set" } else if is_night { "it's night now" } else { "it's day now" })?
+                    },
+                    None => heapless::format!("Night mode is off\r\n")?,
+                };
+                (ctx.print)(line.as_bytes());
+                return Ok(());
+            },
+            Some("off") => None,
+            Some(range) => {
+                let contrast = if args.is_empty() { DEFAULT_NIGHT_CONTRAST } else { args.next_int::<u8>()? };
+                match NightSchedule::parse(range, contrast) {
+                    Ok(night) => Some(night),
+                    Err(e) => {
+                        log_warn!("Failed to parse the night {:?}, expected e.g. 22:00-07:00", range);
+                        return Err(e);
+                    },
+                }
+            },
+        };
+        args.finish()?;
+        log_info!("Setting the night mode to {} (command 'night')", if night.is_some() { "on" } else { "off" });
+        if night.is_some() && !ctx.clock.is_set() {
+            log_warn!("The time isn't set, the display won't dim until it is (see 'settime')");
+        }
+
+        ctx.state.settings.night = night;
+        if !ctx.state.settings.auto_brightness {
+            ctx.disp_refcell.borrow_mut().set_contrast(ctx.state.settings.contrast_at(ctx.clock.status_time()))?;
+        }
+        persist::save_settings(&ctx.state.settings)
+    }
+}
+
+pub struct Led;
+
+impl<D: Panel> Command<D> for Led {
+    fn names(&self) -> &'static [&'static str] { &["led"] }
+    fn usage(&self) -> &'static str { "led on|off|blink: Turn the onboard LED on or off, or blink it" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let mode = match args.next_str()? {
+            "on" => LedMode::On,
+            "off" => LedMode::Off,
+            "blink" => LedMode::Blink,
+            other => {
+                log_warn!("Expected on, off or blink, got {:?}", other);
+                return Err(CE::BadInput);
+            },
+        };
+        args.finish()?;
+        log_info!("Setting the LED to {:?} (command 'led')", mode);
+        ctx.state.led = mode; // The main loop drives the LED by it
+        Ok(())
+    }
+}
+
+pub struct Invert;
+
+impl<D: Panel> Command<D> for Invert {
+    fn names(&self) -> &'static [&'static str] { &["invert"] }
+    fn usage(&self) -> &'static str { "invert on|off: Invert the display, command mode shows the opposite" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let inverted = args.next_on_off()?;
+        args.finish()?;
+        log_info!("Setting display inversion to {} (command 'invert')", inverted);
+
+        ctx.state.settings.inverted = inverted;
+        // Command mode sets it again once it's done, but macros run outside of it
+        ctx.disp_refcell.borrow_mut().set_inverted(inverted)?;
+        persist::save_settings(&ctx.state.settings)
+    }
+}
+
+pub struct Saver;
+
+impl<D: Panel> Command<D> for Saver {
+    fn names(&self) -> &'static [&'static str] { &["saver"] }
+    fn usage(&self) -> &'static str { "saver N [blank|bounce]|off: Start the screensaver after N seconds without input" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let (secs, mode) = match args.next_str()? {
+            "off" => (0, ctx.state.settings.saver_mode),
+            secs => {
+                let mode = match args.next() {
+                    None | Some("bounce") => SaverMode::Bounce,
+                    Some("blank") => SaverMode::Blank,
+                    Some(other) => {
+                        log_warn!("Unknown screensaver mode: {:?}", other);
+                        return Err(CE::BadInput);
+                    }
+                };
+                (secs.parse::<u16>()?, mode)
+            },
+        };
+        args.finish()?;
+        log_info!("Setting the screensaver to {} s, {:?} (command 'saver')", secs, mode);
+
+        ctx.state.settings.saver_secs = secs;
+        ctx.state.settings.saver_mode = mode;
+        ctx.state.screensaver.wake(get_timestamp_us()); // The timeout starts now, not at the last key before command mode
+        persist::save_settings(&ctx.state.settings)
+    }
+}
+
+pub struct Sleep;
+
+impl<D: Panel> Command<D> for Sleep {
+    fn names(&self) -> &'static [&'static str] { &["sleep"] }
+    fn usage(&self) -> &'static str { "sleep N|off: Turn the display off and sleep after N seconds without input" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let secs = match args.next_str()? {
+            "off" => 0,
+            secs => secs.parse::<u16>()?,
+        };
+        args.finish()?;
+        log_info!("Setting the sleep timeout to {} s (command 'sleep')", secs);
+
+        ctx.state.settings.sleep_secs = secs;
+        // The timeout starts now, not at the last key before command mode
+        ctx.state.power.wake(&mut *ctx.disp_refcell.borrow_mut(), ctx.state.settings.contrast, get_timestamp_us())?;
+        persist::save_settings(&ctx.state.settings)
+    }
+}
+
+pub struct Echo;
+
+impl<D: Panel> Command<D> for Echo {
+    fn names(&self) -> &'static [&'static str] { &["echo"] }
+    fn usage(&self) -> &'static str { "echo on|off: Echo received characters back, for terminals without local echo" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let echo = args.next_on_off()?;
+        args.finish()?;
+        log_info!("Setting UART echo to {} (command 'echo')", echo);
+
+        ctx.state.settings.echo = echo;
+        persist::save_settings(&ctx.state.settings)
+    }
+}
+
+pub struct Crlf;
+
+impl<D: Panel> Command<D> for Crlf {
+    fn names(&self) -> &'static [&'static str] { &["crlf"] }
+    fn usage(&self) -> &'static str { "crlf on|off: End lines sent over UART with CR LF, or just LF" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let crlf = args.next_on_off()?;
+        args.finish()?;
+        // The print function got the old value already, it takes effect from the next command
+        log_info!("Setting UART CR LF line endings to {} (command 'crlf')", crlf);
+
+        ctx.state.settings.crlf = crlf;
+        persist::save_settings(&ctx.state.settings)
+    }
+}
+
+pub struct Baud;
+
+impl<D: Panel> Command<D> for Baud {
+    fn names(&self) -> &'static [&'static str] { &["baud"] }
+    fn usage(&self) -> &'static str { "baud N: Switch the UART to N baud, press Enter at the new rate within 10 s to keep it" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let baud = args.next_int::<u32>()?;
+        args.finish()?;
+        let old_baud = ctx.state.settings.baud;
+        log_info!("Switching UART to {} baud (command 'baud')", baud);
+
+        let msg: String<80> = heapless::format!(
+            "Switching to {} baud, press Enter within {} s to keep it\r\n", baud, BAUD_CONFIRM_US / 1_000_000
+        )?;
+        (ctx.print)(msg.as_bytes());
+        baud::set_baud_rate(baud, ctx.uart_clock_hz)?;
+
+        // If the terminal isn't switched in time (or can't do the rate), we mustn't stay deaf to it forever
+        let deadline = get_timestamp_us() + BAUD_CONFIRM_US;
+        while get_timestamp_us() < deadline {
+            // Whatever else arrives is probably garbage from the terminal still being at the old rate
+            if let Some(b'\r' | b'\n') = (ctx.poll_byte)() {
+                ctx.state.settings.baud = baud;
+                (ctx.print)(b"Baud rate saved\r\n");
+                return persist::save_settings(&ctx.state.settings);
+            }
+        }
+
+        log_warn!("Baud rate not confirmed, switching back to {}", old_baud);
+        baud::set_baud_rate(old_baud, ctx.uart_clock_hz)?;
+        (ctx.print)(b"Not confirmed, switched back\r\n");
+        Err(CE::Cancelled)
+    }
+}
+
+pub struct Flow;
+
+impl<D: Panel> Command<D> for Flow {
+    fn names(&self) -> &'static [&'static str] { &["flow"] }
+    fn usage(&self) -> &'static str { "flow on|off: Use RTS/CTS flow control over UART (CTS on GPIO 2, RTS on GPIO 3)" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let on = args.next_on_off()?;
+        args.finish()?;
+        log_info!("Setting UART flow control to {} (command 'flow')", on);
+
+        baud::set_flow_control(on)?;
+        ctx.state.settings.flow_control = on;
+        persist::save_settings(&ctx.state.settings)
+    }
+}
+
+pub struct Log;
+
+impl<D: Panel> Command<D> for Log {
+    fn names(&self) -> &'static [&'static str] { &["log"] }
+    fn usage(&self) -> &'static str { "log [LEVEL | mirror on|off]: Show or set the log level, or mirror the log to UART" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        match args.next() {
+            None => {
+                let msg: String<64> = heapless::format!(
+                    "Log level: {}, mirrored to UART: {}\r\n",
+                    log::level().name(), if log::is_mirrored() { "on" } else { "off" }
+                )?;
+                (ctx.print)(msg.as_bytes());
+            },
+            Some("mirror") => {
+                let mirrored = args.next_on_off()?;
+                args.finish()?;
+                log_info!("Setting log mirroring to UART to {} (command 'log')", mirrored);
+                log::set_mirrored(mirrored);
+            },
+            Some(name) => {
+                let level = Level::parse(name)?;
+                args.finish()?;
+                // Logged before setting, so that `log off` still leaves a trace of why the log went silent
+                log_info!("Setting log level to {} (command 'log')", level.name());
+                log::set_level(level);
+            },
+        }
+        Ok(())
+    }
+}
+
+pub struct ErrLog;
+
+impl<D: Panel> Command<D> for ErrLog {
+    fn names(&self) -> &'static [&'static str] { &["errlog"] }
+    fn usage(&self) -> &'static str { "errlog [save|clear]: List the last errors a page at a time (Ctrl-C or Esc quits), save them into flash, or forget them" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        match args.next() {
+            None => {},
+            Some("save") => {
+                args.finish()?;
+                log_info!("Saving the error log into flash (command 'errlog')");
+                return ctx.state.errlog.save();
+            },
+            Some("clear") => {
+                args.finish()?;
+                log_info!("Clearing the error log (command 'errlog')");
+                ctx.state.errlog.clear();
+                return match ErrorLog::remove_saved() {
+                    Err(CE::BadInput) => Ok(()), // There was nothing saved, which is fine
+                    result => result,
+                };
+            },
+            Some(other) => {
+                log_warn!("Expected save or clear, got {:?}", other);
+                return Err(CE::BadInput);
+            },
+        }
+
+        log_info!("Listing {} errors (command 'errlog')", ctx.state.errlog.len());
+        ctx.state.errlog.mark_seen();
+        if ctx.state.errlog.is_empty() {
+            (ctx.print)(b"No errors logged\r\n");
+            return Ok(());
+        }
+        // Two lines for each, the time and the message; the message gets cut off by the display's edge
+        let mut lines: Vec<String<TEXT_BUFFER_SIZE>, { 2 * ERROR_LOG_SIZE }> = Vec::new();
+        for (i, record) in ctx.state.errlog.iter().enumerate() {
+            let secs = record.timestamp_us / 1_000_000;
+            let previous_boot = if record.previous_boot { " (prev boot)" } else { "" };
+            let time: String<TEXT_BUFFER_SIZE> = heapless::format!(
+                "{}. {}:{:02}:{:02}.{:03}{}",
+                i + 1, secs / 3600, secs / 60 % 60, secs % 60, record.timestamp_us / 1000 % 1000, previous_boot
+            )?;
+            let msg: String<96> = heapless::format!("{} {}\r\n", time, record.message)?;
+            (ctx.print)(msg.as_bytes());
+
+            let mut message = String::new();
+            // Cut off, the display can't show more than that anyway
+            let _ = core::fmt::write(&mut TruncatingString(&mut message), format_args!("{}", record.message));
+            lines.push(time).map_err(|_| CE::Impossible)?; // Two for each of at most `ERROR_LOG_SIZE` records
+            lines.push(message).map_err(|_| CE::Impossible)?;
+        }
+
+        let lines_per_page = (ctx.disp_refcell.borrow().bounding_box().size.height / PAGE_LINE_HEIGHT) as usize;
+        // Whole records on each page, so that a message never gets separated from its time
+        let lines_per_page = (lines_per_page / 2 * 2).max(2);
+        let page_count = lines.len().div_ceil(lines_per_page);
+        for (page_index, page) in lines.chunks(lines_per_page).enumerate() {
+            draw_page(ctx, page)?;
+
+            // Wait for a key before showing the next page, or before going back to the stack after the last one
+            log_trace!("Showing page {} of {} of the error log", page_index + 1, page_count);
+            if let 0x03 | 0x1B = (ctx.read_byte)()? { // Ctrl-C or Esc
+                break;
+            }
+        }
+
+        ctx.stack.invalidate();
+        ctx.stack.draw(true)
+    }
+}
+
+pub struct Clear;
+
+impl<D: Panel> Command<D> for Clear {
+    fn names(&self) -> &'static [&'static str] { &["clear", "cls", "c"] }
+    fn usage(&self) -> &'static str { "clear: Clear the stack" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        // We automatically cleared the textbox when switching to command mode
+        if ctx.stack.is_empty() {
+            log_info!("Stack is already empty, ignoring clear command.");
+            return Ok(());
+        }
+        log_info!("Clearing stack by user request (command 'clear')");
+        ctx.stack.clear();
+        ctx.stack.draw(false) // No need to force flush here, we flush after handling the command anyway
+    }
+}
+
+pub struct Dup;
+
+impl<D: Panel> Command<D> for Dup {
+    fn names(&self) -> &'static [&'static str] { &["duplicate", "dup"] }
+    fn usage(&self) -> &'static str { "dup: Duplicate the top element" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        let Some(&val) = ctx.stack.peek() else {
+            log_warn!("Failed to duplicate top element of stack: stack is empty");
+            return Err(CE::BadInput);
+        };
+        push_and_draw(ctx, val, "duplicated top element")
+    }
+}
+
+pub struct DropTop;
+
+impl<D: Panel> Command<D> for DropTop {
+    fn names(&self) -> &'static [&'static str] { &["drop"] }
+    fn usage(&self) -> &'static str { "drop [N]: Remove the top element, or the top N elements" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        if args.is_empty() {
+            if ctx.stack.pop().is_none() {
+                log_warn!("Failed to drop top element of stack: stack is empty.");
+                return Err(CE::BadInput);
+            };
+            return ctx.stack.draw(false);
+        }
+
+        let count = args.next_int::<usize>()?;
+        args.finish()?;
+        // This checks if the stack isn't empty as well in sort of a roundabout way
+        // (non-zero count will always be greater than stack size if stack is empty)
+        if (count == 0) || (count > ctx.stack.len()) {
+            return Err(CE::BadInput);
+        }
+
+        let iter = ctx.stack.multipop(count).expect("We already checked if the stack is not empty!");
+
+        // We need this, can't rely on macro debug_assert_eq!()
+        #[cfg(debug_assertions)]
+        defmt::assert_eq!(iter.count(), count); // Counting the number of stuff popped
+        // (consuming the iterator in the process), and asserting that it's as expected.
+
+        // With this attribute, compiler is happy that iterator gets consumed before `draw()` no matter what
+        #[cfg(not(debug_assertions))]
+        drop(iter); // Automatically pops remaining unconsumed elements without bothering to count them
+
+        ctx.stack.draw(false) // The cfg-s does not apply to this line anymore
+    }
+}
+
+pub struct Swap;
+
+impl<D: Panel> Command<D> for Swap {
+    fn names(&self) -> &'static [&'static str] { &["swap", "s"] }
+    fn usage(&self) -> &'static str { "swap: Swap the top two elements" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        if let Err(e) = ctx.stack.swap_at(0, 1) {
+            log_warn!("Not enough numbers on stack to perform swap. Need 2, got {}.", ctx.stack.len());
+            return Err(e);
+        };
+        ctx.stack.draw(false)
+    }
+}
+
+pub struct Over;
+
+impl<D: Panel> Command<D> for Over {
+    fn names(&self) -> &'static [&'static str] { &["over"] }
+    fn usage(&self) -> &'static str { "over: Push a copy of the second element" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        if ctx.stack.len() < 2 {
+            log_warn!("Not enough numbers on stack to perform over. Need 2, got {}.", ctx.stack.len());
+            return Err(CE::BadInput);
+        }
+        ctx.stack.over()?; // Can only fail on CapacityError now
+        ctx.stack.draw(false)
+    }
+}
+
+pub struct Rot;
+
+impl<D: Panel> Command<D> for Rot {
+    fn names(&self) -> &'static [&'static str] { &["rot"] }
+    fn usage(&self) -> &'static str { "rot: Move the third element to the top" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        if ctx.stack.len() < 3 {
+            log_warn!("Not enough numbers on stack to perform rot. Need 3, got {}.", ctx.stack.len());
+            return Err(CE::BadInput);
+        }
+        ctx.stack.rot()?;
+        ctx.stack.draw(false)
+    }
+}
+
+pub struct Pick;
+
+impl<D: Panel> Command<D> for Pick {
+    fn names(&self) -> &'static [&'static str] { &["pick"] }
+    fn usage(&self) -> &'static str { "pick N: Push a copy of the N-th element (pick 0 = dup)" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let n = args.next_int::<usize>()?;
+        args.finish()?;
+        if let Err(e) = ctx.stack.pick(n) {
+            log_warn!("Failed to pick element {} of stack with {} elements: {:?}", n, ctx.stack.len(), e);
+            return Err(e);
+        };
+        ctx.stack.draw(false)
+    }
+}
+
+pub struct Roll;
+
+impl<D: Panel> Command<D> for Roll {
+    fn names(&self) -> &'static [&'static str] { &["roll"] }
+    fn usage(&self) -> &'static str { "roll N: Move the N-th element to the top (roll 1 = swap)" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let n = args.next_int::<usize>()?;
+        args.finish()?;
+        if let Err(e) = ctx.stack.roll(n) {
+            log_warn!("Failed to roll element {} of stack with {} elements: {:?}", n, ctx.stack.len(), e);
+            return Err(e);
+        };
+        ctx.stack.draw(false)
+    }
+}
+
+pub struct Neg;
+
+impl<D: Panel> Command<D> for Neg {
+    fn names(&self) -> &'static [&'static str] { &["neg"] }
+    fn usage(&self) -> &'static str { "neg [N]: Negate the top element, or the top N elements" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let count = if args.is_empty() { 1 } else { args.next_int::<usize>()? };
+        args.finish()?;
+
+        let top = ctx.stack.peek().copied();
+        // Either all of them get negated, or none of them
+        if let Err(e) = ctx.stack.apply_top_n(count, |x| { *x = (-*x)?; Ok(()) }) {
+            log_warn!("Failed to negate top {} elements of stack with {} elements: {:?}", count, ctx.stack.len(), e);
+            return Err(e);
+        };
+        if count > 0 {
+            ctx.state.last_x = top;
+        }
+        ctx.stack.draw(false)
+    }
+}
+
+pub struct Label;
+
+impl<D: Panel> Command<D> for Label {
+    fn names(&self) -> &'static [&'static str] { &["label"] }
+    fn usage(&self) -> &'static str { "label [TEXT]: Label the top element (up to 8 bytes, quote it if it has spaces), or remove its label" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        // Labels with spaces have to be quoted, e.g. `label "R 1"`
+        let text = args.next().unwrap_or("");
+        args.finish()?;
+        if let Err(e) = ctx.stack.set_label(0, text) {
+            log_warn!("Failed to label the top of stack with {} elements as {:?}: {:?}", ctx.stack.len(), text, e);
+            return Err(e);
+        };
+        log_info!("Labelled the top of stack as {:?} (command 'label')", text);
+        ctx.stack.draw(false)
+    }
+}
+
+pub struct Sort;
+
+impl<D: Panel> Command<D> for Sort {
+    fn names(&self) -> &'static [&'static str] { &["sort"] }
+    fn usage(&self) -> &'static str { "sort: Sort the stack in ascending order (biggest on top)" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        log_info!("Sorting the stack (command 'sort')");
+        ctx.stack.sort();
+        ctx.stack.draw(false)
+    }
+}
+
+pub struct Reverse;
+
+impl<D: Panel> Command<D> for Reverse {
+    fn names(&self) -> &'static [&'static str] { &["reverse", "rev"] }
+    fn usage(&self) -> &'static str { "reverse: Reverse the order of the stack" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        log_info!("Reversing the stack (command 'reverse')");
+        ctx.stack.reverse();
+        ctx.stack.draw(false)
+    }
+}
+
+pub struct Dump;
+
+impl<D: Panel> Command<D> for Dump {
+    fn names(&self) -> &'static [&'static str] { &["dump"] }
+    fn usage(&self) -> &'static str { "dump [csv|json]: Send the stack over UART, one `index,value` line per element (0 is the top)" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let json = match args.next() {
+            None | Some("csv") => false,
+            Some("json") => true,
+            Some(other) => {
+                log_warn!("Unknown dump format {:?}, expected csv or json", other);
+                return Err(CE::BadInput);
+            },
+        };
+        args.finish()?;
+        log_info!("Dumping {} elements as {} (command 'dump')", ctx.stack.len(), if json { "JSON" } else { "CSV" });
+        if ctx.stack.spilled_len() > 0 {
+            log_warn!("Not dumping {} elements spilled into flash", ctx.stack.spilled_len());
+        }
+
+        if !json {
+            (ctx.print)(b"index,value\r\n");
+        }
+        for (index, value) in ctx.stack.iter_from_top().enumerate() {
+            // The value is printed with full precision, not in the display format, so that nothing gets lost
+            let line: String<80> = if json {
+                heapless::format!("{{\"index\":{},\"value\":{}}}\r\n", index, value)?
+            } else {
+                heapless::format!("{},{}\r\n", index, value)?
+            };
+            (ctx.print)(line.as_bytes());
+        }
+        Ok(())
+    }
+}
+
+pub struct Screenshot;
+
+impl<D: Panel> Command<D> for Screenshot {
+    fn names(&self) -> &'static [&'static str] { &["screenshot"] }
+    fn usage(&self) -> &'static str { "screenshot [pbm|xbm]: Send what the display shows over UART as a PBM (PGM in grayscale) or XBM image" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let format = match args.next() {
+            None => Format::Pbm,
+            Some(name) => match Format::from_name(name) {
+                Ok(format) => format,
+                Err(e) => {
+                    log_warn!("Unknown screenshot format {:?}, expected pbm or xbm", name);
+                    return Err(e);
+                },
+            },
+        };
+        args.finish()?;
+
+        let mut disp = ctx.disp_refcell.borrow_mut();
+        disp.flush_now()?; // A frame held back isn't on the panel yet, but it's what the user sees a moment later
+        if disp.pixel(Point::zero()).is_none() {
+            log_warn!("Failed to take a screenshot: the display can't be read back");
+            return Err(CE::BadInput); // Not worth the grave error screen, the firmware runs on regardless
+        }
+        let size = disp.bounding_box().size;
+        log_info!("Sending a {}x{} screenshot as {} (command 'screenshot')",
+            size.width, size.height, if format == Format::Xbm { "XBM" } else { "PBM" });
+        let print = ctx.print;
+        screenshot::encode(
+            format, size, ctx.state.settings.inverted,
+            |point| disp.pixel(point).unwrap_or(D::Color::BACKGROUND),
+            |line| print(line),
+        )
+    }
+}
+
+pub struct Load;
+
+impl<D: Panel> Command<D> for Load {
+    fn names(&self) -> &'static [&'static str] { &["load"] }
+    fn usage(&self) -> &'static str { "load: Push numbers sent over UART, one per line, until an empty line or Ctrl-D" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, args: Args<'_>) -> Result<(), CustomError> {
+        args.finish()?;
+        log_info!("Loading numbers from UART (command 'load')");
+        (ctx.print)(b"Send numbers one per line, end with an empty line or Ctrl-D\r\n");
+
+        let (mut accepted, mut rejected) = (0_usize, 0_usize);
+        let mut line: String<LOAD_LINE_SIZE> = String::new();
+        let mut too_long = false;
+        let mut last_byte = 0_u8;
+        loop {
+            let byte = (ctx.read_byte)()?;
+            let end = match byte {
+                b'\n' if last_byte == b'\r' => { // The second half of CR LF
+                    last_byte = byte;
+                    continue;
+                },
+                b'\r' | b'\n' if line.is_empty() && !too_long => break,
+                b'\r' | b'\n' => false,
+                0x04 => true, // EOT, the last line needn't be terminated
+                _ => {
+                    // Non-ASCII bytes get mangled, but such a line wouldn't be a number anyway
+                    too_long |= line.push(byte as char).is_err();
+                    last_byte = byte;
+                    continue;
+                },
+            };
+            last_byte = byte;
+
+            if !line.is_empty() || too_long {
+                match line.trim().parse::<DecimalFixed>() {
+                    Ok(value) if !too_long => {
+                        if ctx.stack.push(value).is_err() {
+                            log_error!("Stack full after loading {} numbers", accepted);
+                            (ctx.print)(b"Stack full, stopped loading\r\n");
+                            ctx.stack.draw(false)?;
+                            return Err(CE::CapacityError);
+                        }
+                        accepted += 1;
+                    },
+                    _ => {
+                        log_warn!("Rejected line {:?} while loading", line.as_str());
+                        rejected += 1;
+                    },
+                }
+                line.clear();
+                too_long = false;
+            }
+            if end {
+                break;
+            }
+        }
+
+        log_info!("Loaded {} numbers, rejected {}", accepted, rejected);
+        let msg: String<64> = heapless::format!("Loaded {} numbers, rejected {}\r\n", accepted, rejected)?;
+        (ctx.print)(msg.as_bytes());
+        ctx.stack.draw(false)
+    }
+}
+
+pub struct Chart;
+
+impl<D: Panel> Command<D> for Chart {
+    fn names(&self) -> &'static [&'static str] { &["chart"] }
+    fn usage(&self) -> &'static str { "chart: Show the top elements as horizontal bars scaled to the widest one, until a key is pressed" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        if ctx.stack.is_empty() {
+            log_warn!("Failed to chart the stack: stack is empty.");
+            return Err(CE::BadInput);
+        }
+        let count = chart::bar_count(ctx.disp_refcell.borrow().bounding_box().size.height);
+        let values = ctx.stack.multipeek(count);
+        log_info!("Charting the top {} elements (command 'chart')", values.len());
+        chart::draw(&mut *ctx.disp_refcell.borrow_mut(), values)?;
+
+        (ctx.read_byte)()?; // Whichever key it is, it only flips back
+        ctx.stack.invalidate();
+        ctx.stack.draw(true)
+    }
+}
+
+pub struct Sum;
+
+impl<D: Panel> Command<D> for Sum {
+    fn names(&self) -> &'static [&'static str] { &["sum"] }
+    fn usage(&self) -> &'static str { "sum: Push the sum of all elements" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        let result = ctx.stack.sum();
+        push_stat(ctx, result, "sum")
+    }
+}
+
+pub struct Product;
+
+impl<D: Panel> Command<D> for Product {
+    fn names(&self) -> &'static [&'static str] { &["product", "prod"] }
+    fn usage(&self) -> &'static str { "product: Push the product of all elements" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        let result = ctx.stack.product();
+        push_stat(ctx, result, "product")
+    }
+}
+
+pub struct Mean;
+
+impl<D: Panel> Command<D> for Mean {
+    fn names(&self) -> &'static [&'static str] { &["mean", "avg"] }
+    fn usage(&self) -> &'static str { "mean: Push the arithmetic mean of all elements" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        let result = ctx.stack.mean();
+        push_stat(ctx, result, "mean")
+    }
+}
+
+pub struct Stddev;
+
+impl<D: Panel> Command<D> for Stddev {
+    fn names(&self) -> &'static [&'static str] { &["sdev", "stddev"] }
+    fn usage(&self) -> &'static str { "sdev: Push the sample standard deviation of all elements" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        let result = ctx.stack.stddev();
+        push_stat(ctx, result, "standard deviation")
+    }
+}
+
+/// Takes the data point of `s+` and `s-` from the top of the stack: just x with zero as y,
+/// or with the `pair` argument x from the top and y from below it. Returns the point and how many elements it took.
+fn stats_point<D>(ctx: &Context<'_, '_, D>, mut args: Args<'_>) -> Result<(DecimalFixed, DecimalFixed, usize), CustomError>
+where
+    D: Panel,
+{
+    let pair = match args.next() {
+        None => false,
+        Some("pair") => true,
+        Some(other) => {
+            log_warn!("Expected pair, got {:?}", other);
+            return Err(CE::BadInput);
+        },
+    };
+    args.finish()?;
+
+    let x = ctx.stack.peek().copied();
+    let y = if pair { ctx.stack.peek_nth(1).copied() } else { Some(DecimalFixed::ZERO) };
+    match (x, y) {
+        (Some(x), Some(y)) => Ok((x, y, if pair { 2 } else { 1 })),
+        _ => {
+            log_warn!("Not enough elements for a data point, stack has {}", ctx.stack.len());
+            Err(CE::BadInput)
+        },
+    }
+}
+
+pub struct StatsAdd;
+
+impl<D: Panel> Command<D> for StatsAdd {
+    fn names(&self) -> &'static [&'static str] { &["s+"] }
+    fn usage(&self) -> &'static str { "s+ [pair]: Pop the top element as x into the statistics registers (Σ+), with pair the top two as x and y" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, args: Args<'_>) -> Result<(), CustomError> {
+        let (x, y, taken) = stats_point(ctx, args)?;
+        if let Err(e) = ctx.state.stats.add(x, y) {
+            log_warn!("Failed to add ({}, {}) into the statistics registers: {:?}", x, y, e);
+            return Err(e);
+        }
+        let _ = ctx.stack.multipop(taken);
+        log_info!("Added ({}, {}) into the statistics registers, n = {} (command 's+')", x, y, ctx.state.stats.n());
+        ctx.stack.draw(false)
+    }
+}
+
+pub struct StatsSub;
+
+impl<D: Panel> Command<D> for StatsSub {
+    fn names(&self) -> &'static [&'static str] { &["s-"] }
+    fn usage(&self) -> &'static str { "s- [pair]: Pop the top element as x out of the statistics registers (Σ-), with pair the top two as x and y" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, args: Args<'_>) -> Result<(), CustomError> {
+        let (x, y, taken) = stats_point(ctx, args)?;
+        if let Err(e) = ctx.state.stats.remove(x, y) {
+            log_warn!("Failed to remove ({}, {}) from the statistics registers: {:?}", x, y, e);
+            return Err(e);
+        }
+        let _ = ctx.stack.multipop(taken);
+        log_info!("Removed ({}, {}) from the statistics registers, n = {} (command 's-')", x, y, ctx.state.stats.n());
+        ctx.stack.draw(false)
+    }
+}
+
+pub struct Stats;
+
+impl<D: Panel> Command<D> for Stats {
+    fn names(&self) -> &'static [&'static str] { &["stats"] }
+    fn usage(&self) -> &'static str { "stats [clear]: List the statistics registers a page at a time (Ctrl-C or Esc quits), or empty them" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        match args.next() {
+            None => {},
+            Some("clear") => {
+                args.finish()?;
+                log_info!("Clearing the statistics registers (command 'stats')");
+                ctx.state.stats.clear();
+                return Ok(());
+            },
+            Some(other) => {
+                log_warn!("Expected clear, got {:?}", other);
+                return Err(CE::BadInput);
+            },
+        }
+
+        log_info!("Listing the statistics registers of {} data points (command 'stats')", ctx.state.stats.n());
+        let mut lines: Vec<String<TEXT_BUFFER_SIZE>, 6> = Vec::new();
+        let n: String<TEXT_BUFFER_SIZE> = heapless::format!("n: {}", ctx.state.stats.n())?;
+        lines.push(n).map_err(|_| CE::Impossible)?; // The count and the five sums
+        for (name, val) in ctx.state.stats.sums() {
+            let line: String<TEXT_BUFFER_SIZE> = match val {
+                Ok(val) => heapless::format!("{}: {}", name, val)?,
+                Err(_) => heapless::format!("{}: too big", name)?, // The statistics computed from it can still fit
+            };
+            lines.push(line).map_err(|_| CE::Impossible)?;
+        }
+        for line in &lines {
+            (ctx.print)(line.as_bytes());
+            (ctx.print)(b"\r\n");
+        }
+
+        let lines_per_page = (ctx.disp_refcell.borrow().bounding_box().size.height / PAGE_LINE_HEIGHT) as usize;
+        let page_count = lines.len().div_ceil(lines_per_page);
+        for (page_index, page) in lines.chunks(lines_per_page).enumerate() {
+            draw_page(ctx, page)?;
+
+            // Wait for a key before showing the next page, or before going back to the stack after the last one
+            log_trace!("Showing page {} of {} of the statistics registers", page_index + 1, page_count);
+            if let 0x03 | 0x1B = (ctx.read_byte)()? { // Ctrl-C or Esc
+                break;
+            }
+        }
+
+        ctx.stack.invalidate();
+        ctx.stack.draw(true)
+    }
+}
+
+/// Pushes a pair of results computed from the statistics registers, the first one ends up below the second one.
+fn push_stats_pair<D>(ctx: &mut Context<'_, '_, D>, result: Result<(DecimalFixed, DecimalFixed), CustomError>, name: &str) -> Result<(), CustomError>
+where
+    D: Panel,
+{
+    match result {
+        Ok((below, top)) => push_pair_and_draw(ctx, below, top, name),
+        Err(e) => {
+            log_warn!("Failed to compute {} of {} data points: {:?}", name, ctx.state.stats.n(), e);
+            Err(e)
+        }
+    }
+}
+
+pub struct StatsMean;
+
+impl<D: Panel> Command<D> for StatsMean {
+    fn names(&self) -> &'static [&'static str] { &["smean"] }
+    fn usage(&self) -> &'static str { "smean: Push the means of y and x from the statistics registers, x on top" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        let result = ctx.state.stats.mean().map(|(x, y)| (y, x));
+        push_stats_pair(ctx, result, "means")
+    }
+}
+
+pub struct StatsStddev;
+
+impl<D: Panel> Command<D> for StatsStddev {
+    fn names(&self) -> &'static [&'static str] { &["ssdev"] }
+    fn usage(&self) -> &'static str { "ssdev: Push the sample standard deviations of y and x from the statistics registers, x on top" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        let result = ctx.state.stats.stddev().map(|(x, y)| (y, x));
+        push_stats_pair(ctx, result, "standard deviations")
+    }
+}
+
+pub struct LinReg;
+
+impl<D: Panel> Command<D> for LinReg {
+    fn names(&self) -> &'static [&'static str] { &["lr", "linreg"] }
+    fn usage(&self) -> &'static str { "lr: Push the slope and the intercept of the line fitted through the statistics registers, intercept on top" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        let result = ctx.state.stats.regression();
+        if let Ok((slope, intercept)) = result {
+            let msg: String<96> = heapless::format!("y = {} * x + {}\r\n", slope, intercept)?;
+            (ctx.print)(msg.as_bytes());
+        }
+        push_stats_pair(ctx, result, "linear regression")
+    }
+}
+
+pub struct Predict;
+
+impl<D: Panel> Command<D> for Predict {
+    fn names(&self) -> &'static [&'static str] { &["predict"] }
+    fn usage(&self) -> &'static str { "predict: Replace the top element x with the y the line fitted through the statistics registers predicts for it" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        let Some(&x) = ctx.stack.peek() else {
+            log_warn!("Failed to predict: stack is empty.");
+            return Err(CE::BadInput);
+        };
+        let y = match ctx.state.stats.predict(x) {
+            Ok(y) => y,
+            Err(e) => {
+                log_warn!("Failed to predict y for x = {} from {} data points: {:?}", x, ctx.state.stats.n(), e);
+                return Err(e);
+            }
+        };
+        ctx.stack.pop();
+        ctx.state.last_x = Some(x);
+        push_and_draw(ctx, y, "prediction")
+    }
+}
+
+/// Takes the optional dimensions of the vectors of the vector commands, 3 if there's none.
+fn vector_dimensions(mut args: Args<'_>) -> Result<usize, CustomError> {
+    let dimensions = match args.is_empty() {
+        true => MAX_DIMENSIONS,
+        false => args.next_int::<usize>()?,
+    };
+    args.finish()?;
+    if !(2..=MAX_DIMENSIONS).contains(&dimensions) {
+        log_warn!("Vectors can only be 2D or 3D, got {}", dimensions);
+        return Err(CE::BadInput);
+    }
+    Ok(dimensions)
+}
+
+/// Copies the `index`-th vector from the top of the stack (the topmost one is 0), each one being `dimensions` elements with x deepest.
+fn peek_vector<D>(ctx: &Context<'_, '_, D>, index: usize, dimensions: usize) -> Result<Vector, CustomError>
+where
+    D: Panel,
+{
+    let mut components: Vec<DecimalFixed, MAX_DIMENSIONS> = Vec::new();
+    for i in (0..dimensions).rev() {
+        let Some(&c) = ctx.stack.peek_nth(index * dimensions + i) else {
+            log_warn!("Not enough elements for {} {}D vectors, stack has {}", index + 1, dimensions, ctx.stack.len());
+            return Err(CE::BadInput);
+        };
+        components.push(c).map_err(|_| CE::BadInput)?; // More than `MAX_DIMENSIONS` got past `vector_dimensions()`
+    }
+    Vector::new(&components)
+}
+
+/// Formats the vector the same way the stack formats its numbers, see `fix`, `sci` and `group`.
+fn format_vector<D>(ctx: &Context<'_, '_, D>, vector: &Vector) -> Result<String<VECTOR_TEXT_SIZE>, CustomError>
+where
+    D: Panel,
+{
+    Ok(match (ctx.stack.number_format(), ctx.stack.digit_grouping()) {
+        (NumberFormat::Full, false) => heapless::format!("{}", vector)?,
+        (NumberFormat::Full, true) => heapless::format!("{:#}", vector)?,
+        (NumberFormat::Fixed(places), false) => heapless::format!("{:.places$}", vector)?,
+        (NumberFormat::Fixed(places), true) => heapless::format!("{:#.places$}", vector)?,
+        (NumberFormat::Scientific(places), _) => heapless::format!("{:.places$e}", vector)?,
+    })
+}
+
+/// Replaces the top `count` elements with the components of the vector, and prints it over UART.
+fn replace_with_vector<D>(ctx: &mut Context<'_, '_, D>, count: usize, vector: &Vector, what: &str) -> Result<(), CustomError>
+where
+    D: Panel,
+{
+    let text = format_vector(ctx, vector)?;
+    let _ = ctx.stack.multipop(count);
+    // Can't run out of space, we've just popped at least as many
+    if let Err(e) = ctx.stack.push_slice(vector.components()) {
+        log_error!("Failed to push {} onto stack: {:?}", what, e);
+        return Err(e);
+    }
+    (ctx.print)(text.as_bytes());
+    (ctx.print)(b"\r\n");
+    ctx.stack.draw(false)
+}
+
+pub struct Dot;
+
+impl<D: Panel> Command<D> for Dot {
+    fn names(&self) -> &'static [&'static str] { &["dot"] }
+    fn usage(&self) -> &'static str { "dot [2|3]: Replace the top two vectors (3D by default) with their dot product" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, args: Args<'_>) -> Result<(), CustomError> {
+        let dimensions = vector_dimensions(args)?;
+        let (a, b) = (peek_vector(ctx, 1, dimensions)?, peek_vector(ctx, 0, dimensions)?);
+        let product = match a.dot(&b) {
+            Ok(product) => product,
+            Err(e) => {
+                log_warn!("Failed to compute the dot product of {} and {}: {:?}", a, b, e);
+                return Err(e);
+            }
+        };
+        log_info!("Dot product of {} and {} is {} (command 'dot')", a, b, product);
+        let _ = ctx.stack.multipop(2 * dimensions);
+        push_and_draw(ctx, product, "dot product")
+    }
+}
+
+pub struct Cross;
+
+impl<D: Panel> Command<D> for Cross {
+    fn names(&self) -> &'static [&'static str] { &["cross"] }
+    fn usage(&self) -> &'static str { "cross: Replace the top two 3D vectors a and b with a × b (b is the topmost)" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        let (a, b) = (peek_vector(ctx, 1, MAX_DIMENSIONS)?, peek_vector(ctx, 0, MAX_DIMENSIONS)?);
+        let product = match a.cross(&b) {
+            Ok(product) => product,
+            Err(e) => {
+                log_warn!("Failed to compute the cross product of {} and {}: {:?}", a, b, e);
+                return Err(e);
+            }
+        };
+        log_info!("Cross product of {} and {} is {} (command 'cross')", a, b, product);
+        replace_with_vector(ctx, 2 * MAX_DIMENSIONS, &product, "cross product")
+    }
+}
+
+pub struct Magnitude;
+
+impl<D: Panel> Command<D> for Magnitude {
+    fn names(&self) -> &'static [&'static str] { &["mag", "vabs"] }
+    fn usage(&self) -> &'static str { "mag [2|3]: Replace the top vector (3D by default) with its magnitude" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, args: Args<'_>) -> Result<(), CustomError> {
+        let dimensions = vector_dimensions(args)?;
+        let v = peek_vector(ctx, 0, dimensions)?;
+        let magnitude = match v.magnitude() {
+            Ok(magnitude) => magnitude,
+            Err(e) => {
+                log_warn!("Failed to compute the magnitude of {}: {:?}", v, e);
+                return Err(e);
+            }
+        };
+        log_info!("Magnitude of {} is {} (command 'mag')", v, magnitude);
+        let _ = ctx.stack.multipop(dimensions);
+        push_and_draw(ctx, magnitude, "magnitude")
+    }
+}
+
+pub struct Unit;
+
+impl<D: Panel> Command<D> for Unit {
+    fn names(&self) -> &'static [&'static str] { &["unit", "normalize"] }
+    fn usage(&self) -> &'static str { "unit [2|3]: Replace the top vector (3D by default) with the unit vector of the same direction" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, args: Args<'_>) -> Result<(), CustomError> {
+        let dimensions = vector_dimensions(args)?;
+        let v = peek_vector(ctx, 0, dimensions)?;
+        let unit = match v.normalize() {
+            Ok(unit) => unit,
+            Err(e) => {
+                log_warn!("Failed to normalize {}: {:?}", v, e);
+                return Err(e);
+            }
+        };
+        log_info!("Normalized {} to {} (command 'unit')", v, unit);
+        replace_with_vector(ctx, dimensions, &unit, "unit vector")
+    }
+}
+
+pub struct ShowVector;
+
+impl<D: Panel> Command<D> for ShowVector {
+    fn names(&self) -> &'static [&'static str] { &["vec"] }
+    fn usage(&self) -> &'static str { "vec [2|3]: Show the top vector (3D by default) as [x, y, z] until a key is pressed" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, args: Args<'_>) -> Result<(), CustomError> {
+        let dimensions = vector_dimensions(args)?;
+        let v = peek_vector(ctx, 0, dimensions)?;
+        let text = format_vector(ctx, &v)?;
+        log_info!("Showing vector {} (command 'vec')", text.as_str());
+        (ctx.print)(text.as_bytes());
+        (ctx.print)(b"\r\n");
+
+        // On a single line if it fits, otherwise a component on each
+        let chars_per_line = (ctx.disp_refcell.borrow().bounding_box().size.width / PAGE_CHAR_WIDTH) as usize;
+        let mut lines: Vec<String<TEXT_BUFFER_SIZE>, MAX_DIMENSIONS> = Vec::new();
+        if text.chars().count() <= chars_per_line {
+            lines.push(String::try_from(text.as_str()).map_err(|_| CE::Impossible)?).map_err(|_| CE::Impossible)?; // Shorter than the line
+        } else {
+            let parts = text.split(", ");
+            let part_count = parts.clone().count();
+            for (i, part) in parts.enumerate() {
+                let mut line = String::new();
+                let indent = if i == 0 { "" } else { " " };
+                let separator = if i + 1 == part_count { "" } else { "," };
+                // Cut off, the display can't show more than that anyway
+                let _ = core::fmt::write(&mut TruncatingString(&mut line), format_args!("{}{}{}", indent, part, separator));
+                lines.push(line).map_err(|_| CE::Impossible)?; // One for each component
+            }
+        }
+        draw_page(ctx, &lines)?;
+        (ctx.read_byte)()?;
+
+        ctx.stack.invalidate();
+        ctx.stack.draw(true)
+    }
+}
+
+pub struct Poly;
+
+impl<D: Panel> Command<D> for Poly {
+    fn names(&self) -> &'static [&'static str] { &["poly"] }
+    fn usage(&self) -> &'static str { "poly [N|eval|roots|clear]: Print the stored polynomial, store one of degree N, evaluate it, or solve a quadratic" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        match args.next() {
+            None => {
+                let mut text: String<POLY_TEXT_SIZE> = String::new();
+                if ctx.state.poly.is_empty() {
+                    let _ = text.push_str("No polynomial stored");
+                } else {
+                    // Cut off, a polynomial that long wouldn't be readable anyway
+                    let _ = core::fmt::write(&mut TruncatingString(&mut text), format_args!("{}", ctx.state.poly));
+                }
+                (ctx.print)(text.as_bytes());
+                (ctx.print)(b"\r\n");
+                Ok(())
+            },
+            Some("eval") => {
+                args.finish()?;
+                let Some(&x) = ctx.stack.peek() else {
+                    log_warn!("Failed to evaluate the polynomial: stack is empty.");
+                    return Err(CE::BadInput);
+                };
+                let y = match ctx.state.poly.evaluate(x) {
+                    Ok(y) => y,
+                    Err(e) => {
+                        log_warn!("Failed to evaluate the polynomial of degree {} at {}: {:?}", ctx.state.poly.degree(), x, e);
+                        return Err(e);
+                    }
+                };
+                log_info!("Polynomial at {} is {} (command 'poly eval')", x, y);
+                ctx.stack.pop();
+                ctx.state.last_x = Some(x);
+                push_and_draw(ctx, y, "value of the polynomial")
+            },
+            Some("roots") => {
+                args.finish()?;
+                let (x1, x2) = match ctx.state.poly.quadratic_roots() {
+                    Ok(roots) => roots,
+                    Err(e) => {
+                        log_warn!("Failed to solve the polynomial of degree {}: {:?}", ctx.state.poly.degree(), e);
+                        return Err(e);
+                    }
+                };
+                log_info!("Roots of the quadratic are {} and {} (command 'poly roots')", x1, x2);
+                push_pair_and_draw(ctx, x1, x2, "roots")
+            },
+            Some("clear") => {
+                args.finish()?;
+                log_info!("Clearing the polynomial (command 'poly clear')");
+                ctx.state.poly.clear();
+                Ok(())
+            },
+            Some(degree) => {
+                args.finish()?;
+                let degree: usize = degree.parse()?;
+                if degree > MAX_DEGREE {
+                    log_warn!("Polynomial degree too high (max {}): {}", MAX_DEGREE, degree);
+                    return Err(CE::BadInput);
+                }
+                let mut coefficients: Vec<DecimalFixed, { MAX_DEGREE + 1 }> = Vec::new();
+                for i in (0..=degree).rev() {
+                    let Some(&c) = ctx.stack.peek_nth(i) else {
+                        log_warn!("Not enough elements for a polynomial of degree {}, stack has {}", degree, ctx.stack.len());
+                        return Err(CE::BadInput);
+                    };
+                    coefficients.push(c).map_err(|_| CE::Impossible)?; // We checked the degree above
+                }
+                ctx.state.poly.set(&coefficients)?;
+                log_info!("Stored a polynomial of degree {} (command 'poly')", degree);
+                let _ = ctx.stack.multipop(degree + 1);
+                ctx.stack.draw(false)
+            },
+        }
+    }
+}
+
+/// Takes the whole parts of the top `N` elements of the stack for the number theory commands, the topmost last, leaving them in place.
+fn peek_integers<D, const N: usize>(ctx: &Context<'_, '_, D>) -> Result<[i64; N], CustomError>
+where
+    D: Panel,
+{
+    let mut integers = [0; N];
+    for (i, integer) in integers.iter_mut().enumerate() {
+        let Some(&val) = ctx.stack.peek_nth(N - 1 - i) else {
+            log_warn!("Not enough elements for {} integers, stack has {}", N, ctx.stack.len());
+            return Err(CE::BadInput);
+        };
+        *integer = val.integer_part()?;
+    }
+    Ok(integers)
+}
+
+/// Replaces the top `count` elements with the result of a number theory command.
+fn replace_with_integer<D>(ctx: &mut Context<'_, '_, D>, count: usize, result: u64, what: &str) -> Result<(), CustomError>
+where
+    D: Panel,
+{
+    let result = DecimalFixed::new(i64::try_from(result)?, None)?;
+    ctx.state.last_x = ctx.stack.peek().copied();
+    let _ = ctx.stack.multipop(count);
+    push_and_draw(ctx, result, what)
+}
+
+/// Takes the optional unit of the angles of `r->p` and `p->r`, degrees if there's none.
+fn angle_unit(mut args: Args<'_>) -> Result<AngleUnit, CustomError> {
+    let unit = match args.next() {
+        None | Some("deg") => AngleUnit::Degrees,
+        Some("rad") => AngleUnit::Radians,
+        Some(other) => {
+            log_warn!("Expected deg or rad, got {:?}", other);
+            return Err(CE::BadInput);
+        },
+    };
+    args.finish()?;
+    Ok(unit)
+}
+
+pub struct PlotPoly;
+
+impl<D: Panel> Command<D> for PlotPoly {
+    fn names(&self) -> &'static [&'static str] { &["plot"] }
+    fn usage(&self) -> &'static str { "plot: Plot the stored polynomial from x_min to x_max (the top element), pan with Left and Right, any other key returns" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        if ctx.state.poly.is_empty() {
+            log_warn!("Failed to plot: no polynomial stored");
+            return Err(CE::BadInput);
+        }
+        let (Some(&x_min), Some(&x_max)) = (ctx.stack.peek_nth(1), ctx.stack.peek()) else {
+            log_warn!("Failed to plot: stack has {} elements", ctx.stack.len());
+            return Err(CE::BadInput);
+        };
+        let width = (ctx.disp_refcell.borrow().bounding_box().size.width as usize).min(plot::MAX_WIDTH);
+        let poly = &ctx.state.poly;
+        let mut plot = match Plot::sample(x_min, x_max, width, |x| poly.evaluate(x)) {
+            Ok(plot) => plot,
+            Err(e) => {
+                log_warn!("Failed to plot from {} to {}: {:?}", x_min, x_max, e);
+                return Err(e);
+            }
+        };
+        if plot.y_range().is_none() {
+            log_warn!("Failed to plot from {} to {}: the polynomial overflows everywhere", x_min, x_max);
+            return Err(CE::MathOverflow);
+        }
+        log_info!("Plotting the polynomial from {} to {} (command 'plot')", x_min, x_max);
+
+        loop {
+            let (x_min, x_max) = plot.x_range();
+            let line: String<RANGE_TEXT_SIZE> = heapless::format!("x from {} to {}\r\n", x_min, x_max)?;
+            (ctx.print)(line.as_bytes());
+            if let Some((y_min, y_max)) = plot.y_range() {
+                let line: String<RANGE_TEXT_SIZE> = heapless::format!("y from {} to {}\r\n", y_min, y_max)?;
+                (ctx.print)(line.as_bytes());
+            }
+            plot.draw(&mut *ctx.disp_refcell.borrow_mut())?;
+
+            let steps = match read_nav_key(ctx)? {
+                NavKey::Left => -1,
+                NavKey::Right => 1,
+                _ => break,
+            };
+            // Past the range of the numbers it just stays where it is
+            let panned = plot.panned(steps).and_then(|(x_min, x_max)| {
+                let poly = &ctx.state.poly;
+                Plot::sample(x_min, x_max, width, |x| poly.evaluate(x))
+            });
+            match panned {
+                Ok(panned) => plot = panned,
+                Err(e) => log_warn!("Failed to pan the plot: {:?}", e),
+            }
+        }
+
+        ctx.stack.invalidate();
+        ctx.stack.draw(true)
+    }
+}
+
+pub struct RectToPolar;
+
+impl<D: Panel> Command<D> for RectToPolar {
+    fn names(&self) -> &'static [&'static str] { &["r->p", "r>p", "polar"] }
+    fn usage(&self) -> &'static str { "r->p [deg|rad]: Replace x and y (on top) with the magnitude and the angle (on top), in degrees by default" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, args: Args<'_>) -> Result<(), CustomError> {
+        let unit = angle_unit(args)?;
+        let (Some(&x), Some(&y)) = (ctx.stack.peek_nth(1), ctx.stack.peek()) else {
+            log_warn!("Failed to convert to polar: stack has {} elements", ctx.stack.len());
+            return Err(CE::BadInput);
+        };
+        let (magnitude, angle) = match polar::to_polar(x, y, unit) {
+            Ok(polar) => polar,
+            Err(e) => {
+                log_warn!("Failed to convert ({}, {}) to polar: {:?}", x, y, e);
+                return Err(e);
+            }
+        };
+        log_info!("({}, {}) is {} at {} {:?} (command 'r->p')", x, y, magnitude, angle, unit);
+        ctx.state.last_x = Some(y);
+        let _ = ctx.stack.multipop(2);
+        push_pair_and_draw(ctx, magnitude, angle, "polar coordinates")
+    }
+}
+
+pub struct PolarToRect;
+
+impl<D: Panel> Command<D> for PolarToRect {
+    fn names(&self) -> &'static [&'static str] { &["p->r", "p>r", "rect"] }
+    fn usage(&self) -> &'static str { "p->r [deg|rad]: Replace the magnitude and the angle (on top, in degrees by default) with x and y (on top)" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, args: Args<'_>) -> Result<(), CustomError> {
+        let unit = angle_unit(args)?;
+        let (Some(&magnitude), Some(&angle)) = (ctx.stack.peek_nth(1), ctx.stack.peek()) else {
+            log_warn!("Failed to convert to rectangular: stack has {} elements", ctx.stack.len());
+            return Err(CE::BadInput);
+        };
+        let (x, y) = match polar::to_rect(magnitude, angle, unit) {
+            Ok(rect) => rect,
+            Err(e) => {
+                log_warn!("Failed to convert {} at {} {:?} to rectangular: {:?}", magnitude, angle, unit, e);
+                return Err(e);
+            }
+        };
+        log_info!("{} at {} {:?} is ({}, {}) (command 'p->r')", magnitude, angle, unit, x, y);
+        ctx.state.last_x = Some(angle);
+        let _ = ctx.stack.multipop(2);
+        push_pair_and_draw(ctx, x, y, "rectangular coordinates")
+    }
+}
+
+pub struct Hypot;
+
+impl<D: Panel> Command<D> for Hypot {
+    fn names(&self) -> &'static [&'static str] { &["hypot"] }
+    fn usage(&self) -> &'static str { "hypot: Replace the top two elements a and b with sqrt(a² + b²)" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        let (Some(&a), Some(&b)) = (ctx.stack.peek_nth(1), ctx.stack.peek()) else {
+            log_warn!("Failed to compute hypot: stack has {} elements", ctx.stack.len());
+            return Err(CE::BadInput);
+        };
+        let result = match a.hypot(b) {
+            Ok(result) => result,
+            Err(e) => {
+                log_warn!("Failed to compute hypot of {} and {}: {:?}", a, b, e);
+                return Err(e);
+            }
+        };
+        ctx.state.last_x = Some(b);
+        let _ = ctx.stack.multipop(2);
+        push_and_draw(ctx, result, "hypot")
+    }
+}
+
+/// Takes the optional kind of the ratios of `db` and `undb`, power if there's none.
+fn ratio_kind(mut args: Args<'_>) -> Result<RatioKind, CustomError> {
+    let kind = match args.next() {
+        None | Some("pow") => RatioKind::Power,
+        Some("amp") => RatioKind::Amplitude,
+        Some(other) => {
+            log_warn!("Expected pow or amp, got {:?}", other);
+            return Err(CE::BadInput);
+        },
+    };
+    args.finish()?;
+    Ok(kind)
+}
+
+pub struct Db;
+
+impl<D: Panel> Command<D> for Db {
+    fn names(&self) -> &'static [&'static str] { &["db"] }
+    fn usage(&self) -> &'static str { "db [pow|amp]: Replace the top element (a ratio, of power by default) with it in decibels" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, args: Args<'_>) -> Result<(), CustomError> {
+        let kind = ratio_kind(args)?;
+        let Some(&ratio) = ctx.stack.peek() else {
+            log_warn!("Failed to convert to decibels: stack is empty.");
+            return Err(CE::BadInput);
+        };
+        let decibels = match ratio.to_db(kind) {
+            Ok(decibels) => decibels,
+            Err(e) => {
+                log_warn!("Failed to convert {} ({:?}) to decibels: {:?}", ratio, kind, e);
+                return Err(e);
+            }
+        };
+        log_info!("{} ({:?}) is {} dB (command 'db')", ratio, kind, decibels);
+        ctx.stack.pop();
+        ctx.state.last_x = Some(ratio);
+        push_and_draw(ctx, decibels, "decibels")
+    }
+}
+
+pub struct Undb;
+
+impl<D: Panel> Command<D> for Undb {
+    fn names(&self) -> &'static [&'static str] { &["undb"] }
+    fn usage(&self) -> &'static str { "undb [pow|amp]: Replace the top element (in decibels) with the ratio, of power by default" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, args: Args<'_>) -> Result<(), CustomError> {
+        let kind = ratio_kind(args)?;
+        let Some(&decibels) = ctx.stack.peek() else {
+            log_warn!("Failed to convert from decibels: stack is empty.");
+            return Err(CE::BadInput);
+        };
+        let ratio = match DecimalFixed::from_db(decibels, kind) {
+            Ok(ratio) => ratio,
+            Err(e) => {
+                log_warn!("Failed to convert {} dB to a ratio ({:?}): {:?}", decibels, kind, e);
+                return Err(e);
+            }
+        };
+        log_info!("{} dB is {} ({:?}) (command 'undb')", decibels, ratio, kind);
+        ctx.stack.pop();
+        ctx.state.last_x = Some(decibels);
+        push_and_draw(ctx, ratio, "ratio")
+    }
+}
+
+pub struct Gcd;
+
+impl<D: Panel> Command<D> for Gcd {
+    fn names(&self) -> &'static [&'static str] { &["gcd"] }
+    fn usage(&self) -> &'static str { "gcd: Replace the top two elements with the greatest common divisor of their whole parts" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        let [a, b] = peek_integers(ctx)?;
+        let result = numtheory::gcd(a.unsigned_abs(), b.unsigned_abs());
+        log_info!("GCD of {} and {} is {} (command 'gcd')", a, b, result);
+        replace_with_integer(ctx, 2, result, "GCD")
+    }
+}
+
+pub struct Lcm;
+
+impl<D: Panel> Command<D> for Lcm {
+    fn names(&self) -> &'static [&'static str] { &["lcm"] }
+    fn usage(&self) -> &'static str { "lcm: Replace the top two elements with the least common multiple of their whole parts" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        let [a, b] = peek_integers(ctx)?;
+        let result = match numtheory::lcm(a.unsigned_abs(), b.unsigned_abs()) {
+            Ok(result) => result,
+            Err(e) => {
+                log_warn!("Failed to compute the LCM of {} and {}: {:?}", a, b, e);
+                return Err(e);
+            }
+        };
+        log_info!("LCM of {} and {} is {} (command 'lcm')", a, b, result);
+        replace_with_integer(ctx, 2, result, "LCM")
+    }
+}
+
+/// Takes n and k of `ncr` and `npr` from the whole parts of the top two elements, k being the topmost. Neither can be negative.
+fn peek_n_k<D>(ctx: &Context<'_, '_, D>) -> Result<(u64, u64), CustomError>
+where
+    D: Panel,
+{
+    let [n, k] = peek_integers(ctx)?;
+    match (u64::try_from(n), u64::try_from(k)) {
+        (Ok(n), Ok(k)) => Ok((n, k)),
+        _ => {
+            log_warn!("Can't pick {} out of {}, neither can be negative", k, n);
+            Err(CE::BadInput)
+        },
+    }
+}
+
+pub struct Ncr;
+
+impl<D: Panel> Command<D> for Ncr {
+    fn names(&self) -> &'static [&'static str] { &["ncr", "comb"] }
+    fn usage(&self) -> &'static str { "ncr: Replace n and k (on top) with the number of combinations, the ways to pick k out of n regardless of order" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        let (n, k) = peek_n_k(ctx)?;
+        let result = match numtheory::ncr(n, k) {
+            Ok(result) => result,
+            Err(e) => {
+                log_warn!("Failed to compute {} choose {}: {:?}", n, k, e);
+                return Err(e);
+            }
+        };
+        log_info!("{} choose {} is {} (command 'ncr')", n, k, result);
+        replace_with_integer(ctx, 2, result, "combinations")
+    }
+}
+
+pub struct Npr;
+
+impl<D: Panel> Command<D> for Npr {
+    fn names(&self) -> &'static [&'static str] { &["npr", "perm"] }
+    fn usage(&self) -> &'static str { "npr: Replace n and k (on top) with the number of permutations, the ways to pick k out of n in order" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        let (n, k) = peek_n_k(ctx)?;
+        let result = match numtheory::npr(n, k) {
+            Ok(result) => result,
+            Err(e) => {
+                log_warn!("Failed to compute the permutations of {} out of {}: {:?}", k, n, e);
+                return Err(e);
+            }
+        };
+        log_info!("{} out of {} can be picked in {} orders (command 'npr')", k, n, result);
+        replace_with_integer(ctx, 2, result, "permutations")
+    }
+}
+
+pub struct IsPrime;
+
+impl<D: Panel> Command<D> for IsPrime {
+    fn names(&self) -> &'static [&'static str] { &["isprime"] }
+    fn usage(&self) -> &'static str { "isprime: Replace the top element with 1 if its whole part is a prime, 0 if not" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        let [n] = peek_integers(ctx)?;
+        // The negative numbers aren't primes by definition
+        let prime = match n {
+            ..0 => false,
+            _ => match numtheory::is_prime(n.unsigned_abs()) {
+                Ok(prime) => prime,
+                Err(e) => {
+                    log_warn!("Failed to check whether {} is a prime (max {}): {:?}", n, numtheory::MAX_TRIAL_DIVISION, e);
+                    return Err(e);
+                }
+            },
+        };
+        log_info!("{} is a prime: {} (command 'isprime')", n, prime);
+        let msg: String<48> = heapless::format!("{} {} a prime\r\n", n, if prime { "is" } else { "is not" })?;
+        (ctx.print)(msg.as_bytes());
+        replace_with_integer(ctx, 1, u64::from(prime), "primality")
+    }
+}
+
+pub struct Factor;
+
+impl<D: Panel> Command<D> for Factor {
+    fn names(&self) -> &'static [&'static str] { &["factor"] }
+    fn usage(&self) -> &'static str { "factor: Replace the top element with the prime factors of its whole part, one per element, the greatest on top" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        let [n] = peek_integers(ctx)?;
+        let factors = match u64::try_from(n).map_err(|_| CE::BadInput).and_then(numtheory::factor) {
+            Ok(factors) => factors,
+            Err(e) => {
+                log_warn!("Failed to factor {} (from 2 to {}): {:?}", n, numtheory::MAX_TRIAL_DIVISION, e);
+                return Err(e);
+            }
+        };
+        let mut values: Vec<DecimalFixed, { numtheory::MAX_FACTORS }> = Vec::new();
+        let mut text: String<96> = String::new();
+        // Cut off, the factors are on the stack anyway
+        let _ = core::fmt::write(&mut TruncatingString(&mut text), format_args!("{} =", n));
+        for (i, &p) in factors.iter().enumerate() {
+            values.push(DecimalFixed::new(i64::try_from(p)?, None)?).map_err(|_| CE::Impossible)?; // Same capacity as `factors`
+            let _ = core::fmt::write(&mut TruncatingString(&mut text), format_args!("{} {}", if i == 0 { "" } else { " *" }, p));
+        }
+        log_info!("Factored {} into {} primes (command 'factor')", n, factors.len());
+
+        let top = ctx.stack.pop();
+        if let Err(e) = ctx.stack.push_slice(&values) {
+            log_warn!("Failed to push {} prime factors onto stack with {} elements: {:?}", values.len(), ctx.stack.len(), e);
+            if let Some(top) = top {
+                let _ = ctx.stack.push(top); // Can't fail, we've just popped it
+            }
+            return Err(e);
+        }
+        ctx.state.last_x = top;
+        (ctx.print)(text.as_bytes());
+        (ctx.print)(b"\r\n");
+        ctx.stack.draw(false)
+    }
+}
+
+pub struct Sto;
+
+impl<D: Panel> Command<D> for Sto {
+    fn names(&self) -> &'static [&'static str] { &["sto"] }
+    fn usage(&self) -> &'static str { "sto X: Store the top element into register X (A-Z)" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let name = args.next_str()?;
+        args.finish()?;
+        let Some(&val) = ctx.stack.peek() else {
+            log_warn!("Failed to store into register: stack is empty.");
+            return Err(CE::BadInput);
+        };
+        // Like on HP calculators, we only copy the value, it stays on the stack
+        ctx.state.registers.store(name, val)?;
+        log_info!("Stored {} into register {} (command 'sto')", val, name);
+        Ok(())
+    }
+}
+
+pub struct StoAdd;
+
+impl<D: Panel> Command<D> for StoAdd {
+    fn names(&self) -> &'static [&'static str] { &["sto+"] }
+    fn usage(&self) -> &'static str { "sto+ X: Add the top element to register X (empty counts as zero)" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let name = args.next_str()?;
+        args.finish()?;
+        let Some(&val) = ctx.stack.peek() else {
+            log_warn!("Failed to store into register: stack is empty.");
+            return Err(CE::BadInput);
+        };
+        ctx.state.registers.store_add(name, val)?;
+        log_info!("Added {} to register {} (command 'sto+')", val, name);
+        Ok(())
+    }
+}
+
+pub struct StoSub;
+
+impl<D: Panel> Command<D> for StoSub {
+    fn names(&self) -> &'static [&'static str] { &["sto-"] }
+    fn usage(&self) -> &'static str { "sto- X: Subtract the top element from register X (empty counts as zero)" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let name = args.next_str()?;
+        args.finish()?;
+        let Some(&val) = ctx.stack.peek() else {
+            log_warn!("Failed to store into register: stack is empty.");
+            return Err(CE::BadInput);
+        };
+        ctx.state.registers.store_sub(name, val)?;
+        log_info!("Subtracted {} from register {} (command 'sto-')", val, name);
+        Ok(())
+    }
+}
+
+pub struct Rcl;
+
+impl<D: Panel> Command<D> for Rcl {
+    fn names(&self) -> &'static [&'static str] { &["rcl"] }
+    fn usage(&self) -> &'static str { "rcl X: Push the value of register X" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let name = args.next_str()?;
+        args.finish()?;
+        let val = match ctx.state.registers.recall(name) {
+            Ok(val) => val,
+            Err(e) => {
+                log_warn!("Failed to recall register {}: invalid name or register is empty.", name);
+                return Err(e);
+            }
+        };
+        push_and_draw(ctx, val, "recalled value")
+    }
+}
+
+pub struct LastX;
+
+impl<D: Panel> Command<D> for LastX {
+    fn names(&self) -> &'static [&'static str] { &["lastx", "lx"] }
+    fn usage(&self) -> &'static str { "lastx: Push the top element from before the last arithmetic operation" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        let Some(val) = ctx.state.last_x else {
+            log_warn!("Failed to push last X: there was no arithmetic operation yet.");
+            return Err(CE::BadInput);
+        };
+        log_info!("Pushing last X {} (command 'lastx')", val);
+        push_and_draw(ctx, val, "last X")
+    }
+}
+
+pub struct Uptime;
+
+impl<D: Panel> Command<D> for Uptime {
+    fn names(&self) -> &'static [&'static str] { &["uptime"] }
+    fn usage(&self) -> &'static str { "uptime: Print the time since boot" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        // The same timer as the defmt timestamps, so that it's easy to match them against the logs
+        let uptime_us = get_timestamp_us();
+        log_info!("Uptime is {} us (command 'uptime')", uptime_us);
+
+        let secs = uptime_us / 1_000_000;
+        let msg: String<48> = heapless::format!(
+            "Uptime: {}:{:02}:{:02}.{:03}\r\n",
+            secs / 3600, secs / 60 % 60, secs % 60, uptime_us / 1000 % 1000
+        )?;
+        (ctx.print)(msg.as_bytes());
+        Ok(())
+    }
+}
+
+pub struct Stopwatch;
+
+impl<D: Panel> Command<D> for Stopwatch {
+    fn names(&self) -> &'static [&'static str] { &["stopwatch", "sw"] }
+    fn usage(&self) -> &'static str { "stopwatch start|lap|stop: Measure time, lap and stop push the elapsed seconds" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let action = args.next_str()?;
+        args.finish()?;
+        let now = get_timestamp_us();
+        let elapsed_us = match action {
+            "start" => {
+                ctx.state.stopwatch.start(now);
+                log_info!("Started the stopwatch (command 'stopwatch start')");
+                return Ok(());
+            },
+            "lap" => ctx.state.stopwatch.lap(now)?,
+            "stop" => ctx.state.stopwatch.stop(now)?,
+            other => {
+                log_warn!("Unknown stopwatch action: {:?}", other);
+                return Err(CE::BadInput);
+            }
+        };
+        log_info!("Stopwatch measured {} us (command 'stopwatch {}')", elapsed_us, action);
+
+        // Microseconds are exactly the 10^-6 exponent, then we convert to the default one so that it can be calculated with
+        let elapsed = DecimalFixed::new_prescaled(i64::try_from(elapsed_us)?, -6).with_exponent(None)?;
+        push_and_draw(ctx, elapsed, "elapsed time")
+    }
+}
+
+pub struct Vbat;
+
+impl<D: Panel> Command<D> for Vbat {
+    fn names(&self) -> &'static [&'static str] { &["vbat"] }
+    fn usage(&self) -> &'static str { "vbat: Push the supply voltage in volts" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        let voltage = ctx.vsys.measure()?;
+        log_info!("VSYS is {} V (command 'vbat')", voltage);
+        push_and_draw(ctx, voltage, "supply voltage")
+    }
+}
+
+pub struct ReadSensor;
+
+impl<D: Panel> Command<D> for ReadSensor {
+    fn names(&self) -> &'static [&'static str] { &["read"] }
+    fn usage(&self) -> &'static str { "read [SENSOR [QUANTITY]]: Push a reading of the sensor on the I²C bus, or list the sensors" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let Some(name) = args.next() else {
+            for sensor in sensors::registry() {
+                (ctx.print)(sensor.name().as_bytes());
+                (ctx.print)(b":");
+                for (quantity, unit) in sensor.quantities() {
+                    let entry: String<32> = heapless::format!(" {} ({})", quantity, unit)?;
+                    (ctx.print)(entry.as_bytes());
+                }
+                (ctx.print)(b"\r\n");
+            }
+            return Ok(());
+        };
+        let Some(sensor) = sensors::find(name) else {
+            log_warn!("There's no driver for a sensor {:?}, see 'read' for those there are", name);
+            return Err(CE::BadInput);
+        };
+        let quantity = match args.next() {
+            None => 0,
+            Some(quantity) => sensor.quantities().iter().position(|(known, _)| known.eq_ignore_ascii_case(quantity)).ok_or_else(|| {
+                log_warn!("The {} doesn't measure {:?}", sensor.name(), quantity);
+                CE::BadInput
+            })?,
+        };
+        args.finish()?;
+
+        let value = sensors::with_bus(|bus| sensor.read(bus, quantity))?;
+        let (quantity, unit) = sensor.quantities()[quantity];
+        log_info!("The {} reads {} {} {} (command 'read')", sensor.name(), quantity, value, unit);
+        push_and_draw(ctx, value, "sensor reading")
+    }
+}
+
+pub struct Time;
+
+impl<D: Panel> Command<D> for Time {
+    fn names(&self) -> &'static [&'static str] { &["time"] }
+    fn usage(&self) -> &'static str { "time: Print the time of day" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        let now = ctx.clock.now()?;
+        log_info!("Time is {}:{:02}:{:02} (command 'time')", now.hour, now.minute, now.second);
+
+        let msg: String<32> = heapless::format!("Time: {:02}:{:02}:{:02}\r\n", now.hour, now.minute, now.second)?;
+        (ctx.print)(msg.as_bytes());
+        if !ctx.clock.is_set() {
+            (ctx.print)(b"The clock wasn't set since boot, see 'settime'\r\n");
+        }
+        Ok(())
+    }
+}
+
+pub struct Date;
+
+impl<D: Panel> Command<D> for Date {
+    fn names(&self) -> &'static [&'static str] { &["date"] }
+    fn usage(&self) -> &'static str { "date: Print the date and the day of the week" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        let now = ctx.clock.now()?;
+        log_info!("Date is {}-{:02}-{:02} (command 'date')", now.year, now.month, now.day);
+
+        let msg: String<32> = heapless::format!(
+            "Date: {}-{:02}-{:02} {}\r\n",
+            now.year, now.month, now.day, clock::day_name(now.day_of_week)
+        )?;
+        (ctx.print)(msg.as_bytes());
+        if !ctx.clock.is_set() {
+            (ctx.print)(b"The clock wasn't set since boot, see 'setdate'\r\n");
+        }
+        Ok(())
+    }
+}
+
+pub struct SetTime;
+
+impl<D: Panel> Command<D> for SetTime {
+    fn names(&self) -> &'static [&'static str] { &["settime"] }
+    fn usage(&self) -> &'static str { "settime HH:MM:SS: Set the time of day, shown in the status bar from then on" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let (hour, minute, second) = clock::parse_triple(args.next_str()?, ':')?;
+        args.finish()?;
+        log_info!("Setting the time to {}:{:02}:{:02} (command 'settime')", hour, minute, second);
+
+        ctx.clock.set_time(hour, minute, second)?;
+        ctx.stack.set_clock(ctx.clock.status_time());
+        ctx.stack.draw(false)
+    }
+}
+
+pub struct SetDate;
+
+impl<D: Panel> Command<D> for SetDate {
+    fn names(&self) -> &'static [&'static str] { &["setdate"] }
+    fn usage(&self) -> &'static str { "setdate YYYY-MM-DD: Set the date" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let (year, month, day) = clock::parse_triple(args.next_str()?, '-')?;
+        args.finish()?;
+        log_info!("Setting the date to {}-{:02}-{:02} (command 'setdate')", year, month, day);
+
+        ctx.clock.set_date(year, month, day)?;
+        ctx.stack.set_clock(ctx.clock.status_time());
+        ctx.stack.draw(false)
+    }
+}
+
+pub struct Now;
+
+impl<D: Panel> Command<D> for Now {
+    fn names(&self) -> &'static [&'static str] { &["now"] }
+    fn usage(&self) -> &'static str { "now: Push the seconds since 1970-01-01 in the local time" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        let timestamp = ctx.clock.timestamp()?;
+        log_info!("Timestamp is {} (command 'now')", timestamp);
+        push_and_draw(ctx, DecimalFixed::new(timestamp, None)?, "timestamp")
+    }
+}
+
+pub struct Macro;
+
+impl<D: Panel> Command<D> for Macro {
+    fn names(&self) -> &'static [&'static str] { &["macro"] }
+    fn usage(&self) -> &'static str { "macro record|stop|play [N]: Record keys and commands into a macro, play it N times (Ctrl-P once)" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        match args.next_str()? {
+            "record" | "rec" => {
+                args.finish()?;
+                ctx.state.macros.start_recording()?;
+                log_info!("Recording a macro (command 'macro record')");
+            },
+            "stop" => {
+                args.finish()?;
+                let steps = ctx.state.macros.stop_recording()?;
+                log_info!("Recorded a macro with {} steps (command 'macro stop')", steps);
+            },
+            "play" => {
+                let times = if args.is_empty() { 1 } else { args.next_int::<usize>()? };
+                args.finish()?;
+                // The steps get played by the main loop once we're out of command mode
+                ctx.state.macros.play(times)?;
+                log_info!("Playing the macro {} times (command 'macro play')", times);
+            },
+            other => {
+                log_warn!("Unknown macro action: {:?}", other);
+                return Err(CE::BadInput);
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct Script;
+
+impl Script {
+    /// Reads a line of the script, without the line ending. Returns `None` at the end of the script.
+    /// A line too long gets read whole anyway, so that its rest doesn't count as the next line, and `CapacityError` is returned.
+    fn read_line<D, const N: usize>(ctx: &Context<'_, '_, D>, line: &mut String<N>) -> Result<Option<()>, CustomError>
+    where
+        D: Panel,
+    {
+        line.clear();
+        let mut too_long = false;
+        loop {
+            match (ctx.read_byte)()? {
+                b'\r' | b'\n' => break,
+                END_OF_SCRIPT if line.is_empty() => return Ok(None),
+                CANCEL_SCRIPT => return Err(CE::Cancelled),
+                // The commands are all lowercase, same as in command mode, and they're ASCII anyway
+                byte if byte.is_ascii() && !byte.is_ascii_control() => {
+                    too_long |= line.push(byte.to_ascii_lowercase() as char).is_err();
+                },
+                _ => {}, // Not worth failing the whole line over
+            }
+        }
+
+        if too_long {
+            return Err(CE::CapacityError);
+        }
+        Ok(Some(()))
+    }
+}
+
+impl<D: Panel> Command<D> for Script {
+    fn names(&self) -> &'static [&'static str] { &["script"] }
+    fn usage(&self) -> &'static str { "script [abort]: Run commands read from UART line by line, until Ctrl-D or end (abort: stop on error)" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let abort_on_error = match args.next() {
+            None => false,
+            Some("abort") => true,
+            Some(other) => {
+                log_warn!("Unknown script option: {:?}", other);
+                return Err(CE::BadInput);
+            }
+        };
+        args.finish()?;
+
+        log_info!("Running a script from UART (command 'script')");
+        (ctx.print)(b"Ready for script, end with Ctrl-D\r\n");
+
+        let mut line: String<TEXT_BUFFER_SIZE> = String::new();
+        let mut line_number: usize = 0;
+        let mut failed: usize = 0;
+        loop {
+            line_number += 1;
+            let result = match Self::read_line(ctx, &mut line) {
+                Ok(None) => break,
+                Ok(Some(())) => {
+                    let command = line.trim();
+                    if command.is_empty() || command.starts_with('#') {
+                        continue;
+                    }
+                    if command == "end" {
+                        break;
+                    }
+                    if command == "script" || command.starts_with("script ") {
+                        log_warn!("Scripts can't run other scripts");
+                        Err(CE::BadInput.into())
+                    } else {
+                        execute_with_context(command, ctx)
+                    }
+                },
+                Err(CE::UartReadError(e)) => return Err(CE::UartReadError(e)), // We'd only get garbage from now on
+                Err(CE::Cancelled) => {
+                    log_info!("Script cancelled at line {}", line_number);
+                    (ctx.print)(b"Cancelled\r\n");
+                    return Err(CE::Cancelled);
+                },
+                Err(e) => Err(e.into()),
+            };
+
+            // The messages are short, they always fit
+            let msg: String<96> = match &result {
+                Ok(()) => heapless::format!("ok {}\r\n", line_number),
+                Err(e) => {
+                    log_warn!("Line {} of the script failed: {:?}", line_number, e);
+                    failed += 1;
+                    heapless::format!("err {}: {}\r\n", line_number, e)
+                },
+            }.map_err(|_| CE::Impossible)?;
+            (ctx.print)(msg.as_bytes());
+
+            if let Err(e) = result && abort_on_error {
+                log_info!("Aborting script at line {}", line_number);
+                (ctx.print)(b"Aborted\r\n");
+                return Err(e.error);
+            }
+        }
+
+        log_info!("Script finished, {} lines failed", failed);
+        let msg: String<64> = heapless::format!("Script done, {} failed\r\n", failed)
+            .map_err(|_| CE::Impossible)?;
+        (ctx.print)(msg.as_bytes());
+        Ok(())
+    }
+}
+
+pub struct Scpi;
+
+/// Takes the output of the commands run by `Scpi`, the host expects nothing but the responses to its queries
+fn discard_output(_bytes: &[u8]) {}
+
+impl Scpi {
+    /// Runs the command line like `execute()`, but with its output discarded
+    fn execute_quietly<D: Panel>(ctx: &mut Context<'_, '_, D>, command: &str) -> Result<(), CustomError> {
+        let mut quiet = Context {
+            print: &discard_output,
+            print_binary: &discard_output,
+            read_byte: ctx.read_byte,
+            poll_byte: ctx.poll_byte,
+            uart_clock_hz: ctx.uart_clock_hz,
+            disp_refcell: ctx.disp_refcell,
+            stack: &mut *ctx.stack,
+            state: &mut *ctx.state,
+            vsys: &mut *ctx.vsys,
+            clock: &mut *ctx.clock,
+        };
+        execute(command, &mut quiet)
+    }
+
+    /// Runs a single command of the line, appending what a query responds to `response`.
+    /// Returns whether it was `SYSTem:LOCal`, i.e. whether to go back to local mode after the line.
+    fn run_one<D: Panel>(
+        ctx: &mut Context<'_, '_, D>,
+        command: &ScpiCommand<'_>,
+        errors: &mut ErrorQueue,
+        response: &mut String<SCPI_RESPONSE_SIZE>,
+    ) -> Result<bool, ScpiError> {
+        log_debug!("SCPI command {:?} (query: {})", command.header, command.query);
+        let mut respond = |args: core::fmt::Arguments<'_>| {
+            if !response.is_empty() {
+                response.push(';').map_err(|_| ScpiError::OutOfMemory)?;
+            }
+            core::fmt::Write::write_fmt(response, args).map_err(|_| ScpiError::OutOfMemory)
+        };
+
+        match (command.header, command.query) {
+            (Header::Identify, true) => {
+                command.no_params()?;
+                respond(format_args!("{},{}", SCPI_IDENTITY, env!("CARGO_PKG_VERSION")))?;
+            },
+            (Header::Reset, false) => {
+                command.no_params()?;
+                ctx.stack.clear();
+            },
+            (Header::ClearStatus, false) => {
+                command.no_params()?;
+                errors.clear();
+            },
+            (Header::OperationComplete, query) => {
+                command.no_params()?;
+                if query {
+                    respond(format_args!("1"))?; // We do everything right away
+                }
+            },
+            (Header::Push, false) => {
+                let value: DecimalFixed = command.param()?.parse()?;
+                ctx.stack.push(value).map_err(|(e, _)| e)?;
+            },
+            (Header::Pop, query) => {
+                command.no_params()?;
+                let value = ctx.stack.pop().ok_or(ScpiError::ExecutionError)?;
+                if query {
+                    respond(format_args!("{}", value))?;
+                }
+            },
+            (Header::Top, true) => {
+                command.no_params()?;
+                respond(format_args!("{}", ctx.stack.peek().ok_or(ScpiError::ExecutionError)?))?;
+            },
+            (Header::Peek, true) => {
+                let depth: usize = command.param()?.parse().map_err(|_| ScpiError::IllegalParameterValue)?;
+                respond(format_args!("{}", ctx.stack.peek_nth(depth).ok_or(ScpiError::DataOutOfRange)?))?;
+            },
+            (Header::Depth, true) => {
+                command.no_params()?;
+                respond(format_args!("{}", ctx.stack.len()))?;
+            },
+            (Header::Clear, false) => {
+                command.no_params()?;
+                ctx.stack.clear();
+            },
+            (Header::Rpn, false) => {
+                paste::apply(command.param()?, ctx.stack, &mut ctx.state.last_x).map_err(|(_, e)| e)?;
+            },
+            (Header::Brightness, false) => {
+                let line: String<TEXT_BUFFER_SIZE> = heapless::format!("brightness {}", command.param()?)
+                    .map_err(|_| ScpiError::IllegalParameterValue)?;
+                Self::execute_quietly(ctx, &line)?;
+            },
+            (Header::Contrast, false) => {
+                let line: String<TEXT_BUFFER_SIZE> = heapless::format!("contrast {}", command.param()?)
+                    .map_err(|_| ScpiError::IllegalParameterValue)?;
+                Self::execute_quietly(ctx, &line)?;
+            },
+            (Header::Contrast, true) => {
+                command.no_params()?;
+                respond(format_args!("{}", ctx.state.settings.contrast))?;
+            },
+            (Header::Error, true) => {
+                command.no_params()?;
+                match errors.pop() {
+                    Some(e) => respond(format_args!("{},\"{}\"", e.code(), e.message()))?,
+                    None => respond(format_args!("0,\"No error\""))?,
+                }
+            },
+            (Header::Version, true) => {
+                command.no_params()?;
+                respond(format_args!("{}", SCPI_VERSION))?;
+            },
+            (Header::Command, false) => {
+                let line = command.param()?.trim();
+                if matches!(line.split(' ').next(), Some("scpi" | "script")) {
+                    log_warn!("SCPI can't run {:?}, it reads the UART by itself", line);
+                    return Err(ScpiError::ExecutionError);
+                }
+                Self::execute_quietly(ctx, line)?;
+            },
+            (Header::Local, false) => {
+                command.no_params()?;
+                return Ok(true);
+            },
+            _ => return Err(ScpiError::UndefinedHeader), // A query of a command without one, or the other way round
+        }
+        Ok(false)
+    }
+}
+
+impl<D: Panel> Command<D> for Scpi {
+    fn names(&self) -> &'static [&'static str] { &["scpi"] }
+    fn usage(&self) -> &'static str { "scpi: Take SCPI commands (e.g. :STACK:PUSH 3.14, :SYST:ERR?) from UART, until :SYST:LOC or Ctrl-D" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, args: Args<'_>) -> Result<(), CustomError> {
+        args.finish()?;
+        log_info!("Entering the SCPI remote mode (command 'scpi')");
+        (ctx.print)(b"Remote mode, leave with :SYST:LOC or Ctrl-D\r\n");
+
+        let mut errors = ErrorQueue::new();
+        let mut line: String<SCPI_LINE_SIZE> = String::new();
+        loop {
+            match Script::read_line(ctx, &mut line) {
+                Ok(None) => break,
+                Ok(Some(())) => {},
+                Err(CE::CapacityError) => {
+                    log_warn!("SCPI line too long, max {} bytes", SCPI_LINE_SIZE);
+                    errors.push(ScpiError::OutOfMemory);
+                    continue;
+                },
+                Err(e) => return Err(e), // Cancelled, or the UART failed
+            }
+
+            let mut response: String<SCPI_RESPONSE_SIZE> = String::new();
+            let mut local = false;
+            for command in scpi::parse(&line) {
+                match command.and_then(|command| Self::run_one(ctx, &command, &mut errors, &mut response)) {
+                    Ok(is_local) => local |= is_local,
+                    Err(e) => {
+                        log_warn!("SCPI command failed: {} {}", e.code(), e.message());
+                        errors.push(e);
+                    },
+                }
+            }
+            if !response.is_empty() {
+                (ctx.print)(response.as_bytes());
+                (ctx.print)(b"\n"); // SCPI ends the responses with LF alone, whatever `crlf` says
+            }
+            if ctx.stack.is_dirty() {
+                ctx.stack.draw(true)?;
+            }
+            if local {
+                break;
+            }
+        }
+
+        log_info!("Leaving the SCPI remote mode");
+        (ctx.print)(b"Local mode\r\n");
+        Ok(())
+    }
+}
+
+/// The holding registers of the `modbus` command, see `modbus::Register`
+struct ModbusMap<'m, 'c, 'a, D: Panel> {
+    ctx: &'m mut Context<'c, 'a, D>,
+    /// Decimal places of the scaled integers
+    scale: u8,
+    /// The master wrote 3 into the control register, we're to go back to command mode
+    done: bool,
+}
+
+impl<D: Panel> ModbusMap<'_, '_, '_, D> {
+    /// The register of the index scaled, `NOT_A_NUMBER` if it's empty
+    fn memory(&self, index: usize) -> i32 {
+        let mut name = [0_u8; 4];
+        let name = RegisterFile::name_of(index).encode_utf8(&mut name);
+        self.ctx.state.registers.recall(name).map_or(NOT_A_NUMBER, |value| modbus::to_scaled(value, self.scale))
+    }
+
+    fn set_memory(&mut self, index: usize, raw: i32) -> Result<(), CustomError> {
+        let mut name = [0_u8; 4];
+        let name = RegisterFile::name_of(index).encode_utf8(&mut name);
+        match raw {
+            NOT_A_NUMBER => self.ctx.state.registers.clear_register(name),
+            raw => self.ctx.state.registers.store(name, modbus::from_scaled(raw, self.scale)?),
+        }
+    }
+}
+
+impl<D: Panel> RegisterMap for ModbusMap<'_, '_, '_, D> {
+    fn read(&mut self, address: u16) -> Result<u16, Exception> {
+        Ok(match Register::at(address).ok_or(Exception::IllegalDataAddress)? {
+            Register::Depth => u16::try_from(self.ctx.stack.len()).unwrap_or(u16::MAX),
+            Register::Scale => u16::from(self.scale),
+            Register::Control | Register::Push { .. } => 0,
+            Register::Level { n, high } => {
+                let raw = self.ctx.stack.peek_nth(n).map_or(NOT_A_NUMBER, |&value| modbus::to_scaled(value, self.scale));
+                modbus::word(raw, high)
+            },
+            Register::Memory { index, high } => modbus::word(self.memory(index), high),
+        })
+    }
+
+    fn write(&mut self, address: u16, values: &[u16]) -> Result<(), Exception> {
+        let mut i = 0;
+        while i < values.len() {
+            let register = address.checked_add(i as u16).and_then(Register::at).ok_or(Exception::IllegalDataAddress)?;
+            // The low word of the same value, if it's written along with the high one
+            let low = values.get(i + 1).copied();
+            match (register, low) {
+                (Register::Scale, _) => {
+                    self.scale = u8::try_from(values[i]).ok()
+                        .filter(|&scale| scale <= MAX_SCALE)
+                        .ok_or(Exception::IllegalDataValue)?;
+                },
+                (Register::Control, _) => match values[i] {
+                    1 => { self.ctx.stack.pop().ok_or(Exception::IllegalDataValue)?; },
+                    2 => self.ctx.stack.clear(),
+                    3 => self.done = true,
+                    _ => return Err(Exception::IllegalDataValue),
+                },
+                (Register::Push { high: true }, Some(low)) => {
+                    let raw = modbus::with_word(modbus::with_word(0, values[i], true), low, false);
+                    self.ctx.stack.push(modbus::from_scaled(raw, self.scale)?).map_err(|(e, _)| e)?;
+                    i += 1;
+                },
+                (Register::Push { .. }, _) => return Err(Exception::IllegalDataValue), // Half a value isn't worth pushing
+                (Register::Memory { index, high }, low) => {
+                    // Written by halves, the other one stays as it was, or zero if the register was empty
+                    let current = match self.memory(index) {
+                        NOT_A_NUMBER => 0,
+                        current => current,
+                    };
+                    let mut raw = modbus::with_word(current, values[i], high);
+                    if high && let Some(low) = low {
+                        raw = modbus::with_word(raw, low, false);
+                        i += 1;
+                    }
+                    self.set_memory(index, raw)?;
+                },
+                (Register::Depth | Register::Level { .. }, _) => return Err(Exception::IllegalDataAddress), // Read-only
+            }
+            i += 1;
+        }
+        Ok(())
+    }
+}
+
+pub struct Modbus;
+
+impl<D: Panel> Command<D> for Modbus {
+    fn names(&self) -> &'static [&'static str] { &["modbus"] }
+    fn usage(&self) -> &'static str { "modbus UNIT [SCALE]: Serve the stack and registers as Modbus RTU slave UNIT, until a lone Ctrl-C" }
+    fn args(&self) -> ArgSpec { ArgSpec::Required }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let unit = args.next_int::<u8>()?;
+        let scale = match args.is_empty() {
+            true => MODBUS_DEFAULT_SCALE,
+            false => args.next_int::<u8>()?,
+        };
+        args.finish()?;
+        if !(1..=MODBUS_MAX_UNIT).contains(&unit) || scale > MAX_SCALE {
+            log_warn!("Modbus unit out of range (1-{}) or too many decimal places (max {}): {}, {}", MODBUS_MAX_UNIT, MAX_SCALE, unit, scale);
+            return Err(CE::BadInput);
+        }
+        log_info!("Serving Modbus RTU as unit {} with {} decimal places (command 'modbus')", unit, scale);
+        (ctx.print)(b"Modbus RTU mode, a lone Ctrl-C leaves\r\n");
+
+        let silence_us = modbus::silence_us(ctx.state.settings.baud);
+        let mut frame: Vec<u8, { modbus::MAX_FRAME }> = Vec::new();
+        let mut map = ModbusMap { ctx, scale, done: false };
+        while !map.done {
+            // A frame is everything until the line goes silent
+            frame.clear();
+            let _ = frame.push((map.ctx.read_byte)()?); // Can't fail, it's empty
+            let mut overflowed = false;
+            let mut last_byte_at = get_timestamp_us();
+            while get_timestamp_us() - last_byte_at < silence_us {
+                if let Some(byte) = (map.ctx.poll_byte)() {
+                    overflowed |= frame.push(byte).is_err();
+                    last_byte_at = get_timestamp_us();
+                }
+            }
+
+            if frame == [CANCEL_SCRIPT] { // Too short to be a frame
+                log_info!("Leaving Modbus mode on Ctrl-C");
+                break;
+            }
+            if overflowed {
+                log_warn!("Modbus frame too long, max {} bytes", modbus::MAX_FRAME);
+                continue;
+            }
+            match modbus::respond(&frame, unit, &mut map) {
+                Some(reply) => (map.ctx.print_binary)(&reply),
+                None => log_debug!("Nothing to reply to the Modbus frame of {} bytes", frame.len()),
+            }
+            if map.ctx.stack.is_dirty() {
+                map.ctx.stack.draw(true)?;
+            }
+        }
+
+        log_info!("Left Modbus mode");
+        (ctx.print)(b"Modbus mode done\r\n");
+        Ok(())
+    }
+}
+
+pub struct ClearRegs;
+
+impl<D: Panel> Command<D> for ClearRegs {
+    fn names(&self) -> &'static [&'static str] { &["clregs"] }
+    fn usage(&self) -> &'static str { "clregs [X]: Empty all registers, or only register X" }
+    fn args(&self) -> ArgSpec { ArgSpec::Optional }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, mut args: Args<'_>) -> Result<(), CustomError> {
+        let name = args.next();
+        args.finish()?;
+        match name {
+            None => {
+                log_info!("Clearing all registers (command 'clregs')");
+                ctx.state.registers.clear();
+            },
+            Some(name) => {
+                log_info!("Clearing register {} (command 'clregs')", name);
+                ctx.state.registers.clear_register(name)?;
+            },
+        }
+        Ok(())
+    }
+}
+
+pub struct Regs;
+
+impl<D: Panel> Command<D> for Regs {
+    fn names(&self) -> &'static [&'static str] { &["regs"] }
+    fn usage(&self) -> &'static str { "regs: List the registers in use, a page at a time on the display (Ctrl-C or Esc quits)" }
+
+    fn run(&self, ctx: &mut Context<'_, '_, D>, _args: Args<'_>) -> Result<(), CustomError> {
+        // Can't fail, there's only so many registers
+        let regs: Vec<(char, DecimalFixed), REGISTER_COUNT> = ctx.state.registers.iter().collect();
+        log_info!("Listing {} registers in use (command 'regs')", regs.len());
+
+        if regs.is_empty() {
+            (ctx.print)(b"No registers in use\r\n");
+            return Ok(());
+        }
+        let mut lines: Vec<String<TEXT_BUFFER_SIZE>, REGISTER_COUNT> = Vec::new();
+        for (name, val) in &regs {
+            let line: String<TEXT_BUFFER_SIZE> = heapless::format!("{}: {}", name, val)?;
+            (ctx.print)(line.as_bytes());
+            (ctx.print)(b"\r\n");
+            lines.push(line).map_err(|_| CE::Impossible)?; // Same capacity as `regs`
+        }
+
+        let lines_per_page = (ctx.disp_refcell.borrow().bounding_box().size.height / PAGE_LINE_HEIGHT) as usize;
+        let page_count = lines.len().div_ceil(lines_per_page);
+        for (page_index, page) in lines.chunks(lines_per_page).enumerate() {
+            draw_page(ctx, page)?;
+
+            // Wait for a key before showing the next page, or before going back to the stack after the last one
+            log_trace!("Showing page {} of {} of registers", page_index + 1, page_count);
+            if let 0x03 | 0x1B = (ctx.read_byte)()? { // Ctrl-C or Esc
+                break;
+            }
+        }
+
+        ctx.stack.invalidate();
+        ctx.stack.draw(true)
+    }
+}
@@ -3,7 +3,7 @@ use defmt::Format as DefmtFormat;
 
 use core::num::{ParseIntError, IntErrorKind, TryFromIntError};
 use display_interface::DisplayError;
-use heapless::CapacityError;
+use heapless::{CapacityError, String};
 use rp2040_hal::uart::ReadErrorType;
 
 // Type aliases to reduce verbosity in the From impls
@@ -15,6 +15,13 @@ type IEKC = IntErrorKindClone;
 type DiE = DisplayError;
 type DiEC = DisplayErrorClone;
 
+/// Longest offending value an `ErrorContext` keeps, longer ones get cut off
+pub const CONTEXT_VALUE_SIZE: usize = 16;
+/// How many characters of the toast's font fit onto the display, see `WithContext::toast_message()`
+const TOAST_MESSAGE_CHARS: usize = 21;
+/// Size of the buffer the toast messages get formatted into, twice the chars for the non-ASCII ones
+const TOAST_MESSAGE_SIZE: usize = 2 * TOAST_MESSAGE_CHARS;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, DefmtFormat, Default)]
 #[non_exhaustive] // So that we can add more error types later without breaking compatibility
 pub enum CustomError {
@@ -22,6 +29,8 @@ pub enum CustomError {
     ParseIntError(IntErrorKindClone),
     FormatError,
     BadInput,
+    /// The result would be a complex number, which we can't represent, e.g. the roots of `x² + 1`
+    ComplexResult,
 
     DisplayError(DisplayErrorClone),
     CapacityError,
@@ -74,6 +83,142 @@ impl fmt::Display for CustomError {
 
 impl core::error::Error for CustomError {}
 
+impl CustomError {
+    /// A short human-readable description that fits onto the display, e.g. for the error toasts.
+    /// Unlike `Display`, it doesn't go into the details, the user can't do much about those anyway.
+    pub fn short_message(&self) -> &'static str {
+        match self {
+            CE::MathOverflow => "Number too big",
+            CE::ParseIntError(_) => "Not a number",
+            CE::FormatError => "Can't format number",
+            CE::BadInput => "Bad input",
+            CE::ComplexResult => "Complex result",
+            CE::DisplayError(_) => "Display error",
+            CE::CapacityError => "Out of space",
+            CE::UartReadError(_) => "UART error",
+            CE::Unimplemented => "Not implemented",
+            CE::Impossible => "Internal error",
+            CE::Cancelled => "Cancelled",
+            CE::Other => "Error",
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// What failed and on what, so that the user gets told more than just the kind of the error
+#[derive(Debug, Clone, PartialEq, Eq, DefmtFormat)]
+pub struct ErrorContext {
+    /// The command or operation that failed, e.g. `"add"` or `"pick"`
+    pub operation: &'static str,
+    /// The offending value (e.g. what was typed in), cut off if it's too long; empty if there's none
+    pub value: String<CONTEXT_VALUE_SIZE>,
+}
+
+/// A `CustomError` with an optional `ErrorContext`, see `ResultExt` for adding one.
+///
+/// Kept apart from `CustomError` itself, so that the latter stays `Copy` and easy to match and compare.
+/// Converts from `CustomError` (without a context), so `?` works on both.
+#[derive(Debug, Clone, PartialEq, Eq, DefmtFormat)]
+pub struct WithContext {
+    pub error: CustomError,
+    pub context: Option<ErrorContext>,
+}
+
+impl WithContext {
+    /// Adds the context, unless there already is one; the innermost context is the most specific.
+    pub fn with_context(mut self, operation: &'static str, value: impl fmt::Display) -> Self {
+        if self.context.is_none() {
+            let mut context = ErrorContext { operation, value: String::new() };
+            // Cuts off whatever doesn't fit, a part of the value is better than none
+            let _ = fmt::write(&mut TruncatingString(&mut context.value), format_args!("{}", value));
+            self.context = Some(context);
+        }
+        self
+    }
+
+    /// Like `CustomError::short_message()`, but with as much of the context as fits onto the display,
+    /// e.g. `pick 9: Bad input`, or `add: Number too big` if the value doesn't fit.
+    pub fn toast_message(&self) -> String<TOAST_MESSAGE_SIZE> {
+        let short_message = self.error.short_message();
+        let fits = |message: &String<TOAST_MESSAGE_SIZE>| message.chars().count() <= TOAST_MESSAGE_CHARS;
+
+        if let Some(context) = &self.context {
+            if !context.value.is_empty()
+                && let Ok(message) = heapless::format!("{} {}: {}", context.operation, context.value, short_message)
+                && fits(&message)
+            {
+                return message;
+            }
+            if let Ok(message) = heapless::format!("{}: {}", context.operation, short_message)
+                && fits(&message)
+            {
+                return message;
+            }
+        }
+        // Can't fail, the short messages fit the display by themselves
+        String::try_from(short_message).unwrap_or_default()
+    }
+}
+
+impl fmt::Display for WithContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error)?;
+        if let Some(context) = &self.context {
+            write!(f, " in {}", context.operation)?;
+            if !context.value.is_empty() {
+                write!(f, " ({:?})", context.value.as_str())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl core::error::Error for WithContext {}
+
+impl From<CustomError> for WithContext {
+    fn from(error: CustomError) -> Self {
+        WithContext { error, context: None }
+    }
+}
+
+/// A `fmt::Write` adapter that cuts off whatever doesn't fit into the String, instead of failing
+pub struct TruncatingString<'b, const N: usize>(pub &'b mut String<N>);
+
+impl<const N: usize> fmt::Write for TruncatingString<'_, N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.0.push(c).is_err() {
+                break;
+            }
+        }
+        Ok(()) // Never fails, that's the whole point
+    }
+}
+
+/// Adds an `ErrorContext` to the error of a `Result`, like `anyhow`'s `Context`, e.g. `(a + b).context("add")?`
+pub trait ResultExt<T> {
+    /// Adds the operation that failed
+    fn context(self, operation: &'static str) -> Result<T, WithContext>;
+    /// Adds the operation that failed, and the value it failed on
+    fn context_value(self, operation: &'static str, value: impl fmt::Display) -> Result<T, WithContext>;
+}
+
+impl<T, E> ResultExt<T> for Result<T, E>
+where
+    E: Into<WithContext>,
+{
+    fn context(self, operation: &'static str) -> Result<T, WithContext> {
+        self.context_value(operation, "")
+    }
+
+    fn context_value(self, operation: &'static str, value: impl fmt::Display) -> Result<T, WithContext> {
+        self.map_err(|e| e.into().with_context(operation, value))
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
 // Happens when you try to convert bigger int into smaller and it's outside the range.
 // We map it to MathOverflow for simplicity, since it's a kind of overflow.
 impl From<TryFromIntError> for CustomError {
@@ -130,8 +275,111 @@ impl From<ReadErrorType> for CustomError {
     }
 }
 
+// Displays that can't fail (e.g. `NullDisplay`) use this as their error type
+impl From<core::convert::Infallible> for CustomError {
+    fn from(err: core::convert::Infallible) -> Self {
+        match err {}
+    }
+}
+
 impl From<()> for CustomError {
     fn from(_: ()) -> Self {
         CE::Other
     }
-}
\ No newline at end of file
+}
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::string::ToString;
+    use display_interface::DisplayError;
+    use super::{CustomError, CE, IntErrorKindClone, DisplayErrorClone, ResultExt, WithContext};
+
+    /// Every variant we can construct, for the tests that go over all of them
+    const ALL: [CustomError; 12] = [
+        CE::MathOverflow,
+        CE::ParseIntError(IntErrorKindClone::Empty),
+        CE::FormatError,
+        CE::BadInput,
+        CE::ComplexResult,
+        CE::DisplayError(DisplayErrorClone::BusWriteError),
+        CE::CapacityError,
+        CE::UartReadError(rp2040_hal::uart::ReadErrorType::Overrun),
+        CE::Unimplemented,
+        CE::Impossible,
+        CE::Cancelled,
+        CE::Other,
+    ];
+
+    #[test]
+    fn converts_parse_int_errors() {
+        assert_eq!(CE::from("".parse::<i64>().unwrap_err()), CE::ParseIntError(IntErrorKindClone::Empty));
+        assert_eq!(CE::from("1a".parse::<i64>().unwrap_err()), CE::ParseIntError(IntErrorKindClone::InvalidDigit));
+        assert_eq!(CE::from("99999999999999999999".parse::<i64>().unwrap_err()), CE::ParseIntError(IntErrorKindClone::PosOverflow));
+        assert_eq!(CE::from("-99999999999999999999".parse::<i64>().unwrap_err()), CE::ParseIntError(IntErrorKindClone::NegOverflow));
+        assert_eq!(CE::from("0".parse::<core::num::NonZeroU8>().unwrap_err()), CE::ParseIntError(IntErrorKindClone::Zero));
+    }
+
+    #[test]
+    fn converts_other_errors() {
+        assert_eq!(CE::from(u8::try_from(256_u16).unwrap_err()), CE::MathOverflow);
+        assert_eq!(CE::from(core::fmt::Error), CE::FormatError);
+        assert_eq!(CE::from(heapless::String::<0>::new().push('x').unwrap_err()), CE::CapacityError);
+        assert_eq!(CE::from(DisplayError::OutOfBoundsError), CE::DisplayError(DisplayErrorClone::OutOfBoundsError));
+        assert_eq!(CE::from(()), CE::Other);
+        assert_eq!(CustomError::default(), CE::Other);
+    }
+
+    #[test]
+    fn display_is_the_variant_name() {
+        assert_eq!(CE::MathOverflow.to_string(), "MathOverflow");
+        assert_eq!(CE::ParseIntError(IntErrorKindClone::Empty).to_string(), "ParseIntError(Empty)");
+    }
+
+    #[test]
+    fn short_messages_fit_the_display() {
+        // 21 characters of the 6 px wide font fit onto the 128 px wide display
+        for err in ALL {
+            let message = err.short_message();
+            assert!(!message.is_empty(), "{:?} has no message", err);
+            assert!(message.len() <= 21, "The message of {:?} is too long: {}", err, message);
+        }
+    }
+
+    #[test]
+    fn context_is_added_once() {
+        let result: Result<(), CustomError> = Err(CE::BadInput);
+        let e = result.context_value("pick", 9).context("command").unwrap_err();
+        assert_eq!(e.error, CE::BadInput);
+        let context = e.context.as_ref().unwrap();
+        assert_eq!((context.operation, context.value.as_str()), ("pick", "9"));
+        assert_eq!(e.to_string(), "BadInput in pick (\"9\")");
+
+        assert_eq!(WithContext::from(CE::Other).to_string(), "Other");
+        assert_eq!(Err::<(), _>(CE::MathOverflow).context("add").unwrap_err().to_string(), "MathOverflow in add");
+    }
+
+    #[test]
+    fn long_values_get_cut_off() {
+        let e = WithContext::from(CE::BadInput).with_context("parse", "12345678901234567890");
+        assert_eq!(e.context.unwrap().value.as_str(), "1234567890123456");
+    }
+
+    #[test]
+    fn toast_messages_fit_the_display() {
+        let e = WithContext::from(CE::BadInput).with_context("pick", 9);
+        assert_eq!(e.toast_message().as_str(), "pick 9: Bad input");
+        // The value doesn't fit, so it's left out
+        let e = WithContext::from(CE::MathOverflow).with_context("parse", "1234567890123");
+        assert_eq!(e.toast_message().as_str(), "parse: Number too big");
+        // Neither does the operation
+        let e = WithContext::from(CE::ParseIntError(IntErrorKindClone::Empty)).with_context("stopwatch", "x");
+        assert_eq!(e.toast_message().as_str(), "Not a number");
+        assert_eq!(WithContext::from(CE::Other).toast_message().as_str(), "Error");
+
+        for err in ALL {
+            let message = WithContext::from(err).with_context("sto+", "ěščřžýáíéúů").toast_message();
+            assert!(message.chars().count() <= 21, "The message of {:?} is too long: {}", err, message);
+        }
+    }
+}
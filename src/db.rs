@@ -0,0 +1,85 @@
+use crate::decfix::DecimalFixed;
+use crate::wide::{to_wide, from_wide, mul_scaled, ln, exp};
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// `20 / ln(10)`, scaled by `SCALE`, i.e. the decibels of an amplitude ratio of `e`
+const DECIBELS_PER_NEPER: i128 = 8_685_889_638_065_036_553;
+/// `ln(10) / 20`, scaled by `SCALE`
+const NEPERS_PER_DECIBEL: i128 = 115_129_254_649_702_284;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// What the ratio converted to or from decibels is of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, defmt::Format)]
+pub enum RatioKind {
+    /// Power, energy or intensity, i.e. `10 * log10(ratio)` decibels
+    #[default] Power,
+    /// Voltage, current or sound pressure, whose squares the power is proportional to, i.e. `20 * log10(ratio)` decibels
+    Amplitude,
+}
+
+impl RatioKind {
+    /// Power ratios are squares of amplitude ones, so they have half the decibels per neper
+    fn divisor(self) -> i128 {
+        match self {
+            RatioKind::Power => 2,
+            RatioKind::Amplitude => 1,
+        }
+    }
+}
+
+impl DecimalFixed {
+    /// Returns the ratio in decibels, e.g. 3.010299957 for a power ratio of 2. Returns `BadInput` unless the ratio is positive.
+    pub fn to_db(self, kind: RatioKind) -> Result<Self, CustomError> {
+        if self.is_negative() || self.is_zero() {
+            return Err(CE::BadInput); // A ratio of zero would be minus infinity
+        }
+        from_wide( mul_scaled(ln(to_wide(self)?), DECIBELS_PER_NEPER) / kind.divisor() )
+    }
+
+    /// Returns the ratio of the decibels, e.g. 100 for 20 dB of power. Returns `MathOverflow` if it doesn't fit,
+    /// ratios too small to tell from zero are zero.
+    pub fn from_db(decibels: Self, kind: RatioKind) -> Result<Self, CustomError> {
+        from_wide( exp(mul_scaled(to_wide(decibels)?, NEPERS_PER_DECIBEL) * kind.divisor())? )
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::RatioKind;
+    use crate::decfix::{num, DecimalFixed};
+    use crate::custom_error::CE;
+
+    #[test]
+    fn ratios_to_decibels() {
+        assert_eq!(num("2").to_db(RatioKind::Power), Ok(num("3.010299957")));
+        assert_eq!(num("0.5").to_db(RatioKind::Amplitude), Ok(num("-6.020599913")));
+        assert_eq!(num("1").to_db(RatioKind::Power), Ok(num("0")));
+        assert_eq!(num("1000000").to_db(RatioKind::Amplitude), Ok(num("120")));
+        assert_eq!(num("0.000000001").to_db(RatioKind::Power), Ok(num("-90")));
+        assert_eq!(num("0").to_db(RatioKind::Power), Err(CE::BadInput));
+        assert_eq!(num("-2").to_db(RatioKind::Power), Err(CE::BadInput));
+    }
+
+    #[test]
+    fn decibels_to_ratios() {
+        assert_eq!(DecimalFixed::from_db(num("3"), RatioKind::Power), Ok(num("1.995262315")));
+        assert_eq!(DecimalFixed::from_db(num("-6"), RatioKind::Amplitude), Ok(num("0.501187234")));
+        assert_eq!(DecimalFixed::from_db(num("60"), RatioKind::Power), Ok(num("1000000")));
+        assert_eq!(DecimalFixed::from_db(num("-40"), RatioKind::Amplitude), Ok(num("0.01")));
+        assert_eq!(DecimalFixed::from_db(num("-300"), RatioKind::Power), Ok(num("0")));
+        assert_eq!(DecimalFixed::from_db(num("100"), RatioKind::Power), Err(CE::MathOverflow));
+
+        // And back, with a ratio large enough not to lose the digits to rounding
+        let ratio = DecimalFixed::from_db(num("46.5"), RatioKind::Amplitude).unwrap();
+        assert_eq!(ratio.to_db(RatioKind::Amplitude), Ok(num("46.5")));
+    }
+}
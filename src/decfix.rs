@@ -1,7 +1,7 @@
 use defmt::Format as DefmtFormat;
 use heapless::{String, format};
 use core::{
-    fmt::Display,
+    fmt::{self, Display, LowerExp},
     ops::{Add, Sub, Neg, Mul, Div},
     str::FromStr,
     cmp::Ordering
@@ -14,6 +14,9 @@ use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
 
 const DEFAULT_EXPONENT: i32 = -9;
 const PARSING_BUFFER_SIZE: usize = 32; // Buffer size for padding fractional parts when parsing strings and displaying them.
+const GROUPING_BUFFER_SIZE: usize = 64; // Buffer size for the ungrouped number, before the separators get put in
+/// Goes between the groups of three digits with the alternate flag, e.g. `1 234 567.89`
+const GROUP_SEPARATOR: char = ' ';
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, DefmtFormat)]
 pub struct DecimalFixed {
@@ -22,7 +25,20 @@ pub struct DecimalFixed {
 }
 
 impl Display for DecimalFixed {
+    /// Without a precision, it prints all the significant digits (e.g. `1.5`),
+    /// with a precision, it rounds to that many decimal places (e.g. `{:.2}` prints `1.50`).
+    /// The alternate flag groups the whole part by thousands (e.g. `{:#}` prints `1 234 567.89`), the fractional part stays as is.
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if f.alternate() {
+            let ungrouped: String<GROUPING_BUFFER_SIZE> = match f.precision() {
+                Some(precision) => format!("{:.precision$}", self)?,
+                None => format!("{}", self)?,
+            };
+            return write_grouped(f, &ungrouped);
+        }
+        if let Some(precision) = f.precision() {
+            return self.fmt_fixed(f, precision);
+        }
         if self.value == 0 {
             return write!(f, "0");
         }
@@ -78,13 +94,112 @@ impl Display for DecimalFixed {
     }
 }
 
+/// Scientific notation, e.g. `1.5e3`. Without a precision, the mantissa has all the significant digits,
+/// with a precision, it's rounded to that many decimal places (e.g. `{:.2e}` prints `1.50e3`).
+impl LowerExp for DecimalFixed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut magnitude = u128::from(self.value.unsigned_abs());
+        // Number of digits of the magnitude, and the power of ten of the first one
+        let (mut digits, mut exponent10) = if magnitude == 0 {
+            (1, 0)
+        } else {
+            let digits = magnitude.ilog10() + 1;
+            (digits, i64::from(self.exponent) + i64::from(digits) - 1)
+        };
+
+        let precision = match f.precision() {
+            Some(precision) => u32::try_from(precision).map_err(|_| fmt::Error)?,
+            None => {
+                // Trailing zeroes aren't significant, we only keep the first digit even if it's zero
+                while digits > 1 && magnitude % 10 == 0 {
+                    magnitude /= 10;
+                    digits -= 1;
+                }
+                digits - 1
+            },
+        };
+
+        let wanted_digits = precision + 1; // The one before the decimal point
+        let mut mantissa = if digits > wanted_digits {
+            round_div(magnitude, pow10(digits - wanted_digits)?)
+        } else {
+            magnitude.checked_mul(pow10(wanted_digits - digits)?).ok_or(fmt::Error)?
+        };
+        if mantissa == pow10(wanted_digits)? {
+            // The rounding carried over into a new digit, e.g. 9.99 to 10.0
+            mantissa /= 10;
+            exponent10 += 1;
+        }
+
+        if self.value.is_negative() {
+            write!(f, "-")?;
+        }
+        write_scaled(f, mantissa, precision)?;
+        write!(f, "e{}", exponent10)
+    }
+}
+
+impl PartialOrd for DecimalFixed {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for DecimalFixed {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Scale the one with the bigger exponent to the smaller one, so that we compare apples to apples
+        let (self_value, other_value) = match self.exponent.cmp(&other.exponent) {
+            Ordering::Equal => (i128::from(self.value), i128::from(other.value)),
+            Ordering::Greater => {
+                match 10_i128.checked_pow((self.exponent - other.exponent).unsigned_abs())
+                    .and_then(|factor| i128::from(self.value).checked_mul(factor))
+                {
+                    Some(scaled) => (scaled, i128::from(other.value)),
+                    // If it overflows even an i128, it's way out of range of the other i64, so only the sign matters.
+                    // If it's zero, the sign of the other one decides, and if both are zero, the exponent does.
+                    None => return self.value.cmp(&0)
+                        .then(0.cmp(&other.value))
+                        .then(self.exponent.cmp(&other.exponent)),
+                }
+            },
+            Ordering::Less => {
+                match 10_i128.checked_pow((other.exponent - self.exponent).unsigned_abs())
+                    .and_then(|factor| i128::from(other.value).checked_mul(factor))
+                {
+                    Some(scaled) => (i128::from(self.value), scaled),
+                    None => return 0.cmp(&other.value) // Same as above, just mirrored
+                        .then(self.value.cmp(&0))
+                        .then(self.exponent.cmp(&other.exponent)),
+                }
+            }
+        };
+
+        // The derived PartialEq compares the fields, so 1.0 with different exponents isn't equal.
+        // We have to be consistent with it, so we break the ties with the exponent.
+        self_value.cmp(&other_value)
+            .then(self.exponent.cmp(&other.exponent))
+    }
+}
+
 impl Default for DecimalFixed {
     fn default() -> Self {
-        Self { value: 0, exponent: DEFAULT_EXPONENT }
+        Self::ZERO
+    }
+}
+
+impl FromStr for DecimalFixed {
+    type Err = CustomError;
+
+    /// Parses with the default exponent, the same as `parse_str(s, None)`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_str(s, None)
     }
 }
 
 impl DecimalFixed {
+    /// Zero with the default exponent, the same as `default()`, but usable in `const` contexts
+    pub const ZERO: Self = Self { value: 0, exponent: DEFAULT_EXPONENT };
+
     /// Creates a new DecimalFixed with the given value and exponent.
     /// This function scales your input value accordingly.
     /// 
@@ -124,6 +239,8 @@ impl DecimalFixed {
     /// or the default exponent specified in a const if you pass None.
     /// 
     /// If the string has a fractional part that isn't the correct size, it will be truncated/padded to fit the exponent.
+    ///
+    /// Scientific notation (e.g. `1.6e-19`) is accepted too, the digits that don't fit into the exponent are truncated the same way.
     pub fn parse_str(s: &str, exp: Option<i32>) -> Result<Self, CustomError> {
         let exp = exp.unwrap_or(DEFAULT_EXPONENT);
         if exp >= 0 { return Err(CE::Unimplemented) }; // TODO: Handle this case if needed
@@ -131,6 +248,15 @@ impl DecimalFixed {
 
         if s.is_empty() { return Err( CE::BadInput ) };
 
+        // The mantissa is parsed as usual, and then just shifted by the power of ten
+        if let Some((mantissa, power)) = s.split_once(['e', 'E']) {
+            let power: i32 = power.parse()?;
+            let mantissa = Self::parse_str(mantissa, Some(exp))?;
+            // Saturating is fine, it either overflows or truncates to zero way before reaching the limits
+            return Self::new_prescaled(mantissa.value, exp.saturating_add(power))
+                .with_exponent(Some(exp));
+        }
+
         let mut iter = s.splitn(2, '.'); // Split into at most two parts, at the first dot from left
 
         let whole_part_str: &str = iter.next().expect("First .next() on SplitN should be Some!");
@@ -193,6 +319,109 @@ impl DecimalFixed {
     pub fn is_zero(&self) -> bool {
         self.value == 0
     }
+
+    /// Returns the exponent of the number, i.e. the power of ten the inner value is scaled by
+    pub fn exponent(&self) -> i32 {
+        self.exponent
+    }
+
+    /// Returns the inner value, the logical value of the number being `value * 10^exponent`
+    pub fn value(&self) -> i64 {
+        self.value
+    }
+
+    /// Returns the whole part of the number, truncated towards zero like `as` does, e.g. `-2` for `-2.7`.
+    /// Returns `MathOverflow` if it doesn't fit into an i64, which can only happen with a positive exponent.
+    pub fn integer_part(self) -> Result<i64, CustomError> {
+        Ok(self.with_exponent(Some(0))?.value)
+    }
+
+    /// Writes the number rounded (half away from zero) to `precision` decimal places, padded with trailing zeroes.
+    fn fmt_fixed(&self, f: &mut fmt::Formatter<'_>, precision: usize) -> fmt::Result {
+        let precision = u32::try_from(precision).map_err(|_| fmt::Error)?;
+        let magnitude = u128::from(self.value.unsigned_abs());
+
+        // Rescale the magnitude to units of 10^(-precision), in u128 so that we don't have to care about overflows much
+        let target_exponent = -i64::from(precision);
+        let scaled = match i64::from(self.exponent).cmp(&target_exponent) {
+            Ordering::Equal => magnitude,
+            Ordering::Greater => magnitude
+                .checked_mul(pow10(u32::try_from(i64::from(self.exponent) - target_exponent).map_err(|_| fmt::Error)?)?)
+                .ok_or(fmt::Error)?,
+            Ordering::Less => round_div(
+                magnitude,
+                pow10(u32::try_from(target_exponent - i64::from(self.exponent)).map_err(|_| fmt::Error)?)?
+            ),
+        };
+
+        // So that e.g. -0.001 rounded to two places is "0.00" and not "-0.00"
+        if self.value.is_negative() && scaled != 0 {
+            write!(f, "-")?;
+        }
+        write_scaled(f, scaled, precision)
+    }
+
+    /// Returns the same number with a different exponent, or the default one if you pass None.
+    /// Digits that don't fit into a greater exponent get truncated, like with `new()`.
+    pub fn with_exponent(self, exponent: Option<i32>) -> Result<Self, CustomError> {
+        let exponent = exponent.unwrap_or(DEFAULT_EXPONENT);
+        let value = match self.exponent.cmp(&exponent) {
+            Ordering::Equal => self.value,
+            Ordering::Greater => self.value.checked_mul(
+                10_i64.checked_pow((self.exponent - exponent).unsigned_abs()).ok_or(CE::MathOverflow)?
+            ).ok_or(CE::MathOverflow)?,
+            // If the divisor overflows, the result would've been truncated to zero anyway
+            Ordering::Less => 10_i64.checked_pow((exponent - self.exponent).unsigned_abs())
+                .map_or(0, |divisor| self.value / divisor),
+        };
+        Ok( DecimalFixed { value, exponent } )
+    }
+
+    /// Serializes the number into 12 little-endian bytes: the 8 bytes of the inner value, then the 4 bytes of the exponent.
+    pub fn to_le_bytes(self) -> [u8; 12] {
+        let mut bytes = [0_u8; 12];
+        bytes[..8].copy_from_slice(&self.value.to_le_bytes());
+        bytes[8..].copy_from_slice(&self.exponent.to_le_bytes());
+        bytes
+    }
+
+    /// Deserializes the number from the format produced by `to_le_bytes()`.
+    pub fn from_le_bytes(bytes: [u8; 12]) -> Self {
+        // The conversions can't fail, the subslices are exactly 8 and 4 bytes long
+        let value = i64::from_le_bytes(bytes[..8].try_into().expect("Subslice is exactly 8 bytes long"));
+        let exponent = i32::from_le_bytes(bytes[8..].try_into().expect("Subslice is exactly 4 bytes long"));
+        Self { value, exponent }
+    }
+
+    /// Returns the square root of the number, rounded down to the precision of the exponent.
+    /// Negative numbers return `BadInput`.
+    pub fn sqrt(self) -> Result<Self, CustomError> {
+        if self.is_negative() { return Err(CE::BadInput) };
+        if self.exponent >= 0 { return Err(CE::Unimplemented) }; // TODO: Handle this case if needed
+
+        // sqrt(value * 10^exp) = sqrt(value * 10^(-exp)) * 10^exp
+        // so we scale the value up once more, and the square root then scales it back down to `exp`.
+        // The i128 is big enough for that, since 10^(-exp) fits into an i64 already.
+        let scaled_value: u128 = (self.value.unsigned_abs() as u128)
+            .checked_mul(10_u64.pow(self.exponent.unsigned_abs()) as u128)
+            .ok_or(CE::MathOverflow)?;
+
+        // A square root of anything up to u128::MAX fits into u64, but not necessarily into i64
+        Ok( DecimalFixed { value: i64::try_from(scaled_value.isqrt())?, exponent: self.exponent } )
+    }
+
+    /// Returns `sqrt(self² + other²)`, i.e. the length of the hypotenuse, rounded down to the precision of `self`'s exponent.
+    /// Returns `MathOverflow` only if the result itself doesn't fit, the squares can't overflow.
+    pub fn hypot(self, other: Self) -> Result<Self, CustomError> {
+        let other = other.with_exponent(Some(self.exponent))?;
+        // Instead of the usual scaling by the greater one, max * sqrt(1 + (min / max)²), which avoids the overflow
+        // of the squares at the cost of some digits, we square them in a u128, where they fit without any scaling:
+        // sqrt((a * 10^exp)² + (b * 10^exp)²) = sqrt(a² + b²) * 10^exp, so the exponent doesn't even have to be negative.
+        // Each square is below 2^126, so their sum is below 2^127.
+        let (a, b) = (u128::from(self.value.unsigned_abs()), u128::from(other.value.unsigned_abs()));
+        let sum_of_squares = a * a + b * b;
+        Ok( DecimalFixed { value: i64::try_from(sum_of_squares.isqrt())?, exponent: self.exponent } )
+    }
 }
 
 impl Add for DecimalFixed {
@@ -312,4 +541,335 @@ impl Div for DecimalFixed {
 
         Ok( DecimalFixed { value: i64::try_from(end_value)? , exponent: self.exponent } )
     }
-}
\ No newline at end of file
+}
+/// Returns 10^exp, or a formatting error if it doesn't fit (only happens with absurd precisions).
+fn pow10(exp: u32) -> Result<u128, fmt::Error> {
+    10_u128.checked_pow(exp).ok_or(fmt::Error)
+}
+
+/// Divides rounding half away from zero (the numbers are unsigned, so half up), unlike the truncating `/`.
+fn round_div(dividend: u128, divisor: u128) -> u128 {
+    let quotient = dividend / divisor;
+    if dividend % divisor >= divisor.div_ceil(2) { quotient + 1 } else { quotient }
+}
+
+/// Writes `value * 10^(-precision)` with exactly `precision` decimal places, e.g. 150 with precision 2 is "1.50".
+fn write_scaled(f: &mut fmt::Formatter<'_>, value: u128, precision: u32) -> fmt::Result {
+    let divisor = pow10(precision)?;
+    write!(f, "{}", value / divisor)?;
+    if precision > 0 {
+        write!(f, ".{:0>width$}", value % divisor, width = precision as usize)?;
+    }
+    Ok(())
+}
+
+/// Writes a formatted number with `GROUP_SEPARATOR` between each three digits of the whole part, counted from the decimal point.
+fn write_grouped(f: &mut fmt::Formatter<'_>, number: &str) -> fmt::Result {
+    let (sign, unsigned) = match number.strip_prefix('-') {
+        Some(unsigned) => ("-", unsigned),
+        None => ("", number),
+    };
+    let (whole_part, frac_part) = unsigned.split_at(unsigned.find('.').unwrap_or(unsigned.len()));
+
+    write!(f, "{}", sign)?;
+    // We make the (reasonable) assumption that it's all ASCII, like when parsing
+    for (i, digit) in whole_part.chars().enumerate() {
+        if i > 0 && (whole_part.len() - i).is_multiple_of(3) {
+            write!(f, "{}", GROUP_SEPARATOR)?;
+        }
+        write!(f, "{}", digit)?;
+    }
+    write!(f, "{}", frac_part) // Including the decimal point, if there's any
+}
+
+/// Shorthand for the numbers with the default exponent in the tests, which is what the calculator uses
+#[cfg(test)]
+pub(crate) fn num(s: &str) -> DecimalFixed {
+    DecimalFixed::parse_str(s, None).unwrap()
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::string::ToString;
+    use core::str::FromStr;
+    use super::{num, DecimalFixed, DEFAULT_EXPONENT};
+    use crate::custom_error::{CE, IntErrorKindClone};
+
+    // ----- Parsing -----
+
+    #[test]
+    fn parses_whole_and_fractional_parts() {
+        assert_eq!(num("1.5"), DecimalFixed::new_prescaled(1_500_000_000, -9));
+        assert_eq!(num("42"), DecimalFixed::new_prescaled(42_000_000_000, -9));
+        assert_eq!(num("42."), num("42"));
+        assert_eq!(num("0.000000001"), DecimalFixed::new_prescaled(1, -9));
+        assert_eq!(num("-1.25"), DecimalFixed::new_prescaled(-1_250_000_000, -9));
+    }
+
+    #[test]
+    fn parses_negative_below_one() {
+        // The whole part is "-0", which parses to a plain zero, so the sign has to be recovered separately
+        assert_eq!(num("-0.1"), DecimalFixed::new_prescaled(-100_000_000, -9));
+        assert!(num("-0.1").is_negative());
+        assert!(num("-0").is_zero());
+    }
+
+    #[test]
+    fn parse_pads_and_truncates_to_exponent() {
+        assert_eq!(DecimalFixed::parse_str("1.5", Some(-3)).unwrap(), DecimalFixed::new_prescaled(1500, -3));
+        // Truncated, not rounded
+        assert_eq!(DecimalFixed::parse_str("1.23756", Some(-2)).unwrap(), DecimalFixed::new_prescaled(123, -2));
+        assert_eq!(DecimalFixed::parse_str("-1.23756", Some(-2)).unwrap(), DecimalFixed::new_prescaled(-123, -2));
+    }
+
+    #[test]
+    fn from_str_uses_default_exponent() {
+        let parsed = DecimalFixed::from_str("3.14").unwrap();
+        assert_eq!(parsed, num("3.14"));
+        assert_eq!(parsed.exponent(), DEFAULT_EXPONENT);
+    }
+
+    #[test]
+    fn parse_rejects_bad_input() {
+        assert_eq!(DecimalFixed::parse_str("", None), Err(CE::BadInput));
+        assert_eq!(DecimalFixed::parse_str("abc", None), Err(CE::ParseIntError(IntErrorKindClone::InvalidDigit)));
+        assert_eq!(DecimalFixed::parse_str("1.2x", None), Err(CE::ParseIntError(IntErrorKindClone::InvalidDigit)));
+        assert_eq!(DecimalFixed::parse_str("1.2.3", None), Err(CE::ParseIntError(IntErrorKindClone::InvalidDigit)));
+        // There has to be a whole part, even if it's just a zero
+        assert_eq!(DecimalFixed::parse_str(".5", None), Err(CE::ParseIntError(IntErrorKindClone::Empty)));
+        // Only negative exponents are supported for now
+        assert_eq!(DecimalFixed::parse_str("1", Some(0)), Err(CE::Unimplemented));
+    }
+
+    #[test]
+    fn parses_scientific_notation() {
+        assert_eq!(num("1.5e3"), num("1500"));
+        assert_eq!(num("-2.5E-3"), num("-0.0025"));
+        assert_eq!(num("1e0"), num("1"));
+        assert_eq!(num("12.5e-1"), num("1.25"));
+        // Digits that don't fit are truncated, like with any other too long fractional part
+        assert_eq!(num("1.6e-19"), num("0"));
+        assert_eq!(num("1234e-12"), num("0.000000001"));
+        assert_eq!(DecimalFixed::parse_str("1e", None), Err(CE::ParseIntError(IntErrorKindClone::Empty)));
+        assert_eq!(DecimalFixed::parse_str("e5", None), Err(CE::BadInput));
+        assert_eq!(DecimalFixed::parse_str("1e2.5", None), Err(CE::ParseIntError(IntErrorKindClone::InvalidDigit)));
+        assert_eq!(DecimalFixed::parse_str("1e10", None), Err(CE::MathOverflow));
+        assert_eq!(DecimalFixed::parse_str("1e2147483647", None), Err(CE::MathOverflow));
+        assert_eq!(num("1e-2147483647"), num("0"));
+    }
+
+    #[test]
+    fn parse_overflows() {
+        // 10^10 scaled by 10^9 doesn't fit into an i64 anymore
+        assert_eq!(DecimalFixed::parse_str("10000000000", None), Err(CE::MathOverflow));
+        assert_eq!(DecimalFixed::parse_str("-10000000000", None), Err(CE::MathOverflow));
+        // Doesn't even fit before scaling
+        assert_eq!(DecimalFixed::parse_str("9223372036854775808", Some(-1)), Err(CE::ParseIntError(IntErrorKindClone::PosOverflow)));
+        // The largest one that fits
+        assert_eq!(DecimalFixed::parse_str("9223372036.854775807", None).unwrap(), DecimalFixed::new_prescaled(i64::MAX, -9));
+        assert_eq!(DecimalFixed::parse_str("9223372036.854775808", None), Err(CE::MathOverflow));
+    }
+
+    #[test]
+    fn new_scales_the_value() {
+        assert_eq!(DecimalFixed::new(5, Some(-2)).unwrap(), DecimalFixed::new_prescaled(500, -2));
+        assert_eq!(DecimalFixed::new(5, Some(0)).unwrap(), DecimalFixed::new_prescaled(5, 0));
+        // Digits that don't fit into a positive exponent are truncated
+        assert_eq!(DecimalFixed::new(1234, Some(2)).unwrap(), DecimalFixed::new_prescaled(12, 2));
+        assert_eq!(DecimalFixed::new(i64::MAX, None), Err(CE::MathOverflow));
+    }
+
+    // ----- Formatting -----
+
+    #[test]
+    fn displays_significant_digits() {
+        assert_eq!(num("1.5").to_string(), "1.5");
+        assert_eq!(num("-0.1").to_string(), "-0.1");
+        assert_eq!(num("42").to_string(), "42");
+        assert_eq!(num("-1.000000001").to_string(), "-1.000000001");
+        assert_eq!(DecimalFixed::default().to_string(), "0");
+        assert_eq!(DecimalFixed::new_prescaled(42, 0).to_string(), "42");
+        assert_eq!(DecimalFixed::new_prescaled(12, 2).to_string(), "1200");
+        assert_eq!(DecimalFixed::new_prescaled(i64::MIN, -9).to_string(), "-9223372036.854775808");
+    }
+
+    #[test]
+    fn displays_with_precision() {
+        assert_eq!(std::format!("{:.2}", num("1.5")), "1.50");
+        assert_eq!(std::format!("{:.0}", num("1.5")), "2");
+        assert_eq!(std::format!("{:.3}", DecimalFixed::new_prescaled(12, 1)), "120.000");
+        assert_eq!(std::format!("{:.2}", DecimalFixed::default()), "0.00");
+    }
+
+    #[test]
+    fn displays_grouped() {
+        assert_eq!(std::format!("{:#}", num("1234567.89")), "1 234 567.89");
+        assert_eq!(std::format!("{:#}", num("-123456")), "-123 456");
+        assert_eq!(std::format!("{:#}", num("999")), "999");
+        assert_eq!(std::format!("{:#}", num("0.000012345")), "0.000012345");
+        assert_eq!(std::format!("{:#.2}", num("9999.995")), "10 000.00");
+        assert_eq!(std::format!("{:#}", DecimalFixed::new_prescaled(12, 4)), "120 000");
+        assert_eq!(std::format!("{:#}", DecimalFixed::new_prescaled(i64::MIN, -9)), "-9 223 372 036.854775808");
+    }
+
+    #[test]
+    fn lower_exp() {
+        assert_eq!(std::format!("{:e}", num("1500")), "1.5e3");
+        assert_eq!(std::format!("{:e}", num("-1500")), "-1.5e3");
+        assert_eq!(std::format!("{:e}", num("0.00123")), "1.23e-3");
+        assert_eq!(std::format!("{:e}", num("7")), "7e0");
+        assert_eq!(std::format!("{:e}", DecimalFixed::default()), "0e0");
+        assert_eq!(std::format!("{:.2e}", num("1500")), "1.50e3");
+        assert_eq!(std::format!("{:.0e}", DecimalFixed::new_prescaled(12, 2)), "1e3");
+    }
+
+    // ----- Rounding -----
+
+    #[test]
+    fn precision_rounds_half_away_from_zero() {
+        assert_eq!(std::format!("{:.2}", num("1.005")), "1.01");
+        assert_eq!(std::format!("{:.2}", num("1.004999999")), "1.00");
+        assert_eq!(std::format!("{:.2}", num("-1.005")), "-1.01");
+        assert_eq!(std::format!("{:.0}", num("2.5")), "3");
+        assert_eq!(std::format!("{:.0}", num("-2.5")), "-3");
+        // Carries into the whole part
+        assert_eq!(std::format!("{:.2}", num("9.995")), "10.00");
+    }
+
+    #[test]
+    fn precision_drops_sign_of_zero() {
+        assert_eq!(std::format!("{:.2}", num("-0.001")), "0.00");
+        assert_eq!(std::format!("{:.2}", num("-0.005")), "-0.01");
+    }
+
+    #[test]
+    fn lower_exp_rounding_carries() {
+        assert_eq!(std::format!("{:.2e}", num("9.999")), "1.00e1");
+        assert_eq!(std::format!("{:.1e}", num("0.0995")), "1.0e-1");
+        assert_eq!(std::format!("{:.1e}", num("-9.96")), "-1.0e1");
+    }
+
+    #[test]
+    fn with_exponent_truncates() {
+        assert_eq!(num("1.999").with_exponent(Some(-2)).unwrap(), DecimalFixed::new_prescaled(199, -2));
+        assert_eq!(num("-1.999").with_exponent(Some(-2)).unwrap(), DecimalFixed::new_prescaled(-199, -2));
+        assert_eq!(DecimalFixed::new_prescaled(199, -2).with_exponent(None).unwrap(), num("1.99"));
+        // So far down that the divisor itself overflows
+        assert_eq!(num("5").with_exponent(Some(30)).unwrap(), DecimalFixed::new_prescaled(0, 30));
+    }
+
+    #[test]
+    fn division_and_sqrt_truncate() {
+        assert_eq!((num("1") / num("3")).unwrap(), num("0.333333333"));
+        assert_eq!((num("2") / num("3")).unwrap(), num("0.666666666"));
+        assert_eq!((num("-2") / num("3")).unwrap(), num("-0.666666666"));
+        assert_eq!(num("2").sqrt().unwrap(), num("1.414213562"));
+        assert_eq!(num("0").sqrt().unwrap(), num("0"));
+        assert_eq!(num("6.25").sqrt().unwrap(), num("2.5"));
+    }
+
+    #[test]
+    fn hypot_doesnt_overflow() {
+        assert_eq!(num("3").hypot(num("-4")), Ok(num("5")));
+        assert_eq!(num("0.00003").hypot(num("0.00004")), Ok(num("0.00005")));
+        assert_eq!(num("1").hypot(num("1")), Ok(num("1.414213562")));
+        // Squaring either would overflow
+        assert_eq!(num("3000000000").hypot(num("4000000000")), Ok(num("5000000000")));
+        assert_eq!(num("9000000000").hypot(num("9000000000")), Err(CE::MathOverflow));
+        assert_eq!(DecimalFixed::new_prescaled(30, 2).hypot(DecimalFixed::new_prescaled(40, 2)), Ok(DecimalFixed::new_prescaled(50, 2)));
+    }
+
+    #[test]
+    fn integer_part_truncates() {
+        assert_eq!(num("2.7").integer_part(), Ok(2));
+        assert_eq!(num("-2.7").integer_part(), Ok(-2));
+        assert_eq!(num("0.999999999").integer_part(), Ok(0));
+        assert_eq!(DecimalFixed::new_prescaled(i64::MAX, 1).integer_part(), Err(CE::MathOverflow));
+    }
+
+    #[test]
+    fn multiplication_truncates() {
+        assert_eq!((num("0.1") * num("0.2")).unwrap(), num("0.02"));
+        // 0.000000001^2 is far below the precision
+        assert_eq!((num("0.000000001") * num("0.000000001")).unwrap(), num("0"));
+        assert_eq!((num("-1.5") * num("1.5")).unwrap(), num("-2.25"));
+    }
+
+    // ----- Arithmetics and overflows -----
+
+    #[test]
+    fn arithmetics() {
+        assert_eq!((num("1.5") + num("2.25")).unwrap(), num("3.75"));
+        assert_eq!((num("1.5") - num("2.25")).unwrap(), num("-0.75"));
+        assert_eq!((-num("1.5")).unwrap(), num("-1.5"));
+        assert_eq!((-num("0")).unwrap(), num("0"));
+        // Adding with different exponents keeps the finer one
+        let coarse = DecimalFixed::new_prescaled(15, -1);
+        let fine = DecimalFixed::new_prescaled(25, -3);
+        assert_eq!((coarse + fine).unwrap(), DecimalFixed::new_prescaled(1525, -3));
+        assert_eq!((fine + coarse).unwrap(), DecimalFixed::new_prescaled(1525, -3));
+    }
+
+    #[test]
+    fn addition_overflows() {
+        let max = DecimalFixed::new_prescaled(i64::MAX, -9);
+        let min = DecimalFixed::new_prescaled(i64::MIN, -9);
+        let ulp = DecimalFixed::new_prescaled(1, -9);
+        assert_eq!(max + ulp, Err(CE::MathOverflow));
+        assert_eq!(min - ulp, Err(CE::MathOverflow));
+        assert_eq!(max - max, Ok(num("0")));
+        // Rescaling to the finer exponent overflows by itself
+        assert_eq!(DecimalFixed::new_prescaled(i64::MAX, 0) + ulp, Err(CE::MathOverflow));
+    }
+
+    #[test]
+    fn negation_overflows() {
+        assert_eq!(-DecimalFixed::new_prescaled(i64::MIN, -9), Err(CE::MathOverflow));
+        // Subtraction negates, so subtracting i64::MIN overflows even when the result would fit
+        assert_eq!(num("-1") - DecimalFixed::new_prescaled(i64::MIN, -9), Err(CE::MathOverflow));
+    }
+
+    #[test]
+    fn multiplication_and_division_overflow() {
+        assert_eq!(num("100000") * num("100000"), Err(CE::MathOverflow));
+        assert_eq!(num("9223372036") / num("0.1"), Err(CE::MathOverflow));
+        assert_eq!(DecimalFixed::new_prescaled(i64::MIN, 0) * DecimalFixed::new_prescaled(i64::MIN, 0), Err(CE::MathOverflow));
+    }
+
+    #[test]
+    fn unsupported_operations() {
+        assert_eq!(num("1") / num("0"), Err(CE::BadInput));
+        assert_eq!(num("-1").sqrt(), Err(CE::BadInput));
+        assert_eq!(DecimalFixed::new_prescaled(4, 0).sqrt(), Err(CE::Unimplemented));
+        // Multiplication and division need matching exponents
+        assert_eq!(num("1") * DecimalFixed::new_prescaled(1, 0), Err(CE::Unimplemented));
+        assert_eq!(num("1") / DecimalFixed::new_prescaled(1, 0), Err(CE::Unimplemented));
+    }
+
+    // ----- Comparison and serialization -----
+
+    #[test]
+    fn ordering_across_exponents() {
+        assert!(num("1.5") < num("2"));
+        assert!(num("-2") < num("-1.5"));
+        assert!(DecimalFixed::new_prescaled(15, -1) < DecimalFixed::new_prescaled(2, 0));
+        assert!(DecimalFixed::new_prescaled(-3, 0) < DecimalFixed::new_prescaled(-25, -1));
+        // Equal values with different exponents aren't `==`, so the ordering breaks the tie by the exponent
+        assert!(DecimalFixed::new_prescaled(1, 0) > num("1"));
+        // Rescaling overflows even an i128, so only the signs decide
+        assert!(DecimalFixed::new_prescaled(1, 30) > DecimalFixed::new_prescaled(i64::MAX, -9));
+        assert!(DecimalFixed::new_prescaled(-1, 30) < DecimalFixed::new_prescaled(i64::MIN, -9));
+        assert!(DecimalFixed::new_prescaled(0, 30) < DecimalFixed::new_prescaled(1, -9));
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        for x in [num("0"), num("-1.5"), DecimalFixed::new_prescaled(i64::MIN, -9), DecimalFixed::new_prescaled(i64::MAX, 7)] {
+            assert_eq!(DecimalFixed::from_le_bytes(x.to_le_bytes()), x);
+        }
+        assert_eq!(num("-1.5").to_le_bytes()[8..], (-9_i32).to_le_bytes());
+    }
+
+}
@@ -0,0 +1,220 @@
+use core::cell::RefCell;
+use embedded_graphics::{
+    prelude::*,
+    pixelcolor::{BinaryColor, Gray4},
+    primitives::Rectangle,
+};
+use display_interface::DisplayError;
+use ssd1306::{
+    Ssd1306,
+    prelude::*,
+    mode::BufferedGraphicsMode,
+};
+
+use crate::custom_error::CustomError; // Because we already have the `mod` in `main.rs`
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Pre-charge period used with a custom contrast, the same one all of the `ssd1306` presets but the dimmest use
+const SSD1306_PRECHARGE: u8 = 0x2;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// The colours the widgets draw with, so that they look right on monochrome as well as grayscale displays.
+/// Converting from `BinaryColor` is for the 1-bit images, e.g. the error icon.
+pub trait Palette: PixelColor + From<BinaryColor> {
+    /// What the display gets cleared to
+    const BACKGROUND: Self;
+    /// Text and lines
+    const FOREGROUND: Self;
+    /// Whatever's less important, e.g. the deeper stack levels or the placeholder text.
+    /// If it's the same as the foreground, `Dimmed` thins out the pixels instead.
+    const DIMMED: Self;
+    /// Behind the highlighted top of the stack, the text on it is in the background colour
+    const HIGHLIGHT: Self;
+    /// Bits per pixel in the frame buffers of `FrameScheduler`
+    const BITS: usize;
+
+    /// The colour packed into `BITS` bits, see `from_bits()`
+    fn to_bits(self) -> u8;
+    fn from_bits(bits: u8) -> Self;
+}
+
+impl Palette for BinaryColor {
+    const BACKGROUND: Self = BinaryColor::Off;
+    const FOREGROUND: Self = BinaryColor::On;
+    const DIMMED: Self = BinaryColor::On;
+    const HIGHLIGHT: Self = BinaryColor::On;
+    const BITS: usize = 1;
+
+    fn to_bits(self) -> u8 {
+        self.is_on() as u8
+    }
+
+    fn from_bits(bits: u8) -> Self {
+        BinaryColor::from(bits != 0)
+    }
+}
+
+impl Palette for Gray4 {
+    const BACKGROUND: Self = Gray4::BLACK;
+    const FOREGROUND: Self = Gray4::WHITE;
+    const DIMMED: Self = Gray4::new(0x7);
+    const HIGHLIGHT: Self = Gray4::new(0xB);
+    const BITS: usize = 4;
+
+    fn to_bits(self) -> u8 {
+        self.luma()
+    }
+
+    fn from_bits(bits: u8) -> Self {
+        Gray4::new(bits)
+    }
+}
+
+/// A display we can draw onto, which buffers the drawing until it's flushed.
+///
+/// This is the only thing the widgets need from a display, so that they aren't tied to the SSD1306
+/// and can also draw e.g. onto a simulator window or a grayscale SSD1327.
+pub trait FlushableDisplay: DrawTarget<Color: Palette> {
+    /// Sends the buffered drawing to the actual display.
+    fn flush_display(&mut self) -> Result<(), CustomError>;
+
+    /// Sends the buffered drawing right away, even what `flush_display()` of a `FrameScheduler` held back.
+    /// Has to be called before waiting for anything, so that the display doesn't show a stale frame meanwhile.
+    fn flush_now(&mut self) -> Result<(), CustomError> {
+        self.flush_display()
+    }
+
+    /// Sends the buffered drawing as the next frame of an animation, see `CustomStack::animate()`.
+    /// A `FrameScheduler` first waits out the frame interval since the last frame, so that every frame shows for about as long.
+    fn flush_frame(&mut self) -> Result<(), CustomError> {
+        self.flush_now()
+    }
+}
+
+/// Calls `flush_now()` before blocking on input. If whoever waits holds the display borrowed, it's up to them to flush it.
+pub fn flush_before_blocking<D: FlushableDisplay>(disp_refcell: &RefCell<D>) -> Result<(), CustomError> {
+    match disp_refcell.try_borrow_mut() {
+        Ok(mut disp) => disp.flush_now(),
+        Err(_) => Ok(()),
+    }
+}
+
+/// A physical display panel, with the settings the commands change besides drawing.
+/// The error is the display interface's, so that it converts into `CustomError` wherever the panel's generic.
+pub trait Panel: FlushableDisplay<Error = DisplayError> {
+    /// Sets the contrast, 0 being the dimmest and 255 the brightest.
+    fn set_contrast(&mut self, contrast: u8) -> Result<(), CustomError>;
+    /// Shows the pixels inverted (or not), without touching the buffer.
+    fn set_inverted(&mut self, inverted: bool) -> Result<(), CustomError>;
+    /// Turns the panel on or off, it keeps what's shown in its memory.
+    fn set_on(&mut self, on: bool) -> Result<(), CustomError>;
+    /// Sends the initialization sequence again, e.g. after a glitch that might've reset the panel.
+    /// It clears the panel and resets its settings, it's up to the caller to draw and set them again.
+    fn reinit(&mut self) -> Result<(), CustomError>;
+
+    /// The colour of the pixel as it was last sent to the panel, e.g. for the `screenshot` command, ignoring `set_inverted()`.
+    /// `None` off the display, or if the panel doesn't keep a buffer that can be read back, as the `ssd1306` driver doesn't.
+    fn pixel(&self, _point: Point) -> Option<Self::Color> {
+        None
+    }
+}
+
+impl<DI, SIZE> FlushableDisplay for Ssd1306<DI, SIZE, BufferedGraphicsMode<SIZE>>
+where
+    DI: WriteOnlyDataCommand,
+    SIZE: DisplaySize,
+{
+    fn flush_display(&mut self) -> Result<(), CustomError> {
+        self.flush()?; // Calls the inherent method, not this trait's one; those take precedence
+        Ok(())
+    }
+}
+
+impl<DI, SIZE> Panel for Ssd1306<DI, SIZE, BufferedGraphicsMode<SIZE>>
+where
+    DI: WriteOnlyDataCommand,
+    SIZE: DisplaySize,
+{
+    fn set_contrast(&mut self, contrast: u8) -> Result<(), CustomError> {
+        self.set_brightness(Brightness::custom(SSD1306_PRECHARGE, contrast))?;
+        Ok(())
+    }
+
+    fn set_inverted(&mut self, inverted: bool) -> Result<(), CustomError> {
+        self.set_invert(inverted)?;
+        Ok(())
+    }
+
+    fn set_on(&mut self, on: bool) -> Result<(), CustomError> {
+        self.set_display_on(on)?;
+        Ok(())
+    }
+
+    fn reinit(&mut self) -> Result<(), CustomError> {
+        self.init()?;
+        Ok(())
+    }
+}
+
+/// Draws whatever is drawn through it in the dimmed colour of the palette (except the background).
+/// On a monochrome display, it draws only every other pixel (in a checkerboard pattern) instead,
+/// which is the closest we can get to a dimmer colour there.
+pub struct Dimmed<'d, D>(pub &'d mut D);
+
+impl<D: DrawTarget<Color: Palette>> Dimensions for Dimmed<'_, D> {
+    fn bounding_box(&self) -> Rectangle {
+        self.0.bounding_box()
+    }
+}
+
+impl<D: DrawTarget<Color: Palette>> DrawTarget for Dimmed<'_, D> {
+    type Color = D::Color;
+    type Error = D::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let grayscale = D::Color::DIMMED != D::Color::FOREGROUND;
+        self.0.draw_iter(pixels.into_iter().filter_map(|Pixel(p, color)| match color {
+            _ if color == D::Color::BACKGROUND => Some(Pixel(p, color)),
+            _ if grayscale => Some(Pixel(p, D::Color::DIMMED)),
+            _ => ((p.x + p.y) % 2 == 0).then_some(Pixel(p, color)),
+        }))
+    }
+}
+
+/// A display that silently discards everything drawn onto it.
+///
+/// Only exists so that headless stacks (see `CustomStackBuilder::build_headless()`) have a concrete display type
+/// to be generic over, e.g. when testing on the host.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullDisplay;
+
+impl OriginDimensions for NullDisplay {
+    fn size(&self) -> Size {
+        Size::zero()
+    }
+}
+
+impl DrawTarget for NullDisplay {
+    type Color = BinaryColor;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, _pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        Ok(())
+    }
+}
+
+impl FlushableDisplay for NullDisplay {
+    fn flush_display(&mut self) -> Result<(), CustomError> {
+        Ok(())
+    }
+}
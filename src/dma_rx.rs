@@ -0,0 +1,161 @@
+use rp2040_hal as hal;
+use hal::dma::{ReadTarget, SingleChannel};
+use hal::uart::{ReadErrorType, Reader, UartDevice, ValidUartPinout};
+use core::cell::Cell;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use crate::uart_rx::UartRx;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Base-2 logarithm of the ring buffer's size, the DMA wraps the write address at this many bits
+const RING_BITS: u8 = 10;
+/// Size of the ring buffer, also its alignment, as the DMA's ring wrapping requires
+pub const RING_SIZE: usize = 1 << RING_BITS;
+
+// HACK: Evaluate the block of code at compile time to assert that constants aren't malformed
+const fn _check_consts() {
+    if core::mem::align_of::<RingBuffer>() != RING_SIZE {
+        core::panic!("The ring buffer must be aligned to its size, fix the `repr(align)` of RingBuffer!");
+    }
+}
+const _: () = _check_consts();
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// The buffer the DMA writes into, it has to be aligned to its size for the wrapping to work.
+#[repr(C, align(1024))]
+pub struct RingBuffer(pub [u8; RING_SIZE]);
+
+/// Receives from UART by a DMA channel writing into a ring buffer in the background,
+/// so that no bytes get lost to the 32 byte FIFO overrunning while we're busy, e.g. flushing the display during a pasted script.
+///
+/// The DMA transfers up to `u32::MAX` bytes before stopping (over four days at 115200 baud),
+/// so we restart it whenever we find it stopped. The UART's error flags (framing, break etc.) get lost on the way,
+/// the only error we can detect is the ring buffer overrunning, i.e. more than `RING_SIZE` bytes arriving without us reading them.
+pub struct DmaReader<CH, D, P>
+where
+    CH: SingleChannel,
+    D: UartDevice,
+    P: ValidUartPinout<D>,
+{
+    channel: CH,
+    /// Kept, so that nobody else can read from the UART behind our back
+    _reader: Reader<D, P>,
+    /// Only the DMA writes into it, we read it volatile-ly through this pointer
+    buf: *const u8,
+    /// Number of bytes transferred by the previous DMA runs (all of them are wrapping counters)
+    previous_runs: Cell<u32>,
+    /// Number of bytes we've already read
+    consumed: Cell<u32>,
+}
+
+impl<CH, D, P> DmaReader<CH, D, P>
+where
+    CH: SingleChannel,
+    D: UartDevice,
+    P: ValidUartPinout<D>,
+{
+    pub fn new(channel: CH, reader: Reader<D, P>, buf: &'static mut RingBuffer) -> Self {
+        let dma_reader = DmaReader {
+            channel,
+            buf: buf.0.as_ptr(),
+            previous_runs: Cell::new(0),
+            consumed: Cell::new(0),
+            _reader: reader,
+        };
+
+        let (uart_address, _) = dma_reader._reader.rx_address_count();
+        let ch = dma_reader.channel.ch();
+        ch.ch_read_addr().write(|w| unsafe { w.bits(uart_address) });
+        ch.ch_write_addr().write(|w| unsafe { w.bits(dma_reader.buf as u32) });
+        dma_reader.start();
+        dma_reader
+    }
+
+    /// (Re)starts the DMA, continuing at the write address where it stopped.
+    fn start(&self) {
+        let ch = self.channel.ch();
+        ch.ch_trans_count().write(|w| unsafe { w.bits(u32::MAX) });
+        // Writing the control register through this alias triggers the channel
+        // SAFETY: The ring buffer is ours for 'static, aligned to its size, and the UART's data register is always readable.
+        ch.ch_ctrl_trig().write(|w| unsafe {
+            w.data_size().size_byte();
+            w.incr_read().clear_bit(); // Always the same data register
+            w.incr_write().set_bit();
+            w.ring_sel().set_bit(); // Wrap the write address, not the read one
+            w.ring_size().bits(RING_BITS);
+            w.treq_sel().bits(<Reader<D, P> as ReadTarget>::rx_treq().unwrap_or(0x3F)); // Paced by the UART, the fallback is unpaced
+            w.chain_to().bits(self.channel.id()); // Chaining to itself means no chaining
+            w.en().set_bit();
+            w
+        });
+    }
+
+    /// Total number of bytes transferred by the DMA so far (wrapping), restarting it if it has stopped.
+    fn transferred(&self) -> u32 {
+        let ch = self.channel.ch();
+        let remaining = ch.ch_trans_count().read().bits();
+        let transferred = self.previous_runs.get().wrapping_add(u32::MAX - remaining);
+
+        if remaining == 0 && ch.ch_ctrl_trig().read().busy().bit_is_clear() {
+            self.previous_runs.set(transferred);
+            self.start();
+        }
+        // Make sure we read the buffer only after reading how much of it is valid
+        compiler_fence(Ordering::Acquire);
+        transferred
+    }
+
+    /// Returns the next received byte, or `None` if there's none yet.
+    /// On overrun, the lost bytes get skipped, so the next call returns whatever's been received since then.
+    pub fn read_byte(&self) -> Result<Option<u8>, ReadErrorType> {
+        let transferred = self.transferred();
+        let pending = transferred.wrapping_sub(self.consumed.get());
+        if pending == 0 {
+            return Ok(None);
+        }
+        if pending as usize > RING_SIZE {
+            self.consumed.set(transferred);
+            return Err(ReadErrorType::Overrun);
+        }
+
+        // The n-th transferred byte is at n modulo the size, since we started at the beginning of the buffer
+        let index = self.consumed.get() as usize % RING_SIZE;
+        // SAFETY: The index is within the buffer, which lives for 'static, and the DMA won't overwrite this byte until we've read it (or overrun).
+        let byte = unsafe { core::ptr::read_volatile(self.buf.add(index)) };
+        self.consumed.set(self.consumed.get().wrapping_add(1));
+        Ok(Some(byte))
+    }
+}
+
+impl<CH, D, P> UartRx for DmaReader<CH, D, P>
+where
+    CH: SingleChannel,
+    D: UartDevice,
+    P: ValidUartPinout<D>,
+{
+    fn read_full_blocking(&self, buf: &mut [u8]) -> Result<(), ReadErrorType> {
+        for byte in buf.iter_mut() {
+            *byte = loop { // Busy wait, same as the HAL does
+                if let Some(b) = self.read_byte()? {
+                    break b;
+                }
+            };
+        }
+        Ok(())
+    }
+
+    fn read_available(&self, buf: &mut [u8]) -> usize {
+        let mut count = 0;
+        for byte in buf.iter_mut() {
+            match self.read_byte() {
+                Ok(Some(b)) => *byte = b,
+                _ => break, // Same as with the HAL, an error is just a reason to stop
+            }
+            count += 1;
+        }
+        count
+    }
+}
@@ -0,0 +1,178 @@
+use core::cell::RefCell;
+use embedded_hal::digital::InputPin;
+use heapless::Deque;
+use rp2040_hal as hal;
+use hal::uart::ReadErrorType;
+
+use crate::uart_rx::UartRx;
+use crate::get_timestamp_us;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// What the encoder sends, as if it were the arrow keys and Insert of a terminal:
+/// turning clockwise is Down, counter-clockwise Up and pressing the knob Insert.
+const CLOCKWISE_SEQUENCE: &[u8] = b"\x1B[B";
+const COUNTER_CLOCKWISE_SEQUENCE: &[u8] = b"\x1B[A";
+const PRESS_SEQUENCE: &[u8] = b"\x1B[2~";
+/// Quadrature transitions per detent (click) of the knob, four for the usual cheap encoders
+const STEPS_PER_DETENT: i8 = 4;
+/// How long the button has to stay pressed (or released) before we believe it, in microseconds
+const DEBOUNCE_US: u64 = 20_000;
+/// Room for the sequences not yet read, more than enough for one event at a time
+const PENDING_SIZE: usize = 8;
+
+/// Direction of a transition from the previous state of the A and B lines (`prev << 2 | current`),
+/// zero for no change or an invalid jump over a state (a missed transition or a bounce)
+const TRANSITIONS: [i8; 16] = [0, -1, 1, 0, 1, 0, 0, -1, -1, 0, 0, 1, 0, 1, -1, 0];
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum EncoderEvent {
+    Clockwise,
+    CounterClockwise,
+    Press,
+}
+
+/// A polled quadrature rotary encoder with a push button, all three lines pulled up and shorted to ground when active.
+///
+/// It has to be polled often enough not to miss a transition, a few hundred times per second is fine for turning by hand.
+pub struct Encoder<A: InputPin, B: InputPin, S: InputPin> {
+    a: A,
+    b: B,
+    switch: S,
+    /// The last state of the A and B lines, A being the upper bit
+    state: u8,
+    /// Transitions since the last detent, positive clockwise
+    steps: i8,
+    /// The last raw state of the button and since when, it becomes `pressed` once it's been there for `DEBOUNCE_US`
+    switch_raw: bool,
+    switch_since: u64,
+    pressed: bool,
+}
+
+impl<A: InputPin, B: InputPin, S: InputPin> Encoder<A, B, S> {
+    /// The pins should already be inputs with pull-ups.
+    pub fn new(a: A, b: B, switch: S) -> Self {
+        let mut encoder = Encoder {
+            a,
+            b,
+            switch,
+            state: 0,
+            steps: 0,
+            switch_raw: false,
+            switch_since: 0,
+            pressed: false,
+        };
+        encoder.state = encoder.read_lines();
+        encoder
+    }
+
+    /// Reads the lines, returns an event once a whole detent has been turned or the button pressed.
+    pub fn poll(&mut self) -> Option<EncoderEvent> {
+        let state = self.read_lines();
+        self.steps += TRANSITIONS[((self.state << 2) | state) as usize];
+        self.state = state;
+        if self.steps >= STEPS_PER_DETENT {
+            self.steps = 0;
+            return Some(EncoderEvent::Clockwise);
+        }
+        if self.steps <= -STEPS_PER_DETENT {
+            self.steps = 0;
+            return Some(EncoderEvent::CounterClockwise);
+        }
+
+        let now = get_timestamp_us();
+        // The RP2040's pins can't fail, and a failing pin would just look released
+        let switch_raw = self.switch.is_low().unwrap_or(false);
+        if switch_raw != self.switch_raw {
+            self.switch_raw = switch_raw;
+            self.switch_since = now;
+        } else if switch_raw != self.pressed && now - self.switch_since >= DEBOUNCE_US {
+            self.pressed = switch_raw;
+            if self.pressed {
+                return Some(EncoderEvent::Press);
+            }
+        }
+        None
+    }
+
+    fn read_lines(&mut self) -> u8 {
+        let a = self.a.is_low().unwrap_or(false) as u8;
+        let b = self.b.is_low().unwrap_or(false) as u8;
+        (a << 1) | b
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Reads from the encoder and the UART, whichever has something, so that the rest of the code doesn't care.
+/// The encoder's events arrive as escape sequences, see `CLOCKWISE_SEQUENCE` etc.
+///
+/// The UART is only polled, so its read errors (e.g. a break from a disconnected wire) go unreported.
+pub struct EncoderOrUart<U: UartRx, A: InputPin, B: InputPin, S: InputPin> {
+    uart: U,
+    // The reads only take `&self`, but polling needs to update the decoder state
+    encoder: RefCell<Encoder<A, B, S>>,
+    /// The rest of the last event's sequence, waiting to be read
+    pending: RefCell<Deque<u8, PENDING_SIZE>>,
+}
+
+impl<U: UartRx, A: InputPin, B: InputPin, S: InputPin> EncoderOrUart<U, A, B, S> {
+    pub fn new(uart: U, encoder: Encoder<A, B, S>) -> Self {
+        EncoderOrUart { uart, encoder: RefCell::new(encoder), pending: RefCell::new(Deque::new()) }
+    }
+
+    /// Polls the encoder for a new event if the last one's sequence has been read whole.
+    fn poll(&self) {
+        let mut pending = self.pending.borrow_mut();
+        if !pending.is_empty() {
+            return;
+        }
+        let Some(event) = self.encoder.borrow_mut().poll() else { return };
+        log_trace!("Encoder event: {:?}", event);
+        let sequence = match event {
+            EncoderEvent::Clockwise => CLOCKWISE_SEQUENCE,
+            EncoderEvent::CounterClockwise => COUNTER_CLOCKWISE_SEQUENCE,
+            EncoderEvent::Press => PRESS_SEQUENCE,
+        };
+        for &b in sequence {
+            let _ = pending.push_back(b); // Can't fail, it was empty and the sequences are shorter
+        }
+    }
+}
+
+impl<U: UartRx, A: InputPin, B: InputPin, S: InputPin> UartRx for EncoderOrUart<U, A, B, S> {
+    fn read_full_blocking(&self, buf: &mut [u8]) -> Result<(), ReadErrorType> {
+        for byte in buf.iter_mut() {
+            *byte = loop {
+                self.poll();
+                if let Some(b) = self.pending.borrow_mut().pop_front() {
+                    break b;
+                }
+                let mut one = [0_u8; 1];
+                if self.uart.read_available(&mut one) > 0 {
+                    break one[0];
+                }
+            };
+        }
+        Ok(())
+    }
+
+    fn read_available(&self, buf: &mut [u8]) -> usize {
+        // The sequence first, so that it doesn't get split by a byte from the UART.
+        // Only one, since the main loop expects one escape sequence per read.
+        self.poll();
+        let mut pending = self.pending.borrow_mut();
+        let mut n = 0;
+        while n < buf.len() && let Some(b) = pending.pop_front() {
+            buf[n] = b;
+            n += 1;
+        }
+        if n > 0 {
+            return n;
+        }
+        self.uart.read_available(buf)
+    }
+}
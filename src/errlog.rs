@@ -0,0 +1,223 @@
+use heapless::{Deque, String, Vec};
+
+use crate::kv;
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE, // Short type alias
+    WithContext,
+    TruncatingString,
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// How many of the last errors are kept, the oldest one gets forgotten to make space for a new one
+pub const ERROR_LOG_SIZE: usize = 16;
+/// Longest message of an error, longer ones are cut off. Enough for the error with its context, see `WithContext`'s `Display`.
+pub const ERROR_MESSAGE_SIZE: usize = 48;
+/// Key of the saved log in the key-value store
+const ERRLOG_KEY: &str = "errlog";
+/// Prefixes the saved log, spells "ERR1" in ASCII; bump the number when the layout of `to_bytes()` changes
+const ERRLOG_MAGIC: u32 = u32::from_le_bytes(*b"ERR1");
+/// Size of one serialized record at most: the timestamp, the length of the message, and the message itself
+const RECORD_SIZE: usize = 8 + 1 + ERROR_MESSAGE_SIZE;
+/// Size of the whole serialized log at most: the magic, the record count, and the records
+const SERIALIZED_SIZE: usize = 4 + 1 + ERROR_LOG_SIZE * RECORD_SIZE;
+
+// HACK: Evaluate the block of code at compile time to assert that constants aren't malformed
+const fn _check_consts() {
+    if ERROR_MESSAGE_SIZE > u8::MAX as usize || ERROR_LOG_SIZE > u8::MAX as usize {
+        core::panic!("The lengths have to fit into the single bytes they're serialized as!");
+    }
+    if SERIALIZED_SIZE > kv::MAX_VALUE_SIZE {
+        core::panic!("The whole log has to fit into the key-value store!");
+    }
+}
+const _: () = _check_consts();
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// A single error, as remembered by `ErrorLog`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorRecord {
+    /// When it happened, in microseconds since boot (see `get_timestamp_us()`)
+    pub timestamp_us: u64,
+    /// Whether it happened before the last reboot, i.e. it was restored from flash and the timestamp is from back then
+    pub previous_boot: bool,
+    /// The error and its context, as formatted by `Display`
+    pub message: String<ERROR_MESSAGE_SIZE>,
+}
+
+/// A ring buffer of the last `ERROR_LOG_SIZE` errors, so that they can be looked at long after they were shown,
+/// see the `errlog` command. It's in RAM, but it can be saved into flash and gets restored from it on boot.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ErrorLog {
+    records: Deque<ErrorRecord, ERROR_LOG_SIZE>,
+    /// Whether there are records the user hasn't listed yet, shown by an icon in the status bar
+    unseen: bool,
+}
+
+impl ErrorLog {
+    pub const fn new() -> Self {
+        ErrorLog { records: Deque::new(), unseen: false }
+    }
+
+    /// Remembers the error, forgetting the oldest one if the log is full. `now` is the current timestamp in microseconds.
+    pub fn record(&mut self, error: &WithContext, now: u64) {
+        let mut message = String::new();
+        // Cuts off whatever doesn't fit, a part of the message is better than none
+        let _ = core::fmt::write(&mut TruncatingString(&mut message), format_args!("{}", error));
+        self.push(ErrorRecord { timestamp_us: now, previous_boot: false, message });
+        self.unseen = true;
+    }
+
+    fn push(&mut self, record: ErrorRecord) {
+        if self.records.is_full() {
+            self.records.pop_front();
+        }
+        let _ = self.records.push_back(record); // Can't fail, we just made space
+    }
+
+    /// The records from the newest one
+    pub fn iter(&self) -> impl Iterator<Item = &ErrorRecord> {
+        self.records.iter().rev()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+        self.unseen = false;
+    }
+
+    /// Whether any error was recorded (or restored from flash) since the last `mark_seen()`
+    pub fn has_unseen(&self) -> bool {
+        self.unseen
+    }
+
+    /// Called once the records were listed to the user
+    pub fn mark_seen(&mut self) {
+        self.unseen = false;
+    }
+
+    /// Serializes the log from the oldest record: the magic and the record count, then each record's timestamp (little-endian),
+    /// the length of its message and the message itself.
+    fn to_bytes(&self) -> Vec<u8, SERIALIZED_SIZE> {
+        let mut bytes = Vec::new();
+        // Can't fail, see `_check_consts()`
+        let _ = bytes.extend_from_slice(&ERRLOG_MAGIC.to_le_bytes());
+        let _ = bytes.push(self.records.len() as u8);
+        for record in &self.records {
+            let _ = bytes.extend_from_slice(&record.timestamp_us.to_le_bytes());
+            let _ = bytes.push(record.message.len() as u8);
+            let _ = bytes.extend_from_slice(record.message.as_bytes());
+        }
+        bytes
+    }
+
+    /// Deserializes the log from the format produced by `to_bytes()`, marking all the records as from the previous boot.
+    /// Returns `None` if it's malformed, or of an old layout.
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let (magic, bytes) = bytes.split_first_chunk::<4>()?;
+        if u32::from_le_bytes(*magic) != ERRLOG_MAGIC {
+            return None;
+        }
+        let (&count, mut bytes) = bytes.split_first()?;
+
+        let mut log = ErrorLog::new();
+        for _ in 0..count {
+            let (timestamp, rest) = bytes.split_first_chunk::<8>()?;
+            let (&len, rest) = rest.split_first()?;
+            let (message, rest) = rest.split_at_checked(len as usize)?;
+            let message = String::try_from(core::str::from_utf8(message).ok()?).ok()?;
+            log.push(ErrorRecord { timestamp_us: u64::from_le_bytes(*timestamp), previous_boot: true, message });
+            bytes = rest;
+        }
+        log.unseen = !log.is_empty(); // They might be why the last boot ended
+        bytes.is_empty().then_some(log)
+    }
+
+    /// Saves the log into the key-value store (see `kv.rs`), replacing whatever was saved before.
+    pub fn save(&self) -> Result<(), CustomError> {
+        kv::set(ERRLOG_KEY, &self.to_bytes())?;
+        log_info!("Saved {} errors into flash", self.len());
+        Ok(())
+    }
+
+    /// Restores the log saved by `save()`, or returns `None` if there was none saved (or it's unreadable).
+    pub fn restore() -> Result<Option<Self>, CustomError> {
+        let mut buf = [0_u8; kv::MAX_VALUE_SIZE];
+        let Some(value) = kv::get(ERRLOG_KEY, &mut buf)? else {
+            return Ok(None);
+        };
+        let Some(log) = Self::from_bytes(value) else {
+            log_warn!("Saved error log is malformed or has an old layout, not restoring it");
+            return Ok(None);
+        };
+        log_info!("Restored {} errors from flash", log.len());
+        Ok(Some(log))
+    }
+
+    /// Forgets the saved log, returns `BadInput` if there was none.
+    pub fn remove_saved() -> Result<(), CustomError> {
+        match kv::remove(ERRLOG_KEY)? {
+            true => Ok(()),
+            false => Err(CE::BadInput),
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::{ErrorLog, ERROR_LOG_SIZE, ERROR_MESSAGE_SIZE};
+    use crate::custom_error::{CE, WithContext};
+
+    #[test]
+    fn forgets_the_oldest() {
+        let mut log = ErrorLog::new();
+        for i in 0..ERROR_LOG_SIZE as u64 + 2 {
+            log.record(&WithContext::from(CE::BadInput).with_context("pick", i), i);
+        }
+        assert_eq!(log.len(), ERROR_LOG_SIZE);
+        let newest = log.iter().next().unwrap();
+        assert_eq!(newest.timestamp_us, ERROR_LOG_SIZE as u64 + 1);
+        assert_eq!(newest.message.as_str(), "BadInput in pick (\"17\")");
+        assert_eq!(log.iter().last().unwrap().timestamp_us, 2);
+
+        assert!(log.has_unseen());
+        log.mark_seen();
+        assert!(!log.has_unseen());
+    }
+
+    #[test]
+    fn long_messages_get_cut_off() {
+        let mut log = ErrorLog::new();
+        log.record(&WithContext::from(CE::ParseIntError(crate::custom_error::IntErrorKindClone::InvalidDigit)).with_context("parse", "1234567890.12345x"), 0);
+        assert_eq!(log.iter().next().unwrap().message.len(), ERROR_MESSAGE_SIZE);
+    }
+
+    #[test]
+    fn serialization_round_trips() {
+        let mut log = ErrorLog::new();
+        log.record(&CE::MathOverflow.into(), 1_000);
+        log.record(&WithContext::from(CE::BadInput).with_context("sto", "ž"), 2_000);
+
+        let restored = ErrorLog::from_bytes(&log.to_bytes()).unwrap();
+        assert_eq!(restored.len(), 2);
+        assert!(restored.iter().all(|record| record.previous_boot));
+        assert!(restored.has_unseen());
+        assert!(restored.iter().zip(log.iter()).all(|(a, b)| a.timestamp_us == b.timestamp_us && a.message == b.message));
+
+        assert_eq!(ErrorLog::from_bytes(&ErrorLog::new().to_bytes()), Some(ErrorLog::new()));
+        assert_eq!(ErrorLog::from_bytes(&log.to_bytes()[..10]), None);
+        assert_eq!(ErrorLog::from_bytes(b"ERR0\x00"), None);
+    }
+}
@@ -0,0 +1,100 @@
+use rp2040_hal::{self as hal, pac};
+use cortex_m_rt::{exception, ExceptionFrame};
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoTextStyle},
+    prelude::*,
+    text::{Baseline, Text},
+};
+use heapless::String;
+#[cfg(not(feature = "ssd1327"))]
+use ssd1306::{Ssd1306, prelude::*};
+
+use crate::display::{FlushableDisplay, Palette};
+use crate::custom_error::CustomError; // Because we already have the `mod` in `main.rs`
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Frequency of the peripheral clock as set up by `init_clocks_and_plls()` in `main()`,
+/// we can't ask the clocks for it since they're long gone by the time we fault
+const PERIPHERAL_CLOCK_HZ: u32 = 125_000_000;
+/// Height of a line of the crash screen, the font is 10 px high
+const LINE_HEIGHT: i32 = 12;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Shows the registers stacked by the fault on the display and logs them, then hangs until a reset.
+///
+/// Triggered by real faults as well as by `breakpoint alt` without a debugger attached.
+/// The PC is the address of the faulting instruction, look it up with `addr2line` or in the disassembly.
+#[exception]
+unsafe fn HardFault(frame: &ExceptionFrame) -> ! {
+    // Straight to defmt, the log level and UART mirror of `log.rs` are of no use to us anymore
+    defmt::error!("HardFault! PC: {:#010X}, LR: {:#010X}, xPSR: {:#010X}", frame.pc(), frame.lr(), frame.xpsr());
+
+    // Nothing we can do if drawing fails too, the log will have to do
+    let _ = draw_crash_screen(frame);
+
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
+
+/// Sets up the I²C and the display from scratch and draws the registers on it.
+/// Whatever the main program was doing with them is forgotten, it isn't coming back anyway.
+fn draw_crash_screen(frame: &ExceptionFrame) -> Result<(), CustomError> {
+    // SAFETY: The main program will never run again, so nothing else owns the peripherals anymore
+    let mut peri = unsafe { pac::Peripherals::steal() };
+    let sio = hal::Sio::new(peri.SIO);
+    let pins = hal::gpio::Pins::new(peri.IO_BANK0, peri.PADS_BANK0, sio.gpio_bank0, &mut peri.RESETS);
+
+    // The same as in `main()`, resetting the I²C aborts a transfer the fault might have interrupted
+    let i2c = hal::I2C::i2c0(
+        peri.I2C0,
+        pins.gpio8.reconfigure(),
+        pins.gpio9.reconfigure(),
+        crate::I2C_FREQ,
+        &mut peri.RESETS,
+        hal::fugit::HertzU32::from_raw(PERIPHERAL_CLOCK_HZ),
+    );
+    #[cfg(not(feature = "ssd1327"))]
+    let mut disp = {
+        let iface = ssd1306::I2CDisplayInterface::new(i2c);
+        let mut disp = Ssd1306::new(iface, DisplaySize128x64, DisplayRotation::Rotate0)
+            .into_buffered_graphics_mode();
+        disp.init()?;
+        disp
+    };
+    #[cfg(feature = "ssd1327")]
+    let mut disp = {
+        let iface = ssd1306::I2CDisplayInterface::new_alternate_address(i2c);
+        let mut disp = crate::ssd1327::Ssd1327::new(iface);
+        disp.init()?;
+        disp
+    };
+    draw_registers(&mut disp, frame)
+}
+
+/// Draws the registers onto the display and flushes it, whichever display it is.
+fn draw_registers<D>(disp: &mut D, frame: &ExceptionFrame) -> Result<(), CustomError>
+where
+    D: FlushableDisplay,
+    CustomError: From<D::Error>,
+{
+    disp.clear(D::Color::BACKGROUND)?;
+
+    let style = MonoTextStyle::new(&FONT_6X10, D::Color::FOREGROUND);
+    let registers = [("PC", frame.pc()), ("LR", frame.lr()), ("xPSR", frame.xpsr())];
+    Text::with_baseline("HARD FAULT", Point::zero(), style, Baseline::Top)
+        .draw(disp)?;
+    for (i, (name, value)) in registers.into_iter().enumerate() {
+        let line: String<20> = heapless::format!("{:<5}{:#010X}", name, value)?;
+        Text::with_baseline(&line, Point::new(0, (i as i32 + 1) * LINE_HEIGHT), style, Baseline::Top)
+            .draw(disp)?;
+    }
+    Text::with_baseline("Reset to continue", Point::new(0, 4 * LINE_HEIGHT), style, Baseline::Top)
+        .draw(disp)?;
+
+    disp.flush_display()?;
+    Ok(())
+}
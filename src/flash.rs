@@ -0,0 +1,337 @@
+use rp2040_hal::rom_data;
+
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Address where the flash is mapped into memory by the XIP (execute-in-place) peripheral
+const XIP_BASE: u32 = 0x1000_0000;
+/// Total size of the flash chip on the Pico
+pub const FLASH_SIZE: u32 = 2048 * 1024;
+/// Smallest erasable unit of the flash
+pub const SECTOR_SIZE: u32 = 4096;
+/// Smallest programmable unit of the flash
+pub const PAGE_SIZE: usize = 256;
+/** Size of the area at the very end of the flash that we reserve for our own persistent storage.
+
+Please maintain consistency with `memory.x`, which shrinks the FLASH region by the same amount,
+so that the firmware itself can never get overwritten. */
+pub const STORAGE_SIZE: u32 = 256 * 1024;
+/// Offset (from the start of the flash, not an address!) of the storage area
+pub const STORAGE_OFFSET: u32 = FLASH_SIZE - STORAGE_SIZE;
+
+/// Offset of the region where the stack is persisted across reboots (see `persist.rs`), one sector large
+pub const STACK_REGION: u32 = STORAGE_OFFSET;
+/// Offset of the region where elements that don't fit into RAM are spilled (see `spill.rs`)
+pub const SPILL_REGION: u32 = STACK_REGION + SECTOR_SIZE;
+/// Size of the spill region, 16 sectors, split evenly between the workspaces
+pub const SPILL_SIZE: u32 = 64 * 1024;
+/// Offset of the region of the key-value store (see `kv.rs`), holding the settings and the named snapshots
+pub const KV_REGION: u32 = SPILL_REGION + SPILL_SIZE;
+/// Size of the key-value store's region, 32 sectors, so that the saves get spread over plenty of them
+pub const KV_SIZE: u32 = 128 * 1024;
+
+/// Block size and command for the ROM's erase function, the same as `rp2040-flash` uses.
+/// The ROM falls back to 4K sector erases by itself for the parts that aren't a whole block.
+const BLOCK_SIZE: u32 = 1 << 16;
+const BLOCK_ERASE_CMD: u8 = 0xD8;
+
+// HACK: Evaluate the block of code at compile time to assert that constants aren't malformed
+const fn _check_consts() {
+    if !STORAGE_SIZE.is_multiple_of(SECTOR_SIZE) || !STORAGE_OFFSET.is_multiple_of(SECTOR_SIZE) {
+        core::panic!("The storage area must consist of whole sectors!");
+    }
+    if SPILL_REGION + SPILL_SIZE > FLASH_SIZE {
+        core::panic!("The spill region doesn't fit into the storage area!");
+    }
+    if KV_REGION + KV_SIZE > FLASH_SIZE {
+        core::panic!("The key-value store's region doesn't fit into the storage area!");
+    }
+}
+const _: () = _check_consts();
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Pointers to the ROM functions we need, looked up beforehand.
+/// The lookup code itself lives in flash, so we can't do it while the flash is disconnected.
+struct FlashFunctionPointers {
+    connect_internal_flash: unsafe extern "C" fn(),
+    flash_exit_xip: unsafe extern "C" fn(),
+    flash_range_erase: unsafe extern "C" fn(u32, usize, u32, u8),
+    flash_range_program: unsafe extern "C" fn(u32, *const u8, usize),
+    flash_flush_cache: unsafe extern "C" fn(),
+    enter_xip: unsafe extern "C" fn(),
+}
+
+/// Erases the given range of the flash. Both `offset` and `len` must be multiples of `SECTOR_SIZE`,
+/// and the range must lie within the storage area.
+pub fn erase(offset: u32, len: u32) -> Result<(), CustomError> {
+    if !offset.is_multiple_of(SECTOR_SIZE) || !len.is_multiple_of(SECTOR_SIZE) {
+        return Err(CE::BadInput);
+    }
+    check_range(offset, len as usize)?;
+
+    // SAFETY: We checked that we're only touching the storage area, not our own code.
+    unsafe { write_flash(offset, len as usize, core::ptr::null(), true) };
+    Ok(())
+}
+
+/// Programs `data` into already erased flash. Both `offset` and the length of `data` must be multiples of `PAGE_SIZE`,
+/// and the range must lie within the storage area.
+///
+/// The data must NOT come from flash itself (e.g. a `const`), since the flash is disconnected while programming.
+pub fn program(offset: u32, data: &[u8]) -> Result<(), CustomError> {
+    if !(offset as usize).is_multiple_of(PAGE_SIZE) || !data.len().is_multiple_of(PAGE_SIZE) {
+        return Err(CE::BadInput);
+    }
+    check_range(offset, data.len())?;
+
+    // SAFETY: Same as above, and the slice is valid for the whole duration of the call.
+    unsafe { write_flash(offset, data.len(), data.as_ptr(), false) };
+    Ok(())
+}
+
+/// Copies the flash starting at `offset` into `buf`, read through the XIP cache.
+/// Unlike writing, reading may touch anything in the flash, not just the storage area.
+///
+/// It's a copy rather than a slice into the XIP window, which `erase()` and `program()` would change under the slice's feet.
+pub fn read(offset: u32, buf: &mut [u8]) -> Result<(), CustomError> {
+    if offset as usize + buf.len() > FLASH_SIZE as usize {
+        return Err(CE::BadInput);
+    }
+
+    // SAFETY: The whole flash is always mapped at XIP_BASE, and we checked we stay within it.
+    // Only we write into it, with interrupts disabled, so it can't change in the middle of the copy.
+    unsafe { core::ptr::copy_nonoverlapping((XIP_BASE + offset) as *const u8, buf.as_mut_ptr(), buf.len()) };
+    Ok(())
+}
+
+fn check_range(offset: u32, len: usize) -> Result<(), CustomError> {
+    if offset < STORAGE_OFFSET || offset as usize + len > FLASH_SIZE as usize {
+        return Err(CE::BadInput);
+    }
+    Ok(())
+}
+
+/// Looks up the ROM functions, copies boot2 into RAM and runs the RAM-resident part with interrupts disabled.
+///
+/// # Safety
+/// The caller must ensure that the range doesn't overlap the firmware, and that `data` is valid for `len` bytes if not erasing.
+unsafe fn write_flash(offset: u32, len: usize, data: *const u8, erase: bool) {
+    // The boot2 has to be in RAM too, because it's the thing that sets up the fast XIP mode again after we're done.
+    // Without it, we'd have to fall back to the ROM's slow generic XIP mode. We need it word-aligned, hence the u32-s.
+    let mut boot2 = [0_u32; 64];
+    // SAFETY: Both are exactly 256 bytes large, and the source is a plain byte array.
+    unsafe {
+        core::ptr::copy_nonoverlapping(crate::BOOT2.as_ptr(), boot2.as_mut_ptr() as *mut u8, crate::BOOT2.len());
+    }
+
+    let ptrs = FlashFunctionPointers {
+        connect_internal_flash: rom_data::connect_internal_flash::ptr(),
+        flash_exit_xip: rom_data::flash_exit_xip::ptr(),
+        flash_range_erase: rom_data::flash_range_erase::ptr(),
+        flash_range_program: rom_data::flash_range_program::ptr(),
+        flash_flush_cache: rom_data::flash_flush_cache::ptr(),
+        // SAFETY: boot2 is a valid Thumb function (hence the +1) that returns to its caller if it was called with a return address.
+        // This is the same trick as `rp2040-flash` and the Pico SDK do.
+        enter_xip: unsafe { core::mem::transmute::<usize, unsafe extern "C" fn()>(boot2.as_ptr() as usize + 1) },
+    };
+
+    // We don't do dualcore, so disabling interrupts is enough to make sure nobody else touches the flash.
+    cortex_m::interrupt::free(|_| {
+        // SAFETY: Forwarded from our caller, plus the pointers are valid ROM (or RAM) functions.
+        unsafe { write_flash_inner(offset, len, data, erase, &ptrs) };
+    });
+}
+
+/// The part that runs while the flash is disconnected, hence it must live in RAM, and must not call anything in flash.
+/// That's why we only use plain arithmetics and the pre-looked-up function pointers here, no `core` helpers whatsoever.
+#[inline(never)]
+#[unsafe(link_section = ".data.ram_func")]
+unsafe fn write_flash_inner(offset: u32, len: usize, data: *const u8, erase: bool, ptrs: &FlashFunctionPointers) {
+    // SAFETY: Forwarded from our caller. This is the sequence the datasheet (section 2.8.3.1.3) prescribes.
+    unsafe {
+        (ptrs.connect_internal_flash)();
+        (ptrs.flash_exit_xip)();
+        if erase {
+            (ptrs.flash_range_erase)(offset, len, BLOCK_SIZE, BLOCK_ERASE_CMD);
+        } else {
+            (ptrs.flash_range_program)(offset, data, len);
+        }
+        (ptrs.flash_flush_cache)(); // So that we don't read stale data through the XIP cache afterwards
+        (ptrs.enter_xip)();
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// The operations above as a trait, so that what's built on them can be tested on the host against `RamFlash`
+pub trait Flash {
+    /// See `read()`
+    fn read(&self, offset: u32, buf: &mut [u8]) -> Result<(), CustomError>;
+    /// See `erase()`
+    fn erase(&mut self, offset: u32, len: u32) -> Result<(), CustomError>;
+    /// See `program()`
+    fn program(&mut self, offset: u32, data: &[u8]) -> Result<(), CustomError>;
+}
+
+/// The flash chip on the Pico, through the functions above
+pub struct OnboardFlash;
+
+impl Flash for OnboardFlash {
+    fn read(&self, offset: u32, buf: &mut [u8]) -> Result<(), CustomError> {
+        read(offset, buf)
+    }
+
+    fn erase(&mut self, offset: u32, len: u32) -> Result<(), CustomError> {
+        erase(offset, len)
+    }
+
+    fn program(&mut self, offset: u32, data: &[u8]) -> Result<(), CustomError> {
+        program(offset, data)
+    }
+}
+
+/// The storage area in RAM, behaving like the flash: erasing sets all the bits, programming can only clear them.
+///
+/// The power can be cut after a number of programmed bytes, see `cut_power_after()`.
+#[cfg(test)]
+#[derive(Clone)]
+pub struct RamFlash {
+    storage: std::vec::Vec<u8>,
+    /// How many more bytes get programmed before the power's cut, `None` for never
+    budget: Option<usize>,
+}
+
+#[cfg(test)]
+impl RamFlash {
+    /// An erased storage area
+    pub fn new() -> Self {
+        RamFlash { storage: std::vec![0xFF; STORAGE_SIZE as usize], budget: None }
+    }
+
+    /// Programs only this many more bytes, the one going over gets cut short and returns `Cancelled`,
+    /// as does everything after it until `restore_power()`.
+    pub fn cut_power_after(&mut self, bytes: usize) {
+        self.budget = Some(bytes);
+    }
+
+    pub fn restore_power(&mut self) {
+        self.budget = None;
+    }
+
+    fn range(offset: u32, len: usize) -> Result<core::ops::Range<usize>, CustomError> {
+        check_range(offset, len)?;
+        let start = (offset - STORAGE_OFFSET) as usize;
+        Ok(start..(start + len))
+    }
+}
+
+#[cfg(test)]
+impl Flash for RamFlash {
+    fn read(&self, offset: u32, buf: &mut [u8]) -> Result<(), CustomError> {
+        buf.copy_from_slice(&self.storage[Self::range(offset, buf.len())?]);
+        Ok(())
+    }
+
+    fn erase(&mut self, offset: u32, len: u32) -> Result<(), CustomError> {
+        if !offset.is_multiple_of(SECTOR_SIZE) || !len.is_multiple_of(SECTOR_SIZE) {
+            return Err(CE::BadInput);
+        }
+        if self.budget == Some(0) {
+            return Err(CE::Cancelled);
+        }
+        self.storage[Self::range(offset, len as usize)?].fill(0xFF);
+        Ok(())
+    }
+
+    fn program(&mut self, offset: u32, data: &[u8]) -> Result<(), CustomError> {
+        if !(offset as usize).is_multiple_of(PAGE_SIZE) || !data.len().is_multiple_of(PAGE_SIZE) {
+            return Err(CE::BadInput);
+        }
+        let range = Self::range(offset, data.len())?;
+        let len = self.budget.map_or(data.len(), |budget| budget.min(data.len()));
+        for (cell, &byte) in self.storage[range].iter_mut().zip(&data[..len]) {
+            *cell &= byte;
+        }
+        match self.budget.as_mut() {
+            Some(budget) if *budget < data.len() => {
+                *budget = 0;
+                Err(CE::Cancelled)
+            },
+            Some(budget) => {
+                *budget -= data.len();
+                Ok(())
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Buffers bytes into pages and programs them one by one, so that we don't need a whole sector worth of RAM.
+/// The region has to be erased beforehand.
+///
+/// It may start in the middle of a page, the bytes before it get programmed as `0xFF`, which leaves them as they were.
+pub struct PageWriter<'f, F: Flash> {
+    flash: &'f mut F,
+    offset: u32,
+    end: u32,
+    buf: [u8; PAGE_SIZE],
+    pos: usize,
+}
+
+impl<'f, F: Flash> PageWriter<'f, F> {
+    /// Creates a writer for `len` bytes of the flash starting at `offset`.
+    pub fn new(flash: &'f mut F, offset: u32, len: u32) -> Self {
+        let pos = offset as usize % PAGE_SIZE;
+        PageWriter {
+            flash,
+            offset: offset - pos as u32,
+            end: offset + len,
+            buf: [0xFF; PAGE_SIZE], // Erased flash reads as all ones, so that's our padding
+            pos,
+        }
+    }
+
+    /// Appends the bytes, programming each page as soon as it fills up.
+    /// Returns `CapacityError` if it'd go past the end of the region.
+    pub fn write(&mut self, mut data: &[u8]) -> Result<(), CustomError> {
+        while !data.is_empty() {
+            if self.offset >= self.end {
+                return Err(CE::CapacityError);
+            }
+
+            let n = core::cmp::min(PAGE_SIZE - self.pos, data.len());
+            self.buf[self.pos..(self.pos + n)].copy_from_slice(&data[..n]);
+            self.pos += n;
+            data = &data[n..];
+
+            if self.pos == PAGE_SIZE {
+                self.flush_page()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Programs the last, partially filled page (if any), padding it with `0xFF`.
+    pub fn finish(mut self) -> Result<(), CustomError> {
+        if self.pos != 0 {
+            self.flush_page()?;
+        }
+        Ok(())
+    }
+
+    fn flush_page(&mut self) -> Result<(), CustomError> {
+        self.flash.program(self.offset, &self.buf)?;
+        self.offset += PAGE_SIZE as u32;
+        self.buf = [0xFF; PAGE_SIZE];
+        self.pos = 0;
+        Ok(())
+    }
+}
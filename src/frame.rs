@@ -0,0 +1,262 @@
+use embedded_graphics::{
+    prelude::*,
+    primitives::Rectangle,
+};
+use display_interface::DisplayError;
+
+use crate::display::{FlushableDisplay, Palette, Panel};
+use crate::get_timestamp_us;
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE, // Short type alias
+    DisplayErrorClone,
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Shortest time between two flushes, in microseconds (30 frames per second). A whole SSD1306 frame takes about 25 ms over I²C.
+const FRAME_INTERVAL_US: u64 = 33_333;
+/// Size of each of the frame buffers, enough for the panel the firmware is built for
+#[cfg(not(feature = "ssd1327"))]
+const BUFFER_SIZE: usize = 128 * 64 / 8;
+#[cfg(feature = "ssd1327")]
+const BUFFER_SIZE: usize = 128 * 128 / 2;
+/// How many times a frame that failed to be sent over the bus gets retried, recovering the bus and the panel before each retry
+const MAX_RECOVERY_ATTEMPTS: u32 = 3;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Wraps the panel, so that the widgets can flush as often as they like: the flushes within a frame interval get coalesced
+/// into one, and only the pixels that actually changed since the last frame reach the panel.
+///
+/// There are two frame buffers: the back one gets drawn into, the front one mirrors what the panel shows.
+/// When a frame is due, the differences between them get drawn onto the panel's own buffer, which is then flushed,
+/// and the back buffer becomes the new front one. Redrawing a widget with the same content thus costs no bus traffic at all,
+/// even though it marks the whole area as changed in the panel's driver.
+///
+/// A held back frame gets sent by `tick()` once the interval is over, or by `flush_now()` before waiting for input.
+///
+/// If sending a frame fails on the bus, e.g. after a glitch on the wires, it recovers the bus (see `set_bus_recovery()`),
+/// initializes the panel again and retries, up to `MAX_RECOVERY_ATTEMPTS` times before giving up with the error.
+pub struct FrameScheduler<D: Panel> {
+    panel: D,
+    back: [u8; BUFFER_SIZE],
+    front: [u8; BUFFER_SIZE],
+    /// Whether the back buffer was flushed since the last frame
+    pending: bool,
+    /// Timestamp of the last frame, in microseconds
+    last_frame: u64,
+    /// Whether the front buffer can't be trusted to mirror the panel, e.g. after initializing it again, so that the next frame is sent whole
+    full_redraw: bool,
+    /// Releases a stuck bus before the panel gets initialized again, see `i2c_recovery::recover_i2c0()`
+    bus_recovery: Option<fn()>,
+    /// The settings of the panel, to be applied again after initializing it again; the contrast is `None` until it's set
+    contrast: Option<u8>,
+    inverted: bool,
+    on: bool,
+}
+
+impl<D: Panel> FrameScheduler<D> {
+    /// Takes over the panel, whose buffer has to be cleared already, as `init()` of both drivers does.
+    pub fn new(panel: D) -> Self {
+        let size = panel.bounding_box().size;
+        assert!(
+            (size.width * size.height) as usize * D::Color::BITS <= BUFFER_SIZE * 8,
+            "The frame buffers are too small for the panel"
+        );
+        // Both buffers start out as the cleared panel
+        let background = D::Color::BACKGROUND.to_bits();
+        let mut byte = 0;
+        for shift in (0..8).step_by(D::Color::BITS) {
+            byte |= background << shift;
+        }
+        FrameScheduler {
+            panel,
+            back: [byte; BUFFER_SIZE],
+            front: [byte; BUFFER_SIZE],
+            pending: false,
+            last_frame: 0,
+            full_redraw: false,
+            bus_recovery: None,
+            contrast: None,
+            inverted: false,
+            on: true, // Both drivers turn the panel on in `init()`
+        }
+    }
+
+    /// Sets what releases a stuck bus when sending a frame fails, before the panel gets initialized again.
+    /// Without it, only the panel gets initialized again.
+    pub fn set_bus_recovery(&mut self, bus_recovery: fn()) {
+        self.bus_recovery = Some(bus_recovery);
+    }
+
+    /// Sends the held back frame, if there's one and the frame interval is over. Meant to be called repeatedly while polling for input.
+    pub fn tick(&mut self, now: u64) -> Result<(), CustomError> {
+        if self.pending && now.saturating_sub(self.last_frame) >= FRAME_INTERVAL_US {
+            self.present(now)?;
+        }
+        Ok(())
+    }
+
+    /// When `tick()` is due to send the held back frame, `None` if there's none
+    pub fn next_frame_at(&self) -> Option<u64> {
+        self.pending.then_some(self.last_frame + FRAME_INTERVAL_US)
+    }
+
+    /// Sends the frame, recovering from the errors on the bus if there are any, and makes the back buffer the new front one.
+    fn present(&mut self, now: u64) -> Result<(), CustomError> {
+        let mut attempt = 0;
+        while let Err(e) = self.send_frame() {
+            if e != CE::DisplayError(DisplayErrorClone::BusWriteError) || attempt == MAX_RECOVERY_ATTEMPTS {
+                return Err(e);
+            }
+            attempt += 1;
+            log_warn!("Failed to send a frame to the display, recovering (attempt {} of {})", attempt, MAX_RECOVERY_ATTEMPTS);
+            // If it fails, the retry most likely does too, and then we try recovering again
+            if let Err(e) = self.recover() {
+                log_warn!("Failed to recover the display: {:?}", e);
+            }
+        }
+
+        self.front.copy_from_slice(&self.back);
+        self.pending = false;
+        self.full_redraw = false;
+        self.last_frame = now;
+        Ok(())
+    }
+
+    /// Draws the pixels that differ between the buffers (or all of them, if `full_redraw` is set) onto the panel and flushes it.
+    fn send_frame(&mut self) -> Result<(), CustomError> {
+        let width = self.panel.bounding_box().size.width as usize;
+        let bits = D::Color::BITS;
+        let mask = (1_u8 << bits) - 1;
+        let per_byte = 8 / bits;
+        let full_redraw = self.full_redraw;
+
+        let (back, front) = (&self.back, &self.front);
+        let changed = back.iter().zip(front).enumerate()
+            .filter(|(_, (b, f))| full_redraw || b != f) // Most of the bytes are the same, we skip them whole
+            .flat_map(|(i, (&b, &f))| (0..per_byte).filter_map(move |j| {
+                let shift = j * bits;
+                let color = (b >> shift) & mask;
+                (full_redraw || color != (f >> shift) & mask).then(|| {
+                    let pixel = i * per_byte + j;
+                    Pixel(Point::new((pixel % width) as i32, (pixel / width) as i32), D::Color::from_bits(color))
+                })
+            }));
+        self.panel.draw_iter(changed)?;
+        self.panel.flush_display()
+    }
+
+    /// Releases the bus, initializes the panel again and restores its settings. The next frame then gets sent whole,
+    /// since whatever the panel showed might be gone.
+    fn recover(&mut self) -> Result<(), CustomError> {
+        if let Some(bus_recovery) = self.bus_recovery {
+            bus_recovery();
+        }
+        self.full_redraw = true;
+        self.panel.reinit()?;
+        if let Some(contrast) = self.contrast {
+            self.panel.set_contrast(contrast)?;
+        }
+        if self.inverted {
+            self.panel.set_inverted(true)?;
+        }
+        if !self.on {
+            self.panel.set_on(false)?;
+        }
+        Ok(())
+    }
+}
+
+impl<D: Panel> Dimensions for FrameScheduler<D> {
+    fn bounding_box(&self) -> Rectangle {
+        self.panel.bounding_box()
+    }
+}
+
+impl<D: Panel> DrawTarget for FrameScheduler<D> {
+    type Color = D::Color;
+    type Error = DisplayError;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let size = self.panel.bounding_box().size;
+        let bits = D::Color::BITS;
+        let mask = (1_u8 << bits) - 1;
+        for Pixel(point, color) in pixels {
+            // Whatever's off the display is silently dropped, the same as the drivers do
+            let (Ok(x), Ok(y)) = (u32::try_from(point.x), u32::try_from(point.y)) else { continue };
+            if x >= size.width || y >= size.height {
+                continue;
+            }
+
+            let offset = (y * size.width + x) as usize * bits;
+            let shift = offset % 8;
+            let byte = &mut self.back[offset / 8];
+            *byte = (*byte & !(mask << shift)) | (color.to_bits() << shift);
+        }
+        Ok(())
+    }
+}
+
+impl<D: Panel> FlushableDisplay for FrameScheduler<D> {
+    /// Sends the frame if the frame interval since the last one is over, otherwise holds it back for `tick()` or `flush_now()`.
+    fn flush_display(&mut self) -> Result<(), CustomError> {
+        self.pending = true;
+        self.tick(get_timestamp_us())
+    }
+
+    fn flush_now(&mut self) -> Result<(), CustomError> {
+        if self.pending {
+            self.present(get_timestamp_us())?;
+        }
+        Ok(())
+    }
+
+    fn flush_frame(&mut self) -> Result<(), CustomError> {
+        while get_timestamp_us().saturating_sub(self.last_frame) < FRAME_INTERVAL_US {
+            core::hint::spin_loop();
+        }
+        self.present(get_timestamp_us())
+    }
+}
+
+/// The settings are remembered, so that they can be applied again after the panel gets initialized again, see `recover()`
+impl<D: Panel> Panel for FrameScheduler<D> {
+    fn set_contrast(&mut self, contrast: u8) -> Result<(), CustomError> {
+        self.contrast = Some(contrast);
+        self.panel.set_contrast(contrast)
+    }
+
+    fn set_inverted(&mut self, inverted: bool) -> Result<(), CustomError> {
+        self.inverted = inverted;
+        self.panel.set_inverted(inverted)
+    }
+
+    fn set_on(&mut self, on: bool) -> Result<(), CustomError> {
+        self.on = on;
+        self.panel.set_on(on)
+    }
+
+    fn reinit(&mut self) -> Result<(), CustomError> {
+        self.recover()
+    }
+
+    /// Read from the front buffer, so that a frame held back doesn't show up before it's sent
+    fn pixel(&self, point: Point) -> Option<Self::Color> {
+        let size = self.panel.bounding_box().size;
+        let (Ok(x), Ok(y)) = (u32::try_from(point.x), u32::try_from(point.y)) else { return None };
+        if x >= size.width || y >= size.height {
+            return None;
+        }
+
+        let bits = D::Color::BITS;
+        let offset = (y * size.width + x) as usize * bits;
+        let mask = (1_u8 << bits) - 1;
+        Some(D::Color::from_bits((self.front[offset / 8] >> (offset % 8)) & mask))
+    }
+}
@@ -0,0 +1,305 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::{RefCell, UnsafeCell};
+use core::mem::MaybeUninit;
+use core::ptr::{self, NonNull};
+use cortex_m::interrupt::{self, Mutex};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Size of the heap, taken out of the RAM statically, so that it shows up in the linker's memory usage like everything else
+pub const HEAP_SIZE: usize = 32 * 1024;
+/// Granularity of the blocks: every block starts at a multiple of it and its size is a multiple of it, so that a free block's
+/// header always fits, and no leftover is ever too small to be a free block by itself
+const BLOCK_ALIGN: usize = core::mem::size_of::<FreeBlock>();
+
+// HACK: Evaluate the block of code at compile time to assert that constants aren't malformed
+const fn _check_consts() {
+    if !BLOCK_ALIGN.is_power_of_two() || BLOCK_ALIGN < core::mem::align_of::<FreeBlock>() {
+        core::panic!("The blocks must be aligned for their headers!");
+    }
+    if !HEAP_SIZE.is_multiple_of(BLOCK_ALIGN) {
+        core::panic!("The heap must be a whole number of blocks!");
+    }
+}
+const _: () = _check_consts();
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// The header written into the start of each free block, linking them into a list sorted by address
+struct FreeBlock {
+    size: usize,
+    next: Option<NonNull<FreeBlock>>,
+}
+
+/// The memory of the heap, aligned for the block headers (on the host too, where they're twice as large)
+#[repr(C, align(16))]
+struct Arena(UnsafeCell<[MaybeUninit<u8>; HEAP_SIZE]>);
+
+// SAFETY: Only ever touched through `Heap`, with interrupts disabled
+unsafe impl Sync for Arena {}
+
+static ARENA: Arena = Arena(UnsafeCell::new([MaybeUninit::uninit(); HEAP_SIZE]));
+
+/// A first-fit allocator over a free list, for the `alloc` feature.
+///
+/// Simple rather than fast: allocating walks the free blocks until one fits, freeing puts the block back in order
+/// and merges it with its free neighbours, so that the heap doesn't fragment over time more than it has to.
+struct Heap {
+    /// The first free block, `None` before `init()` or once the heap is all used up
+    free: Option<NonNull<FreeBlock>>,
+    /// Bytes handed out, including the rounding up to whole blocks
+    used: usize,
+}
+
+// SAFETY: The pointers only point into its memory (`ARENA`, which lives forever), and the heap is only touched with interrupts disabled
+unsafe impl Send for Heap {}
+
+impl Heap {
+    const fn empty() -> Self {
+        Heap { free: None, used: 0 }
+    }
+
+    /// Hands `size` bytes at `memory` over as one free block.
+    ///
+    /// # Safety
+    /// Must be called only once, before any allocation. The memory has to be aligned to `BLOCK_ALIGN`, a whole number of blocks long,
+    /// and used by nothing else for as long as the heap is.
+    unsafe fn init(&mut self, memory: *mut u8, size: usize) {
+        let block = memory as *mut FreeBlock;
+        // SAFETY: The memory is aligned for the header and big enough for it, and nothing else uses it
+        unsafe { block.write(FreeBlock { size, next: None }) };
+        self.free = NonNull::new(block);
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let size = layout.size().max(1).next_multiple_of(BLOCK_ALIGN);
+        let align = layout.align().max(BLOCK_ALIGN);
+
+        let mut prev: Option<NonNull<FreeBlock>> = None;
+        let mut current = self.free;
+        while let Some(block) = current {
+            // SAFETY: Every block in the list is a valid header inside the arena
+            let FreeBlock { size: block_size, next } = unsafe { block.as_ptr().read() };
+            let start = block.as_ptr() as usize;
+            let end = start + block_size;
+            // Both are multiples of `BLOCK_ALIGN`, so the front padding is either nothing or a whole free block
+            let aligned = start.next_multiple_of(align);
+
+            if aligned + size <= end {
+                // The part after the allocation stays free, or the next block takes its place
+                let after = if aligned + size < end {
+                    let tail = (aligned + size) as *mut FreeBlock;
+                    // SAFETY: The tail lies inside the free block, and it's at least `BLOCK_ALIGN` large
+                    unsafe { tail.write(FreeBlock { size: end - (aligned + size), next }) };
+                    NonNull::new(tail)
+                } else {
+                    next
+                };
+                // So does the padding before it, or the previous block gets linked to what's after
+                if aligned > start {
+                    // SAFETY: Same as above, the header stays where it was, only smaller
+                    unsafe { block.as_ptr().write(FreeBlock { size: aligned - start, next: after }) };
+                } else {
+                    self.link(prev, after);
+                }
+                self.used += size;
+                return aligned as *mut u8;
+            }
+            prev = current;
+            current = next;
+        }
+        ptr::null_mut()
+    }
+
+    /// # Safety
+    /// `ptr` has to come from `allocate()` with the same layout, and must not be freed twice.
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let size = layout.size().max(1).next_multiple_of(BLOCK_ALIGN);
+        let start = ptr as usize;
+        self.used -= size;
+
+        // Finds the free blocks around it, the list is sorted by address
+        let mut prev: Option<NonNull<FreeBlock>> = None;
+        let mut next = self.free;
+        while let Some(block) = next && (block.as_ptr() as usize) < start {
+            prev = next;
+            // SAFETY: Every block in the list is a valid header inside the arena
+            next = unsafe { block.as_ref().next };
+        }
+
+        let freed = ptr as *mut FreeBlock;
+        // SAFETY: The memory is ours again and it's aligned and large enough for the header, see `allocate()`
+        unsafe { freed.write(FreeBlock { size, next }) };
+        let mut freed = NonNull::new(freed).expect("An allocated pointer isn't null");
+        self.link(prev, Some(freed));
+
+        // SAFETY: Both neighbours are valid headers, and we hold the only references into the list
+        unsafe {
+            if let Some(next) = next && start + size == next.as_ptr() as usize {
+                let next = next.as_ptr().read();
+                *freed.as_mut() = FreeBlock { size: size + next.size, next: next.next };
+            }
+            if let Some(mut prev) = prev && prev.as_ptr() as usize + prev.as_ref().size == start {
+                let freed = freed.as_ptr().read();
+                let prev = prev.as_mut();
+                *prev = FreeBlock { size: prev.size + freed.size, next: freed.next };
+            }
+        }
+    }
+
+    /// Makes the block after `prev` (or the first one, if there's no `prev`) be `block`
+    fn link(&mut self, prev: Option<NonNull<FreeBlock>>, block: Option<NonNull<FreeBlock>>) {
+        match prev {
+            // SAFETY: Every block in the list is a valid header inside the arena
+            Some(mut prev) => unsafe { prev.as_mut().next = block },
+            None => self.free = block,
+        }
+    }
+}
+
+/// The global allocator, see `Heap`
+pub struct Allocator {
+    heap: Mutex<RefCell<Heap>>,
+}
+
+#[cfg_attr(not(test), global_allocator)] // The host tests keep the standard library's one
+static ALLOCATOR: Allocator = Allocator { heap: Mutex::new(RefCell::new(Heap::empty())) };
+
+// SAFETY: The heap is only touched with interrupts disabled, and we don't do dualcore
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        interrupt::free(|cs| self.heap.borrow(cs).borrow_mut().allocate(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // SAFETY: Forwarded from our caller
+        interrupt::free(|cs| unsafe { self.heap.borrow(cs).borrow_mut().deallocate(ptr, layout) });
+    }
+}
+
+/// Sets up the heap, has to be called once at the start of `main()`, before anything allocates.
+pub fn init() {
+    // SAFETY: Called once, before any allocation, see above
+    interrupt::free(|cs| unsafe { ALLOCATOR.heap.borrow(cs).borrow_mut().init(ARENA.0.get().cast(), HEAP_SIZE) });
+    log_debug!("Heap of {} bytes initialized", HEAP_SIZE);
+}
+
+/// Bytes of the heap in use, including the rounding up of each allocation
+pub fn used() -> usize {
+    interrupt::free(|cs| ALLOCATOR.heap.borrow(cs).borrow().used)
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Room for eight blocks of 16 bytes (the size of a header on the host)
+    const TEST_SIZE: usize = 128;
+
+    /// Aligned beyond the blocks, so that where an allocation aligned like that lands is known
+    #[repr(C, align(64))]
+    struct TestArena([u8; TEST_SIZE]);
+
+    /// A heap over the arena, which has to outlive it
+    fn heap(arena: &mut TestArena) -> Heap {
+        let mut heap = Heap::empty();
+        // SAFETY: The arena is aligned and sized for the blocks, and the tests only touch it through the heap
+        unsafe { heap.init(arena.0.as_mut_ptr(), TEST_SIZE) };
+        heap
+    }
+
+    /// Offsets from the arena's start and sizes of the free blocks, in order
+    fn free_blocks(heap: &Heap, arena: &TestArena) -> Vec<(usize, usize)> {
+        let mut blocks = Vec::new();
+        let mut current = heap.free;
+        while let Some(block) = current {
+            // SAFETY: Every block in the list is a valid header inside the arena
+            let block_ref = unsafe { block.as_ref() };
+            blocks.push((block.as_ptr() as usize - arena.0.as_ptr() as usize, block_ref.size));
+            current = block_ref.next;
+        }
+        blocks
+    }
+
+    fn bytes(size: usize) -> Layout {
+        Layout::from_size_align(size, 1).unwrap()
+    }
+
+    #[test]
+    fn allocates_and_frees() {
+        let mut arena = TestArena([0; TEST_SIZE]);
+        let mut heap = heap(&mut arena);
+        let a = heap.allocate(bytes(10));
+        let b = heap.allocate(bytes(20));
+        assert!(!a.is_null() && !b.is_null());
+        assert_eq!(b as usize - a as usize, BLOCK_ALIGN, "Rounded up to a whole block");
+        assert_eq!(heap.used, BLOCK_ALIGN * 3);
+        // SAFETY: Both come from `allocate()` with these layouts
+        unsafe {
+            ptr::write_bytes(a, 0xAA, 10);
+            ptr::write_bytes(b, 0xBB, 20);
+            assert_eq!(*a.add(9), 0xAA, "The allocations don't overlap");
+            heap.deallocate(a, bytes(10));
+            heap.deallocate(b, bytes(20));
+        }
+        assert_eq!(heap.used, 0);
+        assert_eq!(free_blocks(&heap, &arena), [(0, TEST_SIZE)]);
+    }
+
+    #[test]
+    fn coalesces_free_neighbours() {
+        let mut arena = TestArena([0; TEST_SIZE]);
+        let mut heap = heap(&mut arena);
+        let [a, b, c] = [(); 3].map(|_| heap.allocate(bytes(BLOCK_ALIGN)));
+        // SAFETY: All come from `allocate()` with this layout, each is freed once
+        unsafe {
+            heap.deallocate(a, bytes(BLOCK_ALIGN));
+            heap.deallocate(c, bytes(BLOCK_ALIGN));
+            // `c` merged with the rest after it, `b` still separates it from `a`
+            assert_eq!(free_blocks(&heap, &arena), [(0, BLOCK_ALIGN), (2 * BLOCK_ALIGN, TEST_SIZE - 2 * BLOCK_ALIGN)]);
+            heap.deallocate(b, bytes(BLOCK_ALIGN));
+        }
+        assert_eq!(free_blocks(&heap, &arena), [(0, TEST_SIZE)]);
+        assert!(!heap.allocate(bytes(TEST_SIZE)).is_null(), "The whole heap fits again");
+    }
+
+    #[test]
+    fn aligns_beyond_the_blocks() {
+        let mut arena = TestArena([0; TEST_SIZE]);
+        let mut heap = heap(&mut arena);
+        let small = heap.allocate(bytes(1));
+        let layout = Layout::from_size_align(8, 4 * BLOCK_ALIGN).unwrap();
+        let aligned = heap.allocate(layout);
+        assert!((aligned as usize).is_multiple_of(4 * BLOCK_ALIGN));
+        // The padding before it stays free, and gets used by the next allocation that fits
+        assert_eq!(free_blocks(&heap, &arena).len(), 2);
+        let padding = heap.allocate(bytes(BLOCK_ALIGN));
+        assert!(padding > small && padding < aligned);
+        // SAFETY: All come from `allocate()` with these layouts, each is freed once
+        unsafe {
+            heap.deallocate(aligned, layout);
+            heap.deallocate(small, bytes(1));
+            heap.deallocate(padding, bytes(BLOCK_ALIGN));
+        }
+        assert_eq!(free_blocks(&heap, &arena), [(0, TEST_SIZE)]);
+    }
+
+    #[test]
+    fn runs_out_of_memory() {
+        let mut arena = TestArena([0; TEST_SIZE]);
+        let mut heap = heap(&mut arena);
+        assert!(heap.allocate(bytes(TEST_SIZE + 1)).is_null());
+        let half = heap.allocate(bytes(TEST_SIZE / 2));
+        let rest = heap.allocate(bytes(TEST_SIZE / 2));
+        assert!(!rest.is_null());
+        assert!(heap.allocate(bytes(1)).is_null(), "All used up");
+        assert_eq!(heap.free, None);
+        // SAFETY: It comes from `allocate()` with this layout
+        unsafe { heap.deallocate(half, bytes(TEST_SIZE / 2)) };
+        assert!(heap.allocate(bytes(TEST_SIZE / 2 + 1)).is_null(), "Too large for the half that's free");
+        assert_eq!(heap.allocate(bytes(TEST_SIZE / 2)), half);
+    }
+}
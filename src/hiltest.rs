@@ -0,0 +1,226 @@
+use core::cell::RefCell;
+use heapless::String;
+
+use crate::display::{NullDisplay, Panel};
+use crate::stack::{CustomStack, CustomStackBuilder, NumberFormat};
+use crate::decfix::DecimalFixed;
+use crate::commands::{self, Context};
+use crate::menu;
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE, // Short type alias
+    IntErrorKindClone,
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Every line of the report starts with it, so that a CI job can pick them out from the rest of the UART output
+pub const REPORT_PREFIX: &str = "HILTEST";
+/// Size of the String-s the steps format numbers into for comparing
+const FORMAT_BUFFER_SIZE: usize = 32;
+
+/// The names of the steps, in the order of the results of `run_all()`
+pub const STEP_NAMES: [&str; 7] = ["push", "pop", "arith", "format", "draw", "errors", "dispatch"];
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Runs the whole script, see `STEP_NAMES` for what the steps are.
+///
+/// Unlike `selftest`, it's meant for a CI job with a real Pico attached rather than for the user,
+/// so it doesn't wait for anything and all the steps are deterministic.
+/// Everything but the `draw` and `dispatch` steps works on a headless stack of its own, the user's stays intact.
+/// The `draw` step leaves its own stack on the display, it's the caller's job to redraw it.
+pub fn run_all<D>(ctx: &mut Context<'_, '_, D>) -> [Result<(), CustomError>; STEP_NAMES.len()]
+where
+    D: Panel,
+{
+    [push(), pop(), arith(), format(), draw(ctx.disp_refcell), errors(), dispatch(ctx)]
+}
+
+/// Returns an error (and logs what failed) if the condition doesn't hold.
+fn check(condition: bool, what: &str) -> Result<(), CustomError> {
+    if condition {
+        Ok(())
+    } else {
+        log_error!("HIL test check failed: {}", what);
+        Err(CE::Other)
+    }
+}
+
+/// Shorthand for parsing with the default exponent, the same as what the user types in
+fn num(s: &str) -> Result<DecimalFixed, CustomError> {
+    DecimalFixed::parse_str(s, None)
+}
+
+/// Checks that a number got formatted as exactly `expected`, logging both if it didn't
+fn check_formatted(formatted: Result<String<FORMAT_BUFFER_SIZE>, core::fmt::Error>, expected: &str) -> Result<(), CustomError> {
+    let formatted = formatted?;
+    if formatted != expected {
+        log_error!("HIL test check failed: formatted as {}, expected {}", formatted.as_str(), expected);
+        return Err(CE::Other);
+    }
+    Ok(())
+}
+
+fn headless_stack<'a>() -> CustomStack<'a, DecimalFixed, NullDisplay> {
+    CustomStackBuilder::new().build_headless()
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Pushes single values as well as several at once, and peeks at them.
+fn push() -> Result<(), CustomError> {
+    let mut stack = headless_stack();
+    stack.push(num("1.5")?).map_err(|(e, _)| e)?;
+    stack.push_array([num("2")?, num("-3.25")?]).map_err(|(e, _)| e)?;
+    stack.push_slice(&[num("4")?])?;
+
+    check(stack.len() == 4, "length after pushing")?;
+    check(stack.peek() == Some(&num("4")?), "peeking the top")?;
+    check(stack.peek_nth(3) == Some(&num("1.5")?), "peeking the bottom")?;
+    check(stack.peek_nth(4).is_none(), "peeking below the bottom")?;
+    check(stack.multipeek(2) == [num("-3.25")?, num("4")?], "peeking the top two")
+}
+
+/// Pops single values as well as several at once, down to an empty stack.
+fn pop() -> Result<(), CustomError> {
+    let mut stack = headless_stack();
+    stack.push_array([num("1")?, num("2")?, num("3")?, num("4")?]).map_err(|(e, _)| e)?;
+
+    check(stack.pop() == Some(num("4")?), "popping the top")?;
+    let mut popped = stack.multipop(2).ok_or(CE::Other)?;
+    // The topmost one comes first
+    check(popped.next() == Some(num("3")?) && popped.next() == Some(num("2")?) && popped.next().is_none(), "popping two")?;
+    drop(popped);
+    check(stack.len() == 1, "length after popping")?;
+    check(stack.pop() == Some(num("1")?) && stack.pop().is_none(), "popping down to empty")?;
+    check(stack.multipop(1).is_none(), "popping several from an empty stack")
+}
+
+/// Does the arithmetics the way the keys do, popping the operands and pushing the result.
+fn arith() -> Result<(), CustomError> {
+    let mut stack = headless_stack();
+    // (1.5 + 2.25) * 4 / 3 - 0.5, the same as typing `1.5 2.25 + 4 * 3 / 0.5 -` in RPN
+    stack.push_array([num("1.5")?, num("2.25")?]).map_err(|(e, _)| e)?;
+    for (operand, operator) in [(None, '+'), (Some("4"), '*'), (Some("3"), '/'), (Some("0.5"), '-')] {
+        if let Some(operand) = operand {
+            stack.push(num(operand)?).map_err(|(e, _)| e)?;
+        }
+        let b = stack.pop().ok_or(CE::Other)?;
+        let a = stack.pop().ok_or(CE::Other)?;
+        let result = match operator {
+            '+' => a + b,
+            '-' => a - b,
+            '*' => a * b,
+            '/' => a / b,
+            _ => return Err(CE::Impossible),
+        }?;
+        stack.push(result).map_err(|(e, _)| e)?;
+    }
+    check(stack.len() == 1 && stack.peek() == Some(&num("4.5")?), "(1.5 + 2.25) * 4 / 3 - 0.5")?;
+
+    // Truncated, not rounded
+    check((num("2")? / num("3")?)? == num("0.666666666")?, "2 / 3")?;
+    check(num("2")?.sqrt()? == num("1.414213562")?, "square root of 2")?;
+    check((-num("7.5")?)? == num("-7.5")?, "negation")?;
+
+    stack.clear();
+    stack.push_array([num("2")?, num("4")?, num("4")?, num("4")?, num("5")?, num("5")?, num("7")?, num("9")?]).map_err(|(e, _)| e)?;
+    check(stack.sum()? == num("40")? && stack.mean()? == num("5")?, "sum and mean")
+}
+
+/// Formats numbers the ways the stack can show them, including the rounding.
+fn format() -> Result<(), CustomError> {
+    check_formatted(heapless::format!("{}", num("007.10")?), "7.1")?;
+    check_formatted(heapless::format!("{}", num("-0.000000001")?), "-0.000000001")?;
+    check_formatted(heapless::format!("{:.2}", num("1.005")?), "1.01")?;
+    check_formatted(heapless::format!("{:.2}", num("-0.001")?), "0.00")?;
+    check_formatted(heapless::format!("{:.2}", num("9.995")?), "10.00")?;
+    check_formatted(heapless::format!("{:e}", num("0.00123")?), "1.23e-3")?;
+    check_formatted(heapless::format!("{:.2e}", num("9.999")?), "1.00e1")
+}
+
+/// Draws a stack onto the real display in all the number formats, with enough elements to scroll.
+/// It can only fail on the bus, like the display part of `selftest`; whether it looks right is up to whoever watches.
+fn draw<D>(disp_refcell: &RefCell<D>) -> Result<(), CustomError>
+where
+    D: Panel,
+{
+    let mut stack: CustomStack<'_, DecimalFixed, D> = CustomStackBuilder::new()
+        .set_gutter(true)
+        .set_highlight_top(true)
+        .build(disp_refcell);
+    for i in 0..10 {
+        stack.push(DecimalFixed::new(i, None)?).map_err(|(e, _)| e)?;
+    }
+    stack.push(num("-1234567.891011121")?).map_err(|(e, _)| e)?;
+    stack.set_label(0, "big")?;
+
+    for number_format in [NumberFormat::Full, NumberFormat::Fixed(2), NumberFormat::Scientific(3)] {
+        stack.set_number_format(number_format);
+        stack.draw(true)?;
+    }
+    stack.scroll_up(3);
+    stack.draw(true)?;
+    check(!stack.is_dirty(), "the stack still being dirty after drawing")?;
+    disp_refcell.borrow_mut().flush_now()
+}
+
+/// Goes down the error paths, each has to fail with the right error and leave the stack as it was.
+fn errors() -> Result<(), CustomError> {
+    let max = DecimalFixed::new_prescaled(i64::MAX, -9);
+    check((max + num("1")?) == Err(CE::MathOverflow), "overflowing an addition")?;
+    check((max * num("2")?) == Err(CE::MathOverflow), "overflowing a multiplication")?;
+    check((num("1")? / num("0")?) == Err(CE::BadInput), "dividing by zero")?;
+    check(num("-4")?.sqrt() == Err(CE::BadInput), "the square root of a negative number")?;
+    check(num("1.2x") == Err(CE::ParseIntError(IntErrorKindClone::InvalidDigit)), "parsing garbage")?;
+    check(num("") == Err(CE::BadInput), "parsing nothing")?;
+    check(num("99999999999") == Err(CE::MathOverflow), "parsing a number too big")?;
+
+    let mut stack = headless_stack();
+    check(stack.pop().is_none() && stack.swap_at(0, 1) == Err(CE::BadInput), "shuffling an empty stack")?;
+    stack.push_array([num("1")?, max]).map_err(|(e, _)| e)?;
+    // Doubling both fails on the bottom one, after the top one was already doubled
+    let result = stack.apply_top_n(2, |x| { *x = (*x + *x)?; Ok(()) });
+    check(result == Err(CE::MathOverflow), "overflowing in the middle of the stack")?;
+    check(stack.multipeek(2) == [num("1")?, max], "the stack being restored after an error")?;
+
+    let mut value = 0;
+    while stack.push(DecimalFixed::new(value, None)?).is_ok() {
+        value += 1;
+    }
+    let full_len = stack.len();
+    check(stack.push(max).is_err_and(|(e, _)| e == CE::CapacityError), "pushing onto a full stack")?;
+    check(stack.len() == full_len, "the length after a rejected push")
+}
+
+/// Runs command lines that the dispatcher has to reject before running anything, so that the user's stack stays intact,
+/// and looks up the commands the menu runs.
+fn dispatch<D>(ctx: &mut Context<'_, '_, D>) -> Result<(), CustomError>
+where
+    D: Panel,
+{
+    let len = ctx.stack.len();
+    check(commands::execute("nosuchcommand", ctx) == Err(CE::BadInput), "an unknown command")?;
+    check(commands::execute("redraw now", ctx) == Err(CE::BadInput), "an argument to a command that takes none")?;
+    check(commands::execute("pick", ctx) == Err(CE::BadInput), "a missing argument")?;
+    check(commands::find::<D>("hiltest").is_some(), "finding the command itself")?;
+    check(menu::commands_exist::<D>(menu::ROOT), "every command of the menu existing")?;
+    check(ctx.stack.len() == len, "the stack being left alone")
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    /// The steps that don't need the hardware have to pass on the host too, or the script itself is wrong
+    #[test]
+    fn headless_steps_pass() {
+        assert_eq!(super::push(), Ok(()));
+        assert_eq!(super::pop(), Ok(()));
+        assert_eq!(super::arith(), Ok(()));
+        assert_eq!(super::format(), Ok(()));
+        assert_eq!(super::errors(), Ok(()));
+    }
+}
@@ -0,0 +1,569 @@
+use rp2040_hal as hal;
+use core::cell::{Cell, RefCell};
+use core::fmt::Write;
+use heapless::{Deque, String, Vec};
+
+use crate::display::Panel;
+use crate::stack::{StackEvent, StackObserver, TraceObserver};
+use crate::stack_set::StackSet;
+use crate::decfix::DecimalFixed;
+use crate::state::CalcState;
+use crate::vsys::Vsys;
+use crate::clock::WallClock;
+use crate::uart_rx::UartRx;
+use crate::command_mode::run_command_printing;
+use crate::get_timestamp_us;
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE, // Short type alias
+    WithContext,
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Sent in reply to `Ping`, bumped whenever the messages change incompatibly
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Longest message before COBS, either way. Fits the longest reply, i.e. the output of `Execute`.
+const MAX_PAYLOAD: usize = 300;
+/// Longest frame, COBS adds a byte per 254 and we add the two zeros around it
+const FRAME_SIZE: usize = MAX_PAYLOAD + MAX_PAYLOAD / 254 + 3;
+/// Output of a command run by `Execute` past this many bytes is cut off
+const OUTPUT_SIZE: usize = 256;
+/// Longest number sent back, the 39 digits of a `DecimalFixed` with the sign and the point fit easily
+const VALUE_SIZE: usize = 64;
+/// Changes to the stack waiting to be sent to the host, more of them in between two keys and the host has to resync
+const QUEUE_SIZE: usize = 16;
+/// How long to wait for the next byte of a frame before dropping it, a host sends the frame all at once
+const FRAME_GAP_US: u64 = 100_000;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/*
+A binary protocol for a program on the host (e.g. a GUI or a logging daemon) to mirror and drive the stack,
+on the same serial port (UART or USB) as the keys typed, so that both work at once.
+
+Every message is encoded like `postcard` (the serde format) encodes the Rust types in the comments below,
+then COBS-framed with a zero byte before and after it. The zero before tells us it's a frame and not a key,
+and lets the host skip whatever else we send between the frames, e.g. the echo or the mirrored log.
+
+From the host: `struct Request { id: u32, body: RequestBody }`
+    enum RequestBody { Ping, Push(&str), Pop, Peek(u32), Execute(&str), Subscribe(bool), Depth }
+To the host: enum Message {
+        Reply { id: u32, result: Result<ReplyBody, &str> },
+        Notification(StackEvent), // Only while subscribed, in the order of `StackEvent`'s variants
+        Overflowed, // Notifications were dropped, the host has to read the stack again
+    }
+    enum ReplyBody { Pong(u32), Done, Value(Option<&str>), Output(&[u8]), Depth(u32) }
+*/
+
+/// Encodes the data with COBS, so that there's no zero byte in it. The delimiters aren't added.
+pub fn cobs_encode<const N: usize>(data: &[u8], out: &mut Vec<u8, N>) -> Result<(), CustomError> {
+    // Each block starts with the distance to the next zero (or to the end of the block, if it's full)
+    let mut code_at = out.len();
+    out.push(0).map_err(|_| CE::CapacityError)?;
+    let mut code = 1_u8;
+    for &byte in data {
+        if byte != 0 {
+            out.push(byte).map_err(|_| CE::CapacityError)?;
+            code += 1;
+        }
+        if byte == 0 || code == 0xFF {
+            out[code_at] = code;
+            code_at = out.len();
+            out.push(0).map_err(|_| CE::CapacityError)?;
+            code = 1;
+        }
+    }
+    out[code_at] = code;
+    Ok(())
+}
+
+/// Decodes a COBS frame (without the delimiters) in place, returning the length of the data.
+/// Returns `BadInput` if the frame is malformed, e.g. cut off.
+pub fn cobs_decode(frame: &mut [u8]) -> Result<usize, CustomError> {
+    let (mut read, mut written) = (0, 0);
+    while read < frame.len() {
+        let code = usize::from(frame[read]);
+        if code == 0 || read + code > frame.len() {
+            return Err(CE::BadInput);
+        }
+        frame.copy_within((read + 1)..(read + code), written);
+        written += code - 1;
+        read += code;
+        // A full block has no zero after it, nor does the last one
+        if code < 0xFF && read < frame.len() {
+            frame[written] = 0;
+            written += 1;
+        }
+    }
+    Ok(written)
+}
+
+/// Appends values the way `postcard` serializes them
+struct Writer<'w, const N: usize> {
+    out: &'w mut Vec<u8, N>,
+}
+
+impl<const N: usize> Writer<'_, N> {
+    fn byte(&mut self, byte: u8) -> Result<(), CustomError> {
+        self.out.push(byte).map_err(|_| CE::CapacityError)
+    }
+
+    /// LEB128, as all the integers except `u8`, and the variant indexes of enums
+    fn varint(&mut self, mut value: u32) -> Result<(), CustomError> {
+        while value >= 0x80 {
+            self.byte((value as u8 & 0x7F) | 0x80)?;
+            value >>= 7;
+        }
+        self.byte(value as u8)
+    }
+
+    /// Byte slices and strings are prefixed by their length
+    fn bytes(&mut self, bytes: &[u8]) -> Result<(), CustomError> {
+        self.varint(bytes.len() as u32)?;
+        self.out.extend_from_slice(bytes).map_err(|_| CE::CapacityError)
+    }
+}
+
+/// Takes values off the data the way `postcard` deserializes them, `BadInput` meaning the data ended early or is malformed
+struct Reader<'f> {
+    rest: &'f [u8],
+}
+
+impl<'f> Reader<'f> {
+    fn byte(&mut self) -> Result<u8, CustomError> {
+        let (&first, rest) = self.rest.split_first().ok_or(CE::BadInput)?;
+        self.rest = rest;
+        Ok(first)
+    }
+
+    fn varint(&mut self) -> Result<u32, CustomError> {
+        let mut value = 0_u32;
+        for shift in (0..32).step_by(7) {
+            let byte = self.byte()?;
+            value |= u32::from(byte & 0x7F).checked_shl(shift).ok_or(CE::BadInput)?;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(CE::BadInput) // Longer than a `u32` can be
+    }
+
+    fn bool(&mut self) -> Result<bool, CustomError> {
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(CE::BadInput),
+        }
+    }
+
+    fn str(&mut self) -> Result<&'f str, CustomError> {
+        let len = self.varint()? as usize;
+        if len > self.rest.len() {
+            return Err(CE::BadInput);
+        }
+        let (bytes, rest) = self.rest.split_at(len);
+        self.rest = rest;
+        core::str::from_utf8(bytes).map_err(|_| CE::BadInput)
+    }
+}
+
+/// What the host asks for, see the protocol above
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Request<'f> {
+    /// Replied to with `Reply::Pong`, e.g. to find the device or check the protocol version
+    Ping,
+    /// Pushes the number, written as it would be typed
+    Push(&'f str),
+    /// Pops the top of the stack and sends it back
+    Pop,
+    /// Sends back the element this deep (the top being zero), leaving it on the stack
+    Peek(u32),
+    /// Runs the command line as in command mode, and sends back what it printed
+    Execute(&'f str),
+    /// Starts or stops sending the changes to the stack as they happen
+    Subscribe(bool),
+    /// Sends back how many elements there are
+    Depth,
+}
+
+impl<'f> Request<'f> {
+    /// Decodes the data of a frame. Without an ID to reply to, it returns the error,
+    /// otherwise the ID and whether the rest is a request we know.
+    pub fn decode(data: &'f [u8]) -> Result<(u32, Result<Self, CustomError>), CustomError> {
+        let mut reader = Reader { rest: data };
+        let id = reader.varint()?;
+        let mut body = || {
+            let request = match reader.varint()? {
+                0 => Request::Ping,
+                1 => Request::Push(reader.str()?),
+                2 => Request::Pop,
+                3 => Request::Peek(reader.varint()?),
+                4 => Request::Execute(reader.str()?),
+                5 => Request::Subscribe(reader.bool()?),
+                6 => Request::Depth,
+                _ => return Err(CE::BadInput),
+            };
+            if reader.rest.is_empty() { Ok(request) } else { Err(CE::BadInput) }
+        };
+        Ok((id, body()))
+    }
+}
+
+/// What a request got done, see the protocol above
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    /// With `PROTOCOL_VERSION`
+    Pong(u32),
+    Done,
+    /// A number of the stack, `None` if there was none
+    Value(Option<String<VALUE_SIZE>>),
+    /// What the command printed, the line endings as they're set (see the `crlf` command)
+    Output(Vec<u8, OUTPUT_SIZE>),
+    Depth(u32),
+}
+
+/// A frame to send, COBS-encoded with the zeros around it
+pub type Frame = Vec<u8, FRAME_SIZE>;
+
+/// Serializes a message with `write`, then frames it
+fn frame(write: impl FnOnce(&mut Writer<'_, MAX_PAYLOAD>) -> Result<(), CustomError>) -> Result<Frame, CustomError> {
+    let mut payload: Vec<u8, MAX_PAYLOAD> = Vec::new();
+    write(&mut Writer { out: &mut payload })?;
+    let mut frame = Frame::new();
+    frame.push(0).map_err(|_| CE::CapacityError)?;
+    cobs_encode(&payload, &mut frame)?;
+    frame.push(0).map_err(|_| CE::CapacityError)?;
+    Ok(frame)
+}
+
+/// Frames the reply to the request with the ID, or the error message if it failed
+pub fn encode_reply(id: u32, result: Result<&Reply, &str>) -> Result<Frame, CustomError> {
+    frame(|w| {
+        w.varint(0)?; // Message::Reply
+        w.varint(id)?;
+        match result {
+            Ok(reply) => {
+                w.varint(0)?; // Ok
+                match reply {
+                    Reply::Pong(version) => { w.varint(0)?; w.varint(*version) },
+                    Reply::Done => w.varint(1),
+                    Reply::Value(None) => { w.varint(2)?; w.byte(0) },
+                    Reply::Value(Some(value)) => { w.varint(2)?; w.byte(1)?; w.bytes(value.as_bytes()) },
+                    Reply::Output(output) => { w.varint(3)?; w.bytes(output) },
+                    Reply::Depth(depth) => { w.varint(4)?; w.varint(*depth) },
+                }
+            },
+            Err(message) => {
+                w.varint(1)?; // Err
+                w.bytes(message.as_bytes())
+            },
+        }
+    })
+}
+
+/// Frames the notification of the change, or of some being dropped if it's `None`
+pub fn encode_notification(event: Option<StackEvent>) -> Result<Frame, CustomError> {
+    frame(|w| {
+        let Some(event) = event else {
+            return w.varint(2); // Message::Overflowed
+        };
+        w.varint(1)?; // Message::Notification
+        let (index, count) = match event {
+            StackEvent::Pushed(n) => (0, Some(n)),
+            StackEvent::Popped(n) => (1, Some(n)),
+            StackEvent::DroppedBottom(n) => (2, Some(n)),
+            StackEvent::Modified => (3, None),
+            StackEvent::Spilled(n) => (4, Some(n)),
+            StackEvent::Unspilled(n) => (5, Some(n)),
+            StackEvent::Cleared => (6, None),
+        };
+        w.varint(index)?;
+        match count {
+            Some(n) => w.varint(n as u32),
+            None => Ok(()),
+        }
+    })
+}
+
+/// Collects the bytes of a frame up to its closing zero into `buf`, returning how many there were.
+/// `next_byte` returns `None` if the next byte doesn't arrive in time, the frame is then dropped with `BadInput`.
+/// A frame too long is read to its end anyway, so that the rest doesn't get typed in as keys, and gives `CapacityError`.
+pub fn collect_frame(buf: &mut [u8], mut next_byte: impl FnMut() -> Option<u8>) -> Result<usize, CustomError> {
+    let mut len = 0;
+    let mut overflowed = false;
+    loop {
+        match next_byte().ok_or(CE::BadInput)? {
+            0 if overflowed => return Err(CE::CapacityError),
+            0 => return Ok(len),
+            byte => match buf.get_mut(len) {
+                Some(slot) => {
+                    *slot = byte;
+                    len += 1;
+                },
+                None => overflowed = true,
+            },
+        }
+    }
+}
+
+/// The stack's observer that keeps the changes for the host while it's subscribed, see `Request::Subscribe`.
+/// Logs every change like `TraceObserver` as well.
+pub struct HostLink {
+    subscribed: Cell<bool>,
+    queue: RefCell<Deque<StackEvent, QUEUE_SIZE>>,
+    /// Some changes didn't fit into the queue and were dropped
+    overflowed: Cell<bool>,
+}
+
+impl HostLink {
+    pub const fn new() -> Self {
+        HostLink {
+            subscribed: Cell::new(false),
+            queue: RefCell::new(Deque::new()),
+            overflowed: Cell::new(false),
+        }
+    }
+
+    /// Starts or stops keeping the changes, dropping the ones kept so far
+    pub fn set_subscribed(&self, subscribed: bool) {
+        self.subscribed.set(subscribed);
+        self.queue.borrow_mut().clear();
+        self.overflowed.set(false);
+    }
+
+    /// Sends the framed notifications of the changes kept since the last call with `send`,
+    /// followed by `Message::Overflowed` if some were dropped.
+    pub fn drain(&self, mut send: impl FnMut(&[u8])) {
+        while let Some(event) = self.queue.borrow_mut().pop_front() {
+            match encode_notification(Some(event)) {
+                Ok(frame) => send(&frame),
+                Err(e) => log_error!("Failed to encode the notification of {:?}: {:?}", event, e),
+            }
+        }
+        if self.overflowed.replace(false) {
+            log_warn!("Dropped some notifications for the host, it has to resync");
+            match encode_notification(None) {
+                Ok(frame) => send(&frame),
+                Err(e) => log_error!("Failed to encode the overflow notification: {:?}", e),
+            }
+        }
+    }
+}
+
+impl StackObserver for HostLink {
+    fn on_change(&self, event: StackEvent) {
+        TraceObserver.on_change(event);
+        if self.subscribed.get() && self.queue.borrow_mut().push_back(event).is_err() {
+            self.overflowed.set(true);
+        }
+    }
+}
+
+/// Reads the rest of a frame after its opening zero, handles the request and replies to it.
+/// `pending` gives the bytes read ahead already (see `PasteDetector::pop_pending()`), they come before the UART.
+///
+/// A malformed frame is dropped, since there's no ID to reply to; other errors are replied with.
+/// Only the display errors are returned, like `run_command()` they're grave.
+#[allow(clippy::too_many_arguments)] // The same as `run_command()`
+pub fn serve<'a, D, R, U, P> (
+    link: &HostLink,
+    mut pending: impl FnMut() -> Option<u8>,
+    uart_rx: &'a R,
+    uart_tx: &'a hal::uart::Writer<U, P>,
+    uart_clock_hz: u32,
+    disp_refcell: &'a RefCell<D>,
+    stack: &mut StackSet<'a, DecimalFixed, D>,
+    state: &mut CalcState,
+    vsys: &mut Vsys,
+    clock: &mut WallClock,
+) -> Result<(), WithContext>
+where
+    D: Panel,
+
+    R: UartRx,
+    U: hal::uart::UartDevice,
+    P: hal::uart::ValidUartPinout<U>
+{
+    let mut buf = [0_u8; FRAME_SIZE];
+    let mut byte = [0_u8; 1];
+    let collected = collect_frame(&mut buf, || pending().or_else(|| {
+        let start = get_timestamp_us();
+        while get_timestamp_us() - start < FRAME_GAP_US {
+            if uart_rx.read_available(&mut byte) > 0 {
+                return Some(byte[0]);
+            }
+        }
+        None
+    }));
+    let request = collected
+        .and_then(|len| cobs_decode(&mut buf[..len]))
+        .and_then(|len| Request::decode(&buf[..len]));
+    let (id, request) = match request {
+        Ok((id, request)) => (id, request),
+        Err(e) => {
+            log_warn!("Dropping a malformed frame from the host: {:?}", e);
+            return Ok(());
+        },
+    };
+
+    let result: Result<Reply, WithContext> = request.map_err(WithContext::from).and_then(|request| {
+        log_debug!("Request {} from the host: {:?}", id, request);
+        match request {
+        Request::Ping => Ok(Reply::Pong(PROTOCOL_VERSION)),
+        Request::Push(text) => {
+            let value: DecimalFixed = text.trim().parse().map_err(|e| WithContext::from(e).with_context("push", text))?;
+            stack.push(value).map_err(|(e, _)| e)?;
+            Ok(Reply::Done)
+        },
+        Request::Pop => Ok(Reply::Value(stack.pop().map(format_value).transpose()?)),
+        Request::Peek(depth) => Ok(Reply::Value(stack.peek_nth(depth as usize).copied().map(format_value).transpose()?)),
+        Request::Execute(command) => {
+            // The output is cut off rather than failing the command, which has run already by then
+            let output: RefCell<Vec<u8, OUTPUT_SIZE>> = RefCell::new(Vec::new());
+            let print = |bytes: &[u8]| {
+                let mut output = output.borrow_mut();
+                let fits = bytes.len().min(OUTPUT_SIZE - output.len());
+                let _ = output.extend_from_slice(&bytes[..fits]); // Can't fail, we checked
+            };
+            run_command_printing(command, &print, &print, uart_rx, uart_clock_hz, disp_refcell, stack, state, vsys, clock)?;
+            Ok(Reply::Output(output.into_inner()))
+        },
+        Request::Subscribe(subscribed) => {
+            link.set_subscribed(subscribed);
+            Ok(Reply::Done)
+        },
+        Request::Depth => Ok(Reply::Depth(stack.len() as u32)),
+        }
+    });
+
+    let frame = match &result {
+        Err(WithContext { error: CE::DisplayError(_), .. }) => return result.map(|_| ()),
+        Err(e) => {
+            log_warn!("Request {} from the host failed: {}", id, e);
+            encode_reply(id, Err(&e.toast_message()))
+        },
+        Ok(reply) => encode_reply(id, Ok(reply)),
+    };
+    match frame {
+        Ok(frame) => crate::uart_tx::write_binary(uart_tx, &frame),
+        Err(e) => log_error!("Failed to encode the reply to request {}: {:?}", id, e),
+    }
+    Ok(())
+}
+
+/// Writes the number out in full, as the host would parse it
+fn format_value(value: DecimalFixed) -> Result<String<VALUE_SIZE>, CustomError> {
+    let mut text = String::new();
+    write!(text, "{}", value)?;
+    Ok(text)
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use heapless::Vec;
+    use super::{cobs_decode, cobs_encode, collect_frame, encode_notification, encode_reply, HostLink, Reply, Request};
+    use crate::stack::{StackEvent, StackObserver};
+    use crate::custom_error::CE;
+
+    fn round_trip(data: &[u8]) {
+        let mut encoded: Vec<u8, 600> = Vec::new();
+        cobs_encode(data, &mut encoded).unwrap();
+        assert!(!encoded.contains(&0), "{:?}", data);
+        let len = cobs_decode(&mut encoded).unwrap();
+        assert_eq!(&encoded[..len], data);
+    }
+
+    #[test]
+    fn cobs_round_trips() {
+        let mut encoded: Vec<u8, 16> = Vec::new();
+        cobs_encode(&[0x11, 0x00, 0x00, 0x22], &mut encoded).unwrap();
+        assert_eq!(encoded, [0x02, 0x11, 0x01, 0x02, 0x22]);
+
+        round_trip(&[]);
+        round_trip(&[0]);
+        round_trip(&[1, 2, 0, 3, 0]);
+        let long: std::vec::Vec<u8> = (0..=600_u32).map(|i| (i % 255 + 1) as u8).collect();
+        round_trip(&long[..254]);
+        round_trip(&long[..255]);
+        round_trip(&long[..300]);
+
+        assert_eq!(cobs_decode(&mut [0x05, 0x11]), Err(CE::BadInput)); // Cut off
+        assert_eq!(cobs_decode(&mut [0x02, 0x11, 0x00, 0x22]), Err(CE::BadInput));
+    }
+
+    #[test]
+    fn decodes_requests() {
+        assert_eq!(Request::decode(&[0x07, 0x00]), Ok((7, Ok(Request::Ping))));
+        // The ID over a byte, then `Push("1.5")`
+        assert_eq!(Request::decode(&[0xAC, 0x02, 0x01, 0x03, b'1', b'.', b'5']), Ok((300, Ok(Request::Push("1.5")))));
+        assert_eq!(Request::decode(&[0x01, 0x03, 0x02]), Ok((1, Ok(Request::Peek(2)))));
+        assert_eq!(Request::decode(&[0x01, 0x05, 0x01]), Ok((1, Ok(Request::Subscribe(true)))));
+
+        assert_eq!(Request::decode(&[0x01, 0x09]), Ok((1, Err(CE::BadInput)))); // Unknown request
+        assert_eq!(Request::decode(&[0x01, 0x01, 0x05, b'1']), Ok((1, Err(CE::BadInput)))); // Cut off string
+        assert_eq!(Request::decode(&[0x01, 0x00, 0x00]), Ok((1, Err(CE::BadInput)))); // Trailing bytes
+        assert_eq!(Request::decode(&[0x80]), Err(CE::BadInput)); // Not even an ID
+    }
+
+    #[test]
+    fn encodes_messages() {
+        let unframe = |frame: &[u8]| {
+            assert_eq!((frame[0], frame[frame.len() - 1]), (0, 0));
+            let mut data = std::vec::Vec::from(&frame[1..(frame.len() - 1)]);
+            let len = cobs_decode(&mut data).unwrap();
+            data.truncate(len);
+            data
+        };
+        let value = Reply::Value(Some(heapless::String::try_from("-2.5").unwrap()));
+        assert_eq!(unframe(&encode_reply(3, Ok(&value)).unwrap()), [0, 3, 0, 2, 1, 4, b'-', b'2', b'.', b'5']);
+        assert_eq!(unframe(&encode_reply(3, Ok(&Reply::Value(None))).unwrap()), [0, 3, 0, 2, 0]);
+        assert_eq!(unframe(&encode_reply(200, Err("Bad")).unwrap()), [0, 0xC8, 0x01, 1, 3, b'B', b'a', b'd']);
+        // The longest output still fits
+        let output = Reply::Output(Vec::from_slice(&[b'x'; super::OUTPUT_SIZE]).unwrap());
+        assert!(encode_reply(u32::MAX, Ok(&output)).is_ok());
+
+        assert_eq!(unframe(&encode_notification(Some(StackEvent::Spilled(2))).unwrap()), [1, 4, 2]);
+        assert_eq!(unframe(&encode_notification(Some(StackEvent::Cleared)).unwrap()), [1, 6]);
+        assert_eq!(unframe(&encode_notification(None).unwrap()), [2]);
+    }
+
+    #[test]
+    fn queues_notifications_while_subscribed() {
+        let link = HostLink::new();
+        let count = |link: &HostLink| {
+            let mut frames = 0;
+            link.drain(|_| frames += 1);
+            frames
+        };
+        link.on_change(StackEvent::Pushed(1));
+        assert_eq!(count(&link), 0);
+
+        link.set_subscribed(true);
+        link.on_change(StackEvent::Pushed(1));
+        link.on_change(StackEvent::Modified);
+        assert_eq!(count(&link), 2);
+        // One too many gets the rest dropped, and the host told so
+        for _ in 0..=super::QUEUE_SIZE {
+            link.on_change(StackEvent::Popped(1));
+        }
+        assert_eq!(count(&link), super::QUEUE_SIZE + 1);
+        assert_eq!(count(&link), 0);
+    }
+
+    #[test]
+    fn collects_frames() {
+        let mut buf = [0_u8; 4];
+        let mut bytes = [1, 2, 0, 9].into_iter();
+        assert_eq!(collect_frame(&mut buf, || bytes.next()), Ok(2));
+        assert_eq!(bytes.next(), Some(9)); // Left for the next frame
+        let mut bytes = [1, 2, 3, 4, 5, 6, 0, 9].into_iter();
+        assert_eq!(collect_frame(&mut buf, || bytes.next()), Err(CE::CapacityError));
+        assert_eq!(bytes.next(), Some(9));
+        let mut bytes = [1, 2].into_iter();
+        assert_eq!(collect_frame(&mut buf, || bytes.next()), Err(CE::BadInput)); // Never finished
+    }
+}
@@ -0,0 +1,224 @@
+//! The calculator as an I²C target on I2C1, so that another microcontroller can use it as a coprocessor or a display head.
+//!
+//! Register map, the master writes the register pointer first, then reads (or writes) from there on, the pointer incrementing:
+//!
+//! | Address     | Register | Access |                                                                            |
+//! |-------------|----------|--------|----------------------------------------------------------------------------|
+//! | 0x00        | STATUS   | R      | bit 0 stack not empty, 1 top doesn't fit TOP, 2 unseen errors, 3 macro playing |
+//! | 0x01        | DEPTH    | R      | Stack depth, saturating at 255                                             |
+//! | 0x02        | SCALE    | R/W    | Decimal places of TOP, 0-9, 3 after boot                                   |
+//! | 0x03        | ID       | R      | Always `ID`, to tell that it's us                                          |
+//! | 0x04-0x07   | TOP      | R      | Top of the stack times 10^SCALE, i32 little-endian, `NOT_A_NUMBER` if none |
+//! | 0x08-0x27   | TEXT     | R      | Top of the stack as shown, ASCII padded by NULs                            |
+//!
+//! The values are taken once a read begins, so a multi-byte one is never torn by a change in the middle of it.
+
+use core::fmt::Write;
+#[cfg(feature = "i2c-peripheral")]
+use core::ops::Deref;
+
+use heapless::String;
+#[cfg(feature = "i2c-peripheral")]
+use rp2040_hal::{self as hal, i2c::peripheral::Event, pac::i2c0::RegisterBlock};
+
+use crate::decfix::DecimalFixed;
+use crate::modbus::{self, MAX_SCALE, NOT_A_NUMBER};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Our 7-bit address on the bus
+pub const ADDRESS: u8 = 0x42;
+/// Value of the ID register
+pub const ID: u8 = 0xCA;
+/// Size of the register map, reading past it gives zeros
+pub const MAP_SIZE: usize = 0x28;
+/// Decimal places of TOP after boot, the same as the Modbus registers' default
+const DEFAULT_SCALE: u8 = 3;
+
+const STATUS: usize = 0x00;
+const DEPTH: usize = 0x01;
+const SCALE: u8 = 0x02;
+const ID_REGISTER: usize = 0x03;
+const TOP: usize = 0x04;
+const TEXT: usize = 0x08;
+const TEXT_SIZE: usize = MAP_SIZE - TEXT;
+
+/// Bits of the STATUS register
+pub const STATUS_NOT_EMPTY: u8 = 1 << 0;
+pub const STATUS_OVERFLOW: u8 = 1 << 1;
+pub const STATUS_ERROR: u8 = 1 << 2;
+pub const STATUS_BUSY: u8 = 1 << 3;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// The register map's contents. `flags` are the STATUS bits besides those about the stack's top, which are set here.
+pub fn snapshot(top: Option<DecimalFixed>, depth: usize, flags: u8, scale: u8) -> [u8; MAP_SIZE] {
+    let mut map = [0; MAP_SIZE];
+    let raw = top.map_or(NOT_A_NUMBER, |value| modbus::to_scaled(value, scale));
+    map[STATUS] = flags
+        | if top.is_some() { STATUS_NOT_EMPTY } else { 0 }
+        | if top.is_some() && raw == NOT_A_NUMBER { STATUS_OVERFLOW } else { 0 };
+    map[DEPTH] = u8::try_from(depth).unwrap_or(u8::MAX);
+    map[usize::from(SCALE)] = scale;
+    map[ID_REGISTER] = ID;
+    map[TOP..TEXT].copy_from_slice(&raw.to_le_bytes());
+    if let Some(value) = top {
+        let mut text: String<TEXT_SIZE> = String::new();
+        let _ = write!(text, "{}", value); // Too long just gets cut off, the same as on the display
+        map[TEXT..TEXT + text.len()].copy_from_slice(text.as_bytes());
+    }
+    map
+}
+
+/// The target's side of the transfers, apart from the hardware
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Registers {
+    pointer: u8,
+    scale: u8,
+    /// The pointer was written since the last start, so the following bytes are writes into the registers
+    addressed: bool,
+    /// The map taken at the first byte read since the last start
+    snapshot: Option<[u8; MAP_SIZE]>,
+}
+
+impl Registers {
+    pub const fn new() -> Self {
+        Registers { pointer: 0, scale: DEFAULT_SCALE, addressed: false, snapshot: None }
+    }
+
+    /// A start, a restart or a stop: the next byte written is the pointer again, the next byte read is of fresh values
+    pub fn start(&mut self) {
+        self.addressed = false;
+        self.snapshot = None;
+    }
+
+    /// A byte the master wrote, the pointer or a value for the register it points at
+    pub fn write(&mut self, byte: u8) {
+        if !self.addressed {
+            self.pointer = byte;
+            self.addressed = true;
+            return;
+        }
+        if self.pointer == SCALE {
+            if byte <= MAX_SCALE {
+                self.scale = byte;
+            } else {
+                log_warn!("The I²C master wrote a scale of {}, the most is {}", byte, MAX_SCALE);
+            }
+        }
+        self.pointer = self.pointer.wrapping_add(1);
+    }
+
+    /// The byte for the master to read, `map` giving the register map's contents at the first byte of a read
+    pub fn read(&mut self, map: impl FnOnce(u8) -> [u8; MAP_SIZE]) -> u8 {
+        let scale = self.scale;
+        let snapshot = self.snapshot.get_or_insert_with(|| map(scale));
+        let byte = snapshot.get(usize::from(self.pointer)).copied().unwrap_or(0);
+        self.pointer = self.pointer.wrapping_add(1);
+        byte
+    }
+}
+
+/// Serves the register map on an I²C block in the target (peripheral) mode.
+///
+/// It's polled, so it only answers while the main loop polls for input, which it always does with this feature;
+/// otherwise (in command mode, asleep, running a command) the clock is held low until we get to it.
+#[cfg(feature = "i2c-peripheral")]
+pub struct I2cHead<T: Deref<Target = RegisterBlock>, P> {
+    i2c: hal::I2C<T, P, hal::i2c::Peripheral>,
+    registers: Registers,
+}
+
+#[cfg(feature = "i2c-peripheral")]
+impl<T: Deref<Target = RegisterBlock>, P> I2cHead<T, P> {
+    /// The block should already be a target at `ADDRESS`.
+    pub fn new(i2c: hal::I2C<T, P, hal::i2c::Peripheral>) -> Self {
+        I2cHead { i2c, registers: Registers::new() }
+    }
+
+    /// Handles whatever happened on the bus since the last poll, `map` giving the register map's contents by the scale
+    pub fn poll(&mut self, mut map: impl FnMut(u8) -> [u8; MAP_SIZE]) {
+        while let Some(event) = self.i2c.next_event() {
+            match event {
+                Event::Start | Event::Restart | Event::Stop => self.registers.start(),
+                Event::TransferWrite => {
+                    let mut buf = [0; 16];
+                    loop {
+                        let count = self.i2c.read(&mut buf);
+                        if count == 0 {
+                            break;
+                        }
+                        buf[..count].iter().for_each(|&byte| self.registers.write(byte));
+                    }
+                },
+                // A byte at a time, as the bytes left over in the FIFO once the master stops reading would go past the pointer
+                Event::TransferRead => {
+                    let byte = self.registers.read(&mut map);
+                    self.i2c.write(&[byte]);
+                },
+            }
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::{snapshot, Registers, ID, MAP_SIZE, STATUS_BUSY, STATUS_NOT_EMPTY, STATUS_OVERFLOW};
+    use crate::decfix::DecimalFixed;
+    use crate::modbus::NOT_A_NUMBER;
+
+    #[test]
+    fn lays_out_the_map() {
+        let value = DecimalFixed::parse_str("-1.5", None).unwrap();
+        let map = snapshot(Some(value), 3, STATUS_BUSY, 3);
+        assert_eq!(map[..4], [STATUS_NOT_EMPTY | STATUS_BUSY, 3, 3, ID]);
+        assert_eq!(i32::from_le_bytes(map[4..8].try_into().unwrap()), -1500);
+        assert_eq!(map[8..12], *b"-1.5");
+        assert!(map[12..].iter().all(|&byte| byte == 0));
+
+        let map = snapshot(None, 0, 0, 3);
+        assert_eq!(map[..2], [0, 0]);
+        assert_eq!(i32::from_le_bytes(map[4..8].try_into().unwrap()), NOT_A_NUMBER);
+        let huge = DecimalFixed::parse_str("3000000", None).unwrap();
+        assert_eq!(snapshot(Some(huge), 300, 0, 3)[..2], [STATUS_NOT_EMPTY | STATUS_OVERFLOW, 255]);
+    }
+
+    #[test]
+    fn reads_and_writes_by_the_pointer() {
+        let mut registers = Registers::new();
+        let mut maps = 0;
+        let mut map = |scale| {
+            maps += 1;
+            let mut map = [0; MAP_SIZE];
+            map[2] = scale;
+            map[4] = 0x11;
+            map[5] = 0x22;
+            map
+        };
+        // Pointer to TOP, restart, read two bytes from one snapshot
+        registers.start();
+        registers.write(0x04);
+        registers.start();
+        assert_eq!(registers.read(&mut map), 0x11);
+        assert_eq!(registers.read(&mut map), 0x22);
+        registers.start();
+        // Setting the scale, out of range is ignored
+        registers.write(0x02);
+        registers.write(5);
+        registers.start();
+        registers.write(0x02);
+        registers.write(10);
+        registers.start();
+        registers.write(0x02);
+        registers.start();
+        assert_eq!(registers.read(&mut map), 5);
+        registers.start();
+        // Past the map
+        registers.write(0xFF);
+        assert_eq!(registers.read(&mut map), 0);
+        assert_eq!(maps, 3);
+    }
+}
@@ -0,0 +1,78 @@
+use rp2040_hal::pac;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// The pins of I2C0 the display is on, they have to match the ones given to `hal::I2C::i2c0()` in `main.rs`
+const SDA_PIN: usize = 8;
+const SCL_PIN: usize = 9;
+/// A stuck device lets go of SDA at the latest after the rest of the byte it's sending and the ACK bit
+const MAX_CLOCK_PULSES: u32 = 9;
+/// Half of a clock period, in CPU cycles (5 µs at the default 125 MHz system clock, i.e. 100 kHz).
+/// We don't have the `Delay` in here, so we just burn the cycles, the same as `command_mode.rs` does.
+const HALF_PERIOD_CYCLES: u32 = 125_000_000 / 200_000;
+/// How long to wait for the controller to stop, in polls of its status
+const DISABLE_POLLS: u32 = 10_000;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Releases I2C0 when a device holds SDA low, e.g. because a glitch cut a transfer short while it was sending.
+///
+/// The controller can't do anything about that by itself, so we stop it and take over the pins: SCL gets clocked
+/// until the device lets go of SDA (at most `MAX_CLOCK_PULSES` times), then a STOP condition ends whatever it thought
+/// was going on. The controller then gets the pins back and starts again with the configuration the HAL gave it.
+///
+/// The pins are driven through the overrides of their function, which emulate open drain (pulled low or let go),
+/// so that the HAL's ownership of them and their configuration stay untouched.
+pub fn recover_i2c0() {
+    // SAFETY: The controller is stopped while we touch the pins, and we only touch the overrides the HAL doesn't use
+    // (which we leave as we found them), the enable bit it doesn't keep any state about, and the inputs, which are read only.
+    let (io, sio, i2c) = unsafe { (&*pac::IO_BANK0::ptr(), &*pac::SIO::ptr(), &*pac::I2C0::ptr()) };
+
+    i2c.ic_enable().modify(|_, w| w.enable().disabled());
+    for _ in 0..DISABLE_POLLS {
+        if i2c.ic_enable_status().read().ic_en().bit_is_clear() {
+            break;
+        }
+    }
+
+    let sda = io.gpio(SDA_PIN).gpio_ctrl();
+    let scl = io.gpio(SCL_PIN).gpio_ctrl();
+    let line_low = |ctrl: &pac::io_bank0::gpio::GPIO_CTRL| ctrl.modify(|_, w| w.outover().low().oeover().enable());
+    let line_release = |ctrl: &pac::io_bank0::gpio::GPIO_CTRL| ctrl.modify(|_, w| w.outover().low().oeover().disable());
+    let sda_is_high = || sio.gpio_in().read().bits() & (1 << SDA_PIN) != 0;
+    line_release(sda);
+    line_release(scl);
+    cortex_m::asm::delay(HALF_PERIOD_CYCLES);
+
+    let mut pulses = 0;
+    while !sda_is_high() && pulses < MAX_CLOCK_PULSES {
+        line_low(scl);
+        cortex_m::asm::delay(HALF_PERIOD_CYCLES);
+        line_release(scl);
+        cortex_m::asm::delay(HALF_PERIOD_CYCLES);
+        pulses += 1;
+    }
+
+    // A STOP condition: SDA going high while SCL is high
+    line_low(scl);
+    cortex_m::asm::delay(HALF_PERIOD_CYCLES);
+    line_low(sda);
+    cortex_m::asm::delay(HALF_PERIOD_CYCLES);
+    line_release(scl);
+    cortex_m::asm::delay(HALF_PERIOD_CYCLES);
+    line_release(sda);
+    cortex_m::asm::delay(HALF_PERIOD_CYCLES);
+
+    let released = sda_is_high();
+    for ctrl in [sda, scl] {
+        ctrl.modify(|_, w| w.outover().normal().oeover().normal());
+    }
+    i2c.ic_enable().modify(|_, w| w.enable().enabled());
+
+    if released {
+        log_info!("I²C bus recovered after {} clock pulses", pulses);
+    } else {
+        log_warn!("I²C bus still stuck after {} clock pulses, SDA stays low", pulses);
+    }
+}
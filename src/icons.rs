@@ -0,0 +1,150 @@
+use embedded_graphics::{
+    prelude::*,
+    image::{Image, ImageDrawableExt},
+    pixelcolor::BinaryColor,
+    primitives::Rectangle,
+};
+use tinybmp::Bmp;
+
+use crate::display::FlushableDisplay;
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// The icons side by side in the order of `Icon::ALL`, each cut out of a lit square like the other indicators are
+const SPRITE_SHEET: &[u8] = include_bytes!("icons.bmp");
+/// Width of a single icon in the sprite sheet, in pixels
+const ICON_WIDTH: u32 = 7;
+/// Height of the icons, the same as the indicators' font
+const ICON_HEIGHT: u32 = 8;
+/// Space between two icons, in pixels
+const ICON_GAP: i32 = 1;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// A small icon in the status bar, see `IconManager`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Icon {
+    /// The display is inverted, see the `invert` command
+    Inverted,
+    /// There are errors the user hasn't listed yet, see the `errlog` command
+    Error,
+    /// A macro is being played back
+    Busy,
+    /// A USB host has configured the serial port
+    Usb,
+    /// Up and Down (or turning the rotary encoder) adjust the contrast instead of scrolling, toggled by Insert
+    Shift,
+}
+
+impl Icon {
+    /// In the order they're in the sprite sheet, and drawn from the right
+    pub const ALL: [Icon; 5] = [Icon::Inverted, Icon::Error, Icon::Busy, Icon::Usb, Icon::Shift];
+
+    fn index(self) -> usize {
+        Self::ALL.iter().position(|&icon| icon == self).unwrap_or(0)
+    }
+
+    fn bit(self) -> u8 {
+        1 << self.index()
+    }
+
+    /// Where it is in the sprite sheet
+    fn sprite(self) -> Rectangle {
+        Rectangle::new(Point::new((self.index() as u32 * ICON_WIDTH) as i32, 0), Size::new(ICON_WIDTH, ICON_HEIGHT))
+    }
+}
+
+/// Keeps which icons the status bar shows and draws them from the sprite sheet, see `StackSet::set_icon()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IconManager {
+    /// A bit for each icon, see `Icon::bit()`
+    shown: u8,
+}
+
+impl IconManager {
+    pub const fn new() -> Self {
+        IconManager { shown: 0 }
+    }
+
+    /// Shows or hides the icon. Returns whether that changed anything, i.e. whether the status bar has to be redrawn.
+    pub fn set(&mut self, icon: Icon, shown: bool) -> bool {
+        let before = self.shown;
+        if shown {
+            self.shown |= icon.bit();
+        } else {
+            self.shown &= !icon.bit();
+        }
+        self.shown != before
+    }
+
+    pub fn is_shown(&self, icon: Icon) -> bool {
+        self.shown & icon.bit() != 0
+    }
+
+    /// Draws the shown icons at the top of the display, leftwards from `right` (exclusive), with a gap between them.
+    /// Returns where the next indicator to the left of them is to end.
+    pub fn draw<D>(&self, disp: &mut D, right: i32) -> Result<i32, CustomError>
+    where
+        D: FlushableDisplay,
+        CustomError: From<D::Error>,
+    {
+        if self.shown == 0 {
+            return Ok(right);
+        }
+        let sheet = Bmp::<BinaryColor>::from_slice(SPRITE_SHEET).map_err(|_| {
+            log_error!("Failed to parse the sprite sheet of the icons, the image data must be malformed");
+            CE::Impossible // It's built in, the tests check that it parses
+        })?;
+
+        let mut right = right;
+        for icon in Icon::ALL.into_iter().filter(|&icon| self.is_shown(icon)) {
+            right -= ICON_WIDTH as i32;
+            Image::new(&sheet.sub_image(&icon.sprite()), Point::new(right, 0))
+                .draw(&mut disp.color_converted())?;
+            right -= ICON_GAP;
+        }
+        Ok(right)
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use embedded_graphics::prelude::*;
+    use embedded_graphics::pixelcolor::BinaryColor;
+    use tinybmp::Bmp;
+    use super::{Icon, IconManager, SPRITE_SHEET, ICON_WIDTH, ICON_HEIGHT};
+    use crate::display::NullDisplay;
+
+    #[test]
+    fn sprite_sheet_has_every_icon() {
+        let sheet = Bmp::<BinaryColor>::from_slice(SPRITE_SHEET).unwrap();
+        assert_eq!(sheet.size(), Size::new(ICON_WIDTH * Icon::ALL.len() as u32, ICON_HEIGHT));
+        // The corners of every icon are lit, the symbol is cut out of the middle
+        let pixels: std::vec::Vec<Pixel<BinaryColor>> = sheet.pixels().collect();
+        for icon in Icon::ALL {
+            let corner = icon.sprite().top_left + Point::new(0, ICON_HEIGHT as i32 - 1);
+            assert!(pixels.contains(&Pixel(corner, BinaryColor::On)), "{:?}", icon);
+        }
+    }
+
+    #[test]
+    fn shows_and_hides() {
+        let mut icons = IconManager::new();
+        assert_eq!(icons.draw(&mut NullDisplay, 100), Ok(100));
+        assert!(icons.set(Icon::Usb, true));
+        assert!(!icons.set(Icon::Usb, true));
+        assert!(icons.set(Icon::Error, true));
+        assert!(icons.is_shown(Icon::Usb) && !icons.is_shown(Icon::Busy));
+        // Two icons and a gap after each
+        assert_eq!(icons.draw(&mut NullDisplay, 100), Ok(100 - 2 * (ICON_WIDTH as i32 + 1)));
+        assert!(icons.set(Icon::Usb, false));
+        assert!(!icons.set(Icon::Shift, false));
+    }
+}
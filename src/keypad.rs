@@ -0,0 +1,169 @@
+// The polled `Keypad` is left unused when a PIO state machine scans the keypad instead (see `pio_keypad.rs`)
+#![cfg_attr(feature = "pio-keypad", allow(dead_code))]
+
+use core::cell::RefCell;
+use embedded_hal::digital::{InputPin, OutputPin};
+use rp2040_hal as hal;
+use hal::uart::ReadErrorType;
+
+use crate::uart_rx::UartRx;
+use crate::get_timestamp_us;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// The bytes the keys produce, as if they were typed over UART. Laid out like the usual 4x4 membrane keypad:
+/// ```text
+/// 1 2 3 A      1 2 3 +
+/// 4 5 6 B  ->  4 5 6 -
+/// 7 8 9 C      7 8 9 *
+/// * 0 # D      . 0 ⏎ /
+/// ```
+pub const KEYMAP: [[u8; COLS]; ROWS] = [
+    [b'1', b'2', b'3', b'+'],
+    [b'4', b'5', b'6', b'-'],
+    [b'7', b'8', b'9', b'*'],
+    [b'.', b'0', b'\r', b'/'],
+];
+pub const ROWS: usize = 4;
+pub const COLS: usize = 4;
+/// How often the keypad gets scanned, in microseconds
+const SCAN_INTERVAL_US: u64 = 1_000;
+/// How long a key has to stay pressed (or released) before we believe it, in microseconds
+const DEBOUNCE_US: u64 = 20_000;
+/// How long a key has to be held before it starts repeating, in microseconds
+pub const REPEAT_DELAY_US: u64 = 500_000;
+/// How often a held key repeats, in microseconds
+pub const REPEAT_INTERVAL_US: u64 = 100_000;
+/// How long to wait after driving a row low before reading the columns, for the lines to settle (about 10 µs at 125 MHz)
+const SETTLE_CYCLES: u32 = 1250;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// A scanned keypad, polled by `KeypadOrUart`
+pub trait KeySource {
+    /// Returns the byte of a newly pressed (or repeating) key.
+    fn poll(&mut self) -> Option<u8>;
+}
+
+/// Scans a matrix keypad, rows driven low one at a time and columns pulled up, a pressed key pulling its column low.
+///
+/// Only one key at a time is recognised, if more are pressed, the first one in reading order wins.
+pub struct Keypad<R: OutputPin, C: InputPin> {
+    rows: [R; ROWS],
+    cols: [C; COLS],
+    next_scan: u64,
+    /// The key seen by the last scan and since when, it becomes `pressed` once it's been there for `DEBOUNCE_US`
+    candidate: Option<(usize, usize)>,
+    candidate_since: u64,
+    pressed: Option<(usize, usize)>,
+    next_repeat: u64,
+}
+
+impl<R: OutputPin, C: InputPin> Keypad<R, C> {
+    /// The rows should already be outputs driven high, and the columns inputs with pull-ups.
+    pub fn new(rows: [R; ROWS], cols: [C; COLS]) -> Self {
+        Keypad {
+            rows,
+            cols,
+            next_scan: 0,
+            candidate: None,
+            candidate_since: 0,
+            pressed: None,
+            next_repeat: 0,
+        }
+    }
+
+    /// Returns the first pressed key in reading order, if any.
+    fn scan(&mut self) -> Option<(usize, usize)> {
+        let mut found = None;
+        for (row, row_pin) in self.rows.iter_mut().enumerate() {
+            // The RP2040's pins can't fail, and there's nothing sensible to do if other ones did
+            let _ = row_pin.set_low();
+            cortex_m::asm::delay(SETTLE_CYCLES);
+            if found.is_none() {
+                found = self.cols.iter_mut()
+                    .position(|col_pin| col_pin.is_low().unwrap_or(false))
+                    .map(|col| (row, col));
+            }
+            let _ = row_pin.set_high();
+        }
+        found
+    }
+}
+
+impl<R: OutputPin, C: InputPin> KeySource for Keypad<R, C> {
+    /// Scans the keypad if it's time to, returns the byte of a newly pressed (or repeating) key.
+    fn poll(&mut self) -> Option<u8> {
+        let now = get_timestamp_us();
+        if now < self.next_scan {
+            return None;
+        }
+        self.next_scan = now + SCAN_INTERVAL_US;
+
+        let key = self.scan();
+        if key != self.candidate {
+            self.candidate = key;
+            self.candidate_since = now;
+            return None;
+        }
+        if now - self.candidate_since < DEBOUNCE_US {
+            return None;
+        }
+
+        if key != self.pressed {
+            self.pressed = key;
+            self.next_repeat = now + REPEAT_DELAY_US;
+        } else if key.is_some() && now >= self.next_repeat {
+            self.next_repeat = now + REPEAT_INTERVAL_US;
+        } else {
+            return None;
+        }
+        let (row, col) = key?; // A release produces nothing
+        log_trace!("Keypad key at row {}, column {}", row, col);
+        Some(KEYMAP[row][col])
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Reads from the keypad and the UART, whichever has something, so that the rest of the code doesn't care.
+///
+/// The UART is only polled, so its read errors (e.g. a break from a disconnected wire) go unreported.
+pub struct KeypadOrUart<U: UartRx, K: KeySource> {
+    uart: U,
+    // The reads only take `&self`, but scanning needs to update the debouncing state
+    keypad: RefCell<K>,
+}
+
+impl<U: UartRx, K: KeySource> KeypadOrUart<U, K> {
+    pub fn new(uart: U, keypad: K) -> Self {
+        KeypadOrUart { uart, keypad: RefCell::new(keypad) }
+    }
+}
+
+impl<U: UartRx, K: KeySource> UartRx for KeypadOrUart<U, K> {
+    fn read_full_blocking(&self, buf: &mut [u8]) -> Result<(), ReadErrorType> {
+        for byte in buf.iter_mut() {
+            *byte = loop {
+                if let Some(b) = self.keypad.borrow_mut().poll() {
+                    break b;
+                }
+                let mut one = [0_u8; 1];
+                if self.uart.read_available(&mut one) > 0 {
+                    break one[0];
+                }
+            };
+        }
+        Ok(())
+    }
+
+    fn read_available(&self, buf: &mut [u8]) -> usize {
+        let mut n = self.uart.read_available(buf);
+        if n < buf.len() && let Some(b) = self.keypad.borrow_mut().poll() {
+            buf[n] = b;
+            n += 1;
+        }
+        n
+    }
+}
@@ -0,0 +1,604 @@
+use heapless::{String, Vec};
+
+use crate::flash::{Flash, OnboardFlash, PageWriter, KV_REGION, KV_SIZE, PAGE_SIZE, SECTOR_SIZE};
+use crate::persist::{fnv1a_init, fnv1a_update};
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Marks a sector belonging to the store, anything else (erased or left over from an older firmware) is free to erase.
+/// Spells "KVS1" in ASCII.
+const SECTOR_MAGIC: u32 = u32::from_le_bytes(*b"KVS1");
+/// Size of the sector header: magic and the sector's sequence number, each a little-endian u32
+const SECTOR_HEADER_SIZE: usize = 8;
+/// Number of sectors the log goes around
+const SECTOR_COUNT: usize = (KV_SIZE / SECTOR_SIZE) as usize;
+/// Size of the record header: its length (u16, without the padding), key length, flags, sequence number and checksum (u32 each)
+const RECORD_HEADER_SIZE: usize = 12;
+/// Records start at multiples of this
+const RECORD_ALIGN: usize = 4;
+/// Length of a record that was never written, i.e. the end of a sector's records
+const ERASED_LENGTH: u16 = 0xFFFF;
+/// The record deletes its key instead of setting it
+const FLAG_TOMBSTONE: u8 = 0x01;
+/// Longest key, in bytes
+pub const MAX_KEY_SIZE: usize = 16;
+/// Largest value, so that a record with the longest key still fits into an empty sector
+pub const MAX_VALUE_SIZE: usize = SECTOR_SIZE as usize - SECTOR_HEADER_SIZE - RECORD_HEADER_SIZE - MAX_KEY_SIZE;
+/// How many keys `keys()` can list at once
+pub const MAX_KEYS: usize = 32;
+/// How many bytes of a value get hashed at once when checking a record, so that we don't need a sector worth of RAM
+const CHUNK_SIZE: usize = 64;
+
+// HACK: Evaluate the block of code at compile time to assert that constants aren't malformed
+const fn _check_consts() {
+    if SECTOR_COUNT < 3 {
+        core::panic!("The store needs at least three sectors: the one written into, the spare and at least one more with data!");
+    }
+    if MAX_VALUE_SIZE + RECORD_HEADER_SIZE + MAX_KEY_SIZE >= ERASED_LENGTH as usize {
+        core::panic!("The length of a record must fit into its u16 without being mistaken for an erased one!");
+    }
+}
+const _: () = _check_consts();
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Where the value of a record gets written, either into the flash or just into the checksum beforehand, see `set_with()`.
+pub trait ValueWriter {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), CustomError>;
+}
+
+impl<F: Flash> ValueWriter for PageWriter<'_, F> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), CustomError> {
+        PageWriter::write(self, bytes)
+    }
+}
+
+/// Only hashes and counts the bytes, so that the header (which comes first) can be written before the value itself
+struct Checksummer {
+    hash: u32,
+    len: usize,
+}
+
+impl ValueWriter for Checksummer {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), CustomError> {
+        self.hash = fnv1a_update(self.hash, bytes);
+        self.len += bytes.len();
+        Ok(())
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// An intact record, with its key copied out of the flash. The value stays there, see `read_value()`.
+#[derive(Clone)]
+struct Record {
+    sector: usize,
+    key: Vec<u8, MAX_KEY_SIZE>,
+    /// Offset of the value in the flash
+    value_offset: u32,
+    value_len: usize,
+    sequence: u32,
+    tombstone: bool,
+}
+
+impl Record {
+    /// Copies the value into the beginning of `buf` and returns that part of it, or `CapacityError` if it doesn't fit.
+    fn read_value<'b>(&self, flash: &impl Flash, buf: &'b mut [u8]) -> Result<&'b [u8], CustomError> {
+        let value = buf.get_mut(..self.value_len).ok_or(CE::CapacityError)?;
+        flash.read(self.value_offset, value)?;
+        Ok(value)
+    }
+}
+
+/// What's at a position within a sector, see `entry_at()`
+enum Entry {
+    /// An intact record, and the position after it
+    Record(Record, usize),
+    /// A record with a wrong checksum (cut short by a power loss), and the position after it
+    Torn(usize),
+    /// The free space, starting here
+    End(usize),
+}
+
+fn sector_offset(sector: usize) -> u32 {
+    KV_REGION + sector as u32 * SECTOR_SIZE
+}
+
+fn read_u32(bytes: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes(bytes[i..(i + 4)].try_into().expect("Subslice is exactly 4 bytes long"))
+}
+
+/// Size a record takes in the sector, including the padding
+const fn record_size(key_len: usize, value_len: usize) -> usize {
+    (RECORD_HEADER_SIZE + key_len + value_len).next_multiple_of(RECORD_ALIGN)
+}
+
+/// The checksum covers everything but the length and itself, those are checked by the parsing
+fn record_checksum(flags: u8, sequence: u32, key: &[u8]) -> u32 {
+    fnv1a_update(fnv1a_update(fnv1a_update(fnv1a_init(), &[flags]), &sequence.to_le_bytes()), key)
+}
+
+/// The sequence number of the sector, `None` if it doesn't belong to the store (yet)
+fn sector_sequence(flash: &impl Flash, sector: usize) -> Result<Option<u32>, CustomError> {
+    let mut header = [0_u8; SECTOR_HEADER_SIZE];
+    flash.read(sector_offset(sector), &mut header)?;
+    Ok((read_u32(&header, 0) == SECTOR_MAGIC).then(|| read_u32(&header, 4)))
+}
+
+/// Whether the whole sector reads as erased
+fn is_erased(flash: &impl Flash, sector: usize) -> Result<bool, CustomError> {
+    let mut page = [0_u8; PAGE_SIZE];
+    for start in (0..SECTOR_SIZE).step_by(PAGE_SIZE) {
+        flash.read(sector_offset(sector) + start, &mut page)?;
+        if page.iter().any(|&b| b != 0xFF) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Reads what's at the position (within the sector), which has to be where a record starts.
+///
+/// Garbage where a length should be makes the rest of the sector unusable, it then reports no free space,
+/// so that nothing gets written over the garbage.
+fn entry_at(flash: &impl Flash, sector: usize, pos: usize) -> Result<Entry, CustomError> {
+    const SIZE: usize = SECTOR_SIZE as usize;
+    if pos + RECORD_HEADER_SIZE > SIZE {
+        return Ok(Entry::End(SIZE));
+    }
+    let offset = sector_offset(sector) + pos as u32;
+    let mut header = [0_u8; RECORD_HEADER_SIZE];
+    flash.read(offset, &mut header)?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    if len == ERASED_LENGTH {
+        return Ok(Entry::End(pos));
+    }
+    let (len, key_len, flags) = (len as usize, header[2] as usize, header[3]);
+    if key_len > MAX_KEY_SIZE || len < RECORD_HEADER_SIZE + key_len || pos + len > SIZE {
+        log_warn!("Garbage in key-value sector {} at {}, skipping the rest of it", sector, pos);
+        return Ok(Entry::End(SIZE));
+    }
+
+    let mut key_buf = [0_u8; MAX_KEY_SIZE];
+    let key = &mut key_buf[..key_len];
+    flash.read(offset + RECORD_HEADER_SIZE as u32, key)?;
+    let sequence = read_u32(&header, 4);
+    let value_offset = offset + (RECORD_HEADER_SIZE + key_len) as u32;
+    let value_len = len - RECORD_HEADER_SIZE - key_len;
+    let mut hash = record_checksum(flags, sequence, key);
+    let mut chunk_buf = [0_u8; CHUNK_SIZE];
+    for start in (0..value_len).step_by(CHUNK_SIZE) {
+        let chunk = &mut chunk_buf[..CHUNK_SIZE.min(value_len - start)];
+        flash.read(value_offset + start as u32, chunk)?;
+        hash = fnv1a_update(hash, chunk);
+    }
+
+    let next = pos + record_size(key_len, value_len);
+    if hash != read_u32(&header, 8) {
+        log_warn!("Skipping a torn record in key-value sector {} at {}", sector, pos);
+        return Ok(Entry::Torn(next));
+    }
+    let key = Vec::from_slice(key).map_err(|_| CE::Impossible)?; // We checked its length above
+    Ok(Entry::Record(Record { sector, key, value_offset, value_len, sequence, tombstone: flags & FLAG_TOMBSTONE != 0 }, next))
+}
+
+/// Calls `f` on each intact record of the sector, oldest first, and returns the offset (within the sector) of its free space.
+/// Stops at the first error `f` returns.
+fn walk(flash: &impl Flash, sector: usize, mut f: impl FnMut(Record) -> Result<(), CustomError>) -> Result<usize, CustomError> {
+    let mut pos = SECTOR_HEADER_SIZE;
+    loop {
+        match entry_at(flash, sector, pos)? {
+            Entry::Record(record, next) => {
+                f(record)?;
+                pos = next;
+            },
+            Entry::Torn(next) => pos = next,
+            Entry::End(free) => return Ok(free),
+        }
+    }
+}
+
+/// Calls `f` on each intact record of the whole store, in no particular order.
+fn walk_all(flash: &impl Flash, mut f: impl FnMut(Record) -> Result<(), CustomError>) -> Result<(), CustomError> {
+    for sector in 0..SECTOR_COUNT {
+        if sector_sequence(flash, sector)?.is_some() {
+            walk(flash, sector, &mut f)?;
+        }
+    }
+    Ok(())
+}
+
+/// The newest record of the key, whether it sets or deletes it
+fn newest(flash: &impl Flash, key: &[u8]) -> Result<Option<Record>, CustomError> {
+    let mut newest: Option<Record> = None;
+    walk_all(flash, |record| {
+        if record.key == key && newest.as_ref().is_none_or(|n| record.sequence > n.sequence) {
+            newest = Some(record);
+        }
+        Ok(())
+    })?;
+    Ok(newest)
+}
+
+/// Whether the record has to be copied before its sector gets erased: it's the newest one of its key,
+/// and if it's a tombstone, there's an older record of the key left elsewhere that it hides.
+///
+/// A copy of it (with the same sequence number) outside the sector means it's been copied already, by a collection cut short.
+fn is_live(flash: &impl Flash, record: &Record) -> Result<bool, CustomError> {
+    let (mut superseded, mut hides_something) = (false, false);
+    walk_all(flash, |other| {
+        if other.key != record.key {
+            return Ok(());
+        }
+        if other.sequence > record.sequence || (other.sequence == record.sequence && other.sector != record.sector) {
+            superseded = true;
+        } else if other.sequence < record.sequence && other.sector != record.sector {
+            hides_something = true;
+        }
+        Ok(())
+    })?;
+    Ok(!superseded && (!record.tombstone || hides_something))
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Where the log currently is, found by looking at all the sectors
+struct Log<'f, F: Flash> {
+    flash: &'f mut F,
+    /// The sector being written into, `None` if the store is empty
+    current: Option<usize>,
+    current_sequence: u32,
+    /// Offset of the free space within the current sector
+    free: usize,
+    /// The sequence number of the next record
+    next_sequence: u32,
+}
+
+impl<'f, F: Flash> Log<'f, F> {
+    fn mount(flash: &'f mut F) -> Result<Self, CustomError> {
+        let mut log = Log { flash, current: None, current_sequence: 0, free: SECTOR_SIZE as usize, next_sequence: 0 };
+        for sector in 0..SECTOR_COUNT {
+            if let Some(sequence) = sector_sequence(&*log.flash, sector)?
+                && log.current.is_none_or(|_| sequence > log.current_sequence)
+            {
+                log.current = Some(sector);
+                log.current_sequence = sequence;
+            }
+        }
+        walk_all(&*log.flash, |record| {
+            log.next_sequence = log.next_sequence.max(record.sequence.wrapping_add(1));
+            Ok(())
+        })?;
+
+        let Some(current) = log.current else {
+            return Ok(log);
+        };
+        log.free = walk(&*log.flash, current, |_| Ok(()))?;
+        // The sector after the current one is the spare, unless a power loss cut its collection short
+        let after = (current + 1) % SECTOR_COUNT;
+        if sector_sequence(&*log.flash, after)?.is_some() {
+            log_warn!("Finishing the collection of key-value sector {}", after);
+            log.collect(after)?;
+        }
+        Ok(log)
+    }
+
+    /// Appends a record, its value written by `fill` (twice, see `Checksummer`). Returns `CapacityError` if it doesn't fit
+    /// into the current sector, and `BadInput` if `fill` writes a different length than `value_len`.
+    fn append(&mut self, key: &[u8], flags: u8, sequence: u32, value_len: usize, fill: &dyn Fn(&mut dyn ValueWriter) -> Result<(), CustomError>) -> Result<(), CustomError> {
+        let Some(current) = self.current else {
+            return Err(CE::CapacityError);
+        };
+        let size = record_size(key.len(), value_len);
+        if self.free + size > SECTOR_SIZE as usize {
+            return Err(CE::CapacityError);
+        }
+
+        let mut checksummer = Checksummer { hash: record_checksum(flags, sequence, key), len: 0 };
+        fill(&mut checksummer)?;
+        if checksummer.len != value_len {
+            log_error!("Key-value record claimed {} bytes, but wrote {}", value_len, checksummer.len);
+            return Err(CE::BadInput);
+        }
+
+        let mut writer = PageWriter::new(&mut *self.flash, sector_offset(current) + self.free as u32, size as u32);
+        writer.write(&((RECORD_HEADER_SIZE + key.len() + value_len) as u16).to_le_bytes())?;
+        writer.write(&[key.len() as u8, flags])?;
+        writer.write(&sequence.to_le_bytes())?;
+        writer.write(&checksummer.hash.to_le_bytes())?;
+        writer.write(key)?;
+        fill(&mut writer)?;
+        writer.finish()?;
+        self.free += size;
+        Ok(())
+    }
+
+    /// Moves on to the spare sector, and collects the one after it (the oldest), which then becomes the new spare.
+    fn advance(&mut self) -> Result<(), CustomError> {
+        let (next, sequence) = match self.current {
+            Some(current) => ((current + 1) % SECTOR_COUNT, self.current_sequence.wrapping_add(1)),
+            None => (0, 0),
+        };
+        // Usually it's the spare, erased by the last collection already
+        if !is_erased(&*self.flash, next)? {
+            self.flash.erase(sector_offset(next), SECTOR_SIZE)?;
+        }
+        let mut writer = PageWriter::new(&mut *self.flash, sector_offset(next), SECTOR_HEADER_SIZE as u32);
+        writer.write(&SECTOR_MAGIC.to_le_bytes())?;
+        writer.write(&sequence.to_le_bytes())?;
+        writer.finish()?;
+        self.current = Some(next);
+        self.current_sequence = sequence;
+        self.free = SECTOR_HEADER_SIZE;
+        log_debug!("Key-value store moved on to sector {}", next);
+
+        let oldest = (next + 1) % SECTOR_COUNT;
+        if sector_sequence(&*self.flash, oldest)?.is_some() {
+            self.collect(oldest)?;
+        }
+        Ok(())
+    }
+
+    /// Copies the live records of the sector into the current one (keeping their sequence numbers), then erases it.
+    fn collect(&mut self, sector: usize) -> Result<(), CustomError> {
+        let (mut copied, mut total) = (0, 0);
+        let mut value_buf = [0_u8; MAX_VALUE_SIZE];
+        // Not by `walk()`, which would keep the flash borrowed while we append
+        let mut pos = SECTOR_HEADER_SIZE;
+        loop {
+            let record = match entry_at(&*self.flash, sector, pos)? {
+                Entry::Record(record, next) => {
+                    pos = next;
+                    record
+                },
+                Entry::Torn(next) => {
+                    pos = next;
+                    continue;
+                },
+                Entry::End(_) => break,
+            };
+            total += 1;
+            if !is_live(&*self.flash, &record)? {
+                continue;
+            }
+            let value = record.read_value(&*self.flash, &mut value_buf)?;
+            let flags = if record.tombstone { FLAG_TOMBSTONE } else { 0 };
+            self.append(&record.key, flags, record.sequence, value.len(), &|writer| writer.write(value))
+                .inspect_err(|_| log_error!("Key-value store is full, can't collect sector {}", sector))?;
+            copied += 1;
+        }
+        self.flash.erase(sector_offset(sector), SECTOR_SIZE)?;
+        log_debug!("Collected key-value sector {}, {} of {} records copied", sector, copied, total);
+        Ok(())
+    }
+
+    /// Appends the record, moving on to the next sectors as many times as it takes to make room.
+    fn write(&mut self, key: &[u8], flags: u8, value_len: usize, fill: &dyn Fn(&mut dyn ValueWriter) -> Result<(), CustomError>) -> Result<(), CustomError> {
+        let sequence = self.next_sequence;
+        // Going around the whole log without finding room means the live records take it all
+        for _ in 0..=SECTOR_COUNT {
+            match self.append(key, flags, sequence, value_len, fill) {
+                Err(CE::CapacityError) => self.advance()?,
+                result => return result,
+            }
+        }
+        log_error!("Key-value store is full");
+        Err(CE::CapacityError)
+    }
+}
+
+fn check_key(key: &str) -> Result<(), CustomError> {
+    if key.is_empty() || key.len() > MAX_KEY_SIZE {
+        log_error!("Invalid key-value store key {:?}, it must be 1 to {} bytes", key, MAX_KEY_SIZE);
+        return Err(CE::BadInput);
+    }
+    Ok(())
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// The key-value store in `KV_REGION` of the flash. The functions below use the onboard one,
+/// this is so that it can be tested against `RamFlash` on the host.
+///
+/// The store is log-structured: every write appends a record with a sequence number to the current sector, the newest record
+/// of a key wins. Once a sector is full, the log moves on to the next one (going around the region), and the oldest sector
+/// gets collected: its records that are still the newest get copied over and it's erased. That spreads the wear over all of them.
+struct KvStore<F: Flash> {
+    flash: F,
+}
+
+impl<F: Flash> KvStore<F> {
+    const fn new(flash: F) -> Self {
+        KvStore { flash }
+    }
+
+    fn get<'b>(&self, key: &str, buf: &'b mut [u8]) -> Result<Option<&'b [u8]>, CustomError> {
+        match newest(&self.flash, key.as_bytes())? {
+            Some(record) if !record.tombstone => Ok(Some(record.read_value(&self.flash, buf)?)),
+            _ => Ok(None),
+        }
+    }
+
+    fn contains(&self, key: &str) -> Result<bool, CustomError> {
+        Ok(newest(&self.flash, key.as_bytes())?.is_some_and(|record| !record.tombstone))
+    }
+
+    fn set_with(&mut self, key: &str, value_len: usize, fill: &dyn Fn(&mut dyn ValueWriter) -> Result<(), CustomError>) -> Result<(), CustomError> {
+        check_key(key)?;
+        if value_len > MAX_VALUE_SIZE {
+            log_error!("Value of {} bytes is too big for the key-value store, {} at most", value_len, MAX_VALUE_SIZE);
+            return Err(CE::CapacityError);
+        }
+        Log::mount(&mut self.flash)?.write(key.as_bytes(), 0, value_len, fill)?;
+        log_debug!("Set key {:?} to {} bytes", key, value_len);
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> Result<bool, CustomError> {
+        check_key(key)?;
+        if !self.contains(key)? {
+            return Ok(false);
+        }
+        Log::mount(&mut self.flash)?.write(key.as_bytes(), FLAG_TOMBSTONE, 0, &|_| Ok(()))?;
+        log_debug!("Removed key {:?}", key);
+        Ok(true)
+    }
+
+    fn keys(&self, prefix: &str) -> Result<Vec<String<MAX_KEY_SIZE>, MAX_KEYS>, CustomError> {
+        let mut keys: Vec<String<MAX_KEY_SIZE>, MAX_KEYS> = Vec::new();
+        walk_all(&self.flash, |record| {
+            let Ok(key) = core::str::from_utf8(&record.key) else { return Ok(()) };
+            if key.starts_with(prefix) && !keys.iter().any(|k| k == key) && self.contains(key)? {
+                keys.push(String::try_from(key)?).map_err(|_| CE::CapacityError)?;
+            }
+            Ok(())
+        })?;
+        Ok(keys)
+    }
+}
+
+/// Copies the value of the key into the beginning of `buf` and returns that part of it, `None` if the key isn't set.
+/// Returns `CapacityError` if it doesn't fit, a buffer of `MAX_VALUE_SIZE` always does. See `KvStore` for how it works.
+pub fn get<'b>(key: &str, buf: &'b mut [u8]) -> Result<Option<&'b [u8]>, CustomError> {
+    KvStore::new(OnboardFlash).get(key, buf)
+}
+
+/// Whether the key is set, without reading its value.
+pub fn contains(key: &str) -> Result<bool, CustomError> {
+    KvStore::new(OnboardFlash).contains(key)
+}
+
+/// Sets the key to the value.
+pub fn set(key: &str, value: &[u8]) -> Result<(), CustomError> {
+    set_with(key, value.len(), &|writer| writer.write(value))
+}
+
+/// Sets the key to a value of `value_len` bytes that `fill` writes, so that big values don't need a buffer.
+/// `fill` gets called twice and has to write the same bytes both times.
+pub fn set_with(key: &str, value_len: usize, fill: &dyn Fn(&mut dyn ValueWriter) -> Result<(), CustomError>) -> Result<(), CustomError> {
+    KvStore::new(OnboardFlash).set_with(key, value_len, fill)
+}
+
+/// Deletes the key, returns whether it was set.
+pub fn remove(key: &str) -> Result<bool, CustomError> {
+    KvStore::new(OnboardFlash).remove(key)
+}
+
+/// Lists the keys that are set and start with `prefix`, in no particular order.
+/// Returns `CapacityError` if there's more than `MAX_KEYS` of them.
+pub fn keys(prefix: &str) -> Result<Vec<String<MAX_KEY_SIZE>, MAX_KEYS>, CustomError> {
+    KvStore::new(OnboardFlash).keys(prefix)
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::{KvStore, MAX_VALUE_SIZE, SECTOR_COUNT};
+    use crate::flash::RamFlash;
+    use crate::custom_error::CE;
+
+    /// A value big enough that only one fits into a sector, so that every write of it moves on to the next one
+    const BIG: usize = 3000;
+
+    fn store() -> KvStore<RamFlash> {
+        KvStore::new(RamFlash::new())
+    }
+
+    fn get(store: &KvStore<RamFlash>, key: &str) -> Option<std::vec::Vec<u8>> {
+        let mut buf = [0_u8; MAX_VALUE_SIZE];
+        store.get(key, &mut buf).unwrap().map(<[u8]>::to_vec)
+    }
+
+    #[test]
+    fn sets_gets_and_removes() {
+        let mut store = store();
+        assert_eq!(get(&store, "a"), None);
+        store.set_with("a", 3, &|w| w.write(b"one")).unwrap();
+        store.set_with("ab", 0, &|_| Ok(())).unwrap();
+        store.set_with("a", 3, &|w| w.write(b"two")).unwrap();
+        assert_eq!(get(&store, "a").as_deref(), Some(&b"two"[..]));
+        assert_eq!(get(&store, "ab").as_deref(), Some(&b""[..]));
+        let mut keys = store.keys("a").unwrap();
+        keys.sort_unstable();
+        assert_eq!(keys, ["a", "ab"]);
+
+        assert_eq!(store.remove("a"), Ok(true));
+        assert_eq!(store.remove("a"), Ok(false));
+        assert_eq!(get(&store, "a"), None);
+        assert_eq!(store.keys("").unwrap(), ["ab"]);
+
+        // Too small a buffer, a bad key, a lying `fill`
+        assert_eq!(store.get("ab", &mut []), Ok(Some(&[][..])));
+        store.set_with("a", 3, &|w| w.write(b"two")).unwrap();
+        assert_eq!(store.get("a", &mut [0; 2]), Err(CE::CapacityError));
+        assert_eq!(store.set_with("", 0, &|_| Ok(())), Err(CE::BadInput));
+        assert_eq!(store.set_with("this key is way too long", 0, &|_| Ok(())), Err(CE::BadInput));
+        assert_eq!(store.set_with("a", 2, &|w| w.write(b"two")), Err(CE::BadInput));
+        assert_eq!(store.set_with("a", MAX_VALUE_SIZE + 1, &|_| Ok(())), Err(CE::CapacityError));
+    }
+
+    #[test]
+    fn collects_around_the_log() {
+        let mut store = store();
+        store.set_with("kept", 4, &|w| w.write(b"kept")).unwrap();
+        store.set_with("gone", 4, &|w| w.write(b"gone")).unwrap();
+        store.remove("gone").unwrap();
+        // Goes around the whole log a few times, collecting every sector over and over
+        for i in 0..(3 * SECTOR_COUNT as u8) {
+            store.set_with("big", BIG, &|w| w.write(&[i; BIG])).unwrap();
+        }
+        assert_eq!(get(&store, "big"), Some(std::vec![3 * SECTOR_COUNT as u8 - 1; BIG]));
+        assert_eq!(get(&store, "kept").as_deref(), Some(&b"kept"[..]));
+        // The tombstone outlived the record it hides, and was dropped only once there was nothing left to hide
+        assert_eq!(get(&store, "gone"), None);
+        assert_eq!(store.keys("").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn survives_a_torn_record() {
+        let mut store = store();
+        store.set_with("a", BIG, &|w| w.write(&[1; BIG])).unwrap();
+        // The header and a page of the value make it, the rest doesn't
+        store.flash.cut_power_after(512);
+        assert_eq!(store.set_with("a", BIG, &|w| w.write(&[2; BIG])), Err(CE::Cancelled));
+        store.flash.restore_power();
+        assert_eq!(get(&store, "a"), Some(std::vec![1; BIG]));
+
+        store.set_with("a", BIG, &|w| w.write(&[3; BIG])).unwrap();
+        assert_eq!(get(&store, "a"), Some(std::vec![3; BIG]));
+    }
+
+    #[test]
+    fn survives_a_power_loss_anywhere_in_a_collection() {
+        let mut store = store();
+        store.set_with("x", 1, &|w| w.write(b"x")).unwrap();
+        store.set_with("y", 1, &|w| w.write(b"y")).unwrap();
+        store.remove("y").unwrap();
+        // Once the log has gone around, every write of a big value collects the oldest sector
+        for i in 0..(SECTOR_COUNT as u8 - 1) {
+            store.set_with("big", BIG, &|w| w.write(&[i; BIG])).unwrap();
+        }
+
+        for budget in (0..(2 * BIG)).step_by(97) {
+            let mut cut = KvStore::new(store.flash.clone());
+            cut.flash.cut_power_after(budget);
+            let result = cut.set_with("big", BIG, &|w| w.write(&[0xAA; BIG]));
+            cut.flash.restore_power();
+
+            let big = get(&cut, "big").unwrap();
+            assert!(big == [0xAA; BIG] || (result.is_err() && big == [SECTOR_COUNT as u8 - 2; BIG]), "Lost the value at {}", budget);
+            assert_eq!(get(&cut, "x").as_deref(), Some(&b"x"[..]), "Lost a key at {}", budget);
+            assert_eq!(get(&cut, "y"), None, "Resurrected a key at {}", budget);
+            // The next write finishes whatever was cut short
+            cut.set_with("big", BIG, &|w| w.write(&[0xBB; BIG])).unwrap();
+            assert_eq!(get(&cut, "big"), Some(std::vec![0xBB; BIG]));
+            assert_eq!(get(&cut, "x").as_deref(), Some(&b"x"[..]));
+        }
+    }
+}
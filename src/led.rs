@@ -0,0 +1,57 @@
+use embedded_hal::digital::OutputPin;
+use rp2040_hal::gpio::{bank0::Gpio25, FunctionSioOutput, Pin, PullDown};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// How long the LED stays on, then off, while blinking, in microseconds
+const BLINK_HALF_PERIOD_US: u64 = 500_000;
+
+/// GPIO25, the Pico's onboard LED. The Pico W has its LED on the wireless chip instead, which we don't drive (see README).
+pub type LedPin = Pin<Gpio25, FunctionSioOutput, PullDown>;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// What the onboard LED should do, see the `led` command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, defmt::Format)]
+pub enum LedMode {
+    #[default] Off,
+    On,
+    Blink,
+}
+
+/// Drives the onboard LED by the mode in the state, blinking it while polling for input.
+pub struct StatusLed {
+    pin: LedPin,
+    lit: bool,
+    /// Timestamp of the next toggle while blinking, in microseconds
+    next_toggle: u64,
+}
+
+impl StatusLed {
+    pub fn new(pin: LedPin) -> Self {
+        StatusLed {
+            pin,
+            lit: false,
+            next_toggle: 0,
+        }
+    }
+
+    /// Turns the LED on or off by the mode, toggling it every `BLINK_HALF_PERIOD_US` while blinking.
+    /// Meant to be called on every key and repeatedly while polling for input.
+    pub fn tick(&mut self, mode: LedMode, now: u64) {
+        let lit = match mode {
+            LedMode::Off => false,
+            LedMode::On => true,
+            LedMode::Blink if now >= self.next_toggle => {
+                self.next_toggle = now + BLINK_HALF_PERIOD_US;
+                !self.lit
+            },
+            LedMode::Blink => self.lit,
+        };
+        if lit != self.lit {
+            self.lit = lit;
+            let _ = if lit { self.pin.set_high() } else { self.pin.set_low() }; // Infallible on the RP2040
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use core::cell::RefCell;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use cortex_m::interrupt::{self, Mutex};
+use heapless::{Deque, String};
+
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// The level we boot with, the same as the `DEFMT_LOG` we used to build with
+const DEFAULT_LEVEL: Level = Level::Debug;
+/// Longest line mirrored to UART, longer ones are cut off
+const MIRROR_LINE_SIZE: usize = 96;
+/// How many lines can wait to be mirrored, newer ones are dropped until the queue gets drained
+const MIRROR_QUEUE_SIZE: usize = 8;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Logs at the given level if the runtime level allows it, and mirrors the message if mirroring is on.
+/// Not to be used directly, see `log_trace!` etc.
+///
+/// The macros take the same arguments as defmt's, which must implement both `defmt::Format`
+/// and `core::fmt::Display` (or `Debug` for `{:?}`), since the mirror formats them as plain text.
+/// defmt's own compile time filter (`DEFMT_LOG`) still applies on top of the runtime level.
+macro_rules! log_at {
+    ($level:ident, $defmt:ident, $fmt:literal $(, $arg:expr)* $(,)?) => {
+        if $crate::log::enabled($crate::log::Level::$level) {
+            #[cfg(not(test))] // There's no probe to log to in the host tests, and the timestamp reads the RP2040's timer
+            defmt::$defmt!($fmt $(, $arg)*);
+            if $crate::log::is_mirrored() {
+                $crate::log::mirror($crate::log::Level::$level, format_args!($fmt $(, $arg)*));
+            }
+        }
+    };
+}
+
+macro_rules! log_trace {
+    ($($arg:tt)+) => { log_at!(Trace, trace, $($arg)+) };
+}
+macro_rules! log_debug {
+    ($($arg:tt)+) => { log_at!(Debug, debug, $($arg)+) };
+}
+macro_rules! log_info {
+    ($($arg:tt)+) => { log_at!(Info, info, $($arg)+) };
+}
+macro_rules! log_warn {
+    ($($arg:tt)+) => { log_at!(Warn, warn, $($arg)+) };
+}
+macro_rules! log_error {
+    ($($arg:tt)+) => { log_at!(Error, error, $($arg)+) };
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Log levels from the most verbose, `Off` silences everything
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+#[repr(u8)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Off,
+}
+
+impl Level {
+    /// All levels, in the order of their `repr(u8)` values
+    const ALL: [Level; 6] = [Level::Trace, Level::Debug, Level::Info, Level::Warn, Level::Error, Level::Off];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Trace => "trace",
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+            Level::Off => "off",
+        }
+    }
+
+    /// Parses the level from its name, as printed by `name()`.
+    pub fn parse(name: &str) -> Result<Self, CustomError> {
+        Self::ALL.into_iter().find(|level| level.name() == name).ok_or_else(|| {
+            log_warn!("Unknown log level {:?}", name);
+            CE::BadInput
+        })
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(DEFAULT_LEVEL as u8);
+static MIRRORED: AtomicBool = AtomicBool::new(false);
+static MIRROR_QUEUE: Mutex<RefCell<Deque<String<MIRROR_LINE_SIZE>, MIRROR_QUEUE_SIZE>>> = Mutex::new(RefCell::new(Deque::new()));
+
+pub fn level() -> Level {
+    // Only ever stored from a `Level`, so the index is always valid
+    Level::ALL[LEVEL.load(Ordering::Relaxed) as usize]
+}
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Whether messages at `level` get logged
+#[inline]
+pub fn enabled(level: Level) -> bool {
+    level >= self::level() && level != Level::Off
+}
+
+pub fn is_mirrored() -> bool {
+    MIRRORED.load(Ordering::Relaxed)
+}
+
+/// Turns the mirroring to UART on or off, turning it off forgets the lines that haven't been sent yet.
+pub fn set_mirrored(mirrored: bool) {
+    MIRRORED.store(mirrored, Ordering::Relaxed);
+    if !mirrored {
+        interrupt::free(|cs| MIRROR_QUEUE.borrow(cs).borrow_mut().clear());
+    }
+}
+
+/// Queues the message to be sent over UART by `drain_mirror()`, since we can't get to the UART from everywhere.
+/// If the queue is full, the message is dropped.
+pub fn mirror(level: Level, args: fmt::Arguments<'_>) {
+    let mut line: String<MIRROR_LINE_SIZE> = String::new();
+    // An error only means the line didn't fit, a cut off line is better than none
+    let _ = write!(line, "[{}] {}", level.name(), args);
+    interrupt::free(|cs| {
+        let _ = MIRROR_QUEUE.borrow(cs).borrow_mut().push_back(line);
+    });
+}
+
+/// Passes the queued lines to `print` one by one, without line endings.
+pub fn drain_mirror(mut print: impl FnMut(&str)) {
+    while let Some(line) = interrupt::free(|cs| MIRROR_QUEUE.borrow(cs).borrow_mut().pop_front()) {
+        print(&line);
+    }
+}
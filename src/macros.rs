@@ -0,0 +1,141 @@
+use heapless::{String, Vec};
+
+use crate::textbox::TEXT_BUFFER_SIZE;
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// How many steps (keystrokes or commands) a macro can have
+const MACRO_SIZE: usize = 64;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// A single recorded step of a macro
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// A key pressed outside of command mode, e.g. a digit or an operator
+    Key(char),
+    /// A whole line entered in command mode, it can't be longer than the textbox anyway
+    Command(String<TEXT_BUFFER_SIZE>),
+}
+
+/// Records keystrokes and commands into RAM and plays them back, see the `macro` command.
+///
+/// The macro doesn't survive a reboot.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MacroRecorder {
+    steps: Vec<Step, MACRO_SIZE>,
+    recording: bool,
+    /// How many more times the macro is to be played, including the current one
+    repeats_left: usize,
+    /// Index of the next step to play
+    position: usize,
+}
+
+impl MacroRecorder {
+    pub const fn new() -> Self {
+        MacroRecorder {
+            steps: Vec::new(),
+            recording: false,
+            repeats_left: 0,
+            position: 0,
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.repeats_left > 0
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    /// Forgets the old macro and starts recording a new one.
+    pub fn start_recording(&mut self) -> Result<(), CustomError> {
+        if self.is_playing() {
+            log_warn!("Can't record a macro while playing one");
+            return Err(CE::BadInput);
+        }
+        self.steps.clear();
+        self.recording = true;
+        Ok(())
+    }
+
+    /// Stops recording, returns the number of recorded steps.
+    pub fn stop_recording(&mut self) -> Result<usize, CustomError> {
+        if !self.recording {
+            log_warn!("Can't stop recording a macro, not recording any");
+            return Err(CE::BadInput);
+        }
+        self.recording = false;
+        Ok(self.steps.len())
+    }
+
+    /// Records the key if we're recording, otherwise does nothing.
+    pub fn record_key(&mut self, key: char) -> Result<(), CustomError> {
+        self.record(Step::Key(key))
+    }
+
+    /// Records the command line if we're recording, otherwise does nothing.
+    pub fn record_command(&mut self, command: &str) -> Result<(), CustomError> {
+        if !self.recording {
+            return Ok(()); // Saves us the conversion
+        }
+        self.record(Step::Command(String::try_from(command)?))
+    }
+
+    /// On error, the recording stops, so that we don't play back a macro with a step missing in the middle.
+    fn record(&mut self, step: Step) -> Result<(), CustomError> {
+        if !self.recording {
+            return Ok(());
+        }
+        if self.steps.push(step).is_err() {
+            log_warn!("Macro is full ({} steps), recording stopped", MACRO_SIZE);
+            self.recording = false;
+            return Err(CE::CapacityError);
+        }
+        Ok(())
+    }
+
+    /// Starts playing the macro `times` times, the steps are then taken out by `next_step()`.
+    pub fn play(&mut self, times: usize) -> Result<(), CustomError> {
+        if self.recording || self.is_playing() {
+            log_warn!("Can't play a macro while recording or playing one");
+            return Err(CE::BadInput);
+        }
+        if self.steps.is_empty() {
+            log_warn!("Can't play a macro, none was recorded");
+            return Err(CE::BadInput);
+        }
+        self.repeats_left = times;
+        self.position = 0;
+        Ok(())
+    }
+
+    /// Stops playing the macro, e.g. after a step failed.
+    pub fn abort(&mut self) {
+        if self.is_playing() {
+            log_info!("Aborting macro playback at step {}", self.position);
+        }
+        self.repeats_left = 0;
+    }
+
+    /// Returns the next step to play, or `None` if we're not playing (anymore).
+    pub fn next_step(&mut self) -> Option<Step> {
+        if !self.is_playing() {
+            return None;
+        }
+        let step = self.steps.get(self.position)?.clone();
+
+        self.position += 1;
+        if self.position == self.steps.len() {
+            self.position = 0;
+            self.repeats_left -= 1;
+        }
+        Some(step)
+    }
+}
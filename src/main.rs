@@ -1,12 +1,19 @@
-#![no_std]
-#![no_main]
+// The unit tests run on the host (see README), with the standard library and its own `main()`
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
+// Most of the firmware is of no use to the tests
+#![cfg_attr(test, allow(dead_code, unused_imports))]
 
 // We start RTT in no-blocking mode, `probe-run` will switch to blocking mode.
 // Do not disconnect the probe while the program is running, unless you stop probe-run first.
 // (Then it will revert to nonblocking: https://github.com/probe-rs/probe-rs/issues/2425)
-use defmt::*;
+#[cfg(not(test))] // Neither is a dependency on the host, see `Cargo.toml`
 use defmt_rtt as _;
+#[cfg(not(test))]
 use panic_probe as _;
+// Only with the `alloc` feature, so that the default build stays fully static
+#[cfg(feature = "alloc")]
+extern crate alloc;
 
 use rp2040_hal::{
     self as hal,
@@ -21,24 +28,121 @@ use rp2040_hal::{
 };
 use core::cell::RefCell;
 use embedded_graphics::{image::Image, prelude::*, pixelcolor::BinaryColor};
-use ssd1306::{Ssd1306, mode::BufferedGraphicsMode, prelude::*};
+#[cfg(not(feature = "ssd1327"))]
+use ssd1306::{Ssd1306, prelude::*};
 use tinybmp::Bmp;
 use heapless::Vec;
 
+#[macro_use] // Must come before the other modules, so that they can use the `log_*!` macros
+mod log;
 mod stack;
 use stack::*;
+mod stack_set;
+use stack_set::{StackSet, WORKSPACE_COUNT};
+mod bigdigits;
 mod textbox;
 use textbox::*;
 mod decfix;
 use decfix::DecimalFixed;
 mod custom_error;
 use custom_error::{
-    CustomError, // Never use `CustomError::*`, it could cause unobvious bugs!
+    CustomError,
     CE, // Using the type alias from `custom_error.rs`
-    IntErrorKindClone as IEKC,
+    WithContext,
+    ResultExt,
 };
+mod display;
+use display::{Palette, Panel};
+mod frame;
+use frame::FrameScheduler;
+mod i2c_recovery;
+#[cfg(feature = "ssd1327")]
+mod ssd1327;
 mod command_mode;
-use command_mode::handle_commands;
+use command_mode::{handle_commands, run_command};
+mod registers;
+mod stats;
+mod vector;
+mod poly;
+mod plot;
+mod chart;
+mod screenshot;
+mod scpi;
+mod modbus;
+mod numtheory;
+mod wide;
+mod polar;
+mod db;
+mod state;
+use state::CalcState;
+mod flash;
+mod kv;
+mod persist;
+mod snapshots;
+mod settings;
+use settings::Settings;
+mod screensaver;
+mod commands;
+mod menu;
+mod pages;
+use pages::{PageData, PageKind, Status};
+mod args;
+mod macros;
+mod stopwatch;
+mod vsys;
+mod selftest;
+mod hiltest;
+use vsys::Vsys;
+mod sensors;
+mod ambient;
+use ambient::LightSensor;
+mod night;
+mod clock;
+use clock::WallClock;
+mod power;
+use power::Sleeper;
+#[cfg(any(feature = "alloc", test))]
+mod heap;
+mod led;
+use led::LedMode;
+use macros::Step;
+mod uart_rx;
+use uart_rx::UartRx;
+mod uart_tx;
+mod hostlink;
+use hostlink::HostLink;
+#[cfg(feature = "usb")]
+mod usb;
+#[cfg(feature = "keypad")]
+mod keypad;
+#[cfg(feature = "pio-keypad")]
+mod pio_keypad;
+#[cfg(feature = "encoder")]
+mod encoder;
+#[cfg(feature = "boot-button")]
+mod boot_button;
+#[cfg(any(feature = "i2c-peripheral", test))]
+mod i2c_peripheral;
+#[cfg(all(feature = "i2c-peripheral", feature = "sensors"))]
+compile_error!("The `i2c-peripheral` and `sensors` features both need I2C1, enable just one of them");
+mod baud;
+#[cfg(feature = "dma-rx")]
+mod dma_rx;
+#[cfg(feature = "dma-rx")]
+use dma_rx::{DmaReader, RingBuffer};
+mod charset;
+mod toast;
+mod icons;
+use icons::Icon;
+mod paste;
+mod errlog;
+use errlog::ErrorLog;
+use paste::PasteDetector;
+#[cfg(not(test))] // The handler is Cortex-M assembly
+mod fault;
+use toast::Toast;
+mod spill;
+use spill::{FlashSpill, SpillStore};
 
 // We store the boot2 code in its own section so the linker script can find it
 // and place it at the correct address in flash, as required by the RP2040.
@@ -48,6 +152,13 @@ pub static BOOT2: [u8; 256] = rp2040_boot2::BOOT_LOADER_W25Q080;
 
 // 1 MHz, the maximum speed for I²C on the RP2040 (so-called Fast Mode Plus; datasheet 4.3.3), and the SSD1306 can handle it well
 const I2C_FREQ: hal::fugit::HertzU32 = hal::fugit::HertzU32::kHz(1000);
+/// How much a step of the Up and Down keys (or the rotary encoder) changes the contrast by while adjusting it
+const CONTRAST_STEP: u8 = 16;
+/// Longest wait between two polls for input, in microseconds, see `Sleeper::idle_until()`. Often enough for the screensaver's frames,
+/// the LED's blinking and the clock; the rest wakes us up by itself, or gets its own deadline.
+const IDLE_POLL_US: u64 = 100_000;
+/// The same, for the inputs that can't wake us up: the keypads, the rotary encoder and the boot button while it's held
+const INPUT_POLL_US: u64 = 10_000;
 
 const GRAVE_ERROR_BMP: Result<Bmp<'static, BinaryColor>, tinybmp::ParseError> = Bmp::from_slice(include_bytes!("calc_grave_err.bmp"));
 const ERROR_BMP: Result<Bmp<'static, BinaryColor>, tinybmp::ParseError> = Bmp::from_slice(include_bytes!("calc_err.bmp"));
@@ -71,15 +182,18 @@ pub fn get_timestamp_us() -> u64 {
 }
 defmt::timestamp!("{=u64:us}", { get_timestamp_us() });
 
+#[cfg(not(test))]
 #[hal::entry]
 fn main() -> ! {
-    info!("Program start");
+    log_info!("Program start");
+    #[cfg(feature = "alloc")]
+    heap::init();
     let mut peri = pac::Peripherals::take().expect("We just booted, so the peripherals should be available.");
     let core = pac::CorePeripherals::take().expect("We just booted, so the core peripherals should be available.");
     let mut watchdog = Watchdog::new(peri.WATCHDOG);
     let sio = Sio::new(peri.SIO);
 
-    let clocks = init_clocks_and_plls(
+    let mut clocks = init_clocks_and_plls(
         12_000_000u32,
         peri.XOSC,
         peri.CLOCKS,
@@ -89,7 +203,7 @@ fn main() -> ! {
         &mut watchdog,
     ).expect("Something went wrong when initializing the clocks.");
     let mut delay = cortex_m::delay::Delay::new(core.SYST, clocks.system_clock.freq().to_Hz());
-    trace!("Clocks initialized");
+    log_trace!("Clocks initialized");
 
     let pins = hal::gpio::Pins::new(
         peri.IO_BANK0,
@@ -98,6 +212,35 @@ fn main() -> ! {
         &mut peri.RESETS,
     );
 
+    // A button to ground, waking us up from sleep; the pin only has to be configured, the sleeper reads its events by the number
+    let _wake_pin = pins.gpio22.into_pull_up_input();
+    let mut sleeper = Sleeper::new(core.SCB, &mut clocks);
+    log_trace!("Sleep configured");
+    let mut status_led = led::StatusLed::new(pins.gpio25.into_push_pull_output());
+    #[cfg(feature = "boot-button")]
+    let mut boot_button = boot_button::BootButton::new(pins.gpio21.into_pull_up_input());
+    // Another microcontroller's view of the stack, see `i2c_peripheral.rs`
+    #[cfg(feature = "i2c-peripheral")]
+    let mut i2c_head = i2c_peripheral::I2cHead::new(hal::I2C::new_peripheral_event_iterator(
+        peri.I2C1,
+        pins.gpio2.reconfigure::<hal::gpio::FunctionI2C, hal::gpio::PullUp>(),
+        pins.gpio3.reconfigure::<hal::gpio::FunctionI2C, hal::gpio::PullUp>(),
+        &mut peri.RESETS,
+        i2c_peripheral::ADDRESS,
+    ));
+
+    let adc = hal::adc::Adc::new(peri.ADC, &mut peri.RESETS);
+    let vsys_pin = hal::adc::AdcPin::new(pins.gpio29.into_floating_input())
+        .expect("GPIO29 is an ADC pin");
+    let mut vsys = Vsys::new(adc, vsys_pin);
+    let light_pin = hal::adc::AdcPin::new(pins.gpio26.into_floating_input())
+        .expect("GPIO26 is an ADC pin");
+    let mut light_sensor = LightSensor::new(light_pin);
+    log_trace!("ADC initialized");
+
+    let mut clock = WallClock::new(peri.RTC, clocks.rtc_clock, &mut peri.RESETS);
+    log_trace!("RTC initialized");
+
     let i2c = hal::I2C::i2c0(
         peri.I2C0,
         pins.gpio8.reconfigure(), // The stuff we're reconfiguring *into* is inferred from the context
@@ -106,14 +249,49 @@ fn main() -> ! {
         &mut peri.RESETS,
         &clocks.peripheral_clock,
     );
-    trace!("I²C initialized");
+    log_trace!("I²C initialized");
+    #[cfg(feature = "sensors")]
+    {
+        sensors::init(hal::I2C::i2c1(
+            peri.I2C1,
+            pins.gpio6.reconfigure(),
+            pins.gpio7.reconfigure(),
+            sensors::BUS_FREQ,
+            &mut peri.RESETS,
+            &clocks.peripheral_clock,
+        ));
+        log_trace!("Sensor bus initialized");
+    }
+
+    #[cfg(not(feature = "ssd1327"))]
+    let mut disp = {
+        let iface = ssd1306::I2CDisplayInterface::new(i2c);
+        let mut disp = Ssd1306::new(iface, DisplaySize128x64, DisplayRotation::Rotate0)
+            .into_buffered_graphics_mode();
+        disp.init().expect("Failed to initialize display. Check wiring.");
+        disp
+    };
+    // The 128x128 modules usually sit at the other address the SSD1306 can have
+    #[cfg(feature = "ssd1327")]
+    let mut disp = {
+        let iface = ssd1306::I2CDisplayInterface::new_alternate_address(i2c);
+        let mut disp = ssd1327::Ssd1327::new(iface);
+        disp.init().expect("Failed to initialize display. Check wiring.");
+        disp
+    };
+    disp.set_contrast(u8::MAX).expect("Failed to set display brightness.");
+    log_trace!("Display initialized");
 
-    let iface = ssd1306::I2CDisplayInterface::new(i2c);
-    let mut disp = Ssd1306::new(iface, DisplaySize128x64, DisplayRotation::Rotate0)
-        .into_buffered_graphics_mode();
-    disp.init().expect("Failed to initialize display. Check wiring.");
-    disp.set_brightness(Brightness::BRIGHTEST).expect("Failed to set display brightness.");
-    trace!("Display initialized");
+    // Before the UART, since it has the baud rate
+    let settings = match persist::restore_settings() {
+        Ok(Some(settings)) => settings,
+        Ok(None) => Settings::new(),
+        // The defaults will do
+        Err(e) => {
+            log_warn!("Failed to restore settings from flash: {:?}", e);
+            Settings::new()
+        },
+    };
 
     // Let me ask one question: Why the hell can't this be as straightforward as I²C is?
     let uart = hal::uart::UartPeripheral::new(
@@ -122,71 +300,428 @@ fn main() -> ! {
         &mut peri.RESETS
     )
     .enable(
-        hal::uart::UartConfig::default(), // Default config is a sane 115200 8N1
+        // 8N1 like the default config, only the baud rate can be changed (by the `baud` command)
+        hal::uart::UartConfig::new(
+            hal::fugit::HertzU32::from_raw(settings.baud),
+            hal::uart::DataBits::Eight,
+            None,
+            hal::uart::StopBits::One
+        ),
         clocks.peripheral_clock.freq()
     )
     .expect("Failed to initialize UART peripheral: bad configuration provided.");
+    // Not a part of the pinout above, so that it can be turned on and off by the `flow` command
+    if settings.flow_control && let Err(e) = baud::set_flow_control(true) {
+        log_warn!("Failed to turn on the saved UART flow control: {:?}", e);
+    }
     let (rx, tx) = uart.split();
-    trace!("UART initialized");
+    #[cfg(any(feature = "dma-rx", feature = "pio-keypad"))]
+    let dma = {
+        use hal::dma::DMAExt;
+        peri.DMA.split(&mut peri.RESETS)
+    };
+    // Pasted scripts would overrun the UART's FIFO during long display flushes, the DMA keeps receiving in the background
+    #[cfg(feature = "dma-rx")]
+    let rx = {
+        let ring = cortex_m::singleton!(: RingBuffer = RingBuffer([0; dma_rx::RING_SIZE]))
+            .expect("The ring buffer is only created once");
+        DmaReader::new(dma.ch0, rx, ring)
+    };
+    #[cfg(feature = "pio-keypad")]
+    let rx = {
+        use hal::gpio::{FunctionPio0, PullNone, PullUp};
+        // Owned by the state machine from now on, the pins themselves aren't needed anymore
+        let _rows = (
+            pins.gpio10.reconfigure::<FunctionPio0, PullNone>(),
+            pins.gpio11.reconfigure::<FunctionPio0, PullNone>(),
+            pins.gpio12.reconfigure::<FunctionPio0, PullNone>(),
+            pins.gpio13.reconfigure::<FunctionPio0, PullNone>(),
+        );
+        let _cols = (
+            pins.gpio14.reconfigure::<FunctionPio0, PullUp>(),
+            pins.gpio15.reconfigure::<FunctionPio0, PullUp>(),
+            pins.gpio16.reconfigure::<FunctionPio0, PullUp>(),
+            pins.gpio17.reconfigure::<FunctionPio0, PullUp>(),
+        );
+        let snapshots = cortex_m::singleton!(: pio_keypad::SnapshotBuffer = pio_keypad::SnapshotBuffer([0; pio_keypad::SNAPSHOT_COUNT]))
+            .expect("The snapshot buffer is only created once");
+        let keypad = pio_keypad::PioKeypad::new(peri.PIO0, dma.ch1, snapshots, clocks.system_clock.freq().to_Hz(), &mut peri.RESETS);
+        keypad::KeypadOrUart::new(rx, keypad)
+    };
+    #[cfg(all(feature = "keypad", not(feature = "pio-keypad")))]
+    let rx = {
+        use hal::gpio::PinState;
+        // Rows idle high and get driven low one by one, a pressed key pulls its column low
+        let rows = [
+            pins.gpio10.into_push_pull_output_in_state(PinState::High).into_dyn_pin(),
+            pins.gpio11.into_push_pull_output_in_state(PinState::High).into_dyn_pin(),
+            pins.gpio12.into_push_pull_output_in_state(PinState::High).into_dyn_pin(),
+            pins.gpio13.into_push_pull_output_in_state(PinState::High).into_dyn_pin(),
+        ];
+        let cols = [
+            pins.gpio14.into_pull_up_input().into_dyn_pin(),
+            pins.gpio15.into_pull_up_input().into_dyn_pin(),
+            pins.gpio16.into_pull_up_input().into_dyn_pin(),
+            pins.gpio17.into_pull_up_input().into_dyn_pin(),
+        ];
+        keypad::KeypadOrUart::new(rx, keypad::Keypad::new(rows, cols))
+    };
+    #[cfg(feature = "encoder")]
+    let rx = {
+        let encoder = encoder::Encoder::new(
+            pins.gpio18.into_pull_up_input(),
+            pins.gpio19.into_pull_up_input(),
+            pins.gpio20.into_pull_up_input(), // The push button
+        );
+        encoder::EncoderOrUart::new(rx, encoder)
+    };
+    #[cfg(feature = "usb")]
+    let rx = {
+        usb::init(peri.USBCTRL_REGS, peri.USBCTRL_DPRAM, clocks.usb_clock, &mut peri.RESETS);
+        usb::UsbOrUart::new(rx)
+    };
+    log_trace!("UART initialized");
 
     // Send a message over UART, also clear the terminal (VT100 codes)
     tx.write_full_blocking(b"\x1b[2J\x1b[HUART initialised!\r\n");
 
     // ----------------------------------------------------------------------------
 
-    let disp_refcell = RefCell::new(disp);
+    // The widgets default to the SSD1306's 128x64, the SSD1327 fits twice as many lines
+    let disp_dimensions = DisplayDimensions::from((disp.size().width, disp.size().height));
+    // The widgets flush after every change, the scheduler turns that into at most one frame per interval
+    let mut frame_scheduler = FrameScheduler::new(disp);
+    frame_scheduler.set_bus_recovery(i2c_recovery::recover_i2c0);
+    let disp_refcell = RefCell::new(frame_scheduler);
+    // Every workspace spills into its own part of the spill region
+    let spill_refcells: [RefCell<FlashSpill>; WORKSPACE_COUNT] = core::array::from_fn(|i| RefCell::new(FlashSpill::new(i)));
+    // Observes every workspace, so that the host gets told of the changes to whichever is active
+    let host_link = HostLink::new();
 
-    let mut stack: CustomStack<'_, DecimalFixed, _, _>;
-    let mut textbox: CustomTextbox<'_, _, _>;
+    let mut stack: StackSet<'_, DecimalFixed, _>;
+    let mut textbox: CustomTextbox<'_, _>;
+    let mut state = CalcState::new();
     const BIG_TEXT: bool = true; // Whether to use the big font (FONT_7X14) or the small one (default FONT_6X12).
     // Big text is 4 stack lines up to 18 chars, small text is 5 stack lines up to 21 chars
 
     if BIG_TEXT {
-        use embedded_graphics::mono_font::{MonoTextStyle, ascii::FONT_7X14};
+        use embedded_graphics::mono_font::{MonoTextStyle, iso_8859_2::FONT_7X14}; // ISO 8859-2, so that labels can be in Czech
 
-        let charstyle = MonoTextStyle::new(&FONT_7X14, BinaryColor::On);
-        stack = CustomStackBuilder::new()
-            .set_character_style(charstyle)
-            .build(&disp_refcell);
+        let charstyle = MonoTextStyle::new(&FONT_7X14, Palette::FOREGROUND);
+        stack = StackSet::new(
+            CustomStackBuilder::new()
+                .set_disp_dimensions(disp_dimensions)
+                .set_character_style(charstyle)
+                .set_gutter(true)
+                .set_alignment(embedded_graphics::text::Alignment::Right)
+                .set_highlight_top(true)
+                .set_overflow_policy(OverflowPolicy::Spill)
+                .set_observer(&host_link),
+            &disp_refcell
+        );
         textbox = CustomTextboxBuilder::new()
+            .set_disp_dimensions(disp_dimensions)
             .set_character_style(charstyle)
+            .set_validator(numeric_validator)
             .build(&disp_refcell);
     } else {
-        stack = CustomStackBuilder::new()
-            .build(&disp_refcell);
+        stack = StackSet::new(
+            CustomStackBuilder::new()
+                .set_disp_dimensions(disp_dimensions)
+                .set_gutter(true)
+                .set_alignment(embedded_graphics::text::Alignment::Right)
+                .set_highlight_top(true)
+                .set_overflow_policy(OverflowPolicy::Spill)
+                .set_observer(&host_link),
+            &disp_refcell
+        );
         textbox = CustomTextboxBuilder::new()
+            .set_disp_dimensions(disp_dimensions)
+            .set_validator(numeric_validator)
             .build(&disp_refcell);
     }
 
+    stack.set_spill_stores(spill_refcells.each_ref().map(|store| store as &RefCell<dyn SpillStore<DecimalFixed>>));
+
+    state.settings = settings;
+    disp_refcell.borrow_mut().set_contrast(state.settings.contrast)
+        .expect("Failed to set display brightness.");
+    disp_refcell.borrow_mut().set_inverted(state.settings.inverted)
+        .expect("Failed to invert display");
+    stack.set_digit_grouping(state.settings.digit_grouping);
+    stack.set_big_digits(state.settings.big_digits);
+    stack.set_animation(state.settings.animation);
+
+    match ErrorLog::restore() {
+        Ok(Some(errlog)) => state.errlog = errlog,
+        Ok(None) => {},
+        Err(e) => log_warn!("Failed to restore the error log from flash: {:?}", e),
+    };
+
+    match persist::restore_stack(&mut stack) {
+        Ok(0) => {},
+        Ok(count) => {
+            // Can't overflow, the number has at most 3 digits
+            let msg: heapless::String<32> = heapless::format!("Restored {} stack elements\r\n", count)
+                .expect("Message fits into the buffer");
+            uart_tx::write(&tx, msg.as_bytes(), state.settings.crlf);
+        },
+        // Not worth dying over, we just start with an empty stack like before
+        Err(e) => log_warn!("Failed to restore stack from flash: {:?}", e),
+    };
+
     // We can't very well draw an error indication on the display if the display is not working, nay?
     // That's why we're panicking on error here, and everywhere else where we have possible display errors.
     stack.draw(false).expect("Error with display");
     textbox.draw(true).expect("Error with display");
 
-    tx.write_full_blocking(b"Entering main loop\r\n");
-    info!("Entering main loop");
+    uart_tx::write(&tx, b"Entering main loop\r\n", state.settings.crlf);
+    log_info!("Entering main loop");
+
+    let mut utf8_decoder = charset::Utf8Decoder::new();
+    let mut toast = Toast::new();
+    let mut adjusting_contrast = false; // Toggled by Insert, see the escape sequences below
+    let mut paste = PasteDetector::new();
 
     // Label the main loop so we can call `continue` simpler-ly (more simply?) in case of errors if there were nested loops.
     'main: loop {
+        uart_tx::drain_log_mirror(&tx, state.settings.crlf); // Whatever got logged while handling the last key
+        host_link.drain(|frame| uart_tx::write_binary(&tx, frame)); // And whatever the last key did to the stack
+        status_led.tick(state.led, get_timestamp_us()); // The last key might have been the `led` command
+        // Whatever the last key changed shows up in the status bar right away, only a change gets the stack redrawn
+        stack.set_icon(Icon::Inverted, state.settings.inverted);
+        stack.set_icon(Icon::Error, state.errlog.has_unseen());
+        stack.set_icon(Icon::Busy, state.macros.is_playing());
+        stack.set_icon(Icon::Shift, adjusting_contrast);
+        #[cfg(feature = "usb")]
+        stack.set_icon(Icon::Usb, usb::is_configured());
+        if stack.is_dirty() && state.pages.current() == PageKind::Stack && !toast.is_shown() && !state.screensaver.is_active() {
+            stack.draw(true).expect("Error with display");
+        }
         // Due to making the buffer only one byte large, we read **one** byte at a time. Most of our input is ASCII anyway.
         let mut buf: [u8; 1] = [0]; // Yes, we do need to initialize it even if we overwrite it immediately.
-        if let Err(e) = rx.read_full_blocking(&mut buf) {
-            error!("Failed to read from UART: {:?}", e);
-            if let hal::uart::ReadErrorType::Break = e {
-                debug!("Check wiring, usually a break indicates a disconnected wire at the RX pin.");
-            };
 
-            disp_error(&disp_refcell);
-            warn!("Delaying for a second before trying to read again");
-            delay.delay_ms(1000); // Wait a second before trying again, to avoid spamming the error indication
-            continue 'main;
-        }
+        // A macro being played back takes precedence over the UART
+        let char_buf = match state.macros.next_step() {
+            Some(Step::Key(c)) => c,
+            Some(Step::Command(command)) => {
+                let result = run_command(
+                    &command, &rx, &tx, clocks.peripheral_clock.freq().to_Hz(),
+                    &disp_refcell, &mut stack, &mut state, &mut vsys, &mut clock
+                );
+                match result {
+                    Ok(()) => {},
+                    Err(WithContext { error: CE::DisplayError(e), .. }) => defmt::panic!("Error with display: {:?}", e),
+                    Err(e) => {
+                        log_error!("Command of the macro failed: {}", e);
+                        state.macros.abort();
+                        disp_error(&disp_refcell);
+                        disp_toast(&disp_refcell, &mut toast, &e.toast_message());
+                        state.errlog.record(&e, get_timestamp_us());
+                    }
+                };
+                continue 'main;
+            },
+            None => {
+                // While a toast is shown, the screensaver, auto brightness or sleep is enabled, the clock is shown, the LED blinks,
+                // there's a boot button or an I²C master, or a page other than the stack is shown, we poll instead of blocking,
+                // so that we can act in time. Between the polls, we idle until the next input or deadline.
+                // A key pressed hides the toast right away, the key itself then gets handled as usual.
+                // The bytes read ahead while checking for a paste come first, they were received already
+                let pending = paste.pop_pending();
+                if let Some(byte) = pending {
+                    buf[0] = byte;
+                }
+                let mut received = pending.is_some();
+                while !received && (toast.is_shown() || state.settings.saver_secs != 0 || state.settings.auto_brightness
+                    || clock.is_set() || state.settings.sleep_secs != 0 || state.led == LedMode::Blink || cfg!(feature = "boot-button")
+                    || cfg!(feature = "i2c-peripheral")
+                    || state.pages.current() != PageKind::Stack) {
+                    received = rx.read_available(&mut buf) > 0;
+                    let now = get_timestamp_us();
+                    status_led.tick(state.led, now);
+                    disp_refcell.borrow_mut().tick(now).expect("Error with display");
+                    #[cfg(feature = "boot-button")]
+                    if boot_button.poll(now) {
+                        log_info!("Rebooting into USB bootloader (boot button held)");
+                        commands::enter_usb_bootloader(&mut *disp_refcell.borrow_mut()).expect("Error with display");
+                    }
+                    #[cfg(feature = "i2c-peripheral")]
+                    i2c_head.poll(|scale| {
+                        let flags = if state.errlog.has_unseen() { i2c_peripheral::STATUS_ERROR } else { 0 }
+                            | if state.macros.is_playing() { i2c_peripheral::STATUS_BUSY } else { 0 };
+                        i2c_peripheral::snapshot(stack.peek().copied(), stack.len(), flags, scale)
+                    });
+                    #[cfg(feature = "boot-button")]
+                    let boot_pressed = boot_button.is_pressed();
+                    #[cfg(not(feature = "boot-button"))]
+                    let boot_pressed = false;
+                    if state.settings.auto_brightness && state.power.is_awake() {
+                        match state.auto_brightness.tick(&mut light_sensor, vsys.adc(), now) {
+                            Ok(Some(contrast)) => disp_refcell.borrow_mut().set_contrast(contrast).expect("Error with display"),
+                            Ok(None) => {},
+                            Err(e) => log_warn!("Failed to measure the ambient light: {:?}", e),
+                        }
+                    }
+                    // The ambient light says more than the time of day, so the night mode only acts without auto brightness
+                    if !state.settings.auto_brightness && state.power.is_awake()
+                        && let Some(contrast) = state.night.tick(state.settings.night, clock.status_time(), state.settings.contrast) {
+                        disp_refcell.borrow_mut().set_contrast(contrast).expect("Error with display");
+                    }
+                    if toast.is_shown() && (received || toast.is_expired(now)) {
+                        toast.dismiss();
+                        redraw_view(&stack, &textbox, &mut state.pages).expect("Error with display");
+                        disp_error(&disp_refcell); // The toast was covering it, but the error still happened
+                    }
 
-        let Some(char_buf) = char::from_u32(buf[0] as u32) else {
-            warn!("Received invalid UTF-8 byte over UART: 0x{:X}, continuing the loop", buf[0]);
-            continue 'main;
+                    if received {
+                        let was_asleep = state.power.wake(&mut *disp_refcell.borrow_mut(), state.settings.contrast_at(clock.status_time()), now)
+                            .expect("Error with display");
+                        if was_asleep {
+                            state.auto_brightness.reset(); // Sets the contrast by the light again, instead of `contrast`
+                        }
+                        if state.screensaver.wake(now) || was_asleep {
+                            // The key only wakes us up, so that it doesn't do anything unexpected on a blank display
+                            redraw_view(&stack, &textbox, &mut state.pages).expect("Error with display");
+                            continue 'main;
+                        }
+                    } else if !toast.is_shown() {
+                        // The toast's redraw would go over the screensaver otherwise
+                        let mut disp = disp_refcell.borrow_mut();
+                        state.screensaver.tick(&mut *disp, state.settings.saver_secs, state.settings.saver_mode, now)
+                            .expect("Error with display");
+                        // Sleeping while the boot button is held would stop us from polling it until it's released
+                        let woken = !boot_pressed && state.power.tick(&mut *disp, &mut sleeper, state.settings.sleep_secs, state.settings.contrast_at(clock.status_time()), now)
+                            .expect("Error with display");
+                        drop(disp); // The stack borrows the display by itself
+                        if woken {
+                            // By the wake button, there's no key to handle
+                            state.screensaver.wake(now);
+                            state.auto_brightness.reset();
+                            redraw_view(&stack, &textbox, &mut state.pages).expect("Error with display");
+                            continue 'main;
+                        }
+
+                        // Redrawn only when the minute changes (or the second, for the pages that show it),
+                        // a flush on every poll would hog the I²C bus
+                        if state.screensaver.is_active() {
+                            // Drawn over by the screensaver
+                        } else if state.pages.current() == PageKind::Stack {
+                            stack.set_clock(clock.status_time());
+                            if stack.is_dirty() {
+                                stack.draw(true).expect("Error with display");
+                            }
+                        } else if state.power.is_awake() {
+                            draw_page(&disp_refcell, &stack, &mut state, &mut vsys, &clock).expect("Error with display");
+                        }
+                    }
+
+                    if !received {
+                        let interval = if boot_pressed || cfg!(any(feature = "keypad", feature = "pio-keypad", feature = "encoder")) {
+                            INPUT_POLL_US
+                        } else {
+                            IDLE_POLL_US
+                        };
+                        let next_frame = disp_refcell.borrow().next_frame_at().unwrap_or(u64::MAX);
+                        sleeper.idle_until(next_frame.min(now + interval));
+                    }
+                }
+
+                if !received {
+                    display::flush_before_blocking(&disp_refcell).expect("Error with display");
+                }
+                if !received && let Err(e) = rx.read_full_blocking(&mut buf) {
+                    log_error!("Failed to read from UART: {:?}", e);
+                    if let hal::uart::ReadErrorType::Break = e {
+                        log_debug!("Check wiring, usually a break indicates a disconnected wire at the RX pin.");
+                    };
+
+                    disp_error(&disp_refcell);
+                    log_warn!("Delaying for a second before trying to read again");
+                    delay.delay_ms(1000); // Wait a second before trying again, to avoid spamming the error indication
+                    continue 'main;
+                }
+
+                // A zero byte starts a frame of the host protocol, nobody types that
+                if buf[0] == 0x00 {
+                    hostlink::serve(
+                        &host_link, || paste.pop_pending(), &rx, &tx, clocks.peripheral_clock.freq().to_Hz(),
+                        &disp_refcell, &mut stack, &mut state, &mut vsys, &mut clock
+                    ).expect("Error with display");
+                    continue 'main;
+                }
+
+                // A burst of bytes faster than anyone can type is a paste, run as a whole with a single redraw at the end.
+                // The escape sequences of the special keys are bursts too, but they're handled further below.
+                if pending.is_none() && buf[0] != 0x1B && paste.detect(&rx, buf[0]) {
+                    let result = paste.text().map_err(|e| (0, e)).and_then(|text| {
+                        // Whatever was typed goes first, like before an operator
+                        if !textbox.is_empty() && textbox.get_text_str() != "-" {
+                            parse_textbox(&mut textbox, &mut stack, false).map_err(|e| (0, e.error))?;
+                        }
+                        paste::apply(text, &mut stack, &mut state.last_x)
+                    });
+                    if textbox.get_text_str() == "-" {
+                        textbox.clear();
+                    }
+                    stack.draw(false).expect("Error with display");
+                    textbox.draw(true).expect("Error with display");
+
+                    // Can't overflow, the numbers are short and so are the names of the errors
+                    let msg: heapless::String<64> = match result {
+                        Ok(lines) => {
+                            log_info!("Pasted {} lines", lines);
+                            heapless::format!("Pasted {} lines\r\n", lines)
+                        },
+                        Err((line, e)) => {
+                            log_warn!("Failed to run the paste at line {}: {:?}", line, e);
+                            disp_error(&disp_refcell);
+                            disp_toast(&disp_refcell, &mut toast, e.short_message());
+                            state.errlog.record(&WithContext::from(e).with_context("paste", line), get_timestamp_us());
+                            heapless::format!("Paste failed at line {}: {:?}\r\n", line, e)
+                        },
+                    }.expect("Message fits into the buffer");
+                    uart_tx::write(&tx, msg.as_bytes(), state.settings.crlf);
+                    continue 'main;
+                }
+
+                // Multi-byte chars are never valid here, but we still need to swallow them whole instead of byte by byte
+                match utf8_decoder.push(buf[0]) {
+                    Ok(Some(c)) => {
+                        uart_tx::echo(&tx, c, &state.settings);
+                        c
+                    },
+                    Ok(None) => continue 'main, // The rest of the char is yet to come
+                    Err(_) => {
+                        log_warn!("Received invalid UTF-8 byte over UART: 0x{:X}, continuing the loop", buf[0]);
+                        continue 'main;
+                    }
+                }
+            }
         };
 
+        // Checked on every key, so that we don't need a timer for it, the ADC is quick anyway
+        #[cfg(feature = "battery")]
+        match vsys.is_low() {
+            Ok(low) => stack.set_low_battery(low), // Drawn along with the stack
+            Err(e) => log_warn!("Failed to check the battery: {:?}", e),
+        }
+
+        // Keys only do anything on the stack page, any other than Tab goes back to it first and then does what it always does
+        if char_buf != '\t' && state.pages.current() != PageKind::Stack {
+            log_debug!("Leaving the {} page for the stack on key {:?}", state.pages.current().name(), char_buf);
+            state.pages.show(PageKind::Stack);
+            redraw_view(&stack, &textbox, &mut state.pages).expect("Error with display");
+        }
+
+        // The keys that start playback, command mode or the menu (which record whole commands instead) don't belong into a macro
+        if !matches!(char_buf, '\x10' | '\x14' | '\x0F' | '\x1B')
+            && let Err(e) = state.macros.record_key(char_buf)
+        {
+            log_error!("Failed to record key into macro: {:?}", e);
+            disp_error(&disp_refcell);
+        }
+
         match char_buf {
             '\r' | '\n' => { // Enter or newline
                 if textbox.is_empty() || textbox.get_text_str() == "-" {
@@ -194,15 +729,19 @@ fn main() -> ! {
                 }
 
                 if let Err(e) = parse_textbox(&mut textbox, &mut stack, true) {
-                    match e {
+                    match e.error {
                         CE::CapacityError |
                         CE::MathOverflow |
-                        CE::ParseIntError(IEKC::PosOverflow | IEKC::NegOverflow) => {
-                            error!("Error parsing textbox: {:?}", e);
+                        // Moving the cursor around can leave a malformed number behind, e.g. by deleting the mantissa before an exponent
+                        CE::BadInput |
+                        CE::ParseIntError(_) => {
+                            log_error!("Error parsing textbox: {}", e);
                             stack.draw(false).expect("Error with display");
                             textbox.draw(true).expect("Error with display");
 
                             disp_error(&disp_refcell);
+                            disp_toast(&disp_refcell, &mut toast, &e.toast_message());
+                            state.errlog.record(&e, get_timestamp_us());
                         },
                         CE::DisplayError(e) => defmt::panic!("Error with display: {:?}", e),
                         _ => disp_grave_error(&disp_refcell, Some(&mut delay))
@@ -211,14 +750,14 @@ fn main() -> ! {
             },
 
             '\x08' | '\x7F' => { // Backspace or Delete
-                trace!("Backspace character received: (0x{:X})", buf[0]);
+                log_trace!("Backspace character received: (0x{:X})", char_buf as u32);
 
                 if textbox.is_empty() {
                     continue 'main;
                 };
                 if textbox.backspace(1).is_err() {
-                    error!("Failed to backspace textbox");
-                    error!("This should normally be impossible, we already checked it's not empty");
+                    log_error!("Failed to backspace textbox");
+                    log_error!("This should normally be impossible, we already checked it's not empty");
                     disp_grave_error(&disp_refcell, Some(&mut delay));
                 };
                 textbox.draw(true).expect("Error with display");
@@ -227,28 +766,43 @@ fn main() -> ! {
             '.' | ',' => { // Decimal point
                 if textbox.is_empty() || textbox.get_text_str() == "-" {
                     if textbox.append_str("0.").is_err() {
-                        error!("It should be impossible to fail to append to an empty textbox.");
+                        log_error!("It should be impossible to fail to append to an empty textbox.");
                         disp_grave_error(&disp_refcell, Some(&mut delay));
                     }
                     textbox.draw(true).expect("Error with display");
                     continue 'main;
                 }
-                if textbox.contains('.') {
-                    debug!("Ignoring decimal point, textbox already contains one");
-                    continue 'main;
-                }
-                if textbox.append_char('.').is_err() {
-                    error!("Failed to append decimal point to textbox: CapacityError");
-                    disp_error(&disp_refcell);
-                    continue 'main;
+                match textbox.append_char('.') {
+                    Ok(()) => {},
+                    Err(CE::BadInput) => { // Rejected by the validator
+                        log_debug!("Ignoring decimal point, textbox already contains one or it'd be in the exponent");
+                        continue 'main;
+                    },
+                    Err(e) => {
+                        log_error!("Failed to append decimal point to textbox: {:?}", e);
+                        disp_error(&disp_refcell);
+                        continue 'main;
+                    }
                 }
                 textbox.draw(true).expect("Error with display");
             },
 
-            'n' => { // Negate
-                if textbox.is_empty() {
+            'n' => { // Negate (CHS)
+                // While typing the exponent, it's the exponent's sign that gets toggled, like on HP calculators
+                if let Some(e_index) = textbox.get_text_str().find('e') {
+                    let result = if textbox.get_text_str()[(e_index + 1)..].starts_with('-') {
+                        textbox.remove_at(e_index + 1).map(|_| ())
+                    } else {
+                        textbox.insert_at(e_index + 1, '-')
+                    };
+                    if let Err(e) = result {
+                        log_error!("Failed to toggle the sign of the exponent: {:?}", e);
+                        disp_error(&disp_refcell);
+                    }
+                    textbox.draw(true).expect("Error with display");
+                } else if textbox.is_empty() {
                     if textbox.append_char('-').is_err() {
-                        error!("It should be impossible to fail to append to an empty textbox.");
+                        log_error!("It should be impossible to fail to append to an empty textbox.");
                         disp_grave_error(&disp_refcell, Some(&mut delay));
                     }
                     textbox.draw(true).expect("Error with display");
@@ -259,20 +813,20 @@ fn main() -> ! {
                             continue 'main;
                         },
                         Ok(other) => { // Popped something else, despite our check
-                            error!("Removed character was not '-' ({:?}), this should be impossible!", other);
+                            log_error!("Removed character was not '-' ({:?}), this should be impossible!", other);
                             disp_grave_error(&disp_refcell, Some(&mut delay));
                         },
                         Err(e) => { // Failed to remove
-                            error!("Failed to remove leading '-' from textbox: {:?}", e);
+                            log_error!("Failed to remove leading '-' from textbox: {:?}", e);
                             disp_grave_error(&disp_refcell, Some(&mut delay));
                         }
                     };
                 } else if textbox.contains('-') {
-                    error!("Textbox contains '-' not at the start, this should be impossible.");
+                    log_error!("Textbox contains '-' not at the start, this should be impossible.");
                     disp_grave_error(&disp_refcell, Some(&mut delay));
                 } else {
                     if let Err(e) = textbox.insert_at(0, '-') {
-                        error!("Failed to insert leading '-' into textbox: {:?}", e);
+                        log_error!("Failed to insert leading '-' into textbox: {:?}", e);
                         disp_error(&disp_refcell);
                     };
                     
@@ -280,6 +834,21 @@ fn main() -> ! {
                 }
             },
 
+            'e' | 'E' => { // Exponent (EEX)
+                // Starting with the exponent means a mantissa of one, e.g. `e3` is a thousand
+                let exponent = if textbox.is_empty() || textbox.get_text_str() == "-" { "1e" } else { "e" };
+                match textbox.append_str(exponent) {
+                    Ok(()) => textbox.draw(true).expect("Error with display"),
+                    Err(CE::BadInput) => { // Rejected by the validator
+                        log_debug!("Ignoring exponent key, the number already has one or there's no mantissa yet");
+                    },
+                    Err(e) => {
+                        log_error!("Failed to append exponent to textbox: {:?}", e);
+                        disp_error(&disp_refcell);
+                    }
+                }
+            },
+
             '0'..='9' => { // Digits
                 if textbox.append_char(char_buf).is_err() {
                     disp_error(&disp_refcell);
@@ -289,18 +858,28 @@ fn main() -> ! {
             }
 
             '+' | '-' | '*' | '/' => {
+                // A lone minus sign from negating an empty textbox isn't a number yet, so there's nothing to commit
+                if textbox.get_text_str() == "-" {
+                    log_debug!("Discarding a lone minus sign before operator {:?}", char_buf);
+                    textbox.clear();
+                    textbox.draw(false).expect("Error with display"); // Flushed by whatever comes next, be it the result or an error
+                }
                 // The short-circuiting is desirable: if it's empty, we never run `parse_textbox()`
                 if !textbox.is_empty()
                     && let Err(e) = parse_textbox(&mut textbox, &mut stack, false)
                 {
-                    match e {
+                    match e.error {
                         CE::CapacityError |
                         CE::MathOverflow |
-                        CE::ParseIntError(IEKC::PosOverflow | IEKC::NegOverflow) => {
-                            error!("Error parsing textbox: {:?}", e);
+                        // Moving the cursor around can leave a malformed number behind, e.g. by deleting the mantissa before an exponent
+                        CE::BadInput |
+                        CE::ParseIntError(_) => {
+                            log_error!("Error parsing textbox: {}", e);
                             stack.draw(false).expect("Error with display");
                             textbox.draw(true).expect("Error with display");
                             disp_error(&disp_refcell);
+                            disp_toast(&disp_refcell, &mut toast, &e.toast_message());
+                            state.errlog.record(&e, get_timestamp_us());
                         },
                         CE::DisplayError(e) => defmt::panic!("Error with display: {:?}", e),
                         _ => disp_grave_error(&disp_refcell, Some(&mut delay))
@@ -309,8 +888,10 @@ fn main() -> ! {
                 }
 
                 if stack.len() < 2 {
-                    warn!("Not enough numbers on stack to perform operation. Need 2, got {}.", stack.len());
+                    log_warn!("Not enough numbers on stack to perform operation. Need 2, got {}.", stack.len());
                     disp_error(&disp_refcell);
+                    disp_toast(&disp_refcell, &mut toast, "Too few numbers");
+                    state.errlog.record(&WithContext::from(CE::BadInput).with_context("operator", char_buf), get_timestamp_us());
                     continue 'main;
                 }
                 // By definition of multipop, the first popped element is the topmost one,
@@ -322,107 +903,154 @@ fn main() -> ! {
                     .collect::<Vec<_, 2>>() // Collect the iterator into a collection
                     .into_array() // Convert the collection into a const-size array
                     .expect("We already checked the stack has at least 2 elements, the collected Vec should have 2 items!");
+                // Saved even if the operation fails, just like the operands are lost then
+                state.last_x = Some(b);
 
-                let c: DecimalFixed = match char_buf {
-                    '+' => match a + b {
-                        Ok(c) => c,
-                        Err(e) => {
-                            error!("Error in addition: {:?}", e);
-                            stack.draw(false).expect("Error with display");
-                            disp_error(&disp_refcell);
-                            continue 'main;
-                        }
-                    },
-                    '-' => match a - b {
-                        Ok(c) => c,
-                        Err(e) => {
-                            error!("Error in subtraction: {:?}", e);
-                            stack.draw(false).expect("Error with display");
-                            disp_error(&disp_refcell);
-                            continue 'main;
-                        }
-                    },
-                    '*' => {
-                        match a * b {
-                            Ok(c) => c,
-                            Err(e) => {
-                                error!("Error in multiplication: {:?}", e);
-                                stack.draw(false).expect("Error with display");
-                                disp_error(&disp_refcell);
-                                continue 'main;
-                            }
-                        }
-                    }
+                let result = match char_buf {
+                    '+' => (a + b).context("add"),
+                    '-' => (a - b).context("sub"),
+                    '*' => (a * b).context("mul"),
                     '/' => {
                         if b.is_zero() {
-                            error!("Division by zero attempted.");
+                            log_error!("Division by zero attempted.");
                             stack.draw(false).expect("Error with display");
                             disp_error(&disp_refcell);
+                            disp_toast(&disp_refcell, &mut toast, "Division by zero");
+                            state.errlog.record(&WithContext::from(CE::BadInput).with_context("div", b), get_timestamp_us());
                             continue 'main;
                         };
-
-                        match a / b {
-                            Ok(c) => c,
-                            Err(e) => {
-                                error!("Error in division: {:?}", e);
-                                stack.draw(false).expect("Error with display");
-                                disp_error(&disp_refcell);
-                                continue 'main;
-                            }
-                        }
+                        (a / b).context("div")
                     },
                     _ => defmt::unreachable!(), // We already checked this above
                 };
+                let c: DecimalFixed = match result {
+                    Ok(c) => c,
+                    Err(e) => {
+                        log_error!("Error in arithmetics: {}", e);
+                        stack.draw(false).expect("Error with display");
+                        disp_error(&disp_refcell);
+                        disp_toast(&disp_refcell, &mut toast, &e.toast_message());
+                        state.errlog.record(&e, get_timestamp_us());
+                        continue 'main;
+                    }
+                };
 
                 if stack.push(c).is_ok() {
                     stack.draw(false).expect("Error with display");
                     textbox.draw(true).expect("Error with display");
                 } else {
-                    error!("Failed to push result onto stack");
-                    error!("This should be impossible, the stack should have enough space since we already popped from it.");
+                    log_error!("Failed to push result onto stack");
+                    log_error!("This should be impossible, the stack should have enough space since we already popped from it.");
                     disp_grave_error(&disp_refcell, Some(&mut delay));
                 };
             },
 
+            '\t' => { // Tab - flip to the next page, see `pages::PageManager`
+                let page = state.pages.next();
+                log_info!("Showing the {} page", page.name());
+                if page == PageKind::Stack {
+                    redraw_view(&stack, &textbox, &mut state.pages).expect("Error with display");
+                }
+                // The other pages get drawn while polling for the next key
+            },
+
             '\x12' => { // Ctrl-R
                 // Force a redraw of both textbox and stack
                 // Amongst other effects, this clears the non-grave error icon
-                info!("Doing a forced redraw of both stack and textbox.");
+                log_info!("Doing a forced redraw of both stack and textbox.");
                 
                 // Just to be ultra-sure, we flush both
+                stack.invalidate();
+                textbox.invalidate();
                 stack.draw(true).expect("Error with display");
                 textbox.draw(true).expect("Error with display");
             },
 
-            '\x14' => { // Ctrl-T
-                match handle_commands(&rx, &disp_refcell, &mut textbox, &mut stack) {
+            '\x10' => { // Ctrl-P
+                // Plays the macro recorded with `macro record` once, see `commands::Macro`
+                if state.macros.play(1).is_err() {
+                    disp_error(&disp_refcell);
+                    disp_toast(&disp_refcell, &mut toast, "No macro to play");
+                }
+            },
+
+            '\x0F' => { // Ctrl-O
+                // The menu draws over everything, it redraws the stack by itself before running what was chosen
+                let result = run_command(
+                    "menu", &rx, &tx, clocks.peripheral_clock.freq().to_Hz(),
+                    &disp_refcell, &mut stack, &mut state, &mut vsys, &mut clock
+                );
+                // The menu reads by itself, so the screensaver and the power manager don't know about the keys it got
+                state.screensaver.wake(get_timestamp_us());
+                state.power.wake(&mut *disp_refcell.borrow_mut(), state.settings.contrast_at(clock.status_time()), get_timestamp_us())
+                    .expect("Error with display");
+                textbox.invalidate();
+                textbox.draw(true).expect("Error with display");
+
+                match result {
                     Ok(()) => {},
+                    Err(WithContext { error: CE::DisplayError(e), .. }) => defmt::panic!("Error with display: {:?}", e),
                     Err(e) => {
-                        match e {
+                        log_warn!("Command from the menu failed: {}", e);
+                        disp_error(&disp_refcell);
+                        disp_toast(&disp_refcell, &mut toast, &e.toast_message());
+                        state.errlog.record(&e, get_timestamp_us());
+                    }
+                };
+            },
+
+            '\x14' => { // Ctrl-T
+                // Whatever happens, we go back to number entry with its own prompt and placeholder
+                // (the command mode filters the chars by itself, hence no validator)
+                let (prompt, placeholder, validator) = (textbox.prompt(), textbox.placeholder(), textbox.validator());
+                textbox.set_prompt(command_mode::PROMPT);
+                textbox.set_placeholder(command_mode::PLACEHOLDER);
+                textbox.set_validator(None);
+                let result = handle_commands(
+                    &rx, &tx, clocks.peripheral_clock.freq().to_Hz(),
+                    &disp_refcell, &mut textbox, &mut stack, &mut state, &mut vsys, &mut clock
+                );
+                textbox.set_prompt(prompt);
+                textbox.set_placeholder(placeholder);
+                textbox.set_validator(validator);
+                // Command mode reads by itself, so the screensaver and the power manager don't know about the keys it got
+                state.screensaver.wake(get_timestamp_us());
+                state.power.wake(&mut *disp_refcell.borrow_mut(), state.settings.contrast_at(clock.status_time()), get_timestamp_us())
+                    .expect("Error with display");
+
+                match result {
+                    Ok(()) => textbox.draw(true).expect("Error with display"),
+                    Err(e) => {
+                        match e.error {
                             CE::BadInput |
+                            CE::MathOverflow |
+                            CE::ComplexResult |
                             CE::ParseIntError(_) |
                             CE::CapacityError => {
                                 {
                                     let mut disp = disp_refcell.borrow_mut();
-                                    disp.set_invert(false).expect("Failed to invert display");
+                                    disp.set_inverted(state.settings.inverted).expect("Failed to invert display");
                                 }
 
                                 textbox.clear();
                                 stack.draw(false).expect("Error with display");
                                 textbox.draw(false).expect("Error with display");
 
+                                log_warn!("Command failed: {}", e);
                                 disp_error(&disp_refcell);
+                                disp_toast(&disp_refcell, &mut toast, &e.toast_message());
+                                state.errlog.record(&e, get_timestamp_us());
                             },
                             CE::Cancelled => { // Not truly an error, just a notification
-                                info!("Command mode cancelled by user.");
+                                log_info!("Command mode cancelled by user.");
                                 textbox.draw(true).expect("Error with display");
                             },
                             CE::DisplayError(e) => defmt::panic!("Error with display: {:?}", e),
                             other => {
-                                error!("An irrecoverable or otherwise unhandled error: {:?}", other);
+                                log_error!("An irrecoverable or otherwise unhandled error: {:?}", other);
                                 {
                                     let mut disp = disp_refcell.borrow_mut();
-                                    disp.set_invert(false).expect("Failed to invert display");
+                                    disp.set_inverted(state.settings.inverted).expect("Failed to invert display");
                                 }
                                 disp_grave_error(&disp_refcell, Some(&mut delay));
                             }
@@ -437,18 +1065,68 @@ fn main() -> ! {
                 buf[0] = 0x1B; // We already read the first byte, so store it
 
                 delay.delay_ms(50); // HACK: Wait a bit to allow the rest of the sequence to arrive.
-                let Ok(num_bytes) = rx.read_raw(&mut buf[1..]) else { // Nonblocking
-                    debug!("Escape byte received over UART: 0x1B");
+                let num_bytes = rx.read_available(&mut buf[1..]); // Nonblocking
+                if num_bytes == 0 {
+                    log_debug!("Escape byte received over UART: 0x1B");
                     continue 'main;
                 };
 
-                // We do not handle the escape sequences at all, just log them for debugging purposes.
-                debug!("Escape sequence received over UART: {:#04X}", buf[..=num_bytes]); // With the RangeToInclusive, we account for the first byte
+                // With the RangeToInclusive, we account for the first byte
+                match &buf[..=num_bytes] {
+                    b"\x1B[5~" => { // Page Up - scroll deeper into the stack by a whole page
+                        let page = stack.visible_lines(); // Separately, because DerefMut doesn't do two-phase borrows
+                        stack.scroll_up(page);
+                        stack.draw(true).expect("Error with display");
+                    },
+                    b"\x1B[6~" => { // Page Down - scroll back towards the top by a whole page
+                        let page = stack.visible_lines();
+                        stack.scroll_down(page);
+                        stack.draw(true).expect("Error with display");
+                    },
+                    // Up and Down (also sent by the rotary encoder) scroll by a line, or adjust the contrast after Insert
+                    b"\x1B[A" | b"\x1B[B" if adjusting_contrast => {
+                        // At night, it's the night contrast that's shown, so that's the one adjusted
+                        let time = clock.status_time();
+                        let night = state.settings.night.as_mut()
+                            .filter(|night| time.is_some_and(|(hour, minute)| night.contains(hour, minute)));
+                        let contrast = match night {
+                            Some(night) => &mut night.contrast,
+                            None => &mut state.settings.contrast,
+                        };
+                        *contrast = match buf[2] {
+                            b'A' => contrast.saturating_sub(CONTRAST_STEP),
+                            _ => contrast.saturating_add(CONTRAST_STEP),
+                        };
+                        disp_refcell.borrow_mut().set_contrast(*contrast).expect("Error with display");
+                    },
+                    b"\x1B[A" => { // Up - scroll deeper into the stack
+                        stack.scroll_up(1);
+                        stack.draw(true).expect("Error with display");
+                    },
+                    b"\x1B[B" => { // Down - scroll back towards the top
+                        stack.scroll_down(1);
+                        stack.draw(true).expect("Error with display");
+                    },
+                    b"\x1B[2~" => { // Insert (also the rotary encoder's button) - start or stop adjusting the contrast
+                        adjusting_contrast = !adjusting_contrast;
+                        if adjusting_contrast {
+                            disp_toast(&disp_refcell, &mut toast, "Adjusting contrast");
+                        } else {
+                            log_info!("Contrast adjusted to {}", state.settings.contrast_at(clock.status_time()));
+                            if let Err(e) = persist::save_settings(&state.settings) {
+                                log_error!("Failed to save settings: {:?}", e);
+                                disp_toast(&disp_refcell, &mut toast, e.short_message());
+                            }
+                        }
+                    },
+                    // We do not handle the other escape sequences at all, just log them for debugging purposes.
+                    other => log_debug!("Escape sequence received over UART: {:?}", other),
+                };
                 continue 'main;
             },
 
             _ => {
-                warn!("Unhandled character received over UART: {:?} ({:#04X})", char_buf, buf[0]);
+                log_warn!("Unhandled character received over UART: {:?} ({:#04X})", char_buf, buf[0]);
                 continue 'main;
             },
         }
@@ -457,14 +1135,10 @@ fn main() -> ! {
 
 
 /// Display the grave error image and reset the microcontroller after a delay, never returning.
-pub fn disp_grave_error<DI, SIZE>(
-    disp_refcell: &RefCell<Ssd1306<DI, SIZE, BufferedGraphicsMode<SIZE>>>,
+pub fn disp_grave_error<D: Panel>(
+    disp_refcell: &RefCell<D>,
     maybe_delay: Option<&mut cortex_m::delay::Delay>
-) -> !
-where 
-    DI: WriteOnlyDataCommand,
-    SIZE: DisplaySize,
-{
+) -> ! {
     let mut disp = disp_refcell.borrow_mut();
 
     // Converted at https://convertico.com/png-to-bmp/ to 1-bit BMP
@@ -474,11 +1148,12 @@ where
         &bmp,
         (0, 0).into(), // Fullscreen
     );
-    img.draw(&mut (*disp)).expect("Failed to draw image on display");
-    // The dereference gives us the inner Ssd1306 struct from the RefCell,
+    // The image is 1-bit, a grayscale display gets it in black and white
+    img.draw(&mut disp.color_converted()).expect("Failed to draw image on display");
+    // The dereference gives us the inner display struct from the RefCell,
     // and then we borrow it mutably to draw on it.
     // We could also do `disp.deref_mut()` instead of `&mut (*disp)`.
-    disp.flush().expect("Failed to flush display");
+    disp.flush_now().expect("Failed to flush display");
 
     maybe_delay.expect("No delay provider given, cannot delay before reset. Panicking.")
         .delay_ms(10_000);
@@ -486,12 +1161,9 @@ where
 }
 
 // Display the non-grave error image (on top-right corner) and return.
-pub fn disp_error<DI, SIZE> (
-    disp_refcell: &RefCell<Ssd1306<DI, SIZE, BufferedGraphicsMode<SIZE>>>,
-) where
-    DI: WriteOnlyDataCommand,
-    SIZE: DisplaySize,
-{
+pub fn disp_error<D: Panel> (
+    disp_refcell: &RefCell<D>,
+) {
     let mut disp = disp_refcell.borrow_mut();
 
     let bmp = ERROR_BMP
@@ -500,31 +1172,93 @@ pub fn disp_error<DI, SIZE> (
         &bmp,
         (117, 0).into(), // Image is 10x10, we put it in the top-right corner
     );
-    img.draw(&mut (*disp)).expect("Failed to draw image on display");
-    disp.flush().expect("Failed to flush display");
+    img.draw(&mut disp.color_converted()).expect("Failed to draw image on display");
+    disp.flush_display().expect("Failed to flush display");
+}
+
+// Display a short message (usually what went wrong) in a banner across the display, the main loop hides it after a while.
+pub fn disp_toast<D: Panel> (
+    disp_refcell: &RefCell<D>,
+    toast: &mut Toast,
+    message: &str,
+) {
+    let mut disp = disp_refcell.borrow_mut();
+    toast.show(&mut *disp, message, get_timestamp_us()).expect("Error with display");
+}
+
+/// Redraws whatever a toast, the screensaver or sleep covered: the stack and the textbox,
+/// or just invalidates the page shown instead of them, for it to be drawn while polling.
+fn redraw_view<'a, D: Panel>(
+    stack: &StackSet<'a, DecimalFixed, D>,
+    textbox: &CustomTextbox<'a, D>,
+    pages: &mut pages::PageManager,
+) -> Result<(), CustomError> {
+    if pages.current() != PageKind::Stack {
+        pages.invalidate();
+        return Ok(());
+    }
+    stack.invalidate();
+    textbox.invalidate();
+    stack.draw(false)?;
+    textbox.draw(true)
+}
+
+/// Draws the page shown instead of the stack if what it shows has changed, see `pages::PageManager`.
+fn draw_page<'a, D: Panel>(
+    disp_refcell: &RefCell<D>,
+    stack: &StackSet<'a, DecimalFixed, D>,
+    state: &mut CalcState,
+    vsys: &mut Vsys,
+    clock: &WallClock,
+) -> Result<(), CustomError> {
+    let data = PageData {
+        registers: &state.registers,
+        uptime_secs: get_timestamp_us() / 1_000_000,
+        time: clock.wall_time(),
+    };
+    let (errors, recording) = (state.errlog.len(), state.macros.is_recording());
+    // Only measured when the status page is about to be drawn
+    let status = || Status {
+        stack_len: stack.len(),
+        workspace: stack.active_index(),
+        vsys: match vsys.measure() {
+            Ok(voltage) => Some(voltage),
+            Err(e) => {
+                log_warn!("Failed to measure VSYS for the status page: {:?}", e);
+                None
+            },
+        },
+        errors,
+        recording,
+        #[cfg(feature = "alloc")]
+        heap_used: Some(heap::used()),
+        #[cfg(not(feature = "alloc"))]
+        heap_used: None,
+    };
+    state.pages.draw_if_dirty(&mut *disp_refcell.borrow_mut(), &data, status)?;
+    Ok(())
 }
 
 // The stack is intentionally not generic, only for DecimalFixed
 // XXX: Will need a rewrite if the stack type changes, since we can't impl FromStr with static exp
-pub fn parse_textbox<'a, DI, SIZE> (
-    textbox: &mut CustomTextbox<'a, DI, SIZE>,
-    stack: &mut CustomStack<'a, DecimalFixed, DI, SIZE>,
+pub fn parse_textbox<'a, D: Panel> (
+    textbox: &mut CustomTextbox<'a, D>,
+    stack: &mut StackSet<'a, DecimalFixed, D>,
     flush: bool,
-) -> Result<(), CustomError>
-where
-    DI: WriteOnlyDataCommand,
-    SIZE: DisplaySize,
-{
+) -> Result<(), WithContext> {
     let txbx_data = textbox.get_text_str();
-    if txbx_data.is_empty() { return Err(CE::BadInput); };
+    if txbx_data.is_empty() { return Err(CE::BadInput.into()); };
+    // An exponent that wasn't typed yet counts as zero, e.g. `1.5e` is just 1.5
+    let txbx_data = txbx_data.strip_suffix("e-").or_else(|| txbx_data.strip_suffix('e')).unwrap_or(txbx_data);
     
-    let num = DecimalFixed::parse_str(txbx_data, None)?; // Use default exponent by passing None
+    let num = DecimalFixed::parse_str(txbx_data, None) // Use default exponent by passing None
+        .context_value("parse", txbx_data)?;
     match stack.push(num) {
         Ok(()) => {},
         Err((e, _)) => { // We drop the returned value, we don't need it
             // .push() will only return CE::CapacityError
-            error!("Failed to push parsed number onto stack (CapacityError)");
-            return Err(e)
+            log_error!("Failed to push parsed number onto stack (CapacityError)");
+            return Err(e).context("push")
         },
     }
 
@@ -534,3 +1268,27 @@ where
     textbox.draw(flush)?;
     Ok(())
 }
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// What `defmt-rtt` and `panic-probe` provide on the microcontroller, for the host tests, which have no probe to talk to
+#[cfg(test)]
+mod host {
+    /// Discards everything, the log macros don't even call into defmt in tests (see `log.rs`),
+    /// but the `defmt::Format` impls and defmt's own assertions still need a logger to link against
+    #[defmt::global_logger]
+    struct NullLogger;
+
+    // SAFETY: It does nothing at all, so there's nothing to get wrong
+    unsafe impl defmt::Logger for NullLogger {
+        fn acquire() {}
+        unsafe fn flush() {}
+        unsafe fn release() {}
+        unsafe fn write(_bytes: &[u8]) {}
+    }
+
+    #[defmt::panic_handler]
+    fn panic() -> ! {
+        core::panic!("defmt panic")
+    }
+}
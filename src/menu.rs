@@ -0,0 +1,452 @@
+use heapless::Vec;
+use embedded_graphics::{
+    prelude::*,
+
+    mono_font::{
+        iso_8859_2::FONT_6X12 as ISO_FONT_6X12,
+        MonoTextStyle,
+    },
+    primitives::{
+        PrimitiveStyle,
+        Rectangle,
+    },
+    text::{
+        Baseline,
+        Text,
+    },
+};
+
+use crate::display::{FlushableDisplay, Palette, Panel};
+use crate::commands;
+use crate::custom_error::CustomError; // Because we already have the `mod` in `main.rs`
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// How deep the submenus can nest, the root counting as one
+const MAX_DEPTH: usize = 4;
+/// Height of a line of the menu, the font's height
+const LINE_HEIGHT: u32 = 12;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+const fn text_style<C: Palette>() -> MonoTextStyle<'static, C> {
+    MonoTextStyle::new(&ISO_FONT_6X12, C::FOREGROUND)
+}
+
+/// The selected item is drawn the other way around, like the highlighted top of the stack
+const fn selected_style<C: Palette>() -> MonoTextStyle<'static, C> {
+    MonoTextStyle::new(&ISO_FONT_6X12, C::BACKGROUND)
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// What choosing an item of the menu does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuAction {
+    /// Opens the nested list
+    Submenu(&'static [MenuItem]),
+    /// Runs the command line, the same as typing it in command mode
+    Command(&'static str),
+    /// Pushes the number, written out the way it'd be typed
+    Constant(&'static str),
+}
+
+/// What happened on `Menu::enter()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    /// A submenu got opened or we went back up, only the menu has to be redrawn
+    Navigated,
+    /// The caller is to carry it out, usually closing the menu first
+    Action(MenuAction),
+    /// The extra item at the root was chosen
+    Closed,
+}
+
+/// A single line of the menu
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MenuItem {
+    /// What's shown, short enough to fit onto the display with the 6 px wide font (21 characters on the SSD1306)
+    pub label: &'static str,
+    pub action: MenuAction,
+}
+
+impl MenuItem {
+    const fn submenu(label: &'static str, items: &'static [MenuItem]) -> Self {
+        MenuItem { label, action: MenuAction::Submenu(items) }
+    }
+
+    const fn command(label: &'static str, command: &'static str) -> Self {
+        MenuItem { label, action: MenuAction::Command(command) }
+    }
+
+    const fn constant(label: &'static str, value: &'static str) -> Self {
+        MenuItem { label, action: MenuAction::Constant(value) }
+    }
+}
+
+/// The whole menu, see the `menu` command. Only commands that need no input beyond the stack are in it,
+/// the ones taking e.g. a register name still have to be typed in command mode.
+pub const ROOT: &[MenuItem] = &[
+    MenuItem::submenu("Settings", SETTINGS),
+    MenuItem::submenu("Stack", STACK),
+    MenuItem::submenu("Pages", PAGES),
+    MenuItem::submenu("Conversions", CONVERSIONS),
+    MenuItem::submenu("Constants", CONSTANTS),
+    MenuItem::submenu("Math", MATH),
+    MenuItem::submenu("Programs", PROGRAMS),
+    MenuItem::submenu("System", SYSTEM),
+];
+
+const SETTINGS: &[MenuItem] = &[
+    MenuItem::submenu("Number format", NUMBER_FORMAT),
+    MenuItem::command("Animation on", "anim on"),
+    MenuItem::command("Animation off", "anim off"),
+    MenuItem::command("Invert on", "invert on"),
+    MenuItem::command("Invert off", "invert off"),
+    MenuItem::command("Auto brightness on", "autobrt on"),
+    MenuItem::command("Auto brightness off", "autobrt off"),
+    MenuItem::command("Night 22:00-07:00", "night 22:00-07:00"),
+    MenuItem::command("Night mode off", "night off"),
+    MenuItem::command("Screensaver 60 s", "saver 60"),
+    MenuItem::command("Screensaver off", "saver off"),
+    MenuItem::command("Sleep after 5 min", "sleep 300"),
+    MenuItem::command("Sleep off", "sleep off"),
+    MenuItem::command("Echo on", "echo on"),
+    MenuItem::command("Echo off", "echo off"),
+];
+
+const NUMBER_FORMAT: &[MenuItem] = &[
+    MenuItem::command("Full precision", "fix"),
+    MenuItem::command("Fixed, 2 places", "fix 2"),
+    MenuItem::command("Fixed, 4 places", "fix 4"),
+    MenuItem::command("Scientific, 3 places", "sci 3"),
+    MenuItem::command("Digit grouping on", "group on"),
+    MenuItem::command("Digit grouping off", "group off"),
+    MenuItem::command("Big digits on", "big on"),
+    MenuItem::command("Big digits off", "big off"),
+];
+
+const STACK: &[MenuItem] = &[
+    MenuItem::command("Duplicate", "dup"),
+    MenuItem::command("Drop", "drop"),
+    MenuItem::command("Swap", "swap"),
+    MenuItem::command("Over", "over"),
+    MenuItem::command("Rotate", "rot"),
+    MenuItem::command("Negate", "neg"),
+    MenuItem::command("Last x", "lastx"),
+    MenuItem::command("Sort", "sort"),
+    MenuItem::command("Reverse", "reverse"),
+    MenuItem::command("Bar chart", "chart"),
+    MenuItem::command("Clear", "clear"),
+];
+
+const PAGES: &[MenuItem] = &[
+    MenuItem::command("Registers", "page regs"),
+    MenuItem::command("Status", "page status"),
+    MenuItem::command("Clock", "page clock"),
+];
+
+const CONVERSIONS: &[MenuItem] = &[
+    MenuItem::command("Rect to polar (deg)", "r->p"),
+    MenuItem::command("Rect to polar (rad)", "r->p rad"),
+    MenuItem::command("Polar to rect (deg)", "p->r"),
+    MenuItem::command("Polar to rect (rad)", "p->r rad"),
+    MenuItem::command("Power ratio to dB", "db"),
+    MenuItem::command("Ampl. ratio to dB", "db amp"),
+    MenuItem::command("dB to power ratio", "undb"),
+    MenuItem::command("dB to ampl. ratio", "undb amp"),
+];
+
+/// Only to the 9 decimal places the stack has, the ones too small for it (e.g. Planck's) aren't here
+const CONSTANTS: &[MenuItem] = &[
+    MenuItem::constant("Pi", "3.141592654"),
+    MenuItem::constant("e", "2.718281828"),
+    MenuItem::constant("sqrt(2)", "1.414213562"),
+    MenuItem::constant("Golden ratio", "1.618033989"),
+    MenuItem::constant("Speed of light m/s", "299792458"),
+    MenuItem::constant("Gravity m/s^2", "9.80665"),
+    MenuItem::constant("0 °C in K", "273.15"),
+    MenuItem::constant("Free space imp. Ohm", "376.730313412"),
+];
+
+const MATH: &[MenuItem] = &[
+    MenuItem::submenu("Statistics", STATISTICS),
+    MenuItem::submenu("Vectors", VECTORS),
+    MenuItem::submenu("Number theory", NUMBER_THEORY),
+    MenuItem::command("Hypotenuse", "hypot"),
+    MenuItem::command("Polynomial at x", "poly eval"),
+    MenuItem::command("Quadratic roots", "poly roots"),
+    MenuItem::command("Plot polynomial", "plot"),
+];
+
+const STATISTICS: &[MenuItem] = &[
+    MenuItem::command("Add x", "s+"),
+    MenuItem::command("Add x, y", "s+ pair"),
+    MenuItem::command("Remove x", "s-"),
+    MenuItem::command("Remove x, y", "s- pair"),
+    MenuItem::command("Means", "smean"),
+    MenuItem::command("Std. deviations", "ssdev"),
+    MenuItem::command("Linear regression", "lr"),
+    MenuItem::command("Predict y", "predict"),
+    MenuItem::command("Show registers", "stats"),
+    MenuItem::command("Clear registers", "stats clear"),
+    MenuItem::command("Sum of stack", "sum"),
+    MenuItem::command("Mean of stack", "mean"),
+    MenuItem::command("Std. dev. of stack", "sdev"),
+];
+
+const VECTORS: &[MenuItem] = &[
+    MenuItem::command("Dot product", "dot"),
+    MenuItem::command("Cross product", "cross"),
+    MenuItem::command("Magnitude", "mag"),
+    MenuItem::command("Unit vector", "unit"),
+    MenuItem::command("Show vector", "vec"),
+];
+
+const NUMBER_THEORY: &[MenuItem] = &[
+    MenuItem::command("GCD", "gcd"),
+    MenuItem::command("LCM", "lcm"),
+    MenuItem::command("Combinations", "ncr"),
+    MenuItem::command("Permutations", "npr"),
+    MenuItem::command("Is prime?", "isprime"),
+    MenuItem::command("Factorize", "factor"),
+];
+
+const PROGRAMS: &[MenuItem] = &[
+    MenuItem::command("Play macro", "macro play"),
+    MenuItem::command("Record macro", "macro record"),
+    MenuItem::command("Stop recording", "macro stop"),
+    MenuItem::command("Stopwatch start", "stopwatch start"),
+    MenuItem::command("Stopwatch lap", "stopwatch lap"),
+    MenuItem::command("Stopwatch stop", "stopwatch stop"),
+    MenuItem::command("Run script", "script"),
+];
+
+const SYSTEM: &[MenuItem] = &[
+    MenuItem::command("Version", "version"),
+    MenuItem::command("Uptime", "uptime"),
+    MenuItem::command("Battery voltage", "vbat"),
+    MenuItem::command("Registers", "regs"),
+    MenuItem::command("Error log", "errlog"),
+    MenuItem::command("Save stack", "persist"),
+    MenuItem::command("Self-test", "selftest"),
+    MenuItem::command("Reset", "reset"),
+];
+
+/// Whether the command of every item of the list and its submenus is in the registry, so that a typo doesn't wait until someone picks it.
+/// Checked by the HIL test, the host has no `Panel` to look the commands up with.
+pub fn commands_exist<D>(items: &[MenuItem]) -> bool
+where
+    D: Panel,
+{
+    items.iter().all(|item| match item.action {
+        MenuAction::Submenu(items) => commands_exist::<D>(items),
+        MenuAction::Command(line) => commands::find::<D>(line.split(' ').next().unwrap_or(line)).is_some(),
+        MenuAction::Constant(_) => true,
+    })
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Where we are in the menu: the lists opened so far, with the item selected in each.
+///
+/// Every list gets an extra item past its end, going back up (or closing the menu at the root),
+/// so that it can be used with just the rotary encoder's turning and pressing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Menu {
+    /// The root first, the list shown last. Never empty.
+    path: Vec<(&'static [MenuItem], usize), MAX_DEPTH>,
+}
+
+impl Menu {
+    pub fn new(root: &'static [MenuItem]) -> Self {
+        let mut path = Vec::new();
+        let _ = path.push((root, 0)); // Can't fail, it's empty
+        Menu { path }
+    }
+
+    fn current(&self) -> (&'static [MenuItem], usize) {
+        *self.path.last().expect("The root is never popped")
+    }
+
+    /// The items of the list shown, without the extra one going back
+    pub fn items(&self) -> &'static [MenuItem] {
+        self.current().0
+    }
+
+    /// Index of the selected item, `items().len()` being the extra one going back
+    pub fn selected(&self) -> usize {
+        self.current().1
+    }
+
+    /// How many lists deep we are, 1 at the root
+    pub fn depth(&self) -> usize {
+        self.path.len()
+    }
+
+    /// Label of the item that opened the list shown, `None` at the root
+    pub fn title(&self) -> Option<&'static str> {
+        let [.., (parent, index), _] = self.path.as_slice() else {
+            return None;
+        };
+        Some(parent[*index].label)
+    }
+
+    fn select(&mut self, index: usize) {
+        if let Some(last) = self.path.last_mut() {
+            last.1 = index;
+        }
+    }
+
+    /// Selects the item above, wrapping around to the bottom
+    pub fn up(&mut self) {
+        let count = self.items().len() + 1;
+        self.select((self.selected() + count - 1) % count);
+    }
+
+    /// Selects the item below, wrapping around to the top
+    pub fn down(&mut self) {
+        let count = self.items().len() + 1;
+        self.select((self.selected() + 1) % count);
+    }
+
+    /// Goes back to the list above. Returns false if we're at the root, i.e. the menu is to be closed.
+    pub fn back(&mut self) -> bool {
+        if self.depth() == 1 {
+            return false;
+        }
+        self.path.pop();
+        true
+    }
+
+    /// Chooses the selected item: opens a submenu, goes back up, or returns the action for the caller to carry out.
+    pub fn enter(&mut self) -> Selection {
+        let (items, index) = self.current();
+        let Some(item) = items.get(index) else {
+            return if self.back() { Selection::Navigated } else { Selection::Closed };
+        };
+        match item.action {
+            MenuAction::Submenu(items) => {
+                if self.path.push((items, 0)).is_err() {
+                    log_error!("Menu {:?} nests deeper than {} lists", item.label, MAX_DEPTH);
+                }
+                Selection::Navigated
+            },
+            action => Selection::Action(action),
+        }
+    }
+
+    /// Clears the display and draws the list shown, the title on the first line, scrolled so that the selected item is visible.
+    pub fn draw<D>(&self, disp: &mut D) -> Result<(), CustomError>
+    where
+        D: FlushableDisplay,
+        CustomError: From<D::Error>,
+    {
+        disp.clear(D::Color::BACKGROUND)?;
+        let area = disp.bounding_box();
+        let title = self.title().unwrap_or("Menu");
+        Text::with_baseline(title, area.top_left, text_style(), Baseline::Top).draw(disp)?;
+        let underline_y = area.top_left.y + LINE_HEIGHT as i32 - 1;
+        Rectangle::new(Point::new(area.top_left.x, underline_y), Size::new(area.size.width, 1))
+            .into_styled(PrimitiveStyle::with_fill(D::Color::FOREGROUND))
+            .draw(disp)?;
+
+        let visible = (area.size.height / LINE_HEIGHT).saturating_sub(1).max(1) as usize; // Without the title
+        let first = (self.selected() + 1).saturating_sub(visible);
+        let back = if self.depth() == 1 { "< Close" } else { "< Back" };
+        let labels = self.items().iter().map(|item| (item.label, matches!(item.action, MenuAction::Submenu(_))))
+            .chain(core::iter::once((back, false)));
+        for (row, (index, (label, is_submenu))) in labels.enumerate().skip(first).take(visible).enumerate() {
+            let top_left = area.top_left + Point::new(0, ((row as u32 + 1) * LINE_HEIGHT) as i32);
+            let style = if index == self.selected() {
+                Rectangle::new(top_left, Size::new(area.size.width, LINE_HEIGHT))
+                    .into_styled(PrimitiveStyle::with_fill(D::Color::HIGHLIGHT))
+                    .draw(disp)?;
+                selected_style()
+            } else {
+                text_style()
+            };
+            Text::with_baseline(label, top_left, style, Baseline::Top).draw(disp)?;
+            if is_submenu {
+                let arrow = Point::new(area.top_left.x + area.size.width as i32 - 6, top_left.y);
+                Text::with_baseline(">", arrow, style, Baseline::Top).draw(disp)?;
+            }
+        }
+        disp.flush_display()
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::{Menu, MenuAction, MenuItem, Selection, ROOT, MAX_DEPTH};
+    use crate::decfix::DecimalFixed;
+    use crate::display::NullDisplay;
+
+    const INNER: &[MenuItem] = &[
+        MenuItem::command("Drop", "drop"),
+        MenuItem::constant("Pi", "3.141592654"),
+    ];
+    const OUTER: &[MenuItem] = &[
+        MenuItem::command("Dup", "dup"),
+        MenuItem::submenu("Inner", INNER),
+    ];
+
+    #[test]
+    fn navigates_into_and_out_of_submenus() {
+        let mut menu = Menu::new(OUTER);
+        assert_eq!(menu.title(), None);
+        assert_eq!(menu.enter(), Selection::Action(MenuAction::Command("dup")));
+
+        menu.down();
+        assert_eq!(menu.enter(), Selection::Navigated);
+        assert_eq!(menu.title(), Some("Inner"));
+        assert_eq!(menu.depth(), 2);
+        menu.up(); // Wraps around onto the extra item going back
+        assert_eq!(menu.selected(), INNER.len());
+        menu.up();
+        assert_eq!(menu.enter(), Selection::Action(MenuAction::Constant("3.141592654")));
+
+        // Back where we came from, on the same item
+        assert!(menu.back());
+        assert_eq!(menu.selected(), 1);
+        assert!(!menu.back());
+    }
+
+    #[test]
+    fn extra_item_goes_back_or_closes() {
+        let mut menu = Menu::new(OUTER);
+        menu.down();
+        menu.enter();
+        menu.draw(&mut NullDisplay).unwrap();
+        menu.down();
+        menu.down();
+        assert_eq!(menu.enter(), Selection::Navigated);
+        assert_eq!(menu.depth(), 1);
+        menu.down();
+        menu.down();
+        assert_eq!(menu.selected(), 0); // Past the extra item, wrapped around
+        menu.up();
+        assert_eq!(menu.enter(), Selection::Closed);
+    }
+
+    /// Walks the whole menu, so that a typo in a constant or a too deep submenu doesn't wait until someone opens it
+    fn check(items: &'static [MenuItem], depth: usize) {
+        assert!(depth <= MAX_DEPTH);
+        for item in items {
+            assert!(item.label.chars().count() <= 20, "{:?} doesn't fit next to the arrow", item.label);
+            match item.action {
+                MenuAction::Submenu(items) => check(items, depth + 1),
+                MenuAction::Constant(value) => assert!(DecimalFixed::parse_str(value, None).is_ok(), "{:?}", value),
+                MenuAction::Command(command) => assert!(!command.is_empty()),
+            }
+        }
+    }
+
+    #[test]
+    fn whole_menu_is_valid() {
+        check(ROOT, 1);
+    }
+}
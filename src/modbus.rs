@@ -0,0 +1,321 @@
+use heapless::Vec;
+
+use crate::decfix::DecimalFixed;
+use crate::registers::REGISTER_COUNT;
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Longest RTU frame (application data unit), as the standard limits it
+pub const MAX_FRAME: usize = 256;
+/// Most decimal places of the scaled integers, a `DecimalFixed` doesn't have more than 9
+pub const MAX_SCALE: u8 = 9;
+/// What a stack level or a register reads as when it's empty or its value doesn't fit into an `i32` scaled
+pub const NOT_A_NUMBER: i32 = i32::MIN;
+/// Stack levels mapped to the holding registers, from the top
+pub const STACK_LEVELS: u16 = 16;
+/// Where the stack levels start, two holding registers each
+const LEVELS_START: u16 = 100;
+/// Where the registers A to Z start, two holding registers each
+const MEMORY_START: u16 = 200;
+/// Most registers a single read may ask for, so that the response fits into a frame
+const MAX_READ: u16 = 125;
+/// Most registers a single write may carry
+const MAX_WRITE: u16 = 123;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Why a request failed, sent back as the exception code of the response
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Exception {
+    IllegalFunction = 1,
+    IllegalDataAddress = 2,
+    IllegalDataValue = 3,
+    DeviceFailure = 4,
+}
+
+impl From<CustomError> for Exception {
+    fn from(e: CustomError) -> Self {
+        match e {
+            CE::BadInput | CE::MathOverflow | CE::ParseIntError(_) => Exception::IllegalDataValue,
+            _ => Exception::DeviceFailure,
+        }
+    }
+}
+
+/// What a holding register holds. The 32-bit values take two registers, the high word first.
+///
+/// | Address       | Access | What                                                                 |
+/// |---------------|--------|----------------------------------------------------------------------|
+/// | 0             | R      | Depth of the stack                                                   |
+/// | 1             | R/W    | Decimal places of the scaled integers, 0 to 9 (e.g. 3: 1.5 is 1500)  |
+/// | 2             | W      | Control: 1 drops the top, 2 clears the stack, 3 ends the Modbus mode |
+/// | 10, 11        | W      | Pushes the value, both registers have to be written at once          |
+/// | 100 + 2n, +1  | R      | Level n of the stack, the top being 0, n up to 15                    |
+/// | 200 + 2r, +1  | R/W    | Register r, A being 0 and Z 25                                       |
+///
+/// The control and push registers read as zero. Empty levels and registers read as `NOT_A_NUMBER`,
+/// and writing it into a register empties it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Register {
+    Depth,
+    Scale,
+    Control,
+    Push { high: bool },
+    Level { n: usize, high: bool },
+    Memory { index: usize, high: bool },
+}
+
+impl Register {
+    /// Returns `None` for an address not in the map
+    pub fn at(address: u16) -> Option<Self> {
+        let high = address.is_multiple_of(2);
+        match address {
+            0 => Some(Register::Depth),
+            1 => Some(Register::Scale),
+            2 => Some(Register::Control),
+            10 | 11 => Some(Register::Push { high: address == 10 }),
+            a if (LEVELS_START..(LEVELS_START + 2 * STACK_LEVELS)).contains(&a) => {
+                Some(Register::Level { n: usize::from((a - LEVELS_START) / 2), high })
+            },
+            a if (MEMORY_START..(MEMORY_START + 2 * REGISTER_COUNT as u16)).contains(&a) => {
+                Some(Register::Memory { index: usize::from((a - MEMORY_START) / 2), high })
+            },
+            _ => None,
+        }
+    }
+}
+
+/// The value multiplied by 10^scale and truncated, or `NOT_A_NUMBER` if it doesn't fit
+pub fn to_scaled(value: DecimalFixed, scale: u8) -> i32 {
+    value.with_exponent(Some(-i32::from(scale)))
+        .ok()
+        .and_then(|scaled| i32::try_from(scaled.value()).ok())
+        .filter(|&raw| raw != NOT_A_NUMBER)
+        .unwrap_or(NOT_A_NUMBER)
+}
+
+/// The value of a scaled integer, see `to_scaled()`. `NOT_A_NUMBER` isn't one, that gives `BadInput`.
+pub fn from_scaled(raw: i32, scale: u8) -> Result<DecimalFixed, CustomError> {
+    if raw == NOT_A_NUMBER {
+        return Err(CE::BadInput);
+    }
+    DecimalFixed::new_prescaled(i64::from(raw), -i32::from(scale)).with_exponent(None)
+}
+
+/// The high or the low word of a 32-bit value
+pub fn word(raw: i32, high: bool) -> u16 {
+    if high { (raw >> 16) as u16 } else { raw as u16 }
+}
+
+/// The 32-bit value with one of its words replaced
+pub fn with_word(raw: i32, value: u16, high: bool) -> i32 {
+    if high {
+        (raw & 0xFFFF) | (i32::from(value as i16) << 16)
+    } else {
+        (raw & !0xFFFF) | i32::from(value)
+    }
+}
+
+/// The CRC of the Modbus RTU frames, sent low byte first
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFF_u16;
+    for &byte in data {
+        crc ^= u16::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xA001 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+/// How long the line has to be silent to end a frame: 3.5 characters of 11 bits, or 1.75 ms above 19200 baud as the standard says
+pub fn silence_us(baud: u32) -> u64 {
+    match baud {
+        0 => 0,
+        b if b > 19_200 => 1_750,
+        b => 38_500_000 / u64::from(b),
+    }
+}
+
+/// Where the holding registers of `respond()` come from
+pub trait RegisterMap {
+    fn read(&mut self, address: u16) -> Result<u16, Exception>;
+    /// Writes the consecutive registers from `address` on, as a single write of the master
+    fn write(&mut self, address: u16, values: &[u16]) -> Result<(), Exception>;
+}
+
+fn be_u16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+/// Handles a function of the request with the data after the function code, returning the data of the response after it
+fn handle<M: RegisterMap>(function: u8, data: &[u8], map: &mut M) -> Result<Vec<u8, MAX_FRAME>, Exception> {
+    let mut response = Vec::new();
+    match function {
+        // Read holding registers, or input registers, which are the same here
+        0x03 | 0x04 => {
+            if data.len() != 4 {
+                return Err(Exception::IllegalDataValue);
+            }
+            let (start, count) = (be_u16(data), be_u16(&data[2..]));
+            if !(1..=MAX_READ).contains(&count) {
+                return Err(Exception::IllegalDataValue);
+            }
+            let _ = response.push((count * 2) as u8); // Can't fail, nor can the ones below, see `MAX_READ`
+            for i in 0..count {
+                let address = start.checked_add(i).ok_or(Exception::IllegalDataAddress)?;
+                let _ = response.extend_from_slice(&map.read(address)?.to_be_bytes());
+            }
+        },
+        // Write single register, the response echoes the request
+        0x06 => {
+            if data.len() != 4 {
+                return Err(Exception::IllegalDataValue);
+            }
+            map.write(be_u16(data), &[be_u16(&data[2..])])?;
+            let _ = response.extend_from_slice(data);
+        },
+        // Write multiple registers, the response echoes the start and the count
+        0x10 => {
+            if data.len() < 5 {
+                return Err(Exception::IllegalDataValue);
+            }
+            let (start, count, byte_count) = (be_u16(data), be_u16(&data[2..]), usize::from(data[4]));
+            if !(1..=MAX_WRITE).contains(&count) || byte_count != usize::from(count) * 2 || data.len() != 5 + byte_count {
+                return Err(Exception::IllegalDataValue);
+            }
+            let values: Vec<u16, { MAX_WRITE as usize }> = data[5..].chunks_exact(2).map(be_u16).collect();
+            map.write(start, &values)?;
+            let _ = response.extend_from_slice(&data[..4]);
+        },
+        _ => return Err(Exception::IllegalFunction),
+    }
+    Ok(response)
+}
+
+/// Handles a whole RTU frame received, returning the frame to reply with.
+/// Returns `None` if there's nothing to reply: the frame is corrupted (too short or the CRC doesn't match), it's for another unit,
+/// or it's a broadcast (unit 0), whose writes are still done.
+pub fn respond<M: RegisterMap>(frame: &[u8], unit: u8, map: &mut M) -> Option<Vec<u8, MAX_FRAME>> {
+    let (body, crc) = frame.split_at_checked(frame.len().checked_sub(2)?)?;
+    if body.len() < 2 || crc16(body).to_le_bytes() != crc {
+        return None;
+    }
+    let (to, function) = (body[0], body[1]);
+    if to != unit && to != 0 {
+        return None;
+    }
+
+    let result = handle(function, &body[2..], map);
+    if to == 0 {
+        return None;
+    }
+    let mut reply: Vec<u8, MAX_FRAME> = Vec::new();
+    // Can't fail, the data is at most `MAX_READ` registers, which leaves room for the rest
+    let _ = match result {
+        Ok(data) => reply.extend_from_slice(&[unit, function]).and_then(|()| reply.extend_from_slice(&data)),
+        Err(exception) => reply.extend_from_slice(&[unit, function | 0x80, exception as u8]),
+    };
+    let crc = crc16(&reply);
+    let _ = reply.extend_from_slice(&crc.to_le_bytes());
+    Some(reply)
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::{crc16, from_scaled, respond, silence_us, to_scaled, with_word, word, Exception, Register, RegisterMap, NOT_A_NUMBER};
+    use crate::decfix::DecimalFixed;
+
+    /// Holding registers that just keep what's written, up to address 9
+    struct Plain([u16; 10]);
+
+    impl RegisterMap for Plain {
+        fn read(&mut self, address: u16) -> Result<u16, Exception> {
+            self.0.get(usize::from(address)).copied().ok_or(Exception::IllegalDataAddress)
+        }
+
+        fn write(&mut self, address: u16, values: &[u16]) -> Result<(), Exception> {
+            for (i, &value) in values.iter().enumerate() {
+                *self.0.get_mut(usize::from(address) + i).ok_or(Exception::IllegalDataAddress)? = value;
+            }
+            Ok(())
+        }
+    }
+
+    /// The request with its CRC appended
+    fn frame(bytes: &[u8]) -> std::vec::Vec<u8> {
+        let mut frame = bytes.to_vec();
+        frame.extend_from_slice(&crc16(bytes).to_le_bytes());
+        frame
+    }
+
+    #[test]
+    fn answers_requests() {
+        // The example of the standard's CRC appendix
+        assert_eq!(crc16(&[0x02, 0x07]), 0x1241);
+
+        let mut map = Plain([0; 10]);
+        map.0[1] = 0x1234;
+        let reply = respond(&frame(&[0x11, 0x03, 0x00, 0x00, 0x00, 0x02]), 0x11, &mut map).unwrap();
+        assert_eq!(reply, *frame(&[0x11, 0x03, 0x04, 0x00, 0x00, 0x12, 0x34]));
+
+        let reply = respond(&frame(&[0x11, 0x06, 0x00, 0x02, 0xAB, 0xCD]), 0x11, &mut map).unwrap();
+        assert_eq!(reply, *frame(&[0x11, 0x06, 0x00, 0x02, 0xAB, 0xCD]));
+        let reply = respond(&frame(&[0x11, 0x10, 0x00, 0x03, 0x00, 0x02, 0x04, 0x00, 0x01, 0x00, 0x02]), 0x11, &mut map).unwrap();
+        assert_eq!(reply, *frame(&[0x11, 0x10, 0x00, 0x03, 0x00, 0x02]));
+        assert_eq!(map.0[2..5], [0xABCD, 1, 2]);
+
+        // Exceptions
+        let reply = respond(&frame(&[0x11, 0x03, 0x00, 0x09, 0x00, 0x02]), 0x11, &mut map).unwrap();
+        assert_eq!(reply, *frame(&[0x11, 0x83, 0x02]));
+        let reply = respond(&frame(&[0x11, 0x2B, 0x0E]), 0x11, &mut map).unwrap();
+        assert_eq!(reply, *frame(&[0x11, 0xAB, 0x01]));
+        let reply = respond(&frame(&[0x11, 0x10, 0x00, 0x00, 0x00, 0x02, 0x02, 0x00, 0x01]), 0x11, &mut map).unwrap();
+        assert_eq!(reply, *frame(&[0x11, 0x90, 0x03]));
+
+        // Nothing to reply to
+        assert_eq!(respond(&frame(&[0x12, 0x03, 0x00, 0x00, 0x00, 0x01]), 0x11, &mut map), None);
+        let mut corrupted = frame(&[0x11, 0x03, 0x00, 0x00, 0x00, 0x01]);
+        corrupted[3] ^= 1;
+        assert_eq!(respond(&corrupted, 0x11, &mut map), None);
+        assert_eq!(respond(&[0x03], 0x11, &mut map), None);
+        assert_eq!(respond(&frame(&[0x00, 0x06, 0x00, 0x00, 0x00, 0x07]), 0x11, &mut map), None);
+        assert_eq!(map.0[0], 7); // The broadcast still got written
+    }
+
+    #[test]
+    fn scales_values() {
+        let value = DecimalFixed::parse_str("-1.5", None).unwrap();
+        assert_eq!(to_scaled(value, 3), -1500);
+        assert_eq!(from_scaled(-1500, 3), Ok(value));
+        assert_eq!(to_scaled(DecimalFixed::parse_str("1234567.891", None).unwrap(), 3), 1_234_567_891);
+        assert_eq!(to_scaled(DecimalFixed::parse_str("3000000", None).unwrap(), 3), NOT_A_NUMBER);
+        assert!(from_scaled(NOT_A_NUMBER, 0).is_err());
+
+        let raw = -1500;
+        assert_eq!((word(raw, true), word(raw, false)), (0xFFFF, 0xFA24));
+        assert_eq!(with_word(with_word(0, 0xFFFF, true), 0xFA24, false), raw);
+        assert_eq!(with_word(raw, 0x0001, true), 0x0001_FA24);
+    }
+
+    #[test]
+    fn maps_addresses() {
+        assert_eq!(Register::at(1), Some(Register::Scale));
+        assert_eq!(Register::at(11), Some(Register::Push { high: false }));
+        assert_eq!(Register::at(100), Some(Register::Level { n: 0, high: true }));
+        assert_eq!(Register::at(131), Some(Register::Level { n: 15, high: false }));
+        assert_eq!(Register::at(132), None);
+        assert_eq!(Register::at(251), Some(Register::Memory { index: 25, high: false }));
+        assert_eq!(Register::at(252), None);
+
+        assert_eq!(silence_us(9600), 4010);
+        assert_eq!(silence_us(115_200), 1750);
+    }
+}
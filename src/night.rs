@@ -0,0 +1,167 @@
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+const MINUTES_PER_DAY: u16 = 24 * 60;
+/// Contrast at night unless the `night` command says otherwise, dim but still readable in a dark room
+pub const DEFAULT_NIGHT_CONTRAST: u8 = 0x10;
+/// Size of the serialized schedule, see `NightSchedule::to_bytes()`
+pub const SCHEDULE_SIZE: usize = 6;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Parses `HH:MM` into the minutes since midnight.
+pub fn parse_time(input: &str) -> Result<u16, CustomError> {
+    let (hour, minute) = input.split_once(':').ok_or(CE::BadInput)?;
+    let (hour, minute): (u16, u16) = (hour.parse()?, minute.parse()?);
+    if hour >= 24 || minute >= 60 {
+        return Err(CE::BadInput);
+    }
+    Ok(hour * 60 + minute)
+}
+
+/// The other contrast profile besides the usual one: from `start` until `end`, the display gets `contrast` instead,
+/// e.g. dimmed from 22:00 to 07:00. See the `night` command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct NightSchedule {
+    /// Minutes since midnight
+    pub start: u16,
+    /// Minutes since midnight, not included. If it's before `start`, the night goes over midnight.
+    pub end: u16,
+    pub contrast: u8,
+}
+
+impl NightSchedule {
+    /// Parses `HH:MM-HH:MM`, returning `BadInput` if it's malformed or both times are the same
+    pub fn parse(range: &str, contrast: u8) -> Result<Self, CustomError> {
+        let (start, end) = range.split_once('-').ok_or(CE::BadInput)?;
+        let (start, end) = (parse_time(start)?, parse_time(end)?);
+        if start == end {
+            return Err(CE::BadInput); // A night of no time, or of the whole day, is better said by `night off` or `contrast`
+        }
+        Ok(NightSchedule { start, end, contrast })
+    }
+
+    /// Whether it's night at the time of the day
+    pub fn contains(&self, hour: u8, minute: u8) -> bool {
+        let now = u16::from(hour) * 60 + u16::from(minute);
+        if self.start < self.end {
+            (self.start..self.end).contains(&now)
+        } else {
+            now >= self.start || now < self.end
+        }
+    }
+
+    /// Serializes the schedule, or its absence, for `Settings::to_bytes()`: a flag, then the times little-endian and the contrast.
+    pub fn to_bytes(schedule: Option<Self>) -> [u8; SCHEDULE_SIZE] {
+        let Some(schedule) = schedule else {
+            return [0; SCHEDULE_SIZE];
+        };
+        let [start_lo, start_hi] = schedule.start.to_le_bytes();
+        let [end_lo, end_hi] = schedule.end.to_le_bytes();
+        [1, start_lo, start_hi, end_lo, end_hi, schedule.contrast]
+    }
+
+    /// Deserializes the format produced by `to_bytes()`, times out of the day meaning there's no schedule.
+    pub fn from_bytes(bytes: [u8; SCHEDULE_SIZE]) -> Option<Self> {
+        let schedule = NightSchedule {
+            start: u16::from_le_bytes([bytes[1], bytes[2]]),
+            end: u16::from_le_bytes([bytes[3], bytes[4]]),
+            contrast: bytes[5],
+        };
+        (bytes[0] == 1 && schedule.start < MINUTES_PER_DAY && schedule.end < MINUTES_PER_DAY).then_some(schedule)
+    }
+}
+
+/// The contrast the display should have at the time, the night one if there's a schedule and it's night; `day` otherwise,
+/// or if the clock isn't set.
+pub fn contrast_at(schedule: Option<NightSchedule>, time: Option<(u8, u8)>, day: u8) -> u8 {
+    match (schedule, time) {
+        (Some(schedule), Some((hour, minute))) if schedule.contains(hour, minute) => schedule.contrast,
+        _ => day,
+    }
+}
+
+/// Switches the contrast between the day and the night, see `NightSchedule`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct NightMode {
+    /// The contrast last set, `None` if it wasn't set yet
+    applied: Option<u8>,
+}
+
+impl NightMode {
+    pub const fn new() -> Self {
+        NightMode { applied: None }
+    }
+
+    /// Returns the contrast to set if it should change, i.e. the night began or ended, or the schedule changed.
+    /// Meant to be called repeatedly while polling for input, with the time from `WallClock::status_time()`.
+    pub fn tick(&mut self, schedule: Option<NightSchedule>, time: Option<(u8, u8)>, day: u8) -> Option<u8> {
+        let contrast = contrast_at(schedule, time, day);
+        if self.applied == Some(contrast) {
+            return None;
+        }
+        // Without a schedule, only the usual contrast ever gets set, and that's set already by whoever changed it
+        if self.applied.is_none() && schedule.is_none() {
+            self.applied = Some(contrast);
+            return None;
+        }
+        log_debug!("Night mode setting the contrast to {}", contrast);
+        self.applied = Some(contrast);
+        Some(contrast)
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::{contrast_at, parse_time, NightMode, NightSchedule};
+    use crate::custom_error::CE;
+
+    #[test]
+    fn parses_the_schedule() {
+        assert_eq!(parse_time("07:30"), Ok(7 * 60 + 30));
+        assert_eq!(parse_time("24:00"), Err(CE::BadInput));
+        assert_eq!(parse_time("7"), Err(CE::BadInput));
+        assert_eq!(NightSchedule::parse("22:00-07:00", 16), Ok(NightSchedule { start: 22 * 60, end: 7 * 60, contrast: 16 }));
+        assert_eq!(NightSchedule::parse("22:00-22:00", 16), Err(CE::BadInput));
+        assert_eq!(NightSchedule::parse("22:00", 16), Err(CE::BadInput));
+
+        let schedule = NightSchedule::parse("22:00-07:00", 16).ok();
+        assert_eq!(NightSchedule::from_bytes(NightSchedule::to_bytes(schedule)), schedule);
+        assert_eq!(NightSchedule::from_bytes(NightSchedule::to_bytes(None)), None);
+        assert_eq!(NightSchedule::from_bytes([1, 0xFF, 0xFF, 0, 0, 16]), None);
+    }
+
+    #[test]
+    fn dims_over_midnight() {
+        let over = NightSchedule::parse("22:00-07:00", 16).unwrap();
+        assert!(over.contains(22, 0) && over.contains(0, 0) && over.contains(6, 59));
+        assert!(!over.contains(7, 0) && !over.contains(21, 59) && !over.contains(12, 0));
+        let within = NightSchedule::parse("01:00-05:00", 16).unwrap();
+        assert!(within.contains(1, 0) && !within.contains(5, 0) && !within.contains(23, 0));
+
+        assert_eq!(contrast_at(Some(over), Some((23, 0)), 200), 16);
+        assert_eq!(contrast_at(Some(over), Some((12, 0)), 200), 200);
+        assert_eq!(contrast_at(Some(over), None, 200), 200); // The clock isn't set
+    }
+
+    #[test]
+    fn sets_the_contrast_on_change() {
+        let schedule = NightSchedule::parse("22:00-07:00", 16).ok();
+        let mut night = NightMode::new();
+        assert_eq!(night.tick(None, Some((23, 0)), 200), None);
+        assert_eq!(night.tick(schedule, Some((21, 59)), 200), None);
+        assert_eq!(night.tick(schedule, Some((22, 0)), 200), Some(16));
+        assert_eq!(night.tick(schedule, Some((22, 1)), 200), None);
+        assert_eq!(night.tick(schedule, Some((7, 0)), 200), Some(200));
+        // Turning it off at night brings the usual contrast back
+        assert_eq!(night.tick(schedule, Some((23, 0)), 200), Some(16));
+        assert_eq!(night.tick(None, Some((23, 0)), 200), Some(200));
+    }
+}
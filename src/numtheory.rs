@@ -0,0 +1,164 @@
+use heapless::Vec;
+
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Most prime factors a number can have, every one is at least 2 and a u64 has 64 bits
+pub const MAX_FACTORS: usize = 64;
+/// Greatest number `is_prime()` and `factor()` accept, so that the trial division takes under a second on the RP2040.
+/// Numbers on the stack (with the default exponent) are below 10^10 anyway.
+pub const MAX_TRIAL_DIVISION: u64 = 1_000_000_000_000;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Returns the greatest common divisor by Euclid's algorithm, `gcd(0, 0)` is zero.
+pub fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Returns the least common multiple, or `MathOverflow` if it doesn't fit. If either is zero, so is the result.
+pub fn lcm(a: u64, b: u64) -> Result<u64, CustomError> {
+    if a == 0 || b == 0 {
+        return Ok(0);
+    }
+    // Dividing first, so that only a result that's really too big overflows
+    (a / gcd(a, b)).checked_mul(b).ok_or(CE::MathOverflow)
+}
+
+/// Returns the number of permutations, i.e. the ways to pick an ordered `k` out of `n`, `n! / (n - k)!`.
+/// Returns zero if `k` is greater than `n`, and `MathOverflow` if it doesn't fit.
+pub fn npr(n: u64, k: u64) -> Result<u64, CustomError> {
+    if k > n {
+        return Ok(0);
+    }
+    // Only the factors that don't cancel out with (n - k)!, so that we don't compute the huge n! at all
+    (n - k + 1..=n).try_fold(1_u64, |acc, i| acc.checked_mul(i).ok_or(CE::MathOverflow))
+}
+
+/// Returns the number of combinations, i.e. the ways to pick an unordered `k` out of `n`, `n! / (k! (n - k)!)`.
+/// Returns zero if `k` is greater than `n`, and `MathOverflow` if it doesn't fit.
+pub fn ncr(n: u64, k: u64) -> Result<u64, CustomError> {
+    if k > n {
+        return Ok(0);
+    }
+    let k = k.min(n - k); // C(n, k) = C(n, n - k), the fewer steps the better
+    let mut result: u64 = 1;
+    for i in 1..=k {
+        // result * (n - k + i) / i, which is always a whole number, C(n - k + i, i) to be exact.
+        // Both get divided by their common divisors first, so that only a result that's really too big overflows.
+        let (numerator, denominator) = (n - k + i, i);
+        let g = gcd(result, denominator);
+        let (result_part, denominator) = (result / g, denominator / g);
+        // The rest of the denominator divides the numerator, since it's coprime with what's left of the result
+        result = result_part.checked_mul(numerator / denominator).ok_or(CE::MathOverflow)?;
+    }
+    Ok(result)
+}
+
+/// Returns whether the number is a prime, by trial division. Returns `BadInput` above `MAX_TRIAL_DIVISION`.
+pub fn is_prime(n: u64) -> Result<bool, CustomError> {
+    if n > MAX_TRIAL_DIVISION {
+        return Err(CE::BadInput);
+    }
+    Ok(n >= 2 && smallest_factor(n) == n)
+}
+
+/// Returns the prime factors of the number in ascending order, repeated as many times as they divide it, e.g. `[2, 2, 3]` for 12.
+/// Returns `BadInput` below 2 (they have no prime factors) or above `MAX_TRIAL_DIVISION`.
+pub fn factor(mut n: u64) -> Result<Vec<u64, MAX_FACTORS>, CustomError> {
+    if !(2..=MAX_TRIAL_DIVISION).contains(&n) {
+        return Err(CE::BadInput);
+    }
+    let mut factors = Vec::new();
+    while n > 1 {
+        let p = smallest_factor(n);
+        factors.push(p).map_err(|_| CE::Impossible)?; // See `MAX_FACTORS`
+        n /= p;
+    }
+    Ok(factors)
+}
+
+/// Returns the smallest factor of `n` greater than one (`n` itself if it's a prime), for `n` at least 2.
+/// Only tries 2, 3, and the numbers of the form 6k ± 1, since all the other ones are multiples of those two.
+fn smallest_factor(n: u64) -> u64 {
+    for p in [2, 3] {
+        if n.is_multiple_of(p) {
+            return p;
+        }
+    }
+    let mut k = 5;
+    while k * k <= n {
+        if n.is_multiple_of(k) {
+            return k;
+        }
+        if n.is_multiple_of(k + 2) {
+            return k + 2;
+        }
+        k += 6;
+    }
+    n
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::{gcd, lcm, npr, ncr, is_prime, factor, MAX_TRIAL_DIVISION};
+    use crate::custom_error::CE;
+
+    #[test]
+    fn gcd_and_lcm() {
+        assert_eq!(gcd(12, 18), 6);
+        assert_eq!(gcd(17, 5), 1);
+        assert_eq!(gcd(0, 7), 7);
+        assert_eq!(gcd(0, 0), 0);
+        assert_eq!(lcm(4, 6), Ok(12));
+        assert_eq!(lcm(0, 6), Ok(0));
+        assert_eq!(lcm(u64::MAX, u64::MAX), Ok(u64::MAX));
+        assert_eq!(lcm(u64::MAX, 2), Err(CE::MathOverflow));
+    }
+
+    #[test]
+    fn combinatorics() {
+        assert_eq!(npr(5, 2), Ok(20));
+        assert_eq!(npr(5, 0), Ok(1));
+        assert_eq!(npr(2, 5), Ok(0));
+        assert_eq!(npr(20, 20), Ok(2_432_902_008_176_640_000)); // 20!
+        assert_eq!(npr(21, 21), Err(CE::MathOverflow));
+
+        assert_eq!(ncr(5, 2), Ok(10));
+        assert_eq!(ncr(49, 6), Ok(13_983_816));
+        assert_eq!(ncr(7, 7), Ok(1));
+        assert_eq!(ncr(2, 5), Ok(0));
+        // Both would overflow on the way with the factorials, or even with the multiplication before the division
+        assert_eq!(ncr(66, 33), Ok(7_219_428_434_016_265_740));
+        assert_eq!(ncr(1_000_000, 1), Ok(1_000_000));
+        assert_eq!(ncr(68, 34), Err(CE::MathOverflow));
+    }
+
+    #[test]
+    fn primes() {
+        let primes: std::vec::Vec<u64> = (0..30).filter(|&n| is_prime(n).unwrap()).collect();
+        assert_eq!(primes, [2, 3, 5, 7, 11, 13, 17, 19, 23, 29]);
+        assert_eq!(is_prime(999_999_937), Ok(true));
+        assert_eq!(is_prime(25), Ok(false));
+        assert_eq!(is_prime(MAX_TRIAL_DIVISION + 1), Err(CE::BadInput));
+    }
+
+    #[test]
+    fn factors() {
+        assert_eq!(factor(12).unwrap().as_slice(), [2, 2, 3]);
+        assert_eq!(factor(97).unwrap().as_slice(), [97]);
+        assert_eq!(factor(1 << 39).unwrap().len(), 39);
+        assert_eq!(factor(9_999_999_967 * 7).unwrap().as_slice(), [7, 9_999_999_967]);
+        assert_eq!(factor(1), Err(CE::BadInput));
+    }
+}
@@ -0,0 +1,431 @@
+use heapless::{String, Vec};
+use embedded_graphics::{
+    prelude::*,
+
+    mono_font::{
+        iso_8859_2::{FONT_6X12 as ISO_FONT_6X12, FONT_10X20 as ISO_FONT_10X20},
+        MonoTextStyle,
+    },
+    primitives::{
+        PrimitiveStyle,
+        Rectangle,
+    },
+    text::{
+        Alignment,
+        Baseline,
+        Text,
+        TextStyleBuilder,
+    },
+};
+
+use crate::decfix::DecimalFixed;
+use crate::registers::{RegisterFile, REGISTER_COUNT};
+use crate::display::{FlushableDisplay, Palette};
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Height of a line of the pages, the small font's height
+const LINE_HEIGHT: u32 = 12;
+/// Longest line of a page, a register's name and a number with all its digits
+const LINE_SIZE: usize = 40;
+/// How many lines the status page has
+const STATUS_LINES: usize = 6;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+const fn text_style<C: Palette>() -> MonoTextStyle<'static, C> {
+    MonoTextStyle::new(&ISO_FONT_6X12, C::FOREGROUND)
+}
+
+/// The time on the clock page, large enough to read from across the desk
+const fn clock_style<C: Palette>() -> MonoTextStyle<'static, C> {
+    MonoTextStyle::new(&ISO_FONT_10X20, C::FOREGROUND)
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Which view fills the display, see `PageManager`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, defmt::Format)]
+pub enum PageKind {
+    /// The stack and the textbox, which draw themselves, the only page where keys do anything
+    #[default] Stack,
+    Registers,
+    Status,
+    Clock,
+}
+
+impl PageKind {
+    /// In the order Tab flips through them
+    pub const ALL: [PageKind; 4] = [PageKind::Stack, PageKind::Registers, PageKind::Status, PageKind::Clock];
+
+    /// The name the `page` command takes
+    pub fn name(self) -> &'static str {
+        match self {
+            PageKind::Stack => "stack",
+            PageKind::Registers => "regs",
+            PageKind::Status => "status",
+            PageKind::Clock => "clock",
+        }
+    }
+
+    /// Returns `BadInput` for an unknown name
+    pub fn from_name(name: &str) -> Result<Self, CustomError> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name).ok_or(CE::BadInput)
+    }
+
+    /// The page after this one, wrapping around to the stack
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&kind| kind == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+/// The date and time shown by the clock page, see `WallClock::wall_time()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WallTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub day_name: &'static str,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+/// What the pages are drawn from, cheap enough to gather on every poll of the main loop
+#[derive(Debug, Clone, Copy)]
+pub struct PageData<'d> {
+    pub registers: &'d RegisterFile,
+    /// Whole seconds since boot, the status page is redrawn when it changes
+    pub uptime_secs: u64,
+    /// `None` until the clock is set
+    pub time: Option<WallTime>,
+}
+
+/// The rest of what the status page shows, gathered only when it's about to be drawn,
+/// as measuring the supply voltage takes a while
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Status {
+    pub stack_len: usize,
+    /// Index of the active stack, see the `ws` command
+    pub workspace: usize,
+    /// `None` if the measurement failed
+    pub vsys: Option<DecimalFixed>,
+    pub errors: usize,
+    pub recording: bool,
+    /// Bytes of the heap in use, `None` without the `alloc` feature
+    pub heap_used: Option<usize>,
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// The registers that aren't empty, as many as fit onto the display
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+struct RegistersPage {
+    /// The registers as they were drawn, `None` if the page has to be drawn anew
+    shown: Option<RegisterFile>,
+}
+
+impl RegistersPage {
+    fn is_dirty(&self, data: &PageData<'_>) -> bool {
+        self.shown.as_ref() != Some(data.registers)
+    }
+
+    /// A line for each register, the last one that fits saying how many more there are
+    fn lines(registers: &RegisterFile, max_lines: usize) -> Result<Vec<String<LINE_SIZE>, REGISTER_COUNT>, CustomError> {
+        let mut lines = Vec::new();
+        let count = registers.count();
+        if count == 0 {
+            lines.push(String::try_from("No registers set")?).map_err(|_| CE::Impossible)?;
+            return Ok(lines);
+        }
+        // The last line says how many don't fit, unless all of them do
+        let shown = if count <= max_lines { count } else { max_lines.saturating_sub(1) };
+        for (name, value) in registers.iter().take(shown) {
+            lines.push(heapless::format!("{}: {}", name, value)?).map_err(|_| CE::Impossible)?;
+        }
+        if shown < count {
+            lines.push(heapless::format!("+{} more", count - shown)?).map_err(|_| CE::Impossible)?;
+        }
+        Ok(lines)
+    }
+
+    fn draw<D>(&mut self, disp: &mut D, data: &PageData<'_>) -> Result<(), CustomError>
+    where
+        D: FlushableDisplay,
+        CustomError: From<D::Error>,
+    {
+        let max_lines = (disp.bounding_box().size.height / LINE_HEIGHT).saturating_sub(1) as usize; // Without the title
+        draw_lines(disp, "Registers", &Self::lines(data.registers, max_lines)?)?;
+        self.shown = Some(data.registers.clone());
+        Ok(())
+    }
+}
+
+/// The diagnostics, refreshed every second
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+struct StatusPage {
+    /// The uptime when it was drawn, `None` if the page has to be drawn anew
+    shown_at: Option<u64>,
+}
+
+impl StatusPage {
+    fn is_dirty(&self, data: &PageData<'_>) -> bool {
+        self.shown_at != Some(data.uptime_secs)
+    }
+
+    fn lines(uptime_secs: u64, status: &Status) -> Result<Vec<String<LINE_SIZE>, STATUS_LINES>, CustomError> {
+        let secs = uptime_secs;
+        let mut lines: Vec<String<LINE_SIZE>, STATUS_LINES> = Vec::new();
+        lines.push(heapless::format!("Up {}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)?).map_err(|_| CE::Impossible)?;
+        lines.push(heapless::format!("Stack {}: {} items", status.workspace + 1, status.stack_len)?).map_err(|_| CE::Impossible)?;
+        let vsys = match status.vsys {
+            Some(vsys) => heapless::format!("VSYS {:.2} V", vsys)?,
+            None => String::try_from("VSYS unknown")?,
+        };
+        lines.push(vsys).map_err(|_| CE::Impossible)?;
+        lines.push(heapless::format!("Errors logged: {}", status.errors)?).map_err(|_| CE::Impossible)?;
+        if status.recording {
+            lines.push(String::try_from("Recording macro")?).map_err(|_| CE::Impossible)?;
+        }
+        if let Some(used) = status.heap_used {
+            lines.push(heapless::format!("Heap {} B used", used)?).map_err(|_| CE::Impossible)?;
+        }
+        Ok(lines)
+    }
+
+    fn draw<D>(&mut self, disp: &mut D, data: &PageData<'_>, status: &Status) -> Result<(), CustomError>
+    where
+        D: FlushableDisplay,
+        CustomError: From<D::Error>,
+    {
+        draw_lines(disp, "Status", &Self::lines(data.uptime_secs, status)?)?;
+        self.shown_at = Some(data.uptime_secs);
+        Ok(())
+    }
+}
+
+/// The time in a large font and the date below it, redrawn when the second changes
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+struct ClockPage {
+    /// The time when it was drawn (itself `None` if the clock wasn't set), `None` if the page has to be drawn anew
+    shown: Option<Option<WallTime>>,
+}
+
+impl ClockPage {
+    fn is_dirty(&self, data: &PageData<'_>) -> bool {
+        self.shown != Some(data.time)
+    }
+
+    /// The large line and the small one below it
+    fn lines(time: Option<WallTime>) -> Result<(String<LINE_SIZE>, String<LINE_SIZE>), CustomError> {
+        let Some(time) = time else {
+            // The RTC counts from its boot date, which would just be confusing
+            return Ok(( String::try_from("--:--:--")?, String::try_from("Not set, see settime")? ));
+        };
+        Ok((
+            heapless::format!("{:02}:{:02}:{:02}", time.hour, time.minute, time.second)?,
+            heapless::format!("{} {}-{:02}-{:02}", time.day_name, time.year, time.month, time.day)?,
+        ))
+    }
+
+    fn draw<D>(&mut self, disp: &mut D, data: &PageData<'_>) -> Result<(), CustomError>
+    where
+        D: FlushableDisplay,
+        CustomError: From<D::Error>,
+    {
+        let (time, date) = Self::lines(data.time)?;
+        disp.clear(D::Color::BACKGROUND)?;
+        let area = disp.bounding_box();
+        let centered = TextStyleBuilder::new().alignment(Alignment::Center).baseline(Baseline::Bottom).build();
+        let center = area.center();
+        Text::with_text_style(&time, center + Point::new(0, 4), clock_style(), centered).draw(disp)?;
+        let centered = TextStyleBuilder::new().alignment(Alignment::Center).baseline(Baseline::Top).build();
+        Text::with_text_style(&date, center + Point::new(0, 8), text_style(), centered).draw(disp)?;
+        disp.flush_display()?;
+        self.shown = Some(data.time);
+        Ok(())
+    }
+}
+
+/// Clears the display and draws the title with a line under it, and the lines below it.
+/// Lines too long for the display just get cut off by its edge.
+fn draw_lines<D>(disp: &mut D, title: &str, lines: &[String<LINE_SIZE>]) -> Result<(), CustomError>
+where
+    D: FlushableDisplay,
+    CustomError: From<D::Error>,
+{
+    disp.clear(D::Color::BACKGROUND)?;
+    let area = disp.bounding_box();
+    Text::with_baseline(title, area.top_left, text_style(), Baseline::Top).draw(disp)?;
+    let underline_y = area.top_left.y + LINE_HEIGHT as i32 - 1;
+    Rectangle::new(Point::new(area.top_left.x, underline_y), Size::new(area.size.width, 1))
+        .into_styled(PrimitiveStyle::with_fill(D::Color::FOREGROUND))
+        .draw(disp)?;
+    for (row, line) in lines.iter().enumerate() {
+        let top_left = area.top_left + Point::new(0, ((row as u32 + 1) * LINE_HEIGHT) as i32);
+        Text::with_baseline(line, top_left, text_style(), Baseline::Top).draw(disp)?;
+    }
+    disp.flush_display()
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Keeps which page fills the display, see the `page` command and the Tab key.
+///
+/// The stack page is the stack and the textbox, drawn by themselves like always. Every other page
+/// remembers what it has drawn, so that the main loop can ask it on every poll and it only gets redrawn when that changes.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PageManager {
+    current: PageKind,
+    registers: RegistersPage,
+    status: StatusPage,
+    clock: ClockPage,
+}
+
+impl PageManager {
+    pub const fn new() -> Self {
+        PageManager {
+            current: PageKind::Stack,
+            registers: RegistersPage { shown: None },
+            status: StatusPage { shown_at: None },
+            clock: ClockPage { shown: None },
+        }
+    }
+
+    pub fn current(&self) -> PageKind {
+        self.current
+    }
+
+    /// Switches to the page, which gets drawn anew even if it was shown already.
+    /// The stack page is for the caller to redraw.
+    pub fn show(&mut self, kind: PageKind) {
+        self.current = kind;
+        self.invalidate();
+    }
+
+    /// Switches to the next page, see `PageKind::next()`, and returns it
+    pub fn next(&mut self) -> PageKind {
+        self.show(self.current.next());
+        self.current
+    }
+
+    /// Makes the current page get drawn anew, e.g. after a toast covered it
+    pub fn invalidate(&mut self) {
+        match self.current {
+            PageKind::Stack => {},
+            PageKind::Registers => self.registers.shown = None,
+            PageKind::Status => self.status.shown_at = None,
+            PageKind::Clock => self.clock.shown = None,
+        }
+    }
+
+    /// Whether the current page shows something else than what it's to show now, never for the stack page
+    pub fn is_dirty(&self, data: &PageData<'_>) -> bool {
+        match self.current {
+            PageKind::Stack => false,
+            PageKind::Registers => self.registers.is_dirty(data),
+            PageKind::Status => self.status.is_dirty(data),
+            PageKind::Clock => self.clock.is_dirty(data),
+        }
+    }
+
+    /// Draws the current page if it's dirty, `status` being called only if it's the status page.
+    /// Returns whether it drew anything.
+    pub fn draw_if_dirty<D, F>(&mut self, disp: &mut D, data: &PageData<'_>, status: F) -> Result<bool, CustomError>
+    where
+        D: FlushableDisplay,
+        CustomError: From<D::Error>,
+        F: FnOnce() -> Status,
+    {
+        if !self.is_dirty(data) {
+            return Ok(false);
+        }
+        match self.current {
+            PageKind::Stack => {},
+            PageKind::Registers => self.registers.draw(disp, data)?,
+            PageKind::Status => self.status.draw(disp, data, &status())?,
+            PageKind::Clock => self.clock.draw(disp, data)?,
+        }
+        Ok(true)
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::{PageData, PageKind, PageManager, RegistersPage, Status, StatusPage, ClockPage, WallTime};
+    use crate::decfix::{num, DecimalFixed};
+    use crate::registers::RegisterFile;
+    use crate::display::NullDisplay;
+    use crate::custom_error::CE;
+
+    #[test]
+    fn flips_through_the_pages() {
+        let mut pages = PageManager::new();
+        assert_eq!(pages.current(), PageKind::Stack);
+        assert_eq!(pages.next(), PageKind::Registers);
+        assert_eq!(pages.next(), PageKind::Status);
+        assert_eq!(pages.next(), PageKind::Clock);
+        assert_eq!(pages.next(), PageKind::Stack);
+
+        for kind in PageKind::ALL {
+            assert_eq!(PageKind::from_name(kind.name()), Ok(kind));
+        }
+        assert_eq!(PageKind::from_name("menu"), Err(CE::BadInput));
+    }
+
+    #[test]
+    fn redraws_only_when_dirty() {
+        let mut registers = RegisterFile::new();
+        let mut pages = PageManager::new();
+        let data = PageData { registers: &registers, uptime_secs: 5, time: None };
+        // The stack draws by itself
+        assert!(!pages.draw_if_dirty(&mut NullDisplay, &data, Status::default).unwrap());
+
+        pages.show(PageKind::Registers);
+        assert!(pages.draw_if_dirty(&mut NullDisplay, &data, Status::default).unwrap());
+        assert!(!pages.draw_if_dirty(&mut NullDisplay, &data, Status::default).unwrap());
+        registers.store("b", num("2")).unwrap();
+        let data = PageData { registers: &registers, uptime_secs: 6, time: None };
+        assert!(pages.is_dirty(&data));
+        assert!(pages.draw_if_dirty(&mut NullDisplay, &data, Status::default).unwrap());
+        pages.invalidate();
+        assert!(pages.is_dirty(&data));
+
+        // The status is only gathered when it's drawn, once a second
+        pages.show(PageKind::Status);
+        let mut gathered = 0;
+        for uptime_secs in [6, 6, 7] {
+            let data = PageData { registers: &registers, uptime_secs, time: None };
+            pages.draw_if_dirty(&mut NullDisplay, &data, || { gathered += 1; Status::default() }).unwrap();
+        }
+        assert_eq!(gathered, 2);
+    }
+
+    #[test]
+    fn page_contents() {
+        let mut registers = RegisterFile::new();
+        assert_eq!(RegistersPage::lines(&registers, 4).unwrap().as_slice(), ["No registers set"]);
+        for (name, value) in [("a", "1.5"), ("c", "-2"), ("x", "3"), ("y", "4"), ("z", "5")] {
+            registers.store(name, num(value)).unwrap();
+        }
+        assert_eq!(RegistersPage::lines(&registers, 4).unwrap().as_slice(), ["A: 1.5", "C: -2", "X: 3", "+2 more"]);
+        assert_eq!(RegistersPage::lines(&registers, 5).unwrap().len(), 5);
+
+        let status = Status { stack_len: 3, workspace: 0, vsys: Some(num("4.987")), errors: 1, recording: true, heap_used: None };
+        assert_eq!(StatusPage::lines(3725, &status).unwrap().as_slice(),
+            ["Up 1:02:05", "Stack 1: 3 items", "VSYS 4.99 V", "Errors logged: 1", "Recording macro"]);
+
+        let time = WallTime { year: 2026, month: 3, day: 7, day_name: "Sat", hour: 9, minute: 5, second: 0 };
+        let (big, small) = ClockPage::lines(Some(time)).unwrap();
+        assert_eq!((big.as_str(), small.as_str()), ("09:05:00", "Sat 2026-03-07"));
+        assert_eq!(ClockPage::lines(None).unwrap().0.as_str(), "--:--:--");
+    }
+}
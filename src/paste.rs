@@ -0,0 +1,242 @@
+use heapless::{Deque, String, Vec};
+
+use crate::display::FlushableDisplay;
+use crate::stack::CustomStack;
+use crate::decfix::DecimalFixed;
+use crate::textbox::TEXT_BUFFER_SIZE;
+use crate::uart_rx::UartRx;
+use crate::get_timestamp_us;
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Longest paste we take, longer ones are read to the end but rejected as a whole
+const PASTE_BUFFER_SIZE: usize = 1024;
+/// How many bytes (including the first one) have to arrive within `BURST_WINDOW_US` to count as a paste.
+/// Nobody types that fast, and the escape sequences of the special keys are excluded by the caller.
+const BURST_BYTES: usize = 4;
+/// How long to wait for the rest of a burst after its first byte, in microseconds.
+/// Four bytes take about 4 ms at 9600 baud, which is the slowest we support (see `baud.rs`).
+const BURST_WINDOW_US: u64 = 5_000;
+/// How long a paste has to stay silent to be over, in microseconds.
+/// Some terminals send pastes in chunks, with short pauses in between.
+const PASTE_GAP_US: u64 = 20_000;
+
+// HACK: Evaluate the block of code at compile time to assert that constants aren't malformed
+const fn _check_consts() {
+    if BURST_BYTES < 2 || BURST_BYTES > PASTE_BUFFER_SIZE {
+        core::panic!("A burst has to be at least two bytes, and fit into the buffer!");
+    }
+}
+const _: () = _check_consts();
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Tells pastes from typing by how fast the bytes arrive, and reads the pastes whole.
+///
+/// Handled key by key, each byte of a paste would redraw the display, and the UART FIFO overruns meanwhile.
+/// Read at once, nothing gets drawn until the paste is over, see `apply()`.
+pub struct PasteDetector {
+    buf: Vec<u8, PASTE_BUFFER_SIZE>,
+    /// Whether the last paste didn't fit into `buf`
+    overflowed: bool,
+    /// Bytes read while looking for a burst that didn't come, to be handled key by key as usual
+    pending: Deque<u8, BURST_BYTES>,
+}
+
+impl PasteDetector {
+    pub const fn new() -> Self {
+        PasteDetector {
+            buf: Vec::new(),
+            overflowed: false,
+            pending: Deque::new(),
+        }
+    }
+
+    /// Takes the next of the bytes that were read ahead by `detect()`, those come before anything else from UART.
+    pub fn pop_pending(&mut self) -> Option<u8> {
+        self.pending.pop_front()
+    }
+
+    /// Checks whether `first`, a byte just read from `rx`, starts a paste. If it does, it reads the whole paste
+    /// (until `PASTE_GAP_US` passes without a byte) and returns true, the paste is then in `text()`.
+    ///
+    /// If it doesn't, the bytes read meanwhile (if any) are left for `pop_pending()`, and it returns false.
+    /// Must only be called with all of those handled already, i.e. not for a pending byte itself.
+    pub fn detect<R: UartRx>(&mut self, rx: &R, first: u8) -> bool {
+        self.buf.clear();
+        self.overflowed = false;
+        let _ = self.buf.push(first); // Can't fail, it's empty
+
+        let mut byte = [0_u8; 1];
+        let deadline = get_timestamp_us() + BURST_WINDOW_US;
+        while self.buf.len() < BURST_BYTES && get_timestamp_us() < deadline {
+            if rx.read_available(&mut byte) > 0 {
+                let _ = self.buf.push(byte[0]); // Can't fail, see `_check_consts()`
+            }
+        }
+        if self.buf.len() < BURST_BYTES {
+            // Can't fail, there's less of them than the capacity, and `pending` was empty
+            self.pending.extend(self.buf.iter().skip(1).copied());
+            return false;
+        }
+
+        let mut last_byte_at = get_timestamp_us();
+        while get_timestamp_us() - last_byte_at < PASTE_GAP_US {
+            if rx.read_available(&mut byte) > 0 {
+                last_byte_at = get_timestamp_us();
+                // We keep reading even when it's full, so that the rest doesn't get typed in key by key
+                if self.buf.push(byte[0]).is_err() {
+                    self.overflowed = true;
+                }
+            }
+        }
+        log_debug!("Paste of {} bytes received (overflowed: {})", self.buf.len(), self.overflowed);
+        true
+    }
+
+    /// The text of the last paste. Returns `CapacityError` if it was too long, or `BadInput` if it isn't valid UTF-8.
+    pub fn text(&self) -> Result<&str, CustomError> {
+        if self.overflowed {
+            return Err(CE::CapacityError);
+        }
+        core::str::from_utf8(&self.buf).map_err(|_| CE::BadInput)
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// A single whitespace-separated word of a paste
+enum Token {
+    Number(DecimalFixed),
+    Operator(char),
+}
+
+/// Splits the text into lines, ended by `\n`, `\r\n` or just `\r` (which is what most terminals send for Enter)
+fn lines(text: &str) -> impl Iterator<Item = &str> {
+    let separator = if text.contains('\n') { '\n' } else { '\r' };
+    text.split(separator).map(|line| line.trim_end_matches('\r'))
+}
+
+fn parse_token(word: &str) -> Result<Token, CustomError> {
+    if let [c @ (b'+' | b'-' | b'*' | b'/')] = word.as_bytes() {
+        return Ok(Token::Operator(char::from(*c)));
+    }
+    // Decimal commas are accepted, just like with the keys
+    let mut number = String::<TEXT_BUFFER_SIZE>::new();
+    for c in word.chars() {
+        number.push(if c == ',' { '.' } else { c })?;
+    }
+    Ok(Token::Number(DecimalFixed::parse_str(&number, None)?))
+}
+
+/// Does an operation on the top two elements. Unlike the keys, it leaves them in place if it fails,
+/// so that a paste stopping at an error doesn't lose anything on top of what it didn't do.
+fn operate<D>(stack: &mut CustomStack<'_, DecimalFixed, D>, last_x: &mut Option<DecimalFixed>, operator: char) -> Result<(), CustomError>
+where
+    D: FlushableDisplay,
+{
+    let (Some(&b), Some(&a)) = (stack.peek(), stack.peek_nth(1)) else {
+        return Err(CE::BadInput);
+    };
+    let c = match operator {
+        '+' => a + b,
+        '-' => a - b,
+        '*' => a * b,
+        '/' if b.is_zero() => Err(CE::BadInput),
+        '/' => a / b,
+        _ => Err(CE::Impossible), // `parse_token()` only gives us these
+    }?;
+
+    let _ = stack.multipop(2); // Dropping the iterator pops them all the same
+    *last_x = Some(b);
+    stack.push(c).map_err(|(e, _)| e) // Can't fail, we just popped two
+}
+
+/// Runs a paste like it was typed in: each line holds numbers and operators separated by spaces,
+/// e.g. `1.5 2.5 +` or just a number on each line, the operators working on whatever is on the stack by then.
+/// Nothing gets drawn, it's up to the caller to redraw the stack once it's done.
+///
+/// Returns how many lines were run. If any word isn't a number or an operator, nothing is run at all;
+/// if an operation fails, everything before it stays done. Either way, the error comes with the line number (starting at 1).
+pub fn apply<D>(text: &str, stack: &mut CustomStack<'_, DecimalFixed, D>, last_x: &mut Option<DecimalFixed>) -> Result<usize, (usize, CustomError)>
+where
+    D: FlushableDisplay,
+{
+    // Checked first, so that a typo doesn't leave the paste half-done
+    for (i, line) in lines(text).enumerate() {
+        for word in line.split_whitespace() {
+            parse_token(word).map_err(|e| (i + 1, e))?;
+        }
+    }
+
+    let mut count = 0;
+    for (i, line) in lines(text).enumerate() {
+        for word in line.split_whitespace() {
+            let result = match parse_token(word) {
+                Ok(Token::Number(value)) => stack.push(value).map_err(|(e, _)| e),
+                Ok(Token::Operator(operator)) => operate(stack, last_x, operator),
+                Err(e) => Err(e),
+            };
+            result.map_err(|e| (i + 1, e))?;
+        }
+        if !line.trim().is_empty() {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use embedded_graphics::pixelcolor::BinaryColor;
+    use super::apply;
+    use crate::stack::{CustomStack, CustomStackBuilder};
+    use crate::display::NullDisplay;
+    use crate::decfix::{num, DecimalFixed};
+    use crate::custom_error::CE;
+
+    fn stack<'a>() -> CustomStack<'a, DecimalFixed, NullDisplay> {
+        CustomStackBuilder::<BinaryColor>::new().build_headless()
+    }
+
+    #[test]
+    fn numbers_and_operators() {
+        let mut s = stack();
+        let mut last_x = None;
+        assert_eq!(apply("1.5\r\n2,5\r\n+\r\n\r\n10 4 -\r\n*\r\n", &mut s, &mut last_x), Ok(5));
+        assert_eq!(s.iter().copied().collect::<std::vec::Vec<_>>(), [num("24")]);
+        assert_eq!(last_x, Some(num("6")));
+
+        // Just carriage returns, and no line ending at the end
+        assert_eq!(apply("1e3\r-2\r/", &mut s, &mut last_x), Ok(3));
+        assert_eq!(s.peek(), Some(&num("-500")));
+        assert_eq!(s.peek_nth(1), Some(&num("24")));
+    }
+
+    #[test]
+    fn typo_runs_nothing() {
+        let mut s = stack();
+        let mut last_x = None;
+        assert_eq!(apply("1\n2\n3x\n+\n", &mut s, &mut last_x), Err((3, CE::ParseIntError(crate::custom_error::IntErrorKindClone::InvalidDigit))));
+        assert!(s.is_empty());
+    }
+
+    #[test]
+    fn failed_operation_keeps_what_was_done() {
+        let mut s = stack();
+        let mut last_x = None;
+        assert_eq!(apply("1\n2 +\n0 /\n5\n", &mut s, &mut last_x), Err((3, CE::BadInput)));
+        // The operands of the failed division stay, the rest doesn't run
+        assert_eq!(s.iter().copied().collect::<std::vec::Vec<_>>(), [num("3"), num("0")]);
+        assert_eq!(last_x, Some(num("2")));
+
+        assert_eq!(apply("*", &mut stack(), &mut last_x), Err((1, CE::BadInput)));
+    }
+}
@@ -0,0 +1,162 @@
+
+use crate::flash::{Flash, OnboardFlash, PageWriter, SECTOR_SIZE, STACK_REGION};
+use crate::kv;
+use crate::stack::CustomStack;
+use crate::display::FlushableDisplay;
+use crate::decfix::DecimalFixed;
+use crate::settings::{Settings, SETTINGS_SIZE};
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Marks a valid saved stack, so that we don't try to restore an erased or foreign sector. Spells "STK1" in ASCII.
+const MAGIC: u32 = u32::from_le_bytes(*b"STK1");
+/// Size of the header: magic, element count and checksum, each a little-endian u32
+const HEADER_SIZE: usize = 12;
+/// Size of one serialized element, see `DecimalFixed::to_le_bytes()`
+const ELEMENT_SIZE: usize = 12;
+/// Prefixes the saved settings, like `MAGIC` does for the stack. Spells "SETV" in ASCII.
+const SETTINGS_MAGIC: u32 = u32::from_le_bytes(*b"SETV");
+/// Follows `SETTINGS_MAGIC`, goes up with every field appended to `Settings::to_bytes()`.
+/// Only shows up in the logs, `Settings::from_bytes()` tells which fields were saved by the length alone.
+const SETTINGS_VERSION: u8 = 1;
+/// Magic of the settings saved before `SETTINGS_VERSION`, their layout is the same as that of version 1
+const LEGACY_SETTINGS_MAGIC: u32 = u32::from_le_bytes(*b"SETC");
+/// Key of the settings in the key-value store
+const SETTINGS_KEY: &str = "settings";
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Saves the whole stack into its flash region, overwriting whatever was saved before.
+/// Takes some tens of milliseconds with interrupts disabled, mostly for the erase.
+///
+/// Only the elements in RAM are saved, not those spilled into flash (see `spill.rs`).
+pub fn save_stack<D>(stack: &CustomStack<'_, DecimalFixed, D>) -> Result<(), CustomError>
+where
+    D: FlushableDisplay,
+{
+    if HEADER_SIZE + stack.len() * ELEMENT_SIZE > SECTOR_SIZE as usize {
+        log_error!("Stack of {} elements doesn't fit into the flash region", stack.len());
+        return Err(CE::CapacityError);
+    }
+
+    // We compute the checksum beforehand, so that we can write it into the header and stream the rest page by page
+    let checksum = stack.iter() // Bottom first, so that they get pushed back in the same order
+        .fold(fnv1a_init(), |hash, x| fnv1a_update(hash, &x.to_le_bytes()));
+
+    let mut flash = OnboardFlash;
+    flash.erase(STACK_REGION, SECTOR_SIZE)?;
+    let mut writer = PageWriter::new(&mut flash, STACK_REGION, SECTOR_SIZE);
+    writer.write(&MAGIC.to_le_bytes())?;
+    writer.write(&(stack.len() as u32).to_le_bytes())?;
+    writer.write(&checksum.to_le_bytes())?;
+    for x in stack {
+        writer.write(&x.to_le_bytes())?;
+    }
+    writer.finish()?;
+
+    log_info!("Saved {} stack elements into flash", stack.len());
+    Ok(())
+}
+
+/// Restores the stack saved by `save_stack()`, pushing the elements on top of whatever's on the stack already.
+/// Returns the number of restored elements, zero if there was nothing saved.
+/// Returns `BadInput` if the saved data is corrupted, in which case the stack is left untouched.
+pub fn restore_stack<D>(stack: &mut CustomStack<'_, DecimalFixed, D>) -> Result<usize, CustomError>
+where
+    D: FlushableDisplay,
+{
+    let mut region = [0_u8; SECTOR_SIZE as usize];
+    OnboardFlash.read(STACK_REGION, &mut region)?;
+    let read_u32 = |i: usize| u32::from_le_bytes(
+        region[i..(i + 4)].try_into().expect("Subslice is exactly 4 bytes long")
+    );
+
+    if read_u32(0) != MAGIC {
+        log_info!("No saved stack found in flash");
+        return Ok(0);
+    }
+
+    let count = read_u32(4) as usize;
+    let Some(elements) = region.get(HEADER_SIZE..(HEADER_SIZE + count * ELEMENT_SIZE)) else {
+        log_error!("Saved stack claims to have {} elements, which doesn't fit into the region", count);
+        return Err(CE::BadInput);
+    };
+
+    let checksum = elements.chunks_exact(ELEMENT_SIZE)
+        .fold(fnv1a_init(), fnv1a_update);
+    if checksum != read_u32(8) {
+        log_error!("Saved stack checksum mismatch, not restoring it");
+        return Err(CE::BadInput);
+    }
+
+    let values = elements.chunks_exact(ELEMENT_SIZE)
+        .map(|chunk| DecimalFixed::from_le_bytes(chunk.try_into().expect("Chunk is exactly ELEMENT_SIZE long")));
+    if stack.push_exact_iterator(values).is_err() {
+        log_error!("Not enough space on the stack to restore {} elements", count);
+        return Err(CE::CapacityError);
+    };
+
+    log_info!("Restored {} stack elements from flash", count);
+    Ok(count)
+}
+
+/// Saves the settings into the key-value store (see `kv.rs`), replacing whatever was saved before.
+/// Usually only appends a record, erasing a sector only once in a while.
+pub fn save_settings(settings: &Settings) -> Result<(), CustomError> {
+    let mut value = [0_u8; 5 + SETTINGS_SIZE];
+    value[..4].copy_from_slice(&SETTINGS_MAGIC.to_le_bytes());
+    value[4] = SETTINGS_VERSION;
+    value[5..].copy_from_slice(&settings.to_bytes());
+    kv::set(SETTINGS_KEY, &value)?;
+
+    log_info!("Saved settings into flash");
+    Ok(())
+}
+
+/// Restores the settings saved by `save_settings()`, or returns `None` if there were none saved.
+/// Settings saved by another firmware version are restored as far as this one knows them, see `Settings::from_bytes()`.
+pub fn restore_settings() -> Result<Option<Settings>, CustomError> {
+    let mut buf = [0_u8; kv::MAX_VALUE_SIZE];
+    let Some(value) = kv::get(SETTINGS_KEY, &mut buf)? else {
+        log_info!("No saved settings found in flash");
+        return Ok(None);
+    };
+    let Some((magic, rest)) = value.split_first_chunk::<4>() else {
+        log_warn!("Saved settings are too short, not restoring them");
+        return Ok(None);
+    };
+    let bytes = match (u32::from_le_bytes(*magic), rest.split_first()) {
+        (SETTINGS_MAGIC, Some((&version, bytes))) => {
+            if version != SETTINGS_VERSION {
+                log_info!("Settings were saved by layout version {}, ours is {}", version, SETTINGS_VERSION);
+            }
+            bytes
+        },
+        (LEGACY_SETTINGS_MAGIC, _) => rest,
+        _ => {
+            log_warn!("Saved settings have an unknown magic, not restoring them");
+            return Ok(None);
+        },
+    };
+
+    log_info!("Restored settings from flash");
+    Ok(Some(Settings::from_bytes(bytes)))
+}
+
+// A 32-bit FNV-1a hash as the checksum. Not cryptographic in the slightest, but dead simple and good enough to catch corruption.
+pub const fn fnv1a_init() -> u32 {
+    0x811C_9DC5
+}
+
+pub fn fnv1a_update(mut hash: u32, bytes: &[u8]) -> u32 {
+    for &b in bytes {
+        hash ^= b as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    hash
+}
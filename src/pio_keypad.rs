@@ -0,0 +1,208 @@
+use rp2040_hal::{self as hal, pac};
+use hal::dma::{ReadTarget, SingleChannel};
+use hal::pio::{PIOBuilder, PIOExt, PinDir, Rx, Running, ShiftDirection, StateMachine, SM0};
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use crate::keypad::{KeySource, COLS, KEYMAP, REPEAT_DELAY_US, REPEAT_INTERVAL_US, ROWS};
+use crate::get_timestamp_us;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// The first of the consecutive row and column pins, the same ones the polled keypad uses
+const FIRST_ROW_PIN: u8 = 10;
+const FIRST_COL_PIN: u8 = 14;
+/// How long one scan of the whole keypad takes, in microseconds.
+/// Longer than the keys bounce, so that at most one scan catches a bounce and every change of the snapshot is a real one.
+const SCAN_INTERVAL_US: u64 = 20_000;
+/// PIO cycles per scan: four rows of a delayed `set` and an `in`, then four instructions comparing and pushing (or discarding) the snapshot
+const CYCLES_PER_SCAN: u64 = 4 * (32 + 1) + 4;
+/// Base-2 logarithm of the snapshot buffer's size in bytes, the DMA wraps the write address at this many bits
+const RING_BITS: u8 = 6;
+/// Number of snapshots the buffer holds, every one of them is a change, so that's 8 presses and releases between two polls
+pub const SNAPSHOT_COUNT: usize = (1 << RING_BITS) / core::mem::size_of::<u32>();
+
+// HACK: Evaluate the block of code at compile time to assert that constants aren't malformed
+const fn _check_consts() {
+    if core::mem::align_of::<SnapshotBuffer>() != 1 << RING_BITS {
+        core::panic!("The snapshot buffer must be aligned to its size, fix the `repr(align)` of SnapshotBuffer!");
+    }
+    if ROWS != 4 || COLS != 4 {
+        core::panic!("The PIO program scans exactly 4 rows of 4 columns!");
+    }
+}
+const _: () = _check_consts();
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// The buffer the DMA writes the snapshots into, it has to be aligned to its size for the wrapping to work.
+#[repr(C, align(64))]
+pub struct SnapshotBuffer(pub [u32; SNAPSHOT_COUNT]);
+
+/// Scans the keypad by a PIO state machine, which pushes a snapshot of all 16 keys whenever it changes,
+/// and a DMA channel moves the snapshots into a ring buffer. The CPU isn't involved until it polls,
+/// so a key pressed and released during a display flush (or while sleeping in WFI) doesn't get lost.
+///
+/// The debouncing is done by scanning slowly (see `SCAN_INTERVAL_US`), so only the key repeat is left to `poll()`.
+/// Wired the same as the polled `Keypad`, rows driven low one at a time and columns pulled up.
+pub struct PioKeypad<CH: SingleChannel> {
+    channel: CH,
+    /// Kept, so that the state machine keeps running and nobody else reads its FIFO
+    _sm: StateMachine<(pac::PIO0, SM0), Running>,
+    _rx: Rx<(pac::PIO0, SM0)>,
+    /// Only the DMA writes into it, we read it volatile-ly through this pointer
+    buf: *const u32,
+    /// Number of snapshots transferred by the previous DMA runs (all of them are wrapping counters)
+    previous_runs: u32,
+    /// Number of snapshots we've already looked at
+    consumed: u32,
+    pressed: Option<(usize, usize)>,
+    next_repeat: u64,
+}
+
+impl<CH: SingleChannel> PioKeypad<CH> {
+    /// The row and column pins should already be given to PIO0, the columns with pull-ups.
+    pub fn new(pio: pac::PIO0, channel: CH, buf: &'static mut SnapshotBuffer, sys_clock_hz: u32, resets: &mut pac::RESETS) -> Self {
+        // Rows driven low one at a time, each given time to settle before the columns get shifted in.
+        // The 16 bits are compared with the last snapshot (kept in Y) and pushed only if they differ.
+        let program = pio::pio_asm!(
+            ".wrap_target",
+            "scan:",
+            "    set pins, 0b1110 [31]",
+            "    in pins, 4",
+            "    set pins, 0b1101 [31]",
+            "    in pins, 4",
+            "    set pins, 0b1011 [31]",
+            "    in pins, 4",
+            "    set pins, 0b0111 [31]",
+            "    in pins, 4",
+            "    mov x, isr",
+            "    jmp x!=y changed",
+            "    mov isr, null", // Also resets the shift counter
+            "    jmp scan",
+            "changed:",
+            "    mov y, x",
+            "    push noblock", // The DMA keeps the FIFO empty
+            ".wrap",
+        );
+
+        let (mut pio, sm0, _, _, _) = pio.split(resets);
+        let installed = pio.install(&program.program).expect("The keypad's program is the only one in PIO0");
+        let divisor = (sys_clock_hz as u64 * SCAN_INTERVAL_US / 1_000_000 / CYCLES_PER_SCAN).min(u16::MAX as u64) as u16;
+        let (mut sm, rx, _) = PIOBuilder::from_installed_program(installed)
+            .set_pins(FIRST_ROW_PIN, ROWS as u8)
+            .in_pin_base(FIRST_COL_PIN)
+            .in_shift_direction(ShiftDirection::Left) // The first row ends up in the top bits
+            .clock_divisor_fixed_point(divisor, 0)
+            .build(sm0);
+        sm.set_pindirs((0..ROWS as u8).map(|i| (FIRST_ROW_PIN + i, PinDir::Output)));
+
+        let keypad = PioKeypad {
+            channel,
+            _sm: sm.start(),
+            buf: buf.0.as_ptr(),
+            previous_runs: 0,
+            consumed: 0,
+            pressed: None,
+            next_repeat: 0,
+            _rx: rx,
+        };
+
+        let (fifo_address, _) = keypad._rx.rx_address_count();
+        let ch = keypad.channel.ch();
+        ch.ch_read_addr().write(|w| unsafe { w.bits(fifo_address) });
+        ch.ch_write_addr().write(|w| unsafe { w.bits(keypad.buf as u32) });
+        keypad.start();
+        log_debug!("PIO keypad scanning every {} us, clock divisor {}", SCAN_INTERVAL_US, divisor);
+        keypad
+    }
+
+    /// (Re)starts the DMA, continuing at the write address where it stopped.
+    fn start(&self) {
+        let ch = self.channel.ch();
+        ch.ch_trans_count().write(|w| unsafe { w.bits(u32::MAX) });
+        // Writing the control register through this alias triggers the channel
+        // SAFETY: The buffer is ours for 'static, aligned to its size, and the RX FIFO is always readable.
+        ch.ch_ctrl_trig().write(|w| unsafe {
+            w.data_size().size_word();
+            w.incr_read().clear_bit(); // Always the same FIFO
+            w.incr_write().set_bit();
+            w.ring_sel().set_bit(); // Wrap the write address, not the read one
+            w.ring_size().bits(RING_BITS);
+            w.treq_sel().bits(<Rx<(pac::PIO0, SM0)> as ReadTarget>::rx_treq().unwrap_or(0x3F)); // Paced by the state machine
+            w.chain_to().bits(self.channel.id()); // Chaining to itself means no chaining
+            w.en().set_bit();
+            w
+        });
+    }
+
+    /// Total number of snapshots transferred by the DMA so far (wrapping), restarting it if it has stopped.
+    fn transferred(&mut self) -> u32 {
+        let ch = self.channel.ch();
+        let remaining = ch.ch_trans_count().read().bits();
+        let transferred = self.previous_runs.wrapping_add(u32::MAX - remaining);
+
+        if remaining == 0 && ch.ch_ctrl_trig().read().busy().bit_is_clear() {
+            self.previous_runs = transferred;
+            self.start();
+        }
+        // Make sure we read the buffer only after reading how much of it is valid
+        compiler_fence(Ordering::Acquire);
+        transferred
+    }
+
+    /// Returns the next snapshot not looked at yet, if any.
+    /// On overrun, the lost snapshots get skipped and only the newest one is returned.
+    fn next_snapshot(&mut self) -> Option<u32> {
+        let transferred = self.transferred();
+        let pending = transferred.wrapping_sub(self.consumed);
+        if pending == 0 {
+            return None;
+        }
+        if pending as usize > SNAPSHOT_COUNT {
+            log_warn!("Keypad snapshot buffer overrun, {} changes lost", pending as usize - 1);
+            self.consumed = transferred.wrapping_sub(1);
+        }
+
+        let index = self.consumed as usize % SNAPSHOT_COUNT;
+        // SAFETY: The index is within the buffer, which lives for 'static, and the DMA won't overwrite this snapshot until we've read it (or overrun).
+        let snapshot = unsafe { core::ptr::read_volatile(self.buf.add(index)) };
+        self.consumed = self.consumed.wrapping_add(1);
+        Some(snapshot)
+    }
+}
+
+/// Returns the first pressed key of the snapshot in reading order, if any.
+/// Each row is a nibble, the first one on top, with a column's bit cleared when its key is pressed.
+fn first_pressed(snapshot: u32) -> Option<(usize, usize)> {
+    (0..ROWS)
+        .flat_map(|row| (0..COLS).map(move |col| (row, col)))
+        .find(|&(row, col)| snapshot & (1 << ((ROWS - 1 - row) * COLS + col)) == 0)
+}
+
+impl<CH: SingleChannel> KeySource for PioKeypad<CH> {
+    /// Goes through the snapshots since the last poll, returns the byte of a newly pressed (or repeating) key.
+    /// A press is returned as soon as it's found, the rest of the snapshots wait for the next poll.
+    fn poll(&mut self) -> Option<u8> {
+        let now = get_timestamp_us();
+        while let Some(snapshot) = self.next_snapshot() {
+            let key = first_pressed(snapshot);
+            if key == self.pressed {
+                continue;
+            }
+            self.pressed = key;
+            self.next_repeat = now + REPEAT_DELAY_US;
+            if let Some((row, col)) = key {
+                log_trace!("PIO keypad key at row {}, column {}", row, col);
+                return Some(KEYMAP[row][col]);
+            }
+        }
+
+        let (row, col) = self.pressed?;
+        if now < self.next_repeat {
+            return None;
+        }
+        self.next_repeat = now + REPEAT_INTERVAL_US;
+        Some(KEYMAP[row][col])
+    }
+}
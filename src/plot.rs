@@ -0,0 +1,198 @@
+use heapless::Vec;
+use embedded_graphics::{
+    prelude::*,
+    primitives::{
+        Line,
+        PrimitiveStyle,
+    },
+};
+
+use crate::decfix::DecimalFixed;
+use crate::display::{FlushableDisplay, Palette};
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Most columns a plot can have, the width of both of our displays
+pub const MAX_WIDTH: usize = 128;
+/// How much of the range a single step of panning shifts it by, a quarter of it
+const PAN_DIVISOR: i128 = 4;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// A function sampled once per column of the display, with the y axis scaled to fit all of its values, see the `plot` command.
+///
+/// The values are kept as the raw mantissas at the default exponent, so that scaling them onto the rows is plain integer arithmetics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plot {
+    x_min: DecimalFixed,
+    x_max: DecimalFixed,
+    /// `None` where the function failed, e.g. by overflowing, the curve has a gap there
+    samples: Vec<Option<i64>, MAX_WIDTH>,
+}
+
+impl Plot {
+    /// Samples the function at `width` evenly spaced points from `x_min` to `x_max` (both included).
+    /// Returns `BadInput` unless `x_min` is less than `x_max` and the width is from 2 to `MAX_WIDTH`.
+    pub fn sample<F>(x_min: DecimalFixed, x_max: DecimalFixed, width: usize, mut f: F) -> Result<Self, CustomError>
+    where
+        F: FnMut(DecimalFixed) -> Result<DecimalFixed, CustomError>,
+    {
+        let (x_min, x_max) = (x_min.with_exponent(None)?, x_max.with_exponent(None)?);
+        if x_min >= x_max || !(2..=MAX_WIDTH).contains(&width) {
+            return Err(CE::BadInput);
+        }
+        let (start, span) = (i128::from(x_min.value()), i128::from(x_max.value()) - i128::from(x_min.value()));
+        let last = width as i128 - 1;
+
+        let mut samples = Vec::new();
+        for i in 0..width as i128 {
+            // Between the two, so it always fits
+            let x = DecimalFixed::new_prescaled((start + span * i / last) as i64, x_min.exponent());
+            let y = f(x).and_then(|y| y.with_exponent(None)).ok().map(|y| y.value());
+            samples.push(y).map_err(|_| CE::Impossible)?; // We checked the width above
+        }
+        Ok(Plot { x_min, x_max, samples })
+    }
+
+    pub fn x_range(&self) -> (DecimalFixed, DecimalFixed) {
+        (self.x_min, self.x_max)
+    }
+
+    /// The least and the greatest value of the function, or `None` if it failed everywhere
+    pub fn y_range(&self) -> Option<(DecimalFixed, DecimalFixed)> {
+        let (min, max) = self.raw_y_range()?;
+        let exponent = self.x_min.exponent();
+        Some((DecimalFixed::new_prescaled(min, exponent), DecimalFixed::new_prescaled(max, exponent)))
+    }
+
+    fn raw_y_range(&self) -> Option<(i64, i64)> {
+        let values = self.samples.iter().flatten().copied();
+        Some((values.clone().min()?, values.max()?))
+    }
+
+    /// Returns the range shifted by a quarter of it for every step, to the right for positive ones, for sampling it again.
+    /// Returns `MathOverflow` if it'd get out of what `DecimalFixed` can hold.
+    pub fn panned(&self, steps: i64) -> Result<(DecimalFixed, DecimalFixed), CustomError> {
+        let (min, max) = (i128::from(self.x_min.value()), i128::from(self.x_max.value()));
+        let shift = (max - min) / PAN_DIVISOR * i128::from(steps);
+        let exponent = self.x_min.exponent();
+        Ok((
+            DecimalFixed::new_prescaled(i64::try_from(min + shift).map_err(|_| CE::MathOverflow)?, exponent),
+            DecimalFixed::new_prescaled(i64::try_from(max + shift).map_err(|_| CE::MathOverflow)?, exponent),
+        ))
+    }
+
+    /// The row the value is drawn on, the greatest value on the top one. A constant function goes through the middle.
+    fn row(&self, y: i64, height: u32) -> i32 {
+        let Some((min, max)) = self.raw_y_range() else {
+            return 0;
+        };
+        if min == max {
+            return (height as i32 - 1) / 2;
+        }
+        ((i128::from(max) - i128::from(y)) * (i128::from(height) - 1) / (i128::from(max) - i128::from(min))) as i32
+    }
+
+    /// The column of x = 0, if it's in the range
+    fn zero_column(&self) -> Option<i32> {
+        let (min, max) = (i128::from(self.x_min.value()), i128::from(self.x_max.value()));
+        let last = self.samples.len() as i128 - 1;
+        (min..=max).contains(&0).then(|| (-min * last / (max - min)) as i32)
+    }
+
+    /// The row of y = 0, if it's in the range of the values
+    fn zero_row(&self, height: u32) -> Option<i32> {
+        let (min, max) = self.raw_y_range()?;
+        (min..=max).contains(&0).then(|| self.row(0, height))
+    }
+
+    /// Clears the display and draws the axes that are in the ranges, and the curve over them, the neighbouring samples joined by lines.
+    pub fn draw<D>(&self, disp: &mut D) -> Result<(), CustomError>
+    where
+        D: FlushableDisplay,
+        CustomError: From<D::Error>,
+    {
+        disp.clear(D::Color::BACKGROUND)?;
+        let area = disp.bounding_box();
+        let height = area.size.height;
+        let (left, top) = (area.top_left.x, area.top_left.y);
+        let (right, bottom) = (left + self.samples.len() as i32 - 1, top + height as i32 - 1);
+
+        let axis_style = PrimitiveStyle::with_stroke(D::Color::DIMMED, 1);
+        if let Some(column) = self.zero_column() {
+            Line::new(Point::new(left + column, top), Point::new(left + column, bottom)).into_styled(axis_style).draw(disp)?;
+        }
+        if let Some(row) = self.zero_row(height) {
+            Line::new(Point::new(left, top + row), Point::new(right, top + row)).into_styled(axis_style).draw(disp)?;
+        }
+
+        let curve_style = PrimitiveStyle::with_stroke(D::Color::FOREGROUND, 1);
+        let mut previous: Option<Point> = None;
+        for (column, sample) in self.samples.iter().enumerate() {
+            let point = sample.map(|y| Point::new(left + column as i32, top + self.row(y, height)));
+            match (previous, point) {
+                (Some(from), Some(to)) => Line::new(from, to).into_styled(curve_style).draw(disp)?,
+                (None, Some(to)) => Pixel(to, D::Color::FOREGROUND).draw(disp)?,
+                _ => {}, // A gap
+            }
+            previous = point;
+        }
+        disp.flush_display()
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::Plot;
+    use crate::decfix::{num, DecimalFixed};
+    use crate::display::NullDisplay;
+    use crate::custom_error::CE;
+
+    #[test]
+    fn samples_and_scales() {
+        // y = x^2 from -2 to 2 over 5 columns, i.e. at every whole x
+        let plot = Plot::sample(num("-2"), num("2"), 5, |x| x * x).unwrap();
+        assert_eq!(plot.y_range(), Some((num("0"), num("4"))));
+        let rows: std::vec::Vec<i32> = plot.samples.iter().map(|y| plot.row(y.unwrap(), 9)).collect();
+        assert_eq!(rows, [0, 6, 8, 6, 0]);
+        assert_eq!(plot.zero_column(), Some(2));
+        assert_eq!(plot.zero_row(9), Some(8));
+        plot.draw(&mut NullDisplay).unwrap();
+
+        // The axes are only drawn when they're in the ranges
+        let plot = Plot::sample(num("1"), num("3"), 3, |x| x + num("1")).unwrap();
+        assert_eq!(plot.zero_column(), None);
+        assert_eq!(plot.zero_row(9), None);
+
+        assert_eq!(Plot::sample(num("1"), num("1"), 5, Ok), Err(CE::BadInput));
+        assert_eq!(Plot::sample(num("1"), num("0"), 5, Ok), Err(CE::BadInput));
+    }
+
+    #[test]
+    fn gaps_and_constants() {
+        let plot = Plot::sample(num("-1"), num("1"), 3, |x| num("1") / x).unwrap();
+        assert_eq!(plot.samples.as_slice(), [Some(-1_000_000_000), None, Some(1_000_000_000)]);
+        let plot = Plot::sample(num("0"), num("1"), 2, |_| Err(CE::MathOverflow)).unwrap();
+        assert_eq!(plot.y_range(), None);
+        plot.draw(&mut NullDisplay).unwrap();
+
+        let plot = Plot::sample(num("0"), num("1"), 2, |_| Ok(num("5"))).unwrap();
+        assert_eq!(plot.row(5_000_000_000, 64), 31);
+    }
+
+    #[test]
+    fn pans_by_quarters() {
+        let plot = Plot::sample(num("-2"), num("2"), 5, Ok).unwrap();
+        assert_eq!(plot.panned(1), Ok((num("-1"), num("3"))));
+        assert_eq!(plot.panned(-2), Ok((num("-4"), num("0"))));
+        let plot = Plot::sample(num("0"), num("9000000000"), 5, Ok).unwrap();
+        assert_eq!(plot.panned(1), Err(CE::MathOverflow));
+    }
+}
@@ -0,0 +1,149 @@
+use crate::decfix::DecimalFixed;
+use crate::wide::{SCALE, to_wide, from_wide, mul_scaled};
+use crate::custom_error::CustomError; // Because we already have the `mod` in `main.rs`
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// `atan(2^-i)` for each iteration `i` of CORDIC, scaled by `SCALE`. After 40 of them, the angle is off by less than 10^-12.
+const ATAN_TABLE: [i128; 40] = [
+    785398163397448310, 463647609000806116, 244978663126864154, 124354994546761435, 62418809995957348,
+    31239833430268276, 15623728620476831, 7812341060101111, 3906230131966972, 1953122516478819,
+    976562189559319, 488281211194898, 244140620149362, 122070311893670, 61035156174209,
+    30517578115526, 15258789061316, 7629394531102, 3814697265606, 1907348632810,
+    953674316406, 476837158203, 238418579102, 119209289551, 59604644775,
+    29802322388, 14901161194, 7450580597, 3725290298, 1862645149,
+    931322575, 465661287, 232830644, 116415322, 58207661,
+    29103830, 14551915, 7275958, 3637979, 1818989,
+];
+/// Every CORDIC rotation lengthens the vector a bit, by `1 / CORDIC_GAIN` in total over all of `ATAN_TABLE`
+const CORDIC_GAIN: i128 = 607_252_935_008_881_256;
+const PI: i128 = 3_141_592_653_589_793_238;
+const DEGREES_PER_RADIAN: i128 = 57_295_779_513_082_320_877;
+const RADIANS_PER_DEGREE: i128 = 17_453_292_519_943_296;
+const HALF_TURN_DEGREES: i128 = 180 * SCALE;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// The unit the angles of `to_polar()` and `to_rect()` are in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, defmt::Format)]
+pub enum AngleUnit {
+    /// The usual unit of the phase of an impedance
+    #[default] Degrees,
+    Radians,
+}
+
+/// Converts rectangular coordinates (e.g. the resistance and the reactance of an impedance) into the magnitude and the angle,
+/// the angle being from -180° (exclusive) to 180°, zero for the zero vector.
+pub fn to_polar(x: DecimalFixed, y: DecimalFixed, unit: AngleUnit) -> Result<(DecimalFixed, DecimalFixed), CustomError> {
+    let magnitude = x.hypot(y)?;
+    let angle = atan2(to_wide(y)?, to_wide(x)?);
+    let angle = match unit {
+        AngleUnit::Radians => angle,
+        AngleUnit::Degrees => mul_scaled(angle, DEGREES_PER_RADIAN),
+    };
+    Ok(( magnitude, from_wide(angle)? ))
+}
+
+/// Converts the magnitude and the angle into rectangular coordinates. The angle can be any, it gets reduced to a single turn.
+pub fn to_rect(magnitude: DecimalFixed, angle: DecimalFixed, unit: AngleUnit) -> Result<(DecimalFixed, DecimalFixed), CustomError> {
+    let angle = to_wide(angle)?;
+    let angle = match unit {
+        AngleUnit::Radians => reduce(angle, PI),
+        // Reduced before the conversion, so that e.g. 720° stays exactly zero
+        AngleUnit::Degrees => mul_scaled(reduce(angle, HALF_TURN_DEGREES), RADIANS_PER_DEGREE),
+    };
+    let (x, y) = rotate(to_wide(magnitude)?, angle);
+    Ok(( from_wide(x)?, from_wide(y)? ))
+}
+
+/// Reduces the angle to a single turn, i.e. from `-half_turn` (exclusive) to `half_turn`.
+fn reduce(angle: i128, half_turn: i128) -> i128 {
+    let angle = angle % (2 * half_turn);
+    if angle > half_turn {
+        angle - 2 * half_turn
+    } else if angle <= -half_turn {
+        angle + 2 * half_turn
+    } else {
+        angle
+    }
+}
+
+/// Returns the angle of the vector in radians by CORDIC in the vectoring mode, i.e. turning it onto the x axis step by step.
+fn atan2(y: i128, x: i128) -> i128 {
+    if x == 0 && y == 0 {
+        return 0; // It has no direction, but zero is what everyone else returns too
+    }
+    // CORDIC only converges up to about ±99°, so a vector in the left half gets turned around by a half turn first
+    let (mut x, mut y, mut angle) = match (x < 0, y < 0) {
+        (false, _) => (x, y, 0),
+        (true, false) => (-x, -y, PI),
+        (true, true) => (-x, -y, -PI),
+    };
+    for (i, &step) in ATAN_TABLE.iter().enumerate() {
+        let (dx, dy) = (y >> i, x >> i);
+        if y > 0 {
+            (x, y, angle) = (x + dx, y - dy, angle + step);
+        } else {
+            (x, y, angle) = (x - dx, y + dy, angle - step);
+        }
+    }
+    angle
+}
+
+/// Returns the coordinates of the vector of the magnitude, turned by the angle in radians (from -π to π)
+/// from the x axis, by CORDIC in the rotation mode.
+fn rotate(magnitude: i128, angle: i128) -> (i128, i128) {
+    // CORDIC only converges up to about ±99°, so the angles past a right one get turned around by a half turn first
+    let (magnitude, angle) = if angle > PI / 2 {
+        (-magnitude, angle - PI)
+    } else if angle < -PI / 2 {
+        (-magnitude, angle + PI)
+    } else {
+        (magnitude, angle)
+    };
+    let (mut x, mut y, mut angle) = (magnitude, 0, angle);
+    for (i, &step) in ATAN_TABLE.iter().enumerate() {
+        let (dx, dy) = (y >> i, x >> i);
+        if angle >= 0 {
+            (x, y, angle) = (x - dx, y + dy, angle - step);
+        } else {
+            (x, y, angle) = (x + dx, y - dy, angle + step);
+        }
+    }
+    (mul_scaled(x, CORDIC_GAIN), mul_scaled(y, CORDIC_GAIN))
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::{to_polar, to_rect, AngleUnit};
+    use crate::decfix::{num, DecimalFixed};
+
+    #[test]
+    fn rectangular_to_polar() {
+        assert_eq!(to_polar(num("3"), num("4"), AngleUnit::Degrees), Ok((num("5"), num("53.130102354"))));
+        assert_eq!(to_polar(num("1"), num("1"), AngleUnit::Radians), Ok((num("1.414213562"), num("0.785398163"))));
+        // All four quadrants, and the axes
+        assert_eq!(to_polar(num("-1"), num("1"), AngleUnit::Degrees).unwrap().1, num("135"));
+        assert_eq!(to_polar(num("-1"), num("-1"), AngleUnit::Degrees).unwrap().1, num("-135"));
+        assert_eq!(to_polar(num("1"), num("-1"), AngleUnit::Degrees).unwrap().1, num("-45"));
+        assert_eq!(to_polar(num("-2"), num("0"), AngleUnit::Degrees).unwrap().1, num("180"));
+        assert_eq!(to_polar(num("0"), num("-2"), AngleUnit::Degrees).unwrap().1, num("-90"));
+        assert_eq!(to_polar(num("0"), num("0"), AngleUnit::Degrees), Ok((num("0"), num("0"))));
+    }
+
+    #[test]
+    fn polar_to_rectangular() {
+        assert_eq!(to_rect(num("2"), num("30"), AngleUnit::Degrees), Ok((num("1.732050808"), num("1"))));
+        assert_eq!(to_rect(num("2"), num("150"), AngleUnit::Degrees), Ok((num("-1.732050808"), num("1"))));
+        assert_eq!(to_rect(num("1"), num("-720"), AngleUnit::Degrees), Ok((num("1"), num("0"))));
+        // Just past π, so the y is just below zero
+        assert_eq!(to_rect(num("10"), num("3.141592654"), AngleUnit::Radians), Ok((num("-10"), num("-0.000000004"))));
+
+        // And back
+        let (x, y) = to_rect(num("230"), num("-36.87"), AngleUnit::Degrees).unwrap();
+        assert_eq!(to_polar(x, y, AngleUnit::Degrees), Ok((num("230"), num("-36.87"))));
+    }
+}
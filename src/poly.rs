@@ -0,0 +1,185 @@
+use core::fmt::{self, Display};
+use heapless::Vec;
+
+use crate::decfix::DecimalFixed;
+use crate::wide::{self, to_wide, from_wide, checked_mul_scaled, checked_div_scaled};
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Highest degree of a polynomial the `poly` command can store
+pub const MAX_DEGREE: usize = 8;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// A polynomial stored by the `poly` command, to be evaluated or solved later.
+///
+/// Like the stack, this is intentionally not generic, only for DecimalFixed.
+/// The coefficients are kept at the default exponent, since multiplication and division need both operands to have the same one.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Polynomial {
+    /// From the highest degree down to the constant, the same order they're entered onto the stack. Empty if none is stored.
+    coefficients: Vec<DecimalFixed, { MAX_DEGREE + 1 }>,
+}
+
+/// Writes it the way it'd be written by hand, e.g. `2x^2 - x + 0.5`, skipping the zero terms.
+impl Display for Polynomial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut written = false;
+        for (i, &c) in self.coefficients.iter().enumerate() {
+            if c.is_zero() {
+                continue;
+            }
+            let degree = self.degree() - i;
+            match (written, c.is_negative()) {
+                (false, false) => {},
+                (false, true) => f.write_str("-")?,
+                (true, false) => f.write_str(" + ")?,
+                (true, true) => f.write_str(" - ")?,
+            }
+            let magnitude = if c.is_negative() { (-c).map_err(|_| fmt::Error)? } else { c };
+            let one = DecimalFixed::new(1, None).map_err(|_| fmt::Error)?;
+            if magnitude != one || degree == 0 {
+                write!(f, "{}", magnitude)?;
+            }
+            match degree {
+                0 => {},
+                1 => f.write_str("x")?,
+                _ => write!(f, "x^{}", degree)?,
+            }
+            written = true;
+        }
+        if !written {
+            f.write_str("0")?;
+        }
+        Ok(())
+    }
+}
+
+impl Polynomial {
+    pub const fn new() -> Self {
+        Polynomial { coefficients: Vec::new() }
+    }
+
+    /// Replaces the polynomial with one of these coefficients, from the highest degree down to the constant.
+    /// Returns `BadInput` if there's none, or more than `MAX_DEGREE + 1` of them.
+    pub fn set(&mut self, coefficients: &[DecimalFixed]) -> Result<(), CustomError> {
+        if coefficients.is_empty() || coefficients.len() > MAX_DEGREE + 1 {
+            return Err(CE::BadInput);
+        }
+        let mut new = Vec::new();
+        for &c in coefficients {
+            new.push(c.with_exponent(None)?).map_err(|_| CE::Impossible)?; // We checked the length above
+        }
+        self.coefficients = new;
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.coefficients.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.coefficients.is_empty()
+    }
+
+    /// The degree it was stored with, i.e. the number of coefficients minus one, even if the leading ones are zero
+    pub fn degree(&self) -> usize {
+        self.coefficients.len().saturating_sub(1)
+    }
+
+    /// Returns the value at x, computed by Horner's method. Returns `BadInput` if there's no polynomial stored.
+    pub fn evaluate(&self, x: DecimalFixed) -> Result<DecimalFixed, CustomError> {
+        let (&first, rest) = self.coefficients.split_first().ok_or(CE::BadInput)?;
+        let x = x.with_exponent(None)?;
+        rest.iter().try_fold(first, |acc, &c| (acc * x)? + c)
+    }
+
+    /// Returns both real roots of a quadratic `ax^2 + bx + c`, the smaller one first (a double root is returned twice).
+    /// Returns `BadInput` unless it's stored as a quadratic with `a` not zero, and `ComplexResult` if the roots aren't real.
+    pub fn quadratic_roots(&self) -> Result<(DecimalFixed, DecimalFixed), CustomError> {
+        let &[a, b, c] = self.coefficients.as_slice() else {
+            return Err(CE::BadInput);
+        };
+        if a.is_zero() {
+            return Err(CE::BadInput);
+        }
+        // In the fixed point of `wide.rs`, so that the squares don't overflow while the roots fit
+        let (a, b, c) = (to_wide(a)?, to_wide(b)?, to_wide(c)?);
+        let discriminant = checked_mul_scaled(b, b)?
+            .checked_sub(checked_mul_scaled(4 * a, c)?) // Can't overflow, `a` came from an i64
+            .ok_or(CE::MathOverflow)?;
+        if discriminant < 0 {
+            return Err(CE::ComplexResult);
+        }
+
+        // q = -(b + sign(b) * sqrt(D)) / 2, then the roots are q / a and c / q.
+        // Unlike the schoolbook formula, this never subtracts two nearly equal numbers, so no digits get lost to it.
+        let root = to_wide(wide::sqrt(discriminant)?)?;
+        let q = -(if b < 0 { b - root } else { b + root }) / 2; // Can't overflow either, both are far below i128::MAX / 2
+        let (x1, x2) = if q == 0 {
+            (0, 0) // Then both b and c are zero
+        } else {
+            (checked_div_scaled(q, a)?, checked_div_scaled(c, q)?)
+        };
+        Ok((from_wide(x1.min(x2))?, from_wide(x1.max(x2))?))
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::string::ToString;
+    use super::{Polynomial, MAX_DEGREE};
+    use crate::decfix::{num, DecimalFixed};
+use crate::wide::{self, to_wide, from_wide, checked_mul_scaled, checked_div_scaled};
+    use crate::custom_error::CE;
+
+    fn poly(coefficients: &[&str]) -> Polynomial {
+        let coefficients: std::vec::Vec<DecimalFixed> = coefficients.iter().map(|s| num(s)).collect();
+        let mut p = Polynomial::new();
+        p.set(&coefficients).unwrap();
+        p
+    }
+
+    #[test]
+    fn evaluates_by_horner() {
+        let p = poly(&["2", "0", "-3", "1"]); // 2x^3 - 3x + 1
+        assert_eq!(p.evaluate(num("2")), Ok(num("11")));
+        assert_eq!(p.evaluate(num("-0.5")), Ok(num("2.25")));
+        assert_eq!(poly(&["7"]).evaluate(num("100")), Ok(num("7")));
+        assert_eq!(Polynomial::new().evaluate(num("1")), Err(CE::BadInput));
+
+        let mut p = Polynomial::new();
+        assert_eq!(p.set(&[num("1"); MAX_DEGREE + 2]), Err(CE::BadInput));
+    }
+
+    #[test]
+    fn solves_quadratics() {
+        assert_eq!(poly(&["1", "-3", "2"]).quadratic_roots(), Ok((num("1"), num("2"))));
+        assert_eq!(poly(&["2", "0", "-8"]).quadratic_roots(), Ok((num("-2"), num("2"))));
+        assert_eq!(poly(&["1", "2", "1"]).quadratic_roots(), Ok((num("-1"), num("-1"))));
+        assert_eq!(poly(&["3", "0", "0"]).quadratic_roots(), Ok((num("0"), num("0"))));
+        // The small root of x^2 - 10000x + 1 would lose most of its digits with the schoolbook formula
+        assert_eq!(poly(&["1", "-10000", "1"]).quadratic_roots().unwrap().0, num("0.000100000"));
+        // Squaring b or multiplying a by c would overflow, but the roots are small
+        assert_eq!(poly(&["100000", "-300000", "200000"]).quadratic_roots(), Ok((num("1"), num("2"))));
+        assert_eq!(poly(&["1", "-100000", "1"]).quadratic_roots(), Ok((num("0.00001"), num("99999.99999"))));
+
+        assert_eq!(poly(&["1", "0", "1"]).quadratic_roots(), Err(CE::ComplexResult));
+        assert_eq!(poly(&["0", "1", "1"]).quadratic_roots(), Err(CE::BadInput));
+        assert_eq!(poly(&["1", "1"]).quadratic_roots(), Err(CE::BadInput));
+    }
+
+    #[test]
+    fn displays_like_handwritten() {
+        assert_eq!(poly(&["2", "0", "-1", "0.5"]).to_string(), "2x^3 - x + 0.5");
+        assert_eq!(poly(&["-1", "1"]).to_string(), "-x + 1");
+        assert_eq!(poly(&["0", "0"]).to_string(), "0");
+    }
+}
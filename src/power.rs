@@ -0,0 +1,250 @@
+use rp2040_hal::{self as hal, pac};
+use hal::clocks::{ClockGate, ClocksManager};
+use cortex_m::peripheral::SCB;
+
+use crate::display::Panel;
+use crate::get_timestamp_us;
+use crate::custom_error::CustomError; // Because we already have the `mod` in `main.rs`
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// The UART's RX pin, a start bit (falling edge) on it wakes us up
+const UART_RX_PIN: usize = 1;
+/// A push button to ground (with the internal pull-up) that wakes us up without sending anything
+const WAKE_PIN: usize = 22;
+/// How long before going to sleep the display gets dimmed, as a warning, in microseconds
+const DIM_LEAD_US: u64 = 10_000_000;
+/// Contrast of the dimmed display
+const DIM_CONTRAST: u8 = 0x00;
+/// Offset of the edge-low event in a pin's nibble of the GPIO interrupt registers
+const EDGE_LOW_EVENT: usize = 2;
+/// The pins whose falling edge wakes us up, the boot button's too, so that holding it gets noticed
+#[cfg(not(feature = "boot-button"))]
+const EVENT_PINS: [usize; 2] = [UART_RX_PIN, WAKE_PIN];
+#[cfg(feature = "boot-button")]
+const EVENT_PINS: [usize; 3] = [UART_RX_PIN, WAKE_PIN, crate::boot_button::BOOT_PIN];
+/// SEVONPEND of the System Control Register: a newly pending interrupt wakes WFE up, even if it's disabled in the NVIC
+const SCR_SEVONPEND: u32 = 1 << 4;
+/// The timer's alarm that ends `Sleeper::idle_until()`, nothing else uses the alarms
+const IDLE_ALARM: u32 = 1 << 0;
+/// The interrupts that wake `Sleeper::idle_until()` up, besides the USB's one, which is always enabled
+#[cfg(not(feature = "i2c-peripheral"))]
+const IDLE_INTERRUPTS: [pac::Interrupt; 2] = [pac::Interrupt::TIMER_IRQ_0, pac::Interrupt::IO_IRQ_BANK0];
+#[cfg(feature = "i2c-peripheral")]
+const IDLE_INTERRUPTS: [pac::Interrupt; 3] = [pac::Interrupt::TIMER_IRQ_0, pac::Interrupt::IO_IRQ_BANK0, pac::Interrupt::I2C1_IRQ];
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// How far the power manager got since the last input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, defmt::Format)]
+enum PowerState {
+    #[default] Awake,
+    Dimmed,
+    /// The display is off and the core sleeps between the polls
+    Asleep,
+}
+
+/// Dims the display and then turns it off and puts the core to sleep when there's no input, see the `sleep` command.
+///
+/// Only the SLEEP state is used: the clocks keep running, but those of the unneeded peripherals are gated (see `Sleeper::new()`),
+/// and the core waits for an event. DORMANT would stop the crystal oscillator too, and so the UART, losing the byte that wakes us up,
+/// and the PLLs would have to be brought up again afterwards.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PowerManager {
+    /// Timestamp of the last input, in microseconds
+    last_activity: u64,
+    state: PowerState,
+}
+
+impl PowerManager {
+    pub const fn new() -> Self {
+        PowerManager {
+            last_activity: 0,
+            state: PowerState::Awake,
+        }
+    }
+
+    /// Whether the display is as bright as it should be
+    pub fn is_awake(&self) -> bool {
+        self.state == PowerState::Awake
+    }
+
+    /// Records input at `now`, restarting the timeout. If the display was dimmed or off, it gets its contrast back and turned on,
+    /// and it returns true, in which case the caller has to redraw it.
+    pub fn wake<D>(&mut self, disp: &mut D, contrast: u8, now: u64) -> Result<bool, CustomError>
+    where
+        D: Panel,
+    {
+        self.last_activity = now;
+        let previous = core::mem::replace(&mut self.state, PowerState::Awake);
+        match previous {
+            PowerState::Awake => return Ok(false),
+            PowerState::Dimmed => {},
+            PowerState::Asleep => disp.set_on(true)?,
+        }
+        log_debug!("Waking up from {:?}", previous);
+        disp.set_contrast(contrast)?;
+        Ok(true)
+    }
+
+    /// Dims the display once `timeout_secs` without input are nearly over, and then turns it off and sleeps until an event.
+    /// Zero seconds means it's disabled. Meant to be called repeatedly while polling for input.
+    ///
+    /// Returns true if the wake button woke us up, in which case it has already called `wake()`.
+    pub fn tick<D>(&mut self, disp: &mut D, sleeper: &mut Sleeper, timeout_secs: u16, contrast: u8, now: u64) -> Result<bool, CustomError>
+    where
+        D: Panel,
+    {
+        if timeout_secs == 0 {
+            return Ok(false);
+        }
+
+        let idle = now.saturating_sub(self.last_activity);
+        let timeout = u64::from(timeout_secs) * 1_000_000;
+        match self.state {
+            PowerState::Awake if idle >= timeout.saturating_sub(DIM_LEAD_US) => {
+                log_debug!("Idle for {} us, dimming the display", idle);
+                self.state = PowerState::Dimmed;
+                disp.set_contrast(DIM_CONTRAST)?;
+            },
+            PowerState::Dimmed if idle >= timeout => {
+                log_info!("Idle for {} s, going to sleep", timeout_secs);
+                self.state = PowerState::Asleep;
+                disp.set_on(false)?;
+            },
+            // Whatever else woke us up, we only poll once more; a received byte then wakes us up through `wake()`
+            PowerState::Asleep if sleeper.sleep_until_event() => {
+                self.wake(disp, contrast, now)?;
+                return Ok(true);
+            },
+            _ => {},
+        }
+        Ok(false)
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// The hardware side of sleeping: the clock gating, the core's sleep mode and the GPIO events that wake it up.
+pub struct Sleeper {
+    scb: SCB,
+}
+
+impl Sleeper {
+    /// Chooses the clocks left running while the core sleeps: the memories, the UART (and USB, DMA or PIO if they're used),
+    /// the timer and the GPIO interrupts, which is all that's needed to receive a key and notice it.
+    /// The wake button has to be an input with a pull-up already.
+    pub fn new(scb: SCB, clocks: &mut ClocksManager) -> Self {
+        let mut gate = ClockGate::default();
+        gate.set_sys_clock(true);
+        gate.set_sys_busctrl(true);
+        gate.set_sys_busfabric(true);
+        gate.set_sys_io(true);
+        gate.set_sys_pads(true);
+        gate.set_sys_psm(true);
+        gate.set_sys_resets(true);
+        gate.set_sys_rom(true);
+        gate.set_sys_sio(true);
+        gate.set_sys_sram0(true);
+        gate.set_sys_sram1(true);
+        gate.set_sys_sram2(true);
+        gate.set_sys_sram3(true);
+        gate.set_sys_sram4(true);
+        gate.set_sys_sram5(true);
+        gate.set_sys_xip(true);
+        gate.set_sys_timer(true);
+        gate.set_sys_watchdog(true); // Generates the timer's ticks
+        gate.set_sys_rtc(true);
+        gate.set_rtc_rtc(true);
+        gate.set_sys_pll_sys(true);
+        gate.set_sys_pll_usb(true);
+        gate.set_sys_xosc(true);
+        gate.set_sys_vreg_and_chip_reset(true);
+        gate.set_peri_uart0(true);
+        gate.set_sys_uart0(true);
+        #[cfg(any(feature = "dma-rx", feature = "pio-keypad"))]
+        gate.set_sys_dma(true);
+        #[cfg(feature = "pio-keypad")]
+        gate.set_sys_pio0(true);
+        #[cfg(feature = "usb")]
+        {
+            gate.set_sys_usbctrl(true);
+            gate.set_usb_usbctrl(true);
+        }
+        clocks.configure_sleep_enable(gate);
+
+        // SAFETY: Only changes what wakes WFE up, nothing else depends on it
+        unsafe { scb.scr.modify(|scr| scr | SCR_SEVONPEND) };
+        // Only latched in the GPIO interrupt registers and pending in the NVIC, never handled, the interrupt stays disabled
+        let io = unsafe { &*pac::IO_BANK0::PTR };
+        for pin in EVENT_PINS {
+            // SAFETY: Sets only the bit of the pin's edge-low event, the rest of the register stays as it was
+            io.proc0_inte(pin / 8).modify(|r, w| unsafe { w.bits(r.bits() | edge_low_bit(pin)) });
+        }
+        Sleeper { scb }
+    }
+
+    /// Sleeps until a GPIO edge, an interrupt or some other event, returns whether it was the wake button.
+    /// Wakes up spuriously sometimes, e.g. on the USB's interrupts, the caller has to poll again anyway.
+    pub fn sleep_until_event(&mut self) -> bool {
+        let io = unsafe { &*pac::IO_BANK0::PTR };
+        // SAFETY: The edge events are write-1-to-clear, only ours get cleared
+        for pin in EVENT_PINS {
+            io.intr(pin / 8).write(|w| unsafe { w.bits(edge_low_bit(pin)) });
+        }
+        // Otherwise it wouldn't become *newly* pending, which is what SEVONPEND reacts to
+        pac::NVIC::unpend(pac::Interrupt::IO_IRQ_BANK0);
+
+        self.scb.set_sleepdeep();
+        cortex_m::asm::wfe();
+        self.scb.clear_sleepdeep();
+
+        io.intr(WAKE_PIN / 8).read().bits() & edge_low_bit(WAKE_PIN) != 0
+    }
+
+    /// Waits with WFI until `deadline` (see `get_timestamp_us()`), a GPIO edge (a received byte or a button),
+    /// the USB's interrupt or a transaction of the I²C master, whichever comes first. Unlike `sleep_until_event()`,
+    /// the core doesn't sleep deeply, so that the peripherals the main loop polls keep their clocks.
+    ///
+    /// The interrupts are only enabled in the NVIC while interrupts are masked on the core, so their handlers
+    /// (which we don't have, except the USB's) never run; being pending is enough to end WFI.
+    pub fn idle_until(&mut self, deadline: u64) {
+        let io = unsafe { &*pac::IO_BANK0::PTR };
+        let timer = unsafe { &*pac::TIMER::PTR };
+        cortex_m::interrupt::free(|_| {
+            // SAFETY: The edge events are write-1-to-clear, only ours get cleared
+            for pin in EVENT_PINS {
+                io.intr(pin / 8).write(|w| unsafe { w.bits(edge_low_bit(pin)) });
+            }
+            // The alarm compares only the low half of the timestamp, a deadline further than that just ends the wait early.
+            // SAFETY: Any value is a valid alarm time, writing it arms the alarm
+            timer.alarm0().write(|w| unsafe { w.bits(deadline as u32) });
+            timer.inte().modify(|r, w| unsafe { w.bits(r.bits() | IDLE_ALARM) });
+            // Checked only once it's armed, an alarm already in the past would only fire after the timer's low half wraps
+            if get_timestamp_us() < deadline {
+                for interrupt in IDLE_INTERRUPTS {
+                    // SAFETY: Interrupts are masked on the core, nothing gets handled before they're disabled again below
+                    unsafe { pac::NVIC::unmask(interrupt) };
+                }
+                cortex_m::asm::wfi();
+                for interrupt in IDLE_INTERRUPTS {
+                    pac::NVIC::mask(interrupt);
+                }
+            }
+
+            // SAFETY: Both are write-1-to-clear, only the alarm gets disarmed and its interrupt cleared
+            timer.armed().write(|w| unsafe { w.bits(IDLE_ALARM) });
+            timer.inte().modify(|r, w| unsafe { w.bits(r.bits() & !IDLE_ALARM) });
+            timer.intr().write(|w| unsafe { w.bits(IDLE_ALARM) });
+            for interrupt in IDLE_INTERRUPTS {
+                pac::NVIC::unpend(interrupt);
+            }
+        });
+    }
+}
+
+/// The bit of the pin's edge-low event in its GPIO interrupt register, each one has four events of eight pins
+const fn edge_low_bit(pin: usize) -> u32 {
+    1 << ((pin % 8) * 4 + EDGE_LOW_EVENT)
+}
@@ -0,0 +1,120 @@
+use crate::decfix::DecimalFixed;
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Number of registers, one for each letter of the English alphabet (A–Z)
+pub const REGISTER_COUNT: usize = 26;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// A register file of named memory slots `A` to `Z`, each either empty or holding one `DecimalFixed`.
+///
+/// Like the stack, this is intentionally not generic, only for DecimalFixed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterFile {
+    data: [Option<DecimalFixed>; REGISTER_COUNT],
+}
+
+impl Default for RegisterFile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[allow(dead_code)]
+impl RegisterFile {
+    /// Creates a new register file with all registers empty.
+    pub const fn new() -> Self {
+        RegisterFile {
+            data: [None; REGISTER_COUNT],
+        }
+    }
+
+    /// Converts a register name (a single ASCII letter, case-insensitive) into an index into the register file.
+    /// Returns `BadInput` for anything else.
+    pub fn index_of(name: &str) -> Result<usize, CustomError> {
+        let mut chars = name.chars();
+        let (Some(c), None) = (chars.next(), chars.next()) else {
+            return Err(CE::BadInput); // Empty or more than one character
+        };
+
+        if !c.is_ascii_alphabetic() {
+            return Err(CE::BadInput);
+        }
+        // We checked it's ASCII, so the cast can't truncate anything
+        Ok((c.to_ascii_uppercase() as u8 - b'A') as usize)
+    }
+
+    /// Converts an index back into the register's (uppercase) name.
+    /// Panics if the index is out of bounds.
+    pub fn name_of(index: usize) -> char {
+        assert!(index < REGISTER_COUNT, "Register index out of bounds!");
+        (b'A' + index as u8) as char
+    }
+
+    /// Stores a value into the register, overwriting whatever was there.
+    pub fn store(&mut self, name: &str, value: DecimalFixed) -> Result<(), CustomError> {
+        self.data[Self::index_of(name)?] = Some(value);
+        Ok(())
+    }
+
+    /// Adds a value to the register (register arithmetic, like `STO+` on HP calculators).
+    /// An empty register is treated as zero.
+    pub fn store_add(&mut self, name: &str, value: DecimalFixed) -> Result<(), CustomError> {
+        let slot = &mut self.data[Self::index_of(name)?];
+
+        // On error we leave the register untouched
+        *slot = Some(match slot {
+            Some(old) => (*old + value)?,
+            None => value,
+        });
+        Ok(())
+    }
+
+    /// Subtracts a value from the register (like `STO-` on HP calculators).
+    /// An empty register is treated as zero.
+    pub fn store_sub(&mut self, name: &str, value: DecimalFixed) -> Result<(), CustomError> {
+        let slot = &mut self.data[Self::index_of(name)?];
+
+        // On error we leave the register untouched
+        *slot = Some(match slot {
+            Some(old) => (*old - value)?,
+            None => (-value)?,
+        });
+        Ok(())
+    }
+
+    /// Returns the value of the register.
+    /// Returns `BadInput` if the register is empty or the name is invalid.
+    pub fn recall(&self, name: &str) -> Result<DecimalFixed, CustomError> {
+        self.data[Self::index_of(name)?].ok_or(CE::BadInput)
+    }
+
+    /// Empties the register.
+    pub fn clear_register(&mut self, name: &str) -> Result<(), CustomError> {
+        self.data[Self::index_of(name)?] = None;
+        Ok(())
+    }
+
+    /// Empties all registers.
+    pub fn clear(&mut self) {
+        self.data = [None; REGISTER_COUNT];
+    }
+
+    /// Iterates over the non-empty registers as (name, value) pairs, in alphabetical order.
+    pub fn iter(&self) -> impl Iterator<Item = (char, DecimalFixed)> + '_ {
+        self.data.iter()
+            .enumerate()
+            .filter_map(|(i, value)| value.map(|v| (Self::name_of(i), v)))
+    }
+
+    /// Number of non-empty registers
+    pub fn count(&self) -> usize {
+        self.data.iter().filter(|value| value.is_some()).count()
+    }
+}
@@ -0,0 +1,359 @@
+use heapless::{Deque, String, Vec};
+
+use crate::custom_error::CE; // Because we already have the `mod` in `main.rs`
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Errors kept for `SYSTem:ERRor?`, as the standard asks for at least two; a newer one past that replaces the newest with `QueueOverflow`
+const ERROR_QUEUE_SIZE: usize = 8;
+/// Most parameters a command takes, more are `ParameterNotAllowed`
+const MAX_PARAMS: usize = 2;
+/// Longest header path kept for the commands after a `;` to be relative to, e.g. `STACK`
+const PREFIX_SIZE: usize = 32;
+
+/// The commands by their long form, the uppercase part being the short form (e.g. `STAC` or `STACK` for `STACk`).
+/// A query is the same command with a `?` after it, whether it's got one is up to `commands::Scpi`.
+const HEADERS: [(&str, Header); 17] = [
+    ("*IDN", Header::Identify),
+    ("*RST", Header::Reset),
+    ("*CLS", Header::ClearStatus),
+    ("*OPC", Header::OperationComplete),
+    ("STACk:PUSH", Header::Push),
+    ("STACk:POP", Header::Pop),
+    ("STACk:TOP", Header::Top),
+    ("STACk:PEEK", Header::Peek),
+    ("STACk:DEPTh", Header::Depth),
+    ("STACk:CLEar", Header::Clear),
+    ("CALCulate:RPN", Header::Rpn),
+    ("DISPlay:BRIGht", Header::Brightness),
+    ("DISPlay:CONTrast", Header::Contrast),
+    ("SYSTem:ERRor", Header::Error),
+    ("SYSTem:VERSion", Header::Version),
+    ("SYSTem:COMMand", Header::Command),
+    ("SYSTem:LOCal", Header::Local),
+];
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// A command of the SCPI-like remote mode, see `HEADERS` for how they're spelled and `commands::Scpi` for what they do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Header {
+    Identify,
+    Reset,
+    ClearStatus,
+    OperationComplete,
+    Push,
+    Pop,
+    Top,
+    Peek,
+    Depth,
+    Clear,
+    Rpn,
+    Brightness,
+    Contrast,
+    Error,
+    Version,
+    Command,
+    Local,
+}
+
+/// The errors `SYSTem:ERRor?` reports, the standard ones of SCPI-99 we can run into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ScpiError {
+    SyntaxError,
+    ParameterNotAllowed,
+    MissingParameter,
+    UndefinedHeader,
+    ExecutionError,
+    DataOutOfRange,
+    IllegalParameterValue,
+    OutOfMemory,
+    QueueOverflow,
+}
+
+impl ScpiError {
+    pub fn code(self) -> i16 {
+        match self {
+            ScpiError::SyntaxError => -102,
+            ScpiError::ParameterNotAllowed => -108,
+            ScpiError::MissingParameter => -109,
+            ScpiError::UndefinedHeader => -113,
+            ScpiError::ExecutionError => -200,
+            ScpiError::DataOutOfRange => -222,
+            ScpiError::IllegalParameterValue => -224,
+            ScpiError::OutOfMemory => -225,
+            ScpiError::QueueOverflow => -350,
+        }
+    }
+
+    pub fn message(self) -> &'static str {
+        match self {
+            ScpiError::SyntaxError => "Syntax error",
+            ScpiError::ParameterNotAllowed => "Parameter not allowed",
+            ScpiError::MissingParameter => "Missing parameter",
+            ScpiError::UndefinedHeader => "Undefined header",
+            ScpiError::ExecutionError => "Execution error",
+            ScpiError::DataOutOfRange => "Data out of range",
+            ScpiError::IllegalParameterValue => "Illegal parameter value",
+            ScpiError::OutOfMemory => "Out of memory",
+            ScpiError::QueueOverflow => "Queue overflow",
+        }
+    }
+}
+
+impl From<CE> for ScpiError {
+    fn from(e: CE) -> Self {
+        match e {
+            CE::BadInput | CE::ParseIntError(_) => ScpiError::IllegalParameterValue,
+            CE::MathOverflow => ScpiError::DataOutOfRange,
+            CE::CapacityError => ScpiError::OutOfMemory,
+            _ => ScpiError::ExecutionError,
+        }
+    }
+}
+
+/// The errors not yet read by `SYSTem:ERRor?`, oldest first
+#[derive(Debug, Clone, Default)]
+pub struct ErrorQueue {
+    errors: Deque<ScpiError, ERROR_QUEUE_SIZE>,
+}
+
+impl ErrorQueue {
+    pub const fn new() -> Self {
+        ErrorQueue { errors: Deque::new() }
+    }
+
+    /// Queues the error. If the queue is full, the newest error gets replaced by `QueueOverflow`, as the standard says.
+    pub fn push(&mut self, error: ScpiError) {
+        if self.errors.push_back(error).is_err() {
+            self.errors.pop_back();
+            let _ = self.errors.push_back(ScpiError::QueueOverflow); // Can't fail, we just made room
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<ScpiError> {
+        self.errors.pop_front()
+    }
+
+    pub fn clear(&mut self) {
+        self.errors.clear();
+    }
+}
+
+/// A single command of a line, with its parameters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScpiCommand<'l> {
+    pub header: Header,
+    /// Whether it ended with a `?`
+    pub query: bool,
+    /// Separated by commas, a string's quotes taken off
+    pub params: Vec<&'l str, MAX_PARAMS>,
+}
+
+impl<'l> ScpiCommand<'l> {
+    /// Checks that there are no parameters
+    pub fn no_params(&self) -> Result<(), ScpiError> {
+        match self.params.is_empty() {
+            true => Ok(()),
+            false => Err(ScpiError::ParameterNotAllowed),
+        }
+    }
+
+    /// The only parameter, or `None` if there's none
+    pub fn optional_param(&self) -> Result<Option<&'l str>, ScpiError> {
+        match self.params.as_slice() {
+            [] => Ok(None),
+            [param] => Ok(Some(param)),
+            _ => Err(ScpiError::ParameterNotAllowed),
+        }
+    }
+
+    /// The only parameter, which there has to be
+    pub fn param(&self) -> Result<&'l str, ScpiError> {
+        self.optional_param()?.ok_or(ScpiError::MissingParameter)
+    }
+}
+
+/// Splits at the first `separator` outside of quotes (single or double, as SCPI has both), returning what's before and after it
+fn split_unquoted(text: &str, separator: char) -> (&str, Option<&str>) {
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, c) if c == separator => return (&text[..i], Some(&text[(i + c.len_utf8())..])),
+            _ => {},
+        }
+    }
+    (text, None)
+}
+
+/// Whether the mnemonic typed is either the short or the long form of the one in `HEADERS`, in any case
+fn mnemonic_matches(typed: &str, pattern: &str) -> bool {
+    let short_len = pattern.find(|c: char| c.is_ascii_lowercase()).unwrap_or(pattern.len());
+    typed.eq_ignore_ascii_case(pattern) || typed.eq_ignore_ascii_case(&pattern[..short_len])
+}
+
+/// Finds the command of the path, e.g. `STAC:PUSH` (without the leading colon or the question mark)
+fn lookup(path: &str) -> Option<Header> {
+    HEADERS.iter()
+        .find(|(pattern, _)| {
+            let (mut typed, mut pattern) = (path.split(':'), pattern.split(':'));
+            loop {
+                match (typed.next(), pattern.next()) {
+                    (None, None) => return true,
+                    (Some(typed), Some(pattern)) if mnemonic_matches(typed, pattern) => {},
+                    _ => return false,
+                }
+            }
+        })
+        .map(|&(_, header)| header)
+}
+
+/// The commands of a line, separated by semicolons, e.g. `:STACK:PUSH 2;PUSH 3;:STACK:DEPTH?`.
+/// As in SCPI, a command without the leading colon is relative to the previous one's path, so `PUSH 3` is `:STACK:PUSH 3` there;
+/// the common commands (`*IDN?` and such) don't change the path.
+pub struct Program<'l> {
+    rest: Option<&'l str>,
+    /// Of the previous command, without its last mnemonic
+    prefix: String<PREFIX_SIZE>,
+}
+
+pub fn parse(line: &str) -> Program<'_> {
+    Program { rest: Some(line), prefix: String::new() }
+}
+
+impl<'l> Program<'l> {
+    fn command(&mut self, unit: &'l str) -> Result<ScpiCommand<'l>, ScpiError> {
+        let (header, params) = unit.split_once(char::is_whitespace).unwrap_or((unit, ""));
+        let (header, query) = match header.strip_suffix('?') {
+            Some(header) => (header, true),
+            None => (header, false),
+        };
+
+        let mut path: String<PREFIX_SIZE> = String::new();
+        if let Some(absolute) = header.strip_prefix(':') {
+            path.push_str(absolute).map_err(|_| ScpiError::UndefinedHeader)?;
+        } else if header.starts_with('*') || self.prefix.is_empty() {
+            path.push_str(header).map_err(|_| ScpiError::UndefinedHeader)?;
+        } else {
+            let mut relative: String<PREFIX_SIZE> = self.prefix.clone();
+            relative.push(':').and_then(|()| relative.push_str(header)).map_err(|_| ScpiError::UndefinedHeader)?;
+            path = relative;
+        }
+        if !header.starts_with('*') {
+            self.prefix.clear();
+            // Can't fail, it's a part of the path, which fit
+            let _ = self.prefix.push_str(path.rsplit_once(':').map_or("", |(prefix, _)| prefix));
+        }
+        let header = lookup(&path).ok_or(ScpiError::UndefinedHeader)?;
+
+        let mut list = Vec::new();
+        let mut rest = Some(params.trim());
+        while let Some(remaining) = rest.filter(|r| !r.is_empty()) {
+            let (param, after) = split_unquoted(remaining, ',');
+            let param = param.trim();
+            if param.is_empty() {
+                return Err(ScpiError::SyntaxError); // E.g. `1,,2`
+            }
+            // Strings are in quotes, so that they can have commas and semicolons in them
+            let unquoted = ['"', '\''].iter()
+                .find_map(|&q| param.strip_prefix(q).and_then(|p| p.strip_suffix(q)))
+                .unwrap_or(param);
+            list.push(unquoted).map_err(|_| ScpiError::ParameterNotAllowed)?;
+            rest = after;
+            if rest.is_some_and(|r| r.trim().is_empty()) {
+                return Err(ScpiError::SyntaxError); // A trailing comma
+            }
+        }
+        Ok(ScpiCommand { header, query, params: list })
+    }
+}
+
+impl<'l> Iterator for Program<'l> {
+    type Item = Result<ScpiCommand<'l>, ScpiError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (unit, rest) = split_unquoted(self.rest?, ';');
+            self.rest = rest;
+            let unit = unit.trim();
+            if !unit.is_empty() { // An empty command between two semicolons is nothing to do
+                return Some(self.command(unit));
+            }
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, ErrorQueue, Header, ScpiError, ERROR_QUEUE_SIZE};
+
+    /// The header, whether it's a query and the parameters
+    type Parsed<'l> = (Header, bool, std::vec::Vec<&'l str>);
+
+    /// Of each command of the line
+    fn commands(line: &str) -> std::vec::Vec<Result<Parsed<'_>, ScpiError>> {
+        parse(line).map(|c| c.map(|c| (c.header, c.query, c.params.to_vec()))).collect()
+    }
+
+    #[test]
+    fn parses_headers() {
+        assert_eq!(commands(":STACK:PUSH 3.14"), [Ok((Header::Push, false, vec!["3.14"]))]);
+        assert_eq!(commands(":disp:bright 4"), [Ok((Header::Brightness, false, vec!["4"]))]);
+        assert_eq!(commands("SYST:ERR?"), [Ok((Header::Error, true, vec![]))]);
+        assert_eq!(commands("*idn?"), [Ok((Header::Identify, true, vec![]))]);
+        assert_eq!(commands(":SYSTEM:ERROR?"), [Ok((Header::Error, true, vec![]))]);
+        // Neither the short nor the long form
+        assert_eq!(commands(":STA:PUSH 1"), [Err(ScpiError::UndefinedHeader)]);
+        assert_eq!(commands(":STACK:PUSHED 1"), [Err(ScpiError::UndefinedHeader)]);
+        assert_eq!(commands(":STACK"), [Err(ScpiError::UndefinedHeader)]);
+    }
+
+    #[test]
+    fn follows_the_path_over_semicolons() {
+        assert_eq!(commands(":STAC:PUSH 2;PUSH 3;*OPC?;DEPTH?;:SYST:VERS?"), [
+            Ok((Header::Push, false, vec!["2"])),
+            Ok((Header::Push, false, vec!["3"])),
+            Ok((Header::OperationComplete, true, vec![])),
+            Ok((Header::Depth, true, vec![])),
+            Ok((Header::Version, true, vec![])),
+        ]);
+        // Relative to nothing, there's no such root command
+        assert_eq!(commands("PUSH 1"), [Err(ScpiError::UndefinedHeader)]);
+        assert_eq!(commands(";; :STACK:CLEAR ;"), [Ok((Header::Clear, false, vec![]))]);
+    }
+
+    #[test]
+    fn parses_parameters() {
+        assert_eq!(commands(":CALC:RPN \"1 2 +; 3 *\""), [Ok((Header::Rpn, false, vec!["1 2 +; 3 *"]))]);
+        assert_eq!(commands(":SYST:COMM 'label \"a,b\"'"), [Ok((Header::Command, false, vec!["label \"a,b\""]))]);
+        assert_eq!(commands(":STACK:PEEK? 1 , 2"), [Ok((Header::Peek, true, vec!["1", "2"]))]);
+        assert_eq!(commands(":STACK:PEEK? 1,2,3"), [Err(ScpiError::ParameterNotAllowed)]);
+        assert_eq!(commands(":STACK:PEEK? 1,"), [Err(ScpiError::SyntaxError)]);
+
+        let command = parse(":STACK:PUSH").next().unwrap().unwrap();
+        assert_eq!(command.param(), Err(ScpiError::MissingParameter));
+        assert_eq!(command.optional_param(), Ok(None));
+        let command = parse(":STACK:DEPTH? 1").next().unwrap().unwrap();
+        assert_eq!(command.no_params(), Err(ScpiError::ParameterNotAllowed));
+    }
+
+    #[test]
+    fn queues_errors() {
+        let mut errors = ErrorQueue::new();
+        assert_eq!(errors.pop(), None);
+        for _ in 0..(ERROR_QUEUE_SIZE + 3) {
+            errors.push(ScpiError::UndefinedHeader);
+        }
+        // The oldest ones stay, the newest says there were more
+        for _ in 0..(ERROR_QUEUE_SIZE - 1) {
+            assert_eq!(errors.pop(), Some(ScpiError::UndefinedHeader));
+        }
+        assert_eq!(errors.pop().map(ScpiError::code), Some(-350));
+        assert_eq!(errors.pop(), None);
+    }
+}
@@ -0,0 +1,167 @@
+use embedded_graphics::{
+    prelude::*,
+
+    mono_font::{
+        ascii::FONT_6X10,
+        MonoTextStyle,
+    },
+    primitives::{
+        PrimitiveStyle,
+        Rectangle,
+    },
+    text::{
+        Alignment,
+        Baseline,
+        Text,
+        TextStyleBuilder,
+    },
+};
+
+use crate::display::{FlushableDisplay, Palette};
+use crate::custom_error::CustomError; // Because we already have the `mod` in `main.rs`
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// How often the logo moves, in microseconds
+const FRAME_US: u64 = 100_000;
+/// How many pixels the logo moves per frame in each direction
+const STEP: i32 = 2;
+/// Size of the bouncing logo, a framed "RPN"
+const LOGO_SIZE: Size = Size::new(24, 14);
+const LOGO_TEXT: &str = "RPN";
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+const fn logo_frame_style<C: Palette>() -> PrimitiveStyle<C> {
+    PrimitiveStyle::with_stroke(C::FOREGROUND, 1)
+}
+
+const fn logo_text_style<C: Palette>() -> MonoTextStyle<'static, C> {
+    MonoTextStyle::new(&FONT_6X10, C::FOREGROUND)
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// What the screensaver shows, see the `saver` command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, defmt::Format)]
+pub enum SaverMode {
+    /// Nothing at all
+    Blank,
+    /// A small logo bouncing around, so that it's obvious the calculator is still on
+    #[default] Bounce,
+}
+
+impl SaverMode {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            SaverMode::Blank => 0,
+            SaverMode::Bounce => 1,
+        }
+    }
+
+    /// Unknown values (e.g. from erased flash) give the default
+    pub fn from_byte(byte: u8) -> Self {
+        match byte {
+            0 => SaverMode::Blank,
+            1 => SaverMode::Bounce,
+            _ => SaverMode::default(),
+        }
+    }
+}
+
+/// Blanks the display after a while without any input, so that the OLED doesn't burn in.
+///
+/// It doesn't keep its own timeout, that's in the settings, so that it's persisted. Like with the toast,
+/// the caller is responsible for redrawing the display once it's woken up, see `wake()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Screensaver {
+    /// Timestamp of the last input, in microseconds
+    last_activity: u64,
+    active: bool,
+    /// Top-left corner of the logo
+    position: Point,
+    /// Direction of the logo's movement, in pixels per frame
+    velocity: Point,
+    /// Timestamp of the next move of the logo
+    next_frame: u64,
+}
+
+impl Screensaver {
+    pub const fn new() -> Self {
+        Screensaver {
+            last_activity: 0,
+            active: false,
+            position: Point::zero(),
+            velocity: Point::new(STEP, STEP),
+            next_frame: 0,
+        }
+    }
+
+    /// Records input at `now`, restarting the timeout. Returns whether the screensaver was active,
+    /// in which case the caller has to redraw the display.
+    pub fn wake(&mut self, now: u64) -> bool {
+        self.last_activity = now;
+        core::mem::replace(&mut self.active, false)
+    }
+
+    /// Whether the screensaver is covering the display
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Starts the screensaver once `timeout_secs` passed without input, and animates it afterwards.
+    /// Zero seconds means it's disabled. Meant to be called repeatedly while polling for input.
+    pub fn tick<D>(&mut self, disp: &mut D, timeout_secs: u16, mode: SaverMode, now: u64) -> Result<(), CustomError>
+    where
+        D: FlushableDisplay,
+        CustomError: From<D::Error>,
+    {
+        if timeout_secs == 0 {
+            return Ok(());
+        }
+
+        if !self.active {
+            if now.saturating_sub(self.last_activity) < u64::from(timeout_secs) * 1_000_000 {
+                return Ok(());
+            }
+            self.active = true;
+            self.next_frame = now;
+            disp.clear(D::Color::BACKGROUND)?;
+            disp.flush_display()?;
+        }
+
+        if mode == SaverMode::Bounce && now >= self.next_frame {
+            self.next_frame = now + FRAME_US;
+            self.move_logo(disp.bounding_box().size);
+
+            disp.clear(D::Color::BACKGROUND)?;
+            let logo = Rectangle::new(self.position, LOGO_SIZE);
+            logo.into_styled(logo_frame_style()).draw(disp)?;
+            let text_style = TextStyleBuilder::new()
+                .alignment(Alignment::Center)
+                .baseline(Baseline::Middle)
+                .build();
+            Text::with_text_style(LOGO_TEXT, logo.center(), logo_text_style(), text_style).draw(disp)?;
+            disp.flush_display()?;
+        }
+        Ok(())
+    }
+
+    /// Moves the logo by a step, bouncing off the edges of the display
+    fn move_logo(&mut self, area: Size) {
+        let max_x = area.width.saturating_sub(LOGO_SIZE.width) as i32;
+        let max_y = area.height.saturating_sub(LOGO_SIZE.height) as i32;
+
+        let mut next = self.position + self.velocity;
+        if next.x < 0 || next.x > max_x {
+            self.velocity.x = -self.velocity.x;
+            next.x = next.x.clamp(0, max_x);
+        }
+        if next.y < 0 || next.y > max_y {
+            self.velocity.y = -self.velocity.y;
+            next.y = next.y.clamp(0, max_y);
+        }
+        self.position = next;
+    }
+}
@@ -0,0 +1,184 @@
+use core::fmt::Write;
+use heapless::String;
+use embedded_graphics::prelude::*;
+
+use crate::display::Palette;
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Longest line sent, the plain PNM formats shouldn't have lines over 70 characters
+const LINE_SIZE: usize = 80;
+/// Pixels per line of a plain PBM, written without spaces between them
+const PBM_PIXELS_PER_LINE: usize = 64;
+/// Pixels per line of a plain PGM, up to three characters each
+const PGM_PIXELS_PER_LINE: usize = 16;
+/// Bytes per line of an XBM, as the `bitmap` program of X11 writes them
+const XBM_BYTES_PER_LINE: usize = 12;
+/// What the XBM's `#define`s and array are named after
+const XBM_NAME: &str = "screenshot";
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// What `encode()` writes the image as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, defmt::Format)]
+pub enum Format {
+    /// The plain (ASCII) PBM of Netpbm, or a plain PGM on a grayscale display; readable by most image viewers and editors
+    #[default] Pbm,
+    /// X BitMap, a C source file with the pixels as an array, e.g. for pasting into another firmware; grayscale gets thresholded
+    Xbm,
+}
+
+impl Format {
+    /// Returns `BadInput` for an unknown name
+    pub fn from_name(name: &str) -> Result<Self, CustomError> {
+        match name {
+            "pbm" | "pgm" => Ok(Format::Pbm),
+            "xbm" => Ok(Format::Xbm),
+            _ => Err(CE::BadInput),
+        }
+    }
+}
+
+/// How bright the pixel shows on the panel, from 0 (off) to the palette's maximum
+fn level<C: Palette>(color: C, inverted: bool) -> u8 {
+    let max = (1_u8 << C::BITS) - 1;
+    if inverted { max - color.to_bits() } else { color.to_bits() }
+}
+
+/// Whether the pixel is closer to off than to the brightest, the images have dark pixels as the set bits
+fn is_dark<C: Palette>(color: C, inverted: bool) -> bool {
+    u16::from(level(color, inverted)) * 2 < 1 << C::BITS
+}
+
+/// Sends the line with CR LF, then starts a new one
+fn send(line: &mut String<LINE_SIZE>, out: &mut impl FnMut(&[u8])) -> Result<(), CustomError> {
+    line.push_str("\r\n").map_err(|_| CE::CapacityError)?;
+    out(line.as_bytes());
+    line.clear();
+    Ok(())
+}
+
+/// Writes the image of the size a line at a time into `out`, reading each pixel from `pixel`, e.g. a frame buffer.
+///
+/// As on the panel, the lit pixels are light and the background dark, so a monochrome image is mostly set bits.
+/// With `inverted`, the pixels get inverted first, the same as `Panel::set_inverted()` shows them.
+pub fn encode<C: Palette>(
+    format: Format,
+    size: Size,
+    inverted: bool,
+    pixel: impl Fn(Point) -> C,
+    mut out: impl FnMut(&[u8]),
+) -> Result<(), CustomError> {
+    let mut line: String<LINE_SIZE> = String::new();
+    let grayscale = C::BITS > 1;
+    match format {
+        Format::Pbm => {
+            if grayscale {
+                write!(line, "P2\r\n{} {}\r\n{}", size.width, size.height, (1_u8 << C::BITS) - 1)?;
+            } else {
+                write!(line, "P1\r\n{} {}", size.width, size.height)?;
+            }
+            send(&mut line, &mut out)?;
+            for y in 0..size.height as i32 {
+                for x in 0..size.width as i32 {
+                    let color = pixel(Point::new(x, y));
+                    if grayscale {
+                        write!(line, "{} ", level(color, inverted))?;
+                    } else {
+                        line.push(if is_dark(color, inverted) { '1' } else { '0' }).map_err(|_| CE::CapacityError)?;
+                    }
+                    let per_line = if grayscale { PGM_PIXELS_PER_LINE } else { PBM_PIXELS_PER_LINE };
+                    if (x as usize + 1).is_multiple_of(per_line) || x as u32 == size.width - 1 {
+                        send(&mut line, &mut out)?;
+                    }
+                }
+            }
+        },
+        Format::Xbm => {
+            write!(line, "#define {}_width {}\r\n#define {}_height {}", XBM_NAME, size.width, XBM_NAME, size.height)?;
+            send(&mut line, &mut out)?;
+            write!(line, "static unsigned char {}_bits[] = {{", XBM_NAME)?;
+            send(&mut line, &mut out)?;
+
+            // Each row is padded to whole bytes, the leftmost pixel being the lowest bit
+            let row_bytes = size.width.div_ceil(8);
+            let total = (row_bytes * size.height) as usize;
+            for i in 0..total {
+                let (row, column) = (i as u32 / row_bytes, i as u32 % row_bytes);
+                let mut byte = 0_u8;
+                for bit in 0..8 {
+                    let x = column * 8 + bit;
+                    if x < size.width && is_dark(pixel(Point::new(x as i32, row as i32)), inverted) {
+                        byte |= 1 << bit;
+                    }
+                }
+                if i.is_multiple_of(XBM_BYTES_PER_LINE) {
+                    line.push_str("   ").map_err(|_| CE::CapacityError)?;
+                }
+                write!(line, " 0x{:02x}{}", byte, if i + 1 < total { "," } else { "" })?;
+                if (i + 1).is_multiple_of(XBM_BYTES_PER_LINE) || i + 1 == total {
+                    send(&mut line, &mut out)?;
+                }
+            }
+            line.push_str("};").map_err(|_| CE::CapacityError)?;
+            send(&mut line, &mut out)?;
+        },
+    }
+    Ok(())
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+    use embedded_graphics::prelude::*;
+    use embedded_graphics::pixelcolor::{BinaryColor, Gray4};
+    use super::{encode, Format};
+    use crate::display::Palette;
+    use crate::custom_error::CE;
+
+    /// A single lit pixel in the top left corner
+    fn corner<C: Palette>(lit: C, off: C) -> impl Fn(Point) -> C {
+        move |p| if p == Point::zero() { lit } else { off }
+    }
+
+    fn collect<C: Palette>(format: Format, size: Size, inverted: bool, pixel: impl Fn(Point) -> C) -> std::string::String {
+        let mut bytes = Vec::new();
+        encode(format, size, inverted, pixel, |line: &[u8]| bytes.extend_from_slice(line)).unwrap();
+        std::string::String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn writes_pbm() {
+        let image = collect(Format::Pbm, Size::new(3, 2), false, corner(BinaryColor::On, BinaryColor::Off));
+        assert_eq!(image, "P1\r\n3 2\r\n011\r\n111\r\n");
+        let image = collect(Format::Pbm, Size::new(3, 2), true, corner(BinaryColor::On, BinaryColor::Off));
+        assert_eq!(image, "P1\r\n3 2\r\n100\r\n000\r\n");
+        // The long rows get split, since the lines shouldn't be over 70 characters
+        let image = collect(Format::Pbm, Size::new(128, 1), false, corner(BinaryColor::On, BinaryColor::Off));
+        assert_eq!(image.lines().map(str::len).collect::<Vec<_>>(), [2, 5, 64, 64]);
+
+        let image = collect(Format::Pbm, Size::new(2, 1), false, corner(Gray4::new(0xB), Gray4::BLACK));
+        assert_eq!(image, "P2\r\n2 1\r\n15\r\n11 0 \r\n");
+    }
+
+    #[test]
+    fn writes_xbm() {
+        let image = collect(Format::Xbm, Size::new(10, 2), false, corner(BinaryColor::On, BinaryColor::Off));
+        assert_eq!(image, "#define screenshot_width 10\r\n#define screenshot_height 2\r\n\
+            static unsigned char screenshot_bits[] = {\r\n    0xfe, 0x03, 0xff, 0x03\r\n};\r\n");
+        // A whole SSD1306 frame, twelve bytes a line
+        let image = collect(Format::Xbm, Size::new(128, 64), false, |_| BinaryColor::Off);
+        assert_eq!(image.lines().count(), 3 + (128 / 8 * 64_usize).div_ceil(12) + 1);
+        assert!(image.lines().all(|line| line.len() <= 76));
+
+        assert_eq!(Format::from_name("xbm"), Ok(Format::Xbm));
+        assert_eq!(Format::from_name("png"), Err(CE::BadInput));
+    }
+}
@@ -0,0 +1,140 @@
+use core::{cell::RefCell, ptr};
+use embedded_graphics::prelude::*;
+use heapless::String;
+
+use crate::display::{Dimmed, FlushableDisplay, NullDisplay, Palette};
+use crate::stack::{CustomStack, CustomStackBuilder};
+use crate::decfix::DecimalFixed;
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// How long each display pattern is shown, in CPU cycles (half a second at the default 125 MHz system clock).
+/// We don't have the `Delay` in here, so we just burn the cycles.
+const PATTERN_CYCLES: u32 = 125_000_000 / 2;
+/// Size of the scratch buffer for the RAM test, in words
+const RAM_TEST_WORDS: usize = 256;
+/// What gets written into every word of the scratch buffer, one pattern after another.
+/// Alternating bits catch shorts between neighbouring bit lines, all zeroes and ones catch stuck bits.
+const RAM_PATTERNS: [u32; 4] = [0x5555_5555, 0xAAAA_AAAA, 0x0000_0000, 0xFFFF_FFFF];
+/// Strings that must be formatted back exactly the same after parsing, or as the second string if it's not normalised
+const DECIMAL_CASES: [(&str, &str); 6] = [
+    ("0", "0"),
+    ("1.5", "1.5"),
+    ("-0.25", "-0.25"),
+    ("123456.789", "123456.789"),
+    ("007.10", "7.1"),
+    ("-0.000000001", "-0.000000001"),
+];
+
+/// The names of the tests, in the order of the results of `run_all()`
+pub const TEST_NAMES: [&str; 4] = ["display", "ram", "stack", "decimal"];
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Runs all the tests, see `TEST_NAMES` for what they are.
+/// The display test leaves the display cleared, it's the caller's job to redraw it.
+pub fn run_all<D>(disp_refcell: &RefCell<D>) -> [Result<(), CustomError>; 4]
+where
+    D: FlushableDisplay,
+    CustomError: From<D::Error>,
+{
+    [display(disp_refcell), ram(), stack(), decimal()]
+}
+
+/// Returns an error (and logs what failed) if the condition doesn't hold.
+fn check(condition: bool, what: &str) -> Result<(), CustomError> {
+    if condition {
+        Ok(())
+    } else {
+        log_error!("Self-test check failed: {}", what);
+        Err(CE::Other)
+    }
+}
+
+/// Shows all pixels on, all off and a checkerboard, so that dead pixels (or rows) are visible.
+/// It can only fail on the bus, since we can't read the display back; the rest is up to the user's eyes.
+fn display<D>(disp_refcell: &RefCell<D>) -> Result<(), CustomError>
+where
+    D: FlushableDisplay,
+    CustomError: From<D::Error>,
+{
+    let mut disp = disp_refcell.borrow_mut();
+    for color in [D::Color::FOREGROUND, D::Color::BACKGROUND] {
+        disp.clear(color)?;
+        disp.flush_now()?;
+        cortex_m::asm::delay(PATTERN_CYCLES);
+    }
+
+    // Every other pixel of an all-on display (or all of them in gray), onto the all-off one
+    Dimmed(&mut *disp).clear(D::Color::FOREGROUND)?;
+    disp.flush_now()?;
+    cortex_m::asm::delay(PATTERN_CYCLES);
+
+    disp.clear(D::Color::BACKGROUND)?;
+    Ok(())
+}
+
+/// Writes patterns into a scratch buffer and reads them back.
+fn ram() -> Result<(), CustomError> {
+    let mut buf = [0_u32; RAM_TEST_WORDS];
+    // After the constant patterns, each word gets its own index, so that address lines shorted together don't go unnoticed
+    for round in 0..=RAM_PATTERNS.len() {
+        let pattern = |i: usize| RAM_PATTERNS.get(round).copied().unwrap_or(i as u32);
+        // Volatile, so that the compiler doesn't optimise the buffer away
+        for (i, word) in buf.iter_mut().enumerate() {
+            unsafe { ptr::write_volatile(word, pattern(i)) }; // Safety: The reference is valid, it's from a local
+        }
+        for (i, word) in buf.iter().enumerate() {
+            let read = unsafe { ptr::read_volatile(word) }; // Safety: Same as above
+            if read != pattern(i) {
+                log_error!("RAM test: wrote 0x{:08X} at word {}, read back 0x{:08X}", pattern(i), i, read);
+                return Err(CE::Other);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the stack behaves like a stack, on a headless one so that the user's stays intact.
+fn stack() -> Result<(), CustomError> {
+    let mut stack: CustomStack<'_, u32, NullDisplay> = CustomStackBuilder::new().build_headless();
+
+    check(stack.pop().is_none(), "popping an empty stack")?;
+    for i in 1..=3 {
+        stack.push(i).map_err(|(e, _)| e)?;
+    }
+    check(stack.len() == 3, "length after pushing")?;
+    check(stack.peek() == Some(&3) && stack.peek_nth(2) == Some(&1), "peeking")?;
+    check(stack.pop() == Some(3) && stack.len() == 2, "popping")?;
+
+    // The default overflow policy rejects pushes onto a full stack, giving the value back
+    let mut value = 0;
+    while stack.push(value).is_ok() {
+        value += 1;
+    }
+    let full_len = stack.len();
+    check(stack.push(42).is_err_and(|(e, v)| e == CE::CapacityError && v == 42), "pushing onto a full stack")?;
+    check(stack.len() == full_len, "length after a rejected push")?;
+
+    stack.clear();
+    check(stack.is_empty(), "clearing")
+}
+
+/// Round-trips numbers through parsing and formatting, including the rounding of the `fix` and `sci` formats.
+fn decimal() -> Result<(), CustomError> {
+    for (input, expected) in DECIMAL_CASES {
+        let formatted: String<32> = heapless::format!("{}", DecimalFixed::parse_str(input, None)?)?;
+        check(formatted == expected, input)?;
+    }
+
+    let num = DecimalFixed::parse_str("2.345", None)?;
+    let fixed: String<32> = heapless::format!("{:.2}", num)?;
+    check(fixed == "2.35", "rounding to 2 places")?;
+    let scientific: String<32> = heapless::format!("{:.1e}", num)?;
+    check(scientific == "2.3e0", "scientific notation")
+}
@@ -0,0 +1,412 @@
+//! Sensors on a second I²C bus (I2C1), read by the `read` command onto the stack.
+//!
+//! To add a sensor, implement `Sensor` for a new unit struct, add it to `registry()` and bump `SENSOR_COUNT`.
+
+#[cfg(feature = "sensors")]
+use core::cell::RefCell;
+#[cfg(feature = "sensors")]
+use cortex_m::interrupt::{self as cs_interrupt, Mutex};
+#[cfg(feature = "sensors")]
+use rp2040_hal::{self as hal, gpio::{bank0::{Gpio6, Gpio7}, FunctionI2C, Pin, PullUp}, pac};
+
+use crate::decfix::DecimalFixed;
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Speed of the sensor bus, the standard mode all of the sensors support
+#[cfg(feature = "sensors")]
+pub const BUS_FREQ: hal::fugit::HertzU32 = hal::fugit::HertzU32::kHz(100);
+/// How many times a sensor is asked whether its conversion is done before giving up, each asking takes about 0.3 ms at `BUS_FREQ`
+const MAX_POLLS: u32 = 200;
+pub const SENSOR_COUNT: usize = 3;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// The I²C transfers the drivers need, so that they don't have to be generic over the bus
+pub trait SensorBus {
+    /// Writes the bytes (e.g. a register's address), then reads into the buffer, in one transaction
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), CustomError>;
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), CustomError>;
+}
+
+/// A driver of one kind of sensor
+pub trait Sensor {
+    /// The name it's read by, e.g. `read bme280`
+    fn name(&self) -> &'static str;
+    /// The quantities it measures with their units, the first being read when none is asked for
+    fn quantities(&self) -> &'static [(&'static str, &'static str)];
+    /// Measures the quantity, an index into `quantities()`
+    fn read(&self, bus: &mut dyn SensorBus, quantity: usize) -> Result<DecimalFixed, CustomError>;
+}
+
+/// All the sensors there are drivers for, in the order `read` lists them in
+pub fn registry() -> [&'static dyn Sensor; SENSOR_COUNT] {
+    [&Bme280, &Ina219, &Ads1115]
+}
+
+/// Finds a sensor's driver by its name
+pub fn find(name: &str) -> Option<&'static dyn Sensor> {
+    registry().into_iter().find(|sensor| sensor.name().eq_ignore_ascii_case(name))
+}
+
+/// Reads a register of 16 bits, big-endian, as all the sensors here have them
+fn read_u16(bus: &mut dyn SensorBus, address: u8, register: u8) -> Result<u16, CustomError> {
+    let mut bytes = [0; 2];
+    bus.write_read(address, &[register], &mut bytes)?;
+    Ok(u16::from_be_bytes(bytes))
+}
+
+/// Asks until `done` says the conversion is done, or gives up after `MAX_POLLS`
+fn wait_until(bus: &mut dyn SensorBus, mut done: impl FnMut(&mut dyn SensorBus) -> Result<bool, CustomError>) -> Result<(), CustomError> {
+    for _ in 0..MAX_POLLS {
+        if done(bus)? {
+            return Ok(());
+        }
+    }
+    log_error!("A sensor didn't finish its conversion in time");
+    Err(CE::Other)
+}
+
+/// The value of a raw reading in units of 10^exponent, with the default exponent as all values on the stack
+fn scaled(value: i64, exponent: i32) -> Result<DecimalFixed, CustomError> {
+    DecimalFixed::new_prescaled(value, exponent).with_exponent(None)
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Bosch BME280 temperature, pressure and humidity sensor, at its default address (SDO to ground).
+/// Each reading is a forced measurement with no oversampling nor filtering, about 8 ms.
+pub struct Bme280;
+
+const BME280_ADDRESS: u8 = 0x76;
+const BME280_CHIP_ID: u8 = 0x60;
+const BME280_REG_ID: u8 = 0xD0;
+const BME280_REG_CALIB_TP: u8 = 0x88;
+const BME280_REG_CALIB_H1: u8 = 0xA1;
+const BME280_REG_CALIB_H: u8 = 0xE1;
+const BME280_REG_CTRL_HUM: u8 = 0xF2;
+const BME280_REG_STATUS: u8 = 0xF3;
+const BME280_REG_CTRL_MEAS: u8 = 0xF4;
+const BME280_REG_DATA: u8 = 0xF7;
+/// Humidity oversampling x1
+const BME280_HUM_X1: u8 = 0b001;
+/// Temperature and pressure oversampling x1, forced mode
+const BME280_MEAS_FORCED: u8 = (0b001 << 5) | (0b001 << 2) | 0b01;
+const BME280_STATUS_MEASURING: u8 = 1 << 3;
+
+/// The trimming parameters of a BME280, named as in its datasheet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bme280Calibration {
+    t1: u16, t2: i16, t3: i16,
+    p1: u16, p2: i16, p3: i16, p4: i16, p5: i16, p6: i16, p7: i16, p8: i16, p9: i16,
+    h1: u8, h2: i16, h3: u8, h4: i16, h5: i16, h6: i8,
+}
+
+impl Bme280Calibration {
+    /// Parses the 24 bytes from 0x88, the byte at 0xA1 and the 7 bytes from 0xE1
+    pub fn from_bytes(tp: &[u8; 24], h1: u8, h: &[u8; 7]) -> Self {
+        let u = |i: usize| u16::from_le_bytes([tp[i], tp[i + 1]]);
+        let s = |i: usize| i16::from_le_bytes([tp[i], tp[i + 1]]);
+        Bme280Calibration {
+            t1: u(0), t2: s(2), t3: s(4),
+            p1: u(6), p2: s(8), p3: s(10), p4: s(12), p5: s(14), p6: s(16), p7: s(18), p8: s(20), p9: s(22),
+            h1,
+            h2: i16::from_le_bytes([h[0], h[1]]),
+            h3: h[2],
+            // 12-bit values sharing the nibbles of 0xE5, sign-extended by their upper byte
+            h4: (i16::from(h[3] as i8) << 4) | i16::from(h[4] & 0x0F),
+            h5: (i16::from(h[5] as i8) << 4) | i16::from(h[4] >> 4),
+            h6: h[6] as i8,
+        }
+    }
+
+    /// The fine temperature the other compensations use, from the raw 20-bit temperature
+    pub fn t_fine(&self, adc_t: i32) -> i32 {
+        let (t1, t2, t3) = (i32::from(self.t1), i32::from(self.t2), i32::from(self.t3));
+        let var1 = (((adc_t >> 3) - (t1 << 1)) * t2) >> 11;
+        let var2 = (((((adc_t >> 4) - t1) * ((adc_t >> 4) - t1)) >> 12) * t3) >> 14;
+        var1 + var2
+    }
+
+    /// Temperature in hundredths of °C
+    pub fn temperature(&self, t_fine: i32) -> i32 {
+        (t_fine * 5 + 128) >> 8
+    }
+
+    /// Pressure in 1/256 Pa, from the raw 20-bit pressure (the 64-bit compensation of the datasheet)
+    pub fn pressure(&self, t_fine: i32, adc_p: i32) -> Result<i64, CustomError> {
+        let var1 = i64::from(t_fine) - 128_000;
+        let var2 = var1 * var1 * i64::from(self.p6) + ((var1 * i64::from(self.p5)) << 17) + (i64::from(self.p4) << 35);
+        let var1 = ((var1 * var1 * i64::from(self.p3)) >> 8) + ((var1 * i64::from(self.p2)) << 12);
+        let var1 = (((1_i64 << 47) + var1) * i64::from(self.p1)) >> 33;
+        if var1 == 0 {
+            log_error!("The BME280's calibration is broken, it'd divide by zero");
+            return Err(CE::Other);
+        }
+        let p = 1_048_576 - i64::from(adc_p);
+        let p = (((p << 31) - var2) * 3125) / var1;
+        let var1 = (i64::from(self.p9) * (p >> 13) * (p >> 13)) >> 25;
+        let var2 = (i64::from(self.p8) * p) >> 19;
+        Ok(((p + var1 + var2) >> 8) + (i64::from(self.p7) << 4))
+    }
+
+    /// Relative humidity in 1/1024 %, from the raw 16-bit humidity
+    pub fn humidity(&self, t_fine: i32, adc_h: i32) -> i32 {
+        let (h1, h2, h3) = (i32::from(self.h1), i32::from(self.h2), i32::from(self.h3));
+        let (h4, h5, h6) = (i32::from(self.h4), i32::from(self.h5), i32::from(self.h6));
+        let v = t_fine - 76_800;
+        let v = ((((adc_h << 14) - (h4 << 20) - (h5 * v)) + 16_384) >> 15)
+            * (((((((v * h6) >> 10) * (((v * h3) >> 11) + 32_768)) >> 10) + 2_097_152) * h2 + 8192) >> 14);
+        let v = v - (((((v >> 15) * (v >> 15)) >> 7) * h1) >> 4);
+        v.clamp(0, 419_430_400) >> 12
+    }
+}
+
+impl Sensor for Bme280 {
+    fn name(&self) -> &'static str { "bme280" }
+    fn quantities(&self) -> &'static [(&'static str, &'static str)] { &[("temp", "°C"), ("pressure", "hPa"), ("humidity", "%")] }
+
+    fn read(&self, bus: &mut dyn SensorBus, quantity: usize) -> Result<DecimalFixed, CustomError> {
+        let mut id = [0];
+        bus.write_read(BME280_ADDRESS, &[BME280_REG_ID], &mut id)?;
+        if id[0] != BME280_CHIP_ID {
+            log_error!("The device at 0x{:02X} isn't a BME280, its ID is 0x{:02X}", BME280_ADDRESS, id[0]);
+            return Err(CE::Other);
+        }
+        let (mut tp, mut h1, mut h) = ([0; 24], [0], [0; 7]);
+        bus.write_read(BME280_ADDRESS, &[BME280_REG_CALIB_TP], &mut tp)?;
+        bus.write_read(BME280_ADDRESS, &[BME280_REG_CALIB_H1], &mut h1)?;
+        bus.write_read(BME280_ADDRESS, &[BME280_REG_CALIB_H], &mut h)?;
+        let calibration = Bme280Calibration::from_bytes(&tp, h1[0], &h);
+
+        // The humidity's setting only takes effect by the write of `ctrl_meas` after it
+        bus.write(BME280_ADDRESS, &[BME280_REG_CTRL_HUM, BME280_HUM_X1])?;
+        bus.write(BME280_ADDRESS, &[BME280_REG_CTRL_MEAS, BME280_MEAS_FORCED])?;
+        wait_until(bus, |bus| {
+            let mut status = [0];
+            bus.write_read(BME280_ADDRESS, &[BME280_REG_STATUS], &mut status)?;
+            Ok(status[0] & BME280_STATUS_MEASURING == 0)
+        })?;
+        let mut data = [0; 8];
+        bus.write_read(BME280_ADDRESS, &[BME280_REG_DATA], &mut data)?;
+        let adc_20 = |i: usize| (i32::from(data[i]) << 12) | (i32::from(data[i + 1]) << 4) | (i32::from(data[i + 2]) >> 4);
+        let t_fine = calibration.t_fine(adc_20(3));
+
+        match quantity {
+            0 => scaled(i64::from(calibration.temperature(t_fine)), -2),
+            // In 1/25600 hPa, the ten-thousandths are as precise as it gets
+            1 => scaled(calibration.pressure(t_fine, adc_20(0))? * 100 / 256, -4),
+            _ => scaled(i64::from(calibration.humidity(t_fine, i32::from(u16::from_be_bytes([data[6], data[7]])))) * 1000 / 1024, -3),
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Texas Instruments INA219 current and power monitor, at its default address (A0 and A1 to ground), in its default configuration
+/// (32 V range, ±320 mV across the shunt). The current is computed from the shunt voltage, the calibration register isn't used.
+pub struct Ina219;
+
+const INA219_ADDRESS: u8 = 0x40;
+const INA219_REG_SHUNT: u8 = 0x01;
+const INA219_REG_BUS: u8 = 0x02;
+/// The shunt resistor of the common breakout boards, in milliohms
+const INA219_SHUNT_MILLIOHMS: i64 = 100;
+/// The bus voltage register's math overflow flag
+const INA219_BUS_OVF: u16 = 1 << 0;
+
+/// The bus voltage in millivolts, from its register (4 mV per bit, from bit 3 up)
+pub fn ina219_bus_millivolts(register: u16) -> Result<i64, CustomError> {
+    if register & INA219_BUS_OVF != 0 {
+        log_warn!("The INA219's current or power is out of its range");
+        return Err(CE::MathOverflow);
+    }
+    Ok(i64::from(register >> 3) * 4)
+}
+
+/// The current in microamps, from the shunt voltage register (10 µV per bit, signed)
+pub fn ina219_microamps(register: u16) -> i64 {
+    i64::from(register as i16) * 10 * 1000 / INA219_SHUNT_MILLIOHMS
+}
+
+impl Sensor for Ina219 {
+    fn name(&self) -> &'static str { "ina219" }
+    fn quantities(&self) -> &'static [(&'static str, &'static str)] { &[("voltage", "V"), ("current", "A"), ("power", "W")] }
+
+    fn read(&self, bus: &mut dyn SensorBus, quantity: usize) -> Result<DecimalFixed, CustomError> {
+        let millivolts = ina219_bus_millivolts(read_u16(bus, INA219_ADDRESS, INA219_REG_BUS)?)?;
+        let microamps = ina219_microamps(read_u16(bus, INA219_ADDRESS, INA219_REG_SHUNT)?);
+        match quantity {
+            0 => scaled(millivolts, -3),
+            1 => scaled(microamps, -6),
+            _ => scaled(millivolts * microamps, -9),
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Texas Instruments ADS1115 16-bit ADC, at its default address (ADDR to ground), reading the inputs against ground
+/// in the ±4.096 V range, single-shot at 128 samples per second (about 8 ms per reading).
+pub struct Ads1115;
+
+const ADS1115_ADDRESS: u8 = 0x48;
+const ADS1115_REG_CONVERSION: u8 = 0x00;
+const ADS1115_REG_CONFIG: u8 = 0x01;
+/// Starts a conversion when written, reads as set once it's done
+const ADS1115_OS: u16 = 1 << 15;
+/// The input multiplexer's setting of AIN0 against ground, the other inputs follow
+const ADS1115_MUX_AIN0_GND: u16 = 0b100 << 12;
+const ADS1115_PGA_4V096: u16 = 0b001 << 9;
+const ADS1115_MODE_SINGLE: u16 = 1 << 8;
+const ADS1115_DR_128SPS: u16 = 0b100 << 5;
+const ADS1115_COMP_DISABLE: u16 = 0b11;
+/// Microvolts per bit in the ±4.096 V range
+const ADS1115_MICROVOLTS_PER_BIT: i64 = 125;
+
+/// The config register starting a single-shot conversion of the input (0 to 3) against ground
+pub fn ads1115_config(input: u8) -> u16 {
+    ADS1115_OS | (ADS1115_MUX_AIN0_GND + (u16::from(input) << 12)) | ADS1115_PGA_4V096 | ADS1115_MODE_SINGLE
+        | ADS1115_DR_128SPS | ADS1115_COMP_DISABLE
+}
+
+impl Sensor for Ads1115 {
+    fn name(&self) -> &'static str { "ads1115" }
+    fn quantities(&self) -> &'static [(&'static str, &'static str)] { &[("a0", "V"), ("a1", "V"), ("a2", "V"), ("a3", "V")] }
+
+    fn read(&self, bus: &mut dyn SensorBus, quantity: usize) -> Result<DecimalFixed, CustomError> {
+        let [high, low] = ads1115_config(quantity as u8).to_be_bytes();
+        bus.write(ADS1115_ADDRESS, &[ADS1115_REG_CONFIG, high, low])?;
+        wait_until(bus, |bus| Ok(read_u16(bus, ADS1115_ADDRESS, ADS1115_REG_CONFIG)? & ADS1115_OS != 0))?;
+        let raw = read_u16(bus, ADS1115_ADDRESS, ADS1115_REG_CONVERSION)? as i16;
+        scaled(i64::from(raw) * ADS1115_MICROVOLTS_PER_BIT, -6)
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Adapts an `embedded-hal` I²C bus into a `SensorBus`
+#[cfg(feature = "sensors")]
+pub struct I2cBus<I: embedded_hal::i2c::I2c>(pub I);
+
+#[cfg(feature = "sensors")]
+impl<I: embedded_hal::i2c::I2c> SensorBus for I2cBus<I> {
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), CustomError> {
+        self.0.write_read(address, bytes, buffer).map_err(|_| {
+            log_error!("I²C transfer with the sensor at 0x{:02X} failed, is it connected?", address);
+            CE::Other
+        })
+    }
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), CustomError> {
+        self.0.write(address, bytes).map_err(|_| {
+            log_error!("I²C transfer with the sensor at 0x{:02X} failed, is it connected?", address);
+            CE::Other
+        })
+    }
+}
+
+#[cfg(feature = "sensors")]
+type SensorI2c = hal::I2C<pac::I2C1, (Pin<Gpio6, FunctionI2C, PullUp>, Pin<Gpio7, FunctionI2C, PullUp>)>;
+
+/// Global, like the USB device (see `usb.rs`), so that it doesn't have to be passed through every command's `Context`
+#[cfg(feature = "sensors")]
+static BUS: Mutex<RefCell<Option<I2cBus<SensorI2c>>>> = Mutex::new(RefCell::new(None));
+
+/// Takes over the bus for `with_bus()`, call it once at boot
+#[cfg(feature = "sensors")]
+pub fn init(i2c: SensorI2c) {
+    cs_interrupt::free(|cs| BUS.borrow(cs).replace(Some(I2cBus(i2c))));
+}
+
+/// Runs `f` with the sensor bus, or returns `Unimplemented` if it's not there (without the `sensors` feature)
+pub fn with_bus<R>(f: impl FnOnce(&mut dyn SensorBus) -> Result<R, CustomError>) -> Result<R, CustomError> {
+    #[cfg(feature = "sensors")]
+    {
+        // Taken out for the while, so that the interrupts don't wait for the sensors' conversions
+        let Some(mut bus) = cs_interrupt::free(|cs| BUS.borrow(cs).take()) else {
+            log_error!("The sensor bus isn't initialized");
+            return Err(CE::Impossible);
+        };
+        let result = f(&mut bus);
+        cs_interrupt::free(|cs| BUS.borrow(cs).replace(Some(bus)));
+        result
+    }
+    #[cfg(not(feature = "sensors"))]
+    {
+        let _ = f;
+        log_warn!("There's no sensor bus, the firmware was built without the `sensors` feature");
+        Err(CE::Unimplemented)
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::{ads1115_config, find, ina219_bus_millivolts, ina219_microamps, Bme280Calibration, SensorBus};
+    use crate::custom_error::{CustomError, CE};
+    use crate::decfix::DecimalFixed;
+
+    /// Answers the reads by the register written before them, from a table
+    struct FakeBus {
+        registers: &'static [(u8, &'static [u8])],
+        written: usize,
+    }
+
+    impl SensorBus for FakeBus {
+        fn write_read(&mut self, _address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), CustomError> {
+            let (_, value) = self.registers.iter().find(|(register, _)| *register == bytes[0]).ok_or(CE::Other)?;
+            buffer.copy_from_slice(&value[..buffer.len()]);
+            Ok(())
+        }
+
+        fn write(&mut self, _address: u8, _bytes: &[u8]) -> Result<(), CustomError> {
+            self.written += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn compensates_the_bme280() {
+        // The example of the BMP280's datasheet, whose temperature and pressure are the same
+        let tp: [u8; 24] = [
+            0x70, 0x6B, 0x43, 0x67, 0x18, 0xFC, // 27504, 26435, -1000
+            0x7D, 0x8E, 0x43, 0xD6, 0xD0, 0x0B, 0x27, 0x0B, 0x8C, 0x00, 0xF9, 0xFF, 0x8C, 0x3C, 0xF8, 0xC6, 0x70, 0x17,
+        ];
+        let calibration = Bme280Calibration::from_bytes(&tp, 75, &[0x6A, 0x01, 0x00, 0x13, 0x2B, 0x03, 0x1E]);
+        let t_fine = calibration.t_fine(519_888);
+        assert_eq!(calibration.temperature(t_fine), 2508);
+        let pascals = calibration.pressure(t_fine, 415_148).unwrap() / 256;
+        assert!((100_652..=100_654).contains(&pascals));
+        // 12-bit parameters split across nibbles, 0x13 << 4 | 0xB and 0x03 << 4 | 0x2
+        assert_eq!((calibration.h4, calibration.h5), (0x13B, 0x32));
+        let humidity = calibration.humidity(t_fine, 0x6000) / 1024;
+        assert!((0..=100).contains(&humidity));
+    }
+
+    #[test]
+    fn reads_the_ina219_and_ads1115() {
+        assert_eq!(ina219_bus_millivolts(0x5DC0), Ok(12_000)); // 3000 << 3
+        assert_eq!(ina219_bus_millivolts(0x5DC1), Err(CE::MathOverflow));
+        assert_eq!(ina219_microamps(1000), 100_000); // 10 mV across 0.1 Ω
+        assert_eq!(ina219_microamps(-1000_i16 as u16), -100_000);
+
+        let mut bus = FakeBus { registers: &[(0x01, &[0x30, 0x00]), (0x02, &[0x5D, 0xC0])], written: 0 };
+        let ina219 = find("INA219").unwrap();
+        assert_eq!(ina219.read(&mut bus, 0), DecimalFixed::parse_str("12", None));
+        assert_eq!(ina219.read(&mut bus, 1), DecimalFixed::parse_str("1.2288", None)); // 0x3000 * 10 µV / 0.1 Ω
+
+        assert_eq!(ads1115_config(0), 0xC383);
+        assert_eq!(ads1115_config(3), 0xF383);
+        let mut bus = FakeBus { registers: &[(0x00, &[0x40, 0x00]), (0x01, &[0xC3, 0x83])], written: 0 };
+        assert_eq!(find("ads1115").unwrap().read(&mut bus, 0), DecimalFixed::parse_str("2.048", None));
+        assert_eq!(bus.written, 1);
+        assert!(find("bmp180").is_none());
+    }
+}
@@ -0,0 +1,154 @@
+use crate::screensaver::SaverMode;
+use crate::baud::DEFAULT_BAUD;
+use crate::night::{self, NightSchedule};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Size of the serialized settings, see `Settings::to_bytes()`
+pub const SETTINGS_SIZE: usize = 24;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// User preferences that survive a reboot, see `persist::save_settings()`.
+///
+/// Every setting has to have a sane default, since that's what we boot with before anything was saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    /// Value of the display's contrast register, see the `contrast` command
+    pub contrast: u8,
+    /// Seconds without input before the screensaver starts, zero if it's disabled; see the `saver` command
+    pub saver_secs: u16,
+    pub saver_mode: SaverMode,
+    /// Whether the display is inverted (black on white), command mode then shows the opposite; see the `invert` command
+    pub inverted: bool,
+    /// Whether to echo the received chars back over UART, for terminals that don't echo locally; see the `echo` command
+    pub echo: bool,
+    /// Whether lines sent over UART end with `\r\n`, or just `\n`; see the `crlf` command
+    pub crlf: bool,
+    /// Baud rate of the UART, see the `baud` command
+    pub baud: u32,
+    /// Whether the contrast follows the ambient light instead of `contrast`, see the `autobrt` command
+    pub auto_brightness: bool,
+    /// Seconds without input before the display turns off and the core sleeps, zero if it's disabled; see the `sleep` command
+    pub sleep_secs: u16,
+    /// Whether the stack groups the digits by thousands, e.g. `1 234 567.89`; see the `group` command
+    pub digit_grouping: bool,
+    /// Whether the stack shows just the top element in large digits; see the `big` command
+    pub big_digits: bool,
+    /// Whether pushing and popping slides the stack's elements into place; see the `anim` command
+    pub animation: bool,
+    /// When the display gets its night contrast instead of `contrast`, `None` if never; see the `night` command
+    pub night: Option<NightSchedule>,
+    /// Whether the UART uses RTS/CTS flow control, see the `flow` command
+    pub flow_control: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Settings {
+    pub const fn new() -> Self {
+        Settings {
+            contrast: 0xFF, // The same as `Brightness::BRIGHTEST`
+            saver_secs: 0,
+            saver_mode: SaverMode::Bounce,
+            inverted: false,
+            echo: false, // Most terminals echo locally
+            crlf: true,
+            baud: DEFAULT_BAUD,
+            auto_brightness: false, // Needs the photoresistor, which not everyone has
+            sleep_secs: 0,
+            digit_grouping: false,
+            big_digits: false,
+            animation: false,
+            night: None,
+            flow_control: false, // Needs the two extra wires, which the usual adapters leave out
+        }
+    }
+
+    /// The contrast the display should have at the time from `WallClock::status_time()`, see `night::contrast_at()`.
+    /// Auto brightness isn't accounted for, it sets the contrast by itself.
+    pub fn contrast_at(&self, time: Option<(u8, u8)>) -> u8 {
+        night::contrast_at(self.night, time, self.contrast)
+    }
+
+    /// Serializes the settings in the order of the fields, multi-byte ones little-endian.
+    ///
+    /// The layout is append-only: never reorder or drop a field, only add new ones at the end, so that `from_bytes()`
+    /// can still read what an older firmware saved.
+    pub fn to_bytes(self) -> [u8; SETTINGS_SIZE] {
+        let [saver_lo, saver_hi] = self.saver_secs.to_le_bytes();
+        let [baud_0, baud_1, baud_2, baud_3] = self.baud.to_le_bytes();
+        let [sleep_lo, sleep_hi] = self.sleep_secs.to_le_bytes();
+        let [night_0, night_1, night_2, night_3, night_4, night_5] = NightSchedule::to_bytes(self.night);
+        [
+            self.contrast, saver_lo, saver_hi, self.saver_mode.to_byte(), self.inverted as u8, self.echo as u8, self.crlf as u8,
+            baud_0, baud_1, baud_2, baud_3, self.auto_brightness as u8, sleep_lo, sleep_hi,
+            self.digit_grouping as u8, self.big_digits as u8, self.animation as u8,
+            night_0, night_1, night_2, night_3, night_4, night_5, self.flow_control as u8,
+        ]
+    }
+
+    /// Deserializes the settings from the format produced by `to_bytes()`, or a prefix of it.
+    /// Fields missing from the end (saved by an older firmware) get their defaults,
+    /// extra bytes past the ones we know of (saved by a newer one) are ignored.
+    pub fn from_bytes(saved: &[u8]) -> Self {
+        let mut bytes = Self::new().to_bytes();
+        let known = saved.len().min(SETTINGS_SIZE);
+        bytes[..known].copy_from_slice(&saved[..known]);
+        Settings {
+            contrast: bytes[0],
+            saver_secs: u16::from_le_bytes([bytes[1], bytes[2]]),
+            saver_mode: SaverMode::from_byte(bytes[3]),
+            inverted: bytes[4] == 1,
+            echo: bytes[5] == 1,
+            crlf: bytes[6] == 1,
+            baud: u32::from_le_bytes([bytes[7], bytes[8], bytes[9], bytes[10]]),
+            auto_brightness: bytes[11] == 1,
+            sleep_secs: u16::from_le_bytes([bytes[12], bytes[13]]),
+            digit_grouping: bytes[14] == 1,
+            big_digits: bytes[15] == 1,
+            animation: bytes[16] == 1,
+            night: NightSchedule::from_bytes([bytes[17], bytes[18], bytes[19], bytes[20], bytes[21], bytes[22]]),
+            flow_control: bytes[23] == 1,
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changed() -> Settings {
+        Settings { contrast: 0x40, baud: 9600, big_digits: true, flow_control: true, ..Settings::new() }
+    }
+
+    #[test]
+    fn round_trip() {
+        assert_eq!(Settings::from_bytes(&changed().to_bytes()), changed());
+    }
+
+    #[test]
+    fn older_layout_gets_defaults() {
+        // Saved before `digit_grouping` and everything after it existed
+        let saved = Settings::from_bytes(&changed().to_bytes()[..14]);
+        assert_eq!(saved.contrast, 0x40);
+        assert_eq!(saved.baud, 9600);
+        assert!(!saved.big_digits);
+        assert!(!saved.flow_control);
+        assert_eq!(Settings::from_bytes(&[]), Settings::new());
+    }
+
+    #[test]
+    fn newer_layout_is_truncated() {
+        let mut saved = [0xAA_u8; SETTINGS_SIZE + 3];
+        saved[..SETTINGS_SIZE].copy_from_slice(&changed().to_bytes());
+        assert_eq!(Settings::from_bytes(&saved), changed());
+    }
+}
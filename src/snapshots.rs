@@ -0,0 +1,189 @@
+use heapless::{String, Vec};
+
+use crate::kv::{self, ValueWriter, MAX_VALUE_SIZE};
+use crate::stack::CustomStack;
+use crate::display::FlushableDisplay;
+use crate::decfix::DecimalFixed;
+use crate::registers::{RegisterFile, REGISTER_COUNT};
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Prefix of the snapshots' keys in the key-value store, followed by the name
+const KEY_PREFIX: &str = "snap/";
+/// Longest name of a snapshot, in bytes
+pub const NAME_SIZE: usize = 8;
+/// How many snapshots can be saved at once, so that they can't fill up the whole key-value store
+pub const MAX_SNAPSHOTS: usize = 16;
+/// Size of the header: element count and register count, each a little-endian u32
+const HEADER_SIZE: usize = 8;
+/// Size of one serialized element, see `DecimalFixed::to_le_bytes()`
+const ELEMENT_SIZE: usize = 12;
+/// Size of one serialized register, its index followed by its value
+const REGISTER_SIZE: usize = 1 + ELEMENT_SIZE;
+
+// HACK: Evaluate the block of code at compile time to assert that constants aren't malformed
+const fn _check_consts() {
+    if KEY_PREFIX.len() + NAME_SIZE > kv::MAX_KEY_SIZE {
+        core::panic!("The key of a snapshot with the longest name doesn't fit into the key-value store!");
+    }
+    if MAX_SNAPSHOTS > kv::MAX_KEYS {
+        core::panic!("The key-value store can't list that many snapshots!");
+    }
+}
+const _: () = _check_consts();
+
+type Key = String<{ kv::MAX_KEY_SIZE }>;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// What `list()` tells about a snapshot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotInfo {
+    pub name: String<NAME_SIZE>,
+    pub element_count: usize,
+    pub register_count: usize,
+}
+
+/// Names may be up to `NAME_SIZE` bytes of printable ASCII without spaces, so that they can be typed as a single argument.
+fn check_name(name: &str) -> Result<(), CustomError> {
+    if name.is_empty() || name.len() > NAME_SIZE || !name.bytes().all(|b| b.is_ascii_graphic()) {
+        log_warn!("Invalid snapshot name {:?}, it must be 1 to {} printable ASCII characters", name, NAME_SIZE);
+        return Err(CE::BadInput);
+    }
+    Ok(())
+}
+
+fn key_of(name: &str) -> Result<Key, CustomError> {
+    check_name(name)?;
+    let mut key = Key::try_from(KEY_PREFIX)?;
+    key.push_str(name)?;
+    Ok(key)
+}
+
+/// Splits the saved value into the element and register bytes, checking that the counts in the header match its size.
+fn parse(value: &[u8]) -> Result<(&[u8], &[u8]), CustomError> {
+    let read_u32 = |i: usize| value.get(i..(i + 4))
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().expect("Subslice is exactly 4 bytes long")) as usize);
+    let (Some(element_count), Some(register_count)) = (read_u32(0), read_u32(4)) else {
+        return Err(CE::BadInput);
+    };
+    // The first two checks keep the multiplications from overflowing
+    if element_count > value.len() || register_count > value.len()
+        || value.len() != HEADER_SIZE + element_count * ELEMENT_SIZE + register_count * REGISTER_SIZE
+    {
+        return Err(CE::BadInput);
+    }
+    Ok(value[HEADER_SIZE..].split_at(element_count * ELEMENT_SIZE))
+}
+
+/// Lists the saved snapshots, in no particular order.
+pub fn list() -> Result<Vec<SnapshotInfo, MAX_SNAPSHOTS>, CustomError> {
+    let mut snaps = Vec::new();
+    let mut buf = [0_u8; MAX_VALUE_SIZE];
+    for key in kv::keys(KEY_PREFIX)? {
+        let Some(Ok((elements, registers))) = kv::get(&key, &mut buf)?.map(parse) else {
+            log_warn!("Ignoring a corrupted snapshot {:?}", key.as_str());
+            continue;
+        };
+        snaps.push(SnapshotInfo {
+            name: String::try_from(&key[KEY_PREFIX.len()..])?,
+            element_count: elements.len() / ELEMENT_SIZE,
+            register_count: registers.len() / REGISTER_SIZE,
+        }).map_err(|_| CE::CapacityError)?;
+    }
+    Ok(snaps)
+}
+
+/// Saves the stack (only the elements in RAM) and the registers under `name`, replacing a snapshot of the same name.
+///
+/// It goes into the key-value store (see `kv.rs`), which spreads the wear over its sectors.
+/// Returns `CapacityError` if there are `MAX_SNAPSHOTS` other snapshots already, or it doesn't fit into a record.
+pub fn save<D>(name: &str, stack: &CustomStack<'_, DecimalFixed, D>, registers: &RegisterFile) -> Result<(), CustomError>
+where
+    D: FlushableDisplay,
+{
+    let key = key_of(name)?;
+    let register_count = registers.count();
+    let size = HEADER_SIZE + stack.len() * ELEMENT_SIZE + register_count * REGISTER_SIZE;
+    if size > MAX_VALUE_SIZE {
+        log_error!("Snapshot of {} elements doesn't fit into a flash sector", stack.len());
+        return Err(CE::CapacityError);
+    }
+    if !kv::contains(&key)? && kv::keys(KEY_PREFIX)?.len() >= MAX_SNAPSHOTS {
+        log_error!("There are {} snapshots saved already", MAX_SNAPSHOTS);
+        return Err(CE::CapacityError);
+    }
+
+    let serialize_register = |(name, value): (char, DecimalFixed)| {
+        let mut bytes = [0_u8; REGISTER_SIZE];
+        bytes[0] = name as u8 - b'A'; // Names are uppercase ASCII letters
+        bytes[1..].copy_from_slice(&value.to_le_bytes());
+        bytes
+    };
+    kv::set_with(&key, size, &|writer: &mut dyn ValueWriter| {
+        writer.write(&(stack.len() as u32).to_le_bytes())?;
+        writer.write(&(register_count as u32).to_le_bytes())?;
+        for x in stack { // Bottom first, so that they get pushed back in the same order
+            writer.write(&x.to_le_bytes())?;
+        }
+        for bytes in registers.iter().map(serialize_register) {
+            writer.write(&bytes)?;
+        }
+        Ok(())
+    })?;
+
+    log_info!("Saved snapshot {:?} ({} elements, {} registers)", name, stack.len(), register_count);
+    Ok(())
+}
+
+/// Replaces the stack and the registers with the snapshot saved under `name`.
+/// Returns `BadInput` if there's no such snapshot or it's corrupted, in which case nothing gets touched.
+pub fn load<D>(name: &str, stack: &mut CustomStack<'_, DecimalFixed, D>, registers: &mut RegisterFile) -> Result<(), CustomError>
+where
+    D: FlushableDisplay,
+{
+    let mut buf = [0_u8; MAX_VALUE_SIZE];
+    let Some(value) = kv::get(&key_of(name)?, &mut buf)? else {
+        log_warn!("No snapshot named {:?}", name);
+        return Err(CE::BadInput);
+    };
+    let (elements, registers_bytes) = parse(value).inspect_err(|_| log_error!("Snapshot {:?} is corrupted, not loading it", name))?;
+    if registers_bytes.chunks_exact(REGISTER_SIZE).any(|chunk| chunk[0] as usize >= REGISTER_COUNT) {
+        log_error!("Snapshot {:?} has an invalid register, not loading it", name);
+        return Err(CE::BadInput);
+    }
+
+    stack.clear();
+    let values = elements.chunks_exact(ELEMENT_SIZE)
+        .map(|chunk| DecimalFixed::from_le_bytes(chunk.try_into().expect("Chunk is exactly ELEMENT_SIZE long")));
+    if stack.push_exact_iterator(values).is_err() {
+        log_error!("Not enough space on the stack to load {} elements", elements.len() / ELEMENT_SIZE);
+        return Err(CE::CapacityError);
+    }
+
+    registers.clear();
+    for chunk in registers_bytes.chunks_exact(REGISTER_SIZE) {
+        let mut name_buf = [0_u8; 4];
+        let register = RegisterFile::name_of(chunk[0] as usize).encode_utf8(&mut name_buf);
+        registers.store(register, DecimalFixed::from_le_bytes(chunk[1..].try_into().expect("Subslice is exactly ELEMENT_SIZE long")))?;
+    }
+
+    log_info!("Loaded snapshot {:?}", name);
+    Ok(())
+}
+
+/// Deletes the snapshot saved under `name`.
+/// Returns `BadInput` if there's no such snapshot.
+pub fn delete(name: &str) -> Result<(), CustomError> {
+    if !kv::remove(&key_of(name)?)? {
+        log_warn!("No snapshot named {:?}", name);
+        return Err(CE::BadInput);
+    }
+    log_info!("Deleted snapshot {:?}", name);
+    Ok(())
+}
@@ -0,0 +1,157 @@
+use heapless::Vec;
+
+use crate::flash::{self, PAGE_SIZE, SECTOR_SIZE, SPILL_REGION, SPILL_SIZE};
+use crate::decfix::DecimalFixed;
+use crate::stack_set::WORKSPACE_COUNT;
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Size of one serialized element, see `DecimalFixed::to_le_bytes()`
+const ELEMENT_SIZE: usize = 12;
+/// How many elements fit into one flash page (the rest of the page is left unused)
+const ELEMENTS_PER_PAGE: usize = PAGE_SIZE / ELEMENT_SIZE;
+/// Size of one workspace's part of the spill region, each has its own
+const REGION_SIZE: usize = SPILL_SIZE as usize / WORKSPACE_COUNT;
+/// Number of pages in one workspace's part of the spill region
+const REGION_PAGES: usize = REGION_SIZE / PAGE_SIZE;
+const PAGES_PER_SECTOR: usize = SECTOR_SIZE as usize / PAGE_SIZE;
+
+// HACK: Evaluate the block of code at compile time to assert that constants aren't malformed
+const fn _check_consts() {
+    if !REGION_SIZE.is_multiple_of(SECTOR_SIZE as usize) || REGION_SIZE * WORKSPACE_COUNT != SPILL_SIZE as usize {
+        core::panic!("Every workspace's part of the spill region must consist of whole sectors!");
+    }
+}
+const _: () = _check_consts();
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Somewhere to put the bottommost elements of a stack that don't fit into RAM anymore,
+/// see `OverflowPolicy::Spill`.
+///
+/// It's a stack itself: the last spilled element is the first one to be unspilled.
+pub trait SpillStore<T> {
+    /// Number of elements currently spilled
+    fn len(&self) -> usize;
+    /// Stores one more element on top of the spilled ones.
+    /// On error, the element was NOT stored and the caller still has to keep it.
+    fn spill(&mut self, value: &T) -> Result<(), CustomError>;
+    /// Takes back the last spilled element, or returns `None` if there's none.
+    fn unspill(&mut self) -> Result<Option<T>, CustomError>;
+    /// Forgets all spilled elements.
+    fn clear(&mut self);
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A `SpillStore` backed by one workspace's part of the spill region of the flash.
+///
+/// The elements are buffered in RAM until they fill a whole page, and only then programmed (write-behind),
+/// so that we don't wear out the flash by programming on every push. They're read back a page at a time as needed.
+///
+/// The pages are used as a ring buffer: a page is never reprogrammed before its whole sector gets erased,
+/// which we only do when reaching the sector again and none of its pages hold any elements.
+/// The contents don't survive a reboot, there's `persist.rs` for that.
+pub struct FlashSpill {
+    /// Offset of our part of the spill region
+    region: u32,
+    /// The topmost spilled elements, not programmed yet
+    buf: Vec<DecimalFixed, ELEMENTS_PER_PAGE>,
+    /// Indices of the programmed pages that hold spilled elements, the oldest first
+    pages: Vec<u16, REGION_PAGES>,
+    /// Index of the next page to program
+    next_page: usize,
+}
+
+impl FlashSpill {
+    /// Takes the part of the spill region belonging to the workspace with the given index, starting from zero.
+    /// Panics if there's no such workspace.
+    pub const fn new(workspace: usize) -> Self {
+        assert!(workspace < WORKSPACE_COUNT, "There's no such workspace");
+        FlashSpill {
+            region: SPILL_REGION + (workspace * REGION_SIZE) as u32,
+            buf: Vec::new(),
+            pages: Vec::new(),
+            next_page: 0,
+        }
+    }
+
+    /// Programs the full buffer into the next free page.
+    /// Returns `CapacityError` if the next sector still holds spilled elements, i.e. the region is full.
+    fn flush_page(&mut self) -> Result<(), CustomError> {
+        if self.next_page.is_multiple_of(PAGES_PER_SECTOR) {
+            // We're entering a new sector, which has to be erased first, unless we'd lose data by that
+            let sector = self.next_page / PAGES_PER_SECTOR;
+            if self.pages.iter().any(|&p| p as usize / PAGES_PER_SECTOR == sector) {
+                log_warn!("Flash spill region is full");
+                return Err(CE::CapacityError);
+            }
+            flash::erase(self.region + (sector * SECTOR_SIZE as usize) as u32, SECTOR_SIZE)?;
+        }
+
+        // Has to be in RAM anyway, we can't program from flash
+        let mut page = [0xFF_u8; PAGE_SIZE];
+        for (chunk, x) in page.chunks_exact_mut(ELEMENT_SIZE).zip(&self.buf) {
+            chunk.copy_from_slice(&x.to_le_bytes());
+        }
+        flash::program(self.region + (self.next_page * PAGE_SIZE) as u32, &page)?;
+
+        // Can't overflow, there's at most one entry per page in the region
+        self.pages.push(self.next_page as u16).map_err(|_| CE::Impossible)?;
+        self.next_page = (self.next_page + 1) % REGION_PAGES;
+        self.buf.clear();
+        log_trace!("Spilled a page of elements into flash, {} pages in use", self.pages.len());
+        Ok(())
+    }
+
+    /// Reads the last programmed page back into the (empty) buffer.
+    fn load_page(&mut self) -> Result<(), CustomError> {
+        let Some(&index) = self.pages.last() else {
+            return Ok(());
+        };
+
+        let mut page = [0_u8; PAGE_SIZE];
+        flash::read(self.region + (index as usize * PAGE_SIZE) as u32, &mut page)?;
+        for chunk in page.chunks_exact(ELEMENT_SIZE).take(ELEMENTS_PER_PAGE) {
+            // Can't fail, the buffer is empty and exactly one page large
+            let bytes = chunk.try_into().map_err(|_| CE::Impossible)?;
+            self.buf.push(DecimalFixed::from_le_bytes(bytes)).map_err(|_| CE::Impossible)?;
+        }
+        // Only now that it's in RAM, so that an error leaves the page in place
+        self.pages.pop();
+        Ok(())
+    }
+}
+
+impl SpillStore<DecimalFixed> for FlashSpill {
+    fn len(&self) -> usize {
+        self.pages.len() * ELEMENTS_PER_PAGE + self.buf.len()
+    }
+
+    fn spill(&mut self, value: &DecimalFixed) -> Result<(), CustomError> {
+        if self.buf.is_full() {
+            self.flush_page()?;
+        }
+        self.buf.push(*value).map_err(|_| CE::Impossible) // We just made space for it
+    }
+
+    fn unspill(&mut self) -> Result<Option<DecimalFixed>, CustomError> {
+        if self.buf.is_empty() {
+            self.load_page()?;
+        }
+        Ok(self.buf.pop())
+    }
+
+    fn clear(&mut self) {
+        // We don't erase anything, the sectors get erased lazily once we get to them again
+        self.buf.clear();
+        self.pages.clear();
+    }
+}
@@ -0,0 +1,166 @@
+use display_interface::{DataFormat, DisplayError, WriteOnlyDataCommand};
+use embedded_graphics::{
+    prelude::*,
+    pixelcolor::Gray4,
+};
+
+use crate::display::{FlushableDisplay, Panel};
+use crate::custom_error::CustomError; // Because we already have the `mod` in `main.rs`
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+pub const WIDTH: u32 = 128;
+pub const HEIGHT: u32 = 128;
+/// Bytes per row of the frame buffer, two pixels per byte
+const ROW_BYTES: usize = (WIDTH / 2) as usize;
+const BUFFER_SIZE: usize = ROW_BYTES * HEIGHT as usize;
+
+const SET_COLUMN_ADDRESS: u8 = 0x15;
+const SET_ROW_ADDRESS: u8 = 0x75;
+const SET_CONTRAST: u8 = 0x81;
+const NORMAL_DISPLAY: u8 = 0xA4;
+const INVERSE_DISPLAY: u8 = 0xA7;
+const DISPLAY_OFF: u8 = 0xAE;
+const DISPLAY_ON: u8 = 0xAF;
+/// The commands (with their arguments) setting up a 128x128 module, the same ones Adafruit's driver sends.
+/// The display stays off, so that the garbage in its memory doesn't flash up before the first flush.
+const INIT_SEQUENCE: &[u8] = &[
+    0xFD, 0x12, // Unlock the commands
+    DISPLAY_OFF,
+    SET_CONTRAST, 0x80,
+    0xA0, 0x51, // Remap: column addresses and COMs, so that the even pixels are in the high nibbles and row 0 is at the top
+    0xA1, 0x00, // Start line
+    0xA2, 0x00, // Display offset
+    NORMAL_DISPLAY,
+    0xA8, 0x7F, // Multiplex ratio, all 128 rows
+    0xB1, 0x11, // Phase lengths
+    0xB3, 0x00, // Clock divider and oscillator frequency
+    0xAB, 0x01, // Internal VDD regulator
+    0xB6, 0x04, // Second pre-charge period
+    0xBE, 0x0F, // VCOMH voltage
+    0xBC, 0x08, // Pre-charge voltage
+    0xD5, 0x62, // Function selection B, enables the second pre-charge
+    0xB9, // The default linear gray scale table
+];
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// A bare-bones buffered driver for the SSD1327, a 128x128 OLED with 16 shades of gray.
+///
+/// The drawing goes into a frame buffer in RAM, `flush()` then sends only the rows that changed since the last one,
+/// since the whole buffer takes about 200 ms over 400 kHz I²C.
+pub struct Ssd1327<DI: WriteOnlyDataCommand> {
+    iface: DI,
+    buffer: [u8; BUFFER_SIZE],
+    /// The first and last row changed since the last flush, `None` if nothing changed
+    dirty_rows: Option<(u8, u8)>,
+}
+
+impl<DI: WriteOnlyDataCommand> Ssd1327<DI> {
+    pub fn new(iface: DI) -> Self {
+        Ssd1327 {
+            iface,
+            buffer: [0; BUFFER_SIZE],
+            dirty_rows: None,
+        }
+    }
+
+    /// Sets the display up, clears it and turns it on.
+    pub fn init(&mut self) -> Result<(), DisplayError> {
+        self.iface.send_commands(DataFormat::U8(INIT_SEQUENCE))?;
+        self.buffer.fill(0);
+        self.dirty_rows = Some((0, (HEIGHT - 1) as u8));
+        self.flush()?;
+        self.iface.send_commands(DataFormat::U8(&[DISPLAY_ON]))
+    }
+
+    /// Sends the rows changed since the last flush to the display.
+    /// If it fails, they stay marked as changed, so that the next flush tries again.
+    pub fn flush(&mut self) -> Result<(), DisplayError> {
+        let Some((first, last)) = self.dirty_rows else {
+            return Ok(());
+        };
+        // The columns are addressed by pairs of pixels, i.e. by bytes
+        self.iface.send_commands(DataFormat::U8(&[
+            SET_COLUMN_ADDRESS, 0, (ROW_BYTES - 1) as u8,
+            SET_ROW_ADDRESS, first, last,
+        ]))?;
+        self.iface.send_data(DataFormat::U8(&self.buffer[(first as usize * ROW_BYTES)..((last as usize + 1) * ROW_BYTES)]))?;
+        self.dirty_rows = None;
+        Ok(())
+    }
+}
+
+impl<DI: WriteOnlyDataCommand> OriginDimensions for Ssd1327<DI> {
+    fn size(&self) -> Size {
+        Size::new(WIDTH, HEIGHT)
+    }
+}
+
+impl<DI: WriteOnlyDataCommand> DrawTarget for Ssd1327<DI> {
+    type Color = Gray4;
+    type Error = DisplayError;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            // Whatever's off the display is silently dropped, the same as the SSD1306 driver does
+            let (Ok(x), Ok(y)) = (u32::try_from(point.x), u32::try_from(point.y)) else { continue };
+            if x >= WIDTH || y >= HEIGHT {
+                continue;
+            }
+
+            let byte = &mut self.buffer[y as usize * ROW_BYTES + x as usize / 2];
+            *byte = if x % 2 == 0 {
+                (*byte & 0x0F) | (color.luma() << 4)
+            } else {
+                (*byte & 0xF0) | color.luma()
+            };
+            let y = y as u8; // Fits, it's less than the height
+            self.dirty_rows = Some(self.dirty_rows.map_or((y, y), |(first, last)| (first.min(y), last.max(y))));
+        }
+        Ok(())
+    }
+}
+
+impl<DI: WriteOnlyDataCommand> FlushableDisplay for Ssd1327<DI> {
+    fn flush_display(&mut self) -> Result<(), CustomError> {
+        self.flush()?;
+        Ok(())
+    }
+}
+
+impl<DI: WriteOnlyDataCommand> Panel for Ssd1327<DI> {
+    fn set_contrast(&mut self, contrast: u8) -> Result<(), CustomError> {
+        self.iface.send_commands(DataFormat::U8(&[SET_CONTRAST, contrast]))?;
+        Ok(())
+    }
+
+    fn set_inverted(&mut self, inverted: bool) -> Result<(), CustomError> {
+        self.iface.send_commands(DataFormat::U8(&[if inverted { INVERSE_DISPLAY } else { NORMAL_DISPLAY }]))?;
+        Ok(())
+    }
+
+    fn set_on(&mut self, on: bool) -> Result<(), CustomError> {
+        self.iface.send_commands(DataFormat::U8(&[if on { DISPLAY_ON } else { DISPLAY_OFF }]))?;
+        Ok(())
+    }
+
+    fn reinit(&mut self) -> Result<(), CustomError> {
+        self.init()?;
+        Ok(())
+    }
+
+    fn pixel(&self, point: Point) -> Option<Self::Color> {
+        let (Ok(x), Ok(y)) = (u32::try_from(point.x), u32::try_from(point.y)) else { return None };
+        if x >= WIDTH || y >= HEIGHT {
+            return None;
+        }
+
+        let byte = self.buffer[y as usize * ROW_BYTES + x as usize / 2];
+        Some(Gray4::new(if x % 2 == 0 { byte >> 4 } else { byte & 0x0F }))
+    }
+}
@@ -1,6 +1,5 @@
 use embedded_graphics::{
     prelude::*,
-    pixelcolor::BinaryColor,
 
     mono_font::{
 //        ascii::FONT_6X12,
@@ -8,6 +7,7 @@ use embedded_graphics::{
         MonoTextStyle
     },
     text::{
+        Alignment,
         Baseline,
         Text,
     },
@@ -18,33 +18,34 @@ use embedded_graphics::{
         Rectangle,
     },
 };
-use ssd1306::{
-    Ssd1306,
-    prelude::*,
-    mode::BufferedGraphicsMode,
-};
-
 use heapless::{Vec, String};
 use core::{
-    cell::RefCell,
+    cell::{Cell, RefCell},
     cmp::min,
-    fmt::Write,
+    fmt::{Display, LowerExp, Write},
 };
 
-// Possibly gate this behind a defmt feature flag if we move this into a library crate
-use defmt::trace; // For logging in `draw()` (nowhere else)
 
 use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
     CustomError,
     CE // Short type alias
 };
 use crate::textbox::DisplayDimensions;
+use crate::display::{FlushableDisplay, Palette};
+use crate::decfix::DecimalFixed;
+use crate::spill::SpillStore;
+use crate::bigdigits::BigDigits;
 
 // ------------------------------------------------------------------------------------------------------------------------------------------------
 
 // Compile time constants
 /// Maximum size of the stack
 const MAX_STACK_SIZE: usize = 256;
+/// How many elements to read back from the spill store at once when we run out of them in RAM,
+/// so that we don't have to spill them again right after the next push
+const REFILL_SIZE: usize = MAX_STACK_SIZE / 2;
+/// Maximum length of an element's label in bytes, see `CustomStack::set_label()`
+pub const LABEL_SIZE: usize = 8;
 /** The fonts we use usually have unused pixels at the top that'd waste space,
 so with this constant we basically cut off the top `n` pixels.
 
@@ -61,52 +62,201 @@ If we had used i128-s (and didn't do fixed-point arithmetics with them),
 we'd've needed at most 40 bytes (the length of i128::MIN in decimal representation),
 but that'd long overflow the display, so who cares? :D */
 const TEXT_BUFFER_SIZE: usize = 32;
+/// Frames of the slide animation when pushing and popping, the last one being the usual drawing; see `CustomStack::set_animation()`
+const ANIMATION_FRAMES: u32 = 3;
 // ------------------------------------------------------------------------------------------------------------------------------------------------
 
-pub struct CustomStackBuilder<'a> {
+/// A short annotation of a stack element, e.g. "Vcc" or "R1". Empty means no label.
+pub type Label = String<LABEL_SIZE>;
+
+/// What to do when pushing onto a full stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Refuse the push and return `CapacityError`
+    #[default] Reject,
+    /// Discard the bottommost (oldest) elements to make room, so that pushing never fails
+    /// (unless pushing more elements at once than the whole stack can hold)
+    DropBottom,
+    /// Move the bottommost elements into the spill store (see `CustomStack::set_spill_store()`),
+    /// and read them back once the stack is popped down to them.
+    /// Acts like `Reject` if there's no spill store, or it's full.
+    Spill,
+}
+
+/// How the values are formatted when drawing, see `CustomStack::set_number_format()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, defmt::Format)]
+pub enum NumberFormat {
+    /// All the significant digits, i.e. plain `Display`
+    #[default] Full,
+    /// Rounded to this many decimal places, e.g. `1.50` with 2
+    Fixed(usize),
+    /// Scientific notation with this many decimal places in the mantissa, e.g. `1.50e3` with 2
+    Scientific(usize),
+}
+
+/// A change that happened to the stack, as reported to a `StackObserver`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum StackEvent {
+    /// This many elements were pushed on top
+    Pushed(usize),
+    /// This many elements were popped off the top
+    Popped(usize),
+    /// This many of the bottommost elements were discarded to make room, see `OverflowPolicy::DropBottom`
+    DroppedBottom(usize),
+    /// The elements were reordered or modified in place, but their count stayed the same
+    Modified,
+    /// This many of the bottommost elements were moved into the spill store, see `OverflowPolicy::Spill`
+    Spilled(usize),
+    /// This many elements were read back from the spill store to the bottom
+    Unspilled(usize),
+    /// The whole stack was emptied
+    Cleared,
+}
+
+/// How the elements moved since the last drawing, so that the slide animation knows where they came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct Motion {
+    /// How many of the elements there were before got popped, net of the ones pushed back over them in the meantime
+    popped: usize,
+    /// How many of the elements on top got pushed since
+    pushed: usize,
+    /// Something besides pushing and popping happened, e.g. clearing the stack, so there's nothing to animate
+    jumbled: bool,
+}
+
+impl Motion {
+    fn record(&mut self, event: StackEvent) {
+        match event {
+            StackEvent::Pushed(n) => self.pushed = self.pushed.saturating_add(n),
+            StackEvent::Popped(n) => {
+                // The ones pushed since go first, then the ones that were there before
+                self.popped = self.popped.saturating_add(n.saturating_sub(self.pushed));
+                self.pushed = self.pushed.saturating_sub(n);
+            },
+            StackEvent::Modified => {}, // The values changed in place, nothing moved
+            StackEvent::DroppedBottom(_) | StackEvent::Spilled(_) | StackEvent::Unspilled(_) | StackEvent::Cleared => self.jumbled = true,
+        }
+    }
+}
+
+/// Something that wants to know about every change to a stack, e.g. for tracing or an undo journal.
+///
+/// It only takes `&self`, so that it can be shared by multiple stacks (see `StackSet`);
+/// implementors that need to keep state have to use a `Cell` or `RefCell` themselves.
+pub trait StackObserver {
+    fn on_change(&self, event: StackEvent);
+}
+
+/// An observer that logs every change at the trace level.
+pub struct TraceObserver;
+
+impl StackObserver for TraceObserver {
+    fn on_change(&self, event: StackEvent) {
+        log_trace!("Stack changed: {:?}", event);
+    }
+}
+
+#[derive(Clone, Copy)] // So that one builder can build multiple stacks, see `StackSet`
+pub struct CustomStackBuilder<'a, C: Palette> {
     disp_dimensions: DisplayDimensions,
-    character_style: MonoTextStyle<'a, BinaryColor>,
-    primitives_style: PrimitiveStyle<BinaryColor>,
+    character_style: MonoTextStyle<'a, C>,
+    primitives_style: PrimitiveStyle<C>,
+    gutter: bool,
+    alignment: Alignment,
+    highlight_top: bool,
+    overflow_policy: OverflowPolicy,
+    observer: Option<&'a dyn StackObserver>,
 }
 
 #[allow(dead_code)]
-impl<'a> CustomStackBuilder<'a> {
+impl<'a, C: Palette> CustomStackBuilder<'a, C> {
     /// Creates a new `CustomStackBuilder` with the default display dimensions of 128x64 pixels
     /// and the default text style.
     /// For custom parameters, use the builder pattern.
     pub const fn new() -> Self {
-        CustomStackBuilder::<'a> {
+        CustomStackBuilder::<'a, C> {
             disp_dimensions: DisplayDimensions::const_default(),
 
             // Standard white text on (by default) transparent background
-            character_style: MonoTextStyle::new(&ISO_FONT_6X12, BinaryColor::On),
+            character_style: MonoTextStyle::new(&ISO_FONT_6X12, C::FOREGROUND),
 
             // Standard black stroke and fill (i.e. all black, effectively erasing anything drawn below it)
             primitives_style: PrimitiveStyleBuilder::new()
-                .stroke_color(BinaryColor::Off)
-                .fill_color(BinaryColor::Off)
+                .stroke_color(C::BACKGROUND)
+                .fill_color(C::BACKGROUND)
                 .build(),
+
+            gutter: false,
+            alignment: Alignment::Left,
+            highlight_top: false,
+            overflow_policy: OverflowPolicy::Reject,
+            observer: None,
         }
     }
 
     /// Build the builder pattern into a finished struct, copying currently set parameters,
     /// initialising empty ones and storing the RefCell provided as a parameter.
-    pub fn build<T, DI, SIZE>(
+    pub fn build<T, D>(
         self,
-        display_refcell: &'a RefCell<Ssd1306<DI, SIZE, BufferedGraphicsMode<SIZE>>>
-    ) -> CustomStack<'a, T, DI, SIZE>
+        display_refcell: &'a RefCell<D>
+    ) -> CustomStack<'a, T, D>
     where
-        DI: WriteOnlyDataCommand,
-        SIZE: DisplaySize,
+        D: FlushableDisplay<Color = C>,
     {
         CustomStack {
             data: Vec::new(), // The <T, MAX_STACK_SIZE> is inferred from the type parameters in the struct definition
+            labels: Vec::new(),
+            scroll_offset: 0,
+            dirty: Cell::new(true), // Nothing was drawn yet
 
             disp_dimensions: self.disp_dimensions,
-            display_refcell,
+            display_refcell: Some(display_refcell),
 
             character_style: self.character_style,
             primitives_style: self.primitives_style,
+            gutter: self.gutter,
+            alignment: self.alignment,
+            highlight_top: self.highlight_top,
+            number_format: NumberFormat::Full,
+            digit_grouping: false,
+            big_digits: false,
+            animation: false,
+            motion: Cell::new(Motion::default()),
+            overflow_policy: self.overflow_policy,
+            observer: self.observer,
+            spill_store: None, // It's generic over T, so it can't come from the builder
+        }
+    }
+
+    /// Like `build()`, but without any display, making `draw()` a no-op.
+    /// Meant for running the data structure logic on the host (e.g. in tests), where there's no display to draw onto;
+    /// `D` is then usually `display::NullDisplay`.
+    pub fn build_headless<T, D>(self) -> CustomStack<'a, T, D>
+    where
+        D: FlushableDisplay<Color = C>,
+    {
+        CustomStack {
+            data: Vec::new(),
+            labels: Vec::new(),
+            scroll_offset: 0,
+            dirty: Cell::new(true),
+
+            disp_dimensions: self.disp_dimensions,
+            display_refcell: None,
+
+            character_style: self.character_style,
+            primitives_style: self.primitives_style,
+            gutter: self.gutter,
+            alignment: self.alignment,
+            highlight_top: self.highlight_top,
+            number_format: NumberFormat::Full,
+            digit_grouping: false,
+            big_digits: false,
+            animation: false,
+            motion: Cell::new(Motion::default()),
+            overflow_policy: self.overflow_policy,
+            observer: self.observer,
+            spill_store: None, // It's generic over T, so it can't come from the builder
         }
     }
 
@@ -116,47 +266,238 @@ impl<'a> CustomStackBuilder<'a> {
         self
     }
 
-    pub const fn set_character_style(mut self, character_style: MonoTextStyle<'a, BinaryColor>) -> Self {
+    pub const fn set_character_style(mut self, character_style: MonoTextStyle<'a, C>) -> Self {
         self.character_style = character_style;
         self
     }
 
-    pub const fn set_primitives_style(mut self, primitives_style: PrimitiveStyle<BinaryColor>) -> Self {
+    pub const fn set_primitives_style(mut self, primitives_style: PrimitiveStyle<C>) -> Self {
         self.primitives_style = primitives_style;
         self
     }
+
+    /// Whether to draw a gutter with the stack level numbers (`1:` being the top) before each element.
+    /// If there are more elements above or below the visible ones, the colon on the outermost line
+    /// is replaced with a `^` or `v` marker respectively.
+    pub const fn set_gutter(mut self, gutter: bool) -> Self {
+        self.gutter = gutter;
+        self
+    }
+
+    /// Sets the horizontal alignment of the values (the gutter stays on the left no matter what).
+    /// Right alignment is the most readable for columns of numbers.
+    pub const fn set_alignment(mut self, alignment: Alignment) -> Self {
+        self.alignment = alignment;
+        self
+    }
+
+    /// Whether to draw the topmost element (the "X register") inverted, so that it's always obvious.
+    pub const fn set_highlight_top(mut self, highlight_top: bool) -> Self {
+        self.highlight_top = highlight_top;
+        self
+    }
+
+    /// Sets what happens when pushing onto a full stack, see `OverflowPolicy`.
+    pub const fn set_overflow_policy(mut self, overflow_policy: OverflowPolicy) -> Self {
+        self.overflow_policy = overflow_policy;
+        self
+    }
+
+    /// Sets the observer that gets notified of every change to the stack, see `StackObserver`.
+    pub const fn set_observer(mut self, observer: &'a dyn StackObserver) -> Self {
+        self.observer = Some(observer);
+        self
+    }
 }
 
 // ------------------------------------------------------------------------------------------------------------------------------------------------
 
 #[allow(dead_code)]
-pub struct CustomStack<'a, T, DI, SIZE>
+pub struct CustomStack<'a, T, D>
 where
-    DI: WriteOnlyDataCommand,
-    SIZE: DisplaySize,
+    D: FlushableDisplay,
 {
     data: Vec<T, MAX_STACK_SIZE>,
+    /// Labels of the elements, always exactly as long as `data`
+    labels: Vec<Label, MAX_STACK_SIZE>,
+    /// How many of the topmost elements are scrolled out of view (below the bottom of the stack area)
+    scroll_offset: usize,
+    /// Whether anything changed since the last `draw()`, so that we can skip redundant redraws.
+    /// It's a Cell, because `draw()` only takes `&self`.
+    dirty: Cell<bool>,
 
     disp_dimensions: DisplayDimensions,
-    display_refcell: &'a RefCell<Ssd1306<DI, SIZE, BufferedGraphicsMode<SIZE>>>,
+    /// `None` if the stack was built headless, see `CustomStackBuilder::build_headless()`
+    display_refcell: Option<&'a RefCell<D>>,
 
-    character_style: MonoTextStyle<'a, BinaryColor>,
-    primitives_style: PrimitiveStyle<BinaryColor>,
+    character_style: MonoTextStyle<'a, D::Color>,
+    primitives_style: PrimitiveStyle<D::Color>,
+    gutter: bool,
+    alignment: Alignment,
+    highlight_top: bool,
+    number_format: NumberFormat,
+    /// Whether the whole parts are grouped by thousands, see `set_digit_grouping()`
+    digit_grouping: bool,
+    /// Whether the top element is drawn alone in large digits, see `set_big_digits()`
+    big_digits: bool,
+    /// Whether pushing and popping slides the elements into place, see `set_animation()`
+    animation: bool,
+    /// Since the last `draw()`, for the slide animation. A Cell for the same reason as `dirty`.
+    motion: Cell<Motion>,
+    overflow_policy: OverflowPolicy,
+    observer: Option<&'a dyn StackObserver>,
+    /// Where the bottommost elements go with `OverflowPolicy::Spill`
+    spill_store: Option<&'a RefCell<dyn SpillStore<T> + 'a>>,
 }
 
 #[allow(dead_code)]
-impl<'a, T, DI, SIZE> CustomStack<'a, T, DI, SIZE>
+impl<'a, T, D> CustomStack<'a, T, D>
 where
-    DI: WriteOnlyDataCommand,
-    SIZE: DisplaySize,
+    D: FlushableDisplay,
 {
+    /// Marks the stack for redrawing and notifies the observer, if there's any.
+    /// Every method that changes the data has to call this.
+    fn changed(&self, event: StackEvent) {
+        self.dirty.set(true);
+        let mut motion = self.motion.get();
+        motion.record(event);
+        self.motion.set(motion);
+        if let Some(observer) = self.observer {
+            observer.on_change(event);
+        }
+    }
+
+    /// Adds empty labels for the `n` elements that were just pushed on top, to keep `labels` as long as `data`.
+    fn push_empty_labels(&mut self, n: usize) {
+        for _ in 0..n {
+            let _ = self.labels.push(Label::new()); // Can't fail, it has the same capacity as `data`
+        }
+    }
+
+    /// Makes sure there's space for `n` more elements, according to the overflow policy.
+    /// Returns `CapacityError` if there isn't and we're not allowed to (or can't) make it.
+    fn make_room(&mut self, n: usize) -> Result<(), CustomError> {
+        let needed = (self.data.len() + n).saturating_sub(MAX_STACK_SIZE);
+        if needed == 0 {
+            return Ok(());
+        }
+
+        match self.overflow_policy {
+            OverflowPolicy::Reject => Err(CE::CapacityError),
+            OverflowPolicy::DropBottom if n > MAX_STACK_SIZE => Err(CE::CapacityError),
+            OverflowPolicy::DropBottom => {
+                self.data.drain(..needed); // Dropping the iterator removes them
+                self.labels.drain(..needed);
+                self.changed(StackEvent::DroppedBottom(needed));
+                Ok(())
+            }
+            OverflowPolicy::Spill if n > MAX_STACK_SIZE => Err(CE::CapacityError),
+            OverflowPolicy::Spill => {
+                let Some(store) = self.spill_store else {
+                    return Err(CE::CapacityError);
+                };
+                let mut store = store.borrow_mut();
+
+                // Bottom first, so that the bottommost one ends up deepest in the store
+                let mut spilled = 0;
+                let mut result = Ok(());
+                for x in &self.data[..needed] {
+                    if let Err(e) = store.spill(x) {
+                        result = Err(e);
+                        break;
+                    }
+                    spilled += 1;
+                }
+
+                // Whatever got spilled must be removed, even if we failed in the middle, or we'd have duplicates
+                self.data.drain(..spilled);
+                self.labels.drain(..spilled); // The labels don't get spilled, only the values
+                drop(store);
+                self.changed(StackEvent::Spilled(spilled));
+                result
+            }
+        }
+    }
+
+    /// Makes sure there's at least `n` elements in RAM if possible, reading them back from the spill store.
+    /// If it has to read, it reads at least `REFILL_SIZE` elements at once.
+    ///
+    /// Errors are only logged, the elements then simply stay in the store.
+    fn ensure_loaded(&mut self, n: usize) {
+        let Some(store) = self.spill_store else {
+            return;
+        };
+        if self.data.len() >= n {
+            return;
+        }
+        let mut store = store.borrow_mut();
+        if store.is_empty() {
+            return;
+        }
+
+        let count = min(
+            core::cmp::max(n - self.data.len(), REFILL_SIZE),
+            MAX_STACK_SIZE - self.data.len()
+        );
+
+        // They come top first, so we collect them aside, and then put the current contents on top of them
+        let mut loaded = Vec::<T, MAX_STACK_SIZE>::new();
+        while loaded.len() < count {
+            match store.unspill() {
+                Ok(Some(x)) => { let _ = loaded.push(x); }, // Can't fail, `count` is less than the capacity
+                Ok(None) => break,
+                Err(e) => {
+                    log_error!("Failed to read spilled elements back: {}", e);
+                    break;
+                }
+            }
+        }
+        drop(store);
+
+        let unspilled = loaded.len();
+        loaded.reverse();
+        // Can't overflow, we only loaded as many as there was free space
+        loaded.extend(self.data.drain(..));
+        self.data = loaded;
+
+        let mut labels = Vec::<Label, MAX_STACK_SIZE>::new();
+        let _ = labels.resize(unspilled, Label::new()); // Can't fail either, for the same reason
+        labels.extend(self.labels.drain(..));
+        self.labels = labels;
+        self.changed(StackEvent::Unspilled(unspilled));
+    }
+
+    /// Sets the store the bottommost elements are spilled into with `OverflowPolicy::Spill`.
+    ///
+    /// Only the elements in RAM can be accessed, e.g. by `peek_nth()` or `roll()`, and only they are counted by `len()`.
+    /// The spilled ones are read back automatically once they're popped down to.
+    pub fn set_spill_store(&mut self, store: &'a RefCell<dyn SpillStore<T> + 'a>) {
+        self.spill_store = Some(store);
+    }
+
+    /// Returns the number of elements in the spill store, see `set_spill_store()`.
+    pub fn spilled_len(&self) -> usize {
+        self.spill_store.map_or(0, |store| store.borrow().len())
+    }
+
     /// Pushes a value onto the stack.
     /// We need ownership of the value to push it onto the stack.
     /// 
     /// In Err we return a tuple including the value that was attempted to be pushed,
     /// so that the caller can decide what to do with it.
+    /// 
+    /// Pushing scrolls the view back to the top, so that the new value is visible.
+    /// What happens when the stack is full depends on the overflow policy.
     pub fn push(&mut self, value: T) -> Result<(), (CustomError, T)> {
-        self.data.push(value).map_err(|t| (CE::CapacityError, t))
+        if let Err(e) = self.make_room(1) {
+            return Err((e, value));
+        }
+
+        self.scroll_offset = 0;
+        self.data.push(value).map_err(|t| (CE::CapacityError, t))?;
+        self.push_empty_labels(1);
+        self.changed(StackEvent::Pushed(1));
+        Ok(())
     }
 
     /// Pushes multiple values onto the stack from any IntoIterator that gives us ownership of T.
@@ -170,17 +511,42 @@ where
     /// and the hint indicates that pushing all elements would overflow the stack,
     /// the method returns an error with the array that could not be pushed as second element of the tuple
     /// (returning ownership back), instead of (possibly) panicking.
-    pub fn push_iterator(&mut self, iter: impl Iterator<Item = T>, check_hint: bool)
+    /// 
+    /// With the `DropBottom` and `Spill` overflow policies, this never panics, since it makes room element by element.
+    pub fn push_iterator(&mut self, mut iter: impl Iterator<Item = T>, check_hint: bool)
     -> Result<(), (CustomError, impl IntoIterator<Item = T>)>
     {
-        if check_hint // If the caller wants us to check the size hint
-            && let Some(hint) = iter.size_hint().1 // If the iterator has an upper bound
-            && self.data.len() + hint > MAX_STACK_SIZE // If it would actually overflow the stack
-        {
-            return Err((CE::CapacityError, iter));
+        // Only a rejecting stack can't take what the hint says, the other policies make room element by element below.
+        // The room isn't made here, the hint may well overestimate and we'd drop or spill more than we push.
+        let free = MAX_STACK_SIZE - self.data.len();
+        let would_overflow = check_hint // If the caller wants us to check the size hint
+            && self.overflow_policy == OverflowPolicy::Reject
+            && iter.size_hint().1.is_some_and(|hint| hint > free); // If the iterator has an upper bound that doesn't fit
+        if would_overflow {
+            return Err((CE::CapacityError, None.into_iter().chain(iter)));
         }
 
-        self.data.extend(iter);
+        let mut pushed = 0;
+        if self.overflow_policy != OverflowPolicy::Reject {
+            while let Some(value) = iter.next() {
+                // With `DropBottom`, making room for a single element can't fail, and then the push can't fail either.
+                // With `Spill`, the store might get full, so we give the element back along with the rest.
+                if let Err(e) = self.make_room(1) {
+                    self.changed(StackEvent::Pushed(pushed));
+                    return Err((e, Some(value).into_iter().chain(iter)));
+                }
+                let _ = self.data.push(value);
+                // Right away, the next `make_room()` drains a label along with the bottom element
+                self.push_empty_labels(1);
+                pushed += 1;
+            }
+        } else {
+            let len_before = self.data.len();
+            self.data.extend(iter);
+            pushed = self.data.len() - len_before;
+            self.push_empty_labels(pushed);
+        }
+        self.changed(StackEvent::Pushed(pushed));
         Ok(())
     }
 
@@ -195,11 +561,14 @@ where
     -> Result<(), (CustomError, impl ExactSizeIterator<Item = T>)>
     {
         // Thanks to ExactSizeIterator, we can get the exact size of the iterator beforehand and check for overflow
-        if self.data.len() + iter.len() > MAX_STACK_SIZE {
-            return Err((CE::CapacityError, iter));
+        if let Err(e) = self.make_room(iter.len()) {
+            return Err((e, iter));
         }
 
+        let n = iter.len();
         self.data.extend(iter);
+        self.push_empty_labels(n);
+        self.changed(StackEvent::Pushed(n));
         Ok(())
     }
 
@@ -210,12 +579,14 @@ where
     /// 
     /// The last element of the array will be the topmost element of the stack.
     pub fn push_array<const N: usize>(&mut self, array: [T; N]) -> Result<(), (CustomError, [T; N])> {
-        if self.data.len() + N > MAX_STACK_SIZE {
-            return Err((CE::CapacityError, array));
+        if let Err(e) = self.make_room(N) {
+            return Err((e, array));
         }
 
         // SAFETY: We already checked that capacity is OK. Can't panic.
         self.data.extend(array); // Internally converts the array into an iterator
+        self.push_empty_labels(N);
+        self.changed(StackEvent::Pushed(N));
         Ok(())
     }
 
@@ -229,13 +600,21 @@ where
     pub fn push_slice(&mut self, slice: &[T]) -> Result<(), CustomError>
     where T: Clone // We need Clone here to be able to clone the slice elements (since we can't own the slice)
     {
-        self.data.extend_from_slice(slice).map_err(|_| CE::CapacityError)
+        self.make_room(slice.len())?;
+        self.data.extend_from_slice(slice).map_err(|_| CE::CapacityError)?;
+        self.push_empty_labels(slice.len());
+        self.changed(StackEvent::Pushed(slice.len()));
+        Ok(())
     }
 
     /// Pops a value from the stack.
     /// If the stack is empty, it returns `None`.
     pub fn pop(&mut self) -> Option<T> {
-        self.data.pop()
+        self.ensure_loaded(2); // So that there's still something on the top afterwards
+        let val = self.data.pop()?;
+        self.labels.pop();
+        self.changed(StackEvent::Popped(1));
+        Some(val)
     }
 
     /// Returns a double-ended iterator that yields up to `n` popped elements from the stack.
@@ -248,10 +627,15 @@ where
     /// (unless using `next_back()`), unlike `multipeek()`.
     pub fn multipop(&mut self, n: usize) -> Option<impl DoubleEndedIterator<Item = T>> {
     // See https://doc.rust-lang.org/stable/book/ch10-02-traits.html#returning-types-that-implement-traits for explanation of what we're returning here.
+        self.ensure_loaded(n.saturating_add(1)); // Same as in `pop()`
         if self.data.is_empty() {
             return None;
         }
 
+        // We notify beforehand, since we can't after returning the iterator; they get removed no matter what, though
+        self.changed(StackEvent::Popped(min(n, self.data.len())));
+        self.labels.truncate(self.data.len().saturating_sub(n));
+
         // The caller may not need to collect it into a Vec, so we return the iterator directly.
         // If the iterator is dropped before it's fully consumed, the data is still removed from the stack.
         // Thanks to saturating_sub, we don't have to check if n > len here.
@@ -267,6 +651,55 @@ where
         self.data.last()
     }
 
+    /// Returns the `n`-th element counted from the top (starting at zero) without removing it.
+    /// `peek_nth(0)` is the same as `peek()`. If there's not enough elements, it returns `None`.
+    pub fn peek_nth(&self, n: usize) -> Option<&T> {
+        // checked_sub, so that we don't underflow if n >= len
+        self.data.len().checked_sub(n + 1)
+            .map(|index| &self.data[index])
+    }
+
+    /// Swaps the `i`-th and `j`-th elements, both counted from the top (starting at zero).
+    /// `swap_at(0, 1)` swaps the top two elements. Returns `BadInput` if there's not enough elements.
+    pub fn swap_at(&mut self, i: usize, j: usize) -> Result<(), CustomError> {
+        let len = self.data.len();
+        if i >= len || j >= len {
+            return Err(CE::BadInput);
+        }
+
+        self.data.swap(len - 1 - i, len - 1 - j);
+        self.labels.swap(len - 1 - i, len - 1 - j);
+        self.changed(StackEvent::Modified);
+        Ok(())
+    }
+
+    /// Applies `f` to each of the top `n` elements in place, from the topmost one downwards.
+    /// Returns `BadInput` if there's less than `n` elements.
+    ///
+    /// If `f` fails on any element, the error is returned and all the elements are restored
+    /// to what they were before, so that we never leave the stack half-modified.
+    pub fn apply_top_n(&mut self, n: usize, mut f: impl FnMut(&mut T) -> Result<(), CustomError>) -> Result<(), CustomError>
+    where T: Clone // For the backup
+    {
+        let len = self.data.len();
+        if n > len {
+            return Err(CE::BadInput);
+        }
+
+        // Can't fail, it can't be bigger than the stack itself
+        let backup = Vec::<T, MAX_STACK_SIZE>::from_slice(&self.data[(len - n)..])
+            .map_err(|_| CE::Impossible)?;
+
+        for i in (0..n).rev() { // From the top down
+            if let Err(e) = f(&mut self.data[len - n + i]) {
+                self.data[(len - n)..].clone_from_slice(&backup);
+                return Err(e);
+            }
+        }
+        self.changed(StackEvent::Modified);
+        Ok(())
+    }
+
     /// Returns the last `n` values pushed onto the stack without removing them as a slice.
     /// If `n` is greater than the stack size, it returns the entire stack as a slice.
     /// If the stack is empty, it returns an empty slice.
@@ -278,9 +711,120 @@ where
         // even returning an empty slice if the stack is empty (desirable).
     }
 
+    /// Pushes a copy of the second topmost element onto the stack (Forth's `OVER`: `a b -- a b a`).
+    /// Equivalent to `pick(1)`.
+    pub fn over(&mut self) -> Result<(), CustomError>
+    where T: Clone
+    {
+        self.pick(1)
+    }
+
+    /// Moves the third topmost element to the top of the stack (Forth's `ROT`: `a b c -- b c a`).
+    /// Equivalent to `roll(2)`.
+    pub fn rot(&mut self) -> Result<(), CustomError> {
+        self.roll(2)
+    }
+
+    /// Pushes a copy of the `n`-th element (counted from the top, starting at zero) onto the stack.
+    /// `pick(0)` duplicates the topmost element, `pick(1)` is the same as `over()`.
+    ///
+    /// Returns `BadInput` if there are not enough elements, or `CapacityError` if the stack is full.
+    pub fn pick(&mut self, n: usize) -> Result<(), CustomError>
+    where T: Clone
+    {
+        let val = self.peek_nth(n).ok_or(CE::BadInput)?.clone();
+        self.push(val).map_err(|(e, _)| e) // We drop the returned value, it's only a clone anyway
+    }
+
+    /// Moves the `n`-th element (counted from the top, starting at zero) to the top of the stack,
+    /// shifting the elements above it down by one.
+    /// `roll(0)` does nothing, `roll(1)` is a swap and `roll(2)` is the same as `rot()`.
+    ///
+    /// Returns `BadInput` if there are not enough elements.
+    pub fn roll(&mut self, n: usize) -> Result<(), CustomError> {
+        if n >= self.data.len() {
+            return Err(CE::BadInput);
+        }
+
+        // Removing shifts the rest down, and then we push it back on top.
+        // The push can't fail, because we just made space for it.
+        // We don't use `self.push()`, since to an observer it's a reordering, not a push.
+        let val = self.data.remove(self.data.len() - 1 - n);
+        let label = self.labels.remove(self.labels.len() - 1 - n);
+        self.scroll_offset = 0;
+        self.data.push(val).map_err(|_| CE::Impossible)?;
+        self.labels.push(label).map_err(|_| CE::Impossible)?;
+        self.changed(StackEvent::Modified);
+        Ok(())
+    }
+
+    /// Sorts the stack in ascending order, so that the biggest element ends up on the top.
+    /// The sort is unstable (equal elements may be reordered), since the stable one needs an allocator.
+    ///
+    /// The labels move along with their elements, but then we have to fall back to a slow insertion sort.
+    pub fn sort(&mut self)
+    where T: Ord
+    {
+        if self.labels.iter().all(|label| label.is_empty()) {
+            self.data.sort_unstable();
+        } else {
+            // At most 256 elements, so quadratic time is still fine
+            for i in 1..self.data.len() {
+                let mut j = i;
+                while j > 0 && self.data[j - 1] > self.data[j] {
+                    self.data.swap(j - 1, j);
+                    self.labels.swap(j - 1, j);
+                    j -= 1;
+                }
+            }
+        }
+        self.changed(StackEvent::Modified);
+    }
+
+    /// Reverses the order of the stack, so that the topmost element ends up on the bottom and vice versa.
+    pub fn reverse(&mut self) {
+        self.data.reverse();
+        self.labels.reverse();
+        self.changed(StackEvent::Modified);
+    }
+
+    /// Attaches a label to the `n`-th element counted from the top (starting at zero), replacing any previous one.
+    /// An empty label removes it. Returns `BadInput` if there's not enough elements,
+    /// or `CapacityError` if the label is longer than `LABEL_SIZE` bytes.
+    ///
+    /// Labels stick to their elements when shuffling the stack, but are lost when they're spilled.
+    pub fn set_label(&mut self, n: usize, label: &str) -> Result<(), CustomError> {
+        let index = self.labels.len().checked_sub(n + 1).ok_or(CE::BadInput)?;
+        self.labels[index] = Label::try_from(label)?;
+        self.changed(StackEvent::Modified);
+        Ok(())
+    }
+
+    /// Returns the label of the `n`-th element counted from the top (starting at zero), if there's any.
+    pub fn label(&self, n: usize) -> Option<&str> {
+        self.labels.len().checked_sub(n + 1)
+            .map(|index| self.labels[index].as_str())
+            .filter(|label| !label.is_empty())
+    }
+
+    /// Returns an iterator over the elements, from the bottom of the stack to the top.
+    pub fn iter(&self) -> core::slice::Iter<'_, T> {
+        self.data.iter()
+    }
+
+    /// Returns an iterator over the elements, from the top of the stack to the bottom.
+    pub fn iter_from_top(&self) -> core::iter::Rev<core::slice::Iter<'_, T>> {
+        self.data.iter().rev()
+    }
+
     /// Clears the entire stack.
     pub fn clear(&mut self) {
         self.data.clear();
+        self.labels.clear();
+        if let Some(store) = self.spill_store {
+            store.borrow_mut().clear();
+        }
+        self.changed(StackEvent::Cleared);
     }
 
     pub fn len(&self) -> usize {
@@ -290,73 +834,808 @@ where
     pub fn is_empty(&self) -> bool {
         self.data.is_empty()
     }
+
+    /// Returns how many stack lines fit on the display, leaving space for the textbox at the bottom.
+    pub fn visible_lines(&self) -> usize {
+        let text_height = self.character_style.font.character_size.height - PIXELS_REMOVED;
+        ((self.disp_dimensions.height / text_height) as usize) // Integer division: always rounded down (desirable here)
+            .saturating_sub(1) // -1 because we want to leave space for the bottom line; saturating so that tiny displays don't panic
+    }
+
+    /// Returns how many of the topmost elements are currently scrolled out of view.
+    pub fn scroll_offset(&self) -> usize {
+        self.scroll_offset
+    }
+
+    /// Sets the scroll offset, i.e. how many of the topmost elements are hidden to reveal the deeper ones.
+    /// The offset is clamped so that the view never scrolls past the bottom of the stack.
+    pub fn set_scroll(&mut self, offset: usize) {
+        self.dirty.set(true);
+        self.scroll_offset = min(
+            offset,
+            self.data.len().saturating_sub(self.visible_lines())
+        );
+    }
+
+    /// Scrolls the view `n` elements deeper into the stack (upwards on the display).
+    pub fn scroll_up(&mut self, n: usize) {
+        self.set_scroll(self.scroll_offset.saturating_add(n));
+    }
+
+    /// Scrolls the view `n` elements back towards the top of the stack (downwards on the display).
+    pub fn scroll_down(&mut self, n: usize) {
+        self.set_scroll(self.scroll_offset.saturating_sub(n));
+    }
     
+    /// Forces the next `draw()` to actually redraw, e.g. when something else has drawn over the stack's area.
+    pub fn invalidate(&self) {
+        self.dirty.set(true);
+    }
+
+    /// Sets how the values are formatted when drawing, see `NumberFormat`. It doesn't change the values themselves.
+    pub fn set_number_format(&mut self, number_format: NumberFormat) {
+        self.number_format = number_format;
+        self.dirty.set(true); // Only the looks changed, so we don't bother the observer
+    }
+
+    pub fn number_format(&self) -> NumberFormat {
+        self.number_format
+    }
+
+    /// Whether to group the whole parts of the values by thousands when drawing, e.g. `1 234 567.89`.
+    /// It's the alternate flag of `Display` (i.e. `{:#}`), so `T` has to support it; the scientific notation stays ungrouped.
+    pub fn set_digit_grouping(&mut self, digit_grouping: bool) {
+        self.digit_grouping = digit_grouping;
+        self.dirty.set(true); // Only the looks changed, so we don't bother the observer
+    }
+
+    pub fn digit_grouping(&self) -> bool {
+        self.digit_grouping
+    }
+
+    /// Whether to draw just the top element, in seven-segment digits as large as fit into the stack's area (see `BigDigits`),
+    /// so that it can be read from afar. A value too long even for the smallest ones, or a scrolled stack, is drawn the usual way.
+    pub fn set_big_digits(&mut self, big_digits: bool) {
+        self.big_digits = big_digits;
+        self.dirty.set(true); // Only the looks changed, so we don't bother the observer
+    }
+
+    pub fn big_digits(&self) -> bool {
+        self.big_digits
+    }
+
+    /// Whether pushing and popping slides the elements into place over a few frames (see `animate()`), so that it's easier
+    /// to follow where they went. The new ones come up from the textbox, the ones already there move by whole lines.
+    pub fn set_animation(&mut self, animation: bool) {
+        self.animation = animation;
+    }
+
+    pub fn animation(&self) -> bool {
+        self.animation
+    }
+
+    /// Formats the value by the number format and the digit grouping, into a writer that never fails, see `TruncatingWriter`.
+    fn write_value<const N: usize>(&self, writer: &mut TruncatingWriter<'_, N>, value: &T) -> core::fmt::Result
+    where
+        T: Display + LowerExp,
+    {
+        match (self.number_format, self.digit_grouping) {
+            (NumberFormat::Full, false) => core::write!(writer, "{}", value),
+            (NumberFormat::Full, true) => core::write!(writer, "{:#}", value),
+            (NumberFormat::Fixed(places), false) => core::write!(writer, "{:.places$}", value),
+            (NumberFormat::Fixed(places), true) => core::write!(writer, "{:#.places$}", value),
+            (NumberFormat::Scientific(places), _) => core::write!(writer, "{:.places$e}", value),
+        }
+    }
+
+    /// Draws the top element in large digits right-aligned into the area, below a line left for its label and the indicators.
+    /// Returns false without drawing anything if it doesn't fit.
+    fn draw_big(&self, display: &mut D, area: Rectangle, line_height: u32) -> Result<bool, CustomError>
+    where
+        T: Display + LowerExp,
+        CustomError: From<D::Error>,
+    {
+        let (Some(top), Some(label)) = (self.data.last(), self.labels.last()) else {
+            return Ok(false);
+        };
+        let mut buf = String::<TEXT_BUFFER_SIZE>::new();
+        let mut writer = TruncatingWriter { buf: &mut buf, truncated: false };
+        self.write_value(&mut writer, top)?;
+        if writer.truncated {
+            return Ok(false);
+        }
+
+        let area = Rectangle::new(
+            area.top_left + Point::new(0, line_height as i32),
+            Size::new(area.size.width, area.size.height.saturating_sub(line_height)),
+        );
+        let Some(digits) = BigDigits::fitting(&buf, area.size) else {
+            return Ok(false);
+        };
+        let width = digits.text_width(&buf).ok_or(CE::Impossible)?; // `fitting()` measured it already
+        let top_left = area.top_left + Point::new(
+            (area.size.width - width) as i32,
+            ((area.size.height - digits.height()) / 2) as i32,
+        );
+        digits.draw(display, &buf, top_left, D::Color::FOREGROUND)?;
+        if !label.is_empty() {
+            Text::with_baseline(label, Point::zero(), self.character_style, Baseline::Top).draw(display)?;
+        }
+        Ok(true)
+    }
+
+    /// Returns whether the next `draw()` is going to actually redraw the stack.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.get()
+    }
+
+    /// The area the stack is drawn into, the whole width above the textbox
+    fn area(&self) -> Result<Rectangle, CustomError> {
+        let text_height = self.character_style.font.character_size.height - PIXELS_REMOVED;
+        Ok(Rectangle::new(
+            (0, 0).into(),
+            (
+                self.disp_dimensions.width,
+                // Checked, so that a display too small for the font returns an error instead of panicking
+                self.disp_dimensions.height.checked_sub(text_height + crate::textbox::TEXTBOX_OFFSET).ok_or(CE::BadInput)?
+            ).into()
+        ))
+    }
+
+    /// Draws the frames of the slide animation before the next `draw()`, if it's enabled and something was pushed or popped
+    /// since the last one, see `set_animation()`. `overlay` draws whatever else is on top of the stack's area in each frame,
+    /// e.g. the indicators of `StackSet`.
+    ///
+    /// Every frame is sent with `FlushableDisplay::flush_frame()`, so the last one (the usual drawing) should be too.
+    /// Returns whether there were any frames.
+    pub fn animate(&self, overlay: impl Fn(&mut D) -> Result<(), CustomError>) -> Result<bool, CustomError>
+    where
+        T: Display + LowerExp,
+        CustomError: From<D::Error>,
+    {
+        let motion = self.motion.get();
+        let Some(display_refcell) = self.display_refcell else {
+            return Ok(false);
+        };
+        // Big digits only ever show the top element, and a scrolled stack would have to be scrolled back first
+        if !self.animation || !self.dirty.get() || motion.jumbled || self.big_digits || self.scroll_offset != 0 {
+            return Ok(false);
+        }
+
+        // The elements that stayed move by the same number of lines, as the view follows the top.
+        // The ones pushed in the meantime start from just below the area, i.e. from the textbox they were typed into.
+        let (len, visible_lines) = (self.data.len(), self.visible_lines());
+        let kept = len.saturating_sub(motion.pushed);
+        let old_len = kept + motion.popped;
+        let first = len - min(len, visible_lines);
+        let old_first = old_len - min(old_len, visible_lines);
+        let moved = first as i32 - old_first as i32;
+        if moved == 0 && motion.pushed == 0 {
+            return Ok(false); // E.g. popping with the whole stack in view, the top line just disappears
+        }
+        log_trace!("Animating a push of {} and a pop of {} elements", motion.pushed, motion.popped);
+
+        let text_height = (self.character_style.font.character_size.height - PIXELS_REMOVED) as i32;
+        // In lines, from where the element ends up
+        let start = |index: usize| if index < kept {
+            moved
+        } else {
+            (visible_lines + index - kept) as i32 - (index as i32 - first as i32)
+        };
+        let area = self.area()?;
+        let mut display_refmut = display_refcell.borrow_mut();
+        let display_ref = &mut (*display_refmut);
+        for frame in 1..ANIMATION_FRAMES {
+            let remaining = (ANIMATION_FRAMES - frame) as i32;
+            area.into_styled(self.primitives_style).draw(display_ref)?;
+            // The ones scrolling out of view at the top are drawn too, the area clips them as they go
+            self.draw_lines(&mut display_ref.clipped(&area), area, moved.max(0) as usize, |index| {
+                start(index) * text_height * remaining / ANIMATION_FRAMES as i32
+            })?;
+            overlay(display_ref)?;
+            display_ref.flush_frame()?;
+        }
+        Ok(true)
+    }
+
+    /// Draws the lines of the elements in view into the cleared area, each moved down by `shift()` pixels (given its index)
+    /// from its place. `extra` more elements above the view get drawn too, see `animate()`.
+    fn draw_lines<DT>(&self, target: &mut DT, area: Rectangle, extra: usize, shift: impl Fn(usize) -> i32) -> Result<(), CustomError>
+    where
+        T: Display + LowerExp,
+        DT: DrawTarget<Color = D::Color>,
+        CustomError: From<DT::Error>,
+    {
+        let text_height = self.character_style.font.character_size.height - PIXELS_REMOVED;
+
+        // The offset might've gotten out of range if elements were popped since scrolling, so we clamp it again.
+        // The pops can't reset it themselves, because we only take `&self` here.
+        let visible_lines = self.visible_lines();
+        let offset = min(self.scroll_offset, self.data.len().saturating_sub(visible_lines));
+        let shown_len = self.data.len() - offset; // Only the elements that aren't scrolled out of view
+
+        // If there is less data than the display can show, we just draw all of it.
+        // In that case, we will "hang" the stack visually from the top of the display (desirable).
+        let num_lines: usize = min(shown_len, visible_lines);
+        let first = shown_len - num_lines;
+
+        // Possibly gate this behind a defmt feature flag if we move this into a library crate
+        log_trace!("Drawing {} lines on the display, scrolled by {}.", num_lines, offset);
+
+        let mut buf = String::<TEXT_BUFFER_SIZE>::new();
+
+        let font = self.character_style.font;
+
+        // The same text, just in the background color, drawn on top of a rectangle in the highlight color
+        let mut highlighted_style = self.character_style;
+        highlighted_style.text_color = Some(D::Color::BACKGROUND);
+        highlighted_style.background_color = None;
+        // Everything but the top of the stack fades into the background a bit, if the display has the shades for it
+        let mut dimmed_style = self.character_style;
+        dimmed_style.text_color = Some(D::Color::DIMMED);
+
+        // The gutter is as wide as the deepest level shown, so that the values stay aligned
+        // The levels start from 1, but if nothing fits on the display, there's no levels at all
+        let gutter_width = (offset + num_lines).checked_ilog10().unwrap_or(0) as usize + 1;
+
+        // We need usize for indexing; `i` is the line, counted from the top of the area
+        for index in (first.saturating_sub(extra)..shown_len).rev() {
+            let i = index as i32 - first as i32;
+            // Explicit, the `From<DT::Error>` bound confuses inference
+            let y = i32::try_from(text_height)? * i + shift(index);
+            let mut left_edge: i32 = 0; // Where the space for the value starts, i.e. after the gutter
+
+            // Only highlight the actual top of the stack, not just the lowest visible line when scrolled
+            let is_top = offset == 0 && index == shown_len - 1;
+            let character_style = if self.highlight_top && is_top {
+                // The glyphs are shifted down by the pixels we cut off, and we mustn't spill into the textbox below
+                Rectangle::new(Point::new(0, y + PIXELS_REMOVED as i32), Size::new(self.disp_dimensions.width, text_height))
+                    .intersection(&area)
+                    .into_styled(PrimitiveStyle::with_fill(D::Color::HIGHLIGHT))
+                    .draw(target)?;
+                highlighted_style
+            } else if is_top {
+                self.character_style
+            } else {
+                dimmed_style
+            };
+
+            if self.gutter {
+                // The topmost visible element is level `offset + 1`, the ones above it count upwards
+                let level = self.data.len() - index;
+                let separator = if index == first && shown_len > num_lines {
+                    '^' // There's more elements above (deeper in the stack)
+                } else if index == shown_len - 1 && offset > 0 {
+                    'v' // There's more elements below (scrolled out of view)
+                } else {
+                    ':'
+                };
+                core::write!(&mut buf, "{:>gutter_width$}{}", level, separator)?;
+
+                // `draw()` returns where the next character would go, i.e. the end of the gutter
+                left_edge = Text::with_baseline(buf.as_str(), Point::new(0, y), character_style, Baseline::Top)
+                    .draw(target)?
+                    .x;
+                buf.clear();
+            }
+            // Format the text as Display into the buffer, cutting off whatever doesn't fit into it
+            let mut writer = TruncatingWriter { buf: &mut buf, truncated: false };
+            if !self.labels[index].is_empty() {
+                core::write!(&mut writer, "{}: ", self.labels[index])?;
+            }
+            self.write_value(&mut writer, &self.data[index])?;
+            let mut truncated = writer.truncated;
+
+            // If it doesn't fit onto the line, we cut off the end and leave the last cell for an ellipsis.
+            // We cut off the end rather than the start, because for numbers that's the least significant digits.
+            let char_advance = font.character_size.width + font.character_spacing;
+            let max_chars = (u32::try_from(i32::try_from(self.disp_dimensions.width)? - left_edge).unwrap_or(0) / char_advance) as usize;
+            if truncated || buf.chars().count() > max_chars {
+                truncated = true;
+                let keep_bytes = buf.char_indices()
+                    .nth(max_chars.saturating_sub(1))
+                    .map_or(buf.len(), |(index, _)| index);
+                buf.truncate(keep_bytes);
+            }
+
+            let mut text = Text::with_baseline(buf.as_str(), Point::new(left_edge, y), character_style, Baseline::Top);
+            // We measure the rendered text and move it accordingly. If it doesn't fit, we keep it left-aligned,
+            // because the start of a number is more important than its end.
+            let free_space = i32::try_from(self.disp_dimensions.width)? - left_edge - i32::try_from(text.bounding_box().size.width)?;
+            if free_space > 0 {
+                text.position.x += match self.alignment {
+                    Alignment::Left => 0,
+                    Alignment::Center => free_space / 2,
+                    Alignment::Right => free_space,
+                };
+            }
+            let text_end = text.draw(target)?;
+
+            if truncated {
+                // Neither the ASCII nor the ISO 8859-2 fonts have a '…' glyph, so we draw three dots on the baseline ourselves
+                let color = character_style.text_color.unwrap_or(D::Color::FOREGROUND);
+                for dot in 0..3 {
+                    Pixel(Point::new(text_end.x + 2 * dot, y + font.baseline as i32), color)
+                        .draw(target)?;
+                }
+            }
+
+            buf.clear();
+        }
+        Ok(())
+    }
+
+    /// Draws the stack onto the display, unless nothing changed since the last time.
+    /// Even then, the display is still flushed if `flush` is true, since something else might've drawn onto it.
     pub fn draw(&self, flush: bool) -> Result<(), CustomError>
-    where T: core::fmt::Display
+    where
+        T: Display + LowerExp, // The latter for `NumberFormat::Scientific`
+        CustomError: From<D::Error>, // So that we can use `?` on the drawing, whatever the display's error is
     {
+        // A headless stack has nothing to draw onto, so we just stay dirty
+        let Some(display_refcell) = self.display_refcell else {
+            return Ok(());
+        };
+
+        if !self.dirty.get() {
+            if flush { display_refcell.borrow_mut().flush_display()?; };
+            return Ok(());
+        }
+        // We only mark it clean after successfully drawing, so that an error makes us try again next time
+        
         // A convenience variable
         let text_height = self.character_style.font.character_size.height - PIXELS_REMOVED;
         
         // Clear the area where the stack will be drawn
-        let clear_rect = Rectangle::new(
-            (0, 0).into(),
-            (self.disp_dimensions.width, (self.disp_dimensions.height - text_height - crate::textbox::TEXTBOX_OFFSET)).into() // We always clear the entire area, e.g. when popping elements
-        )
-        .into_styled(self.primitives_style);
+        // We always clear the entire area, e.g. when popping elements
+        let clear_rect = self.area()?.into_styled(self.primitives_style);
 
         // If the stack is empty, we don't need to draw anything so we expediently return
         if self.data.is_empty() {
             // We only borrow the RefCell at the end and do everything in bulk to minimize the critical section
-            let mut display_refmut = self.display_refcell.borrow_mut();
+            let mut display_refmut = display_refcell.borrow_mut();
             // Unpack the RefMut to get the inner struct, then get a mutable reference to it
             // In method calls, the compiler does this for us, but not so when we need to pass a reference to `draw()`
             let display_ref = &mut (*display_refmut);
 
             clear_rect.draw(display_ref)?;
-            if flush { display_ref.flush()?; };
+            self.dirty.set(false);
+            self.motion.set(Motion::default());
+            if flush { display_ref.flush_display()?; };
             return Ok(());
         }
 
-        // If there is less data than the display can show, we just draw all of it.
-        // In that case, we will "hang" the stack visually from the top of the display (desirable).
-        let num_lines: usize = min(
-            self.data.len(),
-            (self.disp_dimensions.height / text_height) as usize // Integer division: always rounded down (desirable here)
-            - 1 // -1 because we want to leave space for the bottom line
-        );
-
-        // Possibly gate this behind a defmt feature flag if we move this into a library crate
-        trace!("Drawing {} lines on the display.", num_lines);
+        if self.big_digits && self.scroll_offset == 0 {
+            let mut display_refmut = display_refcell.borrow_mut();
+            let display_ref = &mut (*display_refmut);
 
-        let topmost_data = self.multipeek(num_lines);
+            clear_rect.draw(display_ref)?;
+            if self.draw_big(display_ref, clear_rect.primitive, text_height)? {
+                self.dirty.set(false);
+                self.motion.set(Motion::default());
+                if flush { display_ref.flush_display()?; };
+                return Ok(());
+            }
+            log_trace!("Top of the stack doesn't fit in big digits, drawing the usual way");
+        }
 
         // Borrow the display RefCell at the end, to minimize the critical section
         // It would be a giant lifetime PITA to try and push the Text-s into a Vec and then draw them later, tho.
-        let mut display_refmut = self.display_refcell.borrow_mut();
+        let mut display_refmut = display_refcell.borrow_mut();
         // Get a mutable reference to the display itself, unpacking it from the RefMut
         // In method calls, the compiler does this for us, but not so when we need to pass a reference to `draw()`
         let display_ref = &mut (*display_refmut);
         
         clear_rect.draw(display_ref)?;
+        self.draw_lines(display_ref, clear_rect.primitive, 0, |_| 0)?;
+        self.dirty.set(false);
+        self.motion.set(Motion::default());
 
-        let mut buf = String::<TEXT_BUFFER_SIZE>::new();
+        if flush { display_ref.flush_display()?; };
+        Ok(())
+    }
+}
 
-        // We need usize for indexing
-        for i in (0..num_lines).rev() {
-            core::write!(&mut buf, "{}", topmost_data[i])?; // Format the text as Display into the buffer
+// ------------------------------------------------------------------------------------------------------------------------------------------------
 
-            Text::with_baseline(
-                buf.as_str(),
-                (0, (text_height * i as u32)).try_into()?,
-                self.character_style,
-                Baseline::Top
-            )
-            .draw(display_ref)?;
+// Iterates from the bottom to the top, same as `iter()`
+impl<'s, T, D> IntoIterator for &'s CustomStack<'_, T, D>
+where
+    D: FlushableDisplay,
+{
+    type Item = &'s T;
+    type IntoIter = core::slice::Iter<'s, T>;
 
-            buf.clear();
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// A `fmt::Write` adapter that silently cuts off whatever doesn't fit into the buffer, instead of failing.
+/// Used in `draw()`, so that a value too long for the buffer gets truncated instead of erroring out.
+struct TruncatingWriter<'b, const N: usize> {
+    buf: &'b mut String<N>,
+    /// Whether anything got cut off
+    truncated: bool,
+}
+
+impl<const N: usize> Write for TruncatingWriter<'_, N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for c in s.chars() {
+            if self.buf.push(c).is_err() {
+                self.truncated = true;
+                break;
+            }
         }
+        Ok(()) // Never fails, that's the whole point
+    }
+}
 
-        if flush { display_ref.flush()?; };
-        Ok(())
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Statistics only make sense for numbers, so we only implement them for DecimalFixed stacks
+#[allow(dead_code)]
+impl<'a, D> CustomStack<'a, DecimalFixed, D>
+where
+    D: FlushableDisplay,
+{
+    /// Returns the sum of all elements of the stack, leaving them in place.
+    /// Returns `BadInput` if the stack is empty.
+    pub fn sum(&self) -> Result<DecimalFixed, CustomError> {
+        let (first, rest) = self.data.split_first().ok_or(CE::BadInput)?;
+        rest.iter().try_fold(*first, |acc, &x| acc + x)
+    }
+
+    /// Returns the product of all elements of the stack, leaving them in place.
+    /// Returns `BadInput` if the stack is empty.
+    pub fn product(&self) -> Result<DecimalFixed, CustomError> {
+        let (first, rest) = self.data.split_first().ok_or(CE::BadInput)?;
+        rest.iter().try_fold(*first, |acc, &x| acc * x)
+    }
+
+    /// Returns the arithmetic mean of all elements of the stack, leaving them in place.
+    /// Returns `BadInput` if the stack is empty.
+    /// The sum is taken in i128, so that it doesn't overflow when the mean itself fits; `MathOverflow` is only for a mean that doesn't.
+    pub fn mean(&self) -> Result<DecimalFixed, CustomError> {
+        let exponent = self.data.first().ok_or(CE::BadInput)?.exponent();
+        let mut sum: i128 = 0;
+        for x in self.data.iter() {
+            if x.exponent() != exponent {
+                return Err(CE::Unimplemented); // The same as adding them
+            }
+            sum += i128::from(x.value()); // Can't overflow, `MAX_STACK_SIZE` is far below 2^64
+        }
+        // Truncated like the division of `DecimalFixed`
+        Ok(DecimalFixed::new_prescaled(i64::try_from(sum / self.data.len() as i128)?, exponent))
+    }
+
+    /// Returns the sample standard deviation (the one with `n - 1` in the denominator)
+    /// of all elements of the stack, leaving them in place.
+    /// Returns `BadInput` if there are less than two elements.
+    pub fn stddev(&self) -> Result<DecimalFixed, CustomError> {
+        if self.data.len() < 2 {
+            return Err(CE::BadInput);
+        }
+
+        let mean = self.mean()?;
+        // Squared in a u128 like in `DecimalFixed::hypot()`, so that they don't overflow when the deviations themselves fit:
+        // sqrt(Σ(d * 10^exp)² / (n - 1)) = sqrt(Σd² / (n - 1)) * 10^exp
+        let mut sum_of_squares: u128 = 0;
+        for x in self.data.iter() {
+            // `mean()` already made sure all the exponents are the same
+            let deviation = (i128::from(x.value()) - i128::from(mean.value())).unsigned_abs();
+            sum_of_squares = deviation.checked_mul(deviation)
+                .and_then(|square| sum_of_squares.checked_add(square))
+                .ok_or(CE::MathOverflow)?;
+        }
+
+        let variance = sum_of_squares / (self.data.len() as u128 - 1);
+        Ok(DecimalFixed::new_prescaled(i64::try_from(variance.isqrt())?, mean.exponent()))
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+    use embedded_graphics::pixelcolor::BinaryColor;
+    use heapless::Vec;
+    use super::{CustomStack, CustomStackBuilder, Motion, OverflowPolicy, StackEvent, StackObserver, MAX_STACK_SIZE};
+    use crate::custom_error::{CustomError, CE};
+    use crate::decfix::{num, DecimalFixed};
+    use crate::display::NullDisplay;
+    use crate::spill::SpillStore;
+
+    type TestStack<'a, T> = CustomStack<'a, T, NullDisplay>;
+
+    fn stack<'a, T>() -> TestStack<'a, T> {
+        CustomStackBuilder::<BinaryColor>::new().build_headless()
+    }
+
+    /// The stack from the bottom to the top, for comparing against arrays
+    fn contents<T: Copy>(stack: &TestStack<'_, T>) -> std::vec::Vec<T> {
+        stack.iter().copied().collect()
+    }
+
+    /// Remembers every event it gets
+    #[derive(Default)]
+    struct Recorder {
+        events: RefCell<std::vec::Vec<StackEvent>>,
+    }
+
+    impl StackObserver for Recorder {
+        fn on_change(&self, event: StackEvent) {
+            self.events.borrow_mut().push(event);
+        }
+    }
+
+    /// A spill store in RAM, failing once it holds `capacity` elements
+    struct RamSpill {
+        data: Vec<u32, 512>,
+        capacity: usize,
+    }
+
+    impl SpillStore<u32> for RamSpill {
+        fn len(&self) -> usize {
+            self.data.len()
+        }
+        fn spill(&mut self, value: &u32) -> Result<(), CustomError> {
+            if self.data.len() >= self.capacity {
+                return Err(CE::CapacityError);
+            }
+            self.data.push(*value).map_err(|_| CE::CapacityError)
+        }
+        fn unspill(&mut self) -> Result<Option<u32>, CustomError> {
+            Ok(self.data.pop())
+        }
+        fn clear(&mut self) {
+            self.data.clear();
+        }
+    }
+
+    #[test]
+    fn push_pop_peek() {
+        let mut s = stack::<u32>();
+        assert!(s.is_empty());
+        assert_eq!(s.pop(), None);
+        assert_eq!(s.peek(), None);
+
+        s.push_array([1, 2, 3]).unwrap();
+        assert_eq!(s.len(), 3);
+        assert_eq!(s.peek(), Some(&3));
+        assert_eq!(s.peek_nth(2), Some(&1));
+        assert_eq!(s.peek_nth(3), None);
+        assert_eq!(s.multipeek(2), &[2, 3]);
+        assert_eq!(s.multipeek(10), &[1, 2, 3]);
+
+        assert_eq!(s.pop(), Some(3));
+        assert_eq!(contents(&s), [1, 2]);
+    }
+
+    #[test]
+    fn multipop_yields_top_first() {
+        let mut s = stack::<u32>();
+        s.push_slice(&[1, 2, 3, 4]).unwrap();
+        let popped: std::vec::Vec<u32> = s.multipop(2).unwrap().collect();
+        assert_eq!(popped, [4, 3]);
+        assert_eq!(contents(&s), [1, 2]);
+
+        // Even if the iterator isn't consumed, and it pops all if there's not enough
+        drop(s.multipop(5));
+        assert!(s.is_empty());
+        assert!(s.multipop(1).is_none());
+    }
+
+    #[test]
+    fn shuffling() {
+        let mut s = stack::<u32>();
+        s.push_array([1, 2, 3, 4]).unwrap();
+
+        s.swap_at(0, 1).unwrap();
+        assert_eq!(contents(&s), [1, 2, 4, 3]);
+        s.rot().unwrap(); // a b c -- b c a
+        assert_eq!(contents(&s), [1, 4, 3, 2]);
+        s.roll(3).unwrap();
+        assert_eq!(contents(&s), [4, 3, 2, 1]);
+        s.roll(0).unwrap();
+        assert_eq!(contents(&s), [4, 3, 2, 1]);
+        s.over().unwrap(); // a b -- a b a
+        assert_eq!(contents(&s), [4, 3, 2, 1, 2]);
+        s.pick(4).unwrap();
+        assert_eq!(contents(&s), [4, 3, 2, 1, 2, 4]);
+        s.reverse();
+        assert_eq!(contents(&s), [4, 2, 1, 2, 3, 4]);
+        s.sort();
+        assert_eq!(contents(&s), [1, 2, 2, 3, 4, 4]);
+
+        assert_eq!(s.swap_at(0, 6), Err(CE::BadInput));
+        assert_eq!(s.roll(6), Err(CE::BadInput));
+        assert_eq!(s.pick(6), Err(CE::BadInput));
+    }
+
+    #[test]
+    fn labels_follow_their_elements() {
+        let mut s = stack::<u32>();
+        s.push_array([3, 1, 2]).unwrap();
+        s.set_label(2, "three").unwrap();
+        s.set_label(0, "two").unwrap();
+
+        s.rot().unwrap();
+        assert_eq!(contents(&s), [1, 2, 3]);
+        assert_eq!(s.label(0), Some("three"));
+        assert_eq!(s.label(1), Some("two"));
+        assert_eq!(s.label(2), None);
+
+        // The labelled sort is a different code path
+        s.reverse();
+        s.sort();
+        assert_eq!(contents(&s), [1, 2, 3]);
+        assert_eq!(s.label(0), Some("three"));
+
+        s.set_label(0, "").unwrap();
+        assert_eq!(s.label(0), None);
+        assert_eq!(s.set_label(0, "too long!"), Err(CE::CapacityError));
+        assert_eq!(s.set_label(3, "x"), Err(CE::BadInput));
+
+        // A fresh push doesn't inherit the popped element's label
+        s.pop();
+        s.push(4).unwrap();
+        assert_eq!(s.label(0), None);
+        assert_eq!(s.label(1), Some("two"));
+    }
+
+    #[test]
+    fn apply_top_n_is_all_or_nothing() {
+        let mut s = stack::<u32>();
+        s.push_array([1, 2, 3]).unwrap();
+        s.apply_top_n(2, |x| { *x *= 10; Ok(()) }).unwrap();
+        assert_eq!(contents(&s), [1, 20, 30]);
+
+        // Fails on the second one, after the first one was already modified
+        let result = s.apply_top_n(3, |x| if *x == 20 { Err(CE::MathOverflow) } else { *x += 1; Ok(()) });
+        assert_eq!(result, Err(CE::MathOverflow));
+        assert_eq!(contents(&s), [1, 20, 30]);
+        assert_eq!(s.apply_top_n(4, |_| Ok(())), Err(CE::BadInput));
+    }
+
+    #[test]
+    fn reject_when_full() {
+        let mut s = stack::<usize>();
+        s.push_exact_iterator(0..MAX_STACK_SIZE).map_err(|(e, _)| e).unwrap();
+        assert_eq!(s.push(1), Err((CE::CapacityError, 1)));
+        assert_eq!(s.push_array([1, 2]), Err((CE::CapacityError, [1, 2])));
+        assert_eq!(s.push_slice(&[1]), Err(CE::CapacityError));
+        assert_eq!(s.over(), Err(CE::CapacityError));
+        assert!(s.push_iterator(0..1, true).is_err());
+        assert_eq!(s.len(), MAX_STACK_SIZE);
+        assert_eq!(s.peek(), Some(&(MAX_STACK_SIZE - 1)));
+    }
+
+    #[test]
+    fn drop_bottom_when_full() {
+        let recorder = Recorder::default();
+        let mut s: TestStack<usize> = CustomStackBuilder::<BinaryColor>::new()
+            .set_overflow_policy(OverflowPolicy::DropBottom)
+            .set_observer(&recorder)
+            .build_headless();
+        s.push_exact_iterator(0..MAX_STACK_SIZE).map_err(|(e, _)| e).unwrap();
+        s.push_array([1000, 1001]).unwrap();
+        assert_eq!(s.len(), MAX_STACK_SIZE);
+        assert_eq!(s.peek_nth(MAX_STACK_SIZE - 1), Some(&2));
+        assert_eq!(s.peek(), Some(&1001));
+        // A size hint too big isn't a reason to drop anything
+        s.push_iterator((2000..2002).chain((0..100).filter(|_| false)), true).map_err(|(e, _)| e).unwrap();
+        assert_eq!(s.peek_nth(MAX_STACK_SIZE - 1), Some(&4));
+
+        // Not even dropping everything makes enough room for this
+        assert!(s.push_exact_iterator(0..(MAX_STACK_SIZE + 1)).is_err());
+        assert_eq!(
+            *recorder.events.borrow(),
+            [
+                StackEvent::Pushed(MAX_STACK_SIZE), StackEvent::DroppedBottom(2), StackEvent::Pushed(2),
+                StackEvent::DroppedBottom(1), StackEvent::DroppedBottom(1), StackEvent::Pushed(2),
+            ]
+        );
+    }
+
+    #[test]
+    fn push_iterator_beyond_the_capacity() {
+        let mut s: TestStack<u32> = CustomStackBuilder::<BinaryColor>::new()
+            .set_overflow_policy(OverflowPolicy::DropBottom)
+            .build_headless();
+        s.push_iterator(0..=(MAX_STACK_SIZE as u32), false).map_err(|(e, _)| e).unwrap();
+        assert_eq!(s.len(), MAX_STACK_SIZE);
+        assert_eq!((s.peek_nth(MAX_STACK_SIZE - 1), s.peek()), (Some(&1), Some(&(MAX_STACK_SIZE as u32))));
+        s.set_label(0, "top").unwrap();
+        assert_eq!(s.label(0), Some("top"));
+
+        let store = RefCell::new(RamSpill { data: Vec::new(), capacity: 512 });
+        let mut s: TestStack<u32> = CustomStackBuilder::<BinaryColor>::new()
+            .set_overflow_policy(OverflowPolicy::Spill)
+            .build_headless();
+        s.set_spill_store(&store);
+        s.push_iterator(0..=(MAX_STACK_SIZE as u32), true).map_err(|(e, _)| e).unwrap();
+        assert_eq!((s.len(), s.spilled_len()), (MAX_STACK_SIZE, 1));
+        assert_eq!(s.peek(), Some(&(MAX_STACK_SIZE as u32)));
+    }
+
+    #[test]
+    fn spills_and_reads_back() {
+        let store = RefCell::new(RamSpill { data: Vec::new(), capacity: 3 });
+        let mut s: TestStack<u32> = CustomStackBuilder::<BinaryColor>::new()
+            .set_overflow_policy(OverflowPolicy::Spill)
+            .build_headless();
+        s.set_spill_store(&store);
+
+        s.push_exact_iterator(0..(MAX_STACK_SIZE as u32)).map_err(|(e, _)| e).unwrap();
+        s.push_array([1000, 1001]).unwrap();
+        assert_eq!(s.spilled_len(), 2);
+        assert_eq!(s.len(), MAX_STACK_SIZE);
+
+        // The store fits only one more
+        assert_eq!(s.push_array([1002, 1003]), Err((CE::CapacityError, [1002, 1003])));
+        assert_eq!(s.spilled_len(), 3);
+        assert_eq!(s.len(), MAX_STACK_SIZE - 1);
+
+        // Popping down to the bottom reads them back in the original order
+        let popped: std::vec::Vec<u32> = core::iter::from_fn(|| s.pop()).collect();
+        assert_eq!(s.spilled_len(), 0);
+        let mut expected: std::vec::Vec<u32> = (0..(MAX_STACK_SIZE as u32)).collect();
+        expected.extend([1000, 1001]);
+        expected.reverse();
+        assert_eq!(popped, expected);
+    }
+
+    #[test]
+    fn clear_resets_everything() {
+        let mut s = stack::<u32>();
+        s.push_array([1, 2, 3]).unwrap();
+        s.set_label(0, "x").unwrap();
+        s.clear();
+        assert!(s.is_empty());
+        s.push(4).unwrap();
+        assert_eq!(s.label(0), None);
+    }
+
+    #[test]
+    fn motion_nets_pushes_and_pops() {
+        let mut s = stack::<u32>();
+        s.push_array([1, 2]).unwrap();
+        s.motion.set(Motion::default()); // As if it was drawn
+        // `2 3 +` pops both operands and pushes the sum, which comes up from the textbox
+        s.push(3).unwrap();
+        let _ = s.multipop(2).unwrap().count();
+        s.push(5).unwrap();
+        assert_eq!(s.motion.get(), Motion { popped: 1, pushed: 1, jumbled: false });
+        s.clear();
+        assert!(s.motion.get().jumbled);
+    }
+
+    #[test]
+    fn statistics() {
+        let mut s = stack::<DecimalFixed>();
+        assert_eq!(s.sum(), Err(CE::BadInput));
+        assert_eq!(s.stddev(), Err(CE::BadInput));
+
+        s.push_array([num("2"), num("4"), num("4"), num("4"), num("5"), num("5"), num("7"), num("9")]).unwrap();
+        assert_eq!(s.sum().unwrap(), num("40"));
+        assert_eq!(s.mean().unwrap(), num("5"));
+        assert_eq!(s.product().unwrap(), num("201600"));
+        // The sample variance is 32 / 7
+        assert_eq!(s.stddev().unwrap(), num("2.138089935"));
+
+        s.push(DecimalFixed::new_prescaled(i64::MAX, -9)).unwrap();
+        assert_eq!(s.sum(), Err(CE::MathOverflow));
+        // The sum doesn't fit, but the mean does
+        assert_eq!(s.mean(), Ok(DecimalFixed::new_prescaled(1_024_819_119_650_530_645, -9)));
+
+        // Squaring the deviations would overflow, but the standard deviation fits
+        s.clear();
+        s.push_array([num("0"), num("200000")]).unwrap();
+        assert_eq!(s.mean().unwrap(), num("100000"));
+        assert_eq!(s.stddev().unwrap(), num("141421.356237309"));
     }
 }
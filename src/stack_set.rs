@@ -0,0 +1,255 @@
+use embedded_graphics::{
+    prelude::*,
+
+    mono_font::{
+        ascii::FONT_5X8,
+        MonoTextStyle,
+        MonoTextStyleBuilder,
+    },
+    text::{
+        Baseline,
+        Text,
+    },
+};
+
+use heapless::String;
+
+use core::{
+    cell::RefCell,
+    ops::{Deref, DerefMut},
+};
+
+use crate::stack::{CustomStack, CustomStackBuilder, NumberFormat};
+use crate::spill::SpillStore;
+use crate::icons::{Icon, IconManager};
+use crate::display::{FlushableDisplay, Palette};
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Number of workspaces, i.e. independent stacks the user can switch between
+pub const WORKSPACE_COUNT: usize = 4;
+/// Shown in the workspace indicator's style when the battery is low, see `StackSet::set_low_battery()`
+const LOW_BATTERY_LABEL: &str = "BAT";
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Small inverted text for the workspace indicator in the top-right corner, so that it doesn't get confused with the stack's contents
+const fn indicator_style<C: Palette>() -> MonoTextStyle<'static, C> {
+    MonoTextStyleBuilder::new()
+        .font(&FONT_5X8)
+        .text_color(C::BACKGROUND)
+        .background_color(C::HIGHLIGHT)
+        .build()
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// A set of independent stacks ("workspaces"), only one of which is active at a time.
+///
+/// It dereferences to the active stack, so it can be used just like a `CustomStack`,
+/// except that its own `draw()` also draws the workspace indicator.
+pub struct StackSet<'a, T, D>
+where
+    D: FlushableDisplay,
+{
+    stacks: [CustomStack<'a, T, D>; WORKSPACE_COUNT],
+    active: usize,
+    display_refcell: &'a RefCell<D>,
+    /// Whether to show the low battery indicator next to the workspace one
+    low_battery: bool,
+    /// Hours and minutes of the clock shown left of the other indicators, `None` to hide it
+    clock: Option<(u8, u8)>,
+    /// Drawn left of the clock
+    icons: IconManager,
+}
+
+#[allow(dead_code)]
+impl<'a, T, D> StackSet<'a, T, D>
+where
+    D: FlushableDisplay,
+{
+    /// Builds all the stacks from the same builder, drawing onto the same display. The first workspace is active.
+    pub fn new(builder: CustomStackBuilder<'a, D::Color>, display_refcell: &'a RefCell<D>) -> Self {
+        StackSet {
+            stacks: core::array::from_fn(|_| builder.build(display_refcell)),
+            active: 0,
+            display_refcell,
+            low_battery: false,
+            clock: None,
+            icons: IconManager::new(),
+        }
+    }
+
+    /// Returns the index of the active workspace, starting from zero.
+    pub fn active_index(&self) -> usize {
+        self.active
+    }
+
+    /// Switches to the workspace with the given index, starting from zero.
+    /// Returns `BadInput` if there's no such workspace.
+    pub fn switch_to(&mut self, index: usize) -> Result<(), CustomError> {
+        if index >= WORKSPACE_COUNT {
+            return Err(CE::BadInput);
+        }
+
+        self.active = index;
+        // The previous workspace's contents are still on the display, so we have to redraw no matter what
+        self.stacks[index].invalidate();
+        Ok(())
+    }
+
+    /// Shows or hides the low battery indicator, it gets drawn with the next redraw.
+    pub fn set_low_battery(&mut self, low_battery: bool) {
+        if self.low_battery != low_battery {
+            self.low_battery = low_battery;
+            self.stacks[self.active].invalidate();
+        }
+    }
+
+    /// Sets the time shown by the clock in the status bar, or hides it. Only a change gets redrawn, with the next redraw.
+    pub fn set_clock(&mut self, clock: Option<(u8, u8)>) {
+        if self.clock != clock {
+            self.clock = clock;
+            self.stacks[self.active].invalidate();
+        }
+    }
+
+    /// Shows or hides the icon in the status bar. Only a change gets redrawn, with the next redraw.
+    pub fn set_icon(&mut self, icon: Icon, shown: bool) {
+        if self.icons.set(icon, shown) {
+            self.stacks[self.active].invalidate();
+        }
+    }
+
+    /// Gives every workspace its own spill store, in the order of the workspaces, see `CustomStack::set_spill_store()`.
+    pub fn set_spill_stores(&mut self, stores: [&'a RefCell<dyn SpillStore<T> + 'a>; WORKSPACE_COUNT]) {
+        for (stack, store) in self.stacks.iter_mut().zip(stores) {
+            stack.set_spill_store(store);
+        }
+    }
+
+    /// Sets the number format of all the workspaces at once, see `CustomStack::set_number_format()`.
+    pub fn set_number_format(&mut self, number_format: NumberFormat) {
+        for stack in self.stacks.iter_mut() {
+            stack.set_number_format(number_format);
+        }
+    }
+
+    /// Sets the digit grouping of all the workspaces at once, see `CustomStack::set_digit_grouping()`.
+    pub fn set_digit_grouping(&mut self, digit_grouping: bool) {
+        for stack in self.stacks.iter_mut() {
+            stack.set_digit_grouping(digit_grouping);
+        }
+    }
+
+    /// Sets the big digits of all the workspaces at once, see `CustomStack::set_big_digits()`.
+    pub fn set_big_digits(&mut self, big_digits: bool) {
+        for stack in self.stacks.iter_mut() {
+            stack.set_big_digits(big_digits);
+        }
+    }
+
+    /// Sets the slide animation of all the workspaces at once, see `CustomStack::set_animation()`.
+    pub fn set_animation(&mut self, animation: bool) {
+        for stack in self.stacks.iter_mut() {
+            stack.set_animation(animation);
+        }
+    }
+
+    /// Draws the active stack, and the workspace indicator on top of it if the stack got redrawn (which clears it).
+    /// If the stack slides (see `CustomStack::animate()`), the indicators are drawn onto every frame, and the last one is sent right away.
+    pub fn draw(&self, flush: bool) -> Result<(), CustomError>
+    where
+        T: core::fmt::Display + core::fmt::LowerExp,
+        CustomError: From<D::Error>,
+    {
+        let animated = self.stacks[self.active].animate(|display| self.draw_indicators(display))?;
+        let redrawn = self.stacks[self.active].is_dirty();
+        self.stacks[self.active].draw(false)?;
+
+        let mut display_refmut = self.display_refcell.borrow_mut();
+        // Unpack the RefMut to get the inner struct, then get a mutable reference to it
+        let display_ref = &mut (*display_refmut);
+
+        if redrawn {
+            self.draw_indicators(display_ref)?;
+        }
+
+        if animated {
+            display_ref.flush_frame()?;
+        } else if flush {
+            display_ref.flush_display()?;
+        }
+        Ok(())
+    }
+
+    /// Draws the workspace indicator into the top-right corner, and the rest of the indicators left of it
+    fn draw_indicators(&self, display_ref: &mut D) -> Result<(), CustomError>
+    where
+        CustomError: From<D::Error>,
+    {
+        let indicator_style = indicator_style::<D::Color>();
+        let mut buf = [0_u8; 1];
+        // Workspaces are numbered from 1 for the user; we only have a handful, so one digit is enough
+        let label = char::from_digit((self.active + 1) as u32, 10)
+            .ok_or(CE::Impossible)?
+            .encode_utf8(&mut buf);
+
+        Text::with_baseline(
+            label,
+            Point::new(
+                display_ref.bounding_box().size.width as i32 - indicator_style.font.character_size.width as i32,
+                0
+            ),
+            indicator_style,
+            Baseline::Top
+        )
+        .draw(display_ref)?;
+
+        // The rest of the indicators go to the left of the workspace one, each with a pixel of space in between
+        let width = indicator_style.font.character_size.width as i32;
+        let mut right = display_ref.bounding_box().size.width as i32 - width - 1;
+        if self.low_battery {
+            right -= width * LOW_BATTERY_LABEL.len() as i32;
+            Text::with_baseline(LOW_BATTERY_LABEL, Point::new(right, 0), indicator_style, Baseline::Top)
+                .draw(display_ref)?;
+            right -= 1;
+        }
+
+        if let Some((hour, minute)) = self.clock {
+            let label: String<5> = heapless::format!("{:02}:{:02}", hour, minute)?;
+            right -= width * label.len() as i32;
+            Text::with_baseline(&label, Point::new(right, 0), indicator_style, Baseline::Top)
+                .draw(display_ref)?;
+            right -= 1;
+        }
+
+        self.icons.draw(display_ref, right)?;
+        Ok(())
+    }
+}
+
+impl<'a, T, D> Deref for StackSet<'a, T, D>
+where
+    D: FlushableDisplay,
+{
+    type Target = CustomStack<'a, T, D>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.stacks[self.active]
+    }
+}
+
+impl<'a, T, D> DerefMut for StackSet<'a, T, D>
+where
+    D: FlushableDisplay,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.stacks[self.active]
+    }
+}
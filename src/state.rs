@@ -0,0 +1,64 @@
+use crate::decfix::DecimalFixed;
+use crate::registers::RegisterFile;
+use crate::macros::MacroRecorder;
+use crate::stopwatch::Stopwatch;
+use crate::settings::Settings;
+use crate::screensaver::Screensaver;
+use crate::ambient::AutoBrightness;
+use crate::night::NightMode;
+use crate::power::PowerManager;
+use crate::led::LedMode;
+use crate::errlog::ErrorLog;
+use crate::stats::StatsAccumulator;
+use crate::poly::Polynomial;
+use crate::pages::PageManager;
+
+/// The calculator's state apart from the stack itself,
+/// shared between the arithmetics in the main loop and the command mode.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CalcState {
+    pub registers: RegisterFile,
+    /// The top of the stack from before the last arithmetic operation, like the LASTx register on HP calculators.
+    /// `None` if there wasn't any operation yet.
+    pub last_x: Option<DecimalFixed>,
+    pub macros: MacroRecorder,
+    pub stopwatch: Stopwatch,
+    /// Restored from flash on boot, see `persist::restore_settings()`
+    pub settings: Settings,
+    pub screensaver: Screensaver,
+    pub auto_brightness: AutoBrightness,
+    /// Switches to the night contrast and back, see the `night` command
+    pub night: NightMode,
+    pub power: PowerManager,
+    /// What the onboard LED does, see the `led` command (not saved)
+    pub led: LedMode,
+    /// The last errors shown to the user, see the `errlog` command
+    pub errlog: ErrorLog,
+    /// The statistics registers filled by `s+`, see the statistics commands (not saved)
+    pub stats: StatsAccumulator,
+    /// The polynomial stored by the `poly` command (not saved)
+    pub poly: Polynomial,
+    /// Which page fills the display, see the `page` command (not saved)
+    pub pages: PageManager,
+}
+
+impl CalcState {
+    pub const fn new() -> Self {
+        CalcState {
+            registers: RegisterFile::new(),
+            last_x: None,
+            macros: MacroRecorder::new(),
+            stopwatch: Stopwatch::new(),
+            settings: Settings::new(),
+            screensaver: Screensaver::new(),
+            auto_brightness: AutoBrightness::new(),
+            night: NightMode::new(),
+            power: PowerManager::new(),
+            led: LedMode::Off,
+            errlog: ErrorLog::new(),
+            stats: StatsAccumulator::new(),
+            poly: Polynomial::new(),
+            pages: PageManager::new(),
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use crate::decfix::DecimalFixed;
+use crate::wide::{self, to_wide, from_wide, checked_mul_scaled, checked_div_scaled};
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// The statistics registers of classic calculators: the count of the data points and the sums over them,
+/// filled by `s+` and emptied by `s-` (see the commands).
+///
+/// Everything is computed from the sums alone, so the data points themselves don't have to be kept anywhere.
+/// The sums are kept in the fixed point of `wide.rs`, so that the squares and the products don't overflow
+/// while the results do fit; they're converted back only for the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatsAccumulator {
+    n: u32,
+    sum_x: i128,
+    sum_x2: i128,
+    sum_y: i128,
+    sum_y2: i128,
+    sum_xy: i128,
+}
+
+impl Default for StatsAccumulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StatsAccumulator {
+    pub const fn new() -> Self {
+        StatsAccumulator { n: 0, sum_x: 0, sum_x2: 0, sum_y: 0, sum_y2: 0, sum_xy: 0 }
+    }
+
+    /// Adds the data point (like `Σ+` on HP calculators), use zero as `y` for statistics of a single variable.
+    /// On overflow it returns `MathOverflow` and the sums stay untouched.
+    pub fn add(&mut self, x: DecimalFixed, y: DecimalFixed) -> Result<(), CustomError> {
+        let (x, y) = (to_wide(x)?, to_wide(y)?);
+        let add = |sum: i128, x: i128| sum.checked_add(x).ok_or(CE::MathOverflow);
+        *self = StatsAccumulator {
+            n: self.n.checked_add(1).ok_or(CE::MathOverflow)?,
+            sum_x: add(self.sum_x, x)?,
+            sum_x2: add(self.sum_x2, checked_mul_scaled(x, x)?)?,
+            sum_y: add(self.sum_y, y)?,
+            sum_y2: add(self.sum_y2, checked_mul_scaled(y, y)?)?,
+            sum_xy: add(self.sum_xy, checked_mul_scaled(x, y)?)?,
+        };
+        Ok(())
+    }
+
+    /// Removes a data point added before (like `Σ-` on HP calculators), e.g. one entered by mistake.
+    /// Returns `BadInput` if there's no data points; on overflow it returns `MathOverflow`. Either way the sums stay untouched.
+    pub fn remove(&mut self, x: DecimalFixed, y: DecimalFixed) -> Result<(), CustomError> {
+        let (x, y) = (to_wide(x)?, to_wide(y)?);
+        let sub = |sum: i128, x: i128| sum.checked_sub(x).ok_or(CE::MathOverflow);
+        *self = StatsAccumulator {
+            n: self.n.checked_sub(1).ok_or(CE::BadInput)?,
+            sum_x: sub(self.sum_x, x)?,
+            sum_x2: sub(self.sum_x2, checked_mul_scaled(x, x)?)?,
+            sum_y: sub(self.sum_y, y)?,
+            sum_y2: sub(self.sum_y2, checked_mul_scaled(y, y)?)?,
+            sum_xy: sub(self.sum_xy, checked_mul_scaled(x, y)?)?,
+        };
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    /// The number of data points
+    pub fn n(&self) -> u32 {
+        self.n
+    }
+
+    /// The registers in the order calculators list them (Σx, Σx², Σy, Σy², Σxy), with names our ISO 8859-2 font can show.
+    /// A sum that doesn't fit into a `DecimalFixed` is `MathOverflow`, although the statistics computed from it can still fit.
+    pub fn sums(&self) -> [(&'static str, Result<DecimalFixed, CustomError>); 5] {
+        [
+            ("sum x", from_wide(self.sum_x)),
+            ("sum x^2", from_wide(self.sum_x2)),
+            ("sum y", from_wide(self.sum_y)),
+            ("sum y^2", from_wide(self.sum_y2)),
+            ("sum xy", from_wide(self.sum_xy)),
+        ]
+    }
+
+    /// Returns the arithmetic means of x and y in the wide fixed point, or `BadInput` if there's no data points.
+    fn wide_mean(&self) -> Result<(i128, i128), CustomError> {
+        if self.n == 0 {
+            return Err(CE::BadInput);
+        }
+        let n = i128::from(self.n);
+        Ok(( self.sum_x / n, self.sum_y / n ))
+    }
+
+    /// Returns the arithmetic means of x and y, or `BadInput` if there's no data points.
+    pub fn mean(&self) -> Result<(DecimalFixed, DecimalFixed), CustomError> {
+        let (mean_x, mean_y) = self.wide_mean()?;
+        Ok(( from_wide(mean_x)?, from_wide(mean_y)? ))
+    }
+
+    /// Returns the sums of the squared deviations from the means of x and y, and of the products of the deviations,
+    /// i.e. `Σ(x - x̄)²`, `Σ(y - ȳ)²` and `Σ(x - x̄)(y - ȳ)`. Returns `BadInput` if there's no data points.
+    fn deviations(&self) -> Result<(i128, i128, i128), CustomError> {
+        let (mean_x, mean_y) = self.wide_mean()?;
+        // Σ(x - x̄)² = Σx² - x̄Σx, the mean times the sum doesn't overflow as soon as squaring the sum would
+        let deviation = |sum: i128, mean: i128, other_sum: i128| {
+            sum.checked_sub(checked_mul_scaled(mean, other_sum)?).ok_or(CE::MathOverflow)
+        };
+        Ok((
+            deviation(self.sum_x2, mean_x, self.sum_x)?,
+            deviation(self.sum_y2, mean_y, self.sum_y)?,
+            deviation(self.sum_xy, mean_x, self.sum_y)?,
+        ))
+    }
+
+    /// Returns the sample standard deviations (the ones with `n - 1` in the denominator) of x and y.
+    /// Returns `BadInput` if there are less than two data points.
+    pub fn stddev(&self) -> Result<(DecimalFixed, DecimalFixed), CustomError> {
+        if self.n < 2 {
+            return Err(CE::BadInput);
+        }
+        let (dev_x, dev_y, _) = self.deviations()?;
+        let n_minus_one = i128::from(self.n - 1);
+        // The truncation of the means can make a deviation of equal values a tiny bit negative, which is still zero
+        Ok(( wide::sqrt((dev_x / n_minus_one).max(0))?, wide::sqrt((dev_y / n_minus_one).max(0))? ))
+    }
+
+    /// Returns the slope and the intercept of the least-squares line in the wide fixed point, see `regression()`.
+    fn wide_regression(&self) -> Result<(i128, i128), CustomError> {
+        if self.n < 2 {
+            return Err(CE::BadInput);
+        }
+        let (dev_x, _, dev_xy) = self.deviations()?;
+        if dev_x == 0 {
+            return Err(CE::BadInput); // A vertical line, the slope would be infinite
+        }
+        let (mean_x, mean_y) = self.wide_mean()?;
+        let slope = checked_div_scaled(dev_xy, dev_x)?;
+        let intercept = mean_y.checked_sub(checked_mul_scaled(slope, mean_x)?).ok_or(CE::MathOverflow)?;
+        Ok(( slope, intercept ))
+    }
+
+    /// Returns the slope and the intercept of the least-squares line `y = slope * x + intercept`.
+    /// Returns `BadInput` if there are less than two data points, or they all have the same x.
+    pub fn regression(&self) -> Result<(DecimalFixed, DecimalFixed), CustomError> {
+        let (slope, intercept) = self.wide_regression()?;
+        Ok(( from_wide(slope)?, from_wide(intercept)? ))
+    }
+
+    /// Returns the y the least-squares line predicts for the x, see `regression()`.
+    pub fn predict(&self, x: DecimalFixed) -> Result<DecimalFixed, CustomError> {
+        let (slope, intercept) = self.wide_regression()?;
+        let y = checked_mul_scaled(slope, to_wide(x)?)?.checked_add(intercept).ok_or(CE::MathOverflow)?;
+        from_wide(y)
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::StatsAccumulator;
+    use crate::decfix::{num, DecimalFixed};
+    use crate::custom_error::CE;
+
+    #[test]
+    fn single_variable() {
+        let mut stats = StatsAccumulator::new();
+        assert_eq!(stats.mean(), Err(CE::BadInput));
+        for x in ["2", "4", "4", "4", "5", "5", "7", "9"] {
+            stats.add(num(x), DecimalFixed::ZERO).unwrap();
+        }
+        assert_eq!(stats.n(), 8);
+        assert_eq!(stats.sums()[0].1, Ok(num("40")));
+        assert_eq!(stats.sums()[1].1, Ok(num("232")));
+        assert_eq!(stats.mean().unwrap().0, num("5"));
+        // The sample deviation, sqrt(32 / 7)
+        assert_eq!(stats.stddev().unwrap().0, num("2.138089935"));
+    }
+
+    #[test]
+    fn regression_of_a_line() {
+        let mut stats = StatsAccumulator::new();
+        for (x, y) in [("1", "5"), ("2", "7"), ("3", "9"), ("4", "11")] {
+            stats.add(num(x), num(y)).unwrap();
+        }
+        assert_eq!(stats.regression(), Ok((num("2"), num("3"))));
+        assert_eq!(stats.predict(num("10")), Ok(num("23")));
+        assert_eq!(stats.predict(num("-0.5")), Ok(num("2")));
+
+        // A point entered by mistake and removed again changes nothing
+        let before = stats;
+        stats.add(num("100"), num("-3")).unwrap();
+        assert_ne!(stats.regression(), before.regression());
+        stats.remove(num("100"), num("-3")).unwrap();
+        assert_eq!(stats, before);
+    }
+
+    #[test]
+    fn degenerate_and_overflowing_input() {
+        let mut stats = StatsAccumulator::new();
+        assert_eq!(stats.remove(num("1"), num("1")), Err(CE::BadInput));
+        stats.add(num("3"), num("1")).unwrap();
+        assert_eq!(stats.stddev(), Err(CE::BadInput));
+        stats.add(num("3"), num("2")).unwrap();
+        assert_eq!(stats.regression(), Err(CE::BadInput)); // Both have the same x
+
+        // The sum of the squares overflows, so nothing gets added at all
+        stats.add(num("9000000000"), DecimalFixed::ZERO).unwrap();
+        stats.add(num("9000000000"), DecimalFixed::ZERO).unwrap();
+        let before = stats;
+        assert_eq!(stats.add(num("9000000000"), DecimalFixed::ZERO), Err(CE::MathOverflow));
+        assert_eq!(stats, before);
+    }
+
+    #[test]
+    fn large_data_points() {
+        // The squares and the products don't fit into a `DecimalFixed`, but everything computed from them does
+        let mut stats = StatsAccumulator::new();
+        for (x, y) in [("100000", "200001"), ("200000", "400001"), ("300000", "600001")] {
+            stats.add(num(x), num(y)).unwrap();
+        }
+        assert_eq!(stats.sums()[0].1, Ok(num("600000")));
+        assert_eq!(stats.sums()[1].1, Err(CE::MathOverflow));
+        assert_eq!(stats.mean(), Ok((num("200000"), num("400001"))));
+        assert_eq!(stats.stddev(), Ok((num("100000"), num("200000"))));
+        assert_eq!(stats.regression(), Ok((num("2"), num("1"))));
+        assert_eq!(stats.predict(num("1000000")), Ok(num("2000001")));
+    }
+}
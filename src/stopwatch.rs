@@ -0,0 +1,50 @@
+
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+/// A stopwatch measuring in microseconds of the hardware timer, see the `stopwatch` command.
+///
+/// It doesn't read the timer itself, the caller passes the current time in, so that it's easy to reason about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Stopwatch {
+    /// Timestamp of the start in microseconds, `None` if it's not running
+    started_at: Option<u64>,
+}
+
+impl Stopwatch {
+    pub const fn new() -> Self {
+        Stopwatch { started_at: None }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.started_at.is_some()
+    }
+
+    /// Starts the stopwatch, or restarts it from zero if it's already running.
+    pub fn start(&mut self, now: u64) {
+        if self.is_running() {
+            log_info!("Restarting the stopwatch");
+        }
+        self.started_at = Some(now);
+    }
+
+    /// Returns the microseconds elapsed since the start, leaving the stopwatch running.
+    /// Returns `BadInput` if it's not running.
+    pub fn lap(&self, now: u64) -> Result<u64, CustomError> {
+        let Some(started_at) = self.started_at else {
+            log_warn!("The stopwatch isn't running");
+            return Err(CE::BadInput);
+        };
+        Ok(now.saturating_sub(started_at))
+    }
+
+    /// Stops the stopwatch and returns the microseconds elapsed since the start.
+    /// Returns `BadInput` if it's not running.
+    pub fn stop(&mut self, now: u64) -> Result<u64, CustomError> {
+        let elapsed = self.lap(now)?;
+        self.started_at = None;
+        Ok(elapsed)
+    }
+}
@@ -1,6 +1,5 @@
 use embedded_graphics::{
     prelude::*,
-    pixelcolor::BinaryColor,
 
     mono_font::{
 //        ascii::FONT_6X12,
@@ -13,24 +12,21 @@ use embedded_graphics::{
     },
 
     primitives::{
+        Line,
         PrimitiveStyle,
         PrimitiveStyleBuilder,
         Rectangle,
     },
 };
-use ssd1306::{
-    Ssd1306,
-    prelude::*,
-    mode::BufferedGraphicsMode,
-};
 
 use heapless::String;
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
 
 use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
     CustomError,
     CE // Short type alias
 };
+use crate::display::{Dimmed, FlushableDisplay, Palette};
 
 // ------------------------------------------------------------------------------------------------------------------------------------------------
 
@@ -41,7 +37,7 @@ so with this constant we basically cut off the top `n` pixels.
 Please maintain consistency with `textbox.rs`. */
 const PIXELS_REMOVED: u32 = 2;
 /// Size of String-s used for buffering text during writes, and for the textbox
-const TEXT_BUFFER_SIZE: usize = 32;
+pub const TEXT_BUFFER_SIZE: usize = 32;
 /** Number of pixels to offset the textbox from the bottom of the display by.
 
 This constant shall be determined by the programmer,
@@ -102,15 +98,56 @@ impl DisplayDimensions {
 
 // ------------------------------------------------------------------------------------------------------------------------------------------------
 
-pub struct CustomTextboxBuilder<'a> {
+/// Decides whether a char may be inserted into the textbox, given the current text and the cursor position (a byte index).
+/// Checked in `append_char()` and `append_str()`, so that invalid input is rejected by the widget itself.
+pub type Validator = fn(text: &str, cursor: usize, c: char) -> bool;
+
+/// A `Validator` for number entry: digits, at most one decimal point and a minus sign only at the very start,
+/// optionally followed by an exponent, e.g. `-1.6e-19`: an `e` after a digit of the mantissa, a minus sign right after it and digits.
+pub fn numeric_validator(text: &str, cursor: usize, c: char) -> bool {
+    let (before, after) = text.split_at(cursor);
+    // Nothing may go before the minus sign, neither the mantissa's nor the exponent's
+    let before_minus = (cursor == 0 && text.starts_with('-')) || (before.ends_with('e') && after.starts_with('-'));
+    let in_exponent = before.contains('e');
+    match c {
+        '0'..='9' => !before_minus,
+        '.' => !before_minus && !in_exponent && !text.contains('.'),
+        '-' => (cursor == 0 && !text.starts_with('-')) || (before.ends_with('e') && !after.starts_with('-')),
+        // The decimal point mustn't end up in the exponent
+        'e' => !text.contains('e') && before.contains(|c: char| c.is_ascii_digit()) && !after.contains('.'),
+        _ => false,
+    }
+}
+
+/// What the textbox looked like when it was last drawn, so that the next `draw()` can redraw only what changed
+#[derive(Debug, Default)]
+struct DrawnState {
+    /// The part of the text that was visible
+    visible: String<TEXT_BUFFER_SIZE>,
+    /// The cell the cursor was under, counted from the start of the visible part
+    cursor_cell: usize,
+    offset: usize,
+    placeholder_shown: bool,
+    right_overflow: bool,
+    /// False if the display might not show the above anymore, e.g. after `invalidate()`, so we have to redraw everything
+    valid: bool,
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+pub struct CustomTextboxBuilder<'a, C: Palette> {
     disp_dimensions: DisplayDimensions,
-    character_style: MonoTextStyle<'a, BinaryColor>,
-    primitives_style: PrimitiveStyle<BinaryColor>,
-    primitives_alternate_style: PrimitiveStyle<BinaryColor>,
+    character_style: MonoTextStyle<'a, C>,
+    primitives_style: PrimitiveStyle<C>,
+    primitives_alternate_style: PrimitiveStyle<C>,
+    overflow_indicators: bool,
+    prompt: &'a str,
+    placeholder: &'a str,
+    validator: Option<Validator>,
 }
 
 #[allow(dead_code)]
-impl<'a> CustomTextboxBuilder<'a> {
+impl<'a, C: Palette> CustomTextboxBuilder<'a, C> {
     /// Creates a new `CustomTextboxBuilder` with the default display dimensions of 128x64 pixels
     /// and the default text style.
     /// For custom parameters, use the builder pattern.
@@ -119,36 +156,44 @@ impl<'a> CustomTextboxBuilder<'a> {
             disp_dimensions: DisplayDimensions::const_default(),
 
             // Standard white text on (by default) transparent background
-            character_style: MonoTextStyle::new(&ISO_FONT_6X12, BinaryColor::On),
+            character_style: MonoTextStyle::new(&ISO_FONT_6X12, C::FOREGROUND),
 
             // Standard white stroke with 1px width and transparent fill
             primitives_style: PrimitiveStyleBuilder::new()
                 .stroke_width(1)
-                .stroke_color(BinaryColor::On)
+                .stroke_color(C::FOREGROUND)
                 //.reset_fill_color() // Reset the fill color to transparent (unnecessary, but for clarity)
                 .build(),
 
             // Standard black stroke with 1px width and black fill
             primitives_alternate_style: PrimitiveStyleBuilder::new()
                 .stroke_width(1)
-                .stroke_color(BinaryColor::Off)
-                .fill_color(BinaryColor::Off)
+                .stroke_color(C::BACKGROUND)
+                .fill_color(C::BACKGROUND)
                 .build(),
+
+            overflow_indicators: true,
+            prompt: "",
+            placeholder: "",
+            validator: None,
         }
     }
 
     /// Build the builder pattern into a finished struct, copying currently set parameters,
     /// initialising empty ones and storing the RefCell provided as a parameter.
-    pub fn build<DI, SIZE> (
+    pub fn build<D> (
         self,
-        display_refcell: &'a RefCell<Ssd1306<DI, SIZE, BufferedGraphicsMode<SIZE>>>
-    ) -> CustomTextbox<'a, DI, SIZE>
+        display_refcell: &'a RefCell<D>
+    ) -> CustomTextbox<'a, D>
     where 
-        DI: WriteOnlyDataCommand,
-        SIZE: DisplaySize,
+        D: FlushableDisplay<Color = C>,
     {
         CustomTextbox {
             text: String::new(),
+            cursor: 0,
+            view_offset: Cell::new(0),
+            dirty: Cell::new(true), // Nothing was drawn yet
+            drawn: RefCell::new(DrawnState::default()), // Not valid, so the first draw is a full one
 
             disp_dimensions: self.disp_dimensions,
             display_refcell,
@@ -156,6 +201,10 @@ impl<'a> CustomTextboxBuilder<'a> {
             character_style: self.character_style,
             primitives_style: self.primitives_style,
             primitives_alternate_style: self.primitives_alternate_style,
+            overflow_indicators: self.overflow_indicators,
+            prompt: self.prompt,
+            placeholder: self.placeholder,
+            validator: self.validator,
         }
     }
 
@@ -165,111 +214,406 @@ impl<'a> CustomTextboxBuilder<'a> {
         self
     }
 
-    pub const fn set_character_style(mut self, character_style: MonoTextStyle<'a, BinaryColor>) -> Self {
+    pub const fn set_character_style(mut self, character_style: MonoTextStyle<'a, C>) -> Self {
         self.character_style = character_style;
         self
     }
 
-    pub const fn set_primitives_style(mut self, primitives_style: PrimitiveStyle<BinaryColor>) -> Self {
+    pub const fn set_primitives_style(mut self, primitives_style: PrimitiveStyle<C>) -> Self {
         self.primitives_style = primitives_style;
         self
     }
 
-    pub const fn set_primitives_alternate_style(mut self, primitives_alternate_style: PrimitiveStyle<BinaryColor>) -> Self {
+    pub const fn set_primitives_alternate_style(mut self, primitives_alternate_style: PrimitiveStyle<C>) -> Self {
         self.primitives_alternate_style = primitives_alternate_style;
         self
     }
+
+    /// Whether to draw a dotted line at the left or right edge when the text is scrolled
+    /// and continues past that edge of the display.
+    pub const fn set_overflow_indicators(mut self, overflow_indicators: bool) -> Self {
+        self.overflow_indicators = overflow_indicators;
+        self
+    }
+
+    /// Sets a prompt (e.g. `"> "`) drawn before the input. It's not a part of the text, see `CustomTextbox::set_prompt()`.
+    pub const fn set_prompt(mut self, prompt: &'a str) -> Self {
+        self.prompt = prompt;
+        self
+    }
+
+    /// Sets a hint drawn dimmed in place of the input while it's empty, see `CustomTextbox::set_placeholder()`.
+    pub const fn set_placeholder(mut self, placeholder: &'a str) -> Self {
+        self.placeholder = placeholder;
+        self
+    }
+
+    /// Sets the validator of typed chars, see `Validator`. By default, anything goes.
+    pub const fn set_validator(mut self, validator: Validator) -> Self {
+        self.validator = Some(validator);
+        self
+    }
 }
 
 // ------------------------------------------------------------------------------------------------------------------------------------------------
 
 #[allow(dead_code)]
-pub struct CustomTextbox<'a, DI, SIZE>
+pub struct CustomTextbox<'a, D>
 where
-    DI: WriteOnlyDataCommand,
-    SIZE: DisplaySize,
+    D: FlushableDisplay,
 {
     text: String<TEXT_BUFFER_SIZE>,
+    /// Where the next character gets inserted, as a byte index into `text` (always on a char boundary)
+    cursor: usize,
+    /// How many chars are scrolled out of view on the left, so that the cursor stays visible in long input.
+    /// It's a Cell, because it's only updated in `draw()`, which only takes `&self`.
+    view_offset: Cell<usize>,
+    /// Whether anything changed since the last `draw()`, so that we can skip redundant redraws.
+    /// It's a Cell, because `draw()` only takes `&self`.
+    dirty: Cell<bool>,
+    /// What's on the display, for partial redraws. A RefCell for the same reason.
+    drawn: RefCell<DrawnState>,
 
     disp_dimensions: DisplayDimensions,
-    display_refcell: &'a RefCell<Ssd1306<DI, SIZE, BufferedGraphicsMode<SIZE>>>,
-
-    character_style: MonoTextStyle<'a, BinaryColor>,
-    primitives_style: PrimitiveStyle<BinaryColor>,
-    primitives_alternate_style: PrimitiveStyle<BinaryColor>,
+    display_refcell: &'a RefCell<D>,
+
+    character_style: MonoTextStyle<'a, D::Color>,
+    primitives_style: PrimitiveStyle<D::Color>,
+    primitives_alternate_style: PrimitiveStyle<D::Color>,
+    overflow_indicators: bool,
+    prompt: &'a str,
+    placeholder: &'a str,
+    validator: Option<Validator>,
 }
 
 #[allow(dead_code)]
-impl<'a, DI, SIZE> CustomTextbox<'a, DI, SIZE>
+impl<'a, D> CustomTextbox<'a, D>
 where
-    DI: WriteOnlyDataCommand,
-    SIZE: DisplaySize,
+    D: FlushableDisplay,
 {
-    pub fn draw(&self, flush: bool) -> Result<(), CustomError> {
+    /// Sets the prompt drawn before the input, e.g. to tell the command mode apart from number entry.
+    /// It's excluded from `get_text_str()` and friends. An empty string means no prompt.
+    pub fn set_prompt(&mut self, prompt: &'a str) {
+        self.invalidate(); // Everything moves
+        self.prompt = prompt;
+    }
+
+    pub fn prompt(&self) -> &'a str {
+        self.prompt
+    }
+
+    /// Sets the hint drawn dimmed in place of the input while it's empty. An empty string means no placeholder.
+    pub fn set_placeholder(&mut self, placeholder: &'a str) {
+        self.invalidate();
+        self.placeholder = placeholder;
+    }
+
+    pub fn placeholder(&self) -> &'a str {
+        self.placeholder
+    }
+
+    /// Sets the validator of typed chars (see `Validator`), or removes it with `None`.
+    /// Doesn't touch the text that's already there.
+    pub fn set_validator(&mut self, validator: Option<Validator>) {
+        self.validator = validator;
+    }
+
+    pub fn validator(&self) -> Option<Validator> {
+        self.validator
+    }
+
+    /// Forces the next `draw()` to actually redraw, e.g. when something else has drawn over the textbox's area.
+    pub fn invalidate(&self) {
+        self.dirty.set(true);
+        self.drawn.borrow_mut().valid = false;
+    }
+
+    /// Draws the textbox onto the display, unless nothing changed since the last time.
+    /// Even then, the display is still flushed if `flush` is true, since something else might've drawn onto it.
+    pub fn draw(&self, flush: bool) -> Result<(), CustomError>
+    where
+        CustomError: From<D::Error>, // So that we can use `?` on the drawing, whatever the display's error is
+    {
+        if !self.dirty.get() {
+            if flush { self.display_refcell.borrow_mut().flush_display()?; };
+            return Ok(());
+        }
+        // We only mark it clean after successfully drawing, so that an error makes us try again next time
+
         let text_height = self.character_style.font.character_size.height - PIXELS_REMOVED;
         let textbox_height = text_height + TEXTBOX_OFFSET;
+        let top = self.disp_dimensions.height - textbox_height;
+
+        // Scroll the view just enough to keep the cursor visible, and no further.
+        // We count in chars, not pixels; the cursor needs a cell of its own after the last char.
+        // The prompt stays put, only the input after it scrolls
+        let char_width = self.character_style.font.character_size.width;
+        let prompt_chars = self.prompt.chars().count();
+        let text_x = prompt_chars as u32 * char_width;
+        let max_chars = core::cmp::max((self.disp_dimensions.width / char_width) as usize, prompt_chars + 1) - prompt_chars;
+        let cursor_chars = self.text[..self.cursor].chars().count();
+        let total_chars = self.text.chars().count();
+
+        let mut offset = self.view_offset.get()
+            .min(cursor_chars) // The cursor went left of the view
+            .min((total_chars + 1).saturating_sub(max_chars)); // Text got deleted, don't leave empty space on the right
+        if cursor_chars >= offset + max_chars { // The cursor went right of the view
+            offset = cursor_chars + 1 - max_chars;
+        }
+        self.view_offset.set(offset);
+
+        // Byte indices of the visible part, so that we can slice the text
+        let start = self.text.char_indices().nth(offset).map_or(self.text.len(), |(i, _)| i);
+        let end = self.text.char_indices().nth(offset + max_chars).map_or(self.text.len(), |(i, _)| i);
+
+        let new_state = DrawnState {
+            visible: String::try_from(&self.text[start..end])?, // Can't fail, it's a part of the text
+            cursor_cell: cursor_chars - offset,
+            offset,
+            placeholder_shown: self.text.is_empty() && !self.placeholder.is_empty(),
+            right_overflow: end < self.text.len(),
+            valid: true,
+        };
 
+        let mut drawn = self.drawn.borrow_mut();
         let mut display_refmut = self.display_refcell.borrow_mut();
         let display_ref = &mut (*display_refmut); // Unpack the RefMut to get the inner struct, then get a mutable reference to it
         // In method calls, the compiler does this for us, but not so when we need to pass a reference to `draw()`
 
-        /* Yes, we could first create the structs and then draw them all at once,
-        to minimize the critical section of RefCell, but in reality it's not worth it.
-        The creation functions are really brief anyways. */
-        
-        // Clearing rectangle so that we don't draw over previously present text
-        Rectangle::with_corners(
-            (0, self.disp_dimensions.height - 1).try_into()?, // Bottom right corner
-            // (even though the method itself doesn't care, any two diagonally opposite corners would fly)
-            (
-                self.disp_dimensions.width - 1,
-                (self.disp_dimensions.height - textbox_height)
-            ).try_into()? // Top left corner
-        )
-        .into_styled(self.primitives_alternate_style)
-        .draw(display_ref)?;
-
-        // The actual text
-        Text::with_baseline(
-            self.text.as_str(),
-            (0, (self.disp_dimensions.height - textbox_height)).try_into()?, // Top left corner
-            self.character_style,
-            Baseline::Top
-        )
-        .draw(display_ref)?;
-
-        // The cursor
-        if TEXTBOX_CURSOR {
-            Rectangle::new(
-                (
-                    self.text.chars().count() as u32 * self.character_style.font.character_size.width, 
-                    (self.disp_dimensions.height - CURSOR_HEIGHT)
-                ).try_into()?,
-                (
-                    self.character_style.font.character_size.width,
-                    CURSOR_HEIGHT
-                ).into()
+        let dotted_line = |x: u32| -> Result<_, CustomError> {
+            let (x, top, bottom) = (i32::try_from(x)?, i32::try_from(top)?, i32::try_from(self.disp_dimensions.height)? - 1);
+            // Dotted (and dimmed, where possible), so that it can't be mistaken for a glyph
+            Ok(Line::new(Point::new(x, top), Point::new(x, bottom))
+                .points()
+                .step_by(2)
+                .map(|p| Pixel(p, D::Color::DIMMED)))
+        };
+        let left_indicator_x = text_x;
+        let right_indicator_x = self.disp_dimensions.width - 1;
+
+        // If nothing but the text and cursor changed, we only redraw the cells that differ.
+        // The display driver then only sends the changed area over I²C, instead of the whole textbox.
+        if drawn.valid
+            && drawn.offset == new_state.offset
+            && drawn.right_overflow == new_state.right_overflow
+            && !drawn.placeholder_shown && !new_state.placeholder_shown
+        {
+            let mut old_chars = drawn.visible.chars();
+            let mut new_chars = new_state.visible.chars();
+            for cell in 0..max_chars {
+                let (old_char, new_char) = (old_chars.next(), new_chars.next());
+                if old_char == new_char && cell != drawn.cursor_cell && cell != new_state.cursor_cell {
+                    continue;
+                }
+
+                let cell_x = text_x + cell as u32 * char_width;
+                // The whole column down to the bottom, including the cursor and any glyph parts reaching into its space
+                Rectangle::new(Point::try_from((cell_x, top))?, (char_width, textbox_height).into())
+                    .into_styled(self.primitives_alternate_style)
+                    .draw(display_ref)?;
+                if let Some(c) = new_char {
+                    let mut char_buf = [0_u8; 4];
+                    Text::with_baseline(c.encode_utf8(&mut char_buf), Point::try_from((cell_x, top))?, self.character_style, Baseline::Top)
+                        .draw(display_ref)?;
+                }
+                if TEXTBOX_CURSOR && cell == new_state.cursor_cell {
+                    Rectangle::new(
+                        Point::try_from((cell_x, self.disp_dimensions.height - CURSOR_HEIGHT))?,
+                        (char_width, CURSOR_HEIGHT).into()
+                    )
+                    .into_styled(self.primitives_style)
+                    .draw(display_ref)?;
+                }
+
+                // We might've erased a part of an indicator, but we mustn't draw them needlessly,
+                // since that would make the driver send the whole width of the display again
+                let cell_range = cell_x..(cell_x + char_width);
+                if self.overflow_indicators && offset > 0 && cell_range.contains(&left_indicator_x) {
+                    display_ref.draw_iter(dotted_line(left_indicator_x)?)?;
+                }
+                if self.overflow_indicators && new_state.right_overflow && cell_range.contains(&right_indicator_x) {
+                    display_ref.draw_iter(dotted_line(right_indicator_x)?)?;
+                }
+            }
+        } else {
+            /* Yes, we could first create the structs and then draw them all at once,
+            to minimize the critical section of RefCell, but in reality it's not worth it.
+            The creation functions are really brief anyways. */
+
+            // Clearing rectangle so that we don't draw over previously present text
+            Rectangle::with_corners(
+                Point::try_from((0, self.disp_dimensions.height - 1))?, // Bottom right corner
+                // (even though the method itself doesn't care, any two diagonally opposite corners would fly)
+                // Explicit, the `From<D::Error>` bound confuses inference
+                Point::try_from((
+                    self.disp_dimensions.width - 1,
+                    top
+                ))? // Top left corner
             )
-            .into_styled(self.primitives_style)
+            .into_styled(self.primitives_alternate_style)
             .draw(display_ref)?;
-        };
-        if flush { display_ref.flush()?; };
+
+            if !self.prompt.is_empty() {
+                Text::with_baseline(
+                    self.prompt,
+                    Point::try_from((0, top))?, // Top left corner
+                    self.character_style,
+                    Baseline::Top
+                )
+                .draw(display_ref)?;
+            }
+
+            if new_state.placeholder_shown {
+                // It's only a hint, so it doesn't scroll, it's simply cut off by the display edge
+                Text::with_baseline(
+                    self.placeholder,
+                    Point::try_from((text_x, top))?,
+                    self.character_style,
+                    Baseline::Top
+                )
+                .draw(&mut Dimmed(display_ref))?;
+            }
+
+            // The actual text
+            Text::with_baseline(
+                new_state.visible.as_str(),
+                Point::try_from((text_x, top))?, // Top left corner, after the prompt
+                self.character_style,
+                Baseline::Top
+            )
+            .draw(display_ref)?;
+
+            if self.overflow_indicators {
+                if offset > 0 {
+                    display_ref.draw_iter(dotted_line(left_indicator_x)?)?;
+                }
+                if new_state.right_overflow {
+                    display_ref.draw_iter(dotted_line(right_indicator_x)?)?;
+                }
+            }
+
+            // The cursor, under the character it's in front of
+            if TEXTBOX_CURSOR {
+                Rectangle::new(
+                    Point::try_from((
+                        text_x + new_state.cursor_cell as u32 * char_width,
+                        (self.disp_dimensions.height - CURSOR_HEIGHT)
+                    ))?,
+                    (
+                        char_width,
+                        CURSOR_HEIGHT
+                    ).into()
+                )
+                .into_styled(self.primitives_style)
+                .draw(display_ref)?;
+            };
+        }
+
+        *drawn = new_state;
+        self.dirty.set(false);
+        if flush { display_ref.flush_display()?; };
 
         Ok(())
     }
 
-    // Append a str at the end of textbox
+    // Insert a str at the cursor (normally at the end of textbox), moving the cursor after it
+    // Returns `BadInput` if the validator rejects any of the chars, in which case none of them are inserted
     pub fn append_str(&mut self, string: &str) -> Result<(), CustomError> {
-        // We do not check for buffer overflow, as `push_str` will do that for us
+        if let Some(validator) = self.validator {
+            // Each char is validated as if the ones before it were already inserted
+            let mut text = self.text.clone();
+            let mut cursor = self.cursor;
+            for c in string.chars() {
+                if !validator(&text, cursor, c) {
+                    return Err(CE::BadInput);
+                }
+                text.insert(cursor, c)?;
+                cursor += c.len_utf8();
+            }
+        }
 
-        // We don't need `map_err(|_| e.into())` for the zero-sized `CapacityError`,
-        // and like this it's perhaps a bit clearer than `Ok(push_str(...)?)`
-        self.text.push_str(string).map_err(|_| CE::CapacityError)
+        // We do not check for buffer overflow, as `insert_str` will do that for us
+        self.dirty.set(true);
+        self.text.insert_str(self.cursor, string)?;
+        self.cursor += string.len();
+        Ok(())
     }
 
-    // Append a single char at the end of the textbox
+    // Insert a single char at the cursor (normally at the end of textbox), moving the cursor after it
+    // Returns `BadInput` if the validator rejects it
     pub fn append_char(&mut self, c: char) -> Result<(), CustomError> {
-        self.text.push(c).map_err(|_| CE::CapacityError)
+        if let Some(validator) = self.validator
+            && !validator(&self.text, self.cursor, c)
+        {
+            return Err(CE::BadInput);
+        }
+        self.dirty.set(true);
+        self.text.insert(self.cursor, c)?;
+        self.cursor += c.len_utf8();
+        Ok(())
+    }
+
+    /// Moves the cursor one character to the left. Returns false if it's already at the start.
+    pub fn cursor_left(&mut self) -> bool {
+        let Some(c) = self.text[..self.cursor].chars().next_back() else {
+            return false;
+        };
+        self.dirty.set(true);
+        self.cursor -= c.len_utf8();
+        true
+    }
+
+    /// Moves the cursor one character to the right. Returns false if it's already at the end.
+    pub fn cursor_right(&mut self) -> bool {
+        let Some(c) = self.text[self.cursor..].chars().next() else {
+            return false;
+        };
+        self.dirty.set(true);
+        self.cursor += c.len_utf8();
+        true
+    }
+
+    /// Moves the cursor to the start of the text (like Ctrl-A in a shell).
+    pub fn home(&mut self) {
+        self.dirty.set(true);
+        self.cursor = 0;
+    }
+
+    /// Moves the cursor to the end of the text (like Ctrl-E in a shell).
+    pub fn end(&mut self) {
+        self.dirty.set(true);
+        self.cursor = self.text.len();
+    }
+
+    /// Removes the word before the cursor, along with any whitespace between it and the cursor (like Ctrl-W in a shell).
+    /// Returns false if there was nothing to remove.
+    pub fn delete_word_back(&mut self) -> bool {
+        let before = &self.text[..self.cursor];
+        // We first skip the whitespace, then find the start of the word
+        let word_end = before.trim_end().len();
+        let word_start = before[..word_end].rfind(char::is_whitespace)
+            .map_or(0, |i| i + 1); // Whitespace is ASCII, i.e. always one byte long
+        if word_start == self.cursor {
+            return false;
+        }
+
+        self.dirty.set(true);
+        self.text.drain(word_start..self.cursor);
+        self.cursor = word_start;
+        true
+    }
+
+    /// Removes everything before the cursor, keeping the rest (like Ctrl-U in a shell).
+    /// With the cursor at the end, that's the whole line.
+    pub fn clear_line(&mut self) {
+        self.dirty.set(true);
+        self.text.drain(..self.cursor);
+        self.cursor = 0;
+    }
+
+    /// Returns the position of the cursor as a byte index into the text.
+    pub fn cursor(&self) -> usize {
+        self.cursor
     }
 
     /// Returns a cloned String of the textbox's text
@@ -281,22 +625,27 @@ where
         self.text.as_str()
     }
 
-    // Pops the last `count` chars at the end
+    // Removes `count` chars before the cursor (normally at the end)
     pub fn backspace(&mut self, count: usize) -> Result<(), CustomError> {
-        if self.text.len() < count {
+        if self.cursor < count {
             return Err(CE::BadInput);
         }
+        self.dirty.set(true);
 
-        if self.text.is_ascii() {
-            // More efficient, but in current implementation requires ASCII-only text
+        if self.cursor == self.text.len() && self.text.is_ascii() {
+            // More efficient, but in current implementation requires ASCII-only text and the cursor at the end
             // In my unscientific benchmarks, this is ~85 µs faster for 1 character on dev build
             // Grace Hopper would be proud, that's a save of about 85 000 nanoseconds! :D
             self.text.truncate(self.text.len() - count);
-        } else {
-            // Fallback, could be slower
-            for _ in 0..count {
-                self.text.pop().expect("We already checked, this shouldn't be possible!");
-            }
+            self.cursor = self.text.len();
+        } else if count > 0 {
+            // Fallback, could be slower: find where the removed chars start and cut them out
+            let start = self.text[..self.cursor].char_indices()
+                .nth_back(count - 1)
+                .ok_or(CE::BadInput)? // Less than `count` (multi-byte) chars before the cursor
+                .0;
+            self.text.drain(start..self.cursor);
+            self.cursor = start;
         }
         
         Ok(())
@@ -336,7 +685,11 @@ where
         }
         
         // Checks for capacity overflow by itself
+        self.dirty.set(true);
         self.text.insert(index, c)?;
+        if index <= self.cursor {
+            self.cursor += c.len_utf8(); // So that it stays in front of the same character
+        }
         Ok(())
     }
     pub fn insert_str_at(&mut self, index: usize, string: &str) -> Result<(), CustomError> {
@@ -348,7 +701,11 @@ where
         }
         
         // Checks for capacity overflow by itself
+        self.dirty.set(true);
         self.text.insert_str(index, string)?;
+        if index <= self.cursor {
+            self.cursor += string.len();
+        }
         Ok(())
     }
 
@@ -360,11 +717,18 @@ where
             return Err(CE::BadInput);
         }
 
-        Ok(self.text.remove(index))
+        self.dirty.set(true);
+        let c = self.text.remove(index);
+        if index < self.cursor {
+            self.cursor -= c.len_utf8();
+        }
+        Ok(c)
     }
 
     pub fn clear(&mut self) {
+        self.dirty.set(true);
         self.text.clear();
+        self.cursor = 0;
     }
 
     pub fn len(&self) -> usize {
@@ -374,4 +738,45 @@ where
     pub fn is_empty(&self) -> bool {
         self.text.len() == 0
     }
-}
\ No newline at end of file
+}
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::numeric_validator;
+
+    /// Whether typing `c` at the end of `text` is allowed
+    fn accepts(text: &str, c: char) -> bool {
+        numeric_validator(text, text.len(), c)
+    }
+
+    #[test]
+    fn mantissa() {
+        assert!(accepts("", '-') && accepts("", '5') && accepts("-", '.'));
+        assert!(accepts("1.5", '2'));
+        assert!(!accepts("1.5", '.'));
+        assert!(!accepts("1", '-'));
+        assert!(!accepts("1", 'x'));
+        // Nothing goes before the minus sign
+        assert!(!numeric_validator("-1", 0, '2'));
+        assert!(!numeric_validator("-1", 0, '-'));
+        assert!(numeric_validator("1", 0, '-'));
+    }
+
+    #[test]
+    fn exponent() {
+        assert!(accepts("1.6", 'e') && accepts("1.6e", '-') && accepts("1.6e-", '1'));
+        assert!(accepts("1.6e", '9'));
+        // Needs a digit of the mantissa first, and there's only one exponent
+        assert!(!accepts("", 'e') && !accepts("-", 'e') && !accepts("-.", 'e'));
+        assert!(!accepts("1e5", 'e'));
+        // No decimal point and only one minus sign in the exponent
+        assert!(!accepts("1e", '.') && !accepts("1e5", '.'));
+        assert!(!accepts("1e-", '-') && !accepts("1e5", '-'));
+        // Nor before its minus sign
+        assert!(!numeric_validator("1e-5", 2, '3'));
+        // An `e` in the middle of the mantissa mustn't leave the decimal point in the exponent
+        assert!(!numeric_validator("12.5", 1, 'e'));
+        assert!(numeric_validator("125", 2, 'e'));
+    }
+}
@@ -0,0 +1,102 @@
+use embedded_graphics::{
+    prelude::*,
+
+    mono_font::{
+        iso_8859_2::FONT_6X12 as ISO_FONT_6X12,
+        MonoTextStyle,
+    },
+    primitives::{
+        PrimitiveStyle,
+        PrimitiveStyleBuilder,
+        Rectangle,
+    },
+    text::{
+        Alignment,
+        Baseline,
+        Text,
+        TextStyleBuilder,
+    },
+};
+
+use crate::display::{FlushableDisplay, Palette};
+use crate::custom_error::CustomError; // Because we already have the `mod` in `main.rs`
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// How long a toast stays on the display, in microseconds
+const TOAST_DURATION_US: u64 = 2_000_000;
+/// Height of the banner, enough for a line of text with a border around it
+const BANNER_HEIGHT: u32 = 18;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Black with a white border, so that it stands out from the stack behind it
+const fn banner_style<C: Palette>() -> PrimitiveStyle<C> {
+    PrimitiveStyleBuilder::new()
+        .fill_color(C::BACKGROUND)
+        .stroke_color(C::FOREGROUND)
+        .stroke_width(1)
+        .build()
+}
+
+const fn message_style<C: Palette>() -> MonoTextStyle<'static, C> {
+    MonoTextStyle::new(&ISO_FONT_6X12, C::FOREGROUND)
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// A temporary banner with a short message (usually an error) across the middle of the display.
+///
+/// It only remembers until when it's to be shown, the caller is responsible for redrawing whatever it covered
+/// once it's expired (or dismissed), usually by invalidating and drawing the stack and the textbox.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Toast {
+    /// Timestamp (see `get_timestamp_us()`) when it's to be hidden, `None` if it isn't shown
+    until: Option<u64>,
+}
+
+impl Toast {
+    pub const fn new() -> Self {
+        Toast { until: None }
+    }
+
+    /// Draws the message and flushes the display. `now` is the current timestamp in microseconds.
+    pub fn show<D>(&mut self, disp: &mut D, message: &str, now: u64) -> Result<(), CustomError>
+    where
+        D: FlushableDisplay,
+        CustomError: From<D::Error>,
+    {
+        let area = disp.bounding_box();
+        let banner = Rectangle::new(
+            Point::new(area.top_left.x, area.center().y - (BANNER_HEIGHT / 2) as i32),
+            Size::new(area.size.width, BANNER_HEIGHT),
+        );
+        banner.into_styled(banner_style()).draw(disp)?;
+
+        // Too long messages just get cut off by the display's edges
+        let text_style = TextStyleBuilder::new()
+            .alignment(Alignment::Center)
+            .baseline(Baseline::Middle)
+            .build();
+        Text::with_text_style(message, banner.center(), message_style(), text_style).draw(disp)?;
+        disp.flush_display()?;
+
+        self.until = Some(now + TOAST_DURATION_US);
+        Ok(())
+    }
+
+    pub fn is_shown(&self) -> bool {
+        self.until.is_some()
+    }
+
+    /// Whether it's shown, but its time is up, so it's to be hidden.
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.until.is_some_and(|until| now >= until)
+    }
+
+    /// Forgets about the toast, the caller has to redraw what it covered.
+    pub fn dismiss(&mut self) {
+        self.until = None;
+    }
+}
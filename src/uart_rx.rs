@@ -0,0 +1,21 @@
+use rp2040_hal as hal;
+use hal::uart::{ReadErrorType, Reader, UartDevice, ValidUartPinout};
+
+/// Where we get the input bytes from, so that the rest of the code doesn't care whether they're read from UART directly or by the DMA.
+pub trait UartRx {
+    /// Blocks until the whole buffer gets filled.
+    fn read_full_blocking(&self, buf: &mut [u8]) -> Result<(), ReadErrorType>;
+    /// Reads whatever has arrived already without blocking, returns how many bytes were read (zero if none).
+    fn read_available(&self, buf: &mut [u8]) -> usize;
+}
+
+impl<D: UartDevice, P: ValidUartPinout<D>> UartRx for Reader<D, P> {
+    fn read_full_blocking(&self, buf: &mut [u8]) -> Result<(), ReadErrorType> {
+        Reader::read_full_blocking(self, buf) // The inherent method, not this one
+    }
+
+    fn read_available(&self, buf: &mut [u8]) -> usize {
+        // The errors are either "nothing arrived yet", or ones we can't do anything about anyway
+        self.read_raw(buf).unwrap_or(0)
+    }
+}
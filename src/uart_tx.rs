@@ -0,0 +1,81 @@
+use rp2040_hal as hal;
+use hal::uart::{UartDevice, ValidUartPinout, Writer};
+
+use crate::charset;
+use crate::log;
+use crate::settings::Settings;
+
+/// Writes the bytes out, turning the `\r\n` line endings we use everywhere into plain `\n`s if `crlf` is off.
+/// A lone `\r` stays, since it's not a line ending.
+pub fn write<D, P>(tx: &Writer<D, P>, bytes: &[u8], crlf: bool)
+where
+    D: UartDevice,
+    P: ValidUartPinout<D>,
+{
+    if crlf {
+        write_raw(tx, bytes);
+        return;
+    }
+
+    // Write everything up to each `\r\n`, then skip its `\r`
+    let mut rest = bytes;
+    while let Some(i) = rest.windows(2).position(|pair| pair == b"\r\n") {
+        write_raw(tx, &rest[..i]);
+        rest = &rest[(i + 1)..];
+    }
+    write_raw(tx, rest);
+}
+
+/// Echoes a received char back if the echo is on, for terminals that don't echo locally.
+/// Enter echoes a line ending, backspace erases the last char on the terminal, other control chars aren't echoed.
+pub fn echo<D, P>(tx: &Writer<D, P>, c: char, settings: &Settings)
+where
+    D: UartDevice,
+    P: ValidUartPinout<D>,
+{
+    if !settings.echo {
+        return;
+    }
+
+    match c {
+        '\r' | '\n' => write(tx, b"\r\n", settings.crlf),
+        '\x08' | '\x7F' => write_raw(tx, b"\x08 \x08"), // Back, overwrite with a space, back again
+        c if charset::is_printable(c) => {
+            let mut buf = [0_u8; 4];
+            write_raw(tx, c.encode_utf8(&mut buf).as_bytes());
+        },
+        _ => {},
+    }
+}
+
+/// Sends out the log lines queued since the last call, if the log is mirrored to UART (see the `log` command).
+pub fn drain_log_mirror<D, P>(tx: &Writer<D, P>, crlf: bool)
+where
+    D: UartDevice,
+    P: ValidUartPinout<D>,
+{
+    log::drain_mirror(|line| {
+        write_raw(tx, line.as_bytes());
+        write(tx, b"\r\n", crlf);
+    });
+}
+
+/// Writes binary data out as it is, whatever `crlf` says, e.g. a frame of the host protocol (see `hostlink`).
+pub fn write_binary<D, P>(tx: &Writer<D, P>, bytes: &[u8])
+where
+    D: UartDevice,
+    P: ValidUartPinout<D>,
+{
+    write_raw(tx, bytes);
+}
+
+/// Writes the bytes out as they are, to the USB serial port as well if it's enabled.
+fn write_raw<D, P>(tx: &Writer<D, P>, bytes: &[u8])
+where
+    D: UartDevice,
+    P: ValidUartPinout<D>,
+{
+    tx.write_full_blocking(bytes);
+    #[cfg(feature = "usb")]
+    crate::usb::write(bytes);
+}
@@ -0,0 +1,289 @@
+//! A USB serial port (CDC ACM) taking input alongside the UART, and mirroring the output to it, see `UsbOrUart`.
+//!
+//! The CDC ACM class is our own rather than `usbd_serial::SerialPort`, because that one buffers both directions by itself:
+//! - Receiving, a packet is only taken once it's sure to fit, leaving the host NAKed until the main program
+//!   makes room, which needs the endpoint itself rather than a buffer in between that would drop or hold the bytes on its own.
+//! - Sending, the bytes are dropped while no terminal has the port open (DTR), or once the host stops taking them for a while,
+//!   so that we never hang; `SerialPort` would keep them in its buffer and leave flushing them to us.
+//!
+//! What's left, the descriptors and the line coding and control line state requests, is little enough that the class only needs `usb-device`.
+
+use core::cell::RefCell;
+use cortex_m::interrupt::{self as cs_interrupt, Mutex};
+use heapless::Deque;
+use rp2040_hal::{self as hal, pac::{self, interrupt}};
+use hal::uart::ReadErrorType;
+use usb_device::{
+    class_prelude::*,
+    control::{Recipient, RequestType},
+    prelude::*,
+};
+
+use crate::uart_rx::UartRx;
+use crate::get_timestamp_us;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// The pid.codes test VID/PID for CDC ACM devices, the same one `usbd-serial`'s examples use
+const VID_PID: UsbVidPid = UsbVidPid(0x16C0, 0x27DD);
+/// Max packet size of the bulk endpoints, the most full-speed USB allows
+const PACKET_SIZE: u16 = 64;
+/// How many received bytes can wait to be read, the host is told to wait (NAKed) when it's full
+const RX_SIZE: usize = 256;
+/// How much room the receive buffer needs for another packet
+const PACKET_ROOM: usize = PACKET_SIZE as usize;
+/// How long a write waits for the host to take the previous packet before dropping the rest, in microseconds.
+/// Without a terminal reading the port, we mustn't hang.
+const WRITE_TIMEOUT_US: u64 = 10_000;
+
+const USB_CLASS_CDC: u8 = 0x02;
+const USB_CLASS_CDC_DATA: u8 = 0x0A;
+const CDC_SUBCLASS_ACM: u8 = 0x02;
+const CDC_PROTOCOL_NONE: u8 = 0x00;
+const CS_INTERFACE: u8 = 0x24;
+const CDC_TYPE_HEADER: u8 = 0x00;
+const CDC_TYPE_CALL_MANAGEMENT: u8 = 0x01;
+const CDC_TYPE_ACM: u8 = 0x02;
+const CDC_TYPE_UNION: u8 = 0x06;
+const REQ_SET_LINE_CODING: u8 = 0x20;
+const REQ_GET_LINE_CODING: u8 = 0x21;
+const REQ_SET_CONTROL_LINE_STATE: u8 = 0x22;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// A bare-bones CDC ACM (virtual serial port) class, just enough for terminals to talk to us.
+/// The line coding is stored and reported back, but means nothing, there's no real UART behind it.
+struct CdcAcm<'a, B: UsbBus> {
+    comm_if: InterfaceNumber,
+    comm_ep: EndpointIn<'a, B>,
+    data_if: InterfaceNumber,
+    read_ep: EndpointOut<'a, B>,
+    write_ep: EndpointIn<'a, B>,
+    /// Baud rate (little-endian u32), stop bits, parity and data bits, as set by the host
+    line_coding: [u8; 7],
+    /// Whether a terminal has the port open
+    dtr: bool,
+}
+
+impl<'a, B: UsbBus> CdcAcm<'a, B> {
+    fn new(alloc: &'a UsbBusAllocator<B>) -> Self {
+        CdcAcm {
+            comm_if: alloc.interface(),
+            comm_ep: alloc.interrupt(8, 255),
+            data_if: alloc.interface(),
+            read_ep: alloc.bulk(PACKET_SIZE),
+            write_ep: alloc.bulk(PACKET_SIZE),
+            line_coding: [0x00, 0xC2, 0x01, 0x00, 0, 0, 8], // 115200 8N1
+            dtr: false,
+        }
+    }
+
+    /// Whether the request is a class request meant for our communication interface
+    fn is_ours(&self, req: &control::Request) -> bool {
+        req.request_type == RequestType::Class
+            && req.recipient == Recipient::Interface
+            && req.index == u8::from(self.comm_if) as u16
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for CdcAcm<'_, B> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> usb_device::Result<()> {
+        writer.interface(self.comm_if, USB_CLASS_CDC, CDC_SUBCLASS_ACM, CDC_PROTOCOL_NONE)?;
+        writer.write(CS_INTERFACE, &[CDC_TYPE_HEADER, 0x10, 0x01])?; // CDC 1.10
+        writer.write(CS_INTERFACE, &[CDC_TYPE_CALL_MANAGEMENT, 0x00, self.data_if.into()])?;
+        writer.write(CS_INTERFACE, &[CDC_TYPE_ACM, 0x02])?; // Supports the line coding and control line state requests
+        writer.write(CS_INTERFACE, &[CDC_TYPE_UNION, self.comm_if.into(), self.data_if.into()])?;
+        writer.endpoint(&self.comm_ep)?;
+
+        writer.interface(self.data_if, USB_CLASS_CDC_DATA, 0x00, 0x00)?;
+        writer.endpoint(&self.write_ep)?;
+        writer.endpoint(&self.read_ep)
+    }
+
+    fn reset(&mut self) {
+        self.dtr = false;
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+        if !self.is_ours(&req) {
+            return;
+        }
+        // Errors mean the host went away mid-transfer, it'll ask again
+        let _ = match req.request {
+            REQ_GET_LINE_CODING => xfer.accept_with(&self.line_coding),
+            _ => xfer.reject(),
+        };
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+        if !self.is_ours(&req) {
+            return;
+        }
+        let _ = match req.request {
+            REQ_SET_LINE_CODING if xfer.data().len() >= self.line_coding.len() => {
+                self.line_coding.copy_from_slice(&xfer.data()[..7]);
+                xfer.accept()
+            },
+            REQ_SET_CONTROL_LINE_STATE => {
+                self.dtr = req.value & 0x01 != 0;
+                xfer.accept()
+            },
+            _ => xfer.reject(),
+        };
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Everything the USB interrupt needs, shared with the main program through `USB`
+struct UsbSerial {
+    device: UsbDevice<'static, hal::usb::UsbBus>,
+    cdc: CdcAcm<'static, hal::usb::UsbBus>,
+    rx: Deque<u8, RX_SIZE>,
+}
+
+static USB: Mutex<RefCell<Option<UsbSerial>>> = Mutex::new(RefCell::new(None));
+
+/// Sets up the USB device and starts serving it from the USB interrupt.
+/// Call only once, the bus allocator is a singleton.
+pub fn init(regs: pac::USBCTRL_REGS, dpram: pac::USBCTRL_DPRAM, clock: hal::clocks::UsbClock, resets: &mut pac::RESETS) {
+    let bus = hal::usb::UsbBus::new(regs, dpram, clock, true, resets);
+    let alloc = cortex_m::singleton!(: UsbBusAllocator<hal::usb::UsbBus> = UsbBusAllocator::new(bus))
+        .expect("The USB bus is only initialised once");
+
+    let cdc = CdcAcm::new(alloc);
+    let device = UsbDeviceBuilder::new(alloc, VID_PID)
+        .strings(&[StringDescriptors::default()
+            .manufacturer("maturitni-projekt")
+            .product("RPN calculator")
+            .serial_number(option_env!("GIT_REVISION").unwrap_or("unknown"))])
+        .expect("Only one language is given")
+        .device_class(USB_CLASS_CDC)
+        .build();
+
+    cs_interrupt::free(|cs| USB.borrow(cs).replace(Some(UsbSerial { device, cdc, rx: Deque::new() })));
+    // SAFETY: The handler only touches `USB`, which is behind a mutex
+    unsafe { pac::NVIC::unmask(pac::Interrupt::USBCTRL_IRQ) };
+    log_info!("USB initialized, enumerating as a CDC ACM serial port");
+}
+
+impl UsbSerial {
+    /// Takes received packets for as long as they're sure to fit, otherwise they stay NAKed until there's room
+    fn take_packets(&mut self) {
+        let mut packet = [0_u8; PACKET_ROOM];
+        while room(&self.rx) >= PACKET_ROOM {
+            let Ok(n) = self.cdc.read_ep.read(&mut packet) else { break };
+            for &b in &packet[..n] {
+                let _ = self.rx.push_back(b); // Can't fail, we checked there's room
+            }
+        }
+    }
+}
+
+#[interrupt]
+fn USBCTRL_IRQ() {
+    cs_interrupt::free(|cs| {
+        let mut usb = USB.borrow(cs).borrow_mut();
+        let Some(usb) = usb.as_mut() else { return };
+        // Polled even when there's no room for input, the control endpoint and bus resets and suspends still need serving
+        if usb.device.poll(&mut [&mut usb.cdc]) {
+            usb.take_packets();
+        }
+        if room(&usb.rx) < PACKET_ROOM {
+            // A packet left unread would keep the interrupt firing over and over, starving the main program
+            // that's supposed to make room. Acknowledging it leaves it in the endpoint's buffer, which keeps the host NAKed,
+            // until `read_byte()` takes it.
+            let out_bit = 1 << (usb.cdc.read_ep.address().index() * 2 + 1);
+            // SAFETY: A write-1-to-clear of our own endpoint's bit, `UsbBus::read()` does the same once it takes the packet
+            unsafe { (*pac::USBCTRL_REGS::ptr()).buff_status().write(|w| w.bits(out_bit)) };
+        }
+    });
+}
+
+fn room(rx: &Deque<u8, RX_SIZE>) -> usize {
+    rx.capacity() - rx.len()
+}
+
+/// Takes one received byte, if there's any.
+/// Also takes a packet left in the endpoint by the interrupt once there's room for it, nothing else would.
+fn read_byte() -> Option<u8> {
+    cs_interrupt::free(|cs| {
+        let mut usb = USB.borrow(cs).borrow_mut();
+        let usb = usb.as_mut()?;
+        let byte = usb.rx.pop_front();
+        usb.take_packets();
+        byte.or_else(|| usb.rx.pop_front())
+    })
+}
+
+/// Whether a host has configured the device, i.e. it's plugged into a computer rather than just a charger
+pub fn is_configured() -> bool {
+    cs_interrupt::free(|cs| {
+        USB.borrow(cs).borrow().as_ref().is_some_and(|usb| usb.device.state() == UsbDeviceState::Configured)
+    })
+}
+
+/// Sends the bytes to the host if a terminal has the port open, otherwise drops them.
+/// Gives up on the rest if the host doesn't take a packet within `WRITE_TIMEOUT_US`.
+pub fn write(bytes: &[u8]) {
+    for packet in bytes.chunks(PACKET_ROOM) {
+        let deadline = get_timestamp_us() + WRITE_TIMEOUT_US;
+        loop {
+            // Leaving the critical section between attempts, so that the interrupt can do its job
+            let sent = cs_interrupt::free(|cs| match USB.borrow(cs).borrow_mut().as_mut() {
+                Some(usb) if usb.cdc.dtr => usb.cdc.write_ep.write(packet).map(|_| true),
+                _ => Ok(false), // Nobody's listening
+            });
+            match sent {
+                Ok(true) => break,
+                Ok(false) => return,
+                Err(UsbError::WouldBlock) if get_timestamp_us() < deadline => continue,
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Reads from the USB serial port and the UART, whichever has something, so that the rest of the code doesn't care.
+///
+/// The UART is only polled, so its read errors (e.g. a break from a disconnected wire) go unreported.
+pub struct UsbOrUart<R: UartRx> {
+    uart: R,
+}
+
+impl<R: UartRx> UsbOrUart<R> {
+    pub fn new(uart: R) -> Self {
+        UsbOrUart { uart }
+    }
+}
+
+impl<R: UartRx> UartRx for UsbOrUart<R> {
+    fn read_full_blocking(&self, buf: &mut [u8]) -> Result<(), ReadErrorType> {
+        for byte in buf.iter_mut() {
+            *byte = loop {
+                if let Some(b) = read_byte() {
+                    break b;
+                }
+                let mut one = [0_u8; 1];
+                if self.uart.read_available(&mut one) > 0 {
+                    break one[0];
+                }
+            };
+        }
+        Ok(())
+    }
+
+    fn read_available(&self, buf: &mut [u8]) -> usize {
+        let mut n = self.uart.read_available(buf);
+        while n < buf.len() && let Some(b) = read_byte() {
+            buf[n] = b;
+            n += 1;
+        }
+        n
+    }
+}
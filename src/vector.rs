@@ -0,0 +1,195 @@
+use core::fmt::{self, Display, LowerExp};
+use heapless::Vec;
+
+use crate::decfix::DecimalFixed;
+use crate::wide::{self, to_wide, from_wide, checked_mul_scaled};
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Most components a vector can have, i.e. it's at most 3D
+pub const MAX_DIMENSIONS: usize = 3;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// A 2D or 3D vector, as taken from (and put back onto) the stack one component per element, x deepest.
+///
+/// Like the stack, this is intentionally not generic, only for DecimalFixed.
+/// The components are kept at the default exponent, since multiplication and division need both operands to have the same one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vector {
+    components: Vec<DecimalFixed, MAX_DIMENSIONS>,
+}
+
+/// Writes the components as `[x, y, z]`, each one with the formatter's flags, e.g. `{:.2}` prints `[1.00, 2.50]`.
+impl Display for Vector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with(f, Display::fmt)
+    }
+}
+
+/// The same as `Display` without any flags, so that the logs show it the same
+impl defmt::Format for Vector {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(f, "[");
+        for (i, c) in self.components.iter().enumerate() {
+            if i > 0 {
+                defmt::write!(f, ", ");
+            }
+            defmt::write!(f, "{}", c);
+        }
+        defmt::write!(f, "]");
+    }
+}
+
+/// The same as `Display`, but with the components in scientific notation.
+impl LowerExp for Vector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with(f, LowerExp::fmt)
+    }
+}
+
+impl Vector {
+    /// Creates a vector from its components, x first. Returns `BadInput` unless there's two or three of them.
+    pub fn new(components: &[DecimalFixed]) -> Result<Self, CustomError> {
+        if !(2..=MAX_DIMENSIONS).contains(&components.len()) {
+            return Err(CE::BadInput);
+        }
+        let mut vector = Vector { components: Vec::new() };
+        for &c in components {
+            vector.components.push(c.with_exponent(None)?).map_err(|_| CE::Impossible)?; // We checked the length above
+        }
+        Ok(vector)
+    }
+
+    /// The components, x first
+    pub fn components(&self) -> &[DecimalFixed] {
+        &self.components
+    }
+
+    pub fn dimensions(&self) -> usize {
+        self.components.len()
+    }
+
+    /// Returns the dot (scalar) product, or `BadInput` if the vectors don't have the same dimensions.
+    pub fn dot(&self, other: &Vector) -> Result<DecimalFixed, CustomError> {
+        from_wide(self.wide_dot(other)?)
+    }
+
+    /// Returns the dot product in the fixed point of `wide.rs`, so that the products don't overflow where the result fits.
+    fn wide_dot(&self, other: &Vector) -> Result<i128, CustomError> {
+        if self.dimensions() != other.dimensions() {
+            return Err(CE::BadInput);
+        }
+        self.components.iter().zip(&other.components).try_fold(0_i128, |acc, (&a, &b)| {
+            acc.checked_add(checked_mul_scaled(to_wide(a)?, to_wide(b)?)?).ok_or(CE::MathOverflow)
+        })
+    }
+
+    /// Returns the cross product `self × other`, or `BadInput` unless both vectors are 3D.
+    pub fn cross(&self, other: &Vector) -> Result<Vector, CustomError> {
+        let (&[ax, ay, az], &[bx, by, bz]) = (self.components.as_slice(), other.components.as_slice()) else {
+            return Err(CE::BadInput);
+        };
+        Vector::new(&[
+            ((ay * bz)? - (az * by)?)?,
+            ((az * bx)? - (ax * bz)?)?,
+            ((ax * by)? - (ay * bx)?)?,
+        ])
+    }
+
+    /// Returns the length of the vector (its Euclidean norm), rounded down to the precision of the default exponent.
+    /// Like `DecimalFixed::hypot()`, it only returns `MathOverflow` if the length itself doesn't fit.
+    pub fn magnitude(&self) -> Result<DecimalFixed, CustomError> {
+        wide::sqrt(self.wide_dot(self)?)
+    }
+
+    /// Returns the unit vector of the same direction, or `BadInput` for the zero vector, which has none.
+    pub fn normalize(&self) -> Result<Vector, CustomError> {
+        let magnitude = self.magnitude()?;
+        if magnitude.is_zero() {
+            return Err(CE::BadInput);
+        }
+        let mut unit = self.clone();
+        for c in unit.components.iter_mut() {
+            *c = (*c / magnitude)?;
+        }
+        Ok(unit)
+    }
+
+    /// Writes the brackets and the separators, and the components with `fmt_component()`
+    fn fmt_with(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        fmt_component: impl Fn(&DecimalFixed, &mut fmt::Formatter<'_>) -> fmt::Result,
+    ) -> fmt::Result {
+        f.write_str("[")?;
+        for (i, c) in self.components.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            fmt_component(c, f)?;
+        }
+        f.write_str("]")
+    }
+}
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::string::ToString;
+    use std::format;
+    use super::Vector;
+    use crate::decfix::DecimalFixed;
+use crate::wide::{self, to_wide, from_wide, checked_mul_scaled};
+    use crate::custom_error::CE;
+
+    fn vector(components: &[&str]) -> Vector {
+        let components: std::vec::Vec<DecimalFixed> = components.iter().map(|s| DecimalFixed::parse_str(s, None).unwrap()).collect();
+        Vector::new(&components).unwrap()
+    }
+
+    #[test]
+    fn products() {
+        let (a, b) = (vector(&["1", "2", "3"]), vector(&["4", "-5", "6"]));
+        assert_eq!(a.dot(&b), DecimalFixed::parse_str("12", None));
+        assert_eq!(a.cross(&b), Ok(vector(&["27", "6", "-13"])));
+        assert_eq!(vector(&["1", "0", "0"]).cross(&vector(&["0", "1", "0"])), Ok(vector(&["0", "0", "1"])));
+
+        assert_eq!(a.dot(&vector(&["1", "2"])), Err(CE::BadInput));
+        assert_eq!(vector(&["1", "2"]).cross(&vector(&["3", "4"])), Err(CE::BadInput));
+    }
+
+    #[test]
+    fn magnitude_and_normalization() {
+        let v = vector(&["3", "4"]);
+        assert_eq!(v.magnitude(), DecimalFixed::parse_str("5", None));
+        assert_eq!(v.normalize(), Ok(vector(&["0.6", "0.8"])));
+        assert_eq!(vector(&["0", "0", "0"]).normalize(), Err(CE::BadInput));
+        assert_eq!(Vector::new(&[DecimalFixed::ZERO]), Err(CE::BadInput));
+    }
+
+    #[test]
+    fn large_components() {
+        // Squaring any of them would overflow, their lengths don't
+        assert_eq!(vector(&["100000", "0"]).magnitude(), DecimalFixed::parse_str("100000", None));
+        let v = vector(&["-300000", "0", "400000"]);
+        assert_eq!(v.magnitude(), DecimalFixed::parse_str("500000", None));
+        assert_eq!(v.normalize(), Ok(vector(&["-0.6", "0", "0.8"])));
+        // The dot product itself doesn't fit
+        assert_eq!(v.dot(&v), Err(CE::MathOverflow));
+    }
+
+    #[test]
+    fn displays_compactly() {
+        let v = vector(&["1.5", "-2", "0"]);
+        assert_eq!(v.to_string(), "[1.5, -2, 0]");
+        assert_eq!(format!("{:.2}", v), "[1.50, -2.00, 0.00]");
+        assert_eq!(format!("{:.1e}", vector(&["1500", "0.25"])), "[1.5e3, 2.5e-1]");
+    }
+}
@@ -0,0 +1,76 @@
+use rp2040_hal as hal;
+use hal::{
+    adc::{Adc, AdcPin},
+    gpio::{bank0::Gpio29, FunctionSioInput, Pin, PullNone},
+};
+
+use crate::decfix::DecimalFixed;
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// How many ADC samples to average, the ADC is rather noisy (and VSYS even more so with the display on it)
+const SAMPLE_COUNT: i64 = 8;
+/// Full scale of the 12-bit ADC
+const ADC_FULL_SCALE: i64 = 4096;
+/// What the full scale corresponds to, in millivolts: the 3.3 V reference times the 1:3 divider on the Pico's GPIO29
+const FULL_SCALE_MILLIVOLTS: i64 = 3 * 3300;
+/// Below this (in millivolts), the battery is considered low, see `Vsys::is_low()`.
+/// A Li-ion cell's at about 10 % at 3.5 V, and the Pico's Schottky diode takes a bit more off of that.
+#[cfg(feature = "battery")]
+const LOW_BATTERY_MILLIVOLTS: i64 = 3400;
+
+/// GPIO29 with its digital circuitry disabled, see `AdcPin`
+pub type VsysPin = AdcPin<Pin<Gpio29, FunctionSioInput, PullNone>>;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Measures the supply voltage VSYS, which the Pico feeds to ADC3 (GPIO29) through a 1:3 divider.
+///
+/// On the Pico W, GPIO29 is shared with the wireless chip's SPI clock, so this only works while that's idle.
+pub struct Vsys {
+    adc: Adc,
+    pin: VsysPin,
+}
+
+impl Vsys {
+    pub fn new(adc: Adc, pin: VsysPin) -> Self {
+        Vsys { adc, pin }
+    }
+
+    /// Lends the ADC to the readers of its other channels, e.g. the `LightSensor`.
+    pub fn adc(&mut self) -> &mut Adc {
+        &mut self.adc
+    }
+
+    /// Returns the average of a few raw ADC readings.
+    fn sample_raw(&mut self) -> Result<i64, CustomError> {
+        let mut sum: i64 = 0;
+        for _ in 0..SAMPLE_COUNT {
+            sum += i64::from(self.adc.read(&mut self.pin).map_err(|e| {
+                log_error!("ADC conversion of VSYS failed: {:?}", e);
+                CE::Other
+            })?);
+        }
+        Ok(sum / SAMPLE_COUNT)
+    }
+
+    /// Samples VSYS and returns it in volts.
+    pub fn measure(&mut self) -> Result<DecimalFixed, CustomError> {
+        let raw = DecimalFixed::new(self.sample_raw()?, None)?;
+        let full_scale_volts = DecimalFixed::new_prescaled(FULL_SCALE_MILLIVOLTS, -3).with_exponent(None)?;
+        (raw * full_scale_volts)? / DecimalFixed::new(ADC_FULL_SCALE, None)?
+    }
+
+    /// Whether the battery is low and should be charged or replaced soon.
+    /// Only makes sense for battery-powered builds, on USB VSYS is always about 4.7 V.
+    #[cfg(feature = "battery")]
+    pub fn is_low(&mut self) -> Result<bool, CustomError> {
+        // No need for DecimalFixed, we only compare
+        Ok(self.sample_raw()? * FULL_SCALE_MILLIVOLTS / ADC_FULL_SCALE < LOW_BATTERY_MILLIVOLTS)
+    }
+}
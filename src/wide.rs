@@ -0,0 +1,126 @@
+use crate::decfix::DecimalFixed;
+use crate::custom_error::{ // Because we already have the `mod` in `main.rs`
+    CustomError,
+    CE // Short type alias
+};
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+// Compile time constants
+/// Numbers here are fixed point with this many decimal places (10^18), in i128,
+/// so that the functions computed by series and the like have 9 guard digits past `DecimalFixed`'s
+pub const SCALE: i128 = 1_000_000_000_000_000_000;
+pub const LN_2: i128 = 693_147_180_559_945_309;
+pub const LN_10: i128 = 2_302_585_092_994_045_684;
+
+// ------------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Converts the number into the fixed point of `SCALE`.
+pub fn to_wide(x: DecimalFixed) -> Result<i128, CustomError> {
+    let x = x.with_exponent(None)?;
+    Ok( i128::from(x.value()) * unscale(x.exponent())? )
+}
+
+/// Converts the number from the fixed point of `SCALE` back to the default exponent, rounding half away from zero.
+/// Returns `MathOverflow` if it doesn't fit.
+pub fn from_wide(x: i128) -> Result<DecimalFixed, CustomError> {
+    let exponent = DecimalFixed::ZERO.exponent();
+    let divisor = unscale(exponent)?;
+    let rounded = (x + x.signum() * divisor / 2) / divisor;
+    Ok( DecimalFixed::new_prescaled(i64::try_from(rounded)?, exponent) )
+}
+
+/// Returns what a value with the exponent has to be multiplied by to get to `SCALE`
+fn unscale(exponent: i32) -> Result<i128, CustomError> {
+    let digits = u32::try_from(SCALE.ilog10() as i32 + exponent).map_err(|_| CE::Impossible)?; // Only if the default exponent were tiny
+    Ok( 10_i128.pow(digits) )
+}
+
+/// Returns `a * b / SCALE` without overflowing on the way, for `b` up to about 10^20.
+pub fn mul_scaled(a: i128, b: i128) -> i128 {
+    let (whole, fraction) = (a / SCALE, a % SCALE);
+    whole * b + fraction * b / SCALE
+}
+
+/// Returns `a * b / SCALE` like `mul_scaled()`, but for any `a` and `b`, or `MathOverflow` if the result doesn't fit.
+pub fn checked_mul_scaled(a: i128, b: i128) -> Result<i128, CustomError> {
+    let (a_whole, a_fraction) = (a / SCALE, a % SCALE);
+    let (b_whole, b_fraction) = (b / SCALE, b % SCALE);
+    // Only the product of the fractions has to be scaled back down, and it's below SCALE², so it fits
+    [
+        a_whole.checked_mul(b_whole).and_then(|x| x.checked_mul(SCALE)),
+        a_whole.checked_mul(b_fraction),
+        a_fraction.checked_mul(b_whole),
+        Some(a_fraction * b_fraction / SCALE),
+    ].into_iter()
+        .try_fold(0_i128, |acc, x| acc.checked_add(x?))
+        .ok_or(CE::MathOverflow)
+}
+
+/// Returns `a * SCALE / b` truncated, or `MathOverflow` if it doesn't fit. `b` mustn't be zero.
+pub fn checked_div_scaled(a: i128, b: i128) -> Result<i128, CustomError> {
+    // Digit by digit like the long division, since `a * SCALE` alone would overflow for a big `a`
+    let (mut quotient, mut remainder) = (a / b, a % b);
+    for _ in 0..SCALE.ilog10() {
+        remainder = remainder.checked_mul(10).ok_or(CE::MathOverflow)?;
+        quotient = quotient.checked_mul(10).and_then(|q| q.checked_add(remainder / b)).ok_or(CE::MathOverflow)?;
+        remainder %= b;
+    }
+    Ok(quotient)
+}
+
+/// Returns the square root of the number at the default exponent, rounded down, or `BadInput` if it's negative.
+/// Returns `MathOverflow` only if the root itself doesn't fit.
+pub fn sqrt(x: i128) -> Result<DecimalFixed, CustomError> {
+    if x < 0 {
+        return Err(CE::BadInput);
+    }
+    // sqrt(x / 10^18) = sqrt(x) / 10^9, so it's just the integer square root with half the decimal places
+    let root = DecimalFixed::new_prescaled(i64::try_from(x.isqrt())?, -(SCALE.ilog10() as i32 / 2));
+    root.with_exponent(None)
+}
+
+/// Returns the natural logarithm of the positive number.
+pub fn ln(x: i128) -> i128 {
+    // Into `m * 2^j * 10^k` with `m` from 1 to 2, so that the series below converges fast
+    let (mut m, mut j, mut k) = (x, 0, 0);
+    while m >= 10 * SCALE {
+        (m, k) = (m / 10, k + 1);
+    }
+    while m < SCALE {
+        (m, k) = (m * 10, k - 1);
+    }
+    while m >= 2 * SCALE {
+        (m, j) = (m / 2, j + 1);
+    }
+
+    // ln(m) = 2 * atanh(z) = 2 * (z + z^3 / 3 + z^5 / 5 + ...) with z = (m - 1) / (m + 1), which is at most 1/3
+    let z = (m - SCALE) * SCALE / (m + SCALE);
+    let z2 = mul_scaled(z, z);
+    let (mut sum, mut power, mut n) = (0, z, 1);
+    while power != 0 {
+        sum += power / n;
+        (power, n) = (mul_scaled(power, z2), n + 2);
+    }
+    2 * sum + j * LN_2 + k * LN_10
+}
+
+/// Returns `e^x`, or `MathOverflow` if it's far past `DecimalFixed`'s range. Results too small for any fixed point are zero.
+pub fn exp(x: i128) -> Result<i128, CustomError> {
+    // e^x = e^r * 2^k with r at most ln(2) / 2 in magnitude, so that the series below converges fast
+    let k = (x + x.signum() * LN_2 / 2) / LN_2;
+    let r = x - k * LN_2;
+    if k > 40 {
+        return Err(CE::MathOverflow); // Way past `DecimalFixed`'s range, the shift below could overflow too
+    } else if k < -80 {
+        return Ok(0);
+    }
+
+    // e^r = 1 + r + r^2 / 2! + r^3 / 3! + ...
+    let (mut sum, mut term, mut n) = (SCALE, SCALE, 1);
+    while term != 0 {
+        term = mul_scaled(term, r) / n;
+        (sum, n) = (sum + term, n + 1);
+    }
+    Ok( if k >= 0 { sum << k } else { sum >> -k } )
+}